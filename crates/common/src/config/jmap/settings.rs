@@ -26,11 +26,13 @@ pub struct JmapConfig {
     pub upload_tmp_quota_size: usize,
     pub upload_tmp_quota_amount: usize,
     pub upload_tmp_ttl: u64,
+    pub upload_tmp_purge_frequency: SimpleCron,
 
     pub mailbox_max_depth: usize,
     pub mailbox_name_max_len: usize,
     pub mail_attachments_max_size: usize,
     pub mail_parse_max_items: usize,
+    pub mail_parse_max_depth: usize,
     pub mail_max_size: usize,
 
     pub sieve_max_script_name: usize,
@@ -125,6 +127,12 @@ impl JmapConfig {
                 .property_or_default_::<Duration>("jmap.protocol.upload.ttl", "1h")
                 .unwrap_or_else(|| Duration::from_secs(3600))
                 .as_secs(),
+            upload_tmp_purge_frequency: config
+                .property_or_default_::<SimpleCron>(
+                    "jmap.protocol.upload.purge.frequency",
+                    "15 * *",
+                )
+                .unwrap_or_else(|| SimpleCron::parse_value("15 * *", "").unwrap()),
             mailbox_max_depth: config.property_("jmap.mailbox.max-depth").unwrap_or(10),
             mailbox_name_max_len: config
                 .property_("jmap.mailbox.max-name-length")
@@ -134,6 +142,7 @@ impl JmapConfig {
                 .unwrap_or(50000000),
             mail_max_size: config.property_("jmap.email.max-size").unwrap_or(75000000),
             mail_parse_max_items: config.property_("jmap.email.parse.max-items").unwrap_or(10),
+            mail_parse_max_depth: config.property_("jmap.email.parse.max-depth").unwrap_or(20),
             sieve_max_script_name: config
                 .property_("sieve.untrusted.limits.name-length")
                 .unwrap_or(512),
@@ -67,6 +67,7 @@ pub enum AuthResult<T> {
     Success(T),
     Failure,
     Banned,
+    PasswordExpired,
 }
 
 pub trait IntoString: Sized {
@@ -184,7 +185,36 @@ impl Core {
             .query(QueryBy::Credentials(credentials), return_member_of)
             .await?
         {
-            Ok(AuthResult::Success(principal))
+            if !principal.is_allowed_ip(&remote_ip) {
+                tracing::info!(
+                    context = "directory",
+                    event = "network-denied",
+                    remote_ip = ?remote_ip,
+                    login = ?principal.name,
+                    "Login attempt from a network not allowed for this account",
+                );
+
+                Ok(AuthResult::Failure)
+            } else if directory.is_password_expired(&principal)
+                && match credentials {
+                    // Application passwords and tokens are not subject to expiration
+                    Credentials::Plain { secret, .. } => !principal.is_app_password(secret).await,
+                    Credentials::XOauth2 { .. } => true,
+                    Credentials::OAuthBearer { .. } => false,
+                }
+            {
+                tracing::info!(
+                    context = "directory",
+                    event = "password-expired",
+                    remote_ip = ?remote_ip,
+                    login = ?principal.name,
+                    "Login attempt with an expired password",
+                );
+
+                Ok(AuthResult::PasswordExpired)
+            } else {
+                Ok(AuthResult::Success(principal))
+            }
         } else if self.has_fail2ban() {
             let login = match credentials {
                 Credentials::Plain { username, .. }
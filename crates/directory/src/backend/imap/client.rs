@@ -212,6 +212,7 @@ mod test {
             &connector,
             "imap.gmail.com",
             true,
+            None,
         )
         .await
         .unwrap();
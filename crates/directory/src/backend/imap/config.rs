@@ -26,7 +26,10 @@ use std::time::Duration;
 use mail_send::smtp::tls::build_tls_connector;
 use utils::config::{utils::AsKey, Config};
 
-use crate::core::config::build_pool;
+use crate::core::{
+    cache::AuthCache,
+    config::{build_keepalive, build_pool},
+};
 
 use super::{ImapConnectionManager, ImapDirectory};
 
@@ -53,6 +56,7 @@ impl ImapDirectory {
             ),
             tls_hostname: address.to_string(),
             tls_implicit,
+            keepalive: build_keepalive(config, &prefix)?,
             mechanisms: 0.into(),
         };
 
@@ -69,6 +73,7 @@ impl ImapDirectory {
                 .values((&prefix, "lookup.domains"))
                 .map(|(_, v)| v.to_lowercase())
                 .collect(),
+            auth_cache: AuthCache::try_from_config(config, &prefix),
         })
     }
 }
@@ -31,6 +31,14 @@ use super::{ImapDirectory, ImapError};
 impl ImapDirectory {
     pub async fn query(&self, query: QueryBy<'_>) -> crate::Result<Option<Principal<u32>>> {
         if let QueryBy::Credentials(credentials) = query {
+            if self
+                .auth_cache
+                .as_ref()
+                .map_or(false, |cache| cache.get(credentials))
+            {
+                return Ok(Some(Principal::default()));
+            }
+
             let mut client = self.pool.get().await?;
             let mechanism = match credentials {
                 Credentials::Plain { .. }
@@ -61,13 +69,23 @@ impl ImapDirectory {
                 }
             };
 
+            // Authenticated sessions cannot be reused for other lookups
+            client.is_valid = false;
             match client.authenticate(mechanism, credentials).await {
                 Ok(_) => {
-                    client.is_valid = false;
+                    if let Some(cache) = &self.auth_cache {
+                        cache.insert(credentials);
+                    }
                     Ok(Some(Principal::default()))
                 }
                 Err(err) => match &err {
-                    ImapError::AuthenticationFailed => Ok(None),
+                    ImapError::AuthenticationFailed => {
+                        client.is_valid = true;
+                        if let Some(cache) = &self.auth_cache {
+                            cache.remove(credentials);
+                        }
+                        Ok(None)
+                    }
                     _ => Err(err.into()),
                 },
             }
@@ -95,4 +113,9 @@ impl ImapDirectory {
     pub async fn is_local_domain(&self, domain: &str) -> crate::Result<bool> {
         Ok(self.domains.contains(domain))
     }
+
+    pub async fn health_check(&self) -> crate::Result<()> {
+        self.pool.get().await?;
+        Ok(())
+    }
 }
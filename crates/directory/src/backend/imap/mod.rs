@@ -34,9 +34,12 @@ use deadpool::managed::Pool;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_rustls::TlsConnector;
 
+use crate::core::{cache::AuthCache, config::TcpKeepalive};
+
 pub struct ImapDirectory {
     pool: Pool<ImapConnectionManager>,
     domains: AHashSet<String>,
+    auth_cache: Option<AuthCache>,
 }
 
 pub struct ImapConnectionManager {
@@ -45,6 +48,7 @@ pub struct ImapConnectionManager {
     tls_connector: TlsConnector,
     tls_hostname: String,
     tls_implicit: bool,
+    keepalive: Option<TcpKeepalive>,
     mechanisms: AtomicU64,
 }
 
@@ -42,6 +42,7 @@ impl managed::Manager for ImapConnectionManager {
             &self.tls_connector,
             &self.tls_hostname,
             self.tls_implicit,
+            self.keepalive.as_ref(),
         )
         .await?;
 
@@ -60,6 +61,12 @@ impl managed::Manager for ImapConnectionManager {
         conn: &mut ImapClient<TlsStream<TcpStream>>,
         _: &managed::Metrics,
     ) -> managed::RecycleResult<ImapError> {
+        if !conn.is_valid {
+            return Err(managed::RecycleError::StaticMessage(
+                "No longer valid: Command interrupted or session authenticated",
+            ));
+        }
+
         conn.noop()
             .await
             .map(|_| ())
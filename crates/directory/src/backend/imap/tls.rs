@@ -28,6 +28,8 @@ use smtp_proto::IntoString;
 use tokio::net::{TcpStream, ToSocketAddrs};
 use tokio_rustls::{client::TlsStream, TlsConnector};
 
+use crate::core::config::TcpKeepalive;
+
 use super::{ImapClient, ImapError};
 
 impl ImapClient<TcpStream> {
@@ -82,15 +84,22 @@ impl ImapClient<TlsStream<TcpStream>> {
         tls_connector: &TlsConnector,
         tls_hostname: &str,
         tls_implicit: bool,
+        keepalive: Option<&TcpKeepalive>,
     ) -> Result<Self, ImapError> {
         let mut client: ImapClient<TcpStream> = tokio::time::timeout(timeout, async {
             match TcpStream::connect(addr).await {
-                Ok(stream) => Ok(ImapClient {
-                    stream,
-                    timeout,
-                    mechanisms: 0,
-                    is_valid: true,
-                }),
+                Ok(stream) => {
+                    if let Some(keepalive) = keepalive {
+                        keepalive.apply(&stream);
+                    }
+
+                    Ok(ImapClient {
+                        stream,
+                        timeout,
+                        mechanisms: 0,
+                        is_valid: true,
+                    })
+                }
                 Err(err) => Err(ImapError::Io(err)),
             }
         })
@@ -0,0 +1,332 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{Principal, Type};
+
+use super::{PrincipalField, PrincipalUpdate, PrincipalValue};
+
+const MAX_LINE_LEN: usize = 76;
+
+impl Principal<String> {
+    pub fn to_ldif(&self, base_dn: &str) -> String {
+        let mut ldif = String::new();
+        let rdn = format!("uid={}", escape_dn(&self.name));
+        if !base_dn.is_empty() {
+            write_attribute(&mut ldif, "dn", &format!("{rdn},{base_dn}"));
+        } else {
+            write_attribute(&mut ldif, "dn", &rdn);
+        }
+        write_attribute(&mut ldif, "objectClass", self.typ.as_ldap_class());
+        write_attribute(&mut ldif, "uid", &self.name);
+        if let Some(description) = &self.description {
+            write_attribute(&mut ldif, "cn", description);
+        }
+        for (pos, email) in self.emails.iter().enumerate() {
+            write_attribute(
+                &mut ldif,
+                if pos == 0 { "mail" } else { "mailAlias" },
+                email,
+            );
+        }
+        for secret in &self.secrets {
+            write_attribute(&mut ldif, "userPassword", secret);
+        }
+        for group in &self.member_of {
+            write_attribute(&mut ldif, "memberOf", group);
+        }
+        if self.quota > 0 {
+            write_attribute(&mut ldif, "diskQuota", &self.quota.to_string());
+        }
+        ldif.push('\n');
+        ldif
+    }
+
+    pub fn into_updates(self) -> Vec<PrincipalUpdate> {
+        let mut updates = vec![PrincipalUpdate::set(
+            PrincipalField::Name,
+            PrincipalValue::String(self.name),
+        )];
+
+        // Only individual accounts can change their type
+        if matches!(self.typ, Type::Individual | Type::Superuser) {
+            updates.push(PrincipalUpdate::set(
+                PrincipalField::Type,
+                PrincipalValue::String(
+                    if self.typ == Type::Superuser {
+                        "superuser"
+                    } else {
+                        "individual"
+                    }
+                    .to_string(),
+                ),
+            ));
+        }
+
+        updates.extend([
+            PrincipalUpdate::set(PrincipalField::Quota, PrincipalValue::Integer(self.quota)),
+            PrincipalUpdate::set(
+                PrincipalField::Description,
+                PrincipalValue::String(self.description.unwrap_or_default()),
+            ),
+            PrincipalUpdate::set(
+                PrincipalField::Secrets,
+                PrincipalValue::StringList(self.secrets),
+            ),
+            PrincipalUpdate::set(
+                PrincipalField::Emails,
+                PrincipalValue::StringList(self.emails),
+            ),
+            PrincipalUpdate::set(
+                PrincipalField::MemberOf,
+                PrincipalValue::StringList(self.member_of),
+            ),
+        ]);
+        updates
+    }
+}
+
+pub fn parse_ldif(ldif: &str) -> Result<Vec<Principal<String>>, String> {
+    let mut principals = Vec::new();
+    let mut attributes: Vec<(String, String)> = Vec::new();
+    let mut line_num = 0;
+
+    for (pos, line) in unfold_lines(ldif) {
+        line_num = pos + 1;
+        if line.is_empty() {
+            if !attributes.is_empty() {
+                principals.push(build_principal(std::mem::take(&mut attributes), line_num)?);
+            }
+            continue;
+        }
+
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid LDIF at line {line_num}: missing attribute value."))?;
+        let value = if let Some(value) = value.strip_prefix(':') {
+            STANDARD
+                .decode(value.trim())
+                .ok()
+                .and_then(|value| String::from_utf8(value).ok())
+                .ok_or_else(|| format!("Invalid LDIF at line {line_num}: invalid base64 value."))?
+        } else if value.starts_with('<') {
+            return Err(format!(
+                "Invalid LDIF at line {line_num}: URL values are not supported."
+            ));
+        } else {
+            value.trim_start_matches(' ').to_string()
+        };
+        let name = name.trim().to_ascii_lowercase();
+
+        // The version line is only allowed before the first entry
+        if name == "version" && attributes.is_empty() && principals.is_empty() {
+            continue;
+        }
+
+        attributes.push((name, value));
+    }
+
+    if !attributes.is_empty() {
+        principals.push(build_principal(attributes, line_num)?);
+    }
+
+    Ok(principals)
+}
+
+fn build_principal(
+    attributes: Vec<(String, String)>,
+    line_num: usize,
+) -> Result<Principal<String>, String> {
+    let mut principal = Principal::default();
+    let mut dn_name = None;
+    let mut aliases = Vec::new();
+    let mut typ = None;
+
+    for (name, value) in attributes {
+        match name.as_str() {
+            "dn" => {
+                dn_name = value
+                    .split_once(',')
+                    .map_or(value.as_str(), |(rdn, _)| rdn)
+                    .split_once('=')
+                    .map(|(_, name)| unescape_dn(name));
+            }
+            "changetype" if value != "add" => {
+                return Err(format!(
+                    "Invalid LDIF entry ending at line {line_num}: unsupported change type {value:?}."
+                ));
+            }
+            "objectclass" => {
+                if typ.is_none() {
+                    typ = Type::from_ldap_class(&value);
+                }
+            }
+            "uid" => {
+                principal.name = value;
+            }
+            "cn" => {
+                principal.description = Some(value);
+            }
+            "description" => {
+                if principal.description.is_none() {
+                    principal.description = Some(value);
+                }
+            }
+            "mail" => {
+                principal.emails.push(value);
+            }
+            "mailalias" => {
+                aliases.push(value);
+            }
+            "userpassword" => {
+                principal.secrets.push(value);
+            }
+            "memberof" => {
+                principal.member_of.push(value);
+            }
+            "diskquota" => {
+                principal.quota = value.parse().map_err(|_| {
+                    format!(
+                        "Invalid LDIF entry ending at line {line_num}: invalid quota {value:?}."
+                    )
+                })?;
+            }
+            _ => (),
+        }
+    }
+
+    if principal.name.is_empty() {
+        principal.name = dn_name.ok_or_else(|| {
+            format!("Invalid LDIF entry ending at line {line_num}: missing principal name.")
+        })?;
+    }
+    principal.emails.extend(aliases);
+    principal.typ = typ.unwrap_or_default();
+
+    Ok(principal)
+}
+
+fn unfold_lines(ldif: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    let mut is_comment = false;
+
+    for (pos, line) in ldif.lines().enumerate() {
+        if let Some(continuation) = line.strip_prefix(' ') {
+            // Continuation of the previous line
+            if !is_comment {
+                if let Some((_, last_line)) = lines.last_mut() {
+                    last_line.push_str(continuation);
+                }
+            }
+        } else {
+            is_comment = line.starts_with('#');
+            if !is_comment {
+                lines.push((pos, line.to_string()));
+            }
+        }
+    }
+
+    lines
+}
+
+fn write_attribute(ldif: &mut String, name: &str, value: &str) {
+    let line = if is_safe_string(value) {
+        format!("{name}: {value}")
+    } else {
+        format!("{name}:: {}", STANDARD.encode(value))
+    };
+
+    // Lines are ASCII at this point, fold them at 76 characters
+    let (first, mut rest) = line.split_at(line.len().min(MAX_LINE_LEN));
+    ldif.push_str(first);
+    ldif.push('\n');
+    while !rest.is_empty() {
+        let (chunk, remaining) = rest.split_at(rest.len().min(MAX_LINE_LEN - 1));
+        ldif.push(' ');
+        ldif.push_str(chunk);
+        ldif.push('\n');
+        rest = remaining;
+    }
+}
+
+fn is_safe_string(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    !matches!(bytes.first(), Some(b' ' | b':' | b'<'))
+        && !matches!(bytes.last(), Some(b' '))
+        && bytes
+            .iter()
+            .all(|&ch| matches!(ch, 0x01..=0x09 | 0x0B..=0x0C | 0x0E..=0x7F))
+}
+
+fn escape_dn(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if matches!(ch, ',' | '+' | '"' | '\\' | '<' | '>' | ';' | '=') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+fn unescape_dn(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            if let Some(ch) = chars.next() {
+                unescaped.push(ch);
+            }
+        } else {
+            unescaped.push(ch);
+        }
+    }
+    unescaped
+}
+
+impl Type {
+    pub fn as_ldap_class(&self) -> &'static str {
+        match self {
+            Type::Individual => "posixAccount",
+            Type::Group => "posixGroup",
+            Type::Superuser => "superuser",
+            Type::Resource => "resource",
+            Type::Location => "location",
+            Type::List => "list",
+            Type::Other => "other",
+        }
+    }
+
+    pub fn from_ldap_class(class: &str) -> Option<Self> {
+        match class.to_ascii_lowercase().as_str() {
+            "admin" | "administrator" | "root" | "superuser" => Some(Type::Superuser),
+            "posixaccount" | "individual" | "person" | "inetorgperson" => Some(Type::Individual),
+            "posixgroup" | "group" => Some(Type::Group),
+            "resource" => Some(Type::Resource),
+            "location" => Some(Type::Location),
+            "list" => Some(Type::List),
+            _ => None,
+        }
+    }
+}
@@ -97,12 +97,7 @@ impl DirectoryStore for Store {
     }
 
     async fn email_to_ids(&self, email: &str) -> crate::Result<Vec<u32>> {
-        if let Some(ptype) = self
-            .get_value::<PrincipalIdType>(ValueKey::from(ValueClass::Directory(
-                DirectoryClass::EmailToId(email.as_bytes().to_vec()),
-            )))
-            .await?
-        {
+        if let Some(ptype) = get_email_id_or_catch_all(self, email).await? {
             if ptype.typ != Type::List {
                 Ok(vec![ptype.account_id])
             } else {
@@ -123,12 +118,9 @@ impl DirectoryStore for Store {
     }
 
     async fn rcpt(&self, address: &str) -> crate::Result<bool> {
-        self.get_value::<()>(ValueKey::from(ValueClass::Directory(
-            DirectoryClass::EmailToId(address.as_bytes().to_vec()),
-        )))
-        .await
-        .map(|ids| ids.is_some())
-        .map_err(Into::into)
+        get_email_id_or_catch_all(self, address)
+            .await
+            .map(|ptype| ptype.is_some())
     }
 
     async fn vrfy(&self, address: &str) -> crate::Result<Vec<String>> {
@@ -160,7 +152,12 @@ impl DirectoryStore for Store {
 
     async fn expn(&self, address: &str) -> crate::Result<Vec<String>> {
         let mut results = Vec::new();
-        for account_id in self.email_to_ids(address).await? {
+        let account_ids = match get_email_id(self, address).await? {
+            Some(ptype) if ptype.typ == Type::List => self.get_members(ptype.account_id).await?,
+            Some(ptype) => vec![ptype.account_id],
+            None => vec![],
+        };
+        for account_id in account_ids {
             if let Some(email) = self
                 .get_value::<Principal<u32>>(ValueKey::from(ValueClass::Directory(
                     DirectoryClass::Principal(account_id),
@@ -175,3 +172,33 @@ impl DirectoryStore for Store {
         Ok(results)
     }
 }
+
+pub(super) async fn get_email_id(
+    store: &Store,
+    email: &str,
+) -> crate::Result<Option<PrincipalIdType>> {
+    store
+        .get_value::<PrincipalIdType>(ValueKey::from(ValueClass::Directory(
+            DirectoryClass::EmailToId(email.as_bytes().to_vec()),
+        )))
+        .await
+        .map_err(Into::into)
+}
+
+async fn get_email_id_or_catch_all(
+    store: &Store,
+    email: &str,
+) -> crate::Result<Option<PrincipalIdType>> {
+    if let Some(ptype) = get_email_id(store, email).await? {
+        return Ok(Some(ptype));
+    }
+
+    // Route unknown local parts to the domain's catch-all principal, which
+    // is registered under the address '@domain'.
+    match email.rsplit_once('@') {
+        Some((local_part, domain)) if !local_part.is_empty() && !domain.is_empty() => {
+            get_email_id(store, &format!("@{domain}")).await
+        }
+        _ => Ok(None),
+    }
+}
@@ -21,47 +21,100 @@
  * for more details.
 */
 
+use std::collections::BTreeMap;
+
+use ahash::AHashSet;
 use jmap_proto::types::collection::Collection;
 use pwhash::sha512_crypt;
 use store::{
     rand::{distributions::Alphanumeric, thread_rng, Rng},
     write::{
-        assert::HashedValue, key::DeserializeBigEndian, BatchBuilder, BitmapClass, DirectoryClass,
-        ValueClass,
+        assert::HashedValue, key::DeserializeBigEndian, now, BatchBuilder, BitmapClass,
+        DirectoryClass, ValueClass,
     },
     BitmapKey, Deserialize, IterateParams, Serialize, Store, ValueKey, U32_LEN,
 };
+use utils::config::{ipmask::IpAddrMask, utils::ParseValue};
 
-use crate::{DirectoryError, ManagementError, Principal, QueryBy, Type};
+use crate::{
+    core::cache::membership_changed, feature_from_name, normalize_features, DirectoryError,
+    ManagementError, Principal, QueryBy, Type,
+};
 
 use super::{
-    lookup::DirectoryStore, PrincipalAction, PrincipalField, PrincipalIdType, PrincipalUpdate,
-    PrincipalValue,
+    lookup::{get_email_id, DirectoryStore},
+    policy::PrincipalPolicy,
+    preferences::{validate_locale, validate_principal, validate_timezone},
+    reserved::ReservedNames,
+    PrincipalAction, PrincipalField, PrincipalFilter, PrincipalIdType, PrincipalUpdate,
+    PrincipalValue, PRINCIPAL_VERSION,
 };
 
+/// A principal serialized for export along with the names of the groups it
+/// belongs to, which are not part of the serialized layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountExport {
+    pub entry: Vec<u8>,
+    pub member_of: Vec<String>,
+}
+
 #[allow(async_fn_in_trait)]
 pub trait ManageDirectory: Sized {
     async fn get_account_id(&self, name: &str) -> crate::Result<Option<u32>>;
     async fn get_or_create_account_id(&self, name: &str) -> crate::Result<u32>;
+    async fn lookup_by_external_id(&self, external_id: &str) -> crate::Result<Option<u32>>;
+    async fn sync_account_id(&self, name: &str, external_id: Option<&str>) -> crate::Result<u32>;
+    async fn provision_account_id(
+        &self,
+        name: &str,
+        external_id: Option<&str>,
+        reserved_names: &ReservedNames,
+    ) -> crate::Result<Option<u32>>;
+    async fn sync_attributes(
+        &self,
+        account_id: u32,
+        attributes: &BTreeMap<String, Vec<String>>,
+    ) -> crate::Result<()>;
     async fn get_account_name(&self, account_id: u32) -> crate::Result<Option<String>>;
     async fn get_member_of(&self, account_id: u32) -> crate::Result<Vec<u32>>;
     async fn get_members(&self, account_id: u32) -> crate::Result<Vec<u32>>;
+    async fn groups_of(&self, account_id: u32, transitive: bool) -> crate::Result<Vec<u32>>;
     async fn create_account(
         &self,
         principal: Principal<String>,
         members: Vec<String>,
     ) -> crate::Result<u32>;
+    async fn create_account_with_policy(
+        &self,
+        principal: Principal<String>,
+        members: Vec<String>,
+        policy: &PrincipalPolicy,
+    ) -> crate::Result<u32>;
     async fn update_account(
         &self,
         by: QueryBy<'_>,
         changes: Vec<PrincipalUpdate>,
     ) -> crate::Result<()>;
+    async fn update_account_with_policy(
+        &self,
+        by: QueryBy<'_>,
+        changes: Vec<PrincipalUpdate>,
+        policy: &PrincipalPolicy,
+    ) -> crate::Result<()>;
     async fn delete_account(&self, by: QueryBy<'_>) -> crate::Result<()>;
     async fn list_accounts(
         &self,
         filter: Option<&str>,
         typ: Option<Type>,
     ) -> crate::Result<Vec<String>>;
+    async fn export_accounts(&self, export_version: u8) -> crate::Result<Vec<AccountExport>>;
+    async fn import_accounts(&self, entries: Vec<AccountExport>) -> crate::Result<Vec<u32>>;
+    async fn search(
+        &self,
+        filter: &PrincipalFilter,
+        page: usize,
+        page_size: usize,
+    ) -> crate::Result<(Vec<Principal<u32>>, usize)>;
     async fn map_group_ids(&self, principal: Principal<u32>) -> crate::Result<Principal<String>>;
     async fn map_principal(
         &self,
@@ -169,10 +222,130 @@ impl ManageDirectory for Store {
         }
     }
 
+    async fn lookup_by_external_id(&self, external_id: &str) -> crate::Result<Option<u32>> {
+        self.get_value::<PrincipalIdType>(ValueKey::from(ValueClass::Directory(
+            DirectoryClass::ExternalIdToId(external_id.as_bytes().to_vec()),
+        )))
+        .await
+        .map(|v| v.map(|v| v.account_id))
+        .map_err(Into::into)
+    }
+
+    // Used by external directories that provide a stable identifier, so that
+    // a principal renamed in the source directory keeps its account id
+    async fn sync_account_id(&self, name: &str, external_id: Option<&str>) -> crate::Result<u32> {
+        let external_id = match external_id {
+            Some(external_id) if !external_id.is_empty() => external_id,
+            _ => return self.get_or_create_account_id(name).await,
+        };
+
+        if let Some(account_id) = self.lookup_by_external_id(external_id).await? {
+            let new_name = name.to_lowercase();
+            if self
+                .get_account_name(account_id)
+                .await?
+                .map_or(false, |old_name| old_name != new_name)
+            {
+                self.update_account(
+                    QueryBy::Id(account_id),
+                    vec![PrincipalUpdate::set(
+                        PrincipalField::Name,
+                        PrincipalValue::String(new_name),
+                    )],
+                )
+                .await?;
+            }
+
+            Ok(account_id)
+        } else {
+            let account_id = self.get_or_create_account_id(name).await?;
+            self.update_account(
+                QueryBy::Id(account_id),
+                vec![PrincipalUpdate::set(
+                    PrincipalField::ExternalId,
+                    PrincipalValue::String(external_id.to_string()),
+                )],
+            )
+            .await?;
+
+            Ok(account_id)
+        }
+    }
+
+    // Used by external directories when provisioning accounts for their users,
+    // reserved names are only mapped to accounts previously created by a superuser
+    async fn provision_account_id(
+        &self,
+        name: &str,
+        external_id: Option<&str>,
+        reserved_names: &ReservedNames,
+    ) -> crate::Result<Option<u32>> {
+        if !reserved_names.is_reserved(name) {
+            self.sync_account_id(name, external_id).await.map(Some)
+        } else if let Some(account_id) = self.get_account_id(&name.to_lowercase()).await? {
+            Ok(Some(account_id))
+        } else {
+            tracing::debug!(
+                context = "directory",
+                event = "reserved",
+                account = name,
+                "Refusing to provision an account with a reserved name"
+            );
+            Ok(None)
+        }
+    }
+
+    // Keeps a copy of the attributes mapped by external directories
+    async fn sync_attributes(
+        &self,
+        account_id: u32,
+        attributes: &BTreeMap<String, Vec<String>>,
+    ) -> crate::Result<()> {
+        let principal = self
+            .get_value::<HashedValue<Principal<u32>>>(ValueKey::from(ValueClass::Directory(
+                DirectoryClass::Principal(account_id),
+            )))
+            .await?
+            .ok_or_else(|| {
+                DirectoryError::Management(ManagementError::NotFound(account_id.to_string()))
+            })?;
+        if &principal.inner.attributes == attributes {
+            return Ok(());
+        }
+
+        let mut batch = BatchBuilder::new();
+        batch.assert_value(
+            ValueClass::Directory(DirectoryClass::Principal(account_id)),
+            &principal,
+        );
+        let mut principal = principal.inner;
+        principal.attributes = attributes.clone();
+        batch.set(
+            ValueClass::Directory(DirectoryClass::Principal(account_id)),
+            principal.serialize(),
+        );
+
+        match self.write(batch.build()).await {
+            // A concurrent lookup stored them first
+            Ok(_) | Err(store::Error::AssertValueFailed) => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
     async fn create_account(
         &self,
         principal: Principal<String>,
         members: Vec<String>,
+    ) -> crate::Result<u32> {
+        self.create_account_with_policy(principal, members, &PrincipalPolicy::default())
+            .await
+    }
+
+    async fn create_account_with_policy(
+        &self,
+        principal: Principal<String>,
+        members: Vec<String>,
+        policy: &PrincipalPolicy,
     ) -> crate::Result<u32> {
         // Make sure the principal has a name
         if principal.name.is_empty() {
@@ -181,10 +354,19 @@ impl ManageDirectory for Store {
             )));
         }
 
+        // Validate password strength
+        policy.password.check_principal(&principal)?;
+
+        // Validate preferences
+        validate_principal(&principal)?;
+
         // Map group names
         let mut principal = self.map_principal(principal, false).await?;
         let members = self.map_group_names(members, false).await?;
 
+        // Apply defaults
+        principal.quota = policy.defaults.quota(principal.quota);
+
         // Make sure new name is not taken
         principal.name = principal.name.to_lowercase();
         if self.get_account_id(&principal.name).await?.is_some() {
@@ -195,9 +377,15 @@ impl ManageDirectory for Store {
         }
 
         // Make sure the e-mail is not taken and validate domain
-        for email in principal.emails.iter_mut() {
-            *email = email.to_lowercase();
-            if self.rcpt(email).await? {
+        let mut seen_emails = AHashSet::with_capacity(principal.emails.len());
+        principal.emails = principal
+            .emails
+            .into_iter()
+            .map(|email| email.to_lowercase())
+            .filter(|email| seen_emails.insert(email.clone()))
+            .collect();
+        for email in &principal.emails {
+            if get_email_id(self, email).await?.is_some() {
                 return Err(DirectoryError::Management(ManagementError::AlreadyExists {
                     field: PrincipalField::Emails,
                     value: email.to_string(),
@@ -212,6 +400,21 @@ impl ManageDirectory for Store {
             }
         }
 
+        // Make sure the external id is not taken
+        if let Some(external_id) = &principal.external_id {
+            if self.lookup_by_external_id(external_id).await?.is_some() {
+                return Err(DirectoryError::Management(ManagementError::AlreadyExists {
+                    field: PrincipalField::ExternalId,
+                    value: external_id.to_string(),
+                }));
+            }
+        }
+
+        // Track when the secrets were set
+        if !principal.secrets.is_empty() && principal.password_changed == 0 {
+            principal.password_changed = now();
+        }
+
         // Assign accountId
         principal.id = self
             .assign_document_id(u32::MAX, Collection::Principal)
@@ -235,19 +438,33 @@ impl ManageDirectory for Store {
                 (&principal).serialize(),
             )
             .set(
-                ValueClass::Directory(DirectoryClass::NameToId(principal.name.into_bytes())),
+                ValueClass::Directory(DirectoryClass::NameToId(
+                    principal.name.clone().into_bytes(),
+                )),
                 ptype.clone(),
             );
 
-        // Write email to id mapping
-        for email in principal.emails {
-            batch.set(
-                ValueClass::Directory(DirectoryClass::EmailToId(email.into_bytes())),
-                ptype.clone(),
-            );
+        // Write external id to id mapping
+        if let Some(external_id) = &principal.external_id {
+            let key = ValueClass::Directory(DirectoryClass::ExternalIdToId(
+                external_id.clone().into_bytes(),
+            ));
+            batch.assert_value(key.clone(), ()).set(key, ptype.clone());
+        }
+
+        // Write email to id mapping, asserting that no concurrent create
+        // claimed any of the addresses in the meantime
+        for email in &principal.emails {
+            let key = ValueClass::Directory(DirectoryClass::EmailToId(email.clone().into_bytes()));
+            batch.assert_value(key.clone(), ()).set(key, ptype.clone());
         }
 
         // Write membership
+        let mut changed_ids = Vec::with_capacity(members.len() + 1);
+        if !principal.member_of.is_empty() {
+            changed_ids.push(principal.id);
+        }
+        changed_ids.extend_from_slice(&members);
         for member_of in principal.member_of {
             batch.set(
                 ValueClass::Directory(DirectoryClass::MemberOf {
@@ -281,9 +498,40 @@ impl ManageDirectory for Store {
             );
         }
 
-        self.write(batch.build()).await?;
-
-        Ok(principal.id)
+        match self.write(batch.build()).await {
+            Ok(_) => {
+                if !changed_ids.is_empty() {
+                    membership_changed(changed_ids);
+                }
+                Ok(principal.id)
+            }
+            Err(store::Error::AssertValueFailed) => {
+                // Report which value was taken by a concurrent create
+                for email in principal.emails {
+                    if get_email_id(self, &email).await?.is_some() {
+                        return Err(DirectoryError::Management(ManagementError::AlreadyExists {
+                            field: PrincipalField::Emails,
+                            value: email,
+                        }));
+                    }
+                }
+                match principal.external_id {
+                    Some(external_id)
+                        if self.lookup_by_external_id(&external_id).await?.is_some() =>
+                    {
+                        Err(DirectoryError::Management(ManagementError::AlreadyExists {
+                            field: PrincipalField::ExternalId,
+                            value: external_id,
+                        }))
+                    }
+                    _ => Err(DirectoryError::Management(ManagementError::AlreadyExists {
+                        field: PrincipalField::Name,
+                        value: principal.name,
+                    })),
+                }
+            }
+            Err(err) => Err(err.into()),
+        }
     }
 
     async fn delete_account(&self, by: QueryBy<'_>) -> crate::Result<()> {
@@ -325,6 +573,10 @@ impl ManageDirectory for Store {
             batch.clear(DirectoryClass::EmailToId(email.into_bytes()));
         }
 
+        if let Some(external_id) = principal.external_id {
+            batch.clear(DirectoryClass::ExternalIdToId(external_id.into_bytes()));
+        }
+
         for member_id in self.get_member_of(account_id).await? {
             batch.clear(DirectoryClass::MemberOf {
                 principal_id: account_id,
@@ -336,7 +588,8 @@ impl ManageDirectory for Store {
             });
         }
 
-        for member_id in self.get_members(account_id).await? {
+        let former_members = self.get_members(account_id).await?;
+        for &member_id in &former_members {
             batch.clear(DirectoryClass::MemberOf {
                 principal_id: member_id,
                 member_of: account_id,
@@ -349,6 +602,9 @@ impl ManageDirectory for Store {
 
         self.write(batch.build()).await?;
 
+        // The account may have been a group or a member of one
+        membership_changed(std::iter::once(account_id).chain(former_members));
+
         Ok(())
     }
 
@@ -356,6 +612,16 @@ impl ManageDirectory for Store {
         &self,
         by: QueryBy<'_>,
         changes: Vec<PrincipalUpdate>,
+    ) -> crate::Result<()> {
+        self.update_account_with_policy(by, changes, &PrincipalPolicy::default())
+            .await
+    }
+
+    async fn update_account_with_policy(
+        &self,
+        by: QueryBy<'_>,
+        changes: Vec<PrincipalUpdate>,
+        policy: &PrincipalPolicy,
     ) -> crate::Result<()> {
         let account_id = match by {
             QueryBy::Name(name) => self.get_account_id(name).await?.ok_or_else(|| {
@@ -365,6 +631,9 @@ impl ManageDirectory for Store {
             QueryBy::Credentials(_) => unreachable!(),
         };
 
+        // Validate password strength
+        policy.password.check_updates(&changes)?;
+
         // Fetch principal
         let mut principal = self
             .get_value::<HashedValue<Principal<u32>>>(ValueKey::from(ValueClass::Directory(
@@ -378,6 +647,7 @@ impl ManageDirectory for Store {
         // Obtain members and memberOf
         let mut member_of = self.get_member_of(account_id).await?;
         let mut members = self.get_members(account_id).await?;
+        let (prev_member_of, prev_members) = (member_of.clone(), members.clone());
 
         // Apply changes
         let mut batch = BatchBuilder::new();
@@ -387,6 +657,9 @@ impl ManageDirectory for Store {
             && !changes
                 .iter()
                 .all(|c| matches!(c.field, PrincipalField::MemberOf | PrincipalField::Members));
+        let update_memberships = changes
+            .iter()
+            .any(|c| matches!(c.field, PrincipalField::MemberOf | PrincipalField::Members));
 
         if update_principal {
             batch.assert_value(
@@ -421,16 +694,22 @@ impl ManageDirectory for Store {
                         );
                     }
                 }
-                (PrincipalAction::Set, PrincipalField::Type, PrincipalValue::String(new_type)) => {
-                    if let Some(new_type) = Type::parse(&new_type) {
-                        if matches!(principal.inner.typ, Type::Individual | Type::Superuser)
-                            && matches!(new_type, Type::Individual | Type::Superuser)
-                        {
+                (PrincipalAction::Set, PrincipalField::Type, PrincipalValue::String(value)) => {
+                    // Promotions to superuser are only reachable through the
+                    // management API, which requires superuser privileges.
+                    match Type::parse(&value) {
+                        Some(new_type) if principal.inner.typ.can_change_to(new_type) => {
                             principal.inner.typ = new_type;
-                            continue;
+                        }
+                        _ => {
+                            return Err(DirectoryError::Management(
+                                ManagementError::InvalidValue {
+                                    field: PrincipalField::Type,
+                                    value,
+                                },
+                            ));
                         }
                     }
-                    return Err(DirectoryError::Unsupported);
                 }
                 (
                     PrincipalAction::Set,
@@ -438,6 +717,7 @@ impl ManageDirectory for Store {
                     PrincipalValue::StringList(secrets),
                 ) => {
                     principal.inner.secrets = secrets;
+                    principal.inner.password_changed = now();
                 }
                 (
                     PrincipalAction::Set,
@@ -450,9 +730,176 @@ impl ManageDirectory for Store {
                         principal.inner.description = None;
                     }
                 }
+                (PrincipalAction::Set, PrincipalField::Locale, PrincipalValue::String(locale)) => {
+                    if !locale.is_empty() {
+                        validate_locale(&locale)?;
+                        principal.inner.locale = Some(locale);
+                    } else {
+                        principal.inner.locale = None;
+                    }
+                }
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::Timezone,
+                    PrincipalValue::String(timezone),
+                ) => {
+                    if !timezone.is_empty() {
+                        validate_timezone(&timezone)?;
+                        principal.inner.timezone = Some(timezone);
+                    } else {
+                        principal.inner.timezone = None;
+                    }
+                }
                 (PrincipalAction::Set, PrincipalField::Quota, PrincipalValue::Integer(quota)) => {
                     principal.inner.quota = quota;
                 }
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::MessageCountQuota,
+                    PrincipalValue::Integer(quota),
+                ) => {
+                    principal.inner.message_count_quota =
+                        (quota > 0).then(|| quota.min(u32::MAX as u64) as u32);
+                }
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::ExternalId,
+                    PrincipalValue::String(external_id),
+                ) => {
+                    if principal.inner.external_id.as_deref().unwrap_or_default() != external_id {
+                        if !external_id.is_empty()
+                            && self.lookup_by_external_id(&external_id).await?.is_some()
+                        {
+                            return Err(DirectoryError::Management(
+                                ManagementError::AlreadyExists {
+                                    field: PrincipalField::ExternalId,
+                                    value: external_id,
+                                },
+                            ));
+                        }
+
+                        if let Some(old_external_id) = principal.inner.external_id.take() {
+                            batch.clear(ValueClass::Directory(DirectoryClass::ExternalIdToId(
+                                old_external_id.into_bytes(),
+                            )));
+                        }
+                        if !external_id.is_empty() {
+                            batch.set(
+                                ValueClass::Directory(DirectoryClass::ExternalIdToId(
+                                    external_id.as_bytes().to_vec(),
+                                )),
+                                ptype.clone(),
+                            );
+                            principal.inner.external_id = Some(external_id);
+                        }
+                    }
+                }
+
+                // Feature flags
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::Features,
+                    PrincipalValue::StringList(features),
+                ) => {
+                    let mut flags = 0;
+                    for feature in features {
+                        flags |= parse_feature(feature)?;
+                    }
+                    principal.inner.features = normalize_features(flags);
+                }
+                (
+                    PrincipalAction::AddItem,
+                    PrincipalField::Features,
+                    PrincipalValue::String(feature),
+                ) => {
+                    principal.inner.features =
+                        normalize_features(principal.inner.features | parse_feature(feature)?);
+                }
+                (
+                    PrincipalAction::RemoveItem,
+                    PrincipalField::Features,
+                    PrincipalValue::String(feature),
+                ) => {
+                    principal.inner.features &= !parse_feature(feature)?;
+                }
+
+                // Custom attributes
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::Attributes,
+                    PrincipalValue::StringList(attributes),
+                ) => {
+                    let mut new_attributes = BTreeMap::<String, Vec<String>>::new();
+                    for attribute in attributes {
+                        let (name, value) = parse_attribute(attribute)?;
+                        let values = new_attributes.entry(name).or_default();
+                        if !values.contains(&value) {
+                            values.push(value);
+                        }
+                    }
+                    principal.inner.attributes = new_attributes;
+                }
+                (
+                    PrincipalAction::AddItem,
+                    PrincipalField::Attributes,
+                    PrincipalValue::String(attribute),
+                ) => {
+                    let (name, value) = parse_attribute(attribute)?;
+                    let values = principal.inner.attributes.entry(name).or_default();
+                    if !values.contains(&value) {
+                        values.push(value);
+                    }
+                }
+                (
+                    PrincipalAction::RemoveItem,
+                    PrincipalField::Attributes,
+                    PrincipalValue::String(attribute),
+                ) => {
+                    let (name, value) = parse_attribute(attribute)?;
+                    if let Some(values) = principal.inner.attributes.get_mut(&name) {
+                        values.retain(|item| item != &value);
+                        if values.is_empty() {
+                            principal.inner.attributes.remove(&name);
+                        }
+                    }
+                }
+
+                // Allowed login networks
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::AllowedNetworks,
+                    PrincipalValue::StringList(networks),
+                ) => {
+                    let mut allowed_networks = Vec::with_capacity(networks.len());
+                    for network in networks {
+                        let network = parse_network(network)?;
+                        if !allowed_networks.contains(&network) {
+                            allowed_networks.push(network);
+                        }
+                    }
+                    principal.inner.allowed_networks = allowed_networks;
+                }
+                (
+                    PrincipalAction::AddItem,
+                    PrincipalField::AllowedNetworks,
+                    PrincipalValue::String(network),
+                ) => {
+                    let network = parse_network(network)?;
+                    if !principal.inner.allowed_networks.contains(&network) {
+                        principal.inner.allowed_networks.push(network);
+                    }
+                }
+                (
+                    PrincipalAction::RemoveItem,
+                    PrincipalField::AllowedNetworks,
+                    PrincipalValue::String(network),
+                ) => {
+                    let network = parse_network(network)?;
+                    principal
+                        .inner
+                        .allowed_networks
+                        .retain(|item| item != &network);
+                }
 
                 // Emails
                 (
@@ -467,7 +914,7 @@ impl ManageDirectory for Store {
                         .collect::<Vec<_>>();
                     for email in &emails {
                         if !principal.inner.emails.contains(email) {
-                            if self.rcpt(email).await? {
+                            if get_email_id(self, email).await?.is_some() {
                                 return Err(DirectoryError::Management(
                                     ManagementError::AlreadyExists {
                                         field: PrincipalField::Emails,
@@ -508,7 +955,7 @@ impl ManageDirectory for Store {
                 ) => {
                     let email = email.to_lowercase();
                     if !principal.inner.emails.contains(&email) {
-                        if self.rcpt(&email).await? {
+                        if get_email_id(self, &email).await?.is_some() {
                             return Err(DirectoryError::Management(
                                 ManagementError::AlreadyExists {
                                     field: PrincipalField::Emails,
@@ -744,6 +1191,23 @@ impl ManageDirectory for Store {
 
         self.write(batch.build()).await?;
 
+        if update_memberships {
+            // Principals whose groups were added or removed
+            membership_changed(
+                (member_of.iter().any(|id| !prev_member_of.contains(id))
+                    || prev_member_of.iter().any(|id| !member_of.contains(id)))
+                .then_some(account_id)
+                .into_iter()
+                .chain(
+                    members
+                        .iter()
+                        .filter(|id| !prev_members.contains(id))
+                        .chain(prev_members.iter().filter(|id| !members.contains(id)))
+                        .copied(),
+                ),
+            );
+        }
+
         Ok(())
     }
 
@@ -785,11 +1249,19 @@ impl ManageDirectory for Store {
             id: principal.id,
             typ: principal.typ,
             quota: principal.quota,
+            message_count_quota: principal.message_count_quota,
             name: principal.name,
             secrets: principal.secrets,
             emails: principal.emails,
             member_of: Vec::with_capacity(principal.member_of.len()),
             description: principal.description,
+            attributes: principal.attributes,
+            allowed_networks: principal.allowed_networks,
+            locale: principal.locale,
+            timezone: principal.timezone,
+            features: principal.features,
+            external_id: principal.external_id,
+            password_changed: principal.password_changed,
         };
 
         for account_id in principal.member_of {
@@ -810,6 +1282,7 @@ impl ManageDirectory for Store {
             id: principal.id,
             typ: principal.typ,
             quota: principal.quota,
+            message_count_quota: principal.message_count_quota,
             name: principal.name,
             secrets: principal.secrets,
             emails: principal.emails,
@@ -817,6 +1290,13 @@ impl ManageDirectory for Store {
                 .map_group_names(principal.member_of, create_if_missing)
                 .await?,
             description: principal.description,
+            attributes: principal.attributes,
+            allowed_networks: principal.allowed_networks,
+            locale: principal.locale,
+            timezone: principal.timezone,
+            features: principal.features,
+            external_id: principal.external_id,
+            password_changed: principal.password_changed,
         })
     }
 
@@ -909,6 +1389,212 @@ impl ManageDirectory for Store {
         }
     }
 
+    async fn export_accounts(&self, export_version: u8) -> crate::Result<Vec<AccountExport>> {
+        if export_version > PRINCIPAL_VERSION {
+            return Err(DirectoryError::Unsupported);
+        }
+
+        let from_key = ValueKey::from(ValueClass::Directory(DirectoryClass::NameToId(vec![])));
+        let to_key = ValueKey::from(ValueClass::Directory(DirectoryClass::NameToId(vec![
+            u8::MAX;
+            10
+        ])));
+
+        let mut account_ids = Vec::new();
+        self.iterate(
+            IterateParams::new(from_key, to_key).ascending(),
+            |_, value| {
+                account_ids.push(PrincipalIdType::deserialize(value)?.account_id);
+                Ok(true)
+            },
+        )
+        .await?;
+
+        // Each entry is written using the layout of the requested schema version,
+        // principals using fields unknown to that version cannot be exported and
+        // are all reported back. Layouts older than version 8 do not store the
+        // time of the last password change, which is dropped from the export.
+        let mut entries = Vec::with_capacity(account_ids.len());
+        let mut unsupported = Vec::new();
+        for account_id in account_ids {
+            let principal = self
+                .get_value::<Principal<u32>>(ValueKey::from(ValueClass::Directory(
+                    DirectoryClass::Principal(account_id),
+                )))
+                .await?
+                .ok_or_else(|| {
+                    DirectoryError::Management(ManagementError::NotFound(account_id.to_string()))
+                })?;
+            let Some(entry) = principal.serialize_version(export_version) else {
+                unsupported.push(principal.name);
+                continue;
+            };
+            let mut member_of = Vec::new();
+            for group_id in self.get_member_of(account_id).await? {
+                if let Some(name) = self.get_account_name(group_id).await? {
+                    member_of.push(name);
+                }
+            }
+            entries.push(AccountExport { entry, member_of });
+        }
+
+        if unsupported.is_empty() {
+            Ok(entries)
+        } else {
+            Err(DirectoryError::Management(
+                ManagementError::UnsupportedVersion {
+                    version: export_version,
+                    accounts: unsupported,
+                },
+            ))
+        }
+    }
+
+    async fn import_accounts(&self, entries: Vec<AccountExport>) -> crate::Result<Vec<u32>> {
+        // Decode all entries before writing anything
+        let mut principals = Vec::with_capacity(entries.len());
+        for entry in entries {
+            principals.push((
+                Principal::<u32>::deserialize(&entry.entry)?,
+                entry.member_of,
+            ));
+        }
+
+        // Accounts are created with new ids and group memberships are restored once
+        // all accounts exist, as groups may appear after their members in the export.
+        // Any failure removes the accounts created so far.
+        let mut account_ids = Vec::with_capacity(principals.len());
+        let mut result = Ok(());
+        for (principal, _) in &principals {
+            let created = async {
+                let principal = self
+                    .map_group_ids(Principal {
+                        member_of: vec![],
+                        ..principal.clone()
+                    })
+                    .await?;
+                self.create_account(principal, vec![]).await
+            }
+            .await;
+            match created {
+                Ok(account_id) => account_ids.push(account_id),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        if result.is_ok() {
+            for (account_id, (_, member_of)) in account_ids.iter().zip(principals) {
+                if !member_of.is_empty() {
+                    if let Err(err) = self
+                        .update_account(
+                            QueryBy::Id(*account_id),
+                            vec![PrincipalUpdate::set(
+                                PrincipalField::MemberOf,
+                                PrincipalValue::StringList(member_of),
+                            )],
+                        )
+                        .await
+                    {
+                        result = Err(err);
+                        break;
+                    }
+                }
+            }
+        }
+
+        match result {
+            Ok(()) => Ok(account_ids),
+            Err(err) => {
+                for account_id in account_ids {
+                    self.delete_account(QueryBy::Id(account_id)).await?;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    async fn search(
+        &self,
+        filter: &PrincipalFilter,
+        page: usize,
+        page_size: usize,
+    ) -> crate::Result<(Vec<Principal<u32>>, usize)> {
+        // Names are stored in order, a prefix narrows down the range to scan
+        let prefix = filter
+            .name_prefix
+            .as_deref()
+            .unwrap_or_default()
+            .to_lowercase()
+            .into_bytes();
+        let mut to_name = prefix.clone();
+        to_name.extend_from_slice(&[u8::MAX; 10]);
+        let from_key = ValueKey::from(ValueClass::Directory(DirectoryClass::NameToId(
+            prefix.clone(),
+        )));
+        let to_key = ValueKey::from(ValueClass::Directory(DirectoryClass::NameToId(to_name)));
+
+        // Superusers are indexed as individuals, so telling them apart
+        // requires fetching the principal
+        let base_type = filter.typ.map(|t| t.into_base_type());
+        let mut account_ids = Vec::new();
+        self.iterate(
+            IterateParams::new(from_key, to_key).ascending(),
+            |key, value| {
+                let pt = PrincipalIdType::deserialize(value)?;
+
+                if key.get(1..).map_or(false, |name| name.starts_with(&prefix))
+                    && base_type.map_or(true, |t| pt.typ == t)
+                {
+                    account_ids.push(pt.account_id);
+                }
+
+                Ok(true)
+            },
+        )
+        .await?;
+
+        let offset = page.saturating_sub(1) * page_size;
+        let limit = if page_size > 0 { page_size } else { usize::MAX };
+        let needs_principal = filter.email.is_some() || base_type == Some(Type::Individual);
+        let email = filter.email.as_ref().map(|e| e.to_lowercase());
+        let mut total = 0;
+        let mut results = Vec::new();
+
+        for account_id in account_ids {
+            if !needs_principal && (total < offset || results.len() >= limit) {
+                total += 1;
+                continue;
+            }
+
+            let principal = self
+                .get_value::<Principal<u32>>(ValueKey::from(ValueClass::Directory(
+                    DirectoryClass::Principal(account_id),
+                )))
+                .await?
+                .ok_or_else(|| {
+                    DirectoryError::Management(ManagementError::NotFound(account_id.to_string()))
+                })?;
+
+            if filter.typ.map_or(true, |t| principal.typ == t)
+                && email.as_ref().map_or(true, |e| {
+                    principal
+                        .emails
+                        .iter()
+                        .any(|email| email.to_lowercase().contains(e))
+                })
+            {
+                if total >= offset && results.len() < limit {
+                    results.push(principal);
+                }
+                total += 1;
+            }
+        }
+
+        Ok((results, total))
+    }
+
     async fn list_domains(&self, filter: Option<&str>) -> crate::Result<Vec<String>> {
         let from_key = ValueKey::from(ValueClass::Directory(DirectoryClass::Domain(vec![])));
         let to_key = ValueKey::from(ValueClass::Directory(DirectoryClass::Domain(vec![
@@ -953,6 +1639,29 @@ impl ManageDirectory for Store {
         Ok(results)
     }
 
+    async fn groups_of(&self, account_id: u32, transitive: bool) -> crate::Result<Vec<u32>> {
+        let mut groups = self.get_member_of(account_id).await?;
+        if !transitive {
+            return Ok(groups);
+        }
+
+        // Walk the membership graph breadth-first, skipping groups that
+        // were already visited so that cyclic memberships terminate.
+        let mut seen = groups.iter().copied().collect::<AHashSet<_>>();
+        seen.insert(account_id);
+        let mut pos = 0;
+        while let Some(group_id) = groups.get(pos).copied() {
+            for member_of in self.get_member_of(group_id).await? {
+                if seen.insert(member_of) {
+                    groups.push(member_of);
+                }
+            }
+            pos += 1;
+        }
+
+        Ok(groups)
+    }
+
     async fn get_members(&self, account_id: u32) -> crate::Result<Vec<u32>> {
         let from_key = ValueKey::from(ValueClass::Directory(DirectoryClass::Members {
             principal_id: account_id,
@@ -975,7 +1684,8 @@ impl ManageDirectory for Store {
     }
 
     async fn init(self) -> crate::Result<Self> {
-        // Create admin account if requested
+        // Create admin account if requested, secrets provisioned here
+        // are not subject to the password policy
         if let (Ok(admin_user), Ok(admin_pass)) = (
             std::env::var("SET_ADMIN_USER"),
             std::env::var("SET_ADMIN_PASS"),
@@ -1060,11 +1770,47 @@ impl From<Principal<String>> for Principal<u32> {
             id: principal.id,
             typ: principal.typ,
             quota: principal.quota,
+            message_count_quota: principal.message_count_quota,
             name: principal.name,
             secrets: principal.secrets,
             emails: principal.emails,
             member_of: Vec::with_capacity(0),
             description: principal.description,
+            attributes: principal.attributes,
+            allowed_networks: principal.allowed_networks,
+            locale: principal.locale,
+            timezone: principal.timezone,
+            features: principal.features,
+            external_id: principal.external_id,
+            password_changed: principal.password_changed,
         }
     }
 }
+
+fn parse_attribute(attribute: String) -> crate::Result<(String, String)> {
+    match attribute.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(DirectoryError::Management(ManagementError::InvalidValue {
+            field: PrincipalField::Attributes,
+            value: attribute,
+        })),
+    }
+}
+
+fn parse_network(network: String) -> crate::Result<IpAddrMask> {
+    IpAddrMask::parse_value("allowedNetworks", &network).map_err(|_| {
+        DirectoryError::Management(ManagementError::InvalidValue {
+            field: PrincipalField::AllowedNetworks,
+            value: network,
+        })
+    })
+}
+
+fn parse_feature(feature: String) -> crate::Result<u32> {
+    feature_from_name(&feature).ok_or_else(|| {
+        DirectoryError::Management(ManagementError::InvalidValue {
+            field: PrincipalField::Features,
+            value: feature,
+        })
+    })
+}
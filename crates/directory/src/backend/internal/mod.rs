@@ -21,15 +21,26 @@
  * for more details.
 */
 
+pub mod defaults;
+pub mod ldif;
 pub mod lookup;
 pub mod manage;
+pub mod password;
+pub mod policy;
+pub mod preferences;
+pub mod reserved;
 
-use std::{fmt::Display, slice::Iter, str::FromStr};
+use std::{collections::BTreeMap, fmt::Display, slice::Iter, str::FromStr};
 
 use store::{write::key::KeySerializer, Deserialize, Serialize, U32_LEN};
-use utils::codec::leb128::Leb128Iterator;
+use utils::{
+    codec::leb128::Leb128Iterator,
+    config::{ipmask::IpAddrMask, utils::ParseValue},
+};
 
-use crate::{Principal, Type};
+use crate::{feature_names, Principal, Type, FEATURES_ALL};
+
+pub const PRINCIPAL_VERSION: u8 = 8;
 
 pub(super) struct PrincipalIdType {
     pub account_id: u32,
@@ -44,15 +55,70 @@ impl Serialize for Principal<u32> {
 
 impl Serialize for &Principal<u32> {
     fn serialize(self) -> Vec<u8> {
+        // Older versions are written when possible to remain readable by previous releases
+        self.serialize_as(self.min_version())
+    }
+}
+
+impl Principal<u32> {
+    // Oldest layout able to represent all the fields set in this principal
+    fn min_version(&self) -> u8 {
+        if self.password_changed != 0 {
+            8
+        } else {
+            self.min_export_version()
+        }
+    }
+
+    // Same as `min_version`, except that the time of the last password change is
+    // not considered: exports using older layouts drop it and leave the password
+    // untracked.
+    fn min_export_version(&self) -> u8 {
+        if self.message_count_quota.is_some() {
+            7
+        } else if self.external_id.is_some() {
+            6
+        } else if self.features != FEATURES_ALL {
+            5
+        } else if self.locale.is_some() || self.timezone.is_some() {
+            4
+        } else if !self.allowed_networks.is_empty() {
+            3
+        } else if !self.attributes.is_empty() {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Serializes the principal using the layout of a specific schema version,
+    /// returns `None` if the version is unknown or cannot hold all its fields.
+    /// Versions older than 8 silently drop the time of the last password change.
+    pub fn serialize_version(&self, version: u8) -> Option<Vec<u8>> {
+        (self.min_export_version()..=PRINCIPAL_VERSION)
+            .contains(&version)
+            .then(|| self.serialize_as(version))
+    }
+
+    fn serialize_as(&self, version: u8) -> Vec<u8> {
         let mut serializer = KeySerializer::new(
-            U32_LEN * 3
+            U32_LEN * 4
                 + 2
                 + self.name.len()
                 + self.emails.iter().map(|s| s.len()).sum::<usize>()
                 + self.secrets.iter().map(|s| s.len()).sum::<usize>()
-                + self.description.as_ref().map(|s| s.len()).unwrap_or(0),
+                + self.description.as_ref().map(|s| s.len()).unwrap_or(0)
+                + self
+                    .attributes
+                    .iter()
+                    .map(|(k, v)| k.len() + v.iter().map(|s| s.len()).sum::<usize>())
+                    .sum::<usize>()
+                + self.allowed_networks.len() * 16
+                + self.locale.as_ref().map_or(0, |s| s.len())
+                + self.timezone.as_ref().map_or(0, |s| s.len())
+                + self.external_id.as_ref().map_or(0, |s| s.len()),
         )
-        .write(1u8)
+        .write(version)
         .write_leb128(self.id)
         .write(self.typ as u8)
         .write_leb128(self.quota)
@@ -61,13 +127,69 @@ impl Serialize for &Principal<u32> {
         .write_leb128(self.description.as_ref().map_or(0, |s| s.len()))
         .write(self.description.as_deref().unwrap_or_default().as_bytes());
 
-        for list in [&self.secrets, &self.emails] {
-            serializer = serializer.write_leb128(list.len());
-            for value in list {
+        serializer = serializer.write_leb128(self.secrets.len());
+        for value in &self.secrets {
+            serializer = serializer.write_leb128(value.len()).write(value.as_bytes());
+        }
+
+        // Aliases are sorted so that equal principals serialize identically,
+        // the primary address is always kept first
+        let mut emails = self.emails.iter().collect::<Vec<_>>();
+        if let Some((_, aliases)) = emails.split_first_mut() {
+            aliases.sort_unstable();
+        }
+        serializer = serializer.write_leb128(emails.len());
+        for value in emails {
+            serializer = serializer.write_leb128(value.len()).write(value.as_bytes());
+        }
+
+        if version >= 2 {
+            serializer = serializer.write_leb128(self.attributes.len());
+            for (name, values) in &self.attributes {
+                serializer = serializer
+                    .write_leb128(name.len())
+                    .write(name.as_bytes())
+                    .write_leb128(values.len());
+                for value in values {
+                    serializer = serializer.write_leb128(value.len()).write(value.as_bytes());
+                }
+            }
+        }
+
+        if version >= 3 {
+            serializer = serializer.write_leb128(self.allowed_networks.len());
+            for network in &self.allowed_networks {
+                let network = network.to_string();
+                serializer = serializer
+                    .write_leb128(network.len())
+                    .write(network.as_bytes());
+            }
+        }
+
+        if version >= 4 {
+            for value in [&self.locale, &self.timezone] {
+                let value = value.as_deref().unwrap_or_default();
                 serializer = serializer.write_leb128(value.len()).write(value.as_bytes());
             }
         }
 
+        if version >= 5 {
+            serializer = serializer.write_leb128(self.features);
+        }
+
+        if version >= 6 {
+            let value = self.external_id.as_deref().unwrap_or_default();
+            serializer = serializer.write_leb128(value.len()).write(value.as_bytes());
+        }
+
+        if version >= 7 {
+            serializer = serializer.write_leb128(self.message_count_quota.unwrap_or_default());
+        }
+
+        if version >= 8 {
+            serializer = serializer.write_leb128(self.password_changed);
+        }
+
         serializer.finalize()
     }
 }
@@ -110,14 +232,16 @@ impl PrincipalIdType {
 
 fn deserialize(bytes: &[u8]) -> Option<Principal<u32>> {
     let mut bytes = bytes.iter();
-    if bytes.next()? != &1 {
+    let version = *bytes.next()?;
+    if !matches!(version, 1..=PRINCIPAL_VERSION) {
         return None;
     }
 
-    Principal {
+    let mut principal = Principal {
         id: bytes.next_leb128()?,
         typ: Type::from_u8(*bytes.next()?),
         quota: bytes.next_leb128()?,
+        message_count_quota: None,
         name: deserialize_string(&mut bytes)?,
         description: deserialize_string(&mut bytes).map(|v| {
             if !v.is_empty() {
@@ -129,8 +253,63 @@ fn deserialize(bytes: &[u8]) -> Option<Principal<u32>> {
         secrets: deserialize_string_list(&mut bytes)?,
         emails: deserialize_string_list(&mut bytes)?,
         member_of: Vec::new(),
+        attributes: Default::default(),
+        allowed_networks: Vec::new(),
+        locale: None,
+        timezone: None,
+        features: FEATURES_ALL,
+        external_id: None,
+        password_changed: 0,
+    };
+
+    // Version 2 adds custom attributes
+    if version >= 2 {
+        for _ in 0..bytes.next_leb128::<usize>()? {
+            principal.attributes.insert(
+                deserialize_string(&mut bytes)?,
+                deserialize_string_list(&mut bytes)?,
+            );
+        }
+    }
+
+    // Version 3 adds allowed login networks
+    if version >= 3 {
+        for network in deserialize_string_list(&mut bytes)? {
+            principal
+                .allowed_networks
+                .push(IpAddrMask::parse_value("network", &network).ok()?);
+        }
+    }
+
+    // Version 4 adds locale and timezone preferences
+    if version >= 4 {
+        principal.locale = deserialize_string(&mut bytes).map(|v| (!v.is_empty()).then_some(v))?;
+        principal.timezone =
+            deserialize_string(&mut bytes).map(|v| (!v.is_empty()).then_some(v))?;
+    }
+
+    // Version 5 adds feature flags
+    if version >= 5 {
+        principal.features = bytes.next_leb128()?;
     }
-    .into()
+
+    // Version 6 adds the identifier assigned by an external directory
+    if version >= 6 {
+        principal.external_id =
+            deserialize_string(&mut bytes).map(|v| (!v.is_empty()).then_some(v))?;
+    }
+
+    // Version 7 adds the maximum number of messages
+    if version >= 7 {
+        principal.message_count_quota = bytes.next_leb128::<u32>().map(|v| (v > 0).then_some(v))?;
+    }
+
+    // Version 8 adds the time the secrets were last changed
+    if version >= 8 {
+        principal.password_changed = bytes.next_leb128()?;
+    }
+
+    principal.into()
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -141,6 +320,8 @@ pub enum PrincipalField {
     Type,
     #[serde(rename = "quota")]
     Quota,
+    #[serde(rename = "messageCountQuota")]
+    MessageCountQuota,
     #[serde(rename = "description")]
     Description,
     #[serde(rename = "secrets")]
@@ -151,6 +332,18 @@ pub enum PrincipalField {
     MemberOf,
     #[serde(rename = "members")]
     Members,
+    #[serde(rename = "locale")]
+    Locale,
+    #[serde(rename = "timezone")]
+    Timezone,
+    #[serde(rename = "features")]
+    Features,
+    #[serde(rename = "externalId")]
+    ExternalId,
+    #[serde(rename = "allowedNetworks")]
+    AllowedNetworks,
+    #[serde(rename = "attributes")]
+    Attributes,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -178,6 +371,13 @@ pub enum PrincipalValue {
     Integer(u64),
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrincipalFilter {
+    pub typ: Option<Type>,
+    pub name_prefix: Option<String>,
+    pub email: Option<String>,
+}
+
 impl PrincipalUpdate {
     pub fn set(field: PrincipalField, value: PrincipalValue) -> PrincipalUpdate {
         PrincipalUpdate {
@@ -204,17 +404,149 @@ impl PrincipalUpdate {
     }
 }
 
+impl Principal<String> {
+    /// Returns the updates that transform `old` into `new`. Secrets are never
+    /// included, a changed secret is reported as the string "changed" and the
+    /// time of the last password change follows from it. Attributes are listed
+    /// as "name=value" items.
+    pub fn diff(old: &Principal<String>, new: &Principal<String>) -> Vec<PrincipalUpdate> {
+        let mut updates = Vec::new();
+
+        if old.name != new.name {
+            updates.push(PrincipalUpdate::set(
+                PrincipalField::Name,
+                PrincipalValue::String(new.name.clone()),
+            ));
+        }
+        if old.typ != new.typ {
+            updates.push(PrincipalUpdate::set(
+                PrincipalField::Type,
+                PrincipalValue::String(new.typ.as_str().to_string()),
+            ));
+        }
+        if old.quota != new.quota {
+            updates.push(PrincipalUpdate::set(
+                PrincipalField::Quota,
+                PrincipalValue::Integer(new.quota),
+            ));
+        }
+        if old.message_count_quota != new.message_count_quota {
+            updates.push(PrincipalUpdate::set(
+                PrincipalField::MessageCountQuota,
+                PrincipalValue::Integer(new.message_count_quota.unwrap_or_default() as u64),
+            ));
+        }
+        if old.description != new.description {
+            updates.push(PrincipalUpdate::set(
+                PrincipalField::Description,
+                PrincipalValue::String(new.description.clone().unwrap_or_default()),
+            ));
+        }
+        if old.locale != new.locale {
+            updates.push(PrincipalUpdate::set(
+                PrincipalField::Locale,
+                PrincipalValue::String(new.locale.clone().unwrap_or_default()),
+            ));
+        }
+        if old.timezone != new.timezone {
+            updates.push(PrincipalUpdate::set(
+                PrincipalField::Timezone,
+                PrincipalValue::String(new.timezone.clone().unwrap_or_default()),
+            ));
+        }
+        if old.features != new.features {
+            updates.push(PrincipalUpdate::set(
+                PrincipalField::Features,
+                PrincipalValue::StringList(feature_names(new.features)),
+            ));
+        }
+        if old.external_id != new.external_id {
+            updates.push(PrincipalUpdate::set(
+                PrincipalField::ExternalId,
+                PrincipalValue::String(new.external_id.clone().unwrap_or_default()),
+            ));
+        }
+        if old.secrets != new.secrets {
+            updates.push(PrincipalUpdate::set(
+                PrincipalField::Secrets,
+                PrincipalValue::String("changed".to_string()),
+            ));
+        }
+        if old.allowed_networks != new.allowed_networks {
+            updates.push(PrincipalUpdate::set(
+                PrincipalField::AllowedNetworks,
+                PrincipalValue::StringList(
+                    new.allowed_networks
+                        .iter()
+                        .map(|network| network.to_string())
+                        .collect(),
+                ),
+            ));
+        }
+
+        let old_attributes = attribute_items(&old.attributes);
+        let new_attributes = attribute_items(&new.attributes);
+        for (field, old_list, new_list) in [
+            (PrincipalField::Emails, &old.emails, &new.emails),
+            (PrincipalField::MemberOf, &old.member_of, &new.member_of),
+            (PrincipalField::Attributes, &old_attributes, &new_attributes),
+        ] {
+            let num_updates = updates.len();
+            for item in old_list {
+                if !new_list.contains(item) {
+                    updates.push(PrincipalUpdate::remove_item(
+                        field,
+                        PrincipalValue::String(item.clone()),
+                    ));
+                }
+            }
+            for item in new_list {
+                if !old_list.contains(item) {
+                    updates.push(PrincipalUpdate::add_item(
+                        field,
+                        PrincipalValue::String(item.clone()),
+                    ));
+                }
+            }
+
+            // Same items in a different order, such as a new primary address
+            if num_updates == updates.len() && old_list != new_list {
+                updates.push(PrincipalUpdate::set(
+                    field,
+                    PrincipalValue::StringList(new_list.clone()),
+                ));
+            }
+        }
+
+        updates
+    }
+}
+
+fn attribute_items(attributes: &BTreeMap<String, Vec<String>>) -> Vec<String> {
+    attributes
+        .iter()
+        .flat_map(|(name, values)| values.iter().map(move |value| format!("{name}={value}")))
+        .collect()
+}
+
 impl Display for PrincipalField {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             PrincipalField::Name => write!(f, "name"),
             PrincipalField::Type => write!(f, "type"),
             PrincipalField::Quota => write!(f, "quota"),
+            PrincipalField::MessageCountQuota => write!(f, "messageCountQuota"),
             PrincipalField::Description => write!(f, "description"),
             PrincipalField::Secrets => write!(f, "secrets"),
             PrincipalField::Emails => write!(f, "emails"),
             PrincipalField::MemberOf => write!(f, "memberOf"),
             PrincipalField::Members => write!(f, "members"),
+            PrincipalField::Locale => write!(f, "locale"),
+            PrincipalField::Timezone => write!(f, "timezone"),
+            PrincipalField::Features => write!(f, "features"),
+            PrincipalField::ExternalId => write!(f, "externalId"),
+            PrincipalField::AllowedNetworks => write!(f, "allowedNetworks"),
+            PrincipalField::Attributes => write!(f, "attributes"),
         }
     }
 }
@@ -250,6 +582,18 @@ impl Type {
         }
     }
 
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Type::Individual => "individual",
+            Type::Superuser => "superuser",
+            Type::Group => "group",
+            Type::Resource => "resource",
+            Type::Location => "location",
+            Type::List => "list",
+            Type::Other => "other",
+        }
+    }
+
     pub fn from_u8(value: u8) -> Self {
         match value {
             0 => Type::Individual,
@@ -268,6 +612,14 @@ impl Type {
             any => any,
         }
     }
+
+    /// Returns true if a principal of this type can be changed to `new_type`.
+    /// Only individuals can be promoted to superuser (and demoted back),
+    /// any other type is fixed for the lifetime of the principal.
+    pub fn can_change_to(self, new_type: Type) -> bool {
+        self == new_type
+            || (new_type != Type::Other && self.into_base_type() == new_type.into_base_type())
+    }
 }
 
 impl FromStr for Type {
@@ -0,0 +1,212 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use sha2::{Digest, Sha256};
+use utils::config::{utils::AsKey, Config};
+
+use crate::{
+    core::secret::{is_hashed_secret, secret_scheme},
+    DirectoryError, ManagementError, Principal,
+};
+
+use super::{PrincipalAction, PrincipalField, PrincipalUpdate, PrincipalValue};
+
+#[derive(Debug, Default, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_special: bool,
+    pub breach_list: Option<BloomFilter>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_hashes: u64,
+}
+
+impl PasswordPolicy {
+    pub fn from_config(config: &Config, prefix: impl AsKey) -> utils::config::Result<Self> {
+        let prefix = prefix.as_key();
+        let mut policy = PasswordPolicy {
+            min_length: config.property((&prefix, "min-length"))?.unwrap_or(0),
+            ..Default::default()
+        };
+
+        for (_, class) in config.values((&prefix, "require")) {
+            match class {
+                "lowercase" => policy.require_lowercase = true,
+                "uppercase" => policy.require_uppercase = true,
+                "digit" => policy.require_digit = true,
+                "special" => policy.require_special = true,
+                _ => {
+                    return Err(format!(
+                        "Invalid character class {class:?} for key {:?}.",
+                        (&prefix, "require").as_key()
+                    ))
+                }
+            }
+        }
+
+        if let Some(path) = config.value((&prefix, "breach-list")) {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|err| format!("Failed to read breached password list {path:?}: {err}"))?;
+            policy.breach_list =
+                BloomFilter::new(contents.lines().map(str::trim).filter(|l| !l.is_empty())).into();
+        }
+
+        Ok(policy)
+    }
+
+    /// Validates a plain text password. Secrets in a recognised hash format,
+    /// such as those produced by the CLI, cannot be inspected and are accepted as is.
+    pub fn check(&self, secret: &str) -> Result<(), String> {
+        if is_hashed_secret(secret) {
+            return Ok(());
+        }
+        let password = match secret_scheme(secret) {
+            Some(scheme)
+                if scheme.eq_ignore_ascii_case("PLAIN") || scheme.eq_ignore_ascii_case("CLEAR") =>
+            {
+                &secret[scheme.len() + 2..]
+            }
+            _ => secret,
+        };
+
+        if password.chars().count() < self.min_length {
+            return Err(format!(
+                "Password must be at least {} characters long.",
+                self.min_length
+            ));
+        }
+        for (required, matches, class) in [
+            (
+                self.require_lowercase,
+                char::is_lowercase as fn(char) -> bool,
+                "a lowercase letter",
+            ),
+            (
+                self.require_uppercase,
+                char::is_uppercase,
+                "an uppercase letter",
+            ),
+            (
+                self.require_digit,
+                |ch: char| ch.is_ascii_digit(),
+                "a digit",
+            ),
+            (
+                self.require_special,
+                |ch: char| !ch.is_alphanumeric(),
+                "a special character",
+            ),
+        ] {
+            if required && !password.chars().any(matches) {
+                return Err(format!("Password must contain at least {class}."));
+            }
+        }
+        if self
+            .breach_list
+            .as_ref()
+            .map_or(false, |list| list.contains(password))
+        {
+            return Err("Password has appeared in a data breach.".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn check_principal(&self, principal: &Principal<String>) -> crate::Result<()> {
+        principal
+            .secrets
+            .iter()
+            .try_for_each(|secret| self.check_secret(secret))
+    }
+
+    pub fn check_updates(&self, changes: &[PrincipalUpdate]) -> crate::Result<()> {
+        for change in changes {
+            match (&change.action, &change.field, &change.value) {
+                (
+                    PrincipalAction::Set | PrincipalAction::AddItem,
+                    PrincipalField::Secrets,
+                    PrincipalValue::String(secret),
+                ) => self.check_secret(secret)?,
+                (
+                    PrincipalAction::Set | PrincipalAction::AddItem,
+                    PrincipalField::Secrets,
+                    PrincipalValue::StringList(secrets),
+                ) => secrets
+                    .iter()
+                    .try_for_each(|secret| self.check_secret(secret))?,
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_secret(&self, secret: &str) -> crate::Result<()> {
+        self.check(secret)
+            .map_err(|err| DirectoryError::Management(ManagementError::WeakPassword(err)))
+    }
+}
+
+impl BloomFilter {
+    const FALSE_POSITIVE_RATE: f64 = 0.001;
+
+    pub fn new<'x>(items: impl Iterator<Item = &'x str> + Clone) -> Self {
+        let num_items = std::cmp::max(items.clone().count(), 1) as f64;
+        let num_bits = (-num_items * Self::FALSE_POSITIVE_RATE.ln()
+            / std::f64::consts::LN_2.powi(2))
+        .ceil() as u64;
+        let num_hashes = ((num_bits as f64 / num_items) * std::f64::consts::LN_2)
+            .round()
+            .max(1.0) as u64;
+
+        let mut filter = BloomFilter {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_hashes,
+        };
+        for item in items {
+            for bit in filter.bit_positions(item) {
+                filter.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+            }
+        }
+        filter
+    }
+
+    pub fn contains(&self, item: &str) -> bool {
+        self.bit_positions(item)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    fn bit_positions(&self, item: &str) -> impl Iterator<Item = u64> {
+        let hash = Sha256::digest(item.as_bytes());
+        let h1 = u64::from_le_bytes(hash[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(hash[8..16].try_into().unwrap());
+        let num_bits = self.bits.len() as u64 * 64;
+        (0..self.num_hashes).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
@@ -0,0 +1,118 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use crate::{DirectoryError, ManagementError, Principal};
+
+use super::PrincipalField;
+
+/// Returns true if the value is a well-formed BCP 47 language tag,
+/// such as "en", "en-US", "zh-Hant-TW" or "es-419".
+pub fn is_valid_locale(locale: &str) -> bool {
+    let mut subtags = locale.split('-').peekable();
+
+    // Primary language subtag
+    match subtags.next() {
+        Some(language)
+            if matches!(language.len(), 2 | 3 | 5..=8)
+                && language.bytes().all(|ch| ch.is_ascii_alphabetic()) => {}
+        _ => return false,
+    }
+
+    // Optional script subtag
+    if subtags.peek().map_or(false, |s| {
+        s.len() == 4 && s.bytes().all(|ch| ch.is_ascii_alphabetic())
+    }) {
+        subtags.next();
+    }
+
+    // Optional region subtag
+    if subtags.peek().map_or(false, |s| {
+        (s.len() == 2 && s.bytes().all(|ch| ch.is_ascii_alphabetic()))
+            || (s.len() == 3 && s.bytes().all(|ch| ch.is_ascii_digit()))
+    }) {
+        subtags.next();
+    }
+
+    // Variants, extensions and private use subtags
+    let mut has_singleton = false;
+    let mut expect_subtag = false;
+    for subtag in subtags {
+        if subtag.is_empty()
+            || subtag.len() > 8
+            || !subtag.bytes().all(|ch| ch.is_ascii_alphanumeric())
+        {
+            return false;
+        } else if subtag.len() == 1 {
+            has_singleton = true;
+            expect_subtag = true;
+        } else if has_singleton
+            || subtag.len() >= 5
+            || (subtag.len() == 4 && subtag.as_bytes()[0].is_ascii_digit())
+        {
+            expect_subtag = false;
+        } else {
+            return false;
+        }
+    }
+
+    !expect_subtag
+}
+
+/// Returns true if the value is a time zone name from the IANA database,
+/// such as "UTC", "Europe/Madrid" or "America/Argentina/Buenos_Aires".
+pub fn is_valid_timezone(timezone: &str) -> bool {
+    timezone.parse::<chrono_tz::Tz>().is_ok()
+}
+
+pub fn validate_locale(locale: &str) -> crate::Result<()> {
+    if is_valid_locale(locale) {
+        Ok(())
+    } else {
+        Err(DirectoryError::Management(ManagementError::InvalidValue {
+            field: PrincipalField::Locale,
+            value: locale.to_string(),
+        }))
+    }
+}
+
+pub fn validate_timezone(timezone: &str) -> crate::Result<()> {
+    if is_valid_timezone(timezone) {
+        Ok(())
+    } else {
+        Err(DirectoryError::Management(ManagementError::InvalidValue {
+            field: PrincipalField::Timezone,
+            value: timezone.to_string(),
+        }))
+    }
+}
+
+pub fn validate_principal<T>(principal: &Principal<T>) -> crate::Result<()> {
+    if let Some(locale) = &principal.locale {
+        validate_locale(locale)?;
+    }
+    if let Some(timezone) = &principal.timezone {
+        validate_timezone(timezone)?;
+    }
+
+    Ok(())
+}
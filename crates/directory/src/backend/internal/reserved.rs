@@ -0,0 +1,105 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use utils::config::{utils::AsKey, Config};
+
+use crate::core::config::LookupType;
+
+/// Principal names that external directories cannot provision, only a
+/// superuser can create accounts using them.
+#[derive(Debug, Clone)]
+pub struct ReservedNames {
+    pub lookup_type: LookupType,
+    pub names: Vec<String>,
+}
+
+impl Default for ReservedNames {
+    fn default() -> Self {
+        ReservedNames {
+            lookup_type: LookupType::Glob,
+            names: Vec::new(),
+        }
+    }
+}
+
+impl ReservedNames {
+    pub fn from_config(config: &Config, prefix: impl AsKey) -> utils::config::Result<Self> {
+        let prefix = prefix.as_key();
+        let lookup_type = config
+            .property::<LookupType>((&prefix, "type"))?
+            .unwrap_or(LookupType::Glob);
+        if !matches!(lookup_type, LookupType::List | LookupType::Glob) {
+            return Err(format!(
+                "Invalid lookup type {lookup_type:?} for key {:?}, expected \"list\" or \"glob\".",
+                (&prefix, "type").as_key()
+            ));
+        }
+
+        Ok(ReservedNames {
+            lookup_type,
+            names: config
+                .values((&prefix, "names"))
+                .map(|(_, name)| name.trim().to_lowercase())
+                .filter(|name| !name.is_empty())
+                .collect(),
+        })
+    }
+
+    pub fn is_reserved(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.names.iter().any(|reserved| match self.lookup_type {
+            LookupType::Glob => glob_match(reserved.as_bytes(), name.as_bytes()),
+            _ => reserved == &name,
+        })
+    }
+}
+
+// Matches a pattern where '*' stands for any sequence of characters
+// and '?' for a single character.
+fn glob_match(pattern: &[u8], value: &[u8]) -> bool {
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack = None;
+
+    while v < value.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(&ch) if ch == b'?' || ch == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bv)) => {
+                    p = bp + 1;
+                    v = bv + 1;
+                    backtrack = Some((bp, bv + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&ch| ch == b'*')
+}
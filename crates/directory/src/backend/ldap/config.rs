@@ -27,7 +27,10 @@ use ldap3::LdapConnSettings;
 use store::Store;
 use utils::config::{utils::AsKey, Config};
 
-use crate::core::config::build_pool;
+use crate::{
+    backend::internal::reserved::ReservedNames,
+    core::config::{build_keepalive, build_pool},
+};
 
 use super::{Bind, LdapConnectionManager, LdapDirectory, LdapFilter, LdapMappings};
 
@@ -44,25 +47,54 @@ impl LdapDirectory {
             None
         };
 
+        let url = config.value_require_((&prefix, "url"))?.to_string();
+        let starttls = match config.value((&prefix, "tls.mode")).map(|v| v.to_string()) {
+            Some(mode) => {
+                let is_ldaps = url.starts_with("ldaps://");
+                match mode.as_str() {
+                    "starttls" | "none" if !is_ldaps => mode == "starttls",
+                    "implicit" if is_ldaps => false,
+                    "starttls" | "none" | "implicit" => {
+                        config.new_parse_error(
+                            (&prefix, "tls.mode"),
+                            format!("TLS mode {mode:?} cannot be used with URL {url:?}"),
+                        );
+                        return None;
+                    }
+                    _ => {
+                        config.new_parse_error(
+                            (&prefix, "tls.mode"),
+                            format!(
+                                "Invalid TLS mode {mode:?}, expected \"none\", \"starttls\" or \"implicit\""
+                            ),
+                        );
+                        return None;
+                    }
+                }
+            }
+            None => config
+                .property_or_default_((&prefix, "tls.enable"), "false")
+                .unwrap_or_default(),
+        };
+
         let manager = LdapConnectionManager::new(
-            config.value_require_((&prefix, "url"))?.to_string(),
+            url,
             LdapConnSettings::new()
                 .set_conn_timeout(
                     config
                         .property_or_default_((&prefix, "timeout"), "30s")
                         .unwrap_or_else(|| Duration::from_secs(30)),
                 )
-                .set_starttls(
-                    config
-                        .property_or_default_((&prefix, "tls.enable"), "false")
-                        .unwrap_or_default(),
-                )
+                .set_starttls(starttls)
                 .set_no_tls_verify(
                     config
                         .property_or_default_((&prefix, "tls.allow-invalid-certs"), "false")
                         .unwrap_or_default(),
                 ),
             bind_dn,
+            // The LDAP client does not expose its socket, so connections idle for
+            // longer than the keepalive time are replaced instead
+            build_keepalive(config, &prefix)?.map(|keepalive| keepalive.time),
         );
 
         let mut mappings = LdapMappings {
@@ -100,10 +132,22 @@ impl LdapDirectory {
                 .values((&prefix, "attributes.quota"))
                 .map(|(_, v)| v.to_string())
                 .collect(),
+            attr_message_count_quota: config
+                .values((&prefix, "attributes.message-count-quota"))
+                .map(|(_, v)| v.to_string())
+                .collect(),
             attr_email_alias: config
                 .values((&prefix, "attributes.email-alias"))
                 .map(|(_, v)| v.to_string())
                 .collect(),
+            attr_extra: config
+                .values((&prefix, "attributes.extra"))
+                .map(|(_, v)| v.to_string())
+                .collect(),
+            attr_external_id: config
+                .values((&prefix, "attributes.external-id"))
+                .map(|(_, v)| v.to_string())
+                .collect(),
             attrs_principal: vec!["objectClass".to_string()],
         };
 
@@ -113,9 +157,12 @@ impl LdapDirectory {
             &mappings.attr_description,
             &mappings.attr_secret,
             &mappings.attr_quota,
+            &mappings.attr_message_count_quota,
             &mappings.attr_groups,
             &mappings.attr_email_address,
             &mappings.attr_email_alias,
+            &mappings.attr_extra,
+            &mappings.attr_external_id,
         ] {
             mappings.attrs_principal.extend(attr.iter().cloned());
         }
@@ -137,6 +184,9 @@ impl LdapDirectory {
                 })
                 .ok()?,
             auth_bind,
+            reserved_names: ReservedNames::from_config(config, "directory.reserved-names")
+                .map_err(|err| config.new_parse_error("directory.reserved-names", err))
+                .ok()?,
             data_store,
         })
     }
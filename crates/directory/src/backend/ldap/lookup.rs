@@ -126,14 +126,38 @@ impl LdapDirectory {
         // Obtain account ID if not available
         if let Some(account_id) = account_id {
             principal.id = account_id;
+        } else if let Some(account_id) = self
+            .data_store
+            .provision_account_id(
+                &account_name,
+                principal.external_id.as_deref(),
+                &self.reserved_names,
+            )
+            .await?
+        {
+            principal.id = account_id;
         } else {
-            principal.id = self
-                .data_store
-                .get_or_create_account_id(&account_name)
-                .await?;
+            return Ok(None);
         }
         principal.name = account_name;
 
+        // Keep a copy of the mapped attributes, the lookup itself does not depend on it
+        if !self.mappings.attr_extra.is_empty() {
+            if let Err(err) = self
+                .data_store
+                .sync_attributes(principal.id, &principal.attributes)
+                .await
+            {
+                tracing::warn!(
+                    context = "directory",
+                    event = "error",
+                    account_id = principal.id,
+                    reason = ?err,
+                    "Failed to store principal attributes"
+                );
+            }
+        }
+
         // Obtain groups
         if return_member_of && !principal.member_of.is_empty() {
             for member_of in principal.member_of.iter_mut() {
@@ -194,7 +218,13 @@ impl LdapDirectory {
             'outer: for attr in &self.mappings.attr_name {
                 if let Some(name) = entry.attrs.get(attr).and_then(|v| v.first()) {
                     if !name.is_empty() {
-                        ids.push(self.data_store.get_or_create_account_id(name).await?);
+                        if let Some(account_id) = self
+                            .data_store
+                            .provision_account_id(name, None, &self.reserved_names)
+                            .await?
+                        {
+                            ids.push(account_id);
+                        }
                         break 'outer;
                     }
                 }
@@ -281,6 +311,11 @@ impl LdapDirectory {
         Ok(emails)
     }
 
+    pub async fn health_check(&self) -> crate::Result<()> {
+        self.pool.get().await?;
+        Ok(())
+    }
+
     pub async fn is_local_domain(&self, domain: &str) -> crate::Result<bool> {
         self.pool
             .get()
@@ -359,6 +394,10 @@ impl LdapMappings {
                 if let Ok(quota) = value.into_iter().next().unwrap_or_default().parse() {
                     principal.quota = quota;
                 }
+            } else if self.attr_message_count_quota.contains(&attr) {
+                if let Ok(quota) = value.into_iter().next().unwrap_or_default().parse::<u32>() {
+                    principal.message_count_quota = (quota > 0).then_some(quota);
+                }
             } else if self.attr_type.contains(&attr) {
                 for value in value {
                     match value.to_ascii_lowercase().as_str() {
@@ -373,6 +412,10 @@ impl LdapMappings {
                     }
                     break;
                 }
+            } else if self.attr_external_id.contains(&attr) {
+                principal.external_id = value.into_iter().next().filter(|v| !v.is_empty());
+            } else if self.attr_extra.contains(&attr) {
+                principal.attributes.entry(attr).or_default().extend(value);
             }
         }
 
@@ -21,10 +21,14 @@
  * for more details.
 */
 
+use std::time::Duration;
+
 use deadpool::managed::Pool;
 use ldap3::{ldap_escape, LdapConnSettings};
 use store::Store;
 
+use super::internal::reserved::ReservedNames;
+
 pub mod config;
 pub mod lookup;
 pub mod pool;
@@ -33,6 +37,7 @@ pub struct LdapDirectory {
     pool: Pool<LdapConnectionManager>,
     mappings: LdapMappings,
     auth_bind: Option<LdapFilter>,
+    reserved_names: ReservedNames,
     pub(crate) data_store: Store,
 }
 
@@ -52,6 +57,9 @@ pub struct LdapMappings {
     attr_email_address: Vec<String>,
     attr_email_alias: Vec<String>,
     attr_quota: Vec<String>,
+    attr_message_count_quota: Vec<String>,
+    attr_extra: Vec<String>,
+    attr_external_id: Vec<String>,
     attrs_principal: Vec<String>,
 }
 
@@ -71,6 +79,7 @@ pub(crate) struct LdapConnectionManager {
     address: String,
     settings: LdapConnSettings,
     bind_dn: Option<Bind>,
+    max_idle: Option<Duration>,
 }
 
 pub(crate) struct Bind {
@@ -79,11 +88,17 @@ pub(crate) struct Bind {
 }
 
 impl LdapConnectionManager {
-    pub fn new(address: String, settings: LdapConnSettings, bind_dn: Option<Bind>) -> Self {
+    pub fn new(
+        address: String,
+        settings: LdapConnSettings,
+        bind_dn: Option<Bind>,
+        max_idle: Option<Duration>,
+    ) -> Self {
         Self {
             address,
             settings,
             bind_dn,
+            max_idle,
         }
     }
 }
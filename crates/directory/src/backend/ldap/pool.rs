@@ -48,8 +48,17 @@ impl managed::Manager for LdapConnectionManager {
     async fn recycle(
         &self,
         conn: &mut Ldap,
-        _: &managed::Metrics,
+        metrics: &managed::Metrics,
     ) -> managed::RecycleResult<LdapError> {
+        if self
+            .max_idle
+            .map_or(false, |max_idle| metrics.last_used() > max_idle)
+        {
+            return Err(managed::RecycleError::StaticMessage(
+                "No longer valid: Idle for longer than the keepalive time",
+            ));
+        }
+
         conn.extended(WhoAmI)
             .await
             .map(|_| ())
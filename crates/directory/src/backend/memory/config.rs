@@ -21,10 +21,17 @@
  * for more details.
 */
 
-use store::Store;
+use store::{Store, Stores};
 use utils::config::{utils::AsKey, Config};
 
-use crate::{backend::internal::manage::ManageDirectory, Principal, Type};
+use crate::{
+    backend::internal::manage::ManageDirectory,
+    core::{
+        config::{LookupMap, QueryMap},
+        secret::{secret_scheme, SECRET_SCHEMES},
+    },
+    Principal, Type,
+};
 
 use super::{EmailType, MemoryDirectory};
 
@@ -32,6 +39,7 @@ impl MemoryDirectory {
     pub async fn from_config(
         config: &mut Config,
         prefix: impl AsKey,
+        stores: &Stores,
         data_store: Store,
     ) -> Option<Self> {
         let prefix = prefix.as_key();
@@ -39,6 +47,8 @@ impl MemoryDirectory {
             data_store,
             principals: Default::default(),
             emails_to_ids: Default::default(),
+            aliases: Default::default(),
+            aliases_query: None,
             domains: Default::default(),
         };
 
@@ -136,12 +146,31 @@ impl MemoryDirectory {
                 }
             }
 
+            // Parse secrets, which may be hashed using any supported scheme
+            let mut secrets = Vec::new();
+            for (key, secret) in config
+                .values((prefix.as_str(), "principals", lookup_id, "secret"))
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+            {
+                match secret_scheme(&secret) {
+                    Some(scheme)
+                        if !SECRET_SCHEMES
+                            .iter()
+                            .any(|s| s.eq_ignore_ascii_case(scheme)) =>
+                    {
+                        config.new_parse_error(
+                            key,
+                            format!("Unsupported password scheme {{{scheme}}}"),
+                        );
+                    }
+                    _ => secrets.push(secret),
+                }
+            }
+
             directory.principals.push(Principal {
                 name: name.clone(),
-                secrets: config
-                    .values((prefix.as_str(), "principals", lookup_id, "secret"))
-                    .map(|(_, v)| v.to_string())
-                    .collect(),
+                secrets,
                 typ,
                 description: config
                     .value((prefix.as_str(), "principals", lookup_id, "description"))
@@ -152,9 +181,60 @@ impl MemoryDirectory {
                 member_of,
                 id,
                 emails,
+                attributes: Default::default(),
+                allowed_networks: config
+                    .properties_((prefix.as_str(), "principals", lookup_id, "allowed-networks"))
+                    .into_iter()
+                    .map(|(_, network)| network)
+                    .collect(),
+                ..Default::default()
             });
         }
 
+        // Parse aliases
+        directory.aliases = LookupMap::from_config(config, (prefix.as_str(), "aliases"));
+        directory.aliases_query =
+            QueryMap::from_config(config, (prefix.as_str(), "aliases"), stores);
+        for alias in directory.aliases.keys() {
+            if let Some((_, domain)) = alias.rsplit_once('@') {
+                directory.domains.insert(domain.to_string());
+            }
+        }
+
         Some(directory)
     }
+
+    #[cfg(feature = "test_mode")]
+    pub fn from_principals(principals: Vec<Principal<u32>>, data_store: Store) -> Self {
+        let mut directory = MemoryDirectory {
+            data_store,
+            principals: Default::default(),
+            emails_to_ids: Default::default(),
+            aliases: Default::default(),
+            aliases_query: None,
+            domains: Default::default(),
+        };
+
+        for mut principal in principals {
+            for (pos, email) in principal.emails.iter_mut().enumerate() {
+                *email = email.to_lowercase();
+                directory
+                    .emails_to_ids
+                    .entry(email.clone())
+                    .or_default()
+                    .push(if pos > 0 {
+                        EmailType::Alias(principal.id)
+                    } else {
+                        EmailType::Primary(principal.id)
+                    });
+
+                if let Some((_, domain)) = email.rsplit_once('@') {
+                    directory.domains.insert(domain.to_string());
+                }
+            }
+            directory.principals.push(principal);
+        }
+
+        directory
+    }
 }
@@ -66,7 +66,7 @@ impl MemoryDirectory {
     }
 
     pub async fn email_to_ids(&self, address: &str) -> crate::Result<Vec<u32>> {
-        Ok(self
+        let mut result = self
             .emails_to_ids
             .get(address)
             .map(|names| {
@@ -79,11 +79,38 @@ impl MemoryDirectory {
                     })
                     .collect::<Vec<_>>()
             })
-            .unwrap_or_default())
+            .unwrap_or_default();
+
+        // Resolve aliases pointing to local accounts
+        for destination in self.alias_destinations(address).await? {
+            for item in self
+                .emails_to_ids
+                .get(&destination.to_lowercase())
+                .map(|names| names.as_slice())
+                .unwrap_or_default()
+            {
+                if let EmailType::Primary(uid) | EmailType::Alias(uid) = item {
+                    if !result.contains(uid) {
+                        result.push(*uid);
+                    }
+                }
+            }
+        }
+
+        Ok(result)
     }
 
     pub async fn rcpt(&self, address: &str) -> crate::Result<bool> {
-        Ok(self.emails_to_ids.contains_key(address))
+        if self.emails_to_ids.contains_key(address) || self.aliases.contains(address) {
+            Ok(true)
+        } else if let Some(aliases) = &self.aliases_query {
+            aliases
+                .lookup_map(address)
+                .await
+                .map(|values| values.is_some())
+        } else {
+            Ok(false)
+        }
     }
 
     pub async fn vrfy(&self, address: &str) -> crate::Result<Vec<String>> {
@@ -114,10 +141,30 @@ impl MemoryDirectory {
                 }
             }
         }
+
+        // Aliases can also forward to remote addresses
+        for destination in self.alias_destinations(address).await? {
+            if !result.contains(&destination) {
+                result.push(destination);
+            }
+        }
+
         Ok(result)
     }
 
     pub async fn is_local_domain(&self, domain: &str) -> crate::Result<bool> {
         Ok(self.domains.contains(domain))
     }
+
+    async fn alias_destinations(&self, address: &str) -> crate::Result<Vec<String>> {
+        let mut destinations = self.aliases.lookup_multi(address);
+        if let Some(aliases) = &self.aliases_query {
+            for destination in aliases.lookup_map(address).await?.unwrap_or_default() {
+                if !destinations.contains(&destination) {
+                    destinations.push(destination);
+                }
+            }
+        }
+        Ok(destinations)
+    }
 }
@@ -24,7 +24,10 @@
 use ahash::{AHashMap, AHashSet};
 use store::Store;
 
-use crate::Principal;
+use crate::{
+    core::config::{LookupMap, QueryMap},
+    Principal,
+};
 
 pub mod config;
 pub mod lookup;
@@ -33,6 +36,8 @@ pub mod lookup;
 pub struct MemoryDirectory {
     principals: Vec<Principal<u32>>,
     emails_to_ids: AHashMap<String, Vec<EmailType>>,
+    aliases: LookupMap,
+    aliases_query: Option<QueryMap>,
     pub(crate) data_store: Store,
     domains: AHashSet<String>,
 }
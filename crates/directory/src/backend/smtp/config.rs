@@ -23,10 +23,10 @@
 
 use std::time::Duration;
 
-use mail_send::{smtp::tls::build_tls_connector, SmtpClientBuilder};
+use mail_send::{smtp::tls::build_tls_connector, Credentials, SmtpClientBuilder};
 use utils::config::{utils::AsKey, Config};
 
-use crate::core::config::build_pool;
+use crate::core::config::{build_keepalive, build_pool};
 
 use super::{SmtpConnectionManager, SmtpDirectory};
 
@@ -41,6 +41,17 @@ impl SmtpDirectory {
             .property_or_default_((&prefix, "port"), if tls_implicit { "465" } else { "25" })
             .unwrap_or(if tls_implicit { 465 } else { 25 });
 
+        let local_host = if config.contains_key("server.hostname") {
+            match config.server_hostname() {
+                Ok(hostname) => hostname.to_string(),
+                Err(err) => {
+                    config.new_parse_error("server.hostname", err);
+                    return None;
+                }
+            }
+        } else {
+            "[127.0.0.1]".to_string()
+        };
         let manager = SmtpConnectionManager {
             builder: SmtpClientBuilder {
                 addr: format!("{address}:{port}"),
@@ -56,12 +67,10 @@ impl SmtpDirectory {
                 tls_implicit,
                 is_lmtp,
                 credentials: None,
-                local_host: config
-                    .value("server.hostname")
-                    .unwrap_or("[127.0.0.1]")
-                    .to_string(),
+                local_host,
                 say_ehlo: false,
             },
+            keepalive: build_keepalive(config, &prefix)?,
             max_rcpt: config
                 .property_or_default_((&prefix, "limits.rcpt"), "10")
                 .unwrap_or(10),
@@ -70,6 +79,16 @@ impl SmtpDirectory {
                 .unwrap_or(10),
         };
 
+        let relay_credentials = config
+            .value((&prefix, "relay.auth.username"))
+            .map(|username| Credentials::Plain {
+                username: username.to_string(),
+                secret: config
+                    .value((&prefix, "relay.auth.secret"))
+                    .unwrap_or_default()
+                    .to_string(),
+            });
+
         Some(SmtpDirectory {
             pool: build_pool(config, &prefix, manager)
                 .map_err(|e| {
@@ -83,6 +102,7 @@ impl SmtpDirectory {
                 .values((&prefix, "lookup.domains"))
                 .map(|(_, v)| v.to_lowercase())
                 .collect(),
+            relay_credentials,
         })
     }
 }
@@ -43,6 +43,7 @@ impl SmtpDirectory {
 
     pub async fn rcpt(&self, address: &str) -> crate::Result<bool> {
         let mut conn = self.pool.get().await?;
+        conn.is_valid = false;
         if !conn.sent_mail_from {
             conn.client
                 .cmd(b"MAIL FROM:<>\r\n")
@@ -54,6 +55,7 @@ impl SmtpDirectory {
             .client
             .cmd(format!("RCPT TO:<{address}>\r\n").as_bytes())
             .await?;
+        conn.is_valid = true;
         match reply.severity() {
             Severity::PositiveCompletion => {
                 conn.num_rcpts += 1;
@@ -88,6 +90,11 @@ impl SmtpDirectory {
     pub async fn is_local_domain(&self, domain: &str) -> crate::Result<bool> {
         Ok(self.domains.contains(domain))
     }
+
+    pub async fn health_check(&self) -> crate::Result<()> {
+        self.pool.get().await?;
+        Ok(())
+    }
 }
 
 impl SmtpClient {
@@ -95,6 +102,8 @@ impl SmtpClient {
         &mut self,
         credentials: &Credentials<String>,
     ) -> crate::Result<Option<Principal<u32>>> {
+        // Authenticated sessions cannot be reused for other lookups
+        self.is_valid = false;
         match self
             .client
             .authenticate(credentials, &self.capabilities)
@@ -104,6 +113,7 @@ impl SmtpClient {
             Err(err) => match &err {
                 mail_send::Error::AuthenticationFailed(err) if err.code() == 535 => {
                     self.num_auth_failures += 1;
+                    self.is_valid = true;
                     Ok(None)
                 }
                 _ => Err(err.into()),
@@ -112,7 +122,9 @@ impl SmtpClient {
     }
 
     async fn expand(&mut self, command: &str) -> crate::Result<Vec<String>> {
+        self.is_valid = false;
         let reply = self.client.cmd(command.as_bytes()).await?;
+        self.is_valid = true;
         match reply.code() {
             250 | 251 => Ok(reply
                 .message()
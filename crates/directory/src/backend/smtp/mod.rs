@@ -24,21 +24,26 @@
 pub mod config;
 pub mod lookup;
 pub mod pool;
+pub mod relay;
 
 use ahash::AHashSet;
 use deadpool::managed::Pool;
-use mail_send::SmtpClientBuilder;
+use mail_send::{Credentials, SmtpClientBuilder};
 use smtp_proto::EhloResponse;
 use tokio::net::TcpStream;
 use tokio_rustls::client::TlsStream;
 
+use crate::core::config::TcpKeepalive;
+
 pub struct SmtpDirectory {
     pool: Pool<SmtpConnectionManager>,
     domains: AHashSet<String>,
+    relay_credentials: Option<Credentials<String>>,
 }
 
 pub struct SmtpConnectionManager {
     builder: SmtpClientBuilder<String>,
+    keepalive: Option<TcpKeepalive>,
     max_rcpt: usize,
     max_auth_errors: usize,
 }
@@ -48,7 +53,9 @@ pub struct SmtpClient {
     capabilities: EhloResponse<String>,
     max_rcpt: usize,
     max_auth_errors: usize,
+    is_lmtp: bool,
     num_rcpts: usize,
     num_auth_failures: usize,
     sent_mail_from: bool,
+    is_valid: bool,
 }
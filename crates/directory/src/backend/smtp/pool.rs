@@ -34,6 +34,9 @@ impl managed::Manager for SmtpConnectionManager {
 
     async fn create(&self) -> Result<SmtpClient, Error> {
         let mut client = self.builder.connect().await?;
+        if let Some(keepalive) = &self.keepalive {
+            keepalive.apply(client.stream.get_ref().0);
+        }
         let capabilities = client
             .capabilities(&self.builder.local_host, self.builder.is_lmtp)
             .await?;
@@ -43,9 +46,11 @@ impl managed::Manager for SmtpConnectionManager {
             client,
             max_auth_errors: self.max_auth_errors,
             max_rcpt: self.max_rcpt,
+            is_lmtp: self.builder.is_lmtp,
             num_rcpts: 0,
             num_auth_failures: 0,
             sent_mail_from: false,
+            is_valid: true,
         })
     }
 
@@ -54,7 +59,11 @@ impl managed::Manager for SmtpConnectionManager {
         conn: &mut SmtpClient,
         _: &managed::Metrics,
     ) -> managed::RecycleResult<Error> {
-        if conn.num_auth_failures < conn.max_auth_errors {
+        if !conn.is_valid {
+            Err(managed::RecycleError::StaticMessage(
+                "No longer valid: Command interrupted or session authenticated",
+            ))
+        } else if conn.num_auth_failures < conn.max_auth_errors {
             conn.client
                 .cmd(b"NOOP\r\n")
                 .await?
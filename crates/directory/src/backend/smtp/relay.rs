@@ -0,0 +1,230 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{borrow::Cow, fmt::Write};
+
+use deadpool::managed::Manager;
+use smtp_proto::{
+    Response, Severity, EXT_8BIT_MIME, EXT_AUTH, EXT_DSN, EXT_SIZE, MAIL_RET_FULL, MAIL_RET_HDRS,
+    RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
+};
+
+use super::{SmtpClient, SmtpDirectory};
+
+pub struct RelayEnvelope<'x> {
+    pub return_path: &'x str,
+    pub flags: u64,
+    pub env_id: Option<&'x str>,
+    pub authenticated_as: Option<&'x str>,
+    pub recipients: Vec<RelayRecipient<'x>>,
+}
+
+pub struct RelayRecipient<'x> {
+    pub address: &'x str,
+    pub flags: u64,
+    pub orcpt: Option<&'x str>,
+}
+
+impl SmtpDirectory {
+    /// Submits a message to the backend server, returning one reply per
+    /// recipient: the reply to its RCPT command when it was not accepted,
+    /// otherwise the reply to the end of the DATA phase.
+    ///
+    /// Pooled connections carry the authentication state of the last lookup,
+    /// so messages are relayed over a dedicated connection that authenticates
+    /// with the configured relay credentials and asserts the submitting user
+    /// with the AUTH parameter.
+    pub async fn relay(
+        &self,
+        envelope: &RelayEnvelope<'_>,
+        message: &[u8],
+    ) -> crate::Result<Vec<Response<String>>> {
+        let mut conn = self.pool.manager().create().await?;
+        if let Some(credentials) = &self.relay_credentials {
+            conn.client
+                .authenticate(credentials, &conn.capabilities)
+                .await?;
+        }
+
+        let result = conn.relay(envelope, message).await;
+        let _ = conn.client.quit().await;
+        result
+    }
+}
+
+impl SmtpClient {
+    async fn relay(
+        &mut self,
+        envelope: &RelayEnvelope<'_>,
+        message: &[u8],
+    ) -> crate::Result<Vec<Response<String>>> {
+        let num_rcpts = envelope.recipients.len();
+        let has_dsn = self.capabilities.has_capability(EXT_DSN);
+        let mut mail_from = String::with_capacity(envelope.return_path.len() + 64);
+        let _ = write!(
+            mail_from,
+            "MAIL FROM:<{}>",
+            quote_address(envelope.return_path)
+        );
+        if self.capabilities.has_capability(EXT_SIZE) {
+            let _ = write!(mail_from, " SIZE={}", message.len());
+        }
+        if self.capabilities.has_capability(EXT_8BIT_MIME) && !message.is_ascii() {
+            mail_from.push_str(" BODY=8BITMIME");
+        }
+        if has_dsn {
+            if envelope.flags & MAIL_RET_FULL != 0 {
+                mail_from.push_str(" RET=FULL");
+            } else if envelope.flags & MAIL_RET_HDRS != 0 {
+                mail_from.push_str(" RET=HDRS");
+            }
+            if let Some(env_id) = envelope.env_id {
+                mail_from.push_str(" ENVID=");
+                xtext_encode(&mut mail_from, env_id);
+            }
+        }
+        if let Some(authenticated_as) = envelope
+            .authenticated_as
+            .filter(|_| self.capabilities.has_capability(EXT_AUTH))
+        {
+            mail_from.push_str(" AUTH=");
+            xtext_encode(&mut mail_from, authenticated_as);
+        }
+        mail_from.push_str("\r\n");
+
+        let reply = self.client.cmd(mail_from.as_bytes()).await?;
+        if reply.severity() != Severity::PositiveCompletion {
+            return Ok(vec![reply; num_rcpts]);
+        }
+
+        // Rejected recipients keep their RCPT reply, the rest are filled in
+        // once the message has been transferred
+        let mut replies = Vec::with_capacity(num_rcpts);
+        let mut accepted = Vec::with_capacity(num_rcpts);
+        for (idx, rcpt) in envelope.recipients.iter().enumerate() {
+            let mut rcpt_to = String::with_capacity(rcpt.address.len() + 64);
+            let _ = write!(rcpt_to, "RCPT TO:<{}>", quote_address(rcpt.address));
+            if has_dsn {
+                if rcpt.flags & RCPT_NOTIFY_NEVER != 0 {
+                    rcpt_to.push_str(" NOTIFY=NEVER");
+                } else if rcpt.flags
+                    & (RCPT_NOTIFY_SUCCESS | RCPT_NOTIFY_FAILURE | RCPT_NOTIFY_DELAY)
+                    != 0
+                {
+                    rcpt_to.push_str(" NOTIFY=");
+                    let notify = [
+                        (RCPT_NOTIFY_SUCCESS, "SUCCESS"),
+                        (RCPT_NOTIFY_DELAY, "DELAY"),
+                        (RCPT_NOTIFY_FAILURE, "FAILURE"),
+                    ]
+                    .into_iter()
+                    .filter(|(flag, _)| rcpt.flags & flag != 0)
+                    .map(|(_, name)| name)
+                    .collect::<Vec<_>>();
+                    rcpt_to.push_str(&notify.join(","));
+                }
+                if let Some(orcpt) = rcpt.orcpt {
+                    rcpt_to.push_str(" ORCPT=rfc822;");
+                    xtext_encode(&mut rcpt_to, orcpt);
+                }
+            }
+            rcpt_to.push_str("\r\n");
+
+            let reply = self.client.cmd(rcpt_to.as_bytes()).await?;
+            if reply.severity() == Severity::PositiveCompletion {
+                accepted.push(idx);
+            }
+            replies.push(reply);
+        }
+        if accepted.is_empty() {
+            self.client.rset().await?;
+            return Ok(replies);
+        }
+
+        let reply = self.client.cmd(b"DATA\r\n").await?;
+        if reply.code() != 354 {
+            self.client.rset().await?;
+            for idx in accepted {
+                replies[idx] = reply.clone();
+            }
+            return Ok(replies);
+        }
+
+        // LMTP servers reply once per accepted recipient
+        let num_replies = if self.is_lmtp { accepted.len() } else { 1 };
+        let data_replies = tokio::time::timeout(self.client.timeout, async {
+            self.client.write_message(message).await?;
+            self.client.read_many(num_replies).await
+        })
+        .await
+        .map_err(|_| mail_send::Error::Timeout)??;
+        for (pos, idx) in accepted.into_iter().enumerate() {
+            replies[idx] = data_replies
+                .get(pos)
+                .or_else(|| data_replies.last())
+                .cloned()
+                .ok_or(mail_send::Error::UnparseableReply)?;
+        }
+
+        Ok(replies)
+    }
+}
+
+// Quotes the local part of an address when it is not a valid dot-atom (RFC 5321, section 4.1.2)
+fn quote_address(address: &str) -> Cow<'_, str> {
+    let (local_part, domain) = address.rsplit_once('@').unwrap_or((address, ""));
+    let is_atext = |ch: char| ch.is_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(ch);
+    if local_part.is_empty()
+        || local_part
+            .split('.')
+            .all(|atom| !atom.is_empty() && atom.chars().all(is_atext))
+    {
+        return address.into();
+    }
+
+    let mut quoted = String::with_capacity(address.len() + 4);
+    quoted.push('"');
+    for ch in local_part.chars().filter(|ch| !ch.is_control()) {
+        if matches!(ch, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(ch);
+    }
+    quoted.push('"');
+    if !domain.is_empty() {
+        quoted.push('@');
+        quoted.push_str(domain);
+    }
+    quoted.into()
+}
+
+// Encodes a value as xtext (RFC 3461, section 4)
+fn xtext_encode(buf: &mut String, value: &str) {
+    for byte in value.bytes() {
+        if (33..=126).contains(&byte) && byte != b'+' && byte != b'=' {
+            buf.push(byte as char);
+        } else {
+            let _ = write!(buf, "+{byte:02X}");
+        }
+    }
+}
@@ -21,10 +21,14 @@
  * for more details.
 */
 
+use std::time::Duration;
+
 use store::{Store, Stores};
 use utils::config::{utils::AsKey, Config};
 
-use super::{SqlDirectory, SqlMappings};
+use crate::backend::internal::reserved::ReservedNames;
+
+use super::{SqlDirectory, SqlMappings, SqlRetry};
 
 impl SqlDirectory {
     pub fn from_config(
@@ -56,10 +60,18 @@ impl SqlDirectory {
                 .value((&prefix, "columns.quota"))
                 .unwrap_or_default()
                 .to_string(),
+            column_message_count_quota: config
+                .value((&prefix, "columns.message-count-quota"))
+                .unwrap_or_default()
+                .to_string(),
             column_type: config
                 .value((&prefix, "columns.class"))
                 .unwrap_or_default()
                 .to_string(),
+            columns_extra: config
+                .values((&prefix, "columns.extra"))
+                .map(|(_, v)| v.to_string())
+                .collect(),
             ..Default::default()
         };
 
@@ -78,9 +90,22 @@ impl SqlDirectory {
                 .to_string();
         }
 
+        let retry = SqlRetry {
+            attempts: config
+                .property_or_default_((&prefix, "retry.attempts"), "2")
+                .unwrap_or(2),
+            backoff: config
+                .property_or_default_((&prefix, "retry.backoff"), "100ms")
+                .unwrap_or_else(|| Duration::from_millis(100)),
+        };
+
         Some(SqlDirectory {
             store,
             mappings,
+            retry,
+            reserved_names: ReservedNames::from_config(config, "directory.reserved-names")
+                .map_err(|err| config.new_parse_error("directory.reserved-names", err))
+                .ok()?,
             data_store,
         })
     }
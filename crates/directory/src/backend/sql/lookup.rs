@@ -22,7 +22,7 @@
 */
 
 use mail_send::Credentials;
-use store::{NamedRows, Rows, Value};
+use store::{NamedRows, QueryResult, Rows, Value};
 
 use crate::{backend::internal::manage::ManageDirectory, Principal, QueryBy, Type};
 
@@ -42,8 +42,7 @@ impl SqlDirectory {
             QueryBy::Name(username) => {
                 account_name = username.to_string();
 
-                self.store
-                    .query::<NamedRows>(&self.mappings.query_name, vec![username.into()])
+                self.run_query::<NamedRows>(&self.mappings.query_name, vec![username.into()])
                     .await?
             }
             QueryBy::Id(uid) => {
@@ -54,12 +53,11 @@ impl SqlDirectory {
                 }
                 account_id = Some(uid);
 
-                self.store
-                    .query::<NamedRows>(
-                        &self.mappings.query_name,
-                        vec![account_name.clone().into()],
-                    )
-                    .await?
+                self.run_query::<NamedRows>(
+                    &self.mappings.query_name,
+                    vec![account_name.clone().into()],
+                )
+                .await?
             }
             QueryBy::Credentials(credentials) => {
                 let (username, secret_) = match credentials {
@@ -70,8 +68,7 @@ impl SqlDirectory {
                 account_name = username.to_string();
                 secret = secret_.into();
 
-                self.store
-                    .query::<NamedRows>(&self.mappings.query_name, vec![username.into()])
+                self.run_query::<NamedRows>(&self.mappings.query_name, vec![username.into()])
                     .await?
             }
         };
@@ -100,19 +97,38 @@ impl SqlDirectory {
         // Obtain account ID if not available
         if let Some(account_id) = account_id {
             principal.id = account_id;
+        } else if let Some(account_id) = self
+            .data_store
+            .provision_account_id(&account_name, None, &self.reserved_names)
+            .await?
+        {
+            principal.id = account_id;
         } else {
-            principal.id = self
-                .data_store
-                .get_or_create_account_id(&account_name)
-                .await?;
+            return Ok(None);
         }
         principal.name = account_name;
 
+        // Keep a copy of the mapped attributes, the lookup itself does not depend on it
+        if !self.mappings.columns_extra.is_empty() {
+            if let Err(err) = self
+                .data_store
+                .sync_attributes(principal.id, &principal.attributes)
+                .await
+            {
+                tracing::warn!(
+                    context = "directory",
+                    event = "error",
+                    account_id = principal.id,
+                    reason = ?err,
+                    "Failed to store principal attributes"
+                );
+            }
+        }
+
         // Obtain members
         if return_member_of && !self.mappings.query_members.is_empty() {
             for row in self
-                .store
-                .query::<Rows>(
+                .run_query::<Rows>(
                     &self.mappings.query_members,
                     vec![principal.name.clone().into()],
                 )
@@ -130,8 +146,7 @@ impl SqlDirectory {
         // Obtain emails
         if !self.mappings.query_emails.is_empty() {
             principal.emails = self
-                .store
-                .query::<Rows>(
+                .run_query::<Rows>(
                     &self.mappings.query_emails,
                     vec![principal.name.clone().into()],
                 )
@@ -144,15 +159,20 @@ impl SqlDirectory {
 
     pub async fn email_to_ids(&self, address: &str) -> crate::Result<Vec<u32>> {
         let names = self
-            .store
-            .query::<Rows>(&self.mappings.query_recipients, vec![address.into()])
+            .run_query::<Rows>(&self.mappings.query_recipients, vec![address.into()])
             .await?;
 
         let mut ids = Vec::with_capacity(names.rows.len());
 
         for row in names.rows {
             if let Some(Value::Text(name)) = row.values.first() {
-                ids.push(self.data_store.get_or_create_account_id(name).await?);
+                if let Some(account_id) = self
+                    .data_store
+                    .provision_account_id(name, None, &self.reserved_names)
+                    .await?
+                {
+                    ids.push(account_id);
+                }
             }
         }
 
@@ -160,43 +180,49 @@ impl SqlDirectory {
     }
 
     pub async fn rcpt(&self, address: &str) -> crate::Result<bool> {
-        self.store
-            .query::<bool>(
-                &self.mappings.query_recipients,
-                vec![address.to_string().into()],
-            )
-            .await
-            .map_err(Into::into)
+        self.run_query::<bool>(
+            &self.mappings.query_recipients,
+            vec![address.to_string().into()],
+        )
+        .await
+        .map_err(Into::into)
     }
 
     pub async fn vrfy(&self, address: &str) -> crate::Result<Vec<String>> {
-        self.store
-            .query::<Rows>(
-                &self.mappings.query_verify,
-                vec![address.to_string().into()],
-            )
-            .await
-            .map(Into::into)
-            .map_err(Into::into)
+        self.run_query::<Rows>(
+            &self.mappings.query_verify,
+            vec![address.to_string().into()],
+        )
+        .await
+        .map(Into::into)
+        .map_err(Into::into)
     }
 
     pub async fn expn(&self, address: &str) -> crate::Result<Vec<String>> {
-        self.store
-            .query::<Rows>(
-                &self.mappings.query_expand,
-                vec![address.to_string().into()],
-            )
-            .await
-            .map(Into::into)
-            .map_err(Into::into)
+        self.run_query::<Rows>(
+            &self.mappings.query_expand,
+            vec![address.to_string().into()],
+        )
+        .await
+        .map(Into::into)
+        .map_err(Into::into)
     }
 
     pub async fn is_local_domain(&self, domain: &str) -> crate::Result<bool> {
-        self.store
-            .query::<bool>(&self.mappings.query_domains, vec![domain.into()])
+        self.run_query::<bool>(&self.mappings.query_domains, vec![domain.into()])
             .await
             .map_err(Into::into)
     }
+
+    async fn run_query<T: QueryResult + std::fmt::Debug>(
+        &self,
+        query: &str,
+        params: Vec<Value<'_>>,
+    ) -> store::Result<T> {
+        self.retry
+            .run(|| self.store.query::<T>(query, params.clone()))
+            .await
+    }
 }
 
 impl SqlMappings {
@@ -224,6 +250,23 @@ impl SqlMappings {
                     if let Value::Integer(quota) = value {
                         principal.quota = quota as u64;
                     }
+                } else if name.eq_ignore_ascii_case(&self.column_message_count_quota) {
+                    if let Value::Integer(quota) = value {
+                        principal.message_count_quota =
+                            u32::try_from(quota).ok().filter(|quota| *quota > 0);
+                    }
+                } else if let Some(column) = self
+                    .columns_extra
+                    .iter()
+                    .find(|column| name.eq_ignore_ascii_case(column))
+                {
+                    if !matches!(value, Value::Null) {
+                        principal
+                            .attributes
+                            .entry(column.clone())
+                            .or_default()
+                            .push(value.to_str().into_owned());
+                    }
                 }
             }
         }
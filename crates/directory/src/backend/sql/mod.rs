@@ -21,17 +21,29 @@
  * for more details.
 */
 
+use std::{future::Future, time::Duration};
+
 use store::{LookupStore, Store};
 
+use super::internal::reserved::ReservedNames;
+
 pub mod config;
 pub mod lookup;
 
 pub struct SqlDirectory {
     store: LookupStore,
     mappings: SqlMappings,
+    retry: SqlRetry,
+    reserved_names: ReservedNames,
     pub(crate) data_store: Store,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct SqlRetry {
+    pub attempts: u32,
+    pub backoff: Duration,
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct SqlMappings {
     query_name: String,
@@ -44,5 +56,40 @@ pub(crate) struct SqlMappings {
     column_description: String,
     column_secret: String,
     column_quota: String,
+    column_message_count_quota: String,
     column_type: String,
+    columns_extra: Vec<String>,
+}
+
+impl SqlRetry {
+    /// Runs a query, retrying up to `attempts` times with exponential backoff
+    /// when the backend reports a transient error. Any other error is
+    /// returned immediately.
+    pub async fn run<T, F, R>(&self, mut query: F) -> store::Result<T>
+    where
+        F: FnMut() -> R,
+        R: Future<Output = store::Result<T>>,
+    {
+        let mut attempt = 0;
+        let mut backoff = self.backoff;
+
+        loop {
+            match query().await {
+                Err(store::Error::TransientError(reason)) if attempt < self.attempts => {
+                    attempt += 1;
+                    tracing::debug!(
+                        context = "directory",
+                        event = "retry",
+                        protocol = "sql",
+                        attempt = attempt,
+                        reason = reason,
+                        "Retrying query after transient error"
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = backoff.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+    }
 }
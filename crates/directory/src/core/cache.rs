@@ -23,52 +23,177 @@
 
 use std::{
     borrow::Borrow,
+    collections::VecDeque,
     hash::Hash,
     time::{Duration, Instant},
 };
 
+use mail_send::Credentials;
 use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use store::rand::{thread_rng, Rng};
 use utils::config::{utils::AsKey, Config};
 
 pub struct CachedDirectory {
     cached_domains: Mutex<LookupCache<String>>,
     cached_rcpts: Mutex<LookupCache<String>>,
+    cached_groups: Mutex<GroupCache>,
+    size: usize,
 }
 
+/// Number of entries held by each cache, `size` is the maximum number of
+/// positive and of negative entries per cache after which the least recently
+/// used ones are evicted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub size: usize,
+    pub domains: usize,
+    pub domains_negative: usize,
+    pub rcpts: usize,
+    pub rcpts_negative: usize,
+    pub groups: usize,
+}
+
+/// Transitive group closures keyed by principal id. When the groups of a
+/// principal change, its closure is discarded along with the closures that
+/// contain it, since those reach their groups through it.
+#[derive(Debug)]
+pub struct GroupCache {
+    entries: lru_cache::LruCache<u32, (Instant, Vec<u32>), ahash::RandomState>,
+    generation: u64,
+    ttl: Duration,
+    ttl_jitter: u64,
+}
+
+/// Principals whose groups changed, the last one having been recorded under
+/// `generation`. Caches apply the changes made since they last checked, and
+/// are cleared if those are no longer retained.
+struct MembershipLog {
+    generation: u64,
+    changes: VecDeque<u32>,
+}
+
+const MAX_MEMBERSHIP_CHANGES: usize = 1024;
+
+static MEMBERSHIP_LOG: Mutex<MembershipLog> = Mutex::new(MembershipLog {
+    generation: 0,
+    changes: VecDeque::new(),
+});
+
+/// Called by the internal store after adding or removing groups of the
+/// given principals.
+pub fn membership_changed(principal_ids: impl IntoIterator<Item = u32>) {
+    let mut log = MEMBERSHIP_LOG.lock();
+    for principal_id in principal_ids {
+        if log.changes.len() == MAX_MEMBERSHIP_CHANGES {
+            log.changes.pop_front();
+        }
+        log.changes.push_back(principal_id);
+        log.generation += 1;
+    }
+}
+
+pub fn membership_generation() -> u64 {
+    MEMBERSHIP_LOG.lock().generation
+}
+
+// Returns the current generation and the principals changed after `generation`,
+// or `None` if some of those changes were already discarded.
+fn membership_changes_since(generation: u64) -> (u64, Option<Vec<u32>>) {
+    let log = MEMBERSHIP_LOG.lock();
+    let pending = (log.generation - generation) as usize;
+    (
+        log.generation,
+        (pending <= log.changes.len()).then(|| {
+            log.changes
+                .range(log.changes.len() - pending..)
+                .copied()
+                .collect()
+        }),
+    )
+}
+
+/// Values returned by a map query, keys without values are cached as `None`.
 #[allow(clippy::type_complexity)]
 #[derive(Debug)]
+pub struct MapCache {
+    entries: lru_cache::LruCache<String, (Instant, Option<Vec<String>>), ahash::RandomState>,
+    ttl_pos: Duration,
+    ttl_neg: Duration,
+}
+
+/// Successful authentications keyed by a salted hash of the credentials,
+/// failed attempts are never cached.
+/// Successful authentications keyed by principal, so that a failed attempt
+/// invalidates whichever secret was cached for it.
+pub struct AuthCache {
+    entries: Mutex<lru_cache::LruCache<[u8; 32], ([u8; 32], Instant), ahash::RandomState>>,
+    salt: [u8; 32],
+    ttl: Duration,
+}
+
+/// Positive and negative lookups are held separately, each bounded by `capacity`.
+#[derive(Debug)]
 pub struct LookupCache<T: Hash + Eq> {
     cache_pos: lru_cache::LruCache<T, Instant, ahash::RandomState>,
     cache_neg: lru_cache::LruCache<T, Instant, ahash::RandomState>,
     ttl_pos: Duration,
     ttl_neg: Duration,
+    ttl_jitter: u64,
 }
 
 impl CachedDirectory {
     pub fn try_from_config(config: &mut Config, prefix: impl AsKey) -> Option<Self> {
         let prefix = prefix.as_key();
-        let cached_entries = config.property_((&prefix, "cache.entries"))?;
+        let cached_entries = config
+            .property_((&prefix, "cache.size"))
+            .or_else(|| config.property_((&prefix, "cache.entries")))?;
         let cache_ttl_positive = config
             .property_((&prefix, "cache.ttl.positive"))
             .unwrap_or(Duration::from_secs(86400));
         let cache_ttl_negative = config
-            .property_((&prefix, "cache.ttl.positive"))
+            .property_((&prefix, "cache.ttl.negative"))
             .unwrap_or_else(|| Duration::from_secs(3600));
+        let cache_ttl_jitter = config
+            .property_::<u64>((&prefix, "cache.ttl.jitter"))
+            .unwrap_or(10)
+            .min(100);
 
         Some(CachedDirectory {
             cached_domains: Mutex::new(LookupCache::new(
                 cached_entries,
                 cache_ttl_positive,
                 cache_ttl_negative,
+                cache_ttl_jitter,
             )),
             cached_rcpts: Mutex::new(LookupCache::new(
                 cached_entries,
                 cache_ttl_positive,
                 cache_ttl_negative,
+                cache_ttl_jitter,
+            )),
+            cached_groups: Mutex::new(GroupCache::new(
+                cached_entries,
+                cache_ttl_positive,
+                cache_ttl_jitter,
             )),
+            size: cached_entries,
         })
     }
 
+    pub fn stats(&self) -> CacheStats {
+        let (domains, domains_negative) = self.cached_domains.lock().len();
+        let (rcpts, rcpts_negative) = self.cached_rcpts.lock().len();
+        CacheStats {
+            size: self.size,
+            domains,
+            domains_negative,
+            rcpts,
+            rcpts_negative,
+            groups: self.cached_groups.lock().len(),
+        }
+    }
+
     pub fn get_rcpt(&self, address: &str) -> Option<bool> {
         self.cached_rcpts.lock().get(address)
     }
@@ -92,15 +217,204 @@ impl CachedDirectory {
             self.cached_domains.lock().insert_neg(domain.to_string());
         }
     }
+
+    pub fn get_groups(&self, account_id: u32) -> Option<Vec<u32>> {
+        self.cached_groups.lock().get(account_id)
+    }
+
+    pub fn set_groups(&self, account_id: u32, groups: Vec<u32>, generation: u64) {
+        self.cached_groups
+            .lock()
+            .insert(account_id, groups, generation);
+    }
 }
 
-impl<T: Hash + Eq> LookupCache<T> {
+impl AuthCache {
+    pub fn try_from_config(config: &mut Config, prefix: impl AsKey) -> Option<Self> {
+        let prefix = prefix.as_key();
+        let ttl = config
+            .property_::<Duration>((&prefix, "cache.auth.ttl"))
+            .filter(|ttl| !ttl.is_zero())?;
+        let capacity = config
+            .property_((&prefix, "cache.auth.entries"))
+            .unwrap_or(1024);
+
+        Some(AuthCache::new(capacity, ttl))
+    }
+
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(lru_cache::LruCache::with_hasher(
+                capacity,
+                ahash::RandomState::new(),
+            )),
+            salt: thread_rng().gen(),
+            ttl,
+        }
+    }
+
+    pub fn get(&self, credentials: &Credentials<String>) -> bool {
+        let (principal, secret) = self.key(credentials);
+        let mut entries = self.entries.lock();
+        match entries.get_mut(&principal) {
+            Some((_, valid_until)) if *valid_until < Instant::now() => {
+                entries.remove(&principal);
+                false
+            }
+            Some((cached_secret, _)) => *cached_secret == secret,
+            None => false,
+        }
+    }
+
+    pub fn insert(&self, credentials: &Credentials<String>) {
+        let (principal, secret) = self.key(credentials);
+        self.entries
+            .lock()
+            .insert(principal, (secret, Instant::now() + self.ttl));
+    }
+
+    pub fn remove(&self, credentials: &Credentials<String>) {
+        let (principal, _) = self.key(credentials);
+        self.entries.lock().remove(&principal);
+    }
+
+    fn key(&self, credentials: &Credentials<String>) -> ([u8; 32], [u8; 32]) {
+        match credentials {
+            Credentials::Plain { username, secret } => (
+                self.hash(&[b"user", username.as_bytes()]),
+                self.hash(&[b"plain", username.as_bytes(), secret.as_bytes()]),
+            ),
+            Credentials::XOauth2 { username, secret } => (
+                self.hash(&[b"user", username.as_bytes()]),
+                self.hash(&[b"xoauth2", username.as_bytes(), secret.as_bytes()]),
+            ),
+            Credentials::OAuthBearer { token } => (
+                self.hash(&[b"token", token.as_bytes()]),
+                self.hash(&[b"oauthbearer", token.as_bytes()]),
+            ),
+        }
+    }
+
+    // Fields are length-prefixed so that their boundaries are unambiguous
+    fn hash(&self, fields: &[&[u8]]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt);
+        for field in fields {
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field);
+        }
+        hasher.finalize().into()
+    }
+}
+
+impl GroupCache {
+    pub fn new(capacity: usize, ttl: Duration, ttl_jitter: u64) -> Self {
+        Self {
+            entries: lru_cache::LruCache::with_hasher(capacity, ahash::RandomState::new()),
+            generation: membership_generation(),
+            ttl,
+            ttl_jitter,
+        }
+    }
+
+    pub fn get(&mut self, account_id: u32) -> Option<Vec<u32>> {
+        self.sync_generation();
+        let (valid_until, groups) = self.entries.get_mut(&account_id)?;
+        if *valid_until >= Instant::now() {
+            Some(groups.clone())
+        } else {
+            self.entries.remove(&account_id);
+            None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Caches a closure computed under `generation`, closures that raced
+    /// with a change to the groups of one of their principals are dropped.
+    pub fn insert(&mut self, account_id: u32, groups: Vec<u32>, generation: u64) {
+        self.sync_generation();
+        if let (_, Some(changes)) = membership_changes_since(generation) {
+            if !changes
+                .iter()
+                .any(|id| *id == account_id || groups.contains(id))
+            {
+                let valid_until = Instant::now() + jittered_ttl(self.ttl, self.ttl_jitter);
+                self.entries.insert(account_id, (valid_until, groups));
+            }
+        }
+    }
+
+    fn sync_generation(&mut self) {
+        match membership_changes_since(self.generation) {
+            (generation, _) if generation == self.generation => (),
+            (generation, Some(changes)) => {
+                let stale = self
+                    .entries
+                    .iter()
+                    .filter(|(account_id, (_, groups))| {
+                        changes
+                            .iter()
+                            .any(|id| id == *account_id || groups.contains(id))
+                    })
+                    .map(|(account_id, _)| *account_id)
+                    .collect::<Vec<_>>();
+                for account_id in stale {
+                    self.entries.remove(&account_id);
+                }
+                self.generation = generation;
+            }
+            (generation, None) => {
+                self.entries.clear();
+                self.generation = generation;
+            }
+        }
+    }
+}
+
+impl MapCache {
     pub fn new(capacity: usize, ttl_pos: Duration, ttl_neg: Duration) -> Self {
+        Self {
+            entries: lru_cache::LruCache::with_hasher(capacity, ahash::RandomState::new()),
+            ttl_pos,
+            ttl_neg,
+        }
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<Option<Vec<String>>> {
+        let (valid_until, values) = self.entries.get_mut(key)?;
+        if *valid_until >= Instant::now() {
+            Some(values.clone())
+        } else {
+            self.entries.remove(key);
+            None
+        }
+    }
+
+    pub fn insert(&mut self, key: String, values: Option<Vec<String>>) {
+        let ttl = if values.is_some() {
+            self.ttl_pos
+        } else {
+            self.ttl_neg
+        };
+        self.entries.insert(key, (Instant::now() + ttl, values));
+    }
+}
+
+impl<T: Hash + Eq> LookupCache<T> {
+    pub fn new(capacity: usize, ttl_pos: Duration, ttl_neg: Duration, ttl_jitter: u64) -> Self {
         Self {
             cache_pos: lru_cache::LruCache::with_hasher(capacity, ahash::RandomState::new()),
             cache_neg: lru_cache::LruCache::with_hasher(capacity, ahash::RandomState::new()),
             ttl_pos,
             ttl_neg,
+            ttl_jitter,
         }
     }
 
@@ -123,21 +437,50 @@ impl<T: Hash + Eq> LookupCache<T> {
         if *valid_until >= Instant::now() {
             Some(false)
         } else {
-            self.cache_pos.remove(name);
+            self.cache_neg.remove(name);
             None
         }
     }
 
     pub fn insert_pos(&mut self, item: T) {
-        self.cache_pos.insert(item, Instant::now() + self.ttl_pos);
+        self.cache_neg.remove(&item);
+        self.cache_pos
+            .insert(item, Instant::now() + self.jittered_ttl(self.ttl_pos));
     }
 
     pub fn insert_neg(&mut self, item: T) {
-        self.cache_neg.insert(item, Instant::now() + self.ttl_neg);
+        self.cache_pos.remove(&item);
+        self.cache_neg
+            .insert(item, Instant::now() + self.jittered_ttl(self.ttl_neg));
+    }
+
+    /// Spreads the TTL by up to `ttl_jitter` percent in either direction, so
+    /// entries cached at the same time do not all expire together.
+    pub fn jittered_ttl(&self, ttl: Duration) -> Duration {
+        jittered_ttl(ttl, self.ttl_jitter)
     }
 
     pub fn clear(&mut self) {
         self.cache_pos.clear();
         self.cache_neg.clear();
     }
+
+    /// Number of positive and negative entries, each bounded by the capacity.
+    pub fn len(&self) -> (usize, usize) {
+        (self.cache_pos.len(), self.cache_neg.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache_pos.is_empty() && self.cache_neg.is_empty()
+    }
+}
+
+fn jittered_ttl(ttl: Duration, ttl_jitter: u64) -> Duration {
+    let jitter = (ttl.as_millis() as u64).saturating_mul(ttl_jitter) / 100;
+    if jitter > 0 {
+        ttl - Duration::from_millis(jitter)
+            + Duration::from_millis(thread_rng().gen_range(0..=jitter * 2))
+    } else {
+        ttl
+    }
 }
@@ -25,10 +25,14 @@ use deadpool::{
     managed::{Manager, Pool},
     Runtime,
 };
-use std::{sync::Arc, time::Duration};
-use store::{Store, Stores};
+use parking_lot::Mutex;
+use socket2::SockRef;
+use std::{net::IpAddr, sync::Arc, time::Duration};
+use store::{LookupStore, Rows, Store, Stores};
+use tokio::net::TcpStream;
 use utils::config::{
-    utils::{AsKey, ParseValue},
+    ipmask::IpAddrMask,
+    utils::{AsKey, DurationRange, ParseValue},
     Config,
 };
 
@@ -39,10 +43,10 @@ use crate::{
         imap::ImapDirectory, internal::manage::ManageDirectory, ldap::LdapDirectory,
         memory::MemoryDirectory, smtp::SmtpDirectory, sql::SqlDirectory,
     },
-    Directories, Directory, DirectoryInner,
+    Directories, Directory, DirectoryInner, LookupErrorPolicy, QuotaInheritance,
 };
 
-use super::cache::CachedDirectory;
+use super::cache::{CachedDirectory, MapCache};
 
 impl Directories {
     pub async fn parse(config: &mut Config, stores: &Stores, data_store: Store) -> Self {
@@ -102,9 +106,11 @@ impl Directories {
                 "lmtp" => {
                     SmtpDirectory::from_config(config, prefix, true).map(DirectoryInner::Smtp)
                 }
-                "memory" => MemoryDirectory::from_config(config, prefix, data_store.clone())
-                    .await
-                    .map(DirectoryInner::Memory),
+                "memory" => {
+                    MemoryDirectory::from_config(config, prefix, stores, data_store.clone())
+                        .await
+                        .map(DirectoryInner::Memory)
+                }
                 unknown => {
                     let err = format!("Unknown directory type: {unknown:?}");
                     config.new_parse_error(("directory", id, "type"), err);
@@ -117,6 +123,18 @@ impl Directories {
                 let directory = Arc::new(Directory {
                     store,
                     cache: CachedDirectory::try_from_config(config, ("directory", id)),
+                    on_error: config
+                        .property_or_default_(("directory", id, "on-error"), "tempfail")
+                        .unwrap_or_default(),
+                    quota_inheritance: config
+                        .property_or_default_(("directory", id, "quota-inheritance"), "disabled")
+                        .unwrap_or_default(),
+                    query_timeout: config
+                        .property_::<Duration>(("directory", id, "query-timeout"))
+                        .filter(|timeout| !timeout.is_zero()),
+                    password_max_age: config
+                        .property_::<Duration>(("directory", id, "password-max-age"))
+                        .filter(|max_age| !max_age.is_zero()),
                 });
 
                 // Add directory
@@ -126,6 +144,27 @@ impl Directories {
 
         Directories { directories }
     }
+
+    /// Builds a single in-memory directory named "memory" that is seeded
+    /// with the given principals, intended for unit tests.
+    #[cfg(feature = "test_mode")]
+    pub fn from_principals(principals: Vec<crate::Principal<u32>>) -> Self {
+        let directory = Arc::new(Directory {
+            store: DirectoryInner::Memory(MemoryDirectory::from_principals(
+                principals,
+                Store::default(),
+            )),
+            cache: None,
+            on_error: LookupErrorPolicy::default(),
+            quota_inheritance: QuotaInheritance::default(),
+            query_timeout: None,
+            password_max_age: None,
+        });
+
+        Directories {
+            directories: AHashMap::from_iter([("memory".to_string(), directory)]),
+        }
+    }
 }
 
 #[allow(async_fn_in_trait)]
@@ -198,7 +237,7 @@ impl ConfigDirectory for Config {
                     DirectoryInner::Smtp(SmtpDirectory::from_config(self, prefix, true).unwrap())
                 }
                 "memory" => DirectoryInner::Memory(
-                    MemoryDirectory::from_config(self, prefix, data_store.clone())
+                    MemoryDirectory::from_config(self, prefix, stores, data_store.clone())
                         .await
                         .unwrap(),
                 ),
@@ -211,6 +250,15 @@ impl ConfigDirectory for Config {
             let directory = Arc::new(Directory {
                 store,
                 cache: CachedDirectory::try_from_config(self, ("directory", id)),
+                on_error: self.property_or_default(("directory", id, "on-error"), "tempfail")?,
+                quota_inheritance: self
+                    .property_or_default(("directory", id, "quota-inheritance"), "disabled")?,
+                query_timeout: self
+                    .property::<Duration>(("directory", id, "query-timeout"))?
+                    .filter(|timeout| !timeout.is_zero()),
+                password_max_age: self
+                    .property::<Duration>(("directory", id, "password-max-age"))?
+                    .filter(|max_age| !max_age.is_zero()),
             });
 
             // Add directory
@@ -221,6 +269,9 @@ impl ConfigDirectory for Config {
     }
 }
 
+// Pool timeouts between 100ms and 1h, longer values are clamped
+type PoolTimeout = DurationRange<100, 3_600_000>;
+
 pub(crate) fn build_pool<M: Manager>(
     config: &mut Config,
     prefix: &str,
@@ -235,12 +286,20 @@ pub(crate) fn build_pool<M: Manager>(
         )
         .create_timeout(
             config
-                .property_or_default_::<Duration>((prefix, "pool.timeout.create"), "30s")
-                .unwrap_or_else(|| Duration::from_secs(30))
+                .property_or_default_::<PoolTimeout>((prefix, "pool.timeout.create"), "30s")
+                .map_or_else(|| Duration::from_secs(30), Duration::from)
                 .into(),
         )
-        .wait_timeout(config.property_or_default_((prefix, "pool.timeout.wait"), "30s"))
-        .recycle_timeout(config.property_or_default_((prefix, "pool.timeout.recycle"), "30s"))
+        .wait_timeout(
+            config
+                .property_or_default_::<PoolTimeout>((prefix, "pool.timeout.wait"), "30s")
+                .map(Duration::from),
+        )
+        .recycle_timeout(
+            config
+                .property_or_default_::<PoolTimeout>((prefix, "pool.timeout.recycle"), "30s")
+                .map(Duration::from),
+        )
         .build()
         .map_err(|err| {
             format!(
@@ -251,12 +310,66 @@ pub(crate) fn build_pool<M: Manager>(
         })
 }
 
+/// TCP keepalive settings applied to pooled backend connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpKeepalive {
+    pub time: Duration,
+    pub interval: Duration,
+}
+
+pub trait KeepaliveSocket {
+    fn set_keepalive(&self, keepalive: &TcpKeepalive) -> std::io::Result<()>;
+}
+
+impl KeepaliveSocket for TcpStream {
+    fn set_keepalive(&self, keepalive: &TcpKeepalive) -> std::io::Result<()> {
+        let params = socket2::TcpKeepalive::new().with_time(keepalive.time);
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "windows"
+        ))]
+        let params = params.with_interval(keepalive.interval);
+
+        SockRef::from(self).set_tcp_keepalive(&params)
+    }
+}
+
+impl TcpKeepalive {
+    pub fn apply(&self, socket: &impl KeepaliveSocket) {
+        if let Err(err) = socket.set_keepalive(self) {
+            tracing::debug!(
+                context = "directory",
+                event = "error",
+                reason = %err,
+                "Failed to set TCP keepalive on socket."
+            );
+        }
+    }
+}
+
+/// Returns `None` if the settings are invalid, and `Some(None)` if keepalive
+/// has been disabled.
+pub fn build_keepalive(config: &mut Config, prefix: &str) -> Option<Option<TcpKeepalive>> {
+    let time =
+        config.property_or_default_::<Option<Duration>>((prefix, "pool.keepalive"), "60s")?;
+    let interval = config.property_or_default_((prefix, "pool.keepalive-interval"), "15s")?;
+
+    Some(time.map(|time| TcpKeepalive { time, interval }))
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LookupType {
     List,
     Glob,
     Regex,
     Map,
+    MultiMap,
+    Cidr,
 }
 
 #[derive(Debug, Clone)]
@@ -283,6 +396,8 @@ impl ParseValue for LookupType {
             "glob" => Ok(LookupType::Glob),
             "regex" => Ok(LookupType::Regex),
             "map" => Ok(LookupType::Map),
+            "multimap" | "multi-map" => Ok(LookupType::MultiMap),
+            "cidr" => Ok(LookupType::Cidr),
             _ => Err(format!(
                 "Invalid value for lookup type {key:?}: {value:?}",
                 key = key.as_key(),
@@ -291,3 +406,228 @@ impl ParseValue for LookupType {
         }
     }
 }
+
+impl ParseValue for LookupErrorPolicy {
+    fn parse_value(key: impl AsKey, value: &str) -> utils::config::Result<Self> {
+        match value {
+            "tempfail" | "temp-fail" => Ok(LookupErrorPolicy::TempFail),
+            "reject" => Ok(LookupErrorPolicy::Reject),
+            "accept" => Ok(LookupErrorPolicy::Accept),
+            _ => Err(format!(
+                "Invalid value for lookup error policy {key:?}: {value:?}",
+                key = key.as_key(),
+                value = value
+            )),
+        }
+    }
+}
+
+impl ParseValue for QuotaInheritance {
+    fn parse_value(key: impl AsKey, value: &str) -> utils::config::Result<Self> {
+        match value {
+            "disabled" | "none" | "false" => Ok(QuotaInheritance::Disabled),
+            "max" | "maximum" => Ok(QuotaInheritance::Max),
+            "min" | "minimum" => Ok(QuotaInheritance::Min),
+            _ => Err(format!(
+                "Invalid value for quota inheritance {key:?}: {value:?}",
+                key = key.as_key(),
+                value = value
+            )),
+        }
+    }
+}
+
+impl LookupFormat {
+    pub fn from_config(config: &mut Config, prefix: impl AsKey, default_type: LookupType) -> Self {
+        let prefix = prefix.as_key();
+        LookupFormat {
+            lookup_type: config
+                .property_::<LookupType>((prefix.as_str(), "type"))
+                .unwrap_or(default_type),
+            comment: config
+                .value((prefix.as_str(), "comment"))
+                .map(|v| v.to_string()),
+            separator: config
+                .value((prefix.as_str(), "separator"))
+                .map(|v| v.to_string()),
+        }
+    }
+}
+
+// Key/value lookup table loaded from text lines. 'Map' keeps the last value
+// seen for a key, while 'MultiMap' groups repeated keys and splits comma
+// separated value lists so one key can resolve to many values. 'Cidr' keys
+// are IP networks that remote addresses are matched against.
+#[derive(Debug, Clone, Default)]
+pub struct LookupMap {
+    entries: AHashMap<String, Vec<String>>,
+    networks: Vec<IpAddrMask>,
+}
+
+impl LookupMap {
+    pub fn from_config(config: &mut Config, prefix: impl AsKey) -> Self {
+        let prefix = prefix.as_key();
+        let format =
+            LookupFormat::from_config(config, (prefix.as_str(), "format"), LookupType::MultiMap);
+        let mut map = LookupMap::default();
+        for (_, value) in config.values((prefix.as_str(), "values")) {
+            map.load(value, &format);
+        }
+        map
+    }
+
+    pub fn parse(contents: &str, format: &LookupFormat) -> Self {
+        let mut map = LookupMap::default();
+        map.load(contents, format);
+        map
+    }
+
+    fn load(&mut self, contents: &str, format: &LookupFormat) {
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty()
+                || format
+                    .comment
+                    .as_ref()
+                    .map_or(false, |comment| line.starts_with(comment.as_str()))
+            {
+                continue;
+            }
+
+            let (key, value) = match &format.separator {
+                Some(separator) => line.split_once(separator.as_str()),
+                None => line.split_once(char::is_whitespace),
+            }
+            .map_or((line, ""), |(key, value)| (key.trim(), value.trim()));
+            let key = key.to_lowercase();
+
+            match format.lookup_type {
+                LookupType::MultiMap => {
+                    let values = self.entries.entry(key).or_default();
+                    for value in value.split(',') {
+                        let value = value.trim();
+                        if !value.is_empty() && !values.iter().any(|v| v == value) {
+                            values.push(value.to_string());
+                        }
+                    }
+                }
+                LookupType::Map => {
+                    self.entries.insert(
+                        key,
+                        if !value.is_empty() {
+                            vec![value.to_string()]
+                        } else {
+                            vec![]
+                        },
+                    );
+                }
+                LookupType::Cidr => {
+                    if let Ok(network) = IpAddrMask::parse_value(key.as_str(), key.as_str()) {
+                        self.networks.push(network);
+                    }
+                    self.entries.entry(key).or_default();
+                }
+                LookupType::List | LookupType::Glob | LookupType::Regex => {
+                    self.entries.entry(key).or_default();
+                }
+            }
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn contains_ip(&self, ip: &IpAddr) -> bool {
+        self.networks.iter().any(|network| network.matches(ip))
+    }
+
+    pub fn lookup(&self, key: &str) -> Option<&str> {
+        self.entries
+            .get(key)
+            .and_then(|values| values.first())
+            .map(|v| v.as_str())
+    }
+
+    pub fn lookup_multi(&self, key: &str) -> Vec<String> {
+        self.entries.get(key).cloned().unwrap_or_default()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(|k| k.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+// Key/value lookup table backed by a SQL query, for maps that are too large
+// to be loaded in memory. The query receives the key as its only parameter
+// and each row returned is a value. Missing keys are cached for a shorter time.
+pub struct QueryMap {
+    store: LookupStore,
+    query: String,
+    cache: Mutex<MapCache>,
+}
+
+impl QueryMap {
+    pub fn from_config(config: &mut Config, prefix: impl AsKey, stores: &Stores) -> Option<Self> {
+        let prefix = prefix.as_key();
+        let query = config.value((prefix.as_str(), "query"))?.to_string();
+        let store_id = config
+            .value_require_((prefix.as_str(), "store"))?
+            .to_string();
+        let store = if let Some(store) = stores.lookup_stores.get(&store_id) {
+            store.clone()
+        } else {
+            let err = format!("Map references a non-existent store {store_id:?}");
+            config.new_build_error((prefix.as_str(), "store"), err);
+            return None;
+        };
+
+        Some(QueryMap {
+            store,
+            query,
+            cache: Mutex::new(MapCache::new(
+                config
+                    .property_or_default_((prefix.as_str(), "cache.entries"), "1024")
+                    .unwrap_or(1024),
+                config
+                    .property_or_default_((prefix.as_str(), "cache.ttl.positive"), "1h")
+                    .unwrap_or_else(|| Duration::from_secs(3600)),
+                config
+                    .property_or_default_((prefix.as_str(), "cache.ttl.negative"), "1m")
+                    .unwrap_or_else(|| Duration::from_secs(60)),
+            )),
+        })
+    }
+
+    pub async fn lookup_map(&self, key: &str) -> crate::Result<Option<Vec<String>>> {
+        if let Some(values) = self.cache.lock().get(key) {
+            return Ok(values);
+        }
+
+        let values: Vec<String> = self
+            .store
+            .query::<Rows>(&self.query, vec![key.to_string().into()])
+            .await?
+            .into();
+        let values = if !values.is_empty() {
+            Some(values)
+        } else {
+            None
+        };
+        self.cache.lock().insert(key.to_string(), values.clone());
+
+        Ok(values)
+    }
+}
+
+impl std::fmt::Debug for QueryMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QueryMap")
+            .field("query", &self.query)
+            .finish()
+    }
+}
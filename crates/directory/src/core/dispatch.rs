@@ -21,35 +21,129 @@
  * for more details.
 */
 
+use std::{future::Future, time::Duration};
+
+use futures::future::join_all;
+use store::write::now;
+
 use crate::{
-    backend::internal::lookup::DirectoryStore, Directory, DirectoryInner, Principal, QueryBy,
+    backend::{
+        internal::{lookup::DirectoryStore, manage::ManageDirectory},
+        smtp::relay::RelayEnvelope,
+    },
+    core::cache::membership_generation,
+    Directories, Directory, DirectoryError, DirectoryInner, Principal, QueryBy, QuotaInheritance,
+    Type,
 };
 
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl Directory {
     pub async fn query(
         &self,
         by: QueryBy<'_>,
         return_member_of: bool,
     ) -> crate::Result<Option<Principal<u32>>> {
-        match &self.store {
-            DirectoryInner::Internal(store) => store.query(by, return_member_of).await,
-            DirectoryInner::Ldap(store) => store.query(by, return_member_of).await,
-            DirectoryInner::Sql(store) => store.query(by, return_member_of).await,
-            DirectoryInner::Imap(store) => store.query(by).await,
-            DirectoryInner::Smtp(store) => store.query(by).await,
-            DirectoryInner::Memory(store) => store.query(by).await,
+        let mut principal = match self.query_store(by, return_member_of).await? {
+            Some(principal) => principal,
+            None => return Ok(None),
+        };
+
+        if principal.quota == 0 && self.quota_inheritance != QuotaInheritance::Disabled {
+            principal.quota = self.inherited_quota(&principal, return_member_of).await?;
+        }
+
+        // Members of a group also belong to the groups it is a member of
+        if return_member_of && matches!(self.store, DirectoryInner::Internal(_)) {
+            principal.member_of = self.transitive_groups(principal.id).await?;
         }
+
+        Ok(Some(principal))
     }
 
-    pub async fn email_to_ids(&self, email: &str) -> crate::Result<Vec<u32>> {
-        match &self.store {
-            DirectoryInner::Internal(store) => store.email_to_ids(email).await,
-            DirectoryInner::Ldap(store) => store.email_to_ids(email).await,
-            DirectoryInner::Sql(store) => store.email_to_ids(email).await,
-            DirectoryInner::Imap(store) => store.email_to_ids(email).await,
-            DirectoryInner::Smtp(store) => store.email_to_ids(email).await,
-            DirectoryInner::Memory(store) => store.email_to_ids(email).await,
+    /// Returns `true` if the principal's password is older than the configured
+    /// maximum age. Superusers are exempt, as are principals whose password
+    /// was last set before password changes were being tracked. Application
+    /// passwords and OAuth tokens are not checked by callers.
+    pub fn is_password_expired(&self, principal: &Principal<u32>) -> bool {
+        self.password_max_age.map_or(false, |max_age| {
+            principal.typ != Type::Superuser
+                && principal.password_changed != 0
+                && principal.password_changed.saturating_add(max_age.as_secs()) < now()
+        })
+    }
+
+    // Principals without a quota of their own are assigned the largest
+    // (or smallest) non-zero quota among the groups they are a member of.
+    async fn inherited_quota(
+        &self,
+        principal: &Principal<u32>,
+        has_member_of: bool,
+    ) -> crate::Result<u64> {
+        let fetched;
+        let member_of = if has_member_of
+            || matches!(
+                self.store,
+                DirectoryInner::Imap(_) | DirectoryInner::Smtp(_) | DirectoryInner::Memory(_)
+            ) {
+            &principal.member_of
+        } else {
+            fetched = self
+                .query_store(QueryBy::Id(principal.id), true)
+                .await?
+                .map(|principal| principal.member_of)
+                .unwrap_or_default();
+            &fetched
+        };
+
+        let mut quota = 0;
+        for group_id in member_of {
+            let group_quota = self
+                .query_store(QueryBy::Id(*group_id), false)
+                .await?
+                .map_or(0, |group| group.quota);
+            if group_quota != 0 {
+                quota = match self.quota_inheritance {
+                    QuotaInheritance::Min if quota != 0 => quota.min(group_quota),
+                    QuotaInheritance::Min => group_quota,
+                    _ => quota.max(group_quota),
+                };
+            }
         }
+
+        Ok(quota)
+    }
+
+    async fn query_store(
+        &self,
+        by: QueryBy<'_>,
+        return_member_of: bool,
+    ) -> crate::Result<Option<Principal<u32>>> {
+        self.with_timeout(async {
+            match &self.store {
+                DirectoryInner::Internal(store) => store.query(by, return_member_of).await,
+                DirectoryInner::Ldap(store) => store.query(by, return_member_of).await,
+                DirectoryInner::Sql(store) => store.query(by, return_member_of).await,
+                DirectoryInner::Imap(store) => store.query(by).await,
+                DirectoryInner::Smtp(store) => store.query(by).await,
+                DirectoryInner::Memory(store) => store.query(by).await,
+            }
+        })
+        .await
+    }
+
+    pub async fn email_to_ids(&self, email: &str) -> crate::Result<Vec<u32>> {
+        self.with_timeout(async {
+            match &self.store {
+                DirectoryInner::Internal(store) => store.email_to_ids(email).await,
+                DirectoryInner::Ldap(store) => store.email_to_ids(email).await,
+                DirectoryInner::Sql(store) => store.email_to_ids(email).await,
+                DirectoryInner::Imap(store) => store.email_to_ids(email).await,
+                DirectoryInner::Smtp(store) => store.email_to_ids(email).await,
+                DirectoryInner::Memory(store) => store.email_to_ids(email).await,
+            }
+        })
+        .await
     }
 
     pub async fn is_local_domain(&self, domain: &str) -> crate::Result<bool> {
@@ -60,14 +154,18 @@ impl Directory {
             }
         }
 
-        let result = match &self.store {
-            DirectoryInner::Internal(store) => store.is_local_domain(domain).await,
-            DirectoryInner::Ldap(store) => store.is_local_domain(domain).await,
-            DirectoryInner::Sql(store) => store.is_local_domain(domain).await,
-            DirectoryInner::Imap(store) => store.is_local_domain(domain).await,
-            DirectoryInner::Smtp(store) => store.is_local_domain(domain).await,
-            DirectoryInner::Memory(store) => store.is_local_domain(domain).await,
-        }?;
+        let result = self
+            .with_timeout(async {
+                match &self.store {
+                    DirectoryInner::Internal(store) => store.is_local_domain(domain).await,
+                    DirectoryInner::Ldap(store) => store.is_local_domain(domain).await,
+                    DirectoryInner::Sql(store) => store.is_local_domain(domain).await,
+                    DirectoryInner::Imap(store) => store.is_local_domain(domain).await,
+                    DirectoryInner::Smtp(store) => store.is_local_domain(domain).await,
+                    DirectoryInner::Memory(store) => store.is_local_domain(domain).await,
+                }
+            })
+            .await?;
 
         // Update cache
         if let Some(cache) = &self.cache {
@@ -85,14 +183,18 @@ impl Directory {
             }
         }
 
-        let result = match &self.store {
-            DirectoryInner::Internal(store) => store.rcpt(email).await,
-            DirectoryInner::Ldap(store) => store.rcpt(email).await,
-            DirectoryInner::Sql(store) => store.rcpt(email).await,
-            DirectoryInner::Imap(store) => store.rcpt(email).await,
-            DirectoryInner::Smtp(store) => store.rcpt(email).await,
-            DirectoryInner::Memory(store) => store.rcpt(email).await,
-        }?;
+        let result = self
+            .with_timeout(async {
+                match &self.store {
+                    DirectoryInner::Internal(store) => store.rcpt(email).await,
+                    DirectoryInner::Ldap(store) => store.rcpt(email).await,
+                    DirectoryInner::Sql(store) => store.rcpt(email).await,
+                    DirectoryInner::Imap(store) => store.rcpt(email).await,
+                    DirectoryInner::Smtp(store) => store.rcpt(email).await,
+                    DirectoryInner::Memory(store) => store.rcpt(email).await,
+                }
+            })
+            .await?;
 
         if result {
             // Update cache
@@ -104,25 +206,133 @@ impl Directory {
         Ok(result)
     }
 
+    pub async fn transitive_groups(&self, account_id: u32) -> crate::Result<Vec<u32>> {
+        let store = match &self.store {
+            DirectoryInner::Internal(store) => store,
+            _ => return Err(DirectoryError::Unsupported),
+        };
+
+        // Check cache
+        if let Some(cache) = &self.cache {
+            if let Some(groups) = cache.get_groups(account_id) {
+                return Ok(groups);
+            }
+        }
+
+        let generation = membership_generation();
+        let groups = self.with_timeout(store.groups_of(account_id, true)).await?;
+
+        // Update cache, unless memberships changed while the closure was computed
+        if let Some(cache) = &self.cache {
+            cache.set_groups(account_id, groups.clone(), generation);
+        }
+
+        Ok(groups)
+    }
+
     pub async fn vrfy(&self, address: &str) -> crate::Result<Vec<String>> {
+        self.with_timeout(async {
+            match &self.store {
+                DirectoryInner::Internal(store) => store.vrfy(address).await,
+                DirectoryInner::Ldap(store) => store.vrfy(address).await,
+                DirectoryInner::Sql(store) => store.vrfy(address).await,
+                DirectoryInner::Imap(store) => store.vrfy(address).await,
+                DirectoryInner::Smtp(store) => store.vrfy(address).await,
+                DirectoryInner::Memory(store) => store.vrfy(address).await,
+            }
+        })
+        .await
+    }
+
+    pub async fn expn(&self, address: &str) -> crate::Result<Vec<String>> {
+        self.with_timeout(async {
+            match &self.store {
+                DirectoryInner::Internal(store) => store.expn(address).await,
+                DirectoryInner::Ldap(store) => store.expn(address).await,
+                DirectoryInner::Sql(store) => store.expn(address).await,
+                DirectoryInner::Imap(store) => store.expn(address).await,
+                DirectoryInner::Smtp(store) => store.expn(address).await,
+                DirectoryInner::Memory(store) => store.expn(address).await,
+            }
+        })
+        .await
+    }
+
+    // Only SMTP directories are able to relay messages to their backend.
+    pub async fn relay(
+        &self,
+        envelope: &RelayEnvelope<'_>,
+        message: &[u8],
+    ) -> crate::Result<Vec<smtp_proto::Response<String>>> {
         match &self.store {
-            DirectoryInner::Internal(store) => store.vrfy(address).await,
-            DirectoryInner::Ldap(store) => store.vrfy(address).await,
-            DirectoryInner::Sql(store) => store.vrfy(address).await,
-            DirectoryInner::Imap(store) => store.vrfy(address).await,
-            DirectoryInner::Smtp(store) => store.vrfy(address).await,
-            DirectoryInner::Memory(store) => store.vrfy(address).await,
+            DirectoryInner::Smtp(store) => store.relay(envelope, message).await,
+            _ => Err(DirectoryError::Unsupported),
         }
     }
 
-    pub async fn expn(&self, address: &str) -> crate::Result<Vec<String>> {
+    // Pool timeouts only bound obtaining a connection, this bounds the lookup
+    // itself so that a runaway query fails instead of hanging the request.
+    // SMTP and IMAP connections dropped halfway through a command are flagged
+    // as invalid and discarded by the pool instead of being recycled, LDAP
+    // replies are matched by message id so late replies are simply ignored.
+    async fn with_timeout<T>(
+        &self,
+        lookup: impl Future<Output = crate::Result<T>>,
+    ) -> crate::Result<T> {
+        match self.query_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, lookup)
+                .await
+                .unwrap_or_else(|_| Err(DirectoryError::timeout(self.store.protocol()))),
+            None => lookup.await,
+        }
+    }
+
+    pub async fn health_check(&self) -> crate::Result<()> {
         match &self.store {
-            DirectoryInner::Internal(store) => store.expn(address).await,
-            DirectoryInner::Ldap(store) => store.expn(address).await,
-            DirectoryInner::Sql(store) => store.expn(address).await,
-            DirectoryInner::Imap(store) => store.expn(address).await,
-            DirectoryInner::Smtp(store) => store.expn(address).await,
-            DirectoryInner::Memory(store) => store.expn(address).await,
+            DirectoryInner::Internal(store) => store.is_local_domain("").await.map(|_| ()),
+            DirectoryInner::Ldap(store) => store.health_check().await,
+            DirectoryInner::Sql(store) => store.is_local_domain("").await.map(|_| ()),
+            DirectoryInner::Imap(store) => store.health_check().await,
+            DirectoryInner::Smtp(store) => store.health_check().await,
+            DirectoryInner::Memory(_) => Ok(()),
+        }
+    }
+}
+
+impl Directories {
+    pub async fn health(&self) -> Vec<(String, Result<(), String>)> {
+        self.health_with_timeout(HEALTH_CHECK_TIMEOUT).await
+    }
+
+    // Checks run concurrently and are bounded by the timeout, so a hung
+    // backend does not delay the status of the other directories.
+    pub async fn health_with_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Vec<(String, Result<(), String>)> {
+        let mut results = join_all(self.directories.iter().map(|(id, directory)| async move {
+            let result = match tokio::time::timeout(timeout, directory.health_check()).await {
+                Ok(Ok(())) => Ok(()),
+                Ok(Err(err)) => Err(format!("{err:?}")),
+                Err(_) => Err(format!("Health check timed out after {timeout:?}")),
+            };
+            (id.clone(), result)
+        }))
+        .await;
+        results.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        results
+    }
+}
+
+impl DirectoryInner {
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            DirectoryInner::Internal(_) => "internal",
+            DirectoryInner::Ldap(_) => "ldap",
+            DirectoryInner::Sql(_) => "sql",
+            DirectoryInner::Imap(_) => "imap",
+            DirectoryInner::Smtp(_) => "smtp",
+            DirectoryInner::Memory(_) => "memory",
         }
     }
 }
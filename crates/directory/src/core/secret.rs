@@ -45,6 +45,60 @@ impl<T: serde::Serialize + serde::de::DeserializeOwned> Principal<T> {
         }
         false
     }
+
+    /// Returns `true` if the secret matches one of the application passwords,
+    /// which are all the secrets after the first one, and not the account
+    /// password itself.
+    pub async fn is_app_password(&self, secret: &str) -> bool {
+        if let Some((password, app_passwords)) = self.secrets.split_first() {
+            if !app_passwords.is_empty() && !verify_secret_hash(password, secret).await {
+                for hashed_secret in app_passwords {
+                    if verify_secret_hash(hashed_secret, secret).await {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Password schemes accepted in the `{SCHEME}hash` format, case-insensitive.
+pub const SECRET_SCHEMES: &[&str] = &[
+    "ARGON2", "ARGON2I", "ARGON2ID", "PBKDF2", "SHA", "SSHA", "SHA256", "SSHA256", "SHA512",
+    "SSHA512", "MD5", "CRYPT", "PLAIN", "CLEAR",
+];
+
+/// Returns the scheme of a secret in `{SCHEME}hash` format, if any.
+pub fn secret_scheme(secret: &str) -> Option<&str> {
+    secret
+        .strip_prefix('{')
+        .and_then(|secret| secret.split_once('}'))
+        .map(|(scheme, _)| scheme)
+}
+
+/// Returns `true` if the secret is stored in one of the supported hash formats,
+/// as opposed to a plain text password.
+pub fn is_hashed_secret(secret: &str) -> bool {
+    if let Some(scheme) = secret_scheme(secret) {
+        let scheme = scheme.to_ascii_uppercase();
+        scheme != "PLAIN" && scheme != "CLEAR" && SECRET_SCHEMES.contains(&scheme.as_str())
+    } else if secret.starts_with('$') {
+        [
+            "$argon2", "$pbkdf2", "$scrypt", "$2a$", "$2b$", "$2x$", "$2y$", "$6$", "$5$",
+            "$sha1$", "$1$",
+        ]
+        .iter()
+        .any(|prefix| secret.starts_with(prefix))
+    } else if secret.starts_with('_') {
+        // Enhanced DES-based hash
+        secret.len() == 20
+            && secret[1..]
+                .bytes()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == b'.' || ch == b'/')
+    } else {
+        false
+    }
 }
 
 async fn verify_hash_prefix(hashed_secret: &str, secret: &str) -> bool {
@@ -117,7 +171,7 @@ async fn verify_secret_hash(hashed_secret: &str, secret: &str) -> bool {
         bsdi_crypt::verify(secret, hashed_secret)
     } else if let Some(hashed_secret) = hashed_secret.strip_prefix('{') {
         if let Some((algo, hashed_secret)) = hashed_secret.split_once('}') {
-            match algo {
+            match algo.to_ascii_uppercase().as_str() {
                 "ARGON2" | "ARGON2I" | "ARGON2ID" | "PBKDF2" => {
                     verify_hash_prefix(hashed_secret, secret).await
                 }
@@ -181,7 +235,7 @@ async fn verify_secret_hash(hashed_secret: &str, secret: &str) -> bool {
                     String::from_utf8(base64_encode(&digest[..]).unwrap_or_default()).unwrap()
                         == hashed_secret
                 }
-                "CRYPT" | "crypt" => {
+                "CRYPT" => {
                     if hashed_secret.starts_with('$') {
                         verify_hash_prefix(hashed_secret, secret).await
                     } else {
@@ -189,7 +243,7 @@ async fn verify_secret_hash(hashed_secret: &str, secret: &str) -> bool {
                         unix_crypt::verify(secret, hashed_secret)
                     }
                 }
-                "PLAIN" | "plain" | "CLEAR" | "clear" => hashed_secret == secret,
+                "PLAIN" | "CLEAR" => hashed_secret == secret,
                 _ => {
                     tracing::warn!(
                         context = "directory",
@@ -22,7 +22,7 @@
 */
 
 use core::cache::CachedDirectory;
-use std::{fmt::Debug, sync::Arc};
+use std::{collections::BTreeMap, fmt::Debug, net::IpAddr, sync::Arc, time::Duration};
 
 use ahash::AHashMap;
 use backend::{
@@ -37,6 +37,7 @@ use deadpool::managed::PoolError;
 use ldap3::LdapError;
 use mail_send::Credentials;
 use store::Store;
+use utils::config::ipmask::IpAddrMask;
 
 pub mod backend;
 pub mod core;
@@ -44,9 +45,29 @@ pub mod core;
 pub struct Directory {
     pub store: DirectoryInner,
     pub cache: Option<CachedDirectory>,
+    pub on_error: LookupErrorPolicy,
+    pub quota_inheritance: QuotaInheritance,
+    pub query_timeout: Option<Duration>,
+    pub password_max_age: Option<Duration>,
 }
 
-#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LookupErrorPolicy {
+    #[default]
+    TempFail,
+    Reject,
+    Accept,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaInheritance {
+    #[default]
+    Disabled,
+    Max,
+    Min,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Principal<T> {
     #[serde(default, skip)]
     pub id: u32,
@@ -54,6 +75,9 @@ pub struct Principal<T> {
     pub typ: Type,
     #[serde(default)]
     pub quota: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "messageCountQuota")]
+    pub message_count_quota: Option<u32>,
     pub name: String,
     #[serde(default)]
     pub secrets: Vec<String>,
@@ -64,6 +88,75 @@ pub struct Principal<T> {
     pub member_of: Vec<T>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[serde(rename = "allowedNetworks")]
+    pub allowed_networks: Vec<IpAddrMask>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(default = "default_features", skip_serializing_if = "has_all_features")]
+    pub features: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "externalId")]
+    pub external_id: Option<String>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    #[serde(rename = "passwordChanged")]
+    pub password_changed: u64,
+}
+
+pub const FEATURE_IMAP: u32 = 1 << 0;
+pub const FEATURE_JMAP: u32 = 1 << 1;
+pub const FEATURE_SEND_EXTERNAL: u32 = 1 << 2;
+pub const FEATURES_ALL: u32 = u32::MAX;
+
+const FEATURE_NAMES: [(&str, u32); 3] = [
+    ("imap", FEATURE_IMAP),
+    ("jmap", FEATURE_JMAP),
+    ("send-external", FEATURE_SEND_EXTERNAL),
+];
+
+/// Returns the feature flag with the given name.
+pub fn feature_from_name(name: &str) -> Option<u32> {
+    FEATURE_NAMES
+        .iter()
+        .find_map(|(feature_name, feature)| (*feature_name == name).then_some(*feature))
+}
+
+/// Returns the names of the feature flags enabled in a bitfield.
+pub fn feature_names(features: u32) -> Vec<String> {
+    FEATURE_NAMES
+        .iter()
+        .filter(|(_, feature)| features & feature != 0)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// Enabling every known feature collapses into `FEATURES_ALL` so that
+/// flags added in later releases default to on.
+pub fn normalize_features(features: u32) -> u32 {
+    if FEATURE_NAMES
+        .iter()
+        .all(|(_, feature)| features & feature != 0)
+    {
+        FEATURES_ALL
+    } else {
+        features
+    }
+}
+
+pub fn default_features() -> u32 {
+    FEATURES_ALL
+}
+
+pub fn has_all_features(features: &u32) -> bool {
+    *features == FEATURES_ALL
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -105,6 +198,15 @@ pub enum ManagementError {
         value: String,
     },
     NotFound(String),
+    WeakPassword(String),
+    InvalidValue {
+        field: PrincipalField,
+        value: String,
+    },
+    UnsupportedVersion {
+        version: u8,
+        accounts: Vec<String>,
+    },
 }
 
 pub enum DirectoryInner {
@@ -122,6 +224,29 @@ pub enum QueryBy<'x> {
     Credentials(&'x Credentials<String>),
 }
 
+impl<T> Default for Principal<T> {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            typ: Type::default(),
+            quota: 0,
+            message_count_quota: None,
+            name: String::new(),
+            secrets: Vec::new(),
+            emails: Vec::new(),
+            member_of: Vec::new(),
+            description: None,
+            attributes: BTreeMap::new(),
+            allowed_networks: Vec::new(),
+            locale: None,
+            timezone: None,
+            features: FEATURES_ALL,
+            external_id: None,
+            password_changed: 0,
+        }
+    }
+}
+
 impl<T: serde::Serialize + serde::de::DeserializeOwned> Principal<T> {
     pub fn name(&self) -> &str {
         &self.name
@@ -134,6 +259,22 @@ impl<T: serde::Serialize + serde::de::DeserializeOwned> Principal<T> {
     pub fn description(&self) -> Option<&str> {
         self.description.as_deref()
     }
+
+    /// Returns true if the principal is allowed to log in from the given
+    /// address. An empty network list places no restriction on logins.
+    pub fn is_allowed_ip(&self, ip: &IpAddr) -> bool {
+        self.allowed_networks.is_empty()
+            || self
+                .allowed_networks
+                .iter()
+                .any(|network| network.matches(ip))
+    }
+
+    /// Returns true if the given feature flag is enabled for the principal,
+    /// principals without explicit flags have every feature enabled.
+    pub fn has_feature(&self, feature: u32) -> bool {
+        self.features & feature == feature
+    }
 }
 
 impl Debug for Directory {
@@ -402,6 +402,7 @@ impl IMAP {
                 let limiter = Arc::new(ConcurrencyLimiters {
                     concurrent_requests: ConcurrencyLimiter::new(self.rate_concurrent),
                     concurrent_uploads: ConcurrencyLimiter::new(self.rate_concurrent),
+                    concurrent_sessions: ConcurrencyLimiter::new(self.rate_concurrent),
                 });
                 self.rate_limiter.insert(account_id, limiter.clone());
                 limiter
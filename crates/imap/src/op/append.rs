@@ -129,6 +129,7 @@ impl<T: SessionStream> SessionData<T> {
                     message: MessageParser::new().parse(&message.message),
                     account_id,
                     account_quota,
+                    account_message_quota: None,
                     mailbox_ids: vec![mailbox_id],
                     keywords: message.flags.into_iter().map(Keyword::from).collect(),
                     received_at: message.received_at.map(|d| d as u64),
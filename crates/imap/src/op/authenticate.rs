@@ -23,7 +23,7 @@
 
 use std::sync::Arc;
 
-use directory::AuthResult;
+use directory::{AuthResult, FEATURE_IMAP};
 use imap_proto::{
     protocol::{authenticate::Mechanism, capability::Capability},
     receiver::{self, Request},
@@ -130,6 +130,16 @@ impl<T: SessionStream> Session<T> {
                     AuthResult::Success(token) => Some(token),
                     AuthResult::Failure => None,
                     AuthResult::Banned => return Err(()),
+                    AuthResult::PasswordExpired => {
+                        self.write_bytes(
+                            StatusResponse::no("Password has expired, please change it.")
+                                .with_tag(tag)
+                                .with_code(ResponseCode::Expired)
+                                .into_bytes(),
+                        )
+                        .await?;
+                        return Ok(());
+                    }
                 }
             }
             Credentials::OAuthBearer { token } => {
@@ -152,12 +162,28 @@ impl<T: SessionStream> Session<T> {
             }
         };
 
+        // Accounts without IMAP access are handled as failed logins
+        let access_token = match access_token {
+            Some(access_token) if !access_token.has_feature(FEATURE_IMAP) => {
+                tracing::debug!(
+                    parent: &self.span,
+                    context = "authenticate",
+                    event = "feature-disabled",
+                    account = access_token.name,
+                    "IMAP access is disabled for this account."
+                );
+                None
+            }
+            access_token => access_token,
+        };
+
         if let Some(access_token) = access_token {
-            // Enforce concurrency limits
+            // Enforce concurrency limits, the slot is held by the session
+            // data until the connection is closed or unauthenticated
             let in_flight = self
                 .imap
                 .get_concurrency_limiter(access_token.primary_id())
-                .concurrent_requests
+                .concurrent_sessions
                 .is_allowed();
             if let Some(in_flight) = in_flight {
                 // Cache access token
@@ -35,6 +35,8 @@ pub enum RequestLimitError {
     ConcurrentRequest,
     #[serde(rename(serialize = "maxConcurrentUpload"))]
     ConcurrentUpload,
+    #[serde(rename(serialize = "maxConcurrentSessions"))]
+    ConcurrentSession,
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -169,6 +171,10 @@ impl RequestError {
                     "The request exceeds the maximum number ",
                     "of concurrent uploads."
                 ),
+                RequestLimitError::ConcurrentSession => concat!(
+                    "The request exceeds the maximum number ",
+                    "of concurrent sessions."
+                ),
             }
             .into(),
             limit: Some(limit_type),
@@ -187,6 +193,14 @@ impl RequestError {
         RequestError::blank(401, "Unauthorized", "You have to authenticate first.")
     }
 
+    pub fn password_expired() -> Self {
+        RequestError::blank(
+            401,
+            "Password expired",
+            "Your password has expired, please change it.",
+        )
+    }
+
     pub fn unknown_capability(capability: &str) -> RequestError {
         RequestError {
             p_type: RequestErrorType::UnknownCapability,
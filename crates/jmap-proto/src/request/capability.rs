@@ -21,7 +21,10 @@
  * for more details.
 */
 
-use utils::map::vec_map::VecMap;
+use utils::{
+    config::utils::{AsKey, ParseValue},
+    map::vec_map::VecMap,
+};
 
 use crate::{
     error::request::RequestError,
@@ -166,6 +169,57 @@ impl Default for SieveSessionCapabilities {
     }
 }
 
+impl Capability {
+    pub fn all() -> &'static [Capability] {
+        &[
+            Capability::Core,
+            Capability::Mail,
+            Capability::Submission,
+            Capability::VacationResponse,
+            Capability::Contacts,
+            Capability::Calendars,
+            Capability::WebSocket,
+            Capability::Sieve,
+            Capability::Blob,
+            Capability::Quota,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::Core => "urn:ietf:params:jmap:core",
+            Capability::Mail => "urn:ietf:params:jmap:mail",
+            Capability::Submission => "urn:ietf:params:jmap:submission",
+            Capability::VacationResponse => "urn:ietf:params:jmap:vacationresponse",
+            Capability::Contacts => "urn:ietf:params:jmap:contacts",
+            Capability::Calendars => "urn:ietf:params:jmap:calendars",
+            Capability::WebSocket => "urn:ietf:params:jmap:websocket",
+            Capability::Sieve => "urn:ietf:params:jmap:sieve",
+            Capability::Blob => "urn:ietf:params:jmap:blob",
+            Capability::Quota => "urn:ietf:params:jmap:quota",
+        }
+    }
+}
+
+impl ParseValue for Capability {
+    fn parse_value(key: impl AsKey, value: &str) -> utils::config::Result<Self> {
+        let name = value.strip_prefix("urn:ietf:params:jmap:").unwrap_or(value);
+        Capability::all()
+            .iter()
+            .find(|capability| {
+                capability.as_str()["urn:ietf:params:jmap:".len()..].eq_ignore_ascii_case(name)
+            })
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "Invalid JMAP capability {:?} for property {:?}.",
+                    value,
+                    key.as_key()
+                )
+            })
+    }
+}
+
 impl JsonObjectParser for Capability {
     fn parse(parser: &mut Parser<'_>) -> crate::parser::Result<Self>
     where
@@ -25,6 +25,8 @@ use std::fmt::Display;
 
 use crate::parser::{json::Parser, JsonObjectParser};
 
+use super::capability::Capability;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct MethodName {
     pub obj: MethodObject,
@@ -153,6 +155,27 @@ impl MethodName {
         }
     }
 
+    pub fn capability(&self) -> Capability {
+        match (self.obj, self.fnc) {
+            (
+                MethodObject::Email
+                | MethodObject::Mailbox
+                | MethodObject::Thread
+                | MethodObject::SearchSnippet,
+                _,
+            ) => Capability::Mail,
+            (MethodObject::Identity | MethodObject::EmailSubmission, _) => Capability::Submission,
+            (MethodObject::VacationResponse, _) => Capability::VacationResponse,
+            (MethodObject::SieveScript, _) => Capability::Sieve,
+            (MethodObject::Quota, _) => Capability::Quota,
+            (MethodObject::Blob, MethodFunction::Copy) => Capability::Core,
+            (MethodObject::Blob, _) => Capability::Blob,
+            (MethodObject::Core | MethodObject::PushSubscription | MethodObject::Principal, _) => {
+                Capability::Core
+            }
+        }
+    }
+
     pub fn as_str(&self) -> &'static str {
         match (self.fnc, self.obj) {
             (MethodFunction::Get, MethodObject::PushSubscription) => "PushSubscription/get",
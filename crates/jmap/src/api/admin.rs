@@ -21,35 +21,49 @@
  * for more details.
 */
 
-use std::sync::Arc;
+use std::{collections::BTreeMap, net::IpAddr, sync::Arc};
 
 use directory::{
-    backend::internal::{lookup::DirectoryStore, manage::ManageDirectory, PrincipalUpdate},
+    backend::internal::{
+        defaults::QUOTA_UNSET, lookup::DirectoryStore, manage::ManageDirectory, PrincipalField,
+        PrincipalUpdate, PrincipalValue,
+    },
     DirectoryError, ManagementError, Principal, QueryBy, Type,
 };
 use http_body_util::combinators::BoxBody;
-use hyper::{body::Bytes, Method, StatusCode};
+use hyper::{body::Bytes, header, Method, StatusCode};
 use jmap_proto::error::request::RequestError;
 use serde_json::json;
 use store::ahash::AHashMap;
-use utils::{config::ConfigKey, url_params::UrlParams};
+use utils::{
+    config::{ipmask::IpAddrMask, ConfigKey},
+    url_params::UrlParams,
+};
 
 use crate::{
-    auth::{oauth::OAuthCodeRequest, AccessToken},
+    auth::{authenticate::decode_basic_auth, oauth::OAuthCodeRequest, AccessToken},
     services::housekeeper,
     JMAP,
 };
 
 use super::{http::ToHttpResponse, HttpRequest, JsonResponse};
 
+#[derive(Debug, serde::Deserialize)]
+pub struct PasswordChangeRequest {
+    pub password: String,
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct PrincipalResponse {
     #[serde(default)]
     pub id: u32,
     #[serde(rename = "type")]
     pub typ: Type,
-    #[serde(default)]
-    pub quota: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "messageCountQuota")]
+    pub message_count_quota: Option<u32>,
     #[serde(rename = "usedQuota")]
     #[serde(default)]
     pub used_quota: u64,
@@ -66,6 +80,20 @@ pub struct PrincipalResponse {
     pub members: Vec<String>,
     #[serde(default)]
     pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[serde(rename = "allowedNetworks")]
+    pub allowed_networks: Vec<IpAddrMask>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(
+        default = "directory::default_features",
+        skip_serializing_if = "directory::has_all_features"
+    )]
+    pub features: u32,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -101,20 +129,32 @@ impl JMAP {
                 if let Some(principal) =
                     body.and_then(|body| serde_json::from_slice::<PrincipalResponse>(&body).ok())
                 {
+                    let members = principal.members;
+                    let principal = Principal {
+                        id: principal.id,
+                        typ: principal.typ,
+                        quota: principal.quota.unwrap_or(QUOTA_UNSET),
+                        message_count_quota: principal.message_count_quota,
+                        name: principal.name,
+                        secrets: principal.secrets,
+                        emails: principal.emails,
+                        member_of: principal.member_of,
+                        description: principal.description,
+                        attributes: principal.attributes,
+                        allowed_networks: principal.allowed_networks,
+                        locale: principal.locale,
+                        timezone: principal.timezone,
+                        features: principal.features,
+                        external_id: None,
+                        password_changed: 0,
+                    };
+
                     match self
                         .store
-                        .create_account(
-                            Principal {
-                                id: principal.id,
-                                typ: principal.typ,
-                                quota: principal.quota,
-                                name: principal.name,
-                                secrets: principal.secrets,
-                                emails: principal.emails,
-                                member_of: principal.member_of,
-                                description: principal.description,
-                            },
-                            principal.members,
+                        .create_account_with_policy(
+                            principal,
+                            members,
+                            &self.config.principal_policy,
                         )
                         .await
                     {
@@ -255,7 +295,11 @@ impl JMAP {
                         }) {
                             match self
                                 .store
-                                .update_account(QueryBy::Id(account_id), changes)
+                                .update_account_with_policy(
+                                    QueryBy::Id(account_id),
+                                    changes,
+                                    &self.config.principal_policy,
+                                )
                                 .await
                             {
                                 Ok(_) => JsonResponse::new(json!({
@@ -348,6 +392,34 @@ impl JMAP {
                     .into_http_response(),
                 }
             }
+            ("directory", Some("cache"), &Method::GET) => {
+                // Report the usage of each directory cache
+                let stats = self
+                    .smtp
+                    .shared
+                    .directories
+                    .iter()
+                    .filter_map(|(id, directory)| {
+                        let stats = directory.cache.as_ref()?.stats();
+                        Some((
+                            id.clone(),
+                            json!({
+                                "size": stats.size,
+                                "domains": stats.domains,
+                                "domainsNegative": stats.domains_negative,
+                                "rcpts": stats.rcpts,
+                                "rcptsNegative": stats.rcpts_negative,
+                                "groups": stats.groups,
+                            }),
+                        ))
+                    })
+                    .collect::<serde_json::Map<_, _>>();
+
+                JsonResponse::new(json!({
+                    "data": stats,
+                }))
+                .into_http_response()
+            }
             ("reload", Some("settings"), &Method::GET) => {
                 let _ = self
                     .housekeeper_tx
@@ -717,6 +789,84 @@ impl JMAP {
         }
     }
 
+    // Users with an expired password cannot obtain an access token, so the
+    // new password is set using the credentials of the expired one.
+    pub async fn handle_password_change(
+        &self,
+        req: &HttpRequest,
+        body: Option<Vec<u8>>,
+        remote_ip: IpAddr,
+    ) -> hyper::Response<BoxBody<Bytes, hyper::Error>> {
+        // Enforce rate limit for authentication requests
+        let remote_addr = self.build_remote_addr(req, remote_ip);
+        if let Err(err) = self.is_auth_allowed_soft(&remote_addr).await {
+            return err.into_http_response();
+        }
+
+        let (account, secret) = match req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.split_once(' '))
+            .filter(|(mechanism, _)| mechanism.eq_ignore_ascii_case("basic"))
+            .and_then(|(_, token)| decode_basic_auth(token.trim()))
+        {
+            Some(credentials) => credentials,
+            None => return RequestError::unauthorized().into_http_response(),
+        };
+        let request = match body
+            .and_then(|body| serde_json::from_slice::<PasswordChangeRequest>(&body).ok())
+        {
+            Some(request) => request,
+            None => {
+                return RequestError::blank(
+                    StatusCode::BAD_REQUEST.as_u16(),
+                    "Invalid parameters",
+                    "Failed to deserialize password change request",
+                )
+                .into_http_response()
+            }
+        };
+
+        match self
+            .authenticate_plain(&account, &secret, remote_addr)
+            .await
+        {
+            AuthResult::Success(_) | AuthResult::PasswordExpired => (),
+            AuthResult::Failure | AuthResult::Banned => {
+                return RequestError::unauthorized().into_http_response()
+            }
+        }
+
+        // Replace the account password, keeping any application passwords
+        let principal = match self.store.query(QueryBy::Name(&account), false).await {
+            Ok(Some(principal)) => principal,
+            Ok(None) => return map_directory_error(DirectoryError::Unsupported),
+            Err(err) => return map_directory_error(err),
+        };
+        let mut secrets = vec![request.password];
+        secrets.extend(principal.secrets.into_iter().skip(1));
+
+        match self
+            .store
+            .update_account_with_policy(
+                QueryBy::Id(principal.id),
+                vec![PrincipalUpdate::set(
+                    PrincipalField::Secrets,
+                    PrincipalValue::StringList(secrets),
+                )],
+                &self.config.principal_policy,
+            )
+            .await
+        {
+            Ok(_) => JsonResponse::new(json!({
+                "data": (),
+            }))
+            .into_http_response(),
+            Err(err) => map_directory_error(err),
+        }
+    }
+
     pub async fn handle_api_request(
         &self,
         req: &HttpRequest,
@@ -770,6 +920,22 @@ fn map_directory_error(err: DirectoryError) -> hyper::Response<BoxBody<Bytes, hy
                     "item": details,
                     "details": format!("'{details}' does not exist."),
                 }),
+                ManagementError::WeakPassword(details) => json!({
+                    "error": "weakPassword",
+                    "details": details,
+                }),
+                ManagementError::InvalidValue { field, value } => json!({
+                    "error": "invalidValue",
+                    "field": field,
+                    "value": value,
+                    "details": format!("Invalid value '{value}' for the '{field}' field."),
+                }),
+                ManagementError::UnsupportedVersion { version, accounts } => json!({
+                    "error": "unsupportedVersion",
+                    "version": version,
+                    "accounts": accounts,
+                    "details": format!("Version {version} cannot hold all the fields of {} account(s).", accounts.len()),
+                }),
             };
             JsonResponse::new(response).into_http_response()
         }
@@ -801,12 +967,18 @@ impl From<Principal<String>> for PrincipalResponse {
         PrincipalResponse {
             id: principal.id,
             typ: principal.typ,
-            quota: principal.quota,
+            quota: Some(principal.quota),
+            message_count_quota: principal.message_count_quota,
             name: principal.name,
             emails: principal.emails,
             member_of: principal.member_of,
             description: principal.description,
             secrets: principal.secrets,
+            attributes: principal.attributes,
+            allowed_networks: principal.allowed_networks,
+            locale: principal.locale,
+            timezone: principal.timezone,
+            features: principal.features,
             used_quota: 0,
             members: Vec::new(),
         }
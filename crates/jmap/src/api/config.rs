@@ -23,8 +23,11 @@
 
 use std::{str::FromStr, time::Duration};
 
+use directory::backend::internal::policy::PrincipalPolicy;
+use jmap_proto::request::capability::Capability;
 use nlp::language::Language;
 use store::rand::{distributions::Alphanumeric, thread_rng, Rng};
+use utils::config::cron::SimpleCron;
 
 use super::session::BaseCapabilities;
 
@@ -67,6 +70,17 @@ impl crate::Config {
             upload_max_concurrent: settings
                 .property("jmap.protocol.upload.max-concurrent")?
                 .unwrap_or(4),
+            session_max_concurrent: settings
+                .property("jmap.protocol.session.max-concurrent")?
+                .unwrap_or(16),
+            upload_allowed_types: settings
+                .values("jmap.protocol.upload.types.allow")
+                .map(|(_, v)| v.to_ascii_lowercase())
+                .collect(),
+            upload_denied_types: settings
+                .values("jmap.protocol.upload.types.deny")
+                .map(|(_, v)| v.to_ascii_lowercase())
+                .collect(),
             upload_tmp_quota_size: settings
                 .property("jmap.protocol.upload.quota.size")?
                 .unwrap_or(50000000),
@@ -76,6 +90,10 @@ impl crate::Config {
             upload_tmp_ttl: settings
                 .property_or_default::<Duration>("jmap.protocol.upload.ttl", "1h")?
                 .as_secs(),
+            upload_tmp_purge_frequency: settings.property_or_default::<SimpleCron>(
+                "jmap.protocol.upload.purge.frequency",
+                "15 * *",
+            )?,
             mailbox_max_depth: settings.property("jmap.mailbox.max-depth")?.unwrap_or(10),
             mailbox_name_max_len: settings
                 .property("jmap.mailbox.max-name-length")?
@@ -89,16 +107,34 @@ impl crate::Config {
             mail_parse_max_items: settings
                 .property("jmap.email.parse.max-items")?
                 .unwrap_or(10),
+            mail_parse_max_depth: settings
+                .property("jmap.email.parse.max-depth")?
+                .unwrap_or(20),
             sieve_max_script_name: settings
                 .property("sieve.untrusted.limits.name-length")?
                 .unwrap_or(512),
             sieve_max_scripts: settings
                 .property("sieve.untrusted.limits.max-scripts")?
                 .unwrap_or(256),
-            capabilities: BaseCapabilities::default(),
+            capabilities: BaseCapabilities {
+                disabled: settings
+                    .properties::<Capability>("jmap.capabilities.disable")
+                    .try_fold(0, |disabled, result| {
+                        let (key, capability) = result?;
+                        if capability != Capability::Core {
+                            Ok(disabled | capability as u32)
+                        } else {
+                            Err(format!(
+                                "The core JMAP capability cannot be disabled (property {key:?})."
+                            ))
+                        }
+                    })?,
+                ..Default::default()
+            },
             session_cache_ttl: settings
                 .property("cache.session.ttl")?
                 .unwrap_or(Duration::from_secs(3600)),
+            session_ttl: settings.property_or_default("jmap.session.ttl", "0")?,
             rate_authenticated: settings
                 .property_or_default("jmap.rate-limit.account", "1000/1m")?,
             rate_authenticate_req: settings
@@ -144,6 +180,7 @@ impl crate::Config {
             principal_allow_lookups: settings
                 .property("jmap.principal.allow-lookups")?
                 .unwrap_or(true),
+            principal_policy: PrincipalPolicy::from_config(settings)?,
             encrypt: settings.property_or_default("storage.encryption.enable", "true")?,
             encrypt_append: settings.property_or_default("storage.encryption.append", "false")?,
             spam_header: settings.value("spam.header.is-spam").and_then(|v| {
@@ -108,6 +108,12 @@ impl JMAP {
         let mut response = StateChangeResponse::new();
         let throttle = self.config.event_source_throttle;
 
+        // Enforce concurrent session limit
+        let in_flight = match self.is_session_allowed(&access_token) {
+            Ok(in_flight) => in_flight,
+            Err(err) => return err.into_http_response(),
+        };
+
         // Register with state manager
         let mut change_rx = if let Some(change_rx) = self
             .subscribe_state_manager(access_token.primary_id(), types)
@@ -123,6 +129,7 @@ impl JMAP {
             .header(header::CONTENT_TYPE, "text/event-stream")
             .header(header::CACHE_CONTROL, "no-store")
             .body(BoxBody::new(StreamBody::new(async_stream::stream! {
+                let _in_flight = in_flight;
                 let mut last_message = Instant::now() - throttle;
                 let mut timeout =
                     ping.as_ref().map(|p| p.interval).unwrap_or(LONG_SLUMBER);
@@ -273,6 +273,12 @@ pub async fn parse_jmap_request(
                 return ().into_http_response();
             }
 
+            // Expired passwords can only be used to set a new one
+            if req.method() == Method::POST && path.next() == Some("password") {
+                let body = fetch_body(&mut req, 8192, &AccessToken::default()).await;
+                return jmap.handle_password_change(&req, body, remote_ip).await;
+            }
+
             // Make sure the user is a superuser
             return match jmap.authenticate_headers(&req, remote_ip).await {
                 Ok(Some((_, access_token))) => {
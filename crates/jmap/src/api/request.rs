@@ -44,6 +44,9 @@ impl JMAP {
         access_token: Arc<AccessToken>,
         instance: &Arc<ServerInstance>,
     ) -> Result<Response, RequestError> {
+        // Reject requests using disabled capabilities
+        self.config.capabilities.validate_using(request.using)?;
+
         let mut response = Response::new(
             access_token.state(),
             request.created_ids.unwrap_or_default(),
@@ -61,6 +64,19 @@ impl JMAP {
             loop {
                 let mut next_call = None;
 
+                // Methods of disabled capabilities are not available
+                if self.config.capabilities.is_disabled(call.name.capability()) {
+                    response.push_error(
+                        call.id,
+                        MethodError::UnknownMethod(format!(
+                            "{} (capability {} is disabled)",
+                            call.name,
+                            call.name.capability().as_str()
+                        )),
+                    );
+                    break;
+                }
+
                 // Add response
                 match self
                     .handle_method_call(call.method, &access_token, &mut next_call, instance)
@@ -255,9 +271,14 @@ impl JMAP {
                 set::RequestArguments::EmailSubmission(arguments) => {
                     access_token.assert_is_member(req.account_id)?;
 
-                    self.email_submission_set(req.with_arguments(arguments), instance, next_call)
-                        .await?
-                        .into()
+                    self.email_submission_set(
+                        req.with_arguments(arguments),
+                        access_token,
+                        instance,
+                        next_call,
+                    )
+                    .await?
+                    .into()
                 }
                 set::RequestArguments::PushSubscription => {
                     self.push_subscription_set(req, access_token).await?.into()
@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use directory::QueryBy;
 use jmap_proto::{
@@ -31,7 +31,11 @@ use jmap_proto::{
     types::{acl::Acl, collection::Collection, id::Id, type_state::DataType},
 };
 use store::ahash::AHashSet;
-use utils::{listener::ServerInstance, map::vec_map::VecMap, UnwrapFailure};
+use utils::{
+    listener::ServerInstance,
+    map::{ttl_dashmap::TtlMap, vec_map::VecMap},
+    UnwrapFailure,
+};
 
 use crate::{auth::AccessToken, JMAP};
 
@@ -181,6 +185,23 @@ pub struct EmptyCapabilities {}
 pub struct BaseCapabilities {
     pub session: VecMap<Capability, Capabilities>,
     pub account: VecMap<Capability, Capabilities>,
+    pub disabled: u32,
+}
+
+impl BaseCapabilities {
+    pub fn is_disabled(&self, capability: Capability) -> bool {
+        self.disabled & capability as u32 != 0
+    }
+
+    pub fn validate_using(&self, using: u32) -> Result<(), RequestError> {
+        match Capability::all()
+            .iter()
+            .find(|capability| using & self.disabled & **capability as u32 != 0)
+        {
+            Some(capability) => Err(RequestError::unknown_capability(capability.as_str())),
+            None => Ok(()),
+        }
+    }
 }
 
 impl JMAP {
@@ -189,6 +210,16 @@ impl JMAP {
         instance: Arc<ServerInstance>,
         access_token: Arc<AccessToken>,
     ) -> Result<Session, RequestError> {
+        // Reuse the last computed session object unless it expired or the account state changed
+        let cache_key = (access_token.primary_id(), instance.id.clone());
+        if let Some(session) = self
+            .cache_sessions
+            .get_with_ttl(&cache_key)
+            .filter(|session| session.state == access_token.state())
+        {
+            return Ok(session.as_ref().clone());
+        }
+
         let mut session = Session::new(&instance.data, &self.config.capabilities);
         session.set_state(access_token.state());
         session.set_primary_account(
@@ -226,6 +257,14 @@ impl JMAP {
             );
         }
 
+        if !self.config.session_ttl.is_zero() {
+            self.cache_sessions.insert_with_ttl(
+                cache_key,
+                Arc::new(session.clone()),
+                Instant::now() + self.config.session_ttl,
+            );
+        }
+
         Ok(session)
     }
 }
@@ -307,16 +346,26 @@ impl crate::Config {
             Capability::Quota,
             Capabilities::Empty(EmptyCapabilities::default()),
         );
+
+        // Remove disabled capabilities
+        for capability in Capability::all() {
+            if self.capabilities.is_disabled(*capability) {
+                self.capabilities.session.remove(capability);
+                self.capabilities.account.remove(capability);
+            }
+        }
     }
 }
 
 impl Session {
     pub fn new(base_url: &str, base_capabilities: &BaseCapabilities) -> Session {
         let mut capabilities = base_capabilities.session.clone();
-        capabilities.append(
-            Capability::WebSocket,
-            Capabilities::WebSocket(WebSocketCapabilities::new(base_url)),
-        );
+        if !base_capabilities.is_disabled(Capability::WebSocket) {
+            capabilities.append(
+                Capability::WebSocket,
+                Capabilities::WebSocket(WebSocketCapabilities::new(base_url)),
+            );
+        }
 
         Session {
             capabilities,
@@ -23,7 +23,7 @@
 
 use std::{net::IpAddr, sync::Arc, time::Instant};
 
-use directory::QueryBy;
+use directory::{QueryBy, FEATURE_JMAP};
 use hyper::header;
 use jmap_proto::error::request::RequestError;
 use mail_parser::decoders::base64::base64_decode;
@@ -55,20 +55,13 @@ impl JMAP {
                     self.is_auth_allowed_soft(&addr).await?;
 
                     // Decode the base64 encoded credentials
-                    if let Some((account, secret)) = base64_decode(token.as_bytes())
-                        .and_then(|token| String::from_utf8(token).ok())
-                        .and_then(|token| {
-                            token.split_once(':').map(|(login, secret)| {
-                                (login.trim().to_lowercase(), secret.to_string())
-                            })
-                        })
-                    {
-                        if let AuthResult::Success(access_token) =
-                            self.authenticate_plain(&account, &secret, addr).await
-                        {
-                            Some(access_token)
-                        } else {
-                            None
+                    if let Some((account, secret)) = decode_basic_auth(&token) {
+                        match self.authenticate_plain(&account, &secret, addr).await {
+                            AuthResult::Success(access_token) => Some(access_token),
+                            AuthResult::PasswordExpired => {
+                                return Err(RequestError::password_expired())
+                            }
+                            AuthResult::Failure | AuthResult::Banned => None,
                         }
                     } else {
                         tracing::debug!(
@@ -107,6 +100,17 @@ impl JMAP {
             };
 
             if let Some(session) = session {
+                // Accounts without JMAP access are handled as unauthenticated
+                if !session.has_feature(FEATURE_JMAP) {
+                    tracing::debug!(
+                        context = "authenticate_headers",
+                        event = "feature-disabled",
+                        account = session.name,
+                        "JMAP access is disabled for this account."
+                    );
+                    return Ok(None);
+                }
+
                 // Enforce authenticated rate limit
                 Ok(Some((self.is_account_allowed(&session).await?, session)))
             } else {
@@ -195,6 +199,7 @@ impl JMAP {
                 AuthResult::Failure
             }
             Ok(AuthResult::Banned) => AuthResult::Banned,
+            Ok(AuthResult::PasswordExpired) => AuthResult::PasswordExpired,
             Err(_) => AuthResult::Failure,
         }
     }
@@ -210,3 +215,14 @@ impl JMAP {
         .await
     }
 }
+
+// Decodes the login and secret of a Basic authorization token
+pub(crate) fn decode_basic_auth(token: &str) -> Option<(String, String)> {
+    base64_decode(token.as_bytes())
+        .and_then(|token| String::from_utf8(token).ok())
+        .and_then(|token| {
+            token
+                .split_once(':')
+                .map(|(login, secret)| (login.trim().to_lowercase(), secret.to_string()))
+        })
+}
@@ -53,6 +53,7 @@ pub struct AccessToken {
     pub description: Option<String>,
     pub quota: u64,
     pub is_superuser: bool,
+    pub features: u32,
 }
 
 impl AccessToken {
@@ -65,9 +66,14 @@ impl AccessToken {
             description: principal.description,
             quota: principal.quota,
             is_superuser: principal.typ == Type::Superuser,
+            features: principal.features,
         }
     }
 
+    pub fn has_feature(&self, feature: u32) -> bool {
+        self.features & feature == feature
+    }
+
     pub fn with_access_to(self, access_to: Vec<(u32, Bitmap<Collection>)>) -> Self {
         Self { access_to, ..self }
     }
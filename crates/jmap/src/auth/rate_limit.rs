@@ -33,6 +33,7 @@ use super::AccessToken;
 pub struct ConcurrencyLimiters {
     pub concurrent_requests: ConcurrencyLimiter,
     pub concurrent_uploads: ConcurrencyLimiter,
+    pub concurrent_sessions: ConcurrencyLimiter,
 }
 
 impl JMAP {
@@ -46,6 +47,9 @@ impl JMAP {
                         self.config.request_max_concurrent,
                     ),
                     concurrent_uploads: ConcurrencyLimiter::new(self.config.upload_max_concurrent),
+                    concurrent_sessions: ConcurrencyLimiter::new(
+                        self.config.session_max_concurrent,
+                    ),
                 });
                 self.concurrency_limiter.insert(account_id, limiter.clone());
                 limiter
@@ -115,6 +119,20 @@ impl JMAP {
         }
     }
 
+    pub fn is_session_allowed(&self, access_token: &AccessToken) -> Result<InFlight, RequestError> {
+        if let Some(in_flight_session) = self
+            .get_concurrency_limiter(access_token.primary_id())
+            .concurrent_sessions
+            .is_allowed()
+        {
+            Ok(in_flight_session)
+        } else if access_token.is_super_user() {
+            Ok(InFlight::default())
+        } else {
+            Err(RequestError::limit(RequestLimitError::ConcurrentSession))
+        }
+    }
+
     pub async fn is_auth_allowed_soft(&self, addr: &IpAddr) -> Result<(), RequestError> {
         if self
             .lookup_store
@@ -154,6 +172,8 @@ impl JMAP {
 
 impl ConcurrencyLimiters {
     pub fn is_active(&self) -> bool {
-        self.concurrent_requests.is_active() || self.concurrent_uploads.is_active()
+        self.concurrent_requests.is_active()
+            || self.concurrent_uploads.is_active()
+            || self.concurrent_sessions.is_active()
     }
 }
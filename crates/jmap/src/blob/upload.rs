@@ -152,6 +152,23 @@ impl JMAP {
                 continue 'outer;
             }
 
+            // Validate content type
+            if let Some(rejected_type) = self.rejected_upload_type(
+                upload_object
+                    .type_
+                    .as_deref()
+                    .unwrap_or("application/octet-stream"),
+                &data,
+            ) {
+                response.not_created.append(
+                    create_id,
+                    SetError::forbidden().with_description(format!(
+                        "Uploading blobs of type {rejected_type:?} is not allowed."
+                    )),
+                );
+                continue 'outer;
+            }
+
             // Enforce quota
             let used = self.store.blob_quota(account_id).await.map_err(|err| {
                 tracing::error!(event = "error",
@@ -202,6 +219,15 @@ impl JMAP {
         // Limit concurrent uploads
         let _in_flight = self.is_upload_allowed(&access_token)?;
 
+        // Validate content type
+        if let Some(rejected_type) = self.rejected_upload_type(content_type, data) {
+            return Err(RequestError::blank(
+                415,
+                "Unsupported Media Type",
+                format!("Uploading blobs of type {rejected_type:?} is not allowed."),
+            ));
+        }
+
         #[cfg(feature = "test_mode")]
         {
             // Used for concurrent upload tests
@@ -255,6 +281,39 @@ impl JMAP {
         })
     }
 
+    pub fn rejected_upload_type(&self, content_type: &str, data: &[u8]) -> Option<String> {
+        if self.config.upload_allowed_types.is_empty() && self.config.upload_denied_types.is_empty()
+        {
+            return None;
+        }
+
+        // Check both the declared type and the type sniffed from the contents,
+        // so that spoofed content types are also rejected.
+        let declared_type = content_type
+            .split(';')
+            .next()
+            .map(|ct| ct.trim().to_ascii_lowercase())
+            .filter(|ct| !ct.is_empty());
+        let sniffed_type = infer::get(data).map(|t| t.mime_type().to_string());
+
+        [declared_type, sniffed_type]
+            .into_iter()
+            .flatten()
+            .find(|typ| {
+                (!self.config.upload_allowed_types.is_empty()
+                    && !self
+                        .config
+                        .upload_allowed_types
+                        .iter()
+                        .any(|pattern| matches_mime_type(pattern, typ)))
+                    || self
+                        .config
+                        .upload_denied_types
+                        .iter()
+                        .any(|pattern| matches_mime_type(pattern, typ))
+            })
+    }
+
     #[allow(clippy::blocks_in_conditions)]
     pub async fn put_blob(
         &self,
@@ -313,3 +372,12 @@ impl JMAP {
         })
     }
 }
+
+fn matches_mime_type(pattern: &str, typ: &str) -> bool {
+    if let Some(prefix) = pattern.strip_suffix("/*") {
+        typ.split_once('/')
+            .map_or(false, |(main_type, _)| main_type == prefix)
+    } else {
+        pattern == typ
+    }
+}
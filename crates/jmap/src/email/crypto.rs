@@ -685,12 +685,14 @@ impl JMAP {
             }
 
             // Authenticate
-            let token = if let AuthResult::Success(token) =
-                self.authenticate_plain(email, password, remote_addr).await
-            {
-                token
-            } else {
-                return Err(Cow::from("Invalid login or password"));
+            let token = match self.authenticate_plain(email, password, remote_addr).await {
+                AuthResult::Success(token) => token,
+                AuthResult::PasswordExpired => {
+                    return Err(Cow::from("Your password has expired, please change it"))
+                }
+                AuthResult::Failure | AuthResult::Banned => {
+                    return Err(Cow::from("Invalid login or password"))
+                }
             };
 
             if encryption != "disable" {
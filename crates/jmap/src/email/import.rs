@@ -41,7 +41,7 @@ use utils::map::vec_map::VecMap;
 
 use crate::{auth::AccessToken, IngestError, JMAP};
 
-use super::ingest::IngestEmail;
+use super::{ingest::IngestEmail, parse::mime_depth};
 
 impl JMAP {
     pub async fn email_import(
@@ -130,6 +130,18 @@ impl JMAP {
                 }
             };
 
+            // Messages nested too deeply are not handed to the parser
+            if mime_depth(&raw_message) > self.config.mail_parse_max_depth {
+                response.not_created.append(
+                    id,
+                    SetError::new(SetErrorType::InvalidEmail).with_description(format!(
+                        "Message exceeds the maximum MIME nesting depth of {}.",
+                        self.config.mail_parse_max_depth
+                    )),
+                );
+                continue;
+            }
+
             // Import message
             match self
                 .email_ingest(IngestEmail {
@@ -137,6 +149,7 @@ impl JMAP {
                     message: MessageParser::new().parse(&raw_message),
                     account_id,
                     account_quota,
+                    account_message_quota: None,
                     mailbox_ids,
                     keywords: email.keywords,
                     received_at: email.received_at.map(|r| r.into()),
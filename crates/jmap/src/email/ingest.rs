@@ -72,6 +72,7 @@ pub struct IngestEmail<'x> {
     pub message: Option<Message<'x>>,
     pub account_id: u32,
     pub account_quota: i64,
+    pub account_message_quota: Option<u32>,
     pub mailbox_ids: Vec<u32>,
     pub keywords: Vec<Keyword>,
     pub received_at: Option<u64>,
@@ -99,6 +100,17 @@ impl JMAP {
         {
             return Err(IngestError::OverQuota);
         }
+        if let Some(max_messages) = params.account_message_quota {
+            if self
+                .get_document_ids(params.account_id, Collection::Email)
+                .await
+                .map_err(|_| IngestError::Temporary)?
+                .map_or(0, |ids| ids.len())
+                >= max_messages as u64
+            {
+                return Err(IngestError::OverQuota);
+            }
+        }
 
         // Parse message
         let mut raw_message = Cow::from(params.raw_message);
@@ -108,7 +108,12 @@ impl JMAP {
                     continue;
                 }
             };
-            let message = if let Some(message) = MessageParser::new().parse(&raw_message) {
+            let message = if mime_depth(&raw_message) <= self.config.mail_parse_max_depth {
+                MessageParser::new().parse(&raw_message)
+            } else {
+                None
+            };
+            let message = if let Some(message) = message {
                 message
             } else {
                 response.not_parsable.push(blob_id);
@@ -264,3 +269,105 @@ impl JMAP {
         Ok(response)
     }
 }
+
+/// Returns the deepest level of nested multiparts and attached messages by
+/// scanning the raw message, so excessive nesting is caught before the
+/// message is handed to the parser.
+pub fn mime_depth(raw_message: &[u8]) -> usize {
+    // Enclosing containers of the current part, `None` for attached messages
+    let mut levels: Vec<Option<Vec<u8>>> = Vec::new();
+    let mut max_depth = 1;
+    let mut in_headers = true;
+    let mut content_type: Option<Vec<u8>> = None;
+    let mut last_header_is_content_type = false;
+
+    for line in raw_message.split(|&ch| ch == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        if in_headers {
+            if line.is_empty() {
+                in_headers = false;
+                last_header_is_content_type = false;
+                match content_type.take().map(ContentType::parse) {
+                    Some(ContentType::Multipart(boundary)) => {
+                        levels.push(Some(boundary));
+                    }
+                    Some(ContentType::Message) => {
+                        levels.push(None);
+                        in_headers = true;
+                        max_depth = max_depth.max(levels.len() + 1);
+                    }
+                    _ => (),
+                }
+            } else if matches!(line.first(), Some(b' ' | b'\t')) {
+                if let (Some(value), true) = (&mut content_type, last_header_is_content_type) {
+                    value.extend_from_slice(line);
+                }
+            } else {
+                last_header_is_content_type =
+                    line.len() > 13 && line[..13].eq_ignore_ascii_case(b"content-type:");
+                if last_header_is_content_type {
+                    content_type = Some(line[13..].to_vec());
+                }
+            }
+        } else if let Some(delimiter) = line.strip_prefix(b"--") {
+            let delimiter = delimiter.trim_ascii_end();
+            if let Some((pos, is_last)) =
+                levels.iter().enumerate().rev().find_map(|(pos, level)| {
+                    let rest = delimiter.strip_prefix(level.as_deref()?)?;
+                    match rest {
+                        b"" => Some((pos, false)),
+                        b"--" => Some((pos, true)),
+                        _ => None,
+                    }
+                })
+            {
+                if is_last {
+                    levels.truncate(pos);
+                } else {
+                    levels.truncate(pos + 1);
+                    in_headers = true;
+                    max_depth = max_depth.max(levels.len() + 1);
+                }
+            }
+        }
+    }
+
+    max_depth
+}
+
+enum ContentType {
+    Multipart(Vec<u8>),
+    Message,
+    Other,
+}
+
+impl ContentType {
+    fn parse(value: Vec<u8>) -> Self {
+        let value = value.trim_ascii();
+        let (mime_type, params) = value
+            .iter()
+            .position(|&ch| ch == b';')
+            .map_or((value, &b""[..]), |pos| (&value[..pos], &value[pos + 1..]));
+
+        match mime_type.trim_ascii().to_ascii_lowercase().as_slice() {
+            b"message/rfc822" | b"message/global" => ContentType::Message,
+            mime_type if mime_type.starts_with(b"multipart/") => params
+                .split(|&ch| ch == b';')
+                .find_map(|param| {
+                    let param = param.trim_ascii();
+                    let boundary = param
+                        .get(..9)
+                        .filter(|name| name.eq_ignore_ascii_case(b"boundary="))
+                        .map(|_| &param[9..])?;
+                    let boundary = boundary
+                        .strip_prefix(b"\"")
+                        .and_then(|b| b.strip_suffix(b"\""))
+                        .unwrap_or(boundary);
+                    (!boundary.is_empty()).then(|| ContentType::Multipart(boundary.to_vec()))
+                })
+                .unwrap_or(ContentType::Other),
+            _ => ContentType::Other,
+        }
+    }
+}
@@ -737,6 +737,7 @@ impl JMAP {
                     message: MessageParser::new().parse(&raw_message),
                     account_id,
                     account_quota,
+                    account_message_quota: None,
                     mailbox_ids: mailboxes,
                     keywords,
                     received_at,
@@ -24,10 +24,10 @@
 use std::{collections::hash_map::RandomState, fmt::Display, sync::Arc, time::Duration};
 
 use ::sieve::{Compiler, Runtime};
-use api::session::BaseCapabilities;
+use api::session::{BaseCapabilities, Session};
 use auth::{oauth::OAuthCode, rate_limit::ConcurrencyLimiters, AccessToken};
 use dashmap::DashMap;
-use directory::{Directories, Directory, QueryBy};
+use directory::{backend::internal::policy::PrincipalPolicy, Directories, Directory, QueryBy};
 use email::cache::Threads;
 use jmap_proto::{
     error::method::MethodError,
@@ -57,7 +57,7 @@ use store::{
 };
 use tokio::sync::mpsc;
 use utils::{
-    config::{Rate, Servers},
+    config::{cron::SimpleCron, Rate, Servers},
     ipc::DeliveryEvent,
     lru_cache::{LruCache, LruCached},
     map::ttl_dashmap::{TtlDashMap, TtlMap},
@@ -104,6 +104,7 @@ pub struct JMAP {
     pub smtp: Arc<SMTP>,
 
     pub cache_threads: LruCache<u32, Arc<Threads>>,
+    pub cache_sessions: TtlDashMap<(u32, String), Arc<Session>>,
 
     pub sieve_compiler: Compiler,
     pub sieve_runtime: Runtime<()>,
@@ -124,21 +125,27 @@ pub struct Config {
 
     pub upload_max_size: usize,
     pub upload_max_concurrent: u64,
+    pub session_max_concurrent: u64,
+    pub upload_allowed_types: Vec<String>,
+    pub upload_denied_types: Vec<String>,
 
     pub upload_tmp_quota_size: usize,
     pub upload_tmp_quota_amount: usize,
     pub upload_tmp_ttl: u64,
+    pub upload_tmp_purge_frequency: SimpleCron,
 
     pub mailbox_max_depth: usize,
     pub mailbox_name_max_len: usize,
     pub mail_attachments_max_size: usize,
     pub mail_parse_max_items: usize,
+    pub mail_parse_max_depth: usize,
     pub mail_max_size: usize,
 
     pub sieve_max_script_name: usize,
     pub sieve_max_scripts: usize,
 
     pub session_cache_ttl: Duration,
+    pub session_ttl: Duration,
     pub rate_authenticated: Rate,
     pub rate_authenticate_req: Rate,
     pub rate_anonymous: Rate,
@@ -167,6 +174,7 @@ pub struct Config {
     pub encrypt_append: bool,
 
     pub principal_allow_lookups: bool,
+    pub principal_policy: PrincipalPolicy,
 
     pub capabilities: BaseCapabilities,
 }
@@ -225,6 +233,7 @@ impl JMAP {
             cache_threads: LruCache::with_capacity(
                 config.property("cache.thread.size")?.unwrap_or(2048),
             ),
+            cache_sessions: TtlDashMap::with_capacity(capacity, shard_amount),
             state_tx,
             housekeeper_tx,
             smtp,
@@ -742,7 +751,7 @@ impl JMAP {
             .map(|_| ())
             .map_err(|err| {
                 match err {
-                    store::Error::InternalError(err) => {
+                    store::Error::InternalError(err) | store::Error::TransientError(err) => {
                         tracing::error!(
                         event = "error",
                         context = "write_batch",
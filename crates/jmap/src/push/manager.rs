@@ -25,7 +25,10 @@ use base64::{engine::general_purpose, Engine};
 use jmap_proto::types::id::Id;
 use store::ahash::{AHashMap, AHashSet};
 use tokio::sync::mpsc;
-use utils::{config::Config, UnwrapFailure};
+use utils::{
+    config::{utils::DurationRange, Config},
+    UnwrapFailure,
+};
 
 use crate::{api::StateChangeResponse, services::IPC_CHANNEL_BUFFER, LONG_SLUMBER};
 
@@ -51,11 +54,13 @@ pub fn spawn_push_manager(settings: &Config) -> mpsc::Sender<Event> {
         .property_or_default("jmap.push.retry.interval", "1s")
         .failed("Invalid configuration");
     let push_timeout: Duration = settings
-        .property_or_default("jmap.push.timeout.request", "10s")
-        .failed("Invalid configuration");
+        .property_or_default::<DurationRange<1000, 300_000>>("jmap.push.timeout.request", "10s")
+        .failed("Invalid configuration")
+        .into();
     let push_verify_timeout: Duration = settings
-        .property_or_default("jmap.push.timeout.verify", "1m")
-        .failed("Invalid configuration");
+        .property_or_default::<DurationRange<1000, 3_600_000>>("jmap.push.timeout.verify", "1m")
+        .failed("Invalid configuration")
+        .into();
     let push_throttle: Duration = settings
         .property_or_default("jmap.push.throttle", "1s")
         .failed("Invalid configuration");
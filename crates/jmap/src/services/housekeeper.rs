@@ -23,6 +23,7 @@
 
 use std::sync::Arc;
 
+use chrono::Local;
 use store::dispatch::blocked::BLOCKED_IP_PREFIX;
 use tokio::sync::mpsc;
 use utils::{
@@ -55,6 +56,7 @@ pub fn spawn_housekeeper(
     let purge_cache = settings
         .property_or_default::<SimpleCron>("jmap.session.purge.frequency", "15 * *")
         .failed("Initialize housekeeper");
+    let purge_uploads = core.config.upload_tmp_purge_frequency;
 
     let certificates = std::mem::take(&mut servers.certificates);
 
@@ -71,8 +73,12 @@ pub fn spawn_housekeeper(
         });
 
         loop {
-            let time_to_next = purge_cache.time_to_next();
+            let now = Local::now();
+            let time_to_purge_cache = purge_cache.time_to_next_from(now);
+            let time_to_purge_uploads = purge_uploads.time_to_next_from(now);
+            let time_to_next = std::cmp::min(time_to_purge_cache, time_to_purge_uploads);
             let mut do_purge = false;
+            let mut do_purge_uploads = false;
 
             match tokio::time::timeout(time_to_next, rx.recv()).await {
                 Ok(Some(event)) => match event {
@@ -173,7 +179,9 @@ pub fn spawn_housekeeper(
                     return;
                 }
                 Err(_) => {
-                    do_purge = true;
+                    // Run every schedule that is due
+                    do_purge = time_to_purge_cache <= time_to_next;
+                    do_purge_uploads = time_to_purge_uploads <= time_to_next;
                 }
             }
 
@@ -183,11 +191,27 @@ pub fn spawn_housekeeper(
                     tracing::info!("Purging session cache.");
                     core.sessions.cleanup();
                     core.access_tokens.cleanup();
+                    core.cache_sessions.cleanup();
                     core.oauth_codes.cleanup();
                     core.concurrency_limiter
                         .retain(|_, limiter| limiter.is_active());
                 });
             }
+
+            if do_purge_uploads {
+                let core = core.clone();
+                tokio::spawn(async move {
+                    tracing::info!("Purging expired temporary uploads.");
+                    if let Err(err) = core.store.purge_tmp_blobs(core.blob_store.clone()).await {
+                        tracing::error!(
+                            context = "housekeeper",
+                            event = "error",
+                            error = ?err,
+                            "Failed to purge expired temporary uploads."
+                        );
+                    }
+                });
+            }
         }
     });
 }
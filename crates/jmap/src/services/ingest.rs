@@ -73,22 +73,24 @@ impl JMAP {
                     .await
                 }
                 Ok(None) => {
-                    let account_quota = match self.directory.query(QueryBy::Id(*uid), false).await {
-                        Ok(Some(p)) => p.quota as i64,
-                        Ok(None) => 0,
-                        Err(_) => {
-                            *status = DeliveryResult::TemporaryFailure {
-                                reason: "Transient server failure.".into(),
-                            };
-                            continue;
-                        }
-                    };
+                    let (account_quota, account_message_quota) =
+                        match self.directory.query(QueryBy::Id(*uid), false).await {
+                            Ok(Some(p)) => (p.quota as i64, p.message_count_quota),
+                            Ok(None) => (0, None),
+                            Err(_) => {
+                                *status = DeliveryResult::TemporaryFailure {
+                                    reason: "Transient server failure.".into(),
+                                };
+                                continue;
+                            }
+                        };
 
                     self.email_ingest(IngestEmail {
                         raw_message: &raw_message,
                         message: MessageParser::new().parse(&raw_message),
                         account_id: *uid,
                         account_quota,
+                        account_message_quota,
                         mailbox_ids: vec![INBOX_ID],
                         keywords: vec![],
                         received_at: None,
@@ -79,13 +79,17 @@ impl JMAP {
         let mut instance = self.sieve_runtime.filter_parsed(message);
 
         // Set account name and obtain quota
-        let (account_quota, mail_from) =
+        let (account_quota, account_message_quota, mail_from) =
             match self.directory.query(QueryBy::Id(account_id), false).await {
                 Ok(Some(p)) => {
                     instance.set_user_full_name(p.description().unwrap_or_else(|| p.name()));
-                    (p.quota as i64, p.emails.into_iter().next())
+                    (
+                        p.quota as i64,
+                        p.message_count_quota,
+                        p.emails.into_iter().next(),
+                    )
                 }
-                Ok(None) => (0, None),
+                Ok(None) => (0, None, None),
                 Err(_) => {
                     return Err(IngestError::Temporary);
                 }
@@ -438,6 +442,7 @@ impl JMAP {
                         message: message.into(),
                         account_id,
                         account_quota,
+                        account_message_quota,
                         mailbox_ids: sieve_message.file_into,
                         keywords: sieve_message.flags,
                         received_at: None,
@@ -56,7 +56,9 @@ use utils::{
     map::vec_map::VecMap,
 };
 
-use crate::{email::metadata::MessageMetadata, identity::set::sanitize_email, JMAP};
+use crate::{
+    auth::AccessToken, email::metadata::MessageMetadata, identity::set::sanitize_email, JMAP,
+};
 
 pub static SCHEMA: &[IndexProperty] = &[
     IndexProperty::new(Property::UndoStatus).index_as(IndexAs::Text {
@@ -73,6 +75,7 @@ impl JMAP {
     pub async fn email_submission_set(
         &self,
         mut request: SetRequest<SetArguments>,
+        access_token: &AccessToken,
         instance: &Arc<ServerInstance>,
         next_call: &mut Option<Call<RequestMethod>>,
     ) -> Result<SetResponse, MethodError> {
@@ -85,7 +88,7 @@ impl JMAP {
         let mut success_email_ids = HashMap::new();
         for (id, object) in request.unwrap_create() {
             match self
-                .send_message(account_id, &response, instance, object)
+                .send_message(account_id, access_token, &response, instance, object)
                 .await?
             {
                 Ok(submission) => {
@@ -314,6 +317,7 @@ impl JMAP {
     async fn send_message(
         &self,
         account_id: u32,
+        access_token: &AccessToken,
         response: &SetResponse,
         instance: &Arc<ServerInstance>,
         object: Object<SetValue>,
@@ -580,6 +584,10 @@ impl JMAP {
         let mut session =
             Session::<NullIo>::local(self.smtp.clone(), instance.clone(), SessionData::default());
 
+        // Submissions share the sending rate of SMTP clients
+        session.data.authenticated_as = access_token.name.clone();
+        session.eval_send_rate(access_token.is_super_user()).await;
+
         // MAIL FROM
         let _ = session.handle_mail_from(mail_from).await;
         if let Some(error) = session.has_failed() {
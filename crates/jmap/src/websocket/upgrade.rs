@@ -79,8 +79,16 @@ pub async fn upgrade_websocket_connection(
         }
     };
 
+    // Enforce concurrent session limit
+    let in_flight = match jmap.is_session_allowed(&access_token) {
+        Ok(in_flight) => in_flight,
+        Err(err) => return err.into_http_response(),
+    };
+
     // Spawn WebSocket connection
     tokio::spawn(async move {
+        let _in_flight = in_flight;
+
         // Upgrade connection
         match hyper::upgrade::on(req).await {
             Ok(upgraded) => {
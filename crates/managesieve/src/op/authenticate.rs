@@ -23,7 +23,7 @@
 
 use std::sync::Arc;
 
-use directory::AuthResult;
+use directory::{AuthResult, FEATURE_IMAP};
 use imap::op::authenticate::{decode_challenge_oauth, decode_challenge_plain};
 use imap_proto::{
     protocol::authenticate::Mechanism,
@@ -107,6 +107,12 @@ impl<T: SessionStream> Session<T> {
                             "Too many authentication requests from this IP address.",
                         ))
                     }
+                    AuthResult::PasswordExpired => {
+                        return Ok(
+                            StatusResponse::no("Password has expired, please change it.")
+                                .into_bytes(),
+                        )
+                    }
                 }
             }
             Credentials::OAuthBearer { token } => {
@@ -129,6 +135,21 @@ impl<T: SessionStream> Session<T> {
             }
         };
 
+        // ManageSieve is offered alongside IMAP and follows the same flag
+        let access_token = match access_token {
+            Some(access_token) if !access_token.has_feature(FEATURE_IMAP) => {
+                tracing::debug!(
+                    parent: &self.span,
+                    context = "authenticate",
+                    event = "feature-disabled",
+                    account = access_token.name,
+                    "ManageSieve access is disabled for this account."
+                );
+                None
+            }
+            access_token => access_token,
+        };
+
         if let Some(access_token) = access_token {
             // Enforce concurrency limits
             let in_flight = self
@@ -31,7 +31,7 @@ use mail_parser::decoders::base64::base64_decode;
 use utils::{
     config::{
         if_block::IfBlock,
-        utils::{AsKey, ConstantValue, ParseValue},
+        utils::{AsKey, ConstantValue, NoConstants, ParseValue},
         Config,
     },
     expr::{self, Constant, Token},
@@ -41,7 +41,8 @@ use crate::core::eval::*;
 
 use super::{
     map_expr_token, ArcAuthConfig, ArcSealer, ConfigContext, DkimAuthConfig, DkimCanonicalization,
-    DkimSigner, DmarcAuthConfig, IpRevAuthConfig, MailAuthConfig, SpfAuthConfig, VerifyStrategy,
+    DkimSignFailure, DkimSigner, DmarcAuthConfig, IpRevAuthConfig, MailAuthConfig, SpfAuthConfig,
+    SpfCheck, VerifyStrategy,
 };
 
 pub trait ConfigAuth {
@@ -77,6 +78,25 @@ impl ConfigAuth for Config {
                 sign: self
                     .parse_if_block("auth.dkim.sign", fn_sender_keys)?
                     .unwrap_or_default(),
+                sign_by_domain: self
+                    .parse_if_block("auth.dkim.sign-by-domain", fn_sender_keys)?
+                    .unwrap_or_else(|| IfBlock::new(false)),
+                on_sign_error: self
+                    .parse_if_block("auth.dkim.on-sign-error", |name| {
+                        map_expr_token::<DkimSignFailure>(
+                            name,
+                            &[
+                                V_SENDER,
+                                V_SENDER_DOMAIN,
+                                V_PRIORITY,
+                                V_AUTHENTICATED_AS,
+                                V_LISTENER,
+                                V_REMOTE_IP,
+                                V_LOCAL_IP,
+                            ],
+                        )
+                    })?
+                    .unwrap_or_else(|| IfBlock::new(DkimSignFailure::SendUnsigned)),
             },
             arc: ArcAuthConfig {
                 verify: self
@@ -93,6 +113,21 @@ impl ConfigAuth for Config {
                 verify_mail_from: self
                     .parse_if_block("auth.spf.verify.mail-from", fn_conn_keys)?
                     .unwrap_or_else(|| IfBlock::new(VerifyStrategy::Relaxed)),
+                check: self
+                    .parse_if_block("auth.spf.check", |name| {
+                        map_expr_token::<SpfCheck>(name, &[V_LISTENER, V_REMOTE_IP, V_LOCAL_IP])
+                    })?
+                    .unwrap_or_default(),
+                max_lookups: self
+                    .parse_if_block("auth.spf.max-lookups", |name| {
+                        map_expr_token::<NoConstants>(name, &[V_LISTENER, V_REMOTE_IP, V_LOCAL_IP])
+                    })?
+                    .unwrap_or_else(|| IfBlock::new(10)),
+                max_macro_lookups: self
+                    .parse_if_block("auth.spf.max-macro-lookups", |name| {
+                        map_expr_token::<NoConstants>(name, &[V_LISTENER, V_REMOTE_IP, V_LOCAL_IP])
+                    })?
+                    .unwrap_or_else(|| IfBlock::new(5)),
             },
             dmarc: DmarcAuthConfig {
                 verify: self
@@ -200,10 +235,29 @@ impl ConfigAuth for Config {
                         ))
                     }
                 };
-            ctx.signers.insert(id.to_string(), Arc::new(signer));
+            let signer = Arc::new(signer);
+            let domain = self.value_require(("signature", id, "domain"))?;
+
+            // The first signature configured for a domain is used for that domain
+            ctx.domain_signers
+                .domains
+                .entry(domain.to_lowercase())
+                .or_insert_with(|| signer.clone());
+            ctx.signers.insert(id.to_string(), signer);
             ctx.sealers.insert(id.to_string(), Arc::new(sealer));
         }
 
+        if let Some(id) = self.value("auth.dkim.default-signer") {
+            ctx.domain_signers.default = ctx
+                .signers
+                .get(id)
+                .ok_or_else(|| {
+                    format!("Signature {id:?} not found for key \"auth.dkim.default-signer\".")
+                })?
+                .clone()
+                .into();
+        }
+
         Ok(())
     }
 }
@@ -270,7 +324,17 @@ fn parse_signature<T: SigningKey, U: SigningKey<Hasher = Sha256>>(
         sealer = sealer.expiration(c.as_secs());
     }
 
-    if let Some(true) = config.property::<bool>(("signature", id, "set-body-length"))? {
+    // Signatures inherit the body length setting from 'auth.dkim.body-length'
+    // unless they override it explicitly.
+    let body_length = match config.property::<bool>(("signature", id, "set-body-length"))? {
+        Some(body_length) => body_length,
+        None => config.property_or_default::<bool>("auth.dkim.body-length", "false")?,
+    };
+    if body_length {
+        tracing::warn!(
+            "DKIM signature {id:?} sets the body length tag (l=), which allows content to be appended to signed messages.",
+            id = id
+        );
         signer = signer.body_length(true);
         sealer = sealer.body_length(true);
     }
@@ -337,6 +401,86 @@ impl ParseValue for VerifyStrategy {
 
 impl ConstantValue for VerifyStrategy {}
 
+impl<'x> TryFrom<expr::Variable<'x>> for DkimSignFailure {
+    type Error = ();
+
+    fn try_from(value: expr::Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            expr::Variable::Integer(0) => Ok(DkimSignFailure::SendUnsigned),
+            expr::Variable::Integer(1) => Ok(DkimSignFailure::Defer),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<DkimSignFailure> for Constant {
+    fn from(value: DkimSignFailure) -> Self {
+        Constant::Integer(match value {
+            DkimSignFailure::SendUnsigned => 0,
+            DkimSignFailure::Defer => 1,
+        })
+    }
+}
+
+impl ParseValue for DkimSignFailure {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "send-unsigned" => Ok(DkimSignFailure::SendUnsigned),
+            "defer" => Ok(DkimSignFailure::Defer),
+            _ => Err(format!(
+                "Invalid value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl ConstantValue for DkimSignFailure {}
+
+impl<'x> TryFrom<expr::Variable<'x>> for SpfCheck {
+    type Error = ();
+
+    fn try_from(value: expr::Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            expr::Variable::Integer(c) => match c {
+                2 => Ok(SpfCheck::MailFrom),
+                3 => Ok(SpfCheck::Helo),
+                4 => Ok(SpfCheck::Both),
+                _ => Err(()),
+            },
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<SpfCheck> for Constant {
+    fn from(value: SpfCheck) -> Self {
+        Constant::Integer(match value {
+            SpfCheck::MailFrom => 2,
+            SpfCheck::Helo => 3,
+            SpfCheck::Both => 4,
+        })
+    }
+}
+
+impl ParseValue for SpfCheck {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "mailfrom" | "mail-from" => Ok(SpfCheck::MailFrom),
+            "helo" | "ehlo" => Ok(SpfCheck::Helo),
+            "both" => Ok(SpfCheck::Both),
+            _ => Err(format!(
+                "Invalid value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl ConstantValue for SpfCheck {}
+
 impl ParseValue for DkimCanonicalization {
     fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
         if let Some((headers, body)) = value.split_once('/') {
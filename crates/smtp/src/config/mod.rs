@@ -33,7 +33,7 @@ pub mod throttle;
 use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use ahash::AHashMap;
-use directory::Directories;
+use directory::{core::config::LookupMap, Directories};
 use mail_auth::{
     common::crypto::{Ed25519Key, RsaKey, Sha256},
     dkim::{Canonicalization, Done},
@@ -44,12 +44,16 @@ use store::Stores;
 use utils::{
     config::{if_block::IfBlock, utils::ConstantValue, Rate, ServerProtocol},
     expr::{Expression, Token},
+    listener::limiter::ConcurrencyLimiter,
     snowflake::SnowflakeIdGenerator,
 };
 
 use crate::{
-    core::eval::{FUNCTIONS_MAP, VARIABLES_MAP},
-    inbound::milter,
+    core::{
+        backpressure::Backpressure,
+        eval::{FUNCTIONS_MAP, VARIABLES_MAP},
+    },
+    inbound::{auth::SaslMechanisms, milter},
 };
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -79,6 +83,8 @@ pub const THROTTLE_REMOTE_IP: u16 = 1 << 7;
 pub const THROTTLE_LOCAL_IP: u16 = 1 << 8;
 pub const THROTTLE_HELO_DOMAIN: u16 = 1 << 9;
 
+pub const DEFAULT_HELP_MESSAGE: &str = "Help can be found at https://stalw.art/smtp/";
+
 pub struct Connect {
     pub script: IfBlock,
 }
@@ -87,41 +93,63 @@ pub struct Ehlo {
     pub script: IfBlock,
     pub require: IfBlock,
     pub reject_non_fqdn: IfBlock,
+    pub helo_extensions: IfBlock,
 }
 
 pub struct Extensions {
     pub pipelining: IfBlock,
+    pub starttls_pipelining: IfBlock,
     pub chunking: IfBlock,
     pub requiretls: IfBlock,
     pub dsn: IfBlock,
     pub vrfy: IfBlock,
     pub expn: IfBlock,
+    pub etrn: IfBlock,
+    pub etrn_domains: Vec<String>,
     pub no_soliciting: IfBlock,
     pub future_release: IfBlock,
     pub deliver_by: IfBlock,
     pub mt_priority: IfBlock,
+    pub enhanced_status_codes: IfBlock,
+    pub size: IfBlock,
+    pub size_value: IfBlock,
 }
 
 pub struct Auth {
     pub directory: IfBlock,
     pub mechanisms: IfBlock,
+    pub custom_mechanisms: IfBlock,
     pub require: IfBlock,
     pub allow_plain_text: IfBlock,
     pub must_match_sender: IfBlock,
+    pub send_as: IfBlock,
+    pub send_rate: IfBlock,
     pub errors_max: IfBlock,
     pub errors_wait: IfBlock,
+    pub max_attempts: IfBlock,
+    pub max_response_size: IfBlock,
+    pub sasl: SaslMechanisms,
 }
 
 pub struct Mail {
     pub script: IfBlock,
     pub rewrite: IfBlock,
+    pub verify_sender_domain: IfBlock,
+    pub address_literals: IfBlock,
+    pub multiple_commands: IfBlock,
+    pub timeout: IfBlock,
 }
 
 pub struct Rcpt {
     pub script: IfBlock,
+    pub timeout: IfBlock,
     pub relay: IfBlock,
     pub directory: IfBlock,
     pub rewrite: IfBlock,
+    pub postmaster: IfBlock,
+    pub bare_local_part: IfBlock,
+    pub default_domain: IfBlock,
+    pub address_literals: IfBlock,
 
     // Errors
     pub errors_max: IfBlock,
@@ -129,6 +157,8 @@ pub struct Rcpt {
 
     // Limits
     pub max_recipients: IfBlock,
+    pub max_recipients_null_sender: IfBlock,
+    pub over_quota: IfBlock,
 }
 
 pub struct Data {
@@ -139,7 +169,23 @@ pub struct Data {
     // Limits
     pub max_messages: IfBlock,
     pub max_message_size: IfBlock,
+    pub max_message_size_domain: LookupMap,
     pub max_received_headers: IfBlock,
+    pub max_line_length: IfBlock,
+    pub max_concurrent: Option<ConcurrencyLimiter>,
+
+    // Timeouts
+    pub timeout_init: IfBlock,
+    pub timeout: IfBlock,
+    pub backpressure: Option<Backpressure>,
+
+    // Line endings
+    pub bare_lf: IfBlock,
+
+    // Duplicate suppression
+    pub duplicate_window: IfBlock,
+    pub duplicate_scope: IfBlock,
+    pub duplicate_action: IfBlock,
 
     // Headers
     pub add_received: IfBlock,
@@ -148,6 +194,17 @@ pub struct Data {
     pub add_auth_results: IfBlock,
     pub add_message_id: IfBlock,
     pub add_date: IfBlock,
+    pub missing_headers: IfBlock,
+    pub eight_bit_headers: IfBlock,
+    pub strip_bcc: IfBlock,
+
+    // Received header
+    pub received_ip: IfBlock,
+    pub received_tls: IfBlock,
+    pub received_protocol: IfBlock,
+
+    // Submission proxy
+    pub proxy_directory: IfBlock,
 }
 
 pub struct Pipe {
@@ -177,7 +234,13 @@ pub struct SessionConfig {
     pub timeout: IfBlock,
     pub duration: IfBlock,
     pub transfer_limit: IfBlock,
+    pub max_line_length: IfBlock,
+    pub command_leniency: IfBlock,
+    pub max_noop_commands: IfBlock,
     pub throttle: SessionThrottle,
+    pub tarpit: Tarpit,
+    pub pipelining: Pipelining,
+    pub help: Help,
 
     pub connect: Connect,
     pub ehlo: Ehlo,
@@ -186,6 +249,108 @@ pub struct SessionConfig {
     pub rcpt: Rcpt,
     pub data: Data,
     pub extensions: Extensions,
+    pub reject: RejectMessages,
+    pub srs: Option<Srs>,
+    pub dnsbl: Dnsbl,
+    pub score: ScoreConfig,
+    pub trusted_networks: LookupMap,
+}
+
+#[derive(Default)]
+pub struct Dnsbl {
+    pub lists: Vec<DnsblList>,
+    pub max_score: Option<u32>,
+    pub negative_ttl: Duration,
+}
+
+pub struct DnsblList {
+    pub id: String,
+    pub zone: String,
+    pub typ: DnsblType,
+    pub action: DnsblAction,
+}
+
+// DNSBL zones are queried by remote IP at connect time, RHSBL zones by
+// sender domain at MAIL FROM time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsblType {
+    Ip,
+    Domain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsblAction {
+    Reject,
+    Score(u32),
+}
+
+// Weights added to the message score for each authentication outcome, keyed
+// by signal and result name (for example 'spf' and 'softfail'). Each DNSBL
+// score point is multiplied by the 'dnsbl' weight.
+#[derive(Debug, Default, Clone)]
+pub struct ScoreConfig {
+    pub weights: AHashMap<(ScoreSignal, &'static str), f64>,
+    pub dnsbl: f64,
+    pub reject: Option<f64>,
+    pub spam: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScoreSignal {
+    Spf,
+    Dkim,
+    Dmarc,
+    Iprev,
+}
+
+#[derive(Clone)]
+pub struct Srs {
+    pub domain: String,
+    pub secret: String,
+    pub max_age: Duration,
+}
+
+pub struct Tarpit {
+    pub delay: IfBlock,
+    pub multiplier: IfBlock,
+    pub max_delay: IfBlock,
+}
+
+pub struct Pipelining {
+    pub max_commands: IfBlock,
+    pub max_size: IfBlock,
+}
+
+pub struct Help {
+    pub enable: IfBlock,
+    pub message: IfBlock,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RejectMessages {
+    pub sender_not_allowed: Option<ResponseTemplate>,
+    pub reverse_dns: Option<ResponseTemplate>,
+    pub unknown_recipient: Option<ResponseTemplate>,
+    pub relay_denied: Option<ResponseTemplate>,
+    pub unverified_recipient: Option<ResponseTemplate>,
+    pub dkim: Option<ResponseTemplate>,
+    pub arc: Option<ResponseTemplate>,
+    pub dmarc: Option<ResponseTemplate>,
+    pub deferral: Option<ResponseTemplate>,
+    pub retry_after: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseTemplate {
+    pub items: Vec<TemplateItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateItem {
+    Text(String),
+    Sender,
+    Recipient,
+    Reason,
 }
 
 pub struct SessionThrottle {
@@ -201,6 +366,7 @@ pub struct RelayHost {
     pub auth: Option<Credentials<String>>,
     pub tls_implicit: bool,
     pub tls_allow_invalid_certs: bool,
+    pub concurrency: Option<ConcurrencyLimiter>,
 }
 
 pub struct QueueConfig {
@@ -217,6 +383,7 @@ pub struct QueueConfig {
     pub ip_strategy: IfBlock,
     pub source_ip: QueueOutboundSourceIp,
     pub tls: QueueOutboundTls,
+    pub downgrade_8bitmime: IfBlock,
     pub dsn: Dsn,
 
     // Timeouts
@@ -260,6 +427,7 @@ pub struct Dsn {
     pub name: IfBlock,
     pub address: IfBlock,
     pub sign: IfBlock,
+    pub max_size: IfBlock,
 }
 
 pub struct AggregateReport {
@@ -342,6 +510,13 @@ pub enum RequireOptional {
     Disable,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Downgrade8BitMime {
+    #[default]
+    Convert,
+    Reject,
+}
+
 pub struct MailAuthConfig {
     pub dkim: DkimAuthConfig,
     pub arc: ArcAuthConfig,
@@ -355,6 +530,12 @@ pub enum DkimSigner {
     Ed25519Sha256(mail_auth::dkim::DkimSigner<Ed25519Key, Done>),
 }
 
+#[derive(Default, Clone)]
+pub struct DkimDomainSigners {
+    pub domains: AHashMap<String, Arc<DkimSigner>>,
+    pub default: Option<Arc<DkimSigner>>,
+}
+
 pub enum ArcSealer {
     RsaSha256(mail_auth::arc::ArcSealer<RsaKey<Sha256>, Done>),
     Ed25519Sha256(mail_auth::arc::ArcSealer<Ed25519Key, Done>),
@@ -363,6 +544,8 @@ pub enum ArcSealer {
 pub struct DkimAuthConfig {
     pub verify: IfBlock,
     pub sign: IfBlock,
+    pub sign_by_domain: IfBlock,
+    pub on_sign_error: IfBlock,
 }
 
 pub struct ArcAuthConfig {
@@ -373,6 +556,9 @@ pub struct ArcAuthConfig {
 pub struct SpfAuthConfig {
     pub verify_ehlo: IfBlock,
     pub verify_mail_from: IfBlock,
+    pub check: IfBlock,
+    pub max_lookups: IfBlock,
+    pub max_macro_lookups: IfBlock,
 }
 pub struct DmarcAuthConfig {
     pub verify: IfBlock,
@@ -388,6 +574,25 @@ pub struct DkimCanonicalization {
     pub body: Canonicalization,
 }
 
+// Action taken when a message cannot be DKIM signed, either queue it
+// without the signature or defer it until the signer is fixed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DkimSignFailure {
+    #[default]
+    SendUnsigned,
+    Defer,
+}
+
+// Identities checked by SPF. Under 'Both' the combined result only passes
+// when both the HELO and MAIL FROM identities pass. When unset, each identity
+// is checked according to its own verify strategy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpfCheck {
+    MailFrom,
+    Helo,
+    Both,
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 pub enum VerifyStrategy {
     #[default]
@@ -396,12 +601,97 @@ pub enum VerifyStrategy {
     Disable,
 }
 
+// Whether SMTP commands with lowercase verbs or extra whitespace are accepted,
+// strict mode rejects anything that deviates from the RFC 5321 syntax.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommandLeniency {
+    Strict,
+    #[default]
+    Lenient,
+}
+
+// Response given to recipients whose mailbox is over quota, either a
+// permanent rejection or a temporary failure so that the sender retries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverQuota {
+    #[default]
+    Reject,
+    Defer,
+}
+
+// Handling of recipients given as a local part without a domain,
+// either rejected or qualified with the default domain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BareLocalPart {
+    #[default]
+    Reject,
+    AppendDefaultDomain,
+}
+
+// Handling of addresses with a domain literal such as user@[192.0.2.1].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddressLiteral {
+    #[default]
+    Reject,
+    Allow,
+}
+
+// Handling of lines terminated by a bare LF rather than CRLF in DATA,
+// which can be abused to smuggle messages past other servers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BareLf {
+    Reject,
+    #[default]
+    Convert,
+    Allow,
+}
+
+// Whether a repeated Message-ID is tracked across all recipients or for
+// each recipient separately.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateScope {
+    Global,
+    #[default]
+    Recipient,
+}
+
+// What to do with a message whose Message-ID was already delivered within
+// the window, silently drop it or reject it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateAction {
+    #[default]
+    Discard,
+    Reject,
+}
+
+// What to do with a message that lacks a Date or Message-ID header which
+// was not added by the server, accept it, flag it with a header or reject it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingHeaders {
+    #[default]
+    Allow,
+    Flag,
+    Reject,
+}
+
+// What to do with a message whose header values contain raw 8-bit bytes
+// outside of RFC 2047 encoded-words, unless the client requested SMTPUTF8.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EightBitHeaders {
+    #[default]
+    Allow,
+    Flag,
+    Encode,
+    Reject,
+}
+
 #[derive(Default)]
 pub struct ConfigContext {
     pub directory: Directories,
     pub stores: Stores,
     pub scripts: AHashMap<String, Arc<Sieve>>,
     pub signers: AHashMap<String, Arc<DkimSigner>>,
+    pub domain_signers: DkimDomainSigners,
     pub sealers: AHashMap<String, Arc<ArcSealer>>,
 }
 
@@ -450,6 +740,10 @@ impl std::fmt::Debug for RelayHost {
             .field("protocol", &self.protocol)
             .field("tls_implicit", &self.tls_implicit)
             .field("tls_allow_invalid_certs", &self.tls_allow_invalid_certs)
+            .field(
+                "max_concurrent",
+                &self.concurrency.as_ref().map(|c| c.max_concurrent),
+            )
             .finish()
     }
 }
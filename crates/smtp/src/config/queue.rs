@@ -30,9 +30,10 @@ use crate::core::eval::*;
 use super::{
     map_expr_token,
     throttle::{ConfigThrottle, ParseTrottleKey},
-    Dsn, QueueConfig, QueueOutboundSourceIp, QueueOutboundTimeout, QueueOutboundTls, QueueQuota,
-    QueueQuotas, QueueThrottle, RequireOptional, THROTTLE_LOCAL_IP, THROTTLE_MX, THROTTLE_RCPT,
-    THROTTLE_RCPT_DOMAIN, THROTTLE_REMOTE_IP, THROTTLE_SENDER, THROTTLE_SENDER_DOMAIN,
+    Downgrade8BitMime, Dsn, QueueConfig, QueueOutboundSourceIp, QueueOutboundTimeout,
+    QueueOutboundTls, QueueQuota, QueueQuotas, QueueThrottle, RequireOptional, THROTTLE_LOCAL_IP,
+    THROTTLE_MX, THROTTLE_RCPT, THROTTLE_RCPT_DOMAIN, THROTTLE_REMOTE_IP, THROTTLE_SENDER,
+    THROTTLE_SENDER_DOMAIN,
 };
 use utils::{
     config::{
@@ -71,7 +72,7 @@ impl ConfigQueue for Config {
             V_MX,
         ];
 
-        let default_hostname = self.value_require("server.hostname")?;
+        let default_hostname = self.server_hostname()?;
 
         let config = QueueConfig {
             retry: self
@@ -148,6 +149,11 @@ impl ConfigQueue for Config {
                     })?
                     .unwrap_or_else(|| IfBlock::new(false)),
             },
+            downgrade_8bitmime: self
+                .parse_if_block("queue.outbound.8bitmime.on-downgrade", |name| {
+                    map_expr_token::<Downgrade8BitMime>(name, mx_envelope_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(Downgrade8BitMime::Convert)),
             throttle: self.parse_queue_throttle()?,
             quota: self.parse_queue_quota()?,
             timeout: QueueOutboundTimeout {
@@ -208,6 +214,11 @@ impl ConfigQueue for Config {
                         map_expr_token::<NoConstants>(name, sender_envelope_keys)
                     })?
                     .unwrap_or_default(),
+                max_size: self
+                    .parse_if_block("report.dsn.max-size", |name| {
+                        map_expr_token::<NoConstants>(name, sender_envelope_keys)
+                    })?
+                    .unwrap_or_else(|| IfBlock::new(10 * 1024 * 1024)),
             },
         };
 
@@ -394,3 +405,40 @@ impl From<RequireOptional> for Constant {
 }
 
 impl ConstantValue for RequireOptional {}
+
+impl ParseValue for Downgrade8BitMime {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "convert" => Ok(Downgrade8BitMime::Convert),
+            "reject" => Ok(Downgrade8BitMime::Reject),
+            _ => Err(format!(
+                "Invalid 8BITMIME downgrade option value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl<'x> TryFrom<Variable<'x>> for Downgrade8BitMime {
+    type Error = ();
+
+    fn try_from(value: Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            utils::expr::Variable::Integer(0) => Ok(Downgrade8BitMime::Convert),
+            utils::expr::Variable::Integer(1) => Ok(Downgrade8BitMime::Reject),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<Downgrade8BitMime> for Constant {
+    fn from(value: Downgrade8BitMime) -> Self {
+        Constant::Integer(match value {
+            Downgrade8BitMime::Convert => 0,
+            Downgrade8BitMime::Reject => 1,
+        })
+    }
+}
+
+impl ConstantValue for Downgrade8BitMime {}
@@ -79,7 +79,7 @@ impl ConfigReport for Config {
             addresses.push(address?.1);
         }
 
-        let default_hostname = self.value_require("server.hostname")?;
+        let default_hostname = self.server_hostname()?;
         Ok(ReportConfig {
             dkim: self.parse_report("dkim", default_hostname, sender_envelope_keys)?,
             spf: self.parse_report("spf", default_hostname, sender_envelope_keys)?,
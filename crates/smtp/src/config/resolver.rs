@@ -41,12 +41,56 @@ use utils::{config::Config, suffixlist::PublicSuffix};
 
 pub trait ConfigResolver {
     fn build_resolvers(&self) -> super::Result<Resolvers>;
+    fn parse_resolver_config(&self) -> super::Result<(ResolverConfig, ResolverOpts)>;
     fn parse_public_suffix(&self) -> super::Result<PublicSuffix>;
 }
 
 impl ConfigResolver for Config {
     fn build_resolvers(&self) -> super::Result<Resolvers> {
-        let (config, mut opts) = match self.value_require("resolver.type")? {
+        let (config, opts) = self.parse_resolver_config()?;
+
+        // Prepare DNSSEC resolver options
+        let config_dnssec = config.clone();
+        let mut opts_dnssec = opts.clone();
+        opts_dnssec.validate = true;
+
+        let mut capacities = [1024usize; 5];
+        for (pos, key) in ["txt", "mx", "ipv4", "ipv6", "ptr"].into_iter().enumerate() {
+            if let Some(capacity) = self.property(("cache.resolver", key))? {
+                capacities[pos] = capacity;
+            }
+        }
+
+        Ok(Resolvers {
+            dns: Resolver::with_capacities(
+                config,
+                opts,
+                capacities[0],
+                capacities[1],
+                capacities[2],
+                capacities[3],
+                capacities[4],
+            )
+            .map_err(|err| format!("Failed to build DNS resolver: {err}"))?,
+            dnssec: DnssecResolver::with_capacity(config_dnssec, opts_dnssec)
+                .map_err(|err| format!("Failed to build DNSSEC resolver: {err}"))?,
+            cache: crate::core::DnsCache {
+                tlsa: LruCache::with_capacity(
+                    self.property("cache.resolver.tlsa.size")?.unwrap_or(1024),
+                ),
+                mta_sts: LruCache::with_capacity(
+                    self.property("cache.resolver.mta-sts.size")?
+                        .unwrap_or(1024),
+                ),
+                dnsbl: LruCache::with_capacity(
+                    self.property("cache.resolver.dnsbl.size")?.unwrap_or(1024),
+                ),
+            },
+        })
+    }
+
+    fn parse_resolver_config(&self) -> super::Result<(ResolverConfig, ResolverOpts)> {
+        let (config, mut opts) = match self.value("resolver.type").unwrap_or("system") {
             "cloudflare" => (ResolverConfig::cloudflare(), ResolverOpts::default()),
             "cloudflare-tls" => (ResolverConfig::cloudflare_tls(), ResolverOpts::default()),
             "quad9" => (ResolverConfig::quad9(), ResolverOpts::default()),
@@ -111,42 +155,11 @@ impl ConfigResolver for Config {
         if let Some(attempts) = self.property("resolver.attempts")? {
             opts.attempts = attempts;
         }
-
-        // Prepare DNSSEC resolver options
-        let config_dnssec = config.clone();
-        let mut opts_dnssec = opts.clone();
-        opts_dnssec.validate = true;
-
-        let mut capacities = [1024usize; 5];
-        for (pos, key) in ["txt", "mx", "ipv4", "ipv6", "ptr"].into_iter().enumerate() {
-            if let Some(capacity) = self.property(("cache.resolver", key))? {
-                capacities[pos] = capacity;
-            }
+        if let Some(edns) = self.property("resolver.edns")? {
+            opts.edns0 = edns;
         }
 
-        Ok(Resolvers {
-            dns: Resolver::with_capacities(
-                config,
-                opts,
-                capacities[0],
-                capacities[1],
-                capacities[2],
-                capacities[3],
-                capacities[4],
-            )
-            .map_err(|err| format!("Failed to build DNS resolver: {err}"))?,
-            dnssec: DnssecResolver::with_capacity(config_dnssec, opts_dnssec)
-                .map_err(|err| format!("Failed to build DNSSEC resolver: {err}"))?,
-            cache: crate::core::DnsCache {
-                tlsa: LruCache::with_capacity(
-                    self.property("cache.resolver.tlsa.size")?.unwrap_or(1024),
-                ),
-                mta_sts: LruCache::with_capacity(
-                    self.property("cache.resolver.mta-sts.size")?
-                        .unwrap_or(1024),
-                ),
-            },
-        })
+        Ok((config, opts))
     }
 
     fn parse_public_suffix(&self) -> super::Result<PublicSuffix> {
@@ -143,7 +143,7 @@ impl ConfigSieve for Config {
         let hostname = if let Some(hostname) = self.value("sieve.trusted.hostname") {
             hostname
         } else {
-            self.value_require("server.hostname")?
+            self.server_hostname()?
         };
         runtime.set_local_hostname(hostname.to_string());
 
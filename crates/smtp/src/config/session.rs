@@ -23,30 +23,41 @@
 
 use std::{net::ToSocketAddrs, time::Duration};
 
+use ahash::AHashMap;
+use directory::core::config::{LookupFormat, LookupMap, LookupType};
 use smtp_proto::*;
 
-use crate::inbound::milter;
+use crate::inbound::{auth::SaslMechanisms, milter};
 
+use crate::core::backpressure::{Backpressure, BackpressureSource};
 use crate::core::eval::*;
 
 use super::{
-    map_expr_token, throttle::ConfigThrottle, Auth, Connect, Data, Ehlo, Extensions, Mail, Milter,
-    Pipe, Rcpt, SessionConfig, SessionThrottle, THROTTLE_AUTH_AS, THROTTLE_HELO_DOMAIN,
-    THROTTLE_LISTENER, THROTTLE_LOCAL_IP, THROTTLE_RCPT, THROTTLE_RCPT_DOMAIN, THROTTLE_REMOTE_IP,
-    THROTTLE_SENDER, THROTTLE_SENDER_DOMAIN,
+    map_expr_token, throttle::ConfigThrottle, AddressLiteral, Auth, BareLf, BareLocalPart,
+    CommandLeniency, Connect, Data, Dnsbl, DnsblAction, DnsblList, DnsblType, DuplicateAction,
+    DuplicateScope, Ehlo, EightBitHeaders, Extensions, Help, Mail, Milter, MissingHeaders,
+    OverQuota, Pipe, Pipelining, Rcpt, RejectMessages, ResponseTemplate, ScoreConfig, ScoreSignal,
+    SessionConfig, SessionThrottle, Srs, Tarpit, TemplateItem, DEFAULT_HELP_MESSAGE,
+    THROTTLE_AUTH_AS, THROTTLE_HELO_DOMAIN, THROTTLE_LISTENER, THROTTLE_LOCAL_IP, THROTTLE_RCPT,
+    THROTTLE_RCPT_DOMAIN, THROTTLE_REMOTE_IP, THROTTLE_SENDER, THROTTLE_SENDER_DOMAIN,
 };
 use utils::{
     config::{
         if_block::IfBlock,
+        ipmask::IpAddrMask,
         utils::{AsKey, ConstantValue, NoConstants, ParseValue},
         Config,
     },
-    expr::{Constant, ExpressionItem, Variable},
+    expr::{Constant, Expression, ExpressionItem, Variable},
+    listener::limiter::ConcurrencyLimiter,
 };
 
 pub trait ConfigSession {
     fn parse_session_config(&self) -> super::Result<SessionConfig>;
     fn parse_session_throttle(&self) -> super::Result<SessionThrottle>;
+    fn parse_session_tarpit(&self) -> super::Result<Tarpit>;
+    fn parse_session_pipelining(&self) -> super::Result<Pipelining>;
+    fn parse_session_help(&self) -> super::Result<Help>;
     fn parse_session_connect(&self) -> super::Result<Connect>;
     fn parse_extensions(&self) -> super::Result<Extensions>;
     fn parse_session_ehlo(&self) -> super::Result<Ehlo>;
@@ -54,13 +65,18 @@ pub trait ConfigSession {
     fn parse_session_mail(&self) -> super::Result<Mail>;
     fn parse_session_rcpt(&self) -> super::Result<Rcpt>;
     fn parse_session_data(&self) -> super::Result<Data>;
+    fn parse_session_reject(&self) -> super::Result<RejectMessages>;
+    fn parse_session_srs(&self) -> super::Result<Option<Srs>>;
+    fn parse_session_dnsbl(&self) -> super::Result<Dnsbl>;
+    fn parse_session_score(&self) -> super::Result<ScoreConfig>;
+    fn parse_trusted_networks(&self) -> super::Result<LookupMap>;
     fn parse_pipes(&self, available_keys: &[u32]) -> super::Result<Vec<Pipe>>;
     fn parse_milters(&self, available_keys: &[u32]) -> super::Result<Vec<Milter>>;
 }
 
 impl ConfigSession for Config {
     fn parse_session_config(&self) -> super::Result<SessionConfig> {
-        let available_keys = &[V_LISTENER, V_REMOTE_IP, V_LOCAL_IP];
+        let available_keys = &[V_LISTENER, V_REMOTE_IP, V_LOCAL_IP, V_IS_TRUSTED];
 
         Ok(SessionConfig {
             duration: self
@@ -73,12 +89,30 @@ impl ConfigSession for Config {
                     map_expr_token::<NoConstants>(name, available_keys)
                 })?
                 .unwrap_or_else(|| IfBlock::new(250 * 1024 * 1024)),
+            max_line_length: self
+                .parse_if_block("session.max-line-length", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(512)),
+            command_leniency: self
+                .parse_if_block("session.command-leniency", |name| {
+                    map_expr_token::<CommandLeniency>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(CommandLeniency::Lenient)),
+            max_noop_commands: self
+                .parse_if_block("session.max-noop-commands", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(20)),
             timeout: self
                 .parse_if_block("session.timeout", |name| {
                     map_expr_token::<Duration>(name, available_keys)
                 })?
                 .unwrap_or_else(|| IfBlock::new(Duration::from_secs(5 * 60))),
             throttle: self.parse_session_throttle()?,
+            tarpit: self.parse_session_tarpit()?,
+            pipelining: self.parse_session_pipelining()?,
+            help: self.parse_session_help()?,
             connect: self.parse_session_connect()?,
             ehlo: self.parse_session_ehlo()?,
             auth: self.parse_session_auth()?,
@@ -86,6 +120,11 @@ impl ConfigSession for Config {
             rcpt: self.parse_session_rcpt()?,
             data: self.parse_session_data()?,
             extensions: self.parse_extensions()?,
+            reject: self.parse_session_reject()?,
+            srs: self.parse_session_srs()?,
+            dnsbl: self.parse_session_dnsbl()?,
+            score: self.parse_session_score()?,
+            trusted_networks: self.parse_trusted_networks()?,
         })
     }
 
@@ -106,6 +145,7 @@ impl ConfigSession for Config {
                 V_AUTHENTICATED_AS,
                 V_LISTENER,
                 V_REMOTE_IP,
+                V_IS_TRUSTED,
                 V_LOCAL_IP,
                 V_PRIORITY,
                 V_HELO_DOMAIN,
@@ -154,8 +194,61 @@ impl ConfigSession for Config {
         Ok(throttle)
     }
 
+    fn parse_session_tarpit(&self) -> super::Result<Tarpit> {
+        let available_keys = &[V_LISTENER, V_REMOTE_IP, V_LOCAL_IP, V_IS_TRUSTED];
+        Ok(Tarpit {
+            delay: self
+                .parse_if_block("session.tarpit.delay", |name| {
+                    map_expr_token::<Duration>(name, available_keys)
+                })?
+                .unwrap_or_default(),
+            multiplier: self
+                .parse_if_block("session.tarpit.multiplier", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(2)),
+            max_delay: self
+                .parse_if_block("session.tarpit.max-delay", |name| {
+                    map_expr_token::<Duration>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(Duration::from_secs(30))),
+        })
+    }
+
+    fn parse_session_pipelining(&self) -> super::Result<Pipelining> {
+        let available_keys = &[V_LISTENER, V_REMOTE_IP, V_LOCAL_IP, V_IS_TRUSTED];
+        Ok(Pipelining {
+            max_commands: self
+                .parse_if_block("session.pipelining.max-commands", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_default(),
+            max_size: self
+                .parse_if_block("session.pipelining.max-size", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_default(),
+        })
+    }
+
+    fn parse_session_help(&self) -> super::Result<Help> {
+        let available_keys = &[V_LISTENER, V_REMOTE_IP, V_LOCAL_IP, V_IS_TRUSTED];
+        Ok(Help {
+            enable: self
+                .parse_if_block("session.help.enable", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(true)),
+            message: self
+                .parse_if_block("session.help.message", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(DEFAULT_HELP_MESSAGE.to_string())),
+        })
+    }
+
     fn parse_session_connect(&self) -> super::Result<Connect> {
-        let available_keys = &[V_LISTENER, V_REMOTE_IP, V_LOCAL_IP];
+        let available_keys = &[V_LISTENER, V_REMOTE_IP, V_LOCAL_IP, V_IS_TRUSTED];
         Ok(Connect {
             script: self
                 .parse_if_block("session.connect.script", |name| {
@@ -169,6 +262,7 @@ impl ConfigSession for Config {
         let available_keys = &[
             V_LISTENER,
             V_REMOTE_IP,
+            V_IS_TRUSTED,
             V_LOCAL_IP,
             V_SENDER,
             V_SENDER_DOMAIN,
@@ -181,6 +275,11 @@ impl ConfigSession for Config {
                     map_expr_token::<NoConstants>(name, available_keys)
                 })?
                 .unwrap_or_else(|| IfBlock::new(true)),
+            starttls_pipelining: self
+                .parse_if_block("session.extensions.starttls-pipelining", |name| {
+                    map_expr_token::<CommandLeniency>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(CommandLeniency::Strict)),
             dsn: self
                 .parse_if_block("session.extensions.dsn", |name| {
                     map_expr_token::<NoConstants>(name, available_keys)
@@ -196,6 +295,15 @@ impl ConfigSession for Config {
                     map_expr_token::<NoConstants>(name, available_keys)
                 })?
                 .unwrap_or_else(|| IfBlock::new(true)),
+            etrn: self
+                .parse_if_block("session.extensions.etrn", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_default(),
+            etrn_domains: self
+                .values("session.extensions.etrn-domains")
+                .map(|(_, domain)| domain.trim().to_lowercase())
+                .collect(),
             chunking: self
                 .parse_if_block("session.extensions.chunking", |name| {
                     map_expr_token::<NoConstants>(name, available_keys)
@@ -226,11 +334,26 @@ impl ConfigSession for Config {
                     map_expr_token::<MtPriority>(name, available_keys)
                 })?
                 .unwrap_or_default(),
+            enhanced_status_codes: self
+                .parse_if_block("session.extensions.enhanced-status-codes", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(true)),
+            size: self
+                .parse_if_block("session.extensions.size", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(true)),
+            size_value: self
+                .parse_if_block("session.extensions.size-value", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_default(),
         })
     }
 
     fn parse_session_ehlo(&self) -> super::Result<Ehlo> {
-        let available_keys = &[V_LISTENER, V_REMOTE_IP, V_LOCAL_IP];
+        let available_keys = &[V_LISTENER, V_REMOTE_IP, V_LOCAL_IP, V_IS_TRUSTED];
 
         Ok(Ehlo {
             script: self
@@ -248,11 +371,22 @@ impl ConfigSession for Config {
                     map_expr_token::<NoConstants>(name, available_keys)
                 })?
                 .unwrap_or_else(|| IfBlock::new(true)),
+            helo_extensions: self
+                .parse_if_block("session.ehlo.helo-extensions", |name| {
+                    map_expr_token::<CommandLeniency>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(CommandLeniency::Strict)),
         })
     }
 
     fn parse_session_auth(&self) -> super::Result<Auth> {
-        let available_keys = &[V_LISTENER, V_REMOTE_IP, V_LOCAL_IP, V_HELO_DOMAIN];
+        let available_keys = &[
+            V_LISTENER,
+            V_REMOTE_IP,
+            V_LOCAL_IP,
+            V_IS_TRUSTED,
+            V_HELO_DOMAIN,
+        ];
 
         Ok(Auth {
             directory: self
@@ -265,6 +399,11 @@ impl ConfigSession for Config {
                     map_expr_token::<Mechanism>(name, available_keys)
                 })?
                 .unwrap_or_default(),
+            custom_mechanisms: self
+                .parse_if_block("session.auth.custom-mechanisms", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_default(),
             require: self
                 .parse_if_block("session.auth.require", |name| {
                     map_expr_token::<NoConstants>(name, available_keys)
@@ -280,6 +419,17 @@ impl ConfigSession for Config {
                     map_expr_token::<Duration>(name, available_keys)
                 })?
                 .unwrap_or_else(|| IfBlock::new(Duration::from_secs(30))),
+            max_attempts: self
+                .parse_if_block("session.auth.max-attempts", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(3)),
+            max_response_size: self
+                .parse_if_block("session.auth.max-response-size", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(64 * 1024)),
+            sasl: SaslMechanisms::default(),
             allow_plain_text: self
                 .parse_if_block("session.auth.allow-plain-text", |name| {
                     map_expr_token::<NoConstants>(name, available_keys)
@@ -290,6 +440,38 @@ impl ConfigSession for Config {
                     map_expr_token::<NoConstants>(name, available_keys)
                 })?
                 .unwrap_or_else(|| IfBlock::new(true)),
+            send_as: self
+                .parse_if_block("session.auth.send-as", |name| {
+                    map_expr_token::<NoConstants>(
+                        name,
+                        &[
+                            V_LISTENER,
+                            V_REMOTE_IP,
+                            V_IS_TRUSTED,
+                            V_LOCAL_IP,
+                            V_HELO_DOMAIN,
+                            V_AUTHENTICATED_AS,
+                            V_SENDER,
+                            V_SENDER_DOMAIN,
+                        ],
+                    )
+                })?
+                .unwrap_or_else(|| IfBlock::new(false)),
+            send_rate: self
+                .parse_if_block("session.auth.send-rate", |name| {
+                    map_expr_token::<Duration>(
+                        name,
+                        &[
+                            V_LISTENER,
+                            V_REMOTE_IP,
+                            V_IS_TRUSTED,
+                            V_LOCAL_IP,
+                            V_HELO_DOMAIN,
+                            V_AUTHENTICATED_AS,
+                        ],
+                    )
+                })?
+                .unwrap_or_default(),
         })
     }
 
@@ -298,6 +480,7 @@ impl ConfigSession for Config {
             V_AUTHENTICATED_AS,
             V_LISTENER,
             V_REMOTE_IP,
+            V_IS_TRUSTED,
             V_LOCAL_IP,
             V_HELO_DOMAIN,
             V_SENDER,
@@ -314,6 +497,29 @@ impl ConfigSession for Config {
                     map_expr_token::<NoConstants>(name, available_keys)
                 })?
                 .unwrap_or_default(),
+            verify_sender_domain: self
+                .parse_if_block("session.mail.verify-sender-domain", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(false)),
+            address_literals: self
+                .parse_if_block("session.mail.address-literals", |name| {
+                    map_expr_token::<AddressLiteral>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(AddressLiteral::Reject)),
+            multiple_commands: self
+                .parse_if_block("session.mail.multiple-commands", |name| {
+                    map_expr_token::<CommandLeniency>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(CommandLeniency::Strict)),
+            timeout: self
+                .parse_if_block("session.mail.timeout", |name| {
+                    map_expr_token::<Duration>(
+                        name,
+                        &[V_LISTENER, V_REMOTE_IP, V_LOCAL_IP, V_IS_TRUSTED],
+                    )
+                })?
+                .unwrap_or_else(|| IfBlock::new(Duration::from_secs(5 * 60))),
         })
     }
 
@@ -324,6 +530,7 @@ impl ConfigSession for Config {
             V_AUTHENTICATED_AS,
             V_LISTENER,
             V_REMOTE_IP,
+            V_IS_TRUSTED,
             V_LOCAL_IP,
             V_HELO_DOMAIN,
         ];
@@ -335,6 +542,7 @@ impl ConfigSession for Config {
             V_AUTHENTICATED_AS,
             V_LISTENER,
             V_REMOTE_IP,
+            V_IS_TRUSTED,
             V_LOCAL_IP,
             V_HELO_DOMAIN,
         ];
@@ -354,6 +562,11 @@ impl ConfigSession for Config {
                     map_expr_token::<NoConstants>(name, available_keys_full)
                 })?
                 .unwrap_or_default(),
+            timeout: self
+                .parse_if_block("session.rcpt.timeout", |name| {
+                    map_expr_token::<Duration>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(Duration::from_secs(5 * 60))),
             errors_max: self
                 .parse_if_block("session.rcpt.errors.max", |name| {
                     map_expr_token::<NoConstants>(name, available_keys)
@@ -369,11 +582,41 @@ impl ConfigSession for Config {
                     map_expr_token::<NoConstants>(name, available_keys)
                 })?
                 .unwrap_or_else(|| IfBlock::new(100)),
+            max_recipients_null_sender: self
+                .parse_if_block("session.rcpt.null-sender.max-recipients", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(1)),
+            over_quota: self
+                .parse_if_block("session.rcpt.over-quota", |name| {
+                    map_expr_token::<OverQuota>(name, available_keys_full)
+                })?
+                .unwrap_or_else(|| IfBlock::new(OverQuota::Reject)),
             rewrite: self
                 .parse_if_block("session.rcpt.rewrite", |name| {
                     map_expr_token::<NoConstants>(name, available_keys_full)
                 })?
                 .unwrap_or_default(),
+            postmaster: self
+                .parse_if_block("session.rcpt.postmaster", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys_full)
+                })?
+                .unwrap_or_default(),
+            bare_local_part: self
+                .parse_if_block("session.rcpt.bare-localpart", |name| {
+                    map_expr_token::<BareLocalPart>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(BareLocalPart::Reject)),
+            address_literals: self
+                .parse_if_block("session.rcpt.address-literals", |name| {
+                    map_expr_token::<AddressLiteral>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(AddressLiteral::Reject)),
+            default_domain: self
+                .parse_if_block("session.rcpt.default-domain", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_default(),
         })
     }
 
@@ -384,10 +627,36 @@ impl ConfigSession for Config {
             V_AUTHENTICATED_AS,
             V_LISTENER,
             V_REMOTE_IP,
+            V_IS_TRUSTED,
             V_LOCAL_IP,
             V_PRIORITY,
             V_HELO_DOMAIN,
         ];
+
+        // Per recipient domain size limits, one "domain size" entry per line
+        let max_message_size_domain = LookupMap::parse(
+            &self
+                .values("session.data.limits.size-domain")
+                .map(|(_, value)| value)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            &LookupFormat {
+                lookup_type: LookupType::Map,
+                ..Default::default()
+            },
+        );
+        for domain in max_message_size_domain.keys() {
+            if max_message_size_domain
+                .lookup(domain)
+                .and_then(|size| size.parse::<usize>().ok())
+                .is_none()
+            {
+                return Err(format!(
+                    "Invalid message size for domain {domain:?} in key \"session.data.limits.size-domain\"."
+                ));
+            }
+        }
+
         Ok(Data {
             script: self
                 .parse_if_block("session.data.script", |name| {
@@ -404,11 +673,61 @@ impl ConfigSession for Config {
                     map_expr_token::<NoConstants>(name, available_keys)
                 })?
                 .unwrap_or_else(|| IfBlock::new(25 * 1024 * 1024)),
+            max_message_size_domain,
             max_received_headers: self
                 .parse_if_block("session.data.limits.received-headers", |name| {
                     map_expr_token::<NoConstants>(name, available_keys)
                 })?
-                .unwrap_or_else(|| IfBlock::new(50)),
+                .unwrap_or_else(|| IfBlock::new(30)),
+            max_line_length: self
+                .parse_if_block("session.data.limits.line-length", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(1000)),
+            max_concurrent: self
+                .property::<u64>("session.data.limits.concurrent")?
+                .filter(|max| *max > 0)
+                .map(ConcurrencyLimiter::new),
+            timeout_init: self
+                .parse_if_block("session.data.timeout-init", |name| {
+                    map_expr_token::<Duration>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(Duration::from_secs(10 * 60))),
+            timeout: self
+                .parse_if_block("session.data.timeout", |name| {
+                    map_expr_token::<Duration>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(Duration::from_secs(10 * 60))),
+            backpressure: self
+                .property::<BackpressureSource>("session.data.backpressure.source")?
+                .map(|source| {
+                    Ok::<_, String>(Backpressure::new(
+                        source,
+                        self.property_or_default("session.data.backpressure.threshold", "1s")?,
+                        self.property_or_default("session.data.backpressure.window", "30s")?,
+                    ))
+                })
+                .transpose()?,
+            bare_lf: self
+                .parse_if_block("session.data.bare-lf", |name| {
+                    map_expr_token::<BareLf>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(BareLf::Convert)),
+            duplicate_window: self
+                .parse_if_block("session.data.duplicate.window", |name| {
+                    map_expr_token::<Duration>(name, available_keys)
+                })?
+                .unwrap_or_default(),
+            duplicate_scope: self
+                .parse_if_block("session.data.duplicate.scope", |name| {
+                    map_expr_token::<DuplicateScope>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(DuplicateScope::Recipient)),
+            duplicate_action: self
+                .parse_if_block("session.data.duplicate.action", |name| {
+                    map_expr_token::<DuplicateAction>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(DuplicateAction::Discard)),
             add_received: self
                 .parse_if_block("session.data.add-headers.received", |name| {
                     map_expr_token::<NoConstants>(name, available_keys)
@@ -439,6 +758,51 @@ impl ConfigSession for Config {
                     map_expr_token::<NoConstants>(name, available_keys)
                 })?
                 .unwrap_or_else(|| IfBlock::new(true)),
+            missing_headers: self
+                .parse_if_block("session.data.missing-headers", |name| {
+                    map_expr_token::<MissingHeaders>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(MissingHeaders::Allow)),
+            strip_bcc: match self.parse_if_block("session.data.strip-bcc", |name| {
+                map_expr_token::<NoConstants>(name, available_keys)
+            })? {
+                Some(strip_bcc) => strip_bcc,
+                // Only messages submitted by authenticated users are stripped by default
+                None => IfBlock {
+                    key: "session.data.strip-bcc".to_string(),
+                    if_then: vec![],
+                    default: Expression::parse(
+                        "session.data.strip-bcc",
+                        "!is_empty(authenticated_as)",
+                        |name| map_expr_token::<NoConstants>(name, available_keys),
+                    )?,
+                },
+            },
+            eight_bit_headers: self
+                .parse_if_block("session.data.8bit-headers", |name| {
+                    map_expr_token::<EightBitHeaders>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(EightBitHeaders::Allow)),
+            received_ip: self
+                .parse_if_block("session.data.received.include-ip", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(true)),
+            received_tls: self
+                .parse_if_block("session.data.received.include-tls", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(true)),
+            received_protocol: self
+                .parse_if_block("session.data.received.include-protocol", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(true)),
+            proxy_directory: self
+                .parse_if_block("session.data.proxy.directory", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_default(),
             pipe_commands: self.parse_pipes(available_keys)?,
             milters: self.parse_milters(available_keys)?,
         })
@@ -468,6 +832,120 @@ impl ConfigSession for Config {
         Ok(pipes)
     }
 
+    fn parse_session_reject(&self) -> super::Result<RejectMessages> {
+        Ok(RejectMessages {
+            sender_not_allowed: self.property("session.reject.sender-not-allowed")?,
+            reverse_dns: self.property("session.reject.reverse-dns")?,
+            unknown_recipient: self.property("session.reject.unknown-recipient")?,
+            relay_denied: self.property("session.reject.relay-denied")?,
+            unverified_recipient: self.property("session.reject.unverified-recipient")?,
+            dkim: self.property("session.reject.dkim")?,
+            arc: self.property("session.reject.arc")?,
+            dmarc: self.property("session.reject.dmarc")?,
+            deferral: self.property("session.reject.deferral")?,
+            retry_after: self.property("session.reject.retry-after")?,
+        })
+    }
+
+    fn parse_session_srs(&self) -> super::Result<Option<Srs>> {
+        if let Some(domain) = self.value("session.srs.domain") {
+            Ok(Some(Srs {
+                domain: domain.to_lowercase(),
+                secret: self.value_require("session.srs.secret")?.to_string(),
+                max_age: self.property_or_default("session.srs.max-age", "21d")?,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_session_dnsbl(&self) -> super::Result<Dnsbl> {
+        let mut lists = Vec::new();
+        for id in self.sub_keys("session.dnsbl.list", ".zone") {
+            let action = match self
+                .value(("session.dnsbl.list", id, "action"))
+                .unwrap_or("reject")
+            {
+                "reject" => DnsblAction::Reject,
+                "score" => DnsblAction::Score(
+                    self.property_or_default(("session.dnsbl.list", id, "score"), "1")?,
+                ),
+                action => {
+                    return Err(format!(
+                        "Invalid value {action:?} for key \"session.dnsbl.list.{id}.action\"."
+                    ))
+                }
+            };
+
+            lists.push(DnsblList {
+                id: id.to_string(),
+                zone: self
+                    .value_require(("session.dnsbl.list", id, "zone"))?
+                    .trim_end_matches('.')
+                    .to_lowercase(),
+                typ: self.property_or_default(("session.dnsbl.list", id, "type"), "ip")?,
+                action,
+            });
+        }
+
+        Ok(Dnsbl {
+            lists,
+            max_score: self.property("session.dnsbl.max-score")?,
+            negative_ttl: self.property_or_default("session.dnsbl.negative-ttl", "5m")?,
+        })
+    }
+
+    fn parse_session_score(&self) -> super::Result<ScoreConfig> {
+        let mut weights = AHashMap::new();
+        for (signal, name) in [
+            (ScoreSignal::Spf, "spf"),
+            (ScoreSignal::Dkim, "dkim"),
+            (ScoreSignal::Dmarc, "dmarc"),
+            (ScoreSignal::Iprev, "iprev"),
+        ] {
+            for result in [
+                "pass",
+                "fail",
+                "softfail",
+                "neutral",
+                "none",
+                "temperror",
+                "permerror",
+            ] {
+                if let Some(weight) = self.property::<f64>(("session.score", name, result))? {
+                    weights.insert((signal, result), weight);
+                }
+            }
+        }
+
+        Ok(ScoreConfig {
+            weights,
+            dnsbl: self.property("session.score.dnsbl")?.unwrap_or(0.0),
+            reject: self.property("session.score.reject")?,
+            spam: self.property("session.score.spam")?,
+        })
+    }
+
+    fn parse_trusted_networks(&self) -> super::Result<LookupMap> {
+        // Validate every network before building the lookup, which skips
+        // entries that are not valid CIDR masks
+        for (key, network) in self.values("server.trusted-networks") {
+            IpAddrMask::parse_value(key, network)?;
+        }
+
+        Ok(LookupMap::parse(
+            &self
+                .values("server.trusted-networks")
+                .map(|(_, value)| value)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            &LookupFormat {
+                lookup_type: LookupType::Cidr,
+                ..Default::default()
+            },
+        ))
+    }
+
     fn parse_milters(&self, available_keys: &[u32]) -> super::Result<Vec<Milter>> {
         let mut milters = Vec::new();
         for id in self.sub_keys("session.data.milter", "") {
@@ -530,9 +1008,427 @@ impl ConfigSession for Config {
     }
 }
 
+impl ParseValue for ResponseTemplate {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        // Line breaks would allow injecting additional responses
+        if value.chars().any(|ch| ch.is_control()) {
+            return Err(format!(
+                "Response template for property {:?} must not contain line breaks or control characters.",
+                key.as_key()
+            ));
+        }
+
+        let mut items = Vec::new();
+        let mut value = value;
+        while let Some((prefix, rest)) = value.split_once('{') {
+            let (name, rest) = match rest.split_once('}') {
+                Some(name_rest) => name_rest,
+                None => break,
+            };
+            let item = match name {
+                "sender" => TemplateItem::Sender,
+                "recipient" => TemplateItem::Recipient,
+                "reason" => TemplateItem::Reason,
+                _ => {
+                    return Err(format!(
+                        "Unknown placeholder {{{name}}} in response template for property {:?}.",
+                        key.as_key()
+                    ))
+                }
+            };
+            if !prefix.is_empty() {
+                items.push(TemplateItem::Text(prefix.to_string()));
+            }
+            items.push(item);
+            value = rest;
+        }
+        if !value.is_empty() {
+            items.push(TemplateItem::Text(value.to_string()));
+        }
+
+        Ok(ResponseTemplate { items })
+    }
+}
+
 #[derive(Default)]
 pub struct Mechanism(u64);
 
+impl ParseValue for CommandLeniency {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "strict" => Ok(CommandLeniency::Strict),
+            "lenient" => Ok(CommandLeniency::Lenient),
+            _ => Err(format!(
+                "Invalid value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl<'x> TryFrom<Variable<'x>> for CommandLeniency {
+    type Error = ();
+
+    fn try_from(value: Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            Variable::Integer(0) => Ok(CommandLeniency::Strict),
+            Variable::Integer(1) => Ok(CommandLeniency::Lenient),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<CommandLeniency> for Constant {
+    fn from(value: CommandLeniency) -> Self {
+        Constant::Integer(match value {
+            CommandLeniency::Strict => 0,
+            CommandLeniency::Lenient => 1,
+        })
+    }
+}
+
+impl ConstantValue for CommandLeniency {}
+
+impl ParseValue for OverQuota {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "reject" => Ok(OverQuota::Reject),
+            "defer" => Ok(OverQuota::Defer),
+            _ => Err(format!(
+                "Invalid value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl<'x> TryFrom<Variable<'x>> for OverQuota {
+    type Error = ();
+
+    fn try_from(value: Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            Variable::Integer(0) => Ok(OverQuota::Reject),
+            Variable::Integer(1) => Ok(OverQuota::Defer),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<OverQuota> for Constant {
+    fn from(value: OverQuota) -> Self {
+        Constant::Integer(match value {
+            OverQuota::Reject => 0,
+            OverQuota::Defer => 1,
+        })
+    }
+}
+
+impl ConstantValue for OverQuota {}
+
+impl ParseValue for BareLocalPart {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "reject" => Ok(BareLocalPart::Reject),
+            "append-default-domain" => Ok(BareLocalPart::AppendDefaultDomain),
+            _ => Err(format!(
+                "Invalid value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl<'x> TryFrom<Variable<'x>> for BareLocalPart {
+    type Error = ();
+
+    fn try_from(value: Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            Variable::Integer(0) => Ok(BareLocalPart::Reject),
+            Variable::Integer(1) => Ok(BareLocalPart::AppendDefaultDomain),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<BareLocalPart> for Constant {
+    fn from(value: BareLocalPart) -> Self {
+        Constant::Integer(match value {
+            BareLocalPart::Reject => 0,
+            BareLocalPart::AppendDefaultDomain => 1,
+        })
+    }
+}
+
+impl ConstantValue for BareLocalPart {}
+
+impl ParseValue for DnsblType {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "ip" => Ok(DnsblType::Ip),
+            "domain" => Ok(DnsblType::Domain),
+            _ => Err(format!(
+                "Invalid value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl ParseValue for AddressLiteral {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "reject" => Ok(AddressLiteral::Reject),
+            "allow" => Ok(AddressLiteral::Allow),
+            _ => Err(format!(
+                "Invalid value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl<'x> TryFrom<Variable<'x>> for AddressLiteral {
+    type Error = ();
+
+    fn try_from(value: Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            Variable::Integer(0) => Ok(AddressLiteral::Reject),
+            Variable::Integer(1) => Ok(AddressLiteral::Allow),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<AddressLiteral> for Constant {
+    fn from(value: AddressLiteral) -> Self {
+        Constant::Integer(match value {
+            AddressLiteral::Reject => 0,
+            AddressLiteral::Allow => 1,
+        })
+    }
+}
+
+impl ConstantValue for AddressLiteral {}
+
+impl ParseValue for DuplicateScope {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "global" => Ok(DuplicateScope::Global),
+            "recipient" => Ok(DuplicateScope::Recipient),
+            _ => Err(format!(
+                "Invalid value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl<'x> TryFrom<Variable<'x>> for DuplicateScope {
+    type Error = ();
+
+    fn try_from(value: Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            Variable::Integer(0) => Ok(DuplicateScope::Global),
+            Variable::Integer(1) => Ok(DuplicateScope::Recipient),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<DuplicateScope> for Constant {
+    fn from(value: DuplicateScope) -> Self {
+        Constant::Integer(match value {
+            DuplicateScope::Global => 0,
+            DuplicateScope::Recipient => 1,
+        })
+    }
+}
+
+impl ConstantValue for DuplicateScope {}
+
+impl ParseValue for DuplicateAction {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "discard" => Ok(DuplicateAction::Discard),
+            "reject" => Ok(DuplicateAction::Reject),
+            _ => Err(format!(
+                "Invalid value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl<'x> TryFrom<Variable<'x>> for DuplicateAction {
+    type Error = ();
+
+    fn try_from(value: Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            Variable::Integer(0) => Ok(DuplicateAction::Discard),
+            Variable::Integer(1) => Ok(DuplicateAction::Reject),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<DuplicateAction> for Constant {
+    fn from(value: DuplicateAction) -> Self {
+        Constant::Integer(match value {
+            DuplicateAction::Discard => 0,
+            DuplicateAction::Reject => 1,
+        })
+    }
+}
+
+impl ConstantValue for DuplicateAction {}
+
+impl ParseValue for BackpressureSource {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "data-store" => Ok(BackpressureSource::DataStore),
+            "blob-store" => Ok(BackpressureSource::BlobStore),
+            _ => Err(format!(
+                "Invalid value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl ParseValue for MissingHeaders {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "allow" => Ok(MissingHeaders::Allow),
+            "flag" => Ok(MissingHeaders::Flag),
+            "reject" => Ok(MissingHeaders::Reject),
+            _ => Err(format!(
+                "Invalid value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl<'x> TryFrom<Variable<'x>> for MissingHeaders {
+    type Error = ();
+
+    fn try_from(value: Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            Variable::Integer(0) => Ok(MissingHeaders::Allow),
+            Variable::Integer(1) => Ok(MissingHeaders::Flag),
+            Variable::Integer(2) => Ok(MissingHeaders::Reject),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<MissingHeaders> for Constant {
+    fn from(value: MissingHeaders) -> Self {
+        Constant::Integer(match value {
+            MissingHeaders::Allow => 0,
+            MissingHeaders::Flag => 1,
+            MissingHeaders::Reject => 2,
+        })
+    }
+}
+
+impl ConstantValue for MissingHeaders {}
+
+impl ParseValue for EightBitHeaders {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "allow" => Ok(EightBitHeaders::Allow),
+            "flag" => Ok(EightBitHeaders::Flag),
+            "encode" => Ok(EightBitHeaders::Encode),
+            "reject" => Ok(EightBitHeaders::Reject),
+            _ => Err(format!(
+                "Invalid value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl<'x> TryFrom<Variable<'x>> for EightBitHeaders {
+    type Error = ();
+
+    fn try_from(value: Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            Variable::Integer(0) => Ok(EightBitHeaders::Allow),
+            Variable::Integer(1) => Ok(EightBitHeaders::Flag),
+            Variable::Integer(2) => Ok(EightBitHeaders::Encode),
+            Variable::Integer(3) => Ok(EightBitHeaders::Reject),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<EightBitHeaders> for Constant {
+    fn from(value: EightBitHeaders) -> Self {
+        Constant::Integer(match value {
+            EightBitHeaders::Allow => 0,
+            EightBitHeaders::Flag => 1,
+            EightBitHeaders::Encode => 2,
+            EightBitHeaders::Reject => 3,
+        })
+    }
+}
+
+impl ConstantValue for EightBitHeaders {}
+
+impl ParseValue for BareLf {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "reject" => Ok(BareLf::Reject),
+            "convert" => Ok(BareLf::Convert),
+            "allow" => Ok(BareLf::Allow),
+            _ => Err(format!(
+                "Invalid value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl<'x> TryFrom<Variable<'x>> for BareLf {
+    type Error = ();
+
+    fn try_from(value: Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            Variable::Integer(c) => match c {
+                2 => Ok(BareLf::Reject),
+                3 => Ok(BareLf::Convert),
+                4 => Ok(BareLf::Allow),
+                _ => Err(()),
+            },
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<BareLf> for Constant {
+    fn from(value: BareLf) -> Self {
+        Constant::Integer(match value {
+            BareLf::Reject => 2,
+            BareLf::Convert => 3,
+            BareLf::Allow => 4,
+        })
+    }
+}
+
+impl ConstantValue for BareLf {}
+
 impl ParseValue for Mechanism {
     fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
         Ok(Mechanism(match value.to_ascii_uppercase().as_str() {
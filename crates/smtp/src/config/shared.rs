@@ -23,7 +23,7 @@
 
 use ahash::AHashMap;
 use mail_send::Credentials;
-use utils::config::Config;
+use utils::{config::Config, listener::limiter::ConcurrencyLimiter};
 
 use crate::core::Shared;
 
@@ -45,6 +45,7 @@ impl ConfigShared for Config {
         Ok(Shared {
             scripts: ctx.scripts.clone(),
             signers: ctx.signers.clone(),
+            domain_signers: ctx.domain_signers.clone(),
             sealers: ctx.sealers.clone(),
             directories: ctx.directory.directories.clone(),
             lookup_stores: ctx.stores.lookup_stores.clone(),
@@ -104,6 +105,10 @@ impl ConfigShared for Config {
             tls_allow_invalid_certs: self
                 .property(("remote", id, "tls.allow-invalid-certs"))?
                 .unwrap_or(false),
+            concurrency: self
+                .property::<u64>(("remote", id, "limits.concurrency"))?
+                .filter(|max_concurrent| *max_concurrent > 0)
+                .map(ConcurrencyLimiter::new),
         })
     }
 }
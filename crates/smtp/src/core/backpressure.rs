@@ -0,0 +1,103 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use super::SMTP;
+
+// Weight of each new sample in the moving average is 1/SMOOTHING
+const SMOOTHING: u64 = 8;
+
+#[derive(Debug)]
+pub struct Backpressure {
+    pub source: BackpressureSource,
+    pub threshold: Duration,
+    pub window: Duration,
+    epoch: Instant,
+    latency: AtomicU64,
+    sampled_at: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackpressureSource {
+    DataStore,
+    BlobStore,
+}
+
+impl Backpressure {
+    pub fn new(source: BackpressureSource, threshold: Duration, window: Duration) -> Self {
+        Backpressure {
+            source,
+            threshold,
+            window,
+            epoch: Instant::now(),
+            latency: AtomicU64::new(0),
+            sampled_at: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, source: BackpressureSource, latency: Duration) {
+        if source == self.source {
+            let sample = latency.as_micros() as u64;
+            let is_stale = self.is_stale();
+
+            // Track an exponentially weighted moving average so that a single
+            // slow or fast write does not toggle backpressure on its own
+            let _ = self
+                .latency
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| {
+                    let average = if is_stale { 0 } else { average };
+                    Some(average - average / SMOOTHING + sample / SMOOTHING)
+                });
+
+            // Timestamps are offset by one so that zero means "no sample"
+            self.sampled_at.store(
+                self.epoch.elapsed().as_millis() as u64 + 1,
+                Ordering::Relaxed,
+            );
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        // Without recent samples there is no evidence of backpressure
+        !self.is_stale()
+            && Duration::from_micros(self.latency.load(Ordering::Relaxed)) > self.threshold
+    }
+
+    fn is_stale(&self) -> bool {
+        let sampled_at = self.sampled_at.load(Ordering::Relaxed);
+        let now = self.epoch.elapsed().as_millis() as u64 + 1;
+        sampled_at == 0 || now.saturating_sub(sampled_at) > self.window.as_millis() as u64
+    }
+}
+
+impl SMTP {
+    pub fn record_latency(&self, source: BackpressureSource, started: Instant) {
+        if let Some(backpressure) = &self.session.config.data.backpressure {
+            backpressure.record(source, started.elapsed());
+        }
+    }
+}
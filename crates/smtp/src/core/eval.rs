@@ -25,6 +25,7 @@ pub const V_LISTENER: u32 = 7;
 pub const V_REMOTE_IP: u32 = 8;
 pub const V_LOCAL_IP: u32 = 9;
 pub const V_PRIORITY: u32 = 10;
+pub const V_IS_TRUSTED: u32 = 11;
 
 pub const F_IS_LOCAL_DOMAIN: u32 = 0;
 pub const F_IS_LOCAL_ADDRESS: u32 = 1;
@@ -48,6 +49,7 @@ pub const VARIABLES_MAP: &[(&str, u32)] = &[
     ("remote_ip", V_REMOTE_IP),
     ("local_ip", V_LOCAL_IP),
     ("priority", V_PRIORITY),
+    ("is_trusted", V_IS_TRUSTED),
 ];
 
 pub const FUNCTIONS_MAP: &[(&str, u32, u32)] = &[
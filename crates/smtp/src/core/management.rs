@@ -376,7 +376,7 @@ impl SMTP {
                     .iterate(
                         IterateParams::new(from_key, to_key).ascending(),
                         |key, value| {
-                            let message = Bincode::<queue::Message>::deserialize(value)?.inner;
+                            let message = queue::Message::deserialize(value)?;
                             let matches = !has_filters
                                 || (text
                                     .as_ref()
@@ -30,7 +30,7 @@ use std::{
 
 use ahash::AHashMap;
 use dashmap::DashMap;
-use directory::Directory;
+use directory::{Directory, FEATURES_ALL, FEATURE_SEND_EXTERNAL};
 use mail_auth::{common::lru::LruCache, IprevOutput, Resolver, SpfOutput};
 use sieve::{runtime::Variable, Runtime, Sieve};
 use smtp_proto::{
@@ -48,6 +48,7 @@ use tokio::{
 use tokio_rustls::TlsConnector;
 use tracing::Span;
 use utils::{
+    config::Rate,
     expr,
     ipc::DeliveryEvent,
     listener::{
@@ -60,8 +61,9 @@ use utils::{
 
 use crate::{
     config::{
-        scripts::SieveContext, ArcSealer, DkimSigner, MailAuthConfig, QueueConfig, RelayHost,
-        ReportConfig, SessionConfig, VerifyStrategy,
+        scripts::SieveContext, ArcSealer, BareLf, CommandLeniency, DkimDomainSigners, DkimSigner,
+        MailAuthConfig, QueueConfig, RelayHost, ReportConfig, SessionConfig, SpfCheck,
+        VerifyStrategy,
     },
     inbound::auth::SaslToken,
     outbound::{
@@ -74,9 +76,11 @@ use crate::{
 
 use self::throttle::{ThrottleKey, ThrottleKeyHasherBuilder};
 
+pub mod backpressure;
 pub mod eval;
 pub mod management;
 pub mod params;
+pub mod srs;
 pub mod throttle;
 pub mod worker;
 
@@ -118,6 +122,7 @@ pub struct SMTP {
 pub struct Shared {
     pub scripts: AHashMap<String, Arc<Sieve>>,
     pub signers: AHashMap<String, Arc<DkimSigner>>,
+    pub domain_signers: DkimDomainSigners,
     pub sealers: AHashMap<String, Arc<ArcSealer>>,
     pub directories: AHashMap<String, Arc<Directory>>,
     pub lookup_stores: AHashMap<String, LookupStore>,
@@ -147,6 +152,7 @@ pub struct Resolvers {
 pub struct DnsCache {
     pub tlsa: LruCache<String, Arc<Tlsa>>,
     pub mta_sts: LruCache<String, Arc<mta_sts::Policy>>,
+    pub dnsbl: LruCache<String, ()>,
 }
 
 pub struct SessionCore {
@@ -178,6 +184,7 @@ pub enum State {
     Data(DataReceiver),
     Sasl(LineReceiver<SaslToken>),
     DataTooLarge(DummyDataReceiver),
+    DataRejected(DummyDataReceiver, &'static [u8]),
     RequestTooLarge(DummyLineReceiver),
     Accepted(QueueId),
     None,
@@ -201,6 +208,7 @@ pub struct SessionData {
     pub remote_ip_str: String,
     pub remote_port: u16,
     pub helo_domain: String,
+    pub helo_only: bool,
 
     pub mail_from: Option<SessionAddress>,
     pub rcpt_to: Vec<SessionAddress>,
@@ -209,20 +217,33 @@ pub struct SessionData {
 
     pub authenticated_as: String,
     pub authenticated_emails: Vec<String>,
+    pub authenticated_features: u32,
     pub auth_errors: usize,
+    pub auth_attempts: usize,
 
     pub priority: i16,
     pub delivery_by: i64,
     pub future_release: u64,
+    pub mail_from_auth: Option<String>,
+    pub mail_from_size: usize,
 
     pub valid_until: Instant,
     pub bytes_left: usize,
     pub messages_sent: usize,
+    pub data_in_flight: Option<InFlight>,
 
     pub iprev: Option<IprevOutput>,
     pub spf_ehlo: Option<SpfOutput>,
     pub spf_mail_from: Option<SpfOutput>,
     pub dnsbl_error: Option<Vec<u8>>,
+    pub dnsbl_score: u32,
+    pub dnsbl_domain_score: u32,
+    pub noop_commands: usize,
+    pub pipelined_commands: usize,
+    pub pipelined_size: usize,
+
+    // Replies to the end of DATA for each recipient of a relayed LMTP transaction
+    pub rcpt_replies: Vec<(String, Vec<u8>)>,
 }
 
 #[derive(Clone)]
@@ -238,32 +259,58 @@ pub struct SessionAddress {
 pub struct SessionParameters {
     // Global parameters
     pub timeout: Duration,
+    pub timeout_mail: Duration,
+    pub timeout_rcpt: Duration,
+    pub timeout_data_init: Duration,
+    pub timeout_data: Duration,
+    pub max_line_length: usize,
+    pub command_leniency: CommandLeniency,
+    pub max_noop_commands: usize,
+    pub pipelining_max_commands: usize,
+    pub pipelining_max_size: usize,
 
     // Ehlo parameters
     pub ehlo_require: bool,
     pub ehlo_reject_non_fqdn: bool,
+    pub ehlo_helo_extensions: CommandLeniency,
 
     // Auth parameters
     pub auth_directory: Option<Arc<Directory>>,
     pub auth_require: bool,
     pub auth_errors_max: usize,
     pub auth_errors_wait: Duration,
+    pub auth_attempts_max: usize,
+    pub auth_max_response_size: usize,
     pub auth_plain_text: bool,
     pub auth_match_sender: bool,
+    pub send_rate: Option<Rate>,
 
     // Rcpt parameters
     pub rcpt_errors_max: usize,
     pub rcpt_errors_wait: Duration,
     pub rcpt_max: usize,
+    pub rcpt_max_null_sender: usize,
     pub rcpt_dsn: bool,
     pub can_expn: bool,
     pub can_vrfy: bool,
+    pub can_etrn: bool,
     pub max_message_size: usize,
+    pub max_data_line_length: usize,
+    pub bare_lf: BareLf,
 
     // Mail authentication parameters
     pub iprev: VerifyStrategy,
     pub spf_ehlo: VerifyStrategy,
     pub spf_mail_from: VerifyStrategy,
+    pub spf_check: Option<SpfCheck>,
+    pub spf_max_lookups: usize,
+    pub spf_max_macro_lookups: usize,
+
+    // Response parameters
+    pub omit_enhanced_status_codes: bool,
+    pub tarpit_delay: Duration,
+    pub tarpit_multiplier: u64,
+    pub tarpit_max_delay: Duration,
 }
 
 impl SessionData {
@@ -275,25 +322,42 @@ impl SessionData {
             remote_ip_str: remote_ip.to_string(),
             remote_port,
             helo_domain: String::new(),
+            helo_only: false,
             mail_from: None,
             rcpt_to: Vec::new(),
             authenticated_as: String::new(),
             authenticated_emails: Vec::new(),
+            authenticated_features: FEATURES_ALL,
             priority: 0,
             valid_until: Instant::now(),
             rcpt_errors: 0,
             message: Vec::with_capacity(0),
             auth_errors: 0,
+            auth_attempts: 0,
             messages_sent: 0,
+            data_in_flight: None,
             bytes_left: 0,
             delivery_by: 0,
             future_release: 0,
+            mail_from_auth: None,
+            mail_from_size: 0,
             iprev: None,
             spf_ehlo: None,
             spf_mail_from: None,
             dnsbl_error: None,
+            dnsbl_score: 0,
+            dnsbl_domain_score: 0,
+            noop_commands: 0,
+            pipelined_commands: 0,
+            pipelined_size: 0,
+            rcpt_replies: Vec::new(),
         }
     }
+
+    /// Unauthenticated sessions are only subject to the relay rules.
+    pub fn can_send_external(&self) -> bool {
+        self.authenticated_as.is_empty() || self.authenticated_features & FEATURE_SEND_EXTERNAL != 0
+    }
 }
 
 pub trait ResolveVariable {
@@ -421,24 +485,48 @@ impl Session<NullIo> {
             data,
             params: SessionParameters {
                 timeout: Default::default(),
+                timeout_mail: Default::default(),
+                timeout_rcpt: Default::default(),
+                timeout_data_init: Default::default(),
+                timeout_data: Default::default(),
+                max_line_length: Default::default(),
+                command_leniency: Default::default(),
+                max_noop_commands: Default::default(),
+                pipelining_max_commands: Default::default(),
+                pipelining_max_size: Default::default(),
                 ehlo_require: Default::default(),
                 ehlo_reject_non_fqdn: Default::default(),
+                ehlo_helo_extensions: Default::default(),
                 auth_directory: Default::default(),
                 auth_require: Default::default(),
                 auth_errors_max: Default::default(),
                 auth_errors_wait: Default::default(),
+                auth_attempts_max: Default::default(),
+                auth_max_response_size: Default::default(),
                 auth_plain_text: false,
                 rcpt_errors_max: Default::default(),
                 rcpt_errors_wait: Default::default(),
                 rcpt_max: Default::default(),
+                rcpt_max_null_sender: Default::default(),
                 rcpt_dsn: Default::default(),
                 max_message_size: Default::default(),
+                max_data_line_length: Default::default(),
+                bare_lf: Default::default(),
                 auth_match_sender: false,
+                send_rate: None,
                 iprev: crate::config::VerifyStrategy::Disable,
                 spf_ehlo: crate::config::VerifyStrategy::Disable,
                 spf_mail_from: crate::config::VerifyStrategy::Disable,
+                spf_check: None,
+                spf_max_lookups: 10,
+                spf_max_macro_lookups: 5,
+                omit_enhanced_status_codes: false,
+                tarpit_delay: Duration::ZERO,
+                tarpit_multiplier: 0,
+                tarpit_max_delay: Duration::ZERO,
                 can_expn: false,
                 can_vrfy: false,
+                can_etrn: false,
             },
             in_flight: vec![],
         }
@@ -492,17 +580,28 @@ impl SessionData {
             message,
             authenticated_as: "local".into(),
             authenticated_emails: vec![],
+            authenticated_features: FEATURES_ALL,
             auth_errors: 0,
+            auth_attempts: 0,
             priority: 0,
             delivery_by: 0,
             future_release: 0,
+            mail_from_auth: None,
+            mail_from_size: 0,
             valid_until: Instant::now(),
             bytes_left: 0,
             messages_sent: 0,
+            data_in_flight: None,
             iprev: None,
             spf_ehlo: None,
             spf_mail_from: None,
             dnsbl_error: None,
+            dnsbl_score: 0,
+            dnsbl_domain_score: 0,
+            noop_commands: 0,
+            pipelined_commands: 0,
+            pipelined_size: 0,
+            rcpt_replies: Vec::new(),
         }
     }
 }
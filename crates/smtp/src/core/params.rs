@@ -23,9 +23,12 @@
 
 use std::time::Duration;
 
+use directory::{QueryBy, Type};
+use smtp_proto::request::receiver::MAX_LINE_LENGTH;
 use tokio::io::{AsyncRead, AsyncWrite};
+use utils::config::Rate;
 
-use crate::config::VerifyStrategy;
+use crate::config::{CommandLeniency, SpfCheck, VerifyStrategy};
 
 use super::Session;
 
@@ -48,6 +51,56 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
             .eval_if(&c.timeout, self)
             .await
             .unwrap_or_else(|| Duration::from_secs(5 * 60));
+        self.params.timeout_mail = self
+            .core
+            .eval_if(&c.mail.timeout, self)
+            .await
+            .unwrap_or_else(|| Duration::from_secs(5 * 60));
+        self.params.max_line_length = self
+            .core
+            .eval_if(&c.max_line_length, self)
+            .await
+            .unwrap_or(512);
+        self.params.command_leniency = self
+            .core
+            .eval_if(&c.command_leniency, self)
+            .await
+            .unwrap_or_default();
+        self.params.max_noop_commands = self
+            .core
+            .eval_if(&c.max_noop_commands, self)
+            .await
+            .unwrap_or(20);
+        self.params.pipelining_max_commands = self
+            .core
+            .eval_if(&c.pipelining.max_commands, self)
+            .await
+            .unwrap_or(128);
+        self.params.pipelining_max_size = self
+            .core
+            .eval_if(&c.pipelining.max_size, self)
+            .await
+            .unwrap_or_default();
+        self.params.omit_enhanced_status_codes = !self
+            .core
+            .eval_if(&c.extensions.enhanced_status_codes, self)
+            .await
+            .unwrap_or(true);
+        self.params.tarpit_delay = self
+            .core
+            .eval_if(&c.tarpit.delay, self)
+            .await
+            .unwrap_or_default();
+        self.params.tarpit_multiplier = self
+            .core
+            .eval_if(&c.tarpit.multiplier, self)
+            .await
+            .unwrap_or(2);
+        self.params.tarpit_max_delay = self
+            .core
+            .eval_if(&c.tarpit.max_delay, self)
+            .await
+            .unwrap_or_else(|| Duration::from_secs(30));
         self.params.spf_ehlo = self
             .core
             .eval_if(&self.core.mail_auth.spf.verify_ehlo, self)
@@ -58,6 +111,25 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
             .eval_if(&self.core.mail_auth.spf.verify_mail_from, self)
             .await
             .unwrap_or(VerifyStrategy::Relaxed);
+        self.params.spf_check = self
+            .core
+            .eval_if(&self.core.mail_auth.spf.check, self)
+            .await;
+        match self.params.spf_check {
+            Some(SpfCheck::MailFrom) => self.params.spf_ehlo = VerifyStrategy::Disable,
+            Some(SpfCheck::Helo) => self.params.spf_mail_from = VerifyStrategy::Disable,
+            Some(SpfCheck::Both) | None => (),
+        }
+        self.params.spf_max_lookups = self
+            .core
+            .eval_if(&self.core.mail_auth.spf.max_lookups, self)
+            .await
+            .unwrap_or(10);
+        self.params.spf_max_macro_lookups = self
+            .core
+            .eval_if(&self.core.mail_auth.spf.max_macro_lookups, self)
+            .await
+            .unwrap_or(5);
         self.params.iprev = self
             .core
             .eval_if(&self.core.mail_auth.iprev.verify, self)
@@ -72,6 +144,11 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
             .eval_if(&ec.reject_non_fqdn, self)
             .await
             .unwrap_or(true);
+        self.params.ehlo_helo_extensions = self
+            .core
+            .eval_if(&ec.helo_extensions, self)
+            .await
+            .unwrap_or(CommandLeniency::Strict);
 
         // Auth parameters
         let ac = &self.core.session.config.auth;
@@ -88,6 +165,17 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
             .eval_if(&ac.errors_wait, self)
             .await
             .unwrap_or_else(|| Duration::from_secs(30));
+        self.params.auth_attempts_max =
+            self.core.eval_if(&ac.max_attempts, self).await.unwrap_or(3);
+        self.params.auth_max_response_size = match self
+            .core
+            .eval_if(&ac.max_response_size, self)
+            .await
+            .unwrap_or(64 * 1024)
+        {
+            0 => MAX_LINE_LENGTH,
+            max_size => max_size,
+        };
         self.params.auth_plain_text = self
             .core
             .eval_if(&ac.allow_plain_text, self)
@@ -99,17 +187,46 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
             .await
             .unwrap_or(true);
 
-        // VRFY/EXPN parameters
+        // VRFY/EXPN/ETRN parameters
         let ec = &self.core.session.config.extensions;
         self.params.can_expn = self.core.eval_if(&ec.expn, self).await.unwrap_or(false);
         self.params.can_vrfy = self.core.eval_if(&ec.vrfy, self).await.unwrap_or(false);
+        self.params.can_etrn = self.core.eval_if(&ec.etrn, self).await.unwrap_or(false);
     }
 
     pub async fn eval_post_auth_params(&mut self) {
-        // Refresh VRFY/EXPN parameters
+        // Refresh VRFY/EXPN/ETRN parameters
         let ec = &self.core.session.config.extensions;
         self.params.can_expn = self.core.eval_if(&ec.expn, self).await.unwrap_or(false);
         self.params.can_vrfy = self.core.eval_if(&ec.vrfy, self).await.unwrap_or(false);
+        self.params.can_etrn = self.core.eval_if(&ec.etrn, self).await.unwrap_or(false);
+
+        let is_superuser = self.is_superuser().await;
+        self.eval_send_rate(is_superuser).await;
+    }
+
+    pub async fn eval_send_rate(&mut self, is_superuser: bool) {
+        // Superusers are not subject to sending rates
+        self.params.send_rate = if !is_superuser && !self.data.authenticated_as.is_empty() {
+            self.core
+                .eval_if::<Rate, _>(&self.core.session.config.auth.send_rate, self)
+                .await
+        } else {
+            None
+        };
+    }
+
+    async fn is_superuser(&self) -> bool {
+        if let Some(directory) = &self.params.auth_directory {
+            matches!(
+                directory
+                    .query(QueryBy::Name(&self.data.authenticated_as), false)
+                    .await,
+                Ok(Some(principal)) if principal.typ == Type::Superuser
+            )
+        } else {
+            false
+        }
     }
 
     pub async fn eval_rcpt_params(&mut self) {
@@ -125,6 +242,16 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
             .eval_if(&rc.max_recipients, self)
             .await
             .unwrap_or(100);
+        self.params.rcpt_max_null_sender = self
+            .core
+            .eval_if(&rc.max_recipients_null_sender, self)
+            .await
+            .unwrap_or(1);
+        self.params.timeout_rcpt = self
+            .core
+            .eval_if(&rc.timeout, self)
+            .await
+            .unwrap_or_else(|| Duration::from_secs(5 * 60));
         self.params.rcpt_dsn = self
             .core
             .eval_if(&self.core.session.config.extensions.dsn, self)
@@ -136,5 +263,25 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
             .eval_if(&self.core.session.config.data.max_message_size, self)
             .await
             .unwrap_or(25 * 1024 * 1024);
+        self.params.max_data_line_length = self
+            .core
+            .eval_if(&self.core.session.config.data.max_line_length, self)
+            .await
+            .unwrap_or(1000);
+        self.params.timeout_data_init = self
+            .core
+            .eval_if(&self.core.session.config.data.timeout_init, self)
+            .await
+            .unwrap_or_else(|| Duration::from_secs(10 * 60));
+        self.params.timeout_data = self
+            .core
+            .eval_if(&self.core.session.config.data.timeout, self)
+            .await
+            .unwrap_or_else(|| Duration::from_secs(10 * 60));
+        self.params.bare_lf = self
+            .core
+            .eval_if(&self.core.session.config.data.bare_lf, self)
+            .await
+            .unwrap_or_default();
     }
 }
@@ -0,0 +1,203 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use store::write::now;
+use utils::listener::SessionStream;
+
+use crate::{config::Srs, queue::Message};
+
+use super::Session;
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const BASE64_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const TIMESTAMP_PRECISION: u64 = 86400;
+const TIMESTAMP_SLOTS: u64 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SrsError {
+    Invalid,
+    HashMismatch,
+    Expired,
+}
+
+impl Srs {
+    pub fn forward(&self, address: &str, now: u64) -> Option<String> {
+        let (local, domain) = address.rsplit_once('@')?;
+        if local.is_empty() || domain.is_empty() {
+            return None;
+        }
+        let timestamp = encode_timestamp(now);
+        let hash = self.hash(&timestamp, domain, local);
+
+        Some(format!(
+            "SRS0={hash}={timestamp}={domain}={local}@{}",
+            self.domain
+        ))
+    }
+
+    pub fn reverse(&self, address: &str, now: u64) -> Result<String, SrsError> {
+        let (local, domain) = address.rsplit_once('@').ok_or(SrsError::Invalid)?;
+        if !domain.eq_ignore_ascii_case(&self.domain) {
+            return Err(SrsError::Invalid);
+        }
+        let mut parts = local
+            .get(..5)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("SRS0="))
+            .and_then(|_| local.get(5..))
+            .ok_or(SrsError::Invalid)?
+            .splitn(4, '=');
+        let (hash, timestamp, domain, local) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(hash), Some(timestamp), Some(domain), Some(local))
+                    if !hash.is_empty() && !domain.is_empty() && !local.is_empty() =>
+                {
+                    (hash, timestamp, domain, local)
+                }
+                _ => return Err(SrsError::Invalid),
+            };
+        let sent = decode_timestamp(timestamp).ok_or(SrsError::Invalid)?;
+
+        // Some MTAs change the case of the local part, compare hashes case-insensitively
+        if !self
+            .hash(timestamp, domain, local)
+            .eq_ignore_ascii_case(hash)
+        {
+            return Err(SrsError::HashMismatch);
+        }
+
+        // Timestamps wrap around every 1024 days
+        let today = (now / TIMESTAMP_PRECISION) % TIMESTAMP_SLOTS;
+        let age = (today + TIMESTAMP_SLOTS - sent) % TIMESTAMP_SLOTS;
+        if age > self.max_age.as_secs() / TIMESTAMP_PRECISION {
+            return Err(SrsError::Expired);
+        }
+
+        Ok(format!("{local}@{domain}"))
+    }
+
+    pub fn is_srs_address(&self, address: &str) -> bool {
+        address.rsplit_once('@').map_or(false, |(local, domain)| {
+            domain.eq_ignore_ascii_case(&self.domain)
+                && local
+                    .get(..5)
+                    .map_or(false, |prefix| prefix.eq_ignore_ascii_case("SRS0="))
+        })
+    }
+
+    fn hash(&self, timestamp: &str, domain: &str, local: &str) -> String {
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        for part in [timestamp, domain, local] {
+            mac.update(part.to_lowercase().as_bytes());
+        }
+        let bytes = mac.finalize().into_bytes();
+
+        // The first 24 bits of the digest, encoded as four base64 characters
+        let bits = (u32::from(bytes[0]) << 16) | (u32::from(bytes[1]) << 8) | u32::from(bytes[2]);
+        (0..4)
+            .map(|i| char::from(BASE64_ALPHABET[((bits >> (18 - i * 6)) & 0x3f) as usize]))
+            .collect()
+    }
+}
+
+fn encode_timestamp(now: u64) -> String {
+    let slot = (now / TIMESTAMP_PRECISION) % TIMESTAMP_SLOTS;
+    [
+        char::from(BASE32_ALPHABET[(slot >> 5) as usize]),
+        char::from(BASE32_ALPHABET[(slot & 0x1f) as usize]),
+    ]
+    .into_iter()
+    .collect()
+}
+
+fn decode_timestamp(timestamp: &str) -> Option<u64> {
+    let mut slot = 0;
+    let mut len = 0;
+    for ch in timestamp.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&c| c == ch.to_ascii_uppercase())?;
+        slot = (slot << 5) | value as u64;
+        len += 1;
+    }
+    (len == 2).then_some(slot)
+}
+
+impl<T: SessionStream> Session<T> {
+    pub async fn srs_forward(&self, message: &mut Message) {
+        let srs = match &self.core.session.config.srs {
+            Some(srs)
+                if !message.return_path.is_empty() && self.data.authenticated_as.is_empty() =>
+            {
+                srs
+            }
+            _ => return,
+        };
+        let directory = if let Some(directory) = self
+            .core
+            .eval_if::<String, _>(&self.core.session.config.rcpt.directory, self)
+            .await
+            .and_then(|name| self.core.get_directory(&name))
+        {
+            directory
+        } else {
+            return;
+        };
+
+        // Only messages from foreign senders that are relayed to foreign domains are rewritten
+        if directory
+            .is_local_domain(&message.return_path_domain)
+            .await
+            .unwrap_or(true)
+        {
+            return;
+        }
+        let mut is_forward = false;
+        for domain in &message.domains {
+            if !directory
+                .is_local_domain(&domain.domain)
+                .await
+                .unwrap_or(true)
+            {
+                is_forward = true;
+                break;
+            }
+        }
+
+        if is_forward {
+            if let Some(return_path) = srs.forward(&message.return_path, now()) {
+                tracing::debug!(parent: &self.span,
+                    context = "srs",
+                    event = "rewrite",
+                    return_path = &message.return_path,
+                    srs_address = &return_path);
+
+                message.return_path_lcase = return_path.to_lowercase();
+                message.return_path_domain = srs.domain.to_lowercase();
+                message.return_path = return_path;
+            }
+        }
+    }
+}
@@ -206,6 +206,10 @@ impl Throttle {
 
 impl<T: AsyncRead + AsyncWrite> Session<T> {
     pub async fn is_allowed(&mut self) -> bool {
+        if self.is_trusted() {
+            return true;
+        }
+
         let throttles = if !self.data.rcpt_to.is_empty() {
             &self.core.session.config.throttle.rcpt_to
         } else if self.data.mail_from.is_some() {
@@ -21,46 +21,333 @@
  * for more details.
 */
 
+use std::sync::Arc;
+
+use directory::QueryBy;
+use mail_builder::encoders::base64::base64_encode;
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
 use smtp_proto::{IntoString, AUTH_LOGIN, AUTH_OAUTHBEARER, AUTH_PLAIN, AUTH_XOAUTH2};
 use tokio::io::{AsyncRead, AsyncWrite};
+use utils::listener::SessionStream;
+
+use crate::{config::session::Mechanism, core::Session};
+
+pub trait SaslMechanism: Send + Sync {
+    /// Name of the mechanism as sent by clients in the AUTH command.
+    fn name(&self) -> &'static str;
+
+    /// Identifier of the mechanism in `smtp_proto`, for example `AUTH_PLAIN`.
+    /// Only built-in mechanisms have one, mechanisms registered by name are
+    /// advertised and dispatched using their name and enabled with the
+    /// `session.auth.custom-mechanisms` setting.
+    fn id(&self) -> u64 {
+        0
+    }
+
+    /// Whether the mechanism exposes credentials and can only be offered
+    /// over TLS, unless clear text authentication is allowed.
+    fn is_tls_required(&self) -> bool {
+        false
+    }
 
-use crate::core::Session;
+    /// Processes the decoded client responses received so far.
+    fn step(&self, responses: &[Vec<u8>]) -> SaslOutcome;
+}
+
+pub enum SaslOutcome {
+    Challenge(Vec<u8>),
+    Authenticate(Credentials<String>),
+    Invalid,
+}
+
+#[derive(Clone)]
+pub struct SaslMechanisms {
+    mechanisms: Vec<Arc<dyn SaslMechanism>>,
+}
 
 pub struct SaslToken {
-    mechanism: u64,
-    credentials: Credentials<String>,
+    mechanism: Arc<dyn SaslMechanism>,
+    responses: Vec<Vec<u8>>,
+}
+
+struct Plain;
+struct Login;
+struct XOAuth2;
+struct OAuthBearer;
+
+impl SaslMechanisms {
+    pub fn register(&mut self, mechanism: impl SaslMechanism + 'static) -> &mut Self {
+        let (id, name) = (mechanism.id(), mechanism.name());
+        self.mechanisms
+            .retain(|m| (id == 0 || m.id() != id) && !m.name().eq_ignore_ascii_case(name));
+        self.mechanisms.push(Arc::new(mechanism));
+        self
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Arc<dyn SaslMechanism>> {
+        self.mechanisms.iter().find(|m| m.id() != 0 && m.id() == id)
+    }
+
+    /// Returns a mechanism registered by name, built-in mechanisms are only
+    /// looked up by their identifier.
+    pub fn get_by_name(&self, name: &str) -> Option<&Arc<dyn SaslMechanism>> {
+        self.mechanisms
+            .iter()
+            .find(|m| m.id() == 0 && m.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the identifiers of all built-in mechanisms, or only of those
+    /// that can be used over clear text connections.
+    pub fn ids(&self, is_tls: bool) -> u64 {
+        self.mechanisms
+            .iter()
+            .filter(|m| is_tls || !m.is_tls_required())
+            .fold(0, |ids, m| ids | m.id())
+    }
+
+    /// Returns the names of the mechanisms registered by name out of the
+    /// `enabled` ones, or only of those that can be used over clear text
+    /// connections.
+    pub fn names(&self, enabled: &[String], is_tls: bool) -> Vec<&'static str> {
+        enabled
+            .iter()
+            .filter_map(|name| self.get_by_name(name))
+            .filter(|m| is_tls || !m.is_tls_required())
+            .map(|m| m.name())
+            .collect()
+    }
+}
+
+impl Default for SaslMechanisms {
+    fn default() -> Self {
+        let mut mechanisms = SaslMechanisms {
+            mechanisms: Vec::new(),
+        };
+        mechanisms
+            .register(Plain)
+            .register(Login)
+            .register(XOAuth2)
+            .register(OAuthBearer);
+        mechanisms
+    }
 }
 
 impl SaslToken {
-    pub fn from_mechanism(mechanism: u64) -> Option<SaslToken> {
-        match mechanism {
-            AUTH_PLAIN | AUTH_LOGIN => SaslToken {
-                mechanism,
-                credentials: Credentials::Plain {
-                    username: String::new(),
-                    secret: String::new(),
-                },
+    pub fn new(mechanism: Arc<dyn SaslMechanism>) -> SaslToken {
+        SaslToken {
+            mechanism,
+            responses: Vec::new(),
+        }
+    }
+}
+
+impl SaslMechanism for Plain {
+    fn name(&self) -> &'static str {
+        "PLAIN"
+    }
+
+    fn id(&self) -> u64 {
+        AUTH_PLAIN
+    }
+
+    fn is_tls_required(&self) -> bool {
+        true
+    }
+
+    fn step(&self, responses: &[Vec<u8>]) -> SaslOutcome {
+        let Some(response) = responses.first() else {
+            return SaslOutcome::Challenge(Vec::new());
+        };
+
+        let mut b_username = Vec::new();
+        let mut b_secret = Vec::new();
+        let mut arg_num = 0;
+        for &ch in response {
+            if ch != 0 {
+                if arg_num == 1 {
+                    b_username.push(ch);
+                } else if arg_num == 2 {
+                    b_secret.push(ch);
+                }
+            } else {
+                arg_num += 1;
+            }
+        }
+        match (String::from_utf8(b_username), String::from_utf8(b_secret)) {
+            (Ok(username), Ok(secret)) if !username.is_empty() => {
+                SaslOutcome::Authenticate(Credentials::Plain { username, secret })
+            }
+            _ => SaslOutcome::Invalid,
+        }
+    }
+}
+
+impl SaslMechanism for Login {
+    fn name(&self) -> &'static str {
+        "LOGIN"
+    }
+
+    fn id(&self) -> u64 {
+        AUTH_LOGIN
+    }
+
+    fn is_tls_required(&self) -> bool {
+        true
+    }
+
+    fn step(&self, responses: &[Vec<u8>]) -> SaslOutcome {
+        match responses {
+            [] => SaslOutcome::Challenge(b"User Name\0".to_vec()),
+            [_] => SaslOutcome::Challenge(b"Password\0".to_vec()),
+            [username, secret, ..] => SaslOutcome::Authenticate(Credentials::Plain {
+                username: username.clone().into_string(),
+                secret: secret.clone().into_string(),
+            }),
+        }
+    }
+}
+
+impl SaslMechanism for XOAuth2 {
+    fn name(&self) -> &'static str {
+        "XOAUTH2"
+    }
+
+    fn id(&self) -> u64 {
+        AUTH_XOAUTH2
+    }
+
+    fn step(&self, responses: &[Vec<u8>]) -> SaslOutcome {
+        let Some(response) = responses.first() else {
+            return SaslOutcome::Challenge(Vec::new());
+        };
+
+        let mut b_username = Vec::new();
+        let mut b_secret = Vec::new();
+        let mut arg_num = 0;
+        let mut in_arg = false;
+
+        for &ch in response {
+            if in_arg {
+                if ch != 1 {
+                    if arg_num == 1 {
+                        b_username.push(ch);
+                    } else if arg_num == 2 {
+                        b_secret.push(ch);
+                    }
+                } else {
+                    in_arg = false;
+                }
+            } else if ch == b'=' {
+                arg_num += 1;
+                in_arg = true;
             }
-            .into(),
-            AUTH_OAUTHBEARER => SaslToken {
-                mechanism,
-                credentials: Credentials::OAuthBearer {
-                    token: String::new(),
-                },
+        }
+        match (String::from_utf8(b_username), String::from_utf8(b_secret)) {
+            (Ok(username), Ok(secret)) if !username.is_empty() => {
+                SaslOutcome::Authenticate(Credentials::XOauth2 { username, secret })
             }
-            .into(),
-            AUTH_XOAUTH2 => SaslToken {
-                mechanism,
-                credentials: Credentials::XOauth2 {
-                    username: String::new(),
-                    secret: String::new(),
-                },
+            _ => SaslOutcome::Invalid,
+        }
+    }
+}
+
+impl SaslMechanism for OAuthBearer {
+    fn name(&self) -> &'static str {
+        "OAUTHBEARER"
+    }
+
+    fn id(&self) -> u64 {
+        AUTH_OAUTHBEARER
+    }
+
+    fn step(&self, responses: &[Vec<u8>]) -> SaslOutcome {
+        match responses.first() {
+            None => SaslOutcome::Challenge(Vec::new()),
+            Some(response) => {
+                let token = response.clone().into_string();
+                if token.contains("auth=") {
+                    SaslOutcome::Authenticate(Credentials::OAuthBearer { token })
+                } else {
+                    SaslOutcome::Invalid
+                }
+            }
+        }
+    }
+}
+
+impl<T: SessionStream> Session<T> {
+    /// Starts a SASL exchange for a built-in mechanism, or for a mechanism
+    /// registered by name when `name` is set. Returns the token to continue
+    /// the exchange with, if the client has to send further responses.
+    pub async fn handle_auth(
+        &mut self,
+        mechanism: u64,
+        name: Option<&str>,
+        initial_response: &[u8],
+    ) -> Result<Option<SaslToken>, ()> {
+        let ac = &self.core.session.config.auth;
+        let auth: u64 = self
+            .core
+            .eval_if::<Mechanism, _>(&ac.mechanisms, self)
+            .await
+            .unwrap_or_default()
+            .into();
+        let custom_auth = self
+            .core
+            .eval_if::<Vec<String>, _>(&ac.custom_mechanisms, self)
+            .await
+            .unwrap_or_default();
+        let sasl = match name {
+            Some(name) => custom_auth
+                .iter()
+                .any(|enabled| enabled.eq_ignore_ascii_case(name))
+                .then(|| ac.sasl.get_by_name(name))
+                .flatten(),
+            None => ac.sasl.get(mechanism & auth),
+        }
+        .cloned();
+
+        if !self.can_use_extensions() {
+            self.write(b"503 5.5.1 Send EHLO to use ESMTP extensions.\r\n")
+                .await?;
+        } else if (auth == 0 && custom_auth.is_empty()) || self.params.auth_directory.is_none() {
+            self.write(b"503 5.5.1 AUTH not allowed.\r\n").await?;
+        } else if !self.data.authenticated_as.is_empty() {
+            self.write(b"503 5.5.1 Already authenticated.\r\n").await?;
+        } else if self.data.auth_attempts >= self.params.auth_attempts_max {
+            self.write(b"421 4.3.0 Too many authentication attempts, disconnecting.\r\n")
+                .await?;
+            tracing::debug!(
+                parent: &self.span,
+                event = "disconnect",
+                reason = "auth-attempts",
+                "Too many authentication attempts."
+            );
+            return Err(());
+        } else if let Some(sasl) = sasl {
+            if sasl.is_tls_required() && !self.stream.is_tls() && !self.params.auth_plain_text {
+                self.write(b"503 5.5.1 Clear text authentication without TLS is forbidden.\r\n")
+                    .await?;
+            } else if initial_response.len() > self.params.auth_max_response_size {
+                self.auth_error(b"500 5.5.6 Authentication Exchange line is too long.\r\n")
+                    .await?;
+            } else {
+                let mut token = SaslToken::new(sasl);
+                self.data.auth_attempts += 1;
+                if self
+                    .handle_sasl_response(&mut token, initial_response)
+                    .await?
+                {
+                    return Ok(Some(token));
+                }
             }
-            .into(),
-            _ => None,
+        } else {
+            self.write(b"554 5.7.8 Authentication mechanism not supported.\r\n")
+                .await?;
         }
+
+        Ok(None)
     }
 }
 
@@ -70,122 +357,52 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         token: &mut SaslToken,
         response: &[u8],
     ) -> Result<bool, ()> {
-        if response.is_empty() {
-            match (token.mechanism, &token.credentials) {
-                (AUTH_PLAIN | AUTH_XOAUTH2 | AUTH_OAUTHBEARER, _) => {
-                    self.write(b"334 Go ahead.\r\n").await?;
-                    return Ok(true);
-                }
-                (AUTH_LOGIN, Credentials::Plain { username, secret }) => {
-                    if username.is_empty() && secret.is_empty() {
-                        self.write(b"334 VXNlciBOYW1lAA==\r\n").await?;
-                        return Ok(true);
-                    }
-                }
-                _ => (),
+        if !response.is_empty() {
+            if let Some(response) = base64_decode(response) {
+                token.responses.push(response);
+            } else {
+                return self.auth_error(b"500 5.5.6 Invalid challenge.\r\n").await;
             }
-        } else if let Some(response) = base64_decode(response) {
-            match (token.mechanism, &mut token.credentials) {
-                (AUTH_PLAIN, Credentials::Plain { username, secret }) => {
-                    let mut b_username = Vec::new();
-                    let mut b_secret = Vec::new();
-                    let mut arg_num = 0;
-                    for ch in response {
-                        if ch != 0 {
-                            if arg_num == 1 {
-                                b_username.push(ch);
-                            } else if arg_num == 2 {
-                                b_secret.push(ch);
-                            }
-                        } else {
-                            arg_num += 1;
-                        }
-                    }
-                    match (String::from_utf8(b_username), String::from_utf8(b_secret)) {
-                        (Ok(s_username), Ok(s_secret)) if !s_username.is_empty() => {
-                            *username = s_username;
-                            *secret = s_secret;
-                            return self
-                                .authenticate(std::mem::take(&mut token.credentials))
-                                .await;
-                        }
-                        _ => (),
-                    }
-                }
-                (AUTH_LOGIN, Credentials::Plain { username, secret }) => {
-                    return if username.is_empty() {
-                        *username = response.into_string();
-                        self.write(b"334 UGFzc3dvcmQA\r\n").await?;
-                        Ok(true)
-                    } else {
-                        *secret = response.into_string();
-                        self.authenticate(std::mem::take(&mut token.credentials))
-                            .await
-                    };
-                }
-                (AUTH_OAUTHBEARER, Credentials::OAuthBearer { token: token_ }) => {
-                    let response = response.into_string();
-                    if response.contains("auth=") {
-                        *token_ = response;
-                        return self
-                            .authenticate(std::mem::take(&mut token.credentials))
-                            .await;
-                    }
-                }
-                (AUTH_XOAUTH2, Credentials::XOauth2 { username, secret }) => {
-                    let mut b_username = Vec::new();
-                    let mut b_secret = Vec::new();
-                    let mut arg_num = 0;
-                    let mut in_arg = false;
-
-                    for ch in response {
-                        if in_arg {
-                            if ch != 1 {
-                                if arg_num == 1 {
-                                    b_username.push(ch);
-                                } else if arg_num == 2 {
-                                    b_secret.push(ch);
-                                }
-                            } else {
-                                in_arg = false;
-                            }
-                        } else if ch == b'=' {
-                            arg_num += 1;
-                            in_arg = true;
-                        }
-                    }
-                    match (String::from_utf8(b_username), String::from_utf8(b_secret)) {
-                        (Ok(s_username), Ok(s_secret)) if !s_username.is_empty() => {
-                            *username = s_username;
-                            *secret = s_secret;
-                            return self
-                                .authenticate(std::mem::take(&mut token.credentials))
-                                .await;
-                        }
-                        _ => (),
-                    }
-                }
+        }
 
-                _ => (),
+        match token.mechanism.step(&token.responses) {
+            SaslOutcome::Challenge(challenge) if challenge.is_empty() => {
+                self.write(b"334 Go ahead.\r\n").await?;
+                Ok(true)
+            }
+            SaslOutcome::Challenge(challenge) => {
+                let mut buf = b"334 ".to_vec();
+                buf.extend_from_slice(&base64_encode(&challenge).unwrap_or_default());
+                buf.extend_from_slice(b"\r\n");
+                self.write(&buf).await?;
+                Ok(true)
             }
+            SaslOutcome::Authenticate(credentials) => {
+                tracing::debug!(
+                    parent: &self.span,
+                    context = "auth",
+                    event = "step",
+                    mechanism = token.mechanism.name(),
+                    "SASL exchange completed."
+                );
+                self.authenticate(credentials).await
+            }
+            SaslOutcome::Invalid => self.auth_error(b"500 5.5.6 Invalid challenge.\r\n").await,
         }
-
-        self.auth_error(b"500 5.5.6 Invalid challenge.\r\n").await
     }
 
     pub async fn authenticate(&mut self, credentials: Credentials<String>) -> Result<bool, ()> {
-        if let Some(lookup) = &self.params.auth_directory {
+        if let Some(lookup) = self.params.auth_directory.clone() {
             let authenticated_as = match &credentials {
                 Credentials::Plain { username, .. }
                 | Credentials::XOauth2 { username, .. }
                 | Credentials::OAuthBearer { token: username } => username.to_string(),
             };
-            let todo = "fix";
-            /*match lookup
-                .authenticate(&credentials, self.data.remote_ip, false)
+            match lookup
+                .query(QueryBy::Credentials(&credentials), false)
                 .await
             {
-                Ok(AuthResult::Success(principal)) => {
+                Ok(Some(principal)) if principal.is_allowed_ip(&self.data.remote_ip) => {
                     tracing::debug!(
                         parent: &self.span,
                         context = "auth",
@@ -199,12 +416,13 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                         .into_iter()
                         .map(|e| e.trim().to_lowercase())
                         .collect();
+                    self.data.authenticated_features = principal.features;
                     self.eval_post_auth_params().await;
                     self.write(b"235 2.7.0 Authentication succeeded.\r\n")
                         .await?;
                     return Ok(false);
                 }
-                Ok(AuthResult::Failure) => {
+                Ok(_) => {
                     tracing::debug!(
                         parent: &self.span,
                         context = "auth",
@@ -216,18 +434,8 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                         .auth_error(b"535 5.7.8 Authentication credentials invalid.\r\n")
                         .await;
                 }
-                Ok(AuthResult::Banned) => {
-                    tracing::debug!(
-                        parent: &self.span,
-                        context = "auth",
-                        event = "authenticate",
-                        result = "banned"
-                    );
-
-                    return Err(());
-                }
-                _ => (),
-            }*/
+                Err(_) => (),
+            }
         } else {
             tracing::warn!(
                 parent: &self.span,
@@ -245,6 +453,7 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
     pub async fn auth_error(&mut self, response: &[u8]) -> Result<bool, ()> {
         tokio::time::sleep(self.params.auth_errors_wait).await;
         self.data.auth_errors += 1;
+        self.tarpit().await;
         self.write(response).await?;
         if self.data.auth_errors < self.params.auth_errors_max {
             Ok(false)
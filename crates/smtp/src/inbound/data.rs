@@ -28,28 +28,40 @@ use std::{
     time::{Duration, SystemTime},
 };
 
+use directory::backend::smtp::relay::{RelayEnvelope, RelayRecipient};
 use mail_auth::{
     common::{headers::HeaderWriter, verify::VerifySignature},
     dmarc, AuthenticatedMessage, AuthenticationResults, DkimResult, DmarcResult, ReceivedSpf,
 };
-use mail_builder::headers::{date::Date, message_id::generate_message_id_header};
+use mail_builder::{
+    encoders::base64::base64_encode,
+    headers::{date::Date, message_id::generate_message_id_header},
+};
+use mail_parser::MessageParser;
 use sieve::runtime::Variable;
 use smtp_proto::{
-    MAIL_BY_RETURN, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
+    Severity, MAIL_BY_RETURN, MAIL_SMTPUTF8, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE,
+    RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
 };
 use store::write::now;
 use tokio::{io::AsyncWriteExt, process::Command};
-use utils::{config::Rate, listener::SessionStream};
+use utils::{
+    config::{Rate, ServerProtocol},
+    listener::SessionStream,
+};
 
 use crate::{
-    config::VerifyStrategy,
+    config::{
+        DkimSignFailure, DuplicateAction, DuplicateScope, EightBitHeaders, MissingHeaders,
+        ScoreSignal, VerifyStrategy,
+    },
     core::{Session, SessionAddress, State},
     queue::{self, Message, SimpleEnvelope},
     reporting::analysis::AnalyzeReport,
     scripts::{ScriptModification, ScriptResult},
 };
 
-use super::AuthResult;
+use super::{build_response, AuthResult};
 
 impl<T: SessionStream> Session<T> {
     pub async fn queue_message(&mut self) -> Cow<'static, [u8]> {
@@ -66,7 +78,7 @@ impl<T: SessionStream> Session<T> {
             return (&b"550 5.7.7 Failed to parse message.\r\n"[..]).into();
         };
 
-        // Loop detection
+        // Loop detection (RFC 5321 section 6.3)
         let dc = &self.core.session.config.data;
         let ac = &self.core.mail_auth;
         let rc = &self.core.report.config;
@@ -75,7 +87,7 @@ impl<T: SessionStream> Session<T> {
                 .core
                 .eval_if(&dc.max_received_headers, self)
                 .await
-                .unwrap_or(50)
+                .unwrap_or(30)
         {
             tracing::info!(parent: &self.span,
                 context = "data",
@@ -83,9 +95,46 @@ impl<T: SessionStream> Session<T> {
                 return_path = self.data.mail_from.as_ref().unwrap().address,
                 from = auth_message.from(),
                 received_headers = auth_message.received_headers_count());
-            return (&b"450 4.4.6 Too many Received headers. Possible loop detected.\r\n"[..])
-                .into();
+            return (&b"554 5.4.6 Too many hops, possible mail loop detected.\r\n"[..]).into();
+        }
+
+        // Look for unencoded 8-bit header values
+        let mut eight_bit_headers: Vec<String> = Vec::new();
+        if self.data.mail_from.as_ref().unwrap().flags & MAIL_SMTPUTF8 == 0 {
+            for (name, value) in auth_message.raw_parsed_headers() {
+                if !value.is_ascii() {
+                    let name = String::from_utf8_lossy(name).trim().to_string();
+                    if !eight_bit_headers
+                        .iter()
+                        .any(|h| h.eq_ignore_ascii_case(&name))
+                    {
+                        eight_bit_headers.push(name);
+                    }
+                }
+            }
         }
+        let eight_bit_action = if !eight_bit_headers.is_empty() {
+            let action = self
+                .core
+                .eval_if(&dc.eight_bit_headers, self)
+                .await
+                .unwrap_or_default();
+            tracing::debug!(parent: &self.span,
+                context = "data",
+                event = "8bit-headers",
+                return_path = self.data.mail_from.as_ref().unwrap().address,
+                headers = ?eight_bit_headers,
+                action = ?action,
+                "Message contains unencoded 8-bit headers.");
+
+            if action == EightBitHeaders::Reject {
+                return (&b"550 5.6.0 Message headers contain unencoded 8-bit characters.\r\n"[..])
+                    .into();
+            }
+            action
+        } else {
+            EightBitHeaders::Allow
+        };
 
         // Verify DKIM
         let dkim = self
@@ -131,7 +180,13 @@ impl<T: SessionStream> Session<T> {
                 {
                     (&b"451 4.7.20 No passing DKIM signatures found.\r\n"[..]).into()
                 } else {
-                    (&b"550 5.7.20 No passing DKIM signatures found.\r\n"[..]).into()
+                    self.reject_response(
+                        &self.core.session.config.reject.dkim,
+                        "550 5.7.20",
+                        &self.rcpt_addresses(),
+                        "No passing DKIM signatures found.",
+                    )
+                    .into()
                 };
             } else {
                 tracing::debug!(parent: &self.span,
@@ -174,7 +229,13 @@ impl<T: SessionStream> Session<T> {
                 return if matches!(arc_output.result(), DkimResult::TempError(_)) {
                     (&b"451 4.7.29 ARC validation failed.\r\n"[..]).into()
                 } else {
-                    (&b"550 5.7.29 ARC validation failed.\r\n"[..]).into()
+                    self.reject_response(
+                        &self.core.session.config.reject.arc,
+                        "550 5.7.29",
+                        &self.rcpt_addresses(),
+                        "ARC validation failed.",
+                    )
+                    .into()
                 };
             } else {
                 tracing::debug!(parent: &self.span,
@@ -291,7 +352,13 @@ impl<T: SessionStream> Session<T> {
                     return if is_temp_fail {
                         (&b"451 4.7.1 Email temporarily rejected per DMARC policy.\r\n"[..]).into()
                     } else {
-                        (&b"550 5.7.1 Email rejected per DMARC policy.\r\n"[..]).into()
+                        self.reject_response(
+                            &self.core.session.config.reject.dmarc,
+                            "550 5.7.1",
+                            &self.rcpt_addresses(),
+                            "Email rejected per DMARC policy.",
+                        )
+                        .into()
                     };
                 }
 
@@ -300,6 +367,58 @@ impl<T: SessionStream> Session<T> {
             _ => (None, None),
         };
 
+        // Score authentication results, submissions are not scored
+        let score_config = &self.core.session.config.score;
+        let auth_score = if score_config.is_enabled() && self.data.authenticated_as.is_empty() {
+            let spf_result = self.data.spf_mail_from.as_ref().map(|o| o.result());
+            let dkim_none = DkimResult::None;
+            let mut results: Vec<(ScoreSignal, &dyn AuthResult)> = Vec::with_capacity(4);
+            if let Some(spf_result) = &spf_result {
+                results.push((ScoreSignal::Spf, spf_result));
+            }
+            if dkim.verify() || dmarc.verify() {
+                results.push((
+                    ScoreSignal::Dkim,
+                    dkim_output
+                        .iter()
+                        .find(|r| matches!(r.result(), DkimResult::Pass))
+                        .or_else(|| dkim_output.first())
+                        .map_or(&dkim_none, |r| r.result()),
+                ));
+            }
+            if let Some(dmarc_result) = &dmarc_result {
+                results.push((ScoreSignal::Dmarc, dmarc_result));
+            }
+            if let Some(iprev) = &self.data.iprev {
+                results.push((ScoreSignal::Iprev, &iprev.result));
+            }
+            let score = score_config.score(&results, self.data.dnsbl_total_score());
+
+            if score_config.is_reject(score) {
+                tracing::info!(parent: &self.span,
+                    context = "score",
+                    event = "reject",
+                    return_path = mail_from.address,
+                    from = auth_message.from(),
+                    score = score,
+                    "Message rejected due to its authentication score.");
+
+                return (b"550 5.7.1 Message rejected due to its authentication score.\r\n"[..])
+                    .into();
+            }
+
+            tracing::debug!(parent: &self.span,
+                context = "score",
+                event = "result",
+                return_path = mail_from.address,
+                from = auth_message.from(),
+                score = score);
+
+            Some(score)
+        } else {
+            None
+        };
+
         // Analyze reports
         if self.is_report() {
             self.core.analyze_report(raw_message.clone());
@@ -529,9 +648,34 @@ impl<T: SessionStream> Session<T> {
             }
         }
 
-        // Build message
+        // Suppress duplicate deliveries
         let mail_from = self.data.mail_from.clone().unwrap();
-        let rcpt_to = std::mem::take(&mut self.data.rcpt_to);
+        let mut rcpt_to = std::mem::take(&mut self.data.rcpt_to);
+        let duplicates = self.filter_duplicates(&raw_message, &mut rcpt_to).await;
+        if rcpt_to.is_empty() {
+            let action = self
+                .core
+                .eval_if(&dc.duplicate_action, self)
+                .await
+                .unwrap_or_default();
+            tracing::info!(parent: &self.span,
+                context = "data",
+                event = "duplicate",
+                return_path = mail_from.address,
+                action = ?action,
+                "Message-ID was already delivered within the duplicate window.");
+
+            return match action {
+                DuplicateAction::Discard => {
+                    (b"250 2.0.0 Message queued for delivery.\r\n"[..]).into()
+                }
+                DuplicateAction::Reject => {
+                    (b"550 5.7.1 Duplicate message rejected.\r\n"[..]).into()
+                }
+            };
+        }
+
+        // Build message
         let mut message = self.build_message(mail_from, rcpt_to).await;
 
         // Add Received header
@@ -541,7 +685,7 @@ impl<T: SessionStream> Session<T> {
             .await
             .unwrap_or(true)
         {
-            self.write_received(&mut headers, message.id)
+            self.write_received(&mut headers, message.id).await;
         }
 
         // Add authentication results header
@@ -573,6 +717,15 @@ impl<T: SessionStream> Session<T> {
             }
         }
 
+        // Flag messages scored as spam
+        if let Some(score) = auth_score {
+            if self.core.session.config.score.is_spam(score) {
+                headers.extend_from_slice(
+                    format!("X-Spam-Status: Yes, score={score:.2}\r\n").as_bytes(),
+                );
+            }
+        }
+
         // ARC Seal
         if let (Some(arc_sealer), Some(arc_output)) = (arc_sealer, &arc_output) {
             if !dkim_output.is_empty() && arc_output.can_be_sealed() {
@@ -593,22 +746,60 @@ impl<T: SessionStream> Session<T> {
         }
 
         // Add any missing headers
-        if !auth_message.has_date_header()
-            && self.core.eval_if(&dc.add_date, self).await.unwrap_or(true)
-        {
-            headers.extend_from_slice(b"Date: ");
-            headers.extend_from_slice(Date::now().to_rfc822().as_bytes());
-            headers.extend_from_slice(b"\r\n");
+        let mut missing_headers = Vec::new();
+        if !auth_message.has_date_header() {
+            if self.core.eval_if(&dc.add_date, self).await.unwrap_or(true) {
+                headers.extend_from_slice(b"Date: ");
+                headers.extend_from_slice(Date::now().to_rfc822().as_bytes());
+                headers.extend_from_slice(b"\r\n");
+            } else {
+                missing_headers.push("Date");
+            }
         }
-        if !auth_message.has_message_id_header()
-            && self
+        if !auth_message.has_message_id_header() {
+            if self
                 .core
                 .eval_if(&dc.add_message_id, self)
                 .await
                 .unwrap_or(true)
-        {
-            headers.extend_from_slice(b"Message-ID: ");
-            let _ = generate_message_id_header(&mut headers, &self.instance.hostname);
+            {
+                headers.extend_from_slice(b"Message-ID: ");
+                let _ = generate_message_id_header(&mut headers, &self.instance.hostname);
+                headers.extend_from_slice(b"\r\n");
+            } else {
+                missing_headers.push("Message-ID");
+            }
+        }
+        if !missing_headers.is_empty() {
+            let action = self
+                .core
+                .eval_if(&dc.missing_headers, self)
+                .await
+                .unwrap_or_default();
+            tracing::debug!(parent: &self.span,
+                context = "data",
+                event = "missing-headers",
+                return_path = message.return_path,
+                headers = ?missing_headers,
+                action = ?action,
+                "Message is missing required headers.");
+
+            match action {
+                MissingHeaders::Allow => (),
+                MissingHeaders::Flag => {
+                    headers.extend_from_slice(b"X-Missing-Headers: ");
+                    headers.extend_from_slice(missing_headers.join(", ").as_bytes());
+                    headers.extend_from_slice(b"\r\n");
+                }
+                MissingHeaders::Reject => {
+                    return (b"550 5.6.0 Missing required Date or Message-ID header.\r\n"[..])
+                        .into();
+                }
+            }
+        }
+        if eight_bit_action == EightBitHeaders::Flag {
+            headers.extend_from_slice(b"X-Unencoded-Headers: ");
+            headers.extend_from_slice(eight_bit_headers.join(", ").as_bytes());
             headers.extend_from_slice(b"\r\n");
         }
 
@@ -624,33 +815,251 @@ impl<T: SessionStream> Session<T> {
             headers.extend_from_slice(b">\r\n");
         }
 
-        // DKIM sign
-        let raw_message = edited_message.unwrap_or(raw_message);
+        // Domain used to select a DKIM signer, the From header takes precedence
+        // only when its domain belongs to one of the authenticated addresses
+        let sign_domain = auth_message
+            .from()
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.to_lowercase())
+            .filter(|domain| {
+                self.data.authenticated_emails.iter().any(|email| {
+                    email.rsplit_once('@').map_or(false, |(_, email_domain)| {
+                        email_domain.eq_ignore_ascii_case(domain)
+                    })
+                })
+            })
+            .unwrap_or_else(|| message.return_path_domain.clone());
+
+        // Encode 8-bit header values before signing, headers that cannot be
+        // encoded are flagged instead
+        let mut raw_message = edited_message.unwrap_or(raw_message);
+        if eight_bit_action == EightBitHeaders::Encode {
+            if let Some(encoded_message) = encode_8bit_headers(&raw_message) {
+                raw_message = Arc::new(encoded_message);
+            } else {
+                tracing::debug!(parent: &self.span,
+                    context = "data",
+                    event = "8bit-headers",
+                    return_path = message.return_path,
+                    headers = ?eight_bit_headers,
+                    "Failed to encode 8-bit headers, flagging message instead.");
+
+                headers.extend_from_slice(b"X-Unencoded-Headers: ");
+                headers.extend_from_slice(eight_bit_headers.join(", ").as_bytes());
+                headers.extend_from_slice(b"\r\n");
+            }
+        }
+
+        // Remove Bcc headers, recipients were already provided in the envelope
+        if self
+            .core
+            .eval_if(&dc.strip_bcc, self)
+            .await
+            .unwrap_or_else(|| !self.data.authenticated_as.is_empty())
+        {
+            if let Some(stripped_message) = strip_headers(&raw_message, b"Bcc") {
+                tracing::debug!(parent: &self.span,
+                    context = "data",
+                    event = "strip-bcc",
+                    return_path = message.return_path,
+                    "Removed Bcc header from message.");
+                raw_message = Arc::new(stripped_message);
+            }
+        }
+
+        // Remove any X-Spam-Status headers not added by this server
+        if auth_score.is_some() {
+            if let Some(stripped_message) = strip_headers(&raw_message, b"X-Spam-Status") {
+                raw_message = Arc::new(stripped_message);
+            }
+        }
+
+        // DKIM sign, signers that cannot be found are treated as signing errors
+        let mut signers = Vec::new();
+        let mut missing_signer = false;
         for signer in self
             .core
             .eval_if::<Vec<String>, _>(&ac.dkim.sign, self)
             .await
             .unwrap_or_default()
         {
-            if let Some(signer) = self.core.get_dkim_signer(&signer) {
-                match signer.sign_chained(&[headers.as_ref(), &raw_message]) {
-                    Ok(signature) => {
-                        signature.write_header(&mut headers);
-                    }
-                    Err(err) => {
-                        tracing::info!(parent: &self.span,
-                        context = "dkim",
-                        event = "sign-failed",
-                        return_path = message.return_path,
-                        "Failed to sign message: {}", err);
+            if let Some(dkim_signer) = self.core.get_dkim_signer(&signer) {
+                signers.push((signer, dkim_signer));
+            } else {
+                missing_signer = true;
+            }
+        }
+        if missing_signer
+            && self
+                .core
+                .eval_if(&ac.dkim.on_sign_error, self)
+                .await
+                .unwrap_or_default()
+                == DkimSignFailure::Defer
+        {
+            return self
+                .defer_response("451 4.3.0", "", "Unable to sign message.")
+                .into();
+        }
+        if self
+            .core
+            .eval_if(&ac.dkim.sign_by_domain, self)
+            .await
+            .unwrap_or(false)
+        {
+            if let Some(dkim_signer) = self.core.shared.domain_signers.get(&sign_domain) {
+                if !signers
+                    .iter()
+                    .any(|(_, signer)| std::ptr::eq(*signer, dkim_signer))
+                {
+                    signers.push((sign_domain, dkim_signer));
+                }
+            }
+        }
+        for (signer, dkim_signer) in signers {
+            match dkim_signer.sign_chained(&[headers.as_ref(), &raw_message]) {
+                Ok(signature) => {
+                    signature.write_header(&mut headers);
+                }
+                Err(err) => {
+                    tracing::warn!(parent: &self.span,
+                    context = "dkim",
+                    event = "sign-failed",
+                    signature = signer,
+                    domain = message.return_path_domain,
+                    return_path = message.return_path,
+                    "Failed to sign message: {}", err);
+
+                    if self
+                        .core
+                        .eval_if(&ac.dkim.on_sign_error, self)
+                        .await
+                        .unwrap_or_default()
+                        == DkimSignFailure::Defer
+                    {
+                        return self
+                            .defer_response("451 4.3.0", "", "Unable to sign message.")
+                            .into();
                     }
                 }
             }
         }
 
+        // Sender Rewriting Scheme
+        self.srs_forward(&mut message).await;
+
         // Update size
         message.size = raw_message.len() + headers.len();
 
+        // Enforce the authenticated principal's sending rate
+        if let Some(rate) = &self.params.send_rate {
+            if !self
+                .throttle_rcpt(&self.data.authenticated_as, rate, "send-rate")
+                .await
+            {
+                tracing::debug!(parent: &self.span,
+                    context = "data",
+                    event = "rate-limit-exceeded",
+                    authenticated_as = &self.data.authenticated_as,
+                    "Sending rate exceeded for authenticated user.");
+
+                return self
+                    .defer_response("452 4.4.5", "", "Sending rate exceeded.")
+                    .into();
+            }
+        }
+
+        // Relay submissions to the backend server when proxying
+        if let Some(directory) = self
+            .core
+            .eval_if::<String, _>(&dc.proxy_directory, self)
+            .await
+            .and_then(|name| self.core.get_directory(&name))
+            .cloned()
+        {
+            let envelope = RelayEnvelope {
+                return_path: &message.return_path,
+                flags: message.flags,
+                env_id: message.env_id.as_deref(),
+                authenticated_as: Some(self.data.authenticated_as.as_str())
+                    .filter(|user| !user.is_empty()),
+                recipients: message
+                    .recipients
+                    .iter()
+                    .map(|rcpt| RelayRecipient {
+                        address: &rcpt.address,
+                        flags: rcpt.flags,
+                        orcpt: rcpt.orcpt.as_deref(),
+                    })
+                    .collect(),
+            };
+            let mut contents = Vec::with_capacity(message.size);
+            contents.extend_from_slice(&headers);
+            contents.extend_from_slice(&raw_message);
+
+            return match directory.relay(&envelope, &contents).await {
+                Ok(responses) => {
+                    let num_accepted = responses
+                        .iter()
+                        .filter(|response| response.severity() == Severity::PositiveCompletion)
+                        .count();
+                    // SMTP clients get a single reply, which is the first failure
+                    // when the backend did not accept every recipient
+                    let reply_idx = responses
+                        .iter()
+                        .position(|response| response.severity() != Severity::PositiveCompletion)
+                        .unwrap_or(0);
+                    tracing::info!(parent: &self.span,
+                        context = "data",
+                        event = "proxy",
+                        return_path = message.return_path,
+                        nrcpts = responses.len(),
+                        accepted = num_accepted,
+                        size = message.size,
+                        "Message relayed to backend server.");
+
+                    if num_accepted > 0 {
+                        self.data.messages_sent += 1;
+                    }
+                    let replies = message
+                        .recipients
+                        .iter()
+                        .zip(responses)
+                        .map(|(rcpt, response)| {
+                            let [class, subject, detail] = response.esc;
+                            let status = if class > 0 {
+                                format!("{} {class}.{subject}.{detail}", response.code)
+                            } else {
+                                response.code.to_string()
+                            };
+                            (
+                                rcpt.address_lcase.clone(),
+                                build_response(&status, &response.message).into_bytes(),
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    let reply = replies
+                        .get(reply_idx)
+                        .map(|(_, reply)| reply.clone())
+                        .unwrap_or_default();
+                    if self.instance.protocol == ServerProtocol::Lmtp {
+                        self.data.rcpt_replies = replies;
+                    }
+                    reply.into()
+                }
+                Err(err) => {
+                    tracing::warn!(parent: &self.span,
+                        context = "data",
+                        event = "proxy-error",
+                        return_path = message.return_path,
+                        reason = ?err,
+                        "Failed to relay message to backend server.");
+
+                    (b"451 4.4.0 Unable to relay message at this time.\r\n"[..]).into()
+                }
+            };
+        }
+
         // Verify queue quota
         if self.core.has_quota(&mut message).await {
             let queue_id = message.id;
@@ -658,6 +1067,22 @@ impl<T: SessionStream> Session<T> {
                 .queue(Some(&headers), &raw_message, &self.core, &self.span)
                 .await
             {
+                if let Some((keys, window)) = duplicates {
+                    for key in keys {
+                        if let Err(err) = self
+                            .core
+                            .shared
+                            .default_lookup_store
+                            .key_set(key, vec![], window.into())
+                            .await
+                        {
+                            tracing::warn!(parent: &self.span,
+                                context = "data",
+                                event = "error",
+                                "Failed to store Message-ID: {}", err);
+                        }
+                    }
+                }
                 self.state = State::Accepted(queue_id);
                 self.data.messages_sent += 1;
                 (b"250 2.0.0 Message queued for delivery.\r\n"[..]).into()
@@ -676,6 +1101,54 @@ impl<T: SessionStream> Session<T> {
         }
     }
 
+    async fn filter_duplicates(
+        &self,
+        raw_message: &[u8],
+        rcpt_to: &mut Vec<SessionAddress>,
+    ) -> Option<(Vec<Vec<u8>>, u64)> {
+        let dc = &self.core.session.config.data;
+        let window = self
+            .core
+            .eval_if::<Duration, _>(&dc.duplicate_window, self)
+            .await?
+            .as_secs();
+        let message_id = MessageParser::new()
+            .parse_headers(raw_message)?
+            .message_id()?
+            .to_string();
+        let store = &self.core.shared.default_lookup_store;
+        let mut keys = Vec::new();
+
+        match self
+            .core
+            .eval_if(&dc.duplicate_scope, self)
+            .await
+            .unwrap_or_default()
+        {
+            DuplicateScope::Global => {
+                let key = format!("dup:{message_id}").into_bytes();
+                if store.key_exists(key.clone()).await.unwrap_or(false) {
+                    rcpt_to.clear();
+                } else {
+                    keys.push(key);
+                }
+            }
+            DuplicateScope::Recipient => {
+                let mut unique_rcpts = Vec::with_capacity(rcpt_to.len());
+                for rcpt in rcpt_to.drain(..) {
+                    let key = format!("dup:{}:{message_id}", rcpt.address_lcase).into_bytes();
+                    if !store.key_exists(key.clone()).await.unwrap_or(false) {
+                        keys.push(key);
+                        unique_rcpts.push(rcpt);
+                    }
+                }
+                *rcpt_to = unique_rcpts;
+            }
+        }
+
+        Some((keys, window))
+    }
+
     pub async fn build_message(
         &self,
         mail_from: SessionAddress,
@@ -697,6 +1170,7 @@ impl<T: SessionStream> Session<T> {
             priority: self.data.priority,
             size: 0,
             env_id: mail_from.dsn_info,
+            auth: self.data.mail_from_auth.clone(),
             blob_hash: Default::default(),
             quota_keys: Vec::new(),
         };
@@ -805,49 +1279,114 @@ impl<T: SessionStream> Session<T> {
     }
 
     pub async fn can_send_data(&mut self) -> Result<bool, ()> {
+        if let Some(response) = self.check_send_data().await {
+            self.write(response).await?;
+            Ok(false)
+        } else {
+            Ok(true)
+        }
+    }
+
+    // Returns the response to send when a message transfer cannot start
+    pub async fn check_send_data(&mut self) -> Option<&'static [u8]> {
         if !self.data.rcpt_to.is_empty() {
             if self.data.messages_sent
-                < self
+                >= self
                     .core
                     .eval_if(&self.core.session.config.data.max_messages, self)
                     .await
                     .unwrap_or(10)
             {
-                Ok(true)
-            } else {
                 tracing::debug!(
                     parent: &self.span,
                     context = "data",
                     event = "too-many-messages",
                     "Maximum number of messages per session exceeded."
                 );
-                self.write(b"451 4.4.5 Maximum number of messages per session exceeded.\r\n")
-                    .await?;
-                Ok(false)
+                Some(b"451 4.4.5 Maximum number of messages per session exceeded.\r\n".as_slice())
+            } else if self.data.data_in_flight.is_some() {
+                None
+            } else if self
+                .core
+                .session
+                .config
+                .data
+                .backpressure
+                .as_ref()
+                .map_or(false, |backpressure| backpressure.is_active())
+            {
+                // Defer the transfer while the storage backend is struggling
+                tracing::debug!(
+                    parent: &self.span,
+                    context = "data",
+                    event = "backpressure",
+                    "Storage backend is under backpressure."
+                );
+                Some(b"451 4.3.2 Storage is busy, try again later.\r\n".as_slice())
+            } else if let Some(limiter) = &self.core.session.config.data.max_concurrent {
+                // Defer the transfer while the server-wide limit is reached
+                if let Some(in_flight) = limiter.is_allowed() {
+                    self.data.data_in_flight = Some(in_flight);
+                    None
+                } else {
+                    tracing::debug!(
+                        parent: &self.span,
+                        context = "data",
+                        event = "concurrency-exceeded",
+                        max_concurrent = limiter.max_concurrent,
+                        "Too many concurrent message transfers."
+                    );
+                    Some(
+                        b"451 4.3.2 Too many concurrent transfers, try again later.\r\n".as_slice(),
+                    )
+                }
+            } else {
+                None
             }
         } else {
-            self.write(b"503 5.5.1 RCPT is required first.\r\n").await?;
-            Ok(false)
+            Some(b"503 5.5.1 RCPT is required first.\r\n".as_slice())
         }
     }
 
-    fn write_received(&self, headers: &mut Vec<u8>, id: u64) {
+    async fn write_received(&self, headers: &mut Vec<u8>, id: u64) {
+        let dc = &self.core.session.config.data;
+        let include_ip = self
+            .core
+            .eval_if(&dc.received_ip, self)
+            .await
+            .unwrap_or(true);
+        let include_tls = self
+            .core
+            .eval_if(&dc.received_tls, self)
+            .await
+            .unwrap_or(true);
+        let include_protocol = self
+            .core
+            .eval_if(&dc.received_protocol, self)
+            .await
+            .unwrap_or(true);
+
         headers.extend_from_slice(b"Received: from ");
         headers.extend_from_slice(self.data.helo_domain.as_bytes());
-        headers.extend_from_slice(b" (");
-        headers.extend_from_slice(
-            self.data
-                .iprev
-                .as_ref()
-                .and_then(|ir| ir.ptr.as_ref())
-                .and_then(|ptr| ptr.first().map(|s| s.strip_suffix('.').unwrap_or(s)))
-                .unwrap_or("unknown")
-                .as_bytes(),
-        );
-        headers.extend_from_slice(b" [");
-        headers.extend_from_slice(self.data.remote_ip.to_string().as_bytes());
-        headers.extend_from_slice(b"])\r\n\t");
-        if self.stream.is_tls() {
+        if include_ip {
+            // RFC 5321 requires TCP-info to contain an address literal, so the
+            // reverse hostname is only written alongside the remote IP.
+            headers.extend_from_slice(b" (");
+            headers.extend_from_slice(
+                self.data
+                    .iprev
+                    .as_ref()
+                    .and_then(|ir| ir.ptr.as_ref())
+                    .and_then(|ptr| ptr.first().map(|s| s.strip_suffix('.').unwrap_or(s)))
+                    .unwrap_or("unknown")
+                    .as_bytes(),
+            );
+            headers.extend_from_slice(b" [");
+            headers.extend_from_slice(self.data.remote_ip.to_string().as_bytes());
+            headers.extend_from_slice(b"])");
+        }
+        headers.extend_from_slice(b"\r\n\t");
+        if include_tls && self.stream.is_tls() {
             let (version, cipher) = self.stream.tls_version_and_cipher();
             headers.extend_from_slice(b"(using ");
             headers.extend_from_slice(version.as_bytes());
@@ -857,19 +1396,223 @@ impl<T: SessionStream> Session<T> {
         }
         headers.extend_from_slice(b"by ");
         headers.extend_from_slice(self.instance.hostname.as_bytes());
-        headers.extend_from_slice(b" (Stalwart SMTP) with ");
-        headers.extend_from_slice(
-            match (self.stream.is_tls(), self.data.authenticated_as.is_empty()) {
-                (true, true) => b"ESMTPS",
-                (true, false) => b"ESMTPSA",
-                (false, true) => b"ESMTP",
-                (false, false) => b"ESMTPA",
-            },
-        );
+        headers.extend_from_slice(b" (Stalwart SMTP)");
+        if include_protocol {
+            headers.extend_from_slice(b" with ");
+            headers.extend_from_slice(
+                match (self.stream.is_tls(), self.data.authenticated_as.is_empty()) {
+                    (true, true) => b"ESMTPS",
+                    (true, false) => b"ESMTPSA",
+                    (false, true) => b"ESMTP",
+                    (false, false) => b"ESMTPA",
+                },
+            );
+        }
         headers.extend_from_slice(b" id ");
         headers.extend_from_slice(format!("{id:X}").as_bytes());
         headers.extend_from_slice(b";\r\n\t");
         headers.extend_from_slice(Date::now().to_rfc822().as_bytes());
         headers.extend_from_slice(b"\r\n");
     }
+
+    fn rcpt_addresses(&self) -> String {
+        self.data
+            .rcpt_to
+            .iter()
+            .map(|rcpt| rcpt.address.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+// Rewrites header values containing raw 8-bit bytes as RFC 2047 encoded-words.
+// Consecutive 8-bit words are encoded together so that spacing is preserved.
+fn encode_8bit_headers(raw_message: &[u8]) -> Option<Vec<u8>> {
+    let message = AuthenticatedMessage::parse(raw_message)?;
+    let mut output = Vec::with_capacity(raw_message.len() + 64);
+    let mut offset = 0;
+
+    for (name, value) in message.raw_parsed_headers() {
+        output.extend_from_slice(name);
+        if value.is_empty() {
+            offset += name.len();
+            continue;
+        }
+        offset += name.len() + value.len() + 1;
+        output.push(b':');
+
+        if value.is_ascii() {
+            output.extend_from_slice(value);
+            continue;
+        }
+
+        // Encoded-words are not allowed in addresses or quoted strings, so
+        // only unstructured headers and display names are encoded. Returns
+        // None when any other structured header contains 8-bit characters.
+        if is_unstructured_header(name) {
+            let value = String::from_utf8_lossy(value).replace(['\r', '\n'], "");
+            let mut words = value.split_whitespace().peekable();
+            while let Some(word) = words.next() {
+                output.push(b' ');
+                if word.is_ascii() {
+                    output.extend_from_slice(word.as_bytes());
+                    continue;
+                }
+
+                let mut text = word.to_string();
+                while let Some(word) = words.next_if(|word| !word.is_ascii()) {
+                    text.push(' ');
+                    text.push_str(word);
+                }
+                encode_words(&mut output, &text)?;
+            }
+        } else if is_address_header(name) {
+            encode_address_list(&mut output, std::str::from_utf8(value).ok()?.trim_end())?;
+        } else {
+            return None;
+        }
+        output.extend_from_slice(b"\r\n");
+    }
+
+    output.extend_from_slice(raw_message.get(offset..)?);
+    Some(output)
+}
+
+// Encodes the display names of an address list, the addresses themselves
+// must be ASCII. Groups and comments are not supported.
+fn encode_address_list(output: &mut Vec<u8>, value: &str) -> Option<()> {
+    let mut mailboxes = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut in_angle = false;
+    let mut is_escaped = false;
+    for (pos, ch) in value.char_indices() {
+        if is_escaped {
+            is_escaped = false;
+            continue;
+        }
+        match ch {
+            '\\' if in_quotes => is_escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '<' if !in_quotes => in_angle = true,
+            '>' if !in_quotes => in_angle = false,
+            ',' if !in_quotes && !in_angle => {
+                mailboxes.push(&value[start..pos]);
+                start = pos + 1;
+            }
+            _ => (),
+        }
+    }
+    mailboxes.push(&value[start..]);
+
+    for (pos, mailbox) in mailboxes.into_iter().enumerate() {
+        if pos > 0 {
+            output.push(b',');
+        }
+        if mailbox.is_ascii() {
+            output.extend_from_slice(mailbox.as_bytes());
+            continue;
+        }
+
+        let (phrase, address) = mailbox.rsplit_once('<')?;
+        let address = address.trim_end();
+        if !address.is_ascii() || !address.ends_with('>') {
+            return None;
+        }
+
+        let mut name = String::with_capacity(phrase.len());
+        let mut in_quotes = false;
+        let mut chars = phrase.chars();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '"' => in_quotes = !in_quotes,
+                '\\' if in_quotes => name.push(chars.next()?),
+                '(' | ':' | ';' if !in_quotes => return None,
+                _ => name.push(ch),
+            }
+        }
+
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        output.push(b' ');
+        encode_words(output, &name)?;
+        output.extend_from_slice(b" <");
+        output.extend_from_slice(address.as_bytes());
+    }
+
+    Some(())
+}
+
+fn encode_words(output: &mut Vec<u8>, text: &str) -> Option<()> {
+    // Keep each encoded-word within the 75 character limit
+    let mut chars = text.chars().peekable();
+    let mut chunk = String::with_capacity(45);
+    while chars.peek().is_some() {
+        chunk.clear();
+        while let Some(ch) = chars.next_if(|ch| chunk.len() + ch.len_utf8() <= 45) {
+            chunk.push(ch);
+        }
+        if output.ends_with(b"?=") {
+            output.extend_from_slice(b"\r\n ");
+        }
+        output.extend_from_slice(b"=?utf-8?B?");
+        output.extend_from_slice(&base64_encode(chunk.as_bytes()).ok()?);
+        output.extend_from_slice(b"?=");
+    }
+
+    Some(())
+}
+
+fn is_unstructured_header(name: &[u8]) -> bool {
+    let name = name.trim_ascii();
+    [b"Subject".as_slice(), b"Comments", b"Content-Description"]
+        .iter()
+        .any(|header| name.eq_ignore_ascii_case(header))
+}
+
+fn is_address_header(name: &[u8]) -> bool {
+    let name = name.trim_ascii();
+    [
+        b"From".as_slice(),
+        b"Sender",
+        b"Reply-To",
+        b"To",
+        b"Cc",
+        b"Bcc",
+        b"Resent-From",
+        b"Resent-Sender",
+        b"Resent-To",
+        b"Resent-Cc",
+        b"Resent-Bcc",
+    ]
+    .iter()
+    .any(|header| name.eq_ignore_ascii_case(header))
+}
+
+fn strip_headers(raw_message: &[u8], header_name: &[u8]) -> Option<Vec<u8>> {
+    let message = AuthenticatedMessage::parse(raw_message)?;
+    let mut output = Vec::with_capacity(raw_message.len());
+    let mut offset = 0;
+    let mut has_header = false;
+
+    for (name, value) in message.raw_parsed_headers() {
+        let len = if value.is_empty() {
+            name.len()
+        } else {
+            name.len() + value.len() + 1
+        };
+        let header = raw_message.get(offset..offset + len)?;
+        offset += len;
+        if !value.is_empty() && name.trim_ascii().eq_ignore_ascii_case(header_name) {
+            has_header = true;
+        } else {
+            output.extend_from_slice(header);
+        }
+    }
+
+    if has_header {
+        output.extend_from_slice(raw_message.get(offset..)?);
+        Some(output)
+    } else {
+        None
+    }
 }
@@ -0,0 +1,134 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{fmt::Write, net::IpAddr, time::Instant};
+
+use mail_auth::common::lru::DnsCache;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    config::{DnsblAction, DnsblType},
+    core::{Session, SessionData},
+};
+
+impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
+    /// Queries the configured blocklists of the given type, returns `false` and
+    /// sets `dnsbl_error` when the session has to be rejected. Listings are
+    /// cached by the DNS resolver, non-listed answers for `negative-ttl` and
+    /// lookup failures are neither cached nor treated as listings.
+    /// Client host scores last for the connection, sender domain scores only for
+    /// the current transaction.
+    pub async fn is_dnsbl_allowed(&mut self, typ: DnsblType, name: &str) -> bool {
+        let dnsbl = &self.core.session.config.dnsbl;
+        if name.is_empty() || self.is_trusted() {
+            return true;
+        }
+
+        for list in dnsbl.lists.iter().filter(|list| list.typ == typ) {
+            let query = match typ {
+                DnsblType::Ip => match name.parse::<IpAddr>() {
+                    Ok(ip) => format!("{}.{}", reverse_ip(&ip), list.zone),
+                    Err(_) => return true,
+                },
+                DnsblType::Domain => format!("{}.{}", name.trim_end_matches('.'), list.zone),
+            };
+
+            // Listings are returned as addresses in 127.0.0.0/8
+            let cache = &self.core.resolvers.cache.dnsbl;
+            if cache.get(&query).is_some() {
+                continue;
+            }
+            let is_listed = match self.core.resolvers.dns.ipv4_lookup(&query).await {
+                Ok(ips) => ips.iter().any(|ip| ip.octets()[0] == 127),
+                Err(mail_auth::Error::DnsRecordNotFound(_)) => false,
+                Err(_) => continue,
+            };
+            if !is_listed {
+                if !dnsbl.negative_ttl.is_zero() {
+                    cache.insert(query, (), Instant::now() + dnsbl.negative_ttl);
+                }
+                continue;
+            }
+
+            tracing::debug!(parent: &self.span,
+                context = "dnsbl",
+                event = "listed",
+                list = &list.id,
+                zone = &list.zone,
+                name = name);
+
+            let is_rejected = match list.action {
+                DnsblAction::Reject => true,
+                DnsblAction::Score(score) => {
+                    match typ {
+                        DnsblType::Ip => self.data.dnsbl_score += score,
+                        DnsblType::Domain => self.data.dnsbl_domain_score += score,
+                    }
+                    dnsbl.max_score.map_or(false, |max_score| {
+                        self.data.dnsbl_total_score() >= max_score
+                    })
+                }
+            };
+            if is_rejected {
+                let what = match typ {
+                    DnsblType::Ip => "Client host",
+                    DnsblType::Domain => "Sender domain",
+                };
+                self.data.dnsbl_error = format!(
+                    "554 5.7.1 Service unavailable; {what} [{name}] blocked using {}.\r\n",
+                    list.zone
+                )
+                .into_bytes()
+                .into();
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl SessionData {
+    pub fn dnsbl_total_score(&self) -> u32 {
+        self.dnsbl_score + self.dnsbl_domain_score
+    }
+}
+
+fn reverse_ip(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let o = ip.octets();
+            format!("{}.{}.{}.{}", o[3], o[2], o[1], o[0])
+        }
+        IpAddr::V6(ip) => {
+            let mut result = String::with_capacity(63);
+            for byte in ip.octets().iter().rev() {
+                if !result.is_empty() {
+                    result.push('.');
+                }
+                let _ = write!(result, "{:x}.{:x}", byte & 0x0f, byte >> 4);
+            }
+            result
+        }
+    }
+}
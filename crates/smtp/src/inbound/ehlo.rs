@@ -47,7 +47,24 @@ impl<T: SessionStream> Session<T> {
 
             // SPF check
             let prev_helo_domain = std::mem::replace(&mut self.data.helo_domain, domain);
-            if self.params.spf_ehlo.verify() {
+            if self.params.spf_ehlo.verify()
+                && !self
+                    .is_spf_within_limits(
+                        &self.data.helo_domain,
+                        &format!("postmaster@{}", self.data.helo_domain),
+                    )
+                    .await
+            {
+                // Records needing too many DNS lookups are not evaluated
+                if !self
+                    .handle_spf_lookup_limit(self.params.spf_ehlo.is_strict())
+                    .await?
+                {
+                    self.data.mail_from = None;
+                    self.data.helo_domain = prev_helo_domain;
+                    return Ok(());
+                }
+            } else if self.params.spf_ehlo.verify() {
                 let spf_output = self
                     .core
                     .resolvers
@@ -114,6 +131,7 @@ impl<T: SessionStream> Session<T> {
         if self.data.mail_from.is_some() {
             self.reset();
         }
+        self.data.helo_only = !is_extended;
 
         if !is_extended {
             return self
@@ -122,8 +140,10 @@ impl<T: SessionStream> Session<T> {
         }
 
         let mut response = EhloResponse::new(self.instance.hostname.as_str());
-        response.capabilities =
-            EXT_ENHANCED_STATUS_CODES | EXT_8BIT_MIME | EXT_BINARY_MIME | EXT_SMTP_UTF8;
+        response.capabilities = EXT_8BIT_MIME | EXT_BINARY_MIME | EXT_SMTP_UTF8;
+        if !self.params.omit_enhanced_status_codes {
+            response.capabilities |= EXT_ENHANCED_STATUS_CODES;
+        }
         if !self.stream.is_tls() {
             response.capabilities |= EXT_START_TLS;
         }
@@ -156,12 +176,18 @@ impl<T: SessionStream> Session<T> {
             response.capabilities |= EXT_VRFY;
         }
 
-        // Require TLS
-        if self
-            .core
-            .eval_if(&ec.requiretls, self)
-            .await
-            .unwrap_or(true)
+        // Remote Queue Starting
+        if self.is_trusted() || self.core.eval_if(&ec.etrn, self).await.unwrap_or(false) {
+            response.capabilities |= EXT_ETRN;
+        }
+
+        // Require TLS (only offered on TLS connections)
+        if self.stream.is_tls()
+            && self
+                .core
+                .eval_if(&ec.requiretls, self)
+                .await
+                .unwrap_or(true)
         {
             response.capabilities |= EXT_REQUIRE_TLS;
         }
@@ -172,6 +198,7 @@ impl<T: SessionStream> Session<T> {
         }
 
         // Authentication
+        let mut custom_mechanisms = Vec::new();
         if self.data.authenticated_as.is_empty() {
             response.auth_mechanisms = self
                 .core
@@ -180,13 +207,25 @@ impl<T: SessionStream> Session<T> {
                 .unwrap_or_default()
                 .into();
             if response.auth_mechanisms != 0 {
-                if !self.stream.is_tls() && !self.params.auth_plain_text {
-                    response.auth_mechanisms &= !(AUTH_PLAIN | AUTH_LOGIN);
-                }
+                response.auth_mechanisms &= self
+                    .core
+                    .session
+                    .config
+                    .auth
+                    .sasl
+                    .ids(self.stream.is_tls() || self.params.auth_plain_text);
                 if response.auth_mechanisms != 0 {
                     response.capabilities |= EXT_AUTH;
                 }
             }
+            custom_mechanisms = self.core.session.config.auth.sasl.names(
+                &self
+                    .core
+                    .eval_if::<Vec<String>, _>(&ac.custom_mechanisms, self)
+                    .await
+                    .unwrap_or_default(),
+                self.stream.is_tls() || self.params.auth_plain_text,
+            );
         }
 
         // Future release
@@ -220,14 +259,22 @@ impl<T: SessionStream> Session<T> {
             response.mt_priority = value;
         }
 
-        // Size
-        response.size = self
-            .core
-            .eval_if(&dc.max_message_size, self)
-            .await
-            .unwrap_or(25 * 1024 * 1024);
-        if response.size > 0 {
-            response.capabilities |= EXT_SIZE;
+        // Size, advertised as the maximum message size unless overridden
+        if self.core.eval_if(&ec.size, self).await.unwrap_or(true) {
+            if let Some(size) = self.core.eval_if(&ec.size_value, self).await {
+                response.capabilities |= EXT_SIZE;
+                response.size = size;
+            } else {
+                response.size = self
+                    .core
+                    .eval_if(&dc.max_message_size, self)
+                    .await
+                    .unwrap_or(25 * 1024 * 1024)
+                    .max(self.max_domain_message_size());
+                if response.size > 0 {
+                    response.capabilities |= EXT_SIZE;
+                }
+            }
         }
 
         // No soliciting
@@ -247,6 +294,43 @@ impl<T: SessionStream> Session<T> {
         // Generate response
         let mut buf = Vec::with_capacity(64);
         response.write(&mut buf).ok();
+        if !custom_mechanisms.is_empty() {
+            add_auth_mechanisms(&mut buf, &custom_mechanisms);
+        }
         self.write(&buf).await
     }
 }
+
+// Mechanisms registered by name have no identifier in `smtp_proto`, so they
+// are appended to the AUTH capability line, which is added if missing.
+fn add_auth_mechanisms(buf: &mut Vec<u8>, names: &[&str]) {
+    let names = names.join(" ");
+    let mut line_start = 0;
+    while line_start < buf.len() {
+        let line_end = buf[line_start..]
+            .iter()
+            .position(|&ch| ch == b'\n')
+            .map_or(buf.len(), |pos| line_start + pos + 1);
+        if buf
+            .get(line_start + 4..line_start + 9)
+            .map_or(false, |capability| {
+                capability.eq_ignore_ascii_case(b"AUTH ")
+            })
+        {
+            let pos = line_end - 2;
+            buf.splice(pos..pos, format!(" {names}").into_bytes());
+            return;
+        }
+        line_start = line_end;
+    }
+
+    // Advertise right after the greeting line
+    if let Some(pos) = buf.iter().position(|&ch| ch == b'\n') {
+        let is_last = pos + 1 == buf.len();
+        buf[3] = b'-';
+        buf.splice(
+            pos + 1..pos + 1,
+            format!("250{}AUTH {names}\r\n", if is_last { ' ' } else { '-' }).into_bytes(),
+        );
+    }
+}
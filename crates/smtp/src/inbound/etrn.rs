@@ -0,0 +1,102 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::core::Session;
+
+impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
+    pub async fn handle_etrn(&mut self, name: String) -> Result<(), ()> {
+        if !self.can_use_extensions() {
+            return self.write(b"502 5.5.1 Command not implemented.\r\n").await;
+        } else if !self.params.can_etrn && !self.is_trusted() {
+            tracing::debug!(parent: &self.span,
+                context = "etrn",
+                event = "forbidden",
+                node = &name);
+
+            return self
+                .write(format!("459 4.7.1 Node {name} not allowed.\r\n").as_bytes())
+                .await;
+        }
+
+        // RFC 1985: '#' requests a named queue, '@' includes subdomains
+        if name.starts_with('#') {
+            return self
+                .write(
+                    format!("458 4.5.0 Unable to queue messages for node {name}.\r\n").as_bytes(),
+                )
+                .await;
+        }
+        let (domain, include_subdomains) = if let Some(domain) = name.strip_prefix('@') {
+            (domain.to_lowercase(), true)
+        } else {
+            (name.to_lowercase(), false)
+        };
+        if domain.is_empty() {
+            return self.write(b"501 5.5.4 Invalid node name.\r\n").await;
+        }
+
+        // Only trusted clients may release domains that are not configured for ETRN
+        if !self.is_trusted()
+            && !self
+                .core
+                .session
+                .config
+                .extensions
+                .etrn_domains
+                .iter()
+                .any(|etrn_domain| {
+                    domain == *etrn_domain
+                        || domain
+                            .strip_suffix(etrn_domain.as_str())
+                            .map_or(false, |prefix| prefix.ends_with('.'))
+                })
+        {
+            tracing::debug!(parent: &self.span,
+                context = "etrn",
+                event = "forbidden",
+                node = &name);
+
+            return self
+                .write(format!("459 4.7.1 Node {name} not allowed.\r\n").as_bytes())
+                .await;
+        }
+
+        let released = self.core.release_domain(&domain, include_subdomains).await;
+
+        tracing::debug!(parent: &self.span,
+            context = "etrn",
+            event = "release",
+            node = &name,
+            messages = released);
+
+        if released > 0 {
+            self.write(format!("250 2.0.0 Queuing for node {name} started.\r\n").as_bytes())
+                .await
+        } else {
+            self.write(format!("251 2.0.0 No messages waiting for node {name}.\r\n").as_bytes())
+                .await
+        }
+    }
+}
@@ -23,18 +23,62 @@
 
 use std::time::{Duration, SystemTime};
 
+use directory::{QueryBy, Type};
 use mail_auth::{IprevOutput, IprevResult, SpfOutput, SpfResult};
-use smtp_proto::{MailFrom, MtPriority, MAIL_BY_NOTIFY, MAIL_BY_RETURN, MAIL_REQUIRETLS};
+use smtp_proto::{
+    MailFrom, MtPriority, MAIL_BY_NOTIFY, MAIL_BY_RETURN, MAIL_REQUIRETLS, MAIL_RET_FULL,
+    MAIL_RET_HDRS,
+};
 use utils::{config::Rate, listener::SessionStream};
 
 use crate::{
+    config::{AddressLiteral, CommandLeniency, DnsblType, SpfCheck},
     core::{Session, SessionAddress},
     queue::DomainPart,
     scripts::{ScriptModification, ScriptResult},
 };
 
 impl<T: SessionStream> Session<T> {
+    pub async fn handle_mail_from_literal(&mut self, from: MailFrom<String>) -> Result<(), ()> {
+        if self
+            .core
+            .eval_if(&self.core.session.config.mail.address_literals, self)
+            .await
+            == Some(AddressLiteral::Allow)
+        {
+            self.handle_mail_from(from).await
+        } else {
+            tracing::debug!(parent: &self.span,
+                context = "mail-from",
+                event = "error",
+                address = &from.address,
+                "Address literal not accepted.");
+
+            self.write(b"501 5.1.7 Address literals are not accepted.\r\n")
+                .await
+        }
+    }
+
     pub async fn handle_mail_from(&mut self, from: MailFrom<String>) -> Result<(), ()> {
+        if self.data.mail_from.is_some() {
+            if self
+                .core
+                .eval_if(&self.core.session.config.mail.multiple_commands, self)
+                .await
+                .unwrap_or(CommandLeniency::Strict)
+                == CommandLeniency::Strict
+            {
+                return self.write(b"503 5.5.1 Sender already specified.\r\n").await;
+            }
+
+            // Lenient clients may start a new transaction without sending RSET first
+            tracing::debug!(parent: &self.span,
+                context = "mail-from",
+                event = "reset",
+                "Transaction reset by a new MAIL command.");
+            self.reset();
+        }
+
         if self.data.helo_domain.is_empty()
             && (self.params.ehlo_require
                 || self.params.spf_ehlo.verify()
@@ -43,11 +87,10 @@ impl<T: SessionStream> Session<T> {
             return self
                 .write(b"503 5.5.1 Polite people say EHLO first.\r\n")
                 .await;
-        } else if self.data.mail_from.is_some() {
-            return self
-                .write(b"503 5.5.1 Multiple MAIL commands not allowed.\r\n")
-                .await;
-        } else if self.params.auth_require && self.data.authenticated_as.is_empty() {
+        } else if self.params.auth_require
+            && self.data.authenticated_as.is_empty()
+            && !self.is_trusted()
+        {
             return self
                 .write(b"503 5.5.1 You must authenticate first.\r\n")
                 .await;
@@ -86,34 +129,41 @@ impl<T: SessionStream> Session<T> {
                     ..
                 })
             ) {
-                &b"451 4.7.25 Temporary error validating reverse DNS.\r\n"[..]
+                b"451 4.7.25 Temporary error validating reverse DNS.\r\n".to_vec()
             } else {
-                &b"550 5.7.25 Reverse DNS validation failed.\r\n"[..]
+                match &self.core.session.config.reject.reverse_dns {
+                    Some(template) => template.render(
+                        "550 5.7.25",
+                        &from.address,
+                        "",
+                        "Reverse DNS validation failed.",
+                    ),
+                    None => b"550 5.7.25 Reverse DNS validation failed.\r\n".to_vec(),
+                }
             };
 
-            return self.write(message).await;
+            return self.write(&message).await;
         }
 
         let (address, address_lcase, domain) = if !from.address.is_empty() {
             let address_lcase = from.address.to_lowercase();
-            let domain = address_lcase.domain_part().to_string();
+            let domain = sender_domain(&address_lcase);
             (from.address, address_lcase, domain)
         } else {
             (String::new(), String::new(), String::new())
         };
 
-        // Make sure that the authenticated user is allowed to send from this address
-        if !self.data.authenticated_as.is_empty()
-            && self.params.auth_match_sender
-            && (self.data.authenticated_as != address_lcase
-                && !self.data.authenticated_emails.contains(&address_lcase))
-        {
-            return self
-                .write(b"501 5.5.4 You are not allowed to send from this address.\r\n")
-                .await;
-        }
+        // RFC 4954 section 5: the AUTH identity is only kept when asserted by
+        // an authenticated or trusted client, otherwise it is replaced by <>
+        self.data.mail_from_auth = from.auth.map(|auth| {
+            if !self.data.authenticated_as.is_empty() || self.is_trusted() {
+                auth
+            } else {
+                "<>".to_string()
+            }
+        });
 
-        let has_dsn = from.env_id.is_some();
+        let has_dsn = from.env_id.is_some() || (from.flags & (MAIL_RET_FULL | MAIL_RET_HDRS)) != 0;
         self.data.mail_from = SessionAddress {
             address,
             address_lcase,
@@ -123,6 +173,28 @@ impl<T: SessionStream> Session<T> {
         }
         .into();
 
+        // Make sure that the authenticated user is allowed to send from this address
+        if !self.data.authenticated_as.is_empty()
+            && self.params.auth_match_sender
+            && !self.is_allowed_sender().await
+        {
+            tracing::debug!(parent: &self.span,
+                context = "mail-from",
+                event = "reject",
+                address = &self.data.mail_from.as_ref().unwrap().address,
+                authenticated_as = &self.data.authenticated_as,
+                "Sender address not owned by the authenticated user.");
+
+            let message = self.reject_response(
+                &self.core.session.config.reject.sender_not_allowed,
+                "553 5.7.1",
+                "",
+                "You are not allowed to send from this address.",
+            );
+            self.data.mail_from = None;
+            return self.write(&message).await;
+        }
+
         // Sieve filtering
         if let Some(script) = self
             .core
@@ -170,7 +242,7 @@ impl<T: SessionStream> Session<T> {
             let mail_from = self.data.mail_from.as_mut().unwrap();
             if new_address.contains('@') {
                 mail_from.address_lcase = new_address.to_lowercase();
-                mail_from.domain = mail_from.address_lcase.domain_part().to_string();
+                mail_from.domain = sender_domain(&mail_from.address_lcase);
                 mail_from.address = new_address;
             } else if new_address.is_empty() {
                 mail_from.address_lcase.clear();
@@ -179,20 +251,73 @@ impl<T: SessionStream> Session<T> {
             }
         }
 
+        // Verify that the sender domain exists, the null sender is exempt
+        let domain = &self.data.mail_from.as_ref().unwrap().domain;
+        if !domain.is_empty()
+            && self
+                .core
+                .eval_if(&self.core.session.config.mail.verify_sender_domain, self)
+                .await
+                .unwrap_or(false)
+        {
+            let result = match self.core.resolvers.dns.mx_lookup(domain.as_str()).await {
+                Ok(mxs) if mxs.iter().any(|mx| !mx.exchanges.is_empty()) => Ok(true),
+                Ok(_) | Err(mail_auth::Error::DnsRecordNotFound(_)) => {
+                    self.core.resolvers.dns.exists(domain.as_str()).await
+                }
+                Err(err) => Err(err),
+            };
+
+            match result {
+                Ok(true) => (),
+                Ok(false) => {
+                    tracing::debug!(parent: &self.span,
+                        context = "mail-from",
+                        event = "reject",
+                        domain = domain,
+                        "Sender domain does not exist.");
+
+                    self.data.mail_from = None;
+                    return self
+                        .write(b"550 5.1.8 Sender domain does not exist.\r\n")
+                        .await;
+                }
+                Err(err) => {
+                    tracing::debug!(parent: &self.span,
+                        context = "mail-from",
+                        event = "error",
+                        domain = domain,
+                        reason = %err,
+                        "Failed to verify sender domain.");
+
+                    self.data.mail_from = None;
+                    return self
+                        .write(b"451 4.1.8 Unable to verify sender domain, try again later.\r\n")
+                        .await;
+                }
+            }
+        }
+
         // Validate parameters
         let config = &self.core.session.config.extensions;
         let config_data = &self.core.session.config.data;
-        if (from.flags & MAIL_REQUIRETLS) != 0
-            && !self
+        if (from.flags & MAIL_REQUIRETLS) != 0 {
+            if !self
                 .core
                 .eval_if(&config.requiretls, self)
                 .await
                 .unwrap_or(false)
-        {
-            self.data.mail_from = None;
-            return self
-                .write(b"501 5.5.4 REQUIRETLS has been disabled.\r\n")
-                .await;
+            {
+                self.data.mail_from = None;
+                return self
+                    .write(b"501 5.5.4 REQUIRETLS has been disabled.\r\n")
+                    .await;
+            } else if !self.stream.is_tls() {
+                self.data.mail_from = None;
+                return self
+                    .write(b"530 5.7.10 REQUIRETLS requires a TLS connection.\r\n")
+                    .await;
+            }
         }
         if (from.flags & (MAIL_BY_NOTIFY | MAIL_BY_RETURN)) != 0 {
             if let Some(duration) = self
@@ -250,12 +375,14 @@ impl<T: SessionStream> Session<T> {
                     .eval_if(&config_data.max_message_size, self)
                     .await
                     .unwrap_or(25 * 1024 * 1024)
+                    .max(self.max_domain_message_size())
         {
             self.data.mail_from = None;
             return self
                 .write(b"552 5.3.4 Message too big for system.\r\n")
                 .await;
         }
+        self.data.mail_from_size = from.size;
         if from.hold_for != 0 || from.hold_until != 0 {
             if let Some(max_hold) = self
                 .core
@@ -302,53 +429,94 @@ impl<T: SessionStream> Session<T> {
                 .await;
         }
 
+        // Sender domain blocklist checks, authenticated senders are exempt
+        self.data.dnsbl_domain_score = 0;
+        if self.data.authenticated_as.is_empty() {
+            let domain = self.data.mail_from.as_ref().unwrap().domain.clone();
+            if !self.is_dnsbl_allowed(DnsblType::Domain, &domain).await {
+                self.data.mail_from = None;
+                let message = self.data.dnsbl_error.take().unwrap_or_default();
+                return self.write(&message).await;
+            }
+        }
+
         if self.is_allowed().await {
             // Verify SPF
             if self.params.spf_mail_from.verify() {
                 let mail_from = self.data.mail_from.as_ref().unwrap();
-                let spf_output = if !mail_from.address.is_empty() {
-                    self.core
-                        .resolvers
-                        .dns
-                        .check_host(
-                            self.data.remote_ip,
-                            &mail_from.domain,
-                            &self.data.helo_domain,
-                            &self.instance.hostname,
-                            &mail_from.address_lcase,
-                        )
-                        .await
+                let (domain, sender) = if !mail_from.address.is_empty() {
+                    (mail_from.domain.clone(), mail_from.address_lcase.clone())
+                } else {
+                    (
+                        self.data.helo_domain.clone(),
+                        format!("postmaster@{}", self.data.helo_domain),
+                    )
+                };
+
+                // Records needing too many DNS lookups are not evaluated
+                if !self.is_spf_within_limits(&domain, &sender).await {
+                    if !self
+                        .handle_spf_lookup_limit(self.params.spf_mail_from.is_strict())
+                        .await?
+                    {
+                        self.data.mail_from = None;
+                        return Ok(());
+                    }
                 } else {
-                    self.core
+                    let spf_output = self
+                        .core
                         .resolvers
                         .dns
                         .check_host(
                             self.data.remote_ip,
-                            &self.data.helo_domain,
+                            &domain,
                             &self.data.helo_domain,
                             &self.instance.hostname,
-                            &format!("postmaster@{}", self.data.helo_domain),
+                            &sender,
                         )
-                        .await
-                };
+                        .await;
 
-                tracing::debug!(parent: &self.span,
+                    tracing::debug!(parent: &self.span,
                         context = "spf",
                         event = "lookup",
                         identity = "mail-from",
-                        domain = self.data.helo_domain,
+                        domain = domain,
                         sender = if !mail_from.address.is_empty() {mail_from.address.as_str()} else {"<>"},
                         result = %spf_output.result(),
-                );
+                    );
+
+                    if self
+                        .handle_spf(&spf_output, self.params.spf_mail_from.is_strict())
+                        .await?
+                    {
+                        self.data.spf_mail_from = spf_output.into();
+                    } else {
+                        self.data.mail_from = None;
+                        return Ok(());
+                    }
+                }
 
-                if self
-                    .handle_spf(&spf_output, self.params.spf_mail_from.is_strict())
-                    .await?
+                // When checking both identities, the combined result only passes
+                // if both identities passed, which is enforced when either of them
+                // is verified strictly.
+                if self.params.spf_check == Some(SpfCheck::Both)
+                    && (self.params.spf_ehlo.is_strict() || self.params.spf_mail_from.is_strict())
                 {
-                    self.data.spf_mail_from = spf_output.into();
-                } else {
-                    self.data.mail_from = None;
-                    return Ok(());
+                    if let Some(result) = [&self.data.spf_ehlo, &self.data.spf_mail_from]
+                        .into_iter()
+                        .flatten()
+                        .map(|spf| spf.result())
+                        .find(|result| *result != SpfResult::Pass)
+                    {
+                        let message = if result == SpfResult::TempError {
+                            "451 4.7.24 Temporary SPF validation error.\r\n".to_string()
+                        } else {
+                            format!("550 5.7.23 SPF validation failed, status: {result}.\r\n")
+                        };
+                        self.data.mail_from = None;
+                        self.data.spf_mail_from = None;
+                        return self.write(message.as_bytes()).await;
+                    }
                 }
             }
 
@@ -361,8 +529,18 @@ impl<T: SessionStream> Session<T> {
             self.write(b"250 2.1.0 OK\r\n").await
         } else {
             self.data.mail_from = None;
-            self.write(b"451 4.4.5 Rate limit exceeded, try again later.\r\n")
-                .await
+            let message = self.defer_response("451 4.4.5", "", "Rate limit exceeded.");
+            self.write(&message).await
+        }
+    }
+
+    pub async fn handle_spf_lookup_limit(&mut self, strict: bool) -> Result<bool, ()> {
+        if strict {
+            self.write(b"550 5.7.23 SPF validation failed, status: permerror.\r\n")
+                .await?;
+            Ok(false)
+        } else {
+            Ok(true)
         }
     }
 
@@ -401,4 +579,55 @@ impl<T: SessionStream> Session<T> {
 
         Ok(result)
     }
+
+    async fn is_allowed_sender(&self) -> bool {
+        let address = &self.data.mail_from.as_ref().unwrap().address_lcase;
+        if &self.data.authenticated_as == address
+            || self.data.authenticated_emails.contains(address)
+        {
+            return true;
+        }
+
+        // Superusers can send from any address
+        if let Some(directory) = &self.params.auth_directory {
+            match directory
+                .query(QueryBy::Name(&self.data.authenticated_as), false)
+                .await
+            {
+                Ok(Some(principal)) => {
+                    if principal.typ == Type::Superuser
+                        || principal
+                            .emails
+                            .iter()
+                            .any(|email| email.eq_ignore_ascii_case(address))
+                    {
+                        return true;
+                    }
+                }
+                Ok(None) => (),
+                Err(err) => {
+                    tracing::debug!(parent: &self.span,
+                        context = "mail-from",
+                        event = "error",
+                        authenticated_as = &self.data.authenticated_as,
+                        reason = ?err,
+                        "Failed to lookup authenticated principal.");
+                }
+            }
+        }
+
+        self.core
+            .eval_if(&self.core.session.config.auth.send_as, self)
+            .await
+            .unwrap_or(false)
+    }
+}
+
+// The envelope domain used for SPF and DMARC alignment is stripped of its
+// trailing dot, the address itself is kept verbatim for logging.
+fn sender_domain(address_lcase: &str) -> String {
+    address_lcase
+        .domain_part()
+        .trim_end_matches('.')
+        .to_string()
 }
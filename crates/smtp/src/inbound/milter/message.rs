@@ -31,7 +31,7 @@ use utils::listener::SessionStream;
 use crate::{
     config::Milter,
     core::{Session, SessionAddress, SessionData},
-    inbound::milter::MilterClient,
+    inbound::{build_response, milter::MilterClient, split_response},
     queue::DomainPart,
     DAEMON_NAME,
 };
@@ -94,14 +94,13 @@ impl<T: SessionStream> Session<T> {
                             (b"451 4.3.5 Unable to accept message at this time.\r\n"[..]).into()
                         }
                         Action::ReplyCode { code, text } => {
-                            let mut response = Vec::with_capacity(text.len() + 6);
-                            response.extend_from_slice(code.as_slice());
-                            response.push(b' ');
-                            response.extend_from_slice(text.as_bytes());
-                            if !text.ends_with('\n') {
-                                response.extend_from_slice(b"\r\n");
+                            let response = format!("{} {text}", String::from_utf8_lossy(&code));
+                            match split_response(&response) {
+                                Some((status, text)) => build_response(status, text),
+                                None => build_response("550 5.7.1", &text),
                             }
-                            response.into()
+                            .into_bytes()
+                            .into()
                         }
                         Action::Shutdown => (b"421 4.3.0 Server shutting down.\r\n"[..]).into(),
                         Action::ConnectionFailure => (b""[..]).into(), // TODO: Not very elegant design, fix.
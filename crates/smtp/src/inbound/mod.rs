@@ -21,21 +21,31 @@
  * for more details.
 */
 
+use std::net::{Ipv4Addr, Ipv6Addr};
+
 use mail_auth::{
     arc::ArcSet, dkim::Signature, dmarc::Policy, ArcOutput, AuthenticatedMessage,
     AuthenticationResults, DkimResult, DmarcResult, IprevResult, SpfResult,
 };
 
-use crate::config::{ArcSealer, DkimSigner};
+use smtp_proto::{request::parser::Rfc5321Parser, Request};
+
+use crate::config::{ArcSealer, DkimDomainSigners, DkimSigner, ResponseTemplate, TemplateItem};
+
+use self::rcpt::is_valid_local_part;
 
 pub mod auth;
 pub mod data;
+pub mod dnsbl;
 pub mod ehlo;
+pub mod etrn;
 pub mod mail;
 pub mod milter;
 pub mod rcpt;
+pub mod score;
 pub mod session;
 pub mod spawn;
+pub mod spf;
 pub mod vrfy;
 
 impl ArcSealer {
@@ -67,6 +77,146 @@ impl DkimSigner {
     }
 }
 
+impl DkimDomainSigners {
+    pub fn get(&self, domain: &str) -> Option<&DkimSigner> {
+        self.domains
+            .get(&domain.to_lowercase())
+            .or(self.default.as_ref())
+            .map(|s| s.as_ref())
+    }
+
+    pub fn sign_for_domain(
+        &self,
+        domain: &str,
+        message: &[u8],
+    ) -> Option<mail_auth::Result<Signature>> {
+        self.get(domain).map(|signer| signer.sign(message))
+    }
+}
+
+impl ResponseTemplate {
+    pub fn render(&self, status: &str, sender: &str, recipient: &str, reason: &str) -> Vec<u8> {
+        let mut response = String::with_capacity(64);
+        for item in &self.items {
+            let value = match item {
+                TemplateItem::Text(text) => {
+                    response.push_str(text);
+                    continue;
+                }
+                TemplateItem::Sender => sender,
+                TemplateItem::Recipient => recipient,
+                TemplateItem::Reason => reason,
+            };
+
+            // Strip line breaks from client supplied values
+            response.extend(value.chars().filter(|ch| !ch.is_control()));
+        }
+        build_response(status, &response).into_bytes()
+    }
+}
+
+/// Formats an SMTP reply from a status such as `550 5.7.1` and a text that
+/// may span several lines. All lines but the last use the `550-` continuation
+/// prefix and the enhanced status code is repeated on each line (RFC 2034).
+/// Continuation lines of a text that is already a multi-line reply keep their
+/// text only, so that their status codes are not repeated.
+pub fn build_response(status: &str, text: &str) -> String {
+    let (code, enhanced_code) = status.split_once(' ').unwrap_or((status, ""));
+    let text = text.trim_end_matches(['\r', '\n']);
+    let mut response = String::with_capacity(status.len() + text.len() + 8);
+    let mut lines = text.split('\n').enumerate().peekable();
+
+    while let Some((line_num, line)) = lines.next() {
+        let mut line = line.trim_end_matches('\r');
+        if line_num > 0 {
+            if let Some(line_text) = line
+                .strip_prefix(code)
+                .and_then(|line| line.strip_prefix(['-', ' ']))
+            {
+                line = match line_text.split_once(' ') {
+                    Some((line_code, line_text)) if is_enhanced_code(line_code) => line_text,
+                    _ => line_text,
+                };
+            }
+        }
+
+        response.push_str(code);
+        response.push(if lines.peek().is_some() { '-' } else { ' ' });
+        if !enhanced_code.is_empty() {
+            response.push_str(enhanced_code);
+            response.push(' ');
+        }
+        response.push_str(line);
+        response.push_str("\r\n");
+    }
+
+    response
+}
+
+/// Splits a single or multi-line reply such as `550 5.7.1 Go away` into its
+/// status (reply code plus optional enhanced status code) and its text.
+pub fn split_response(response: &str) -> Option<(&str, &str)> {
+    let bytes = response.as_bytes();
+    if bytes.len() < 4 || !bytes[..3].iter().all(u8::is_ascii_digit) || bytes[3] != b' ' {
+        return None;
+    }
+
+    let status_len = response[4..]
+        .split_once(' ')
+        .filter(|(enhanced_code, _)| is_enhanced_code(enhanced_code))
+        .map_or(3, |(enhanced_code, _)| 4 + enhanced_code.len());
+
+    Some((
+        &response[..status_len],
+        response[status_len..].trim_start_matches(' '),
+    ))
+}
+
+fn is_enhanced_code(code: &str) -> bool {
+    let mut parts = code.split('.');
+    (0..3).all(|_| {
+        parts.next().map_or(false, |part| {
+            (1..=3).contains(&part.len()) && part.bytes().all(|ch| ch.is_ascii_digit())
+        })
+    }) && parts.next().is_none()
+}
+
+/// Parses a `MAIL FROM` or `RCPT TO` command whose domain is an IPv4 or
+/// `IPv6:` address literal, such as `<user@[192.0.2.1]>`.
+pub fn address_literal(request: &[u8], command: &str) -> Option<Request<String>> {
+    let request = std::str::from_utf8(request).ok()?.trim();
+    let (address, params) = request
+        .get(..command.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(command))
+        .and_then(|_| request.get(command.len()..))?
+        .trim_start()
+        .strip_prefix('<')?
+        .split_once('>')?;
+    let (local_part, domain) = address.rsplit_once('@')?;
+    let literal = domain.strip_prefix('[')?.strip_suffix(']')?;
+    let is_valid_literal = match literal.get(..5) {
+        Some(tag) if tag.eq_ignore_ascii_case("ipv6:") => literal[5..].parse::<Ipv6Addr>().is_ok(),
+        _ => literal.parse::<Ipv4Addr>().is_ok(),
+    };
+    if !is_valid_literal || !is_valid_local_part(local_part) {
+        return None;
+    }
+
+    let params = format!("{params}\r\n");
+    let mut parser = Rfc5321Parser::new(&mut params.as_bytes().iter());
+    if command.eq_ignore_ascii_case("mail from:") {
+        parser
+            .mail_from_parameters(address.to_string())
+            .ok()
+            .map(|from| Request::Mail { from })
+    } else {
+        parser
+            .rcpt_to_parameters(address.to_string())
+            .ok()
+            .map(|to| Request::Rcpt { to })
+    }
+}
+
 pub trait AuthResult {
     fn as_str(&self) -> &'static str;
 }
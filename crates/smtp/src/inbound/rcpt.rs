@@ -21,13 +21,18 @@
  * for more details.
 */
 
+use directory::{Directory, LookupErrorPolicy, QueryBy};
 use smtp_proto::{
-    RcptTo, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
+    request::parser::Rfc5321Parser, RcptTo, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE,
+    RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
 };
+use store::write::{now, DirectoryClass};
 use utils::listener::SessionStream;
 
 use crate::{
-    core::{Session, SessionAddress},
+    config::{AddressLiteral, BareLocalPart, OverQuota},
+    core::{srs::SrsError, Session, SessionAddress},
+    inbound::split_response,
     queue::DomainPart,
     scripts::{ScriptModification, ScriptResult},
 };
@@ -47,6 +52,13 @@ impl<T: SessionStream> Session<T> {
             return self.write(b"503 5.5.1 MAIL is required first.\r\n").await;
         } else if self.data.rcpt_to.len() >= self.params.rcpt_max {
             return self.write(b"451 4.5.3 Too many recipients.\r\n").await;
+        } else if self.data.rcpt_to.len() >= self.params.rcpt_max_null_sender
+            && self.data.mail_from.as_ref().unwrap().address.is_empty()
+        {
+            // Bounces to many recipients are a backscatter vector
+            return self
+                .write(b"550 5.5.3 Too many recipients for a null sender message.\r\n")
+                .await;
         }
 
         // Verify parameters
@@ -76,6 +88,52 @@ impl<T: SessionStream> Session<T> {
         }
         self.data.rcpt_to.push(rcpt);
 
+        // Route replies to rewritten senders back to the original address
+        let mut is_srs = false;
+        if let Some(srs) = &self.core.session.config.srs {
+            let rcpt = self.data.rcpt_to.last_mut().unwrap();
+            if srs.is_srs_address(&rcpt.address_lcase) {
+                match srs.reverse(&rcpt.address, now()) {
+                    Ok(address) => {
+                        tracing::debug!(parent: &self.span,
+                            context = "srs",
+                            event = "reverse",
+                            address = &rcpt.address,
+                            original_address = &address);
+
+                        rcpt.address_lcase = address.to_lowercase();
+                        rcpt.domain = rcpt.address_lcase.domain_part().to_string();
+                        rcpt.address = address;
+                        is_srs = true;
+
+                        // Check for duplicates
+                        let rcpt = self.data.rcpt_to.last().unwrap();
+                        if self.data.rcpt_to.iter().filter(|r| r == &rcpt).count() > 1 {
+                            self.data.rcpt_to.pop();
+                            return self.write(b"250 2.1.5 OK\r\n").await;
+                        }
+                    }
+                    Err(err) => {
+                        tracing::debug!(parent: &self.span,
+                            context = "srs",
+                            event = "error",
+                            address = &rcpt.address,
+                            reason = ?err,
+                            "Invalid SRS address.");
+
+                        self.data.rcpt_to.pop();
+                        return self
+                            .rcpt_error(if err == SrsError::Expired {
+                                &b"550 5.1.1 SRS address has expired.\r\n"[..]
+                            } else {
+                                &b"550 5.1.1 Invalid SRS address.\r\n"[..]
+                            })
+                            .await;
+                    }
+                }
+            }
+        }
+
         // Address rewriting and Sieve filtering
         let rcpt_script = self
             .core
@@ -113,8 +171,22 @@ impl<T: SessionStream> Session<T> {
                         event = "reject",
                         address = self.data.rcpt_to.last().unwrap().address,
                         reason = message);
+
+                        // Temporary failures such as greylisting include the retry hint
+                        let message = match split_response(&message) {
+                            Some((status, text))
+                                if status.starts_with('4') && !text.trim_end().contains('\n') =>
+                            {
+                                self.defer_response(
+                                    status,
+                                    &self.data.rcpt_to.last().unwrap().address,
+                                    text.trim_end(),
+                                )
+                            }
+                            _ => message.into_bytes(),
+                        };
                         self.data.rcpt_to.pop();
-                        return self.write(message.as_bytes()).await;
+                        return self.write(&message).await;
                     }
                     _ => (),
                 }
@@ -142,9 +214,35 @@ impl<T: SessionStream> Session<T> {
             }
         }
 
+        // Route postmaster to the fallback mailbox
+        if is_postmaster(&self.data.rcpt_to.last().unwrap().address_lcase) {
+            if let Some(fallback) = self.postmaster_fallback().await {
+                tracing::debug!(parent: &self.span,
+                    context = "rcpt",
+                    event = "postmaster",
+                    address = &self.data.rcpt_to.last().unwrap().address_lcase,
+                    fallback = &fallback,
+                    "Routing postmaster to fallback mailbox.");
+
+                let rcpt = self.data.rcpt_to.last_mut().unwrap();
+                rcpt.address_lcase = fallback.to_lowercase();
+                rcpt.domain = rcpt.address_lcase.domain_part().to_string();
+                rcpt.address = fallback;
+
+                // Check for duplicates
+                let rcpt = self.data.rcpt_to.last().unwrap();
+                if self.data.rcpt_to.iter().filter(|r| r == &rcpt).count() > 1 {
+                    self.data.rcpt_to.pop();
+                    return self.write(b"250 2.1.5 OK\r\n").await;
+                }
+            }
+        }
+
         // Verify address
         let rcpt = self.data.rcpt_to.last().unwrap();
-        if let Some(directory) = self
+        if is_srs {
+            // The SRS hash proves that the original sender relayed through this server
+        } else if let Some(directory) = self
             .core
             .eval_if::<String, _>(&self.core.session.config.rcpt.directory, self)
             .await
@@ -160,64 +258,115 @@ impl<T: SessionStream> Session<T> {
                                             address = &rcpt.address_lcase,
                                             "Mailbox does not exist.");
 
+                            let message = self.reject_response(
+                                &self.core.session.config.reject.unknown_recipient,
+                                "550 5.1.2",
+                                &rcpt.address,
+                                "Mailbox does not exist.",
+                            );
                             self.data.rcpt_to.pop();
-                            return self
-                                .rcpt_error(b"550 5.1.2 Mailbox does not exist.\r\n")
-                                .await;
-                        }
-                    } else {
-                        tracing::debug!(parent: &self.span,
-                            context = "rcpt", 
-                            event = "error",
-                            address = &rcpt.address_lcase,
-                            "Temporary address verification failure.");
+                            return self.rcpt_error(&message).await;
+                        } else if self.is_over_quota(directory, &rcpt.address_lcase).await {
+                            tracing::debug!(parent: &self.span,
+                                            context = "rcpt",
+                                            event = "error",
+                                            address = &rcpt.address_lcase,
+                                            "Mailbox over quota.");
 
-                        self.data.rcpt_to.pop();
-                        return self
-                            .write(b"451 4.4.3 Unable to verify address at this time.\r\n")
-                            .await;
+                            let policy = self
+                                .core
+                                .eval_if(&self.core.session.config.rcpt.over_quota, self)
+                                .await
+                                .unwrap_or_default();
+                            self.data.rcpt_to.pop();
+                            return match policy {
+                                OverQuota::Reject => {
+                                    self.rcpt_error(b"550 5.2.2 Mailbox full.\r\n").await
+                                }
+                                OverQuota::Defer => {
+                                    self.write(b"452 4.2.2 Mailbox full, try again later.\r\n")
+                                        .await
+                                }
+                            };
+                        }
+                    } else if let Some(result) = self.lookup_error(directory.on_error).await {
+                        return result;
                     }
-                } else if !self
-                    .core
-                    .eval_if(&self.core.session.config.rcpt.relay, self)
-                    .await
-                    .unwrap_or(false)
-                {
+                } else if !self.is_relay_allowed().await || !self.data.can_send_external() {
                     tracing::debug!(parent: &self.span,
                         context = "rcpt", 
                         event = "error",
                         address = &rcpt.address_lcase,
                         "Relay not allowed.");
 
+                    let message = self.reject_response(
+                        &self.core.session.config.reject.relay_denied,
+                        "550 5.1.2",
+                        &rcpt.address,
+                        "Relay not allowed.",
+                    );
                     self.data.rcpt_to.pop();
-                    return self.rcpt_error(b"550 5.1.2 Relay not allowed.\r\n").await;
+                    return self.rcpt_error(&message).await;
                 }
             } else {
-                tracing::debug!(parent: &self.span,
-                    context = "rcpt", 
-                    event = "error",
-                    address = &rcpt.address_lcase,
-                    "Temporary address verification failure.");
-
-                self.data.rcpt_to.pop();
-                return self
-                    .write(b"451 4.4.3 Unable to verify address at this time.\r\n")
-                    .await;
+                // Unverifiable domains are only accepted when relaying is allowed
+                let policy = match directory.on_error {
+                    LookupErrorPolicy::Accept if !self.is_relay_allowed().await => {
+                        LookupErrorPolicy::TempFail
+                    }
+                    policy => policy,
+                };
+                if let Some(result) = self.lookup_error(policy).await {
+                    return result;
+                }
             }
-        } else if !self
-            .core
-            .eval_if(&self.core.session.config.rcpt.relay, self)
-            .await
-            .unwrap_or(false)
-        {
+        } else if !self.is_relay_allowed().await || !self.data.can_send_external() {
             tracing::debug!(parent: &self.span,
                 context = "rcpt", 
                 event = "error",
                 address = &rcpt.address_lcase,
                 "Relay not allowed.");
 
+            let message = self.reject_response(
+                &self.core.session.config.reject.relay_denied,
+                "550 5.1.2",
+                &rcpt.address,
+                "Relay not allowed.",
+            );
+            self.data.rcpt_to.pop();
+            return self.rcpt_error(&message).await;
+        }
+
+        // Recipient domains without a size limit of their own use the global limit
+        let max_size = match self
+            .core
+            .session
+            .config
+            .data
+            .max_message_size_domain
+            .lookup(&self.data.rcpt_to.last().unwrap().domain)
+            .and_then(|size| size.parse::<usize>().ok())
+        {
+            Some(max_size) => max_size,
+            None => self
+                .core
+                .eval_if(&self.core.session.config.data.max_message_size, self)
+                .await
+                .unwrap_or(25 * 1024 * 1024),
+        };
+        if self.data.mail_from_size > max_size {
+            tracing::debug!(parent: &self.span,
+                context = "rcpt",
+                event = "error",
+                address = &self.data.rcpt_to.last().unwrap().address_lcase,
+                size = self.data.mail_from_size,
+                max_size = max_size,
+                "Declared message size exceeds the recipient limit.");
+
             self.data.rcpt_to.pop();
-            return self.rcpt_error(b"550 5.1.2 Relay not allowed.\r\n").await;
+            return self
+                .write(b"552 5.3.4 Message too big for recipient domain.\r\n")
+                .await;
         }
 
         if self.is_allowed().await {
@@ -226,18 +375,208 @@ impl<T: SessionStream> Session<T> {
                     event = "success",
                     address = &self.data.rcpt_to.last().unwrap().address);
         } else {
+            let message = self.defer_response(
+                "451 4.4.5",
+                &self.data.rcpt_to.last().unwrap().address,
+                "Rate limit exceeded.",
+            );
             self.data.rcpt_to.pop();
-            return self
-                .write(b"451 4.4.5 Rate limit exceeded, try again later.\r\n")
-                .await;
+            return self.write(&message).await;
         }
 
+        // The smallest size limit among all recipients applies
+        self.params.max_message_size = if self.data.rcpt_to.len() == 1 {
+            max_size
+        } else {
+            self.params.max_message_size.min(max_size)
+        };
+
         self.write(b"250 2.1.5 OK\r\n").await
     }
 
+    // Largest size limit configured for a recipient domain, which may exceed
+    // the global limit.
+    pub(crate) fn max_domain_message_size(&self) -> usize {
+        let limits = &self.core.session.config.data.max_message_size_domain;
+        limits
+            .keys()
+            .filter_map(|domain| limits.lookup(domain)?.parse::<usize>().ok())
+            .max()
+            .unwrap_or(0)
+    }
+
+    async fn lookup_error(&mut self, policy: LookupErrorPolicy) -> Option<Result<(), ()>> {
+        let address = &self.data.rcpt_to.last().unwrap().address_lcase;
+        match policy {
+            LookupErrorPolicy::TempFail => {
+                tracing::debug!(parent: &self.span,
+                    context = "rcpt",
+                    event = "error",
+                    address = address,
+                    "Temporary address verification failure.");
+
+                let message = self.defer_response(
+                    "451 4.4.3",
+                    &self.data.rcpt_to.last().unwrap().address,
+                    "Unable to verify address at this time.",
+                );
+                self.data.rcpt_to.pop();
+                Some(self.write(&message).await)
+            }
+            LookupErrorPolicy::Reject => {
+                tracing::debug!(parent: &self.span,
+                    context = "rcpt",
+                    event = "error",
+                    address = address,
+                    "Address verification failure, rejecting recipient.");
+
+                let message = self.reject_response(
+                    &self.core.session.config.reject.unverified_recipient,
+                    "550 5.1.1",
+                    &self.data.rcpt_to.last().unwrap().address,
+                    "Unable to verify address.",
+                );
+                self.data.rcpt_to.pop();
+                Some(self.rcpt_error(&message).await)
+            }
+            LookupErrorPolicy::Accept => {
+                tracing::debug!(parent: &self.span,
+                    context = "rcpt",
+                    event = "error",
+                    address = address,
+                    "Address verification failure, accepting recipient.");
+
+                None
+            }
+        }
+    }
+
+    // Only addresses that resolve to a single account are checked, a
+    // mailing list is not rejected because one of its members is full.
+    async fn is_over_quota(&self, directory: &Directory, address: &str) -> bool {
+        let account_id = match directory.email_to_ids(address).await.as_deref() {
+            Ok([account_id]) => *account_id,
+            _ => return false,
+        };
+        let quota = match directory.query(QueryBy::Id(account_id), false).await {
+            Ok(Some(principal)) if principal.quota > 0 => principal.quota,
+            _ => return false,
+        };
+
+        match self
+            .core
+            .shared
+            .default_data_store
+            .get_counter(DirectoryClass::UsedQuota(account_id))
+            .await
+        {
+            Ok(used) => used >= quota as i64,
+            Err(err) => {
+                tracing::debug!(parent: &self.span,
+                    context = "rcpt",
+                    event = "error",
+                    address = address,
+                    reason = ?err,
+                    "Failed to obtain used quota.");
+                false
+            }
+        }
+    }
+
+    pub async fn handle_bare_local_part(&mut self, mut to: RcptTo<String>) -> Result<(), ()> {
+        // RFC 5321 allows a bare <Postmaster> without a domain
+        let local_part = &to.address;
+        to.address = if local_part.eq_ignore_ascii_case("postmaster") {
+            "postmaster".to_string()
+        } else {
+            let rc = &self.core.session.config.rcpt;
+            let domain = match self.core.eval_if(&rc.bare_local_part, self).await {
+                Some(BareLocalPart::AppendDefaultDomain) => self
+                    .core
+                    .eval_if::<String, _>(&rc.default_domain, self)
+                    .await
+                    .filter(|domain| !domain.is_empty()),
+                _ => None,
+            };
+
+            if let Some(domain) = domain {
+                format!("{local_part}@{domain}")
+            } else {
+                tracing::debug!(parent: &self.span,
+                    context = "rcpt",
+                    event = "error",
+                    address = local_part,
+                    "Recipient without a domain.");
+
+                return self
+                    .write(b"501 5.1.3 Bad destination mailbox address syntax.\r\n")
+                    .await;
+            }
+        };
+
+        self.handle_rcpt_to(to).await
+    }
+
+    pub async fn handle_rcpt_to_literal(&mut self, to: RcptTo<String>) -> Result<(), ()> {
+        if self
+            .core
+            .eval_if(&self.core.session.config.rcpt.address_literals, self)
+            .await
+            == Some(AddressLiteral::Allow)
+        {
+            self.handle_rcpt_to(to).await
+        } else {
+            tracing::debug!(parent: &self.span,
+                context = "rcpt",
+                event = "error",
+                address = &to.address,
+                "Address literal not accepted.");
+
+            self.write(b"501 5.1.3 Address literals are not accepted.\r\n")
+                .await
+        }
+    }
+
+    async fn postmaster_fallback(&self) -> Option<String> {
+        let fallback = self
+            .core
+            .eval_if::<String, _>(&self.core.session.config.rcpt.postmaster, self)
+            .await
+            .filter(|address| address.contains('@'))?;
+
+        // A bare postmaster has no domain to look up, otherwise the fallback
+        // is only used when the directory has no such principal.
+        let rcpt = self.data.rcpt_to.last().unwrap();
+        if rcpt.domain.is_empty() {
+            return Some(fallback);
+        }
+        let directory = self
+            .core
+            .eval_if::<String, _>(&self.core.session.config.rcpt.directory, self)
+            .await
+            .and_then(|name| self.core.get_directory(&name))?;
+        if directory.is_local_domain(&rcpt.domain).await.ok()?
+            && !directory.rcpt(&rcpt.address_lcase).await.ok()?
+        {
+            Some(fallback)
+        } else {
+            None
+        }
+    }
+
+    async fn is_relay_allowed(&self) -> bool {
+        self.is_trusted()
+            || self
+                .core
+                .eval_if(&self.core.session.config.rcpt.relay, self)
+                .await
+                .unwrap_or(false)
+    }
+
     async fn rcpt_error(&mut self, response: &[u8]) -> Result<(), ()> {
         tokio::time::sleep(self.params.rcpt_errors_wait).await;
         self.data.rcpt_errors += 1;
+        self.tarpit().await;
         self.write(response).await?;
         if self.data.rcpt_errors < self.params.rcpt_errors_max {
             Ok(())
@@ -255,3 +594,36 @@ impl<T: SessionStream> Session<T> {
         }
     }
 }
+
+fn is_postmaster(address: &str) -> bool {
+    address
+        .rsplit_once('@')
+        .map_or(address, |(local_part, _)| local_part)
+        == "postmaster"
+}
+
+pub fn bare_local_part(request: &[u8]) -> Option<RcptTo<String>> {
+    let request = std::str::from_utf8(request).ok()?.trim();
+    let (local_part, params) = request
+        .get(..8)
+        .filter(|command| command.eq_ignore_ascii_case("rcpt to:"))
+        .and_then(|_| request.get(8..))?
+        .trim_start()
+        .strip_prefix('<')?
+        .split_once('>')?;
+    if !is_valid_local_part(local_part) {
+        return None;
+    }
+
+    Rfc5321Parser::new(&mut format!("{params}\r\n").as_bytes().iter())
+        .rcpt_to_parameters(local_part.to_string())
+        .ok()
+}
+
+pub fn is_valid_local_part(local_part: &str) -> bool {
+    !local_part.is_empty()
+        && local_part.len() <= 64
+        && local_part
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~.".contains(ch))
+}
@@ -0,0 +1,51 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use crate::config::{ScoreConfig, ScoreSignal};
+
+use super::AuthResult;
+
+impl ScoreConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.reject.is_some() || self.spam.is_some()
+    }
+
+    /// Adds up the weights of the given authentication results and of the
+    /// DNSBL score accumulated by the session. Signals that were not
+    /// evaluated should be left out rather than passed as 'none'.
+    pub fn score(&self, results: &[(ScoreSignal, &dyn AuthResult)], dnsbl_score: u32) -> f64 {
+        results
+            .iter()
+            .filter_map(|(signal, result)| self.weights.get(&(*signal, result.as_str())))
+            .sum::<f64>()
+            + self.dnsbl * dnsbl_score as f64
+    }
+
+    pub fn is_reject(&self, score: f64) -> bool {
+        self.reject.map_or(false, |reject| score >= reject)
+    }
+
+    pub fn is_spam(&self, score: f64) -> bool {
+        self.spam.map_or(false, |spam| score >= spam)
+    }
+}
@@ -21,10 +21,11 @@
  * for more details.
 */
 
+use std::{borrow::Cow, time::Duration};
+
 use smtp_proto::{
     request::receiver::{
         BdatReceiver, DataReceiver, DummyDataReceiver, DummyLineReceiver, LineReceiver,
-        MAX_LINE_LENGTH,
     },
     *,
 };
@@ -32,11 +33,11 @@ use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use utils::{config::ServerProtocol, listener::SessionStream};
 
 use crate::{
-    config::session::Mechanism,
+    config::{BareLf, CommandLeniency, ResponseTemplate, DEFAULT_HELP_MESSAGE},
     core::{eval::*, ResolveVariable, Session, State},
 };
 
-use super::auth::SaslToken;
+use super::{address_literal, build_response, rcpt::bare_local_part};
 
 impl<T: SessionStream> Session<T> {
     pub async fn ingest(&mut self, bytes: &[u8]) -> Result<bool, ()> {
@@ -46,7 +47,144 @@ impl<T: SessionStream> Session<T> {
         'outer: loop {
             match &mut state {
                 State::Request(receiver) => loop {
-                    match receiver.ingest(&mut iter, bytes) {
+                    let request = iter.as_slice();
+                    let buffered_len = receiver.buf.len();
+                    // Commands split across reads are partially held by the receiver
+                    let buffered = receiver.buf.clone();
+                    let result = receiver.ingest(&mut iter, bytes);
+
+                    // Enforce the maximum command line length
+                    if self.params.max_line_length > 0 {
+                        if matches!(
+                            result,
+                            Err(Error::NeedsMoreData { .. } | Error::ResponseTooLong)
+                        ) {
+                            if receiver.buf.len() > self.params.max_line_length
+                                && !is_auth_command(&receiver.buf)
+                            {
+                                receiver.buf.clear();
+                                state = State::RequestTooLarge(DummyLineReceiver::default());
+                                continue 'outer;
+                            }
+                        } else if buffered_len + request.len() - iter.as_slice().len()
+                            > self.params.max_line_length
+                            && !matches!(result, Ok(Request::Auth { .. }))
+                        {
+                            self.write(b"500 5.5.2 Line is too long.\r\n").await?;
+                            continue;
+                        }
+                    }
+
+                    // Enforce pipelining limits. Commands are counted until the client
+                    // has no partial command queued, which may span several reads, and
+                    // each command above the limits is rejected.
+                    if !matches!(
+                        result,
+                        Err(Error::NeedsMoreData { .. } | Error::ResponseTooLong)
+                    ) {
+                        self.data.pipelined_commands += 1;
+                        self.data.pipelined_size +=
+                            buffered_len + request.len() - iter.as_slice().len();
+                        if (self.params.pipelining_max_commands > 0
+                            && self.data.pipelined_commands > self.params.pipelining_max_commands)
+                            || (self.params.pipelining_max_size > 0
+                                && self.data.pipelined_size > self.params.pipelining_max_size)
+                        {
+                            tracing::debug!(
+                                parent: &self.span,
+                                context = "pipelining",
+                                event = "limit-exceeded",
+                                commands = self.data.pipelined_commands,
+                                size = self.data.pipelined_size,
+                                "Pipelining limits exceeded."
+                            );
+                            self.write(b"503 5.5.0 Too many pipelined commands.\r\n")
+                                .await?;
+                            continue;
+                        }
+                    } else if matches!(result, Err(Error::NeedsMoreData { .. }))
+                        && receiver.buf.is_empty()
+                    {
+                        self.data.pipelined_commands = 0;
+                        self.data.pipelined_size = 0;
+                    }
+
+                    // Strict mode rejects lowercase verbs and extra whitespace
+                    if result.is_ok()
+                        && self.params.command_leniency == CommandLeniency::Strict
+                        && !is_strict_command(
+                            buffered
+                                .iter()
+                                .chain(&request[..request.len() - iter.as_slice().len()]),
+                        )
+                    {
+                        self.write(b"500 5.5.2 Syntax error.\r\n").await?;
+                        continue;
+                    }
+
+                    // Close sessions that keep issuing non-productive commands, the
+                    // count is only reset once a message has been transferred
+                    if let Ok(
+                        Request::Noop { .. }
+                        | Request::Rset
+                        | Request::Help { .. }
+                        | Request::Vrfy { .. }
+                        | Request::Expn { .. },
+                    ) = &result
+                    {
+                        self.data.noop_commands += 1;
+                        if self.params.max_noop_commands > 0
+                            && self.data.noop_commands > self.params.max_noop_commands
+                        {
+                            self.write(
+                                b"421 4.7.0 Too many non-productive commands, closing connection.\r\n",
+                            )
+                            .await?;
+                            tracing::debug!(
+                                parent: &self.span,
+                                event = "disconnect",
+                                reason = "noop-flood",
+                                count = self.data.noop_commands,
+                                "Too many non-productive commands."
+                            );
+                            return Err(());
+                        }
+                    }
+
+                    // Mechanisms registered by name are not known to the parser
+                    if result.is_err()
+                        && !matches!(
+                            result,
+                            Err(Error::NeedsMoreData { .. } | Error::ResponseTooLong)
+                        )
+                    {
+                        let command = [
+                            buffered.as_slice(),
+                            &request[..request.len() - iter.as_slice().len()],
+                        ]
+                        .concat();
+                        if let Some((name, initial_response)) =
+                            auth_command(&command).filter(|(name, _)| {
+                                self.core
+                                    .session
+                                    .config
+                                    .auth
+                                    .sasl
+                                    .get_by_name(name)
+                                    .is_some()
+                            })
+                        {
+                            if let Some(token) =
+                                self.handle_auth(0, Some(name), initial_response).await?
+                            {
+                                state = State::Sasl(LineReceiver::new(token));
+                                continue 'outer;
+                            }
+                            continue;
+                        }
+                    }
+
+                    match result {
                         Ok(request) => match request {
                             Request::Rcpt { to } => {
                                 self.handle_rcpt_to(to).await?;
@@ -75,17 +213,23 @@ impl<T: SessionStream> Session<T> {
                                 is_last,
                             } => {
                                 state = if chunk_size + self.data.message.len()
-                                    < self.params.max_message_size
+                                    >= self.params.max_message_size
                                 {
+                                    // Chunk is too large, ignore.
+                                    State::DataTooLarge(DummyDataReceiver::new_bdat(chunk_size))
+                                } else if let Some(response) = self.check_send_data().await {
+                                    // Transfer not allowed, discard the chunk without buffering it
+                                    State::DataRejected(
+                                        DummyDataReceiver::new_bdat(chunk_size),
+                                        response,
+                                    )
+                                } else {
                                     if self.data.message.is_empty() {
                                         self.data.message = Vec::with_capacity(chunk_size);
                                     } else {
                                         self.data.message.reserve(chunk_size);
                                     }
                                     State::Bdat(BdatReceiver::new(chunk_size, is_last))
-                                } else {
-                                    // Chunk is too large, ignore.
-                                    State::DataTooLarge(DummyDataReceiver::new_bdat(chunk_size))
                                 };
                                 continue 'outer;
                             }
@@ -93,42 +237,12 @@ impl<T: SessionStream> Session<T> {
                                 mechanism,
                                 initial_response,
                             } => {
-                                let auth: u64 = self
-                                    .core
-                                    .eval_if::<Mechanism, _>(
-                                        &self.core.session.config.auth.mechanisms,
-                                        self,
-                                    )
-                                    .await
-                                    .unwrap_or_default()
-                                    .into();
-                                if auth == 0 || self.params.auth_directory.is_none() {
-                                    self.write(b"503 5.5.1 AUTH not allowed.\r\n").await?;
-                                } else if !self.data.authenticated_as.is_empty() {
-                                    self.write(b"503 5.5.1 Already authenticated.\r\n").await?;
-                                } else if mechanism & (AUTH_LOGIN | AUTH_PLAIN) != 0
-                                    && !self.stream.is_tls()
-                                    && !self.params.auth_plain_text
+                                if let Some(token) = self
+                                    .handle_auth(mechanism, None, initial_response.as_bytes())
+                                    .await?
                                 {
-                                    self.write(b"503 5.5.1 Clear text authentication without TLS is forbidden.\r\n").await?;
-                                } else if let Some(mut token) =
-                                    SaslToken::from_mechanism(mechanism & auth)
-                                {
-                                    if self
-                                        .handle_sasl_response(
-                                            &mut token,
-                                            initial_response.as_bytes(),
-                                        )
-                                        .await?
-                                    {
-                                        state = State::Sasl(LineReceiver::new(token));
-                                        continue 'outer;
-                                    }
-                                } else {
-                                    self.write(
-                                        b"554 5.7.8 Authentication mechanism not supported.\r\n",
-                                    )
-                                    .await?;
+                                    state = State::Sasl(LineReceiver::new(token));
+                                    continue 'outer;
                                 }
                             }
                             Request::Noop { .. } => {
@@ -141,13 +255,50 @@ impl<T: SessionStream> Session<T> {
                                 self.handle_expn(value).await?;
                             }
                             Request::StartTls => {
-                                if !self.stream.is_tls() {
+                                if !self.can_use_extensions() {
+                                    self.write(b"503 5.5.1 Send EHLO to use ESMTP extensions.\r\n")
+                                        .await?;
+                                } else if !self.stream.is_tls() {
                                     if self.instance.acceptor.is_tls() {
+                                        // Input buffered after STARTTLS was sent in clear text and
+                                        // must not be processed once TLS is established (CVE-2011-0411)
+                                        if !iter.as_slice().is_empty() {
+                                            let leniency = self
+                                                .core
+                                                .eval_if(
+                                                    &self
+                                                        .core
+                                                        .session
+                                                        .config
+                                                        .extensions
+                                                        .starttls_pipelining,
+                                                    self,
+                                                )
+                                                .await
+                                                .unwrap_or(CommandLeniency::Strict);
+                                            tracing::debug!(
+                                                parent: &self.span,
+                                                context = "starttls",
+                                                event = "pipelining",
+                                                size = iter.as_slice().len(),
+                                                leniency = ?leniency,
+                                                "Commands pipelined after STARTTLS."
+                                            );
+
+                                            if leniency == CommandLeniency::Strict {
+                                                self.write(b"554 5.5.0 Commands pipelined after STARTTLS are not allowed.\r\n")
+                                                    .await?;
+                                                return Err(());
+                                            }
+                                        }
+
                                         self.write(b"220 2.0.0 Ready to start TLS.\r\n").await?;
                                         #[cfg(any(test, feature = "test_mode"))]
                                         if self.data.helo_domain.contains("badtls") {
                                             return Err(());
                                         }
+                                        // Discard any buffered input before the handshake
+                                        receiver.buf.clear();
                                         self.state = State::default();
                                         return Ok(false);
                                     } else {
@@ -166,10 +317,7 @@ impl<T: SessionStream> Session<T> {
                                 return Err(());
                             }
                             Request::Help { .. } => {
-                                self.write(
-                                    b"250 2.0.0 Help can be found at https://stalw.art/smtp/\r\n",
-                                )
-                                .await?;
+                                self.handle_help().await?;
                             }
                             Request::Helo { host } => {
                                 if self.instance.protocol == ServerProtocol::Smtp {
@@ -185,7 +333,10 @@ impl<T: SessionStream> Session<T> {
                                     self.write(b"502 5.5.1 Invalid command.\r\n").await?;
                                 }
                             }
-                            Request::Etrn { .. } | Request::Atrn { .. } | Request::Burl { .. } => {
+                            Request::Etrn { name } => {
+                                self.handle_etrn(name).await?;
+                            }
+                            Request::Atrn { .. } | Request::Burl { .. } => {
                                 self.write(b"502 5.5.1 Command not implemented.\r\n")
                                     .await?;
                             }
@@ -196,14 +347,38 @@ impl<T: SessionStream> Session<T> {
                                 self.write(b"500 5.5.1 Invalid command.\r\n").await?;
                             }
                             Error::InvalidSenderAddress => {
-                                self.write(b"501 5.1.8 Bad sender's system address.\r\n")
-                                    .await?;
+                                let command = [
+                                    buffered.as_slice(),
+                                    &request[..request.len() - iter.as_slice().len()],
+                                ]
+                                .concat();
+                                if let Some(Request::Mail { from }) =
+                                    address_literal(&command, "mail from:")
+                                {
+                                    self.handle_mail_from_literal(from).await?;
+                                } else {
+                                    self.write(b"501 5.1.8 Bad sender's system address.\r\n")
+                                        .await?;
+                                }
                             }
                             Error::InvalidRecipientAddress => {
-                                self.write(
-                                    b"501 5.1.3 Bad destination mailbox address syntax.\r\n",
-                                )
-                                .await?;
+                                let command = [
+                                    buffered.as_slice(),
+                                    &request[..request.len() - iter.as_slice().len()],
+                                ]
+                                .concat();
+                                if let Some(to) = bare_local_part(&command) {
+                                    self.handle_bare_local_part(to).await?;
+                                } else if let Some(Request::Rcpt { to }) =
+                                    address_literal(&command, "rcpt to:")
+                                {
+                                    self.handle_rcpt_to_literal(to).await?;
+                                } else {
+                                    self.write(
+                                        b"501 5.1.3 Bad destination mailbox address syntax.\r\n",
+                                    )
+                                    .await?;
+                                }
                             }
                             Error::SyntaxError { syntax } => {
                                 self.write(
@@ -236,17 +411,54 @@ impl<T: SessionStream> Session<T> {
                 State::Data(receiver) => {
                     if self.data.message.len() + bytes.len() < self.params.max_message_size {
                         if receiver.ingest(&mut iter, &mut self.data.message) {
-                            let num_rcpts = self.data.rcpt_to.len();
+                            match self.params.bare_lf {
+                                BareLf::Reject if has_bare_lf(&self.data.message) => {
+                                    tracing::debug!(
+                                        parent: &self.span,
+                                        context = "data",
+                                        event = "bare-lf",
+                                        "Message contains bare LF characters."
+                                    );
+
+                                    self.reset();
+                                    self.write(
+                                        b"500 5.5.2 Bare LF characters are not allowed.\r\n",
+                                    )
+                                    .await?;
+                                    state = State::default();
+                                    continue 'outer;
+                                }
+                                BareLf::Convert if has_bare_lf(&self.data.message) => {
+                                    self.data.message = normalize_bare_lf(&self.data.message);
+                                }
+                                _ => (),
+                            }
+
+                            if self.params.max_data_line_length > 0
+                                && exceeds_line_length(
+                                    &self.data.message,
+                                    self.params.max_data_line_length,
+                                )
+                            {
+                                tracing::debug!(
+                                    parent: &self.span,
+                                    context = "data",
+                                    event = "line-too-long",
+                                    "Message contains lines that are too long."
+                                );
+
+                                self.reset();
+                                self.write(b"500 5.5.2 Line is too long.\r\n").await?;
+                                state = State::default();
+                                continue 'outer;
+                            }
+
+                            let rcpts = self.lmtp_recipients();
                             let message = self.queue_message().await;
                             if !message.is_empty() {
-                                if self.instance.protocol == ServerProtocol::Smtp {
-                                    self.write(message.as_ref()).await?;
-                                } else {
-                                    for _ in 0..num_rcpts {
-                                        self.write(message.as_ref()).await?;
-                                    }
-                                }
+                                self.write_data_reply(rcpts, message.as_ref()).await?;
                                 self.reset();
+                                self.data.noop_commands = 0;
                                 state = State::default();
                             } else {
                                 // Disconnect requested
@@ -261,28 +473,19 @@ impl<T: SessionStream> Session<T> {
                 }
                 State::Bdat(receiver) => {
                     if receiver.ingest(&mut iter, &mut self.data.message) {
-                        if self.can_send_data().await? {
-                            if receiver.is_last {
-                                let num_rcpts = self.data.rcpt_to.len();
-                                let message = self.queue_message().await;
-                                if !message.is_empty() {
-                                    if self.instance.protocol == ServerProtocol::Smtp {
-                                        self.write(message.as_ref()).await?;
-                                    } else {
-                                        for _ in 0..num_rcpts {
-                                            self.write(message.as_ref()).await?;
-                                        }
-                                    }
-                                    self.reset();
-                                } else {
-                                    // Disconnect requested
-                                    return Err(());
-                                }
+                        if receiver.is_last {
+                            let rcpts = self.lmtp_recipients();
+                            let message = self.queue_message().await;
+                            if !message.is_empty() {
+                                self.write_data_reply(rcpts, message.as_ref()).await?;
+                                self.reset();
+                                self.data.noop_commands = 0;
                             } else {
-                                self.write(b"250 2.6.0 Chunk accepted.\r\n").await?;
+                                // Disconnect requested
+                                return Err(());
                             }
                         } else {
-                            self.data.message = Vec::with_capacity(0);
+                            self.write(b"250 2.6.0 Chunk accepted.\r\n").await?;
                         }
                         state = State::default();
                     } else {
@@ -290,8 +493,9 @@ impl<T: SessionStream> Session<T> {
                     }
                 }
                 State::Sasl(receiver) => {
-                    if receiver.ingest(&mut iter) {
-                        if receiver.buf.len() < MAX_LINE_LENGTH {
+                    let max_size = self.params.auth_max_response_size;
+                    if ingest_sasl_response(&mut receiver.buf, &mut iter, max_size) {
+                        if receiver.buf.len() <= max_size {
                             if self
                                 .handle_sasl_response(&mut receiver.state, &receiver.buf)
                                 .await?
@@ -320,6 +524,7 @@ impl<T: SessionStream> Session<T> {
                         );
 
                         self.data.message = Vec::with_capacity(0);
+                        self.data.data_in_flight = None;
                         self.write(b"552 5.3.4 Message too big for system.\r\n")
                             .await?;
                         state = State::default();
@@ -327,9 +532,18 @@ impl<T: SessionStream> Session<T> {
                         break 'outer;
                     }
                 }
+                State::DataRejected(receiver, response) => {
+                    if receiver.ingest(&mut iter) {
+                        self.data.message = Vec::with_capacity(0);
+                        self.write(response).await?;
+                        state = State::default();
+                    } else {
+                        break 'outer;
+                    }
+                }
                 State::RequestTooLarge(receiver) => {
                     if receiver.ingest(&mut iter) {
-                        self.write(b"554 5.3.4 Line is too long.\r\n").await?;
+                        self.write(b"500 5.5.2 Line is too long.\r\n").await?;
                         state = State::default();
                     } else {
                         break 'outer;
@@ -353,10 +567,159 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         self.data.priority = 0;
         self.data.delivery_by = 0;
         self.data.future_release = 0;
+        self.data.mail_from_auth = None;
+        self.data.mail_from_size = 0;
+        self.data.dnsbl_domain_score = 0;
+        self.data.data_in_flight = None;
+        self.data.rcpt_replies.clear();
+    }
+
+    fn lmtp_recipients(&self) -> Vec<String> {
+        if self.instance.protocol == ServerProtocol::Lmtp {
+            self.data
+                .rcpt_to
+                .iter()
+                .map(|rcpt| rcpt.address_lcase.clone())
+                .collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    // LMTP replies once per recipient, relayed messages may carry a
+    // different reply for each of them (RFC 2033 section 4.2)
+    async fn write_data_reply(&mut self, rcpts: Vec<String>, reply: &[u8]) -> Result<(), ()> {
+        if self.instance.protocol == ServerProtocol::Smtp {
+            return self.write(reply).await;
+        }
+
+        let rcpt_replies = std::mem::take(&mut self.data.rcpt_replies);
+        for rcpt in rcpts {
+            let reply = rcpt_replies
+                .iter()
+                .find(|(address, _)| address == &rcpt)
+                .map_or(reply, |(_, reply)| reply.as_slice());
+            self.write(reply).await?;
+        }
+        Ok(())
+    }
+
+    // Clients greeting with HELO are not entitled to ESMTP extensions (RFC 5321 section 2.2.1)
+    pub fn can_use_extensions(&self) -> bool {
+        !self.data.helo_only || self.params.ehlo_helo_extensions == CommandLeniency::Lenient
+    }
+
+    pub async fn tarpit(&self) {
+        // Authenticated sessions are exempt from tarpitting
+        let failures = self.data.rcpt_errors + self.data.auth_errors;
+        if self.params.tarpit_delay.is_zero()
+            || failures == 0
+            || !self.data.authenticated_as.is_empty()
+        {
+            return;
+        }
+
+        // Each consecutive failure multiplies the previous delay, up to the maximum
+        let mut delay = Duration::from_millis(
+            (self.params.tarpit_delay.as_millis() as u64).saturating_mul(
+                self.params
+                    .tarpit_multiplier
+                    .saturating_pow((failures - 1) as u32),
+            ),
+        );
+        if !self.params.tarpit_max_delay.is_zero() {
+            delay = delay.min(self.params.tarpit_max_delay);
+        }
+        tracing::debug!(
+            parent: &self.span,
+            event = "tarpit",
+            failures = failures,
+            delay = delay.as_millis() as u64,
+            "Delaying response."
+        );
+        tokio::time::sleep(delay).await;
+    }
+
+    pub async fn handle_help(&mut self) -> Result<(), ()> {
+        let config = &self.core.session.config.help;
+        if self
+            .core
+            .eval_if(&config.enable, self)
+            .await
+            .unwrap_or(true)
+        {
+            let message = self
+                .core
+                .eval_if::<String, _>(&config.message, self)
+                .await
+                .unwrap_or_else(|| DEFAULT_HELP_MESSAGE.to_string());
+            self.write(build_response("250 2.0.0", &message).as_bytes())
+                .await
+        } else {
+            self.write(b"502 5.5.1 HELP is not available.\r\n").await
+        }
+    }
+
+    pub fn reject_response(
+        &self,
+        template: &Option<ResponseTemplate>,
+        status: &str,
+        recipient: &str,
+        reason: &str,
+    ) -> Vec<u8> {
+        match template {
+            Some(template) => template.render(
+                status,
+                self.data
+                    .mail_from
+                    .as_ref()
+                    .map_or("", |mail_from| mail_from.address.as_str()),
+                recipient,
+                reason,
+            ),
+            None => build_response(status, reason).into_bytes(),
+        }
+    }
+
+    /// Builds a temporary failure response, which includes the configured
+    /// retry hint and defaults to the enhanced status code 4.7.1.
+    pub fn defer_response(&self, status: &str, recipient: &str, reason: &str) -> Vec<u8> {
+        let config = &self.core.session.config.reject;
+        let status = match status.split_once(' ') {
+            Some((code, enhanced_code)) if !enhanced_code.starts_with('4') => {
+                format!("{code} 4{}", enhanced_code.get(1..).unwrap_or_default())
+            }
+            Some(_) => status.to_string(),
+            None => format!("{status} 4.7.1"),
+        };
+
+        match config.retry_after {
+            Some(retry_after) => {
+                let retry_after = retry_after.as_secs();
+                let retry_after = if retry_after >= 60 && retry_after % 60 == 0 {
+                    format!("{} minute(s)", retry_after / 60)
+                } else {
+                    format!("{retry_after} second(s)")
+                };
+                self.reject_response(
+                    &config.deferral,
+                    &status,
+                    recipient,
+                    &format!("{reason} Please try again in {retry_after}."),
+                )
+            }
+            None => self.reject_response(&config.deferral, &status, recipient, reason),
+        }
     }
 
     #[inline(always)]
     pub async fn write(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        let bytes = if !self.params.omit_enhanced_status_codes {
+            Cow::Borrowed(bytes)
+        } else {
+            Cow::Owned(remove_enhanced_status_codes(bytes))
+        };
+        let bytes = bytes.as_ref();
         let err = match self.stream.write_all(bytes).await {
             Ok(_) => match self.stream.flush().await {
                 Ok(_) => {
@@ -402,6 +765,18 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
     }
 }
 
+impl<T: AsyncRead + AsyncWrite> Session<T> {
+    /// Connections from `server.trusted-networks` are exempt from the
+    /// authentication, relay and rate limiting policies.
+    pub fn is_trusted(&self) -> bool {
+        self.core
+            .session
+            .config
+            .trusted_networks
+            .contains_ip(&self.data.remote_ip)
+    }
+}
+
 impl<T: AsyncRead + AsyncWrite> ResolveVariable for Session<T> {
     fn resolve_variable(&self, variable: u32) -> utils::expr::Variable<'_> {
         match variable {
@@ -439,7 +814,154 @@ impl<T: AsyncRead + AsyncWrite> ResolveVariable for Session<T> {
             V_REMOTE_IP => self.data.remote_ip_str.as_str().into(),
             V_LOCAL_IP => self.data.local_ip_str.as_str().into(),
             V_PRIORITY => self.data.priority.to_string().into(),
+            V_IS_TRUSTED => self.is_trusted().into(),
             _ => utils::expr::Variable::default(),
         }
     }
 }
+
+// Removes the RFC 3463 "class.subject.detail" code that follows the reply code
+fn remove_enhanced_status_codes(response: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(response.len());
+    for line in response.split_inclusive(|&ch| ch == b'\n') {
+        match enhanced_status_code_len(line) {
+            0 => result.extend_from_slice(line),
+            len => {
+                result.extend_from_slice(&line[..4]);
+                result.extend_from_slice(&line[4 + len..]);
+            }
+        }
+    }
+    result
+}
+
+fn enhanced_status_code_len(line: &[u8]) -> usize {
+    if line.len() < 4
+        || !line[..3].iter().all(u8::is_ascii_digit)
+        || !matches!(line[3], b' ' | b'-')
+    {
+        return 0;
+    }
+
+    let mut dots = 0;
+    let mut digits = 0;
+    for (pos, &ch) in line[4..].iter().enumerate() {
+        match ch {
+            b'0'..=b'9' if digits < 3 => digits += 1,
+            b'.' if digits > 0 && dots < 2 => {
+                dots += 1;
+                digits = 0;
+            }
+            b' ' if digits > 0 && dots == 2 => return pos + 1,
+            _ => break,
+        }
+    }
+
+    0
+}
+
+// Buffers a SASL response line, keeping at most one byte past the maximum
+// size so that oversized responses are detected without being stored.
+fn ingest_sasl_response(
+    buf: &mut Vec<u8>,
+    bytes: &mut std::slice::Iter<'_, u8>,
+    max_size: usize,
+) -> bool {
+    let rest = bytes.as_slice();
+    let (line, is_complete) = match rest.iter().position(|&ch| ch == b'\n') {
+        Some(pos) => {
+            *bytes = rest[pos + 1..].iter();
+            (&rest[..pos], true)
+        }
+        None => {
+            *bytes = [].iter();
+            (rest, false)
+        }
+    };
+
+    for &ch in line {
+        if ch != b'\r' && buf.len() <= max_size {
+            buf.push(ch);
+        }
+    }
+
+    is_complete
+}
+
+fn is_auth_command(line: &[u8]) -> bool {
+    // RFC 4954 allows AUTH command lines of up to 12288 octets
+    line.get(..5)
+        .map_or(false, |command| command.eq_ignore_ascii_case(b"AUTH "))
+}
+
+// Splits an AUTH command line into the mechanism name and initial response
+fn auth_command(line: &[u8]) -> Option<(&str, &[u8])> {
+    let line = std::str::from_utf8(line).ok()?;
+    if !is_auth_command(line.as_bytes()) {
+        return None;
+    }
+    let mut args = line[5..]
+        .trim_end_matches(['\r', '\n'])
+        .split_ascii_whitespace();
+    Some((args.next()?, args.next().unwrap_or_default().as_bytes()))
+}
+
+fn is_strict_command<'x>(line: impl Iterator<Item = &'x u8>) -> bool {
+    let mut in_verb = true;
+    let mut last_ch = 0;
+
+    for &ch in line {
+        match ch {
+            b'\r' | b'\n' => break,
+            b' ' if !matches!(last_ch, 0 | b' ' | b':') => {
+                in_verb = false;
+            }
+            b' ' | b'\t' => return false,
+            b'a'..=b'z' if in_verb => return false,
+            _ => (),
+        }
+        last_ch = ch;
+    }
+
+    last_ch != b' '
+}
+
+fn has_bare_lf(message: &[u8]) -> bool {
+    message
+        .iter()
+        .enumerate()
+        .any(|(pos, &ch)| ch == b'\n' && (pos == 0 || message[pos - 1] != b'\r'))
+}
+
+fn normalize_bare_lf(message: &[u8]) -> Vec<u8> {
+    // Bare LFs are treated as line endings, so the lines that follow them
+    // are also unstuffed. A "<LF>.<CRLF>" sequence becomes an empty line
+    // rather than a second end of data marker downstream.
+    let mut normalized = Vec::with_capacity(message.len() + 64);
+    let mut after_bare_lf = false;
+    let mut last_ch = 0;
+
+    for &ch in message {
+        match ch {
+            b'\n' if last_ch != b'\r' => {
+                normalized.extend_from_slice(b"\r\n");
+                after_bare_lf = true;
+                last_ch = ch;
+                continue;
+            }
+            b'.' if after_bare_lf => {}
+            _ => normalized.push(ch),
+        }
+        after_bare_lf = false;
+        last_ch = ch;
+    }
+
+    normalized
+}
+
+fn exceeds_line_length(message: &[u8], max_line_length: usize) -> bool {
+    // Line lengths include the trailing CRLF
+    message
+        .split(|&ch| ch == b'\n')
+        .any(|line| line.len() + 1 > max_line_length)
+}
@@ -21,12 +21,16 @@
  * for more details.
 */
 
-use std::{net::IpAddr, time::Instant};
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
 
 use tokio_rustls::server::TlsStream;
 use utils::listener::{SessionManager, SessionStream};
 
 use crate::{
+    config::DnsblType,
     core::{Session, SessionData, SessionParameters, SmtpSessionManager, State},
     queue, reporting,
     scripts::ScriptResult,
@@ -86,6 +90,14 @@ impl<T: SessionStream> Session<T> {
     pub async fn init_conn(&mut self) -> bool {
         self.eval_session_params().await;
 
+        // Remote IP blocklist checks
+        let remote_ip = self.data.remote_ip.to_string();
+        if !self.is_dnsbl_allowed(DnsblType::Ip, &remote_ip).await {
+            let message = self.data.dnsbl_error.take().unwrap_or_default();
+            let _ = self.write(&message).await;
+            return false;
+        }
+
         // Sieve filtering
         if let Some(script) = self
             .core
@@ -120,9 +132,10 @@ impl<T: SessionStream> Session<T> {
         let mut shutdown_rx = self.instance.shutdown_rx.clone();
 
         loop {
+            let (timeout, phase) = self.read_timeout();
             tokio::select! {
                 result = tokio::time::timeout(
-                    self.params.timeout,
+                    timeout,
                     self.read(&mut buf)) => {
                         match result {
                             Ok(Ok(bytes_read)) => {
@@ -181,12 +194,20 @@ impl<T: SessionStream> Session<T> {
                                     parent: &self.span,
                                     event = "disconnect",
                                     reason = "timeout",
+                                    phase = phase,
                                     "Connection timed out."
                                 );
-                                self
-                                    .write(format!("221 2.0.0 {} Disconnecting inactive client.\r\n", self.instance.hostname).as_bytes())
-                                    .await
-                                    .ok();
+                                if let Some(phase) = phase {
+                                    self
+                                        .write(format!("421 4.4.2 {} Timeout exceeded waiting for {}, closing connection.\r\n", self.instance.hostname, phase).as_bytes())
+                                        .await
+                                        .ok();
+                                } else {
+                                    self
+                                        .write(format!("221 2.0.0 {} Disconnecting inactive client.\r\n", self.instance.hostname).as_bytes())
+                                        .await
+                                        .ok();
+                                }
                                 break;
                             }
                         }
@@ -207,6 +228,29 @@ impl<T: SessionStream> Session<T> {
         false
     }
 
+    // The per-phase timeouts from RFC 5321 section 4.5.3.2 can only shorten
+    // the session timeout, the name of the phase is returned when it applies.
+    fn read_timeout(&self) -> (Duration, Option<&'static str>) {
+        let (phase, timeout) = match &self.state {
+            State::Data(_) | State::Bdat(_) | State::DataTooLarge(_) | State::DataRejected(..) => {
+                if self.data.message.is_empty() {
+                    ("data-init", self.params.timeout_data_init)
+                } else {
+                    ("data", self.params.timeout_data)
+                }
+            }
+            _ if self.data.mail_from.is_some() => ("rcpt", self.params.timeout_rcpt),
+            _ if !self.data.helo_domain.is_empty() => ("mail", self.params.timeout_mail),
+            _ => return (self.params.timeout, None),
+        };
+
+        if !timeout.is_zero() && timeout < self.params.timeout {
+            (timeout, Some(phase))
+        } else {
+            (self.params.timeout, None)
+        }
+    }
+
     pub async fn into_tls(self) -> Result<Session<TlsStream<T>>, ()> {
         let span = self.span;
         Ok(Session {
@@ -0,0 +1,143 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_auth::{
+    common::parse::TxtRecordParser,
+    spf::{Macro, Variables},
+};
+use utils::listener::SessionStream;
+
+use crate::core::Session;
+
+#[cfg(feature = "test_mode")]
+pub static SPF_TEST_RECORDS: parking_lot::Mutex<Vec<(String, String)>> =
+    parking_lot::Mutex::new(Vec::new());
+
+impl<T: SessionStream> Session<T> {
+    /// Walks the SPF record of `domain` along with its `include` and `redirect`
+    /// records and returns `false` if evaluating it would need more DNS lookups,
+    /// or more macro-expanded lookups, than the session allows.
+    pub async fn is_spf_within_limits(&self, domain: &str, sender: &str) -> bool {
+        let mut lookups = 0;
+        let mut macro_lookups = 0;
+        let mut pending = vec![domain.to_string()];
+
+        while let Some(domain) = pending.pop() {
+            let record = match self.spf_record(&domain).await {
+                Some(record) => record,
+                None => continue,
+            };
+
+            for term in record.split_ascii_whitespace().skip(1) {
+                let term = term.trim_start_matches(['+', '-', '~', '?']);
+                let (name, domain_spec) =
+                    term.split_at(term.find([':', '=', '/']).unwrap_or(term.len()));
+                let domain_spec = domain_spec.strip_prefix([':', '=']).map_or("", |spec| {
+                    spec.split_once('/').map_or(spec, |(spec, _)| spec)
+                });
+
+                match name.to_ascii_lowercase().as_str() {
+                    name @ ("include" | "redirect") => {
+                        lookups += 1;
+                        if has_macros(domain_spec) {
+                            macro_lookups += 1;
+                        }
+                        if let Some(target) = self.expand_domain_spec(domain_spec, &domain, sender)
+                        {
+                            if name == "redirect" {
+                                // Terms after a redirect are never evaluated
+                                pending.insert(0, target);
+                            } else {
+                                pending.push(target);
+                            }
+                        }
+                    }
+                    "a" | "mx" | "ptr" | "exists" => {
+                        lookups += 1;
+                        if has_macros(domain_spec) {
+                            macro_lookups += 1;
+                        }
+                    }
+                    _ => (),
+                }
+
+                if lookups > self.params.spf_max_lookups
+                    || macro_lookups > self.params.spf_max_macro_lookups
+                {
+                    tracing::debug!(parent: &self.span,
+                        context = "spf",
+                        event = "lookup-limit",
+                        domain = &domain,
+                        lookups = lookups,
+                        macro_lookups = macro_lookups,
+                        "SPF record exceeds the DNS lookup limits.");
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    fn expand_domain_spec(&self, domain_spec: &str, domain: &str, sender: &str) -> Option<String> {
+        let mut vars = Variables::new();
+        vars.set_ip(&self.data.remote_ip);
+        vars.set_sender(sender.as_bytes());
+        vars.set_domain(domain.as_bytes());
+        vars.set_helo_domain(self.data.helo_domain.as_bytes());
+        vars.set_host_domain(self.instance.hostname.as_bytes());
+
+        Macro::parse(domain_spec.as_bytes())
+            .ok()
+            .map(|domain_spec| domain_spec.eval(&vars, "", false).into_owned())
+            .filter(|domain| !domain.is_empty())
+    }
+
+    async fn spf_record(&self, domain: &str) -> Option<String> {
+        #[cfg(not(feature = "test_mode"))]
+        let record = self
+            .core
+            .resolvers
+            .dns
+            .txt_raw_lookup(domain)
+            .await
+            .ok()
+            .and_then(|record| String::from_utf8(record).ok());
+        #[cfg(feature = "test_mode")]
+        let record = SPF_TEST_RECORDS
+            .lock()
+            .iter()
+            .find(|(name, _)| name == domain.trim_end_matches('.'))
+            .map(|(_, record)| record.clone());
+
+        record.filter(|record| {
+            record
+                .get(..7)
+                .map_or(false, |v| v.eq_ignore_ascii_case("v=spf1 "))
+        })
+    }
+}
+
+fn has_macros(domain_spec: &str) -> bool {
+    domain_spec.contains("%{")
+}
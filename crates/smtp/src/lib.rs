@@ -91,6 +91,7 @@ impl SMTP {
                     tls_implicit: Default::default(),
                     tls_allow_invalid_certs: Default::default(),
                     auth: None,
+                    concurrency: None,
                 },
             );
         }
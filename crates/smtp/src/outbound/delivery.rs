@@ -37,7 +37,7 @@ use store::write::{now, BatchBuilder, QueueClass, QueueEvent, ValueClass};
 use utils::config::ServerProtocol;
 
 use crate::{
-    config::{AggregateFrequency, RequireOptional, TlsStrategy},
+    config::{AggregateFrequency, RelayHost, RequireOptional, TlsStrategy},
     core::SMTP,
     queue::{ErrorDetails, Message},
     reporting::{tls::TlsRptOptions, PolicyType, TlsEvent},
@@ -662,6 +662,26 @@ impl DeliveryAttempt {
                             }
                         }
 
+                        // Limit simultaneous sessions to the relay host,
+                        // messages beyond the limit wait in the queue
+                        if let NextHop::Relay(RelayHost {
+                            concurrency: Some(limiter),
+                            ..
+                        }) = remote_host
+                        {
+                            if let Some(inflight) = limiter.is_allowed() {
+                                in_flight_host.push(inflight);
+                            } else {
+                                domain.set_throttle_error(
+                                    throttle::Error::Concurrency {
+                                        limiter: limiter.clone(),
+                                    },
+                                    &mut on_hold,
+                                );
+                                continue 'next_domain;
+                            }
+                        }
+
                         // Connect
                         let conn_timeout = core
                             .eval_if(&queue_config.timeout.connect, &envelope)
@@ -735,6 +755,10 @@ impl DeliveryAttempt {
                                 .eval_if(&queue_config.timeout.data, &envelope)
                                 .await
                                 .unwrap_or_else(|| Duration::from_secs(5 * 60)),
+                            downgrade_8bitmime: core
+                                .eval_if(&queue_config.downgrade_8bitmime, &envelope)
+                                .await
+                                .unwrap_or_default(),
                         };
 
                         // Prepare TLS connector
@@ -958,6 +982,22 @@ impl DeliveryAttempt {
                                         continue 'next_host;
                                     }
                                 }
+                            } else if (message.flags & MAIL_REQUIRETLS) != 0 {
+                                // REQUIRETLS forbids falling back to plain-text
+                                tracing::info!(
+                                    parent: &span,
+                                    context = "tls",
+                                    event = "required",
+                                    mx = envelope.mx,
+                                    reason = "REQUIRETLS was requested but TLS is disabled for this host",
+                                );
+
+                                last_status =
+                                    Status::PermanentFailure(Error::TlsError(ErrorDetails {
+                                        entity: envelope.mx.to_string(),
+                                        details: "TLS is disabled for this host.".to_string(),
+                                    }));
+                                continue 'next_host;
                             } else {
                                 // TLS has been disabled
                                 tracing::info!(
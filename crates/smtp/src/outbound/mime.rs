@@ -0,0 +1,77 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_builder::encoders::quoted_printable::quoted_printable_encode;
+use mail_parser::{Encoding, HeaderName, MessageParser, PartType};
+
+/// Converts any 8-bit MIME body parts to quoted-printable so the message can be
+/// relayed to hosts that do not support 8BITMIME. Returns `None` when the message
+/// cannot be made 7-bit clean, for example because it contains 8-bit headers.
+pub fn downgrade_8bitmime(raw_message: &[u8]) -> Option<Vec<u8>> {
+    let message = MessageParser::default().parse(raw_message)?;
+    let mut output = Vec::with_capacity(raw_message.len() + raw_message.len() / 4);
+    let mut last_offset = 0;
+
+    for part in &message.parts {
+        let body = raw_message.get(part.offset_body..part.offset_end)?;
+        if !matches!(
+            part.body,
+            PartType::Text(_) | PartType::Html(_) | PartType::Binary(_) | PartType::InlineBinary(_)
+        ) || part.encoding != Encoding::None
+            || body.is_ascii()
+        {
+            continue;
+        }
+
+        // Remove any existing Content-Transfer-Encoding headers
+        for header in &part.headers {
+            if header.name == HeaderName::ContentTransferEncoding {
+                output.extend_from_slice(raw_message.get(last_offset..header.offset_field)?);
+                last_offset = header.offset_end;
+            }
+        }
+
+        // Add the new header right before the empty line that ends the header block
+        let headers = raw_message.get(..part.offset_body)?;
+        let headers_end = if headers.ends_with(b"\r\n\r\n") {
+            part.offset_body - 2
+        } else if headers.ends_with(b"\n\n") {
+            part.offset_body - 1
+        } else {
+            return None;
+        };
+        output.extend_from_slice(raw_message.get(last_offset..headers_end)?);
+        output.extend_from_slice(b"Content-Transfer-Encoding: quoted-printable\r\n");
+        output.extend_from_slice(&raw_message[headers_end..part.offset_body]);
+        quoted_printable_encode(body, &mut output, false, true).ok()?;
+        last_offset = part.offset_end;
+    }
+    output.extend_from_slice(raw_message.get(last_offset..)?);
+
+    // Headers and nested messages cannot be downgraded
+    if output.is_ascii() {
+        Some(output)
+    } else {
+        None
+    }
+}
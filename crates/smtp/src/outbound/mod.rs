@@ -37,6 +37,7 @@ pub mod delivery;
 #[cfg(feature = "local_delivery")]
 pub mod local;
 pub mod lookup;
+pub mod mime;
 pub mod mta_sts;
 pub mod session;
 
@@ -23,9 +23,10 @@
 
 use mail_send::{smtp::AssertReply, Credentials, SmtpClient};
 use smtp_proto::{
-    EhloResponse, Response, Severity, EXT_CHUNKING, EXT_DSN, EXT_REQUIRE_TLS, EXT_SIZE,
-    EXT_SMTP_UTF8, EXT_START_TLS, MAIL_REQUIRETLS, MAIL_RET_FULL, MAIL_RET_HDRS, MAIL_SMTPUTF8,
-    RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
+    EhloResponse, Response, Severity, EXT_8BIT_MIME, EXT_AUTH, EXT_CHUNKING, EXT_DSN,
+    EXT_REQUIRE_TLS, EXT_SIZE, EXT_SMTP_UTF8, EXT_START_TLS, MAIL_REQUIRETLS, MAIL_RET_FULL,
+    MAIL_RET_HDRS, MAIL_SMTPUTF8, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER,
+    RCPT_NOTIFY_SUCCESS,
 };
 use std::fmt::Write;
 use std::time::Duration;
@@ -36,11 +37,13 @@ use tokio::{
 use tokio_rustls::{client::TlsStream, TlsConnector};
 
 use crate::{
-    config::{RequireOptional, TlsStrategy},
+    config::{Downgrade8BitMime, RequireOptional, TlsStrategy},
     core::SMTP,
     queue::{ErrorDetails, HostResponse, RCPT_STATUS_CHANGED},
 };
 
+use super::mime::downgrade_8bitmime;
+
 use crate::queue::{Error, Message, Recipient, Status};
 
 pub struct SessionParams<'x> {
@@ -54,6 +57,7 @@ pub struct SessionParams<'x> {
     pub timeout_mail: Duration,
     pub timeout_rcpt: Duration,
     pub timeout_data: Duration,
+    pub downgrade_8bitmime: Downgrade8BitMime,
 }
 
 impl Message {
@@ -111,9 +115,64 @@ impl Message {
             };*/
         }
 
+        // Messages sent with REQUIRETLS can only be relayed to hosts supporting it
+        if self.has_flag(MAIL_REQUIRETLS) && !capabilities.has_capability(EXT_REQUIRE_TLS) {
+            tracing::info!(
+                parent: params.span,
+                context = "requiretls",
+                event = "rejected",
+                mx = &params.hostname,
+                "REQUIRETLS was requested but is not supported by host."
+            );
+            quit(smtp_client).await;
+            return Status::PermanentFailure(Error::TlsError(ErrorDetails {
+                entity: params.hostname.to_string(),
+                details: "REQUIRETLS not advertised by host.".to_string(),
+            }));
+        }
+
+        // Fetch message
+        let mut raw_message = match fetch_message(self, &params).await {
+            Ok(raw_message) => raw_message,
+            Err(status) => {
+                quit(smtp_client).await;
+                return status;
+            }
+        };
+
+        // Downgrade 8-bit content if the host does not support 8BITMIME
+        if !capabilities.has_capability(EXT_8BIT_MIME) && !raw_message.is_ascii() {
+            match (params.downgrade_8bitmime, downgrade_8bitmime(&raw_message)) {
+                (Downgrade8BitMime::Convert, Some(downgraded_message)) => {
+                    tracing::debug!(
+                        parent: params.span,
+                        context = "8bitmime",
+                        event = "downgrade",
+                        mx = &params.hostname,
+                        "Converted 8-bit message to 7-bit for host without 8BITMIME support."
+                    );
+                    raw_message = downgraded_message;
+                }
+                _ => {
+                    tracing::info!(
+                        parent: params.span,
+                        context = "8bitmime",
+                        event = "rejected",
+                        mx = &params.hostname,
+                        "Message contains 8-bit content that cannot be relayed to host."
+                    );
+                    quit(smtp_client).await;
+                    return Status::PermanentFailure(Error::ConnectionError(ErrorDetails {
+                        entity: params.hostname.to_string(),
+                        details: "8BITMIME not advertised by host.".to_string(),
+                    }));
+                }
+            }
+        }
+
         // MAIL FROM
         smtp_client.timeout = params.timeout_mail;
-        let cmd = self.build_mail_from(&capabilities);
+        let cmd = self.build_mail_from(&capabilities, &raw_message);
         if let Err(err) = smtp_client
             .cmd(cmd.as_bytes())
             .await
@@ -203,12 +262,14 @@ impl Message {
         // Send message
         if !accepted_rcpts.is_empty() {
             let bdat_cmd = if capabilities.has_capability(EXT_CHUNKING) {
-                format!("BDAT {} LAST\r\n", self.size).into()
+                format!("BDAT {} LAST\r\n", raw_message.len()).into()
             } else {
                 None
             };
 
-            if let Err(status) = send_message(&mut smtp_client, self, &bdat_cmd, &params).await {
+            if let Err(status) =
+                send_message(&mut smtp_client, &raw_message, &bdat_cmd, &params).await
+            {
                 tracing::info!(
                     parent: params.span,
                     context = "message",
@@ -354,11 +415,14 @@ impl Message {
         }
     }
 
-    fn build_mail_from(&self, capabilities: &EhloResponse<String>) -> String {
+    fn build_mail_from(&self, capabilities: &EhloResponse<String>, raw_message: &[u8]) -> String {
         let mut mail_from = String::with_capacity(self.return_path.len() + 60);
         let _ = write!(mail_from, "MAIL FROM:<{}>", self.return_path);
         if capabilities.has_capability(EXT_SIZE) {
-            let _ = write!(mail_from, " SIZE={}", self.size);
+            let _ = write!(mail_from, " SIZE={}", raw_message.len());
+        }
+        if capabilities.has_capability(EXT_8BIT_MIME) && !raw_message.is_ascii() {
+            mail_from.push_str(" BODY=8BITMIME");
         }
         if self.has_flag(MAIL_REQUIRETLS) & capabilities.has_capability(EXT_REQUIRE_TLS) {
             mail_from.push_str(" REQUIRETLS");
@@ -376,6 +440,12 @@ impl Message {
                 let _ = write!(mail_from, " ENVID={env_id}");
             }
         }
+        if capabilities.has_capability(EXT_AUTH) {
+            if let Some(auth) = &self.auth {
+                mail_from.push_str(" AUTH=");
+                xtext_encode(&mut mail_from, auth);
+            }
+        }
 
         mail_from.push_str("\r\n");
         mail_from
@@ -527,12 +597,10 @@ pub async fn write_chunks<T: AsyncRead + AsyncWrite + Unpin>(
         .map_err(mail_send::Error::from)
 }
 
-pub async fn send_message<T: AsyncRead + AsyncWrite + Unpin>(
-    smtp_client: &mut SmtpClient<T>,
+pub async fn fetch_message(
     message: &Message,
-    bdat_cmd: &Option<String>,
     params: &SessionParams<'_>,
-) -> Result<(), Status<(), Error>> {
+) -> Result<Vec<u8>, Status<(), Error>> {
     match params
         .core
         .shared
@@ -540,23 +608,7 @@ pub async fn send_message<T: AsyncRead + AsyncWrite + Unpin>(
         .get_blob(message.blob_hash.as_slice(), 0..usize::MAX)
         .await
     {
-        Ok(Some(raw_message)) => tokio::time::timeout(params.timeout_data, async {
-            if let Some(bdat_cmd) = bdat_cmd {
-                write_chunks(smtp_client, &[bdat_cmd.as_bytes(), &raw_message]).await
-            } else {
-                write_chunks(smtp_client, &[b"DATA\r\n"]).await?;
-                smtp_client.read().await?.assert_code(354)?;
-                smtp_client
-                    .write_message(&raw_message)
-                    .await
-                    .map_err(mail_send::Error::from)
-            }
-        })
-        .await
-        .map_err(|_| Status::timeout(params.hostname, "sending message"))?
-        .map_err(|err| {
-            Status::from_smtp_error(params.hostname, bdat_cmd.as_deref().unwrap_or("DATA"), err)
-        }),
+        Ok(Some(raw_message)) => Ok(raw_message),
         Ok(None) => {
             tracing::error!(parent: params.span,
             context = "queue",
@@ -582,6 +634,31 @@ pub async fn send_message<T: AsyncRead + AsyncWrite + Unpin>(
     }
 }
 
+pub async fn send_message<T: AsyncRead + AsyncWrite + Unpin>(
+    smtp_client: &mut SmtpClient<T>,
+    raw_message: &[u8],
+    bdat_cmd: &Option<String>,
+    params: &SessionParams<'_>,
+) -> Result<(), Status<(), Error>> {
+    tokio::time::timeout(params.timeout_data, async {
+        if let Some(bdat_cmd) = bdat_cmd {
+            write_chunks(smtp_client, &[bdat_cmd.as_bytes(), raw_message]).await
+        } else {
+            write_chunks(smtp_client, &[b"DATA\r\n"]).await?;
+            smtp_client.read().await?.assert_code(354)?;
+            smtp_client
+                .write_message(raw_message)
+                .await
+                .map_err(mail_send::Error::from)
+        }
+    })
+    .await
+    .map_err(|_| Status::timeout(params.hostname, "sending message"))?
+    .map_err(|err| {
+        Status::from_smtp_error(params.hostname, bdat_cmd.as_deref().unwrap_or("DATA"), err)
+    })
+}
+
 pub async fn say_helo<T: AsyncRead + AsyncWrite + Unpin>(
     smtp_client: &mut SmtpClient<T>,
     params: &SessionParams<'_>,
@@ -655,3 +732,14 @@ impl TlsStrategy {
             || self.is_mta_sts_required()
     }
 }
+
+// Encodes a value as xtext (RFC 3461, section 4)
+fn xtext_encode(buf: &mut String, value: &str) {
+    for byte in value.bytes() {
+        if (33..=126).contains(&byte) && byte != b'+' && byte != b'=' {
+            buf.push(byte as char);
+        } else {
+            let _ = write!(buf, "+{byte:02X}");
+        }
+    }
+}
@@ -27,7 +27,8 @@ use mail_builder::mime::{make_boundary, BodyPart, MimePart};
 use mail_builder::MessageBuilder;
 use mail_parser::DateTime;
 use smtp_proto::{
-    Response, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
+    Response, MAIL_RET_FULL, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER,
+    RCPT_NOTIFY_SUCCESS,
 };
 use std::fmt::Write;
 use std::time::Duration;
@@ -267,13 +268,34 @@ impl Message {
         self.write_dsn_headers(&mut dsn_header, &reporting_mta);
         let dsn = dsn_header + &dsn;
 
-        // Fetch up to 1024 bytes of message headers
-        let headers = match core
+        // Fetch the entire message if requested with RET=FULL and within the
+        // maximum size, otherwise up to 1024 bytes of message headers
+        let max_size = core
+            .eval_if(&config.dsn.max_size, self)
+            .await
+            .unwrap_or(10 * 1024 * 1024);
+        let return_full = (self.flags & MAIL_RET_FULL) != 0
+            && (max_size == 0 || self.size <= max_size || {
+                tracing::debug!(
+                    parent: span,
+                    context = "queue",
+                    event = "dsn-truncated",
+                    size = self.size,
+                    max_size = max_size,
+                    "Original message too large, returning headers only."
+                );
+                false
+            });
+        let original = match core
             .shared
             .default_blob_store
-            .get_blob(self.blob_hash.as_slice(), 0..1024)
+            .get_blob(
+                self.blob_hash.as_slice(),
+                0..if return_full { usize::MAX } else { 1024 },
+            )
             .await
         {
+            Ok(Some(buf)) if return_full => buf,
             Ok(Some(mut buf)) => {
                 let mut prev_ch = 0;
                 let mut last_lf = buf.len();
@@ -297,7 +319,7 @@ impl Message {
                 if last_lf < 1024 {
                     buf.truncate(last_lf);
                 }
-                String::from_utf8(buf).unwrap_or_default()
+                buf
             }
             Ok(None) => {
                 tracing::error!(
@@ -307,7 +329,7 @@ impl Message {
                     "Failed to open blob {:?}: not found",
                     self.blob_hash
                 );
-                String::new()
+                Vec::new()
             }
             Err(err) => {
                 tracing::error!(
@@ -318,10 +340,23 @@ impl Message {
                     self.blob_hash,
                     err
                 );
-                String::new()
+                Vec::new()
             }
         };
 
+        // 8-bit originals are included as-is rather than re-encoded
+        let original = match String::from_utf8(original) {
+            Ok(text) => MimePart::new(
+                ContentType::new("message/rfc822"),
+                BodyPart::Text(text.into()),
+            ),
+            Err(err) => MimePart::new(
+                ContentType::new("message/rfc822"),
+                BodyPart::Binary(err.into_bytes().into()),
+            )
+            .header("Content-Transfer-Encoding", HeaderType::Text("8bit".into())),
+        };
+
         // Build message
         MessageBuilder::new()
             .from((from_name.as_str(), from_addr.as_str()))
@@ -337,10 +372,7 @@ impl Message {
                         ContentType::new("message/delivery-status"),
                         BodyPart::Text(dsn.into()),
                     ),
-                    MimePart::new(
-                        ContentType::new("message/rfc822"),
-                        BodyPart::Text(headers.into()),
-                    ),
+                    original,
                 ]),
             ))
             .write_to_vec()
@@ -29,7 +29,7 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 use smtp_proto::Response;
-use store::write::now;
+use store::write::{now, Bincode};
 use utils::{
     listener::limiter::{ConcurrencyLimiter, InFlight},
     BlobHash,
@@ -85,6 +85,58 @@ pub struct Message {
 
     pub size: usize,
     pub quota_keys: Vec<QuotaKey>,
+
+    pub auth: Option<String>,
+}
+
+// Layout of messages queued before the AUTH parameter was stored
+#[derive(serde::Serialize, serde::Deserialize)]
+struct LegacyMessage {
+    id: QueueId,
+    created: u64,
+    blob_hash: BlobHash,
+    return_path: String,
+    return_path_lcase: String,
+    return_path_domain: String,
+    recipients: Vec<Recipient>,
+    domains: Vec<Domain>,
+    flags: u64,
+    env_id: Option<String>,
+    priority: i16,
+    size: usize,
+    quota_keys: Vec<QuotaKey>,
+}
+
+impl store::Deserialize for Message {
+    fn deserialize(bytes: &[u8]) -> store::Result<Self> {
+        match <Bincode<Message> as store::Deserialize>::deserialize(bytes) {
+            Ok(message) => Ok(message.inner),
+            Err(err) => <Bincode<LegacyMessage> as store::Deserialize>::deserialize(bytes)
+                .map(|message| message.inner.into())
+                .map_err(|_| err),
+        }
+    }
+}
+
+impl From<LegacyMessage> for Message {
+    fn from(message: LegacyMessage) -> Self {
+        Message {
+            id: message.id,
+            created: message.created,
+            blob_hash: message.blob_hash,
+            return_path: message.return_path,
+            return_path_lcase: message.return_path_lcase,
+            return_path_domain: message.return_path_domain,
+            recipients: message.recipients,
+            domains: message.domains,
+            flags: message.flags,
+            env_id: message.env_id,
+            priority: message.priority,
+            size: message.size,
+            quota_keys: message.quota_keys,
+            auth: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -23,13 +23,13 @@
 
 use crate::queue::DomainPart;
 use std::borrow::Cow;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use store::write::key::DeserializeBigEndian;
 use store::write::{now, BatchBuilder, Bincode, BlobOp, QueueClass, QueueEvent, ValueClass};
 use store::{Deserialize, IterateParams, Serialize, ValueKey, U64_LEN};
 use utils::BlobHash;
 
-use crate::core::{QueueCore, SMTP};
+use crate::core::{backpressure::BackpressureSource, QueueCore, SMTP};
 
 use super::{
     Domain, Event, Message, QueueId, QuotaKey, Recipient, Schedule, SimpleEnvelope, Status,
@@ -66,6 +66,7 @@ impl QueueCore {
             domains: Vec::with_capacity(1),
             flags: 0,
             env_id: None,
+            auth: None,
             priority: 0,
             size: 0,
             blob_hash: Default::default(),
@@ -169,16 +170,93 @@ impl SMTP {
         }
     }
 
+    /// Reschedules all pending deliveries to `domain` (and optionally its subdomains)
+    /// for immediate delivery, returning the number of messages released.
+    pub async fn release_domain(&self, domain: &str, include_subdomains: bool) -> usize {
+        let from_key = ValueKey::from(ValueClass::Queue(QueueClass::Message(0)));
+        let to_key = ValueKey::from(ValueClass::Queue(QueueClass::Message(u64::MAX)));
+        let subdomain = format!(".{domain}");
+        let is_match = |name: &str| {
+            name == domain || (include_subdomains && name.ends_with(subdomain.as_str()))
+        };
+
+        let mut messages = Vec::new();
+        let result = self
+            .shared
+            .default_data_store
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending(),
+                |key, value| {
+                    // A single unreadable entry does not prevent releasing the rest
+                    match Message::deserialize(value) {
+                        Ok(message) => {
+                            if message.domains.iter().any(|d| {
+                                matches!(d.status, Status::Scheduled | Status::TemporaryFailure(_))
+                                    && is_match(&d.domain)
+                            }) {
+                                messages.push(message);
+                            }
+                        }
+                        Err(err) => {
+                            tracing::warn!(
+                                context = "queue",
+                                event = "error",
+                                key = ?key,
+                                "Failed to deserialize queued message: {}",
+                                err
+                            );
+                        }
+                    }
+                    Ok(true)
+                },
+            )
+            .await;
+
+        if let Err(err) = result {
+            tracing::error!(
+                context = "queue",
+                event = "error",
+                "Failed to read from store: {}",
+                err
+            );
+            return 0;
+        }
+
+        let now = now();
+        let released = messages.len();
+        for mut message in messages {
+            let prev_event = message.next_event().unwrap_or_default();
+            for domain in &mut message.domains {
+                if matches!(
+                    domain.status,
+                    Status::Scheduled | Status::TemporaryFailure(_)
+                ) && is_match(&domain.domain)
+                    && domain.retry.due > now
+                {
+                    domain.retry.due = now;
+                }
+            }
+            let next_event = message.next_event().unwrap_or_default();
+            message
+                .save_changes(self, prev_event.into(), next_event.into())
+                .await;
+        }
+
+        if released > 0 {
+            let _ = self.queue.tx.send(Event::Reload).await;
+        }
+
+        released
+    }
+
     pub async fn read_message(&self, id: QueueId) -> Option<Message> {
         match self
             .shared
             .default_data_store
-            .get_value::<Bincode<Message>>(ValueKey::from(ValueClass::Queue(QueueClass::Message(
-                id,
-            ))))
+            .get_value::<Message>(ValueKey::from(ValueClass::Queue(QueueClass::Message(id))))
             .await
         {
-            Ok(Some(message)) => Some(message.inner),
+            Ok(Some(message)) => Some(message),
             Ok(None) => None,
             Err(err) => {
                 tracing::error!(
@@ -226,7 +304,10 @@ impl Message {
             },
             0u32.serialize(),
         );
-        if let Err(err) = core.shared.default_data_store.write(batch.build()).await {
+        let time = Instant::now();
+        let result = core.shared.default_data_store.write(batch.build()).await;
+        core.record_latency(BackpressureSource::DataStore, time);
+        if let Err(err) = result {
             tracing::error!(
                 parent: span,
                 context = "queue",
@@ -236,12 +317,14 @@ impl Message {
             );
             return false;
         }
-        if let Err(err) = core
+        let time = Instant::now();
+        let result = core
             .shared
             .default_blob_store
             .put_blob(self.blob_hash.as_slice(), message.as_ref())
-            .await
-        {
+            .await;
+        core.record_latency(BackpressureSource::BlobStore, time);
+        if let Err(err) = result {
             tracing::error!(
                 parent: span,
                 context = "queue",
@@ -303,7 +386,10 @@ impl Message {
                 Bincode::new(self).serialize(),
             );
 
-        if let Err(err) = core.shared.default_data_store.write(batch.build()).await {
+        let time = Instant::now();
+        let result = core.shared.default_data_store.write(batch.build()).await;
+        core.record_latency(BackpressureSource::DataStore, time);
+        if let Err(err) = result {
             tracing::error!(
                 parent: span,
                 context = "queue",
@@ -34,7 +34,11 @@ use smtp_proto::{
 };
 use tokio::runtime::Handle;
 
-use crate::{core::SMTP, queue::DomainPart};
+use crate::{
+    core::SMTP,
+    inbound::{build_response, split_response},
+    queue::DomainPart,
+};
 
 use super::{plugins::PluginContext, ScriptModification, ScriptParameters, ScriptResult};
 
@@ -359,20 +363,11 @@ impl SMTP {
 
         if keep_id == 0 {
             ScriptResult::Accept { modifications }
-        } else if let Some(mut reject_reason) = reject_reason {
-            if !reject_reason.ends_with('\n') {
-                reject_reason.push_str("\r\n");
-            }
-            let mut reject_bytes = reject_reason.as_bytes().iter();
-            if matches!(reject_bytes.next(), Some(ch) if ch.is_ascii_digit())
-                && matches!(reject_bytes.next(), Some(ch) if ch.is_ascii_digit())
-                && matches!(reject_bytes.next(), Some(ch) if ch.is_ascii_digit())
-                && matches!(reject_bytes.next(), Some(ch) if ch == &b' ' )
-            {
-                ScriptResult::Reject(reject_reason)
-            } else {
-                ScriptResult::Reject(format!("503 5.5.3 {reject_reason}"))
-            }
+        } else if let Some(reject_reason) = reject_reason {
+            ScriptResult::Reject(match split_response(&reject_reason) {
+                Some((status, text)) => build_response(status, text),
+                None => build_response("503 5.5.3", &reject_reason),
+            })
         } else if keep_id != usize::MAX - 1 {
             if let Some(message) = messages.into_iter().nth(keep_id - 1) {
                 ScriptResult::Replace {
@@ -55,6 +55,11 @@ impl MysqlStore {
         if let Some(port) = config.property_((&prefix, "port")) {
             opts = opts.tcp_port(port);
         }
+        opts = opts.tcp_keepalive(
+            config
+                .property_or_default_::<Option<Duration>>((&prefix, "pool.keepalive"), "60s")?
+                .map(|t| t.as_millis() as u32),
+        );
 
         if config
             .property_or_default_::<bool>((&prefix, "tls.allow-invalid-certs"), "false")
@@ -35,7 +35,14 @@ pub struct MysqlStore {
 
 impl From<mysql_async::Error> for crate::Error {
     fn from(err: mysql_async::Error) -> Self {
-        Self::InternalError(format!("mySQL error: {}", err))
+        match &err {
+            // Connection failures, deadlocks (1213) and lock wait timeouts (1205)
+            mysql_async::Error::Io(_) => Self::TransientError(format!("mySQL error: {}", err)),
+            mysql_async::Error::Server(server) if matches!(server.code, 1205 | 1213) => {
+                Self::TransientError(format!("mySQL error: {}", err))
+            }
+            _ => Self::InternalError(format!("mySQL error: {}", err)),
+        }
     }
 }
 
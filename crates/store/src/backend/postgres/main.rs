@@ -28,6 +28,8 @@ use crate::{
 
 use super::PostgresStore;
 
+use std::time::Duration;
+
 use deadpool_postgres::{
     Config, CreatePoolError, ManagerConfig, PoolConfig, RecyclingMethod, Runtime,
 };
@@ -47,6 +49,9 @@ impl PostgresStore {
         cfg.password = config.value((&prefix, "password")).map(|s| s.to_string());
         cfg.port = config.property_((&prefix, "port"));
         cfg.connect_timeout = config.property_((&prefix, "timeout"));
+        cfg.keepalives_idle =
+            config.property_or_default_::<Option<Duration>>((&prefix, "pool.keepalive"), "60s")?;
+        cfg.keepalives = cfg.keepalives_idle.is_some().into();
         cfg.manager = Some(ManagerConfig {
             recycling_method: RecyclingMethod::Fast,
         });
@@ -22,6 +22,7 @@
 */
 
 use deadpool_postgres::{Pool, PoolError};
+use tokio_postgres::error::SqlState;
 
 pub mod blob;
 pub mod lookup;
@@ -36,13 +37,29 @@ pub struct PostgresStore {
 
 impl From<PoolError> for crate::Error {
     fn from(err: PoolError) -> Self {
-        Self::InternalError(format!("Connection pool error: {}", err))
+        Self::TransientError(format!("Connection pool error: {}", err))
     }
 }
 
 impl From<tokio_postgres::Error> for crate::Error {
     fn from(err: tokio_postgres::Error) -> Self {
-        Self::InternalError(format!("PostgreSQL error: {}", err))
+        let is_transient = err.is_closed()
+            || err.code().map_or(false, |code| {
+                [
+                    &SqlState::T_R_SERIALIZATION_FAILURE,
+                    &SqlState::T_R_DEADLOCK_DETECTED,
+                    &SqlState::CONNECTION_EXCEPTION,
+                    &SqlState::CONNECTION_FAILURE,
+                    &SqlState::ADMIN_SHUTDOWN,
+                ]
+                .contains(&code)
+            });
+
+        if is_transient {
+            Self::TransientError(format!("PostgreSQL error: {}", err))
+        } else {
+            Self::InternalError(format!("PostgreSQL error: {}", err))
+        }
     }
 }
 
@@ -31,7 +31,10 @@ use redis::{
     cluster::{ClusterClient, ClusterClientBuilder},
     Client, RedisError,
 };
-use utils::config::{utils::AsKey, Config};
+use utils::config::{
+    utils::{AsKey, DurationRange},
+    Config,
+};
 
 pub mod lookup;
 pub mod pool;
@@ -154,6 +157,9 @@ impl RedisStore {
     }
 }
 
+// Pool timeouts between 100ms and 1h, longer values are clamped
+type PoolTimeout = DurationRange<100, 3_600_000>;
+
 fn build_pool<M: Manager>(
     config: &mut Config,
     prefix: &str,
@@ -168,12 +174,20 @@ fn build_pool<M: Manager>(
         )
         .create_timeout(
             config
-                .property_or_default_::<Duration>((prefix, "pool.create-timeout"), "30s")
-                .unwrap_or_else(|| Duration::from_secs(30))
+                .property_or_default_::<PoolTimeout>((prefix, "pool.create-timeout"), "30s")
+                .map_or_else(|| Duration::from_secs(30), Duration::from)
                 .into(),
         )
-        .wait_timeout(config.property_or_default_((prefix, "pool.wait-timeout"), "30s"))
-        .recycle_timeout(config.property_or_default_((prefix, "pool.recycle-timeout"), "30s"))
+        .wait_timeout(
+            config
+                .property_or_default_::<PoolTimeout>((prefix, "pool.wait-timeout"), "30s")
+                .map(Duration::from),
+        )
+        .recycle_timeout(
+            config
+                .property_or_default_::<PoolTimeout>((prefix, "pool.recycle-timeout"), "30s")
+                .map(Duration::from),
+        )
         .build()
         .map_err(|err| {
             format!(
@@ -34,13 +34,18 @@ pub mod write;
 
 impl From<r2d2::Error> for crate::Error {
     fn from(err: r2d2::Error) -> Self {
-        Self::InternalError(format!("Connection pool error: {}", err))
+        Self::TransientError(format!("Connection pool error: {}", err))
     }
 }
 
 impl From<rusqlite::Error> for crate::Error {
     fn from(err: rusqlite::Error) -> Self {
-        Self::InternalError(format!("SQLite error: {}", err))
+        match err.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked) => {
+                Self::TransientError(format!("SQLite error: {}", err))
+            }
+            _ => Self::InternalError(format!("SQLite error: {}", err)),
+        }
     }
 }
 
@@ -515,7 +515,7 @@ impl ConfigStore for Config {
 impl From<crate::Error> for String {
     fn from(err: crate::Error) -> Self {
         match err {
-            crate::Error::InternalError(err) => err,
+            crate::Error::InternalError(err) | crate::Error::TransientError(err) => err,
             crate::Error::AssertValueFailed => unimplemented!(),
         }
     }
@@ -149,6 +149,7 @@ impl Default for BlobClass {
 #[derive(Debug, PartialEq, Eq)]
 pub enum Error {
     InternalError(String),
+    TransientError(String),
     AssertValueFailed,
 }
 
@@ -158,6 +159,7 @@ impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::InternalError(msg) => write!(f, "Internal Error: {}", msg),
+            Error::TransientError(msg) => write!(f, "Transient Error: {}", msg),
             Error::AssertValueFailed => write!(f, "Transaction failed: Hash mismatch"),
         }
     }
@@ -240,7 +240,111 @@ impl Store {
             }
         }
 
-        // Delete hashes
+        self.clear_blob_keys(delete_keys).await
+    }
+
+    pub async fn purge_tmp_blobs(&self, blob_store: BlobStore) -> crate::Result<()> {
+        // Find expired temporary blobs
+        let from_key = ValueKey {
+            account_id: 0,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::Blob(BlobOp::Reserve {
+                until: 0,
+                hash: BlobHash::default(),
+            }),
+        };
+        let to_key = ValueKey {
+            account_id: u32::MAX,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::Blob(BlobOp::Reserve {
+                until: 0,
+                hash: BlobHash::default(),
+            }),
+        };
+        let mut delete_keys = Vec::new();
+        let mut expired_hashes = AHashSet::new();
+        let mut active_hashes = AHashSet::new();
+        let now = now();
+        self.iterate(
+            IterateParams::new(from_key, to_key).ascending().no_values(),
+            |key, _| {
+                let hash = BlobHash::try_from_hash_slice(
+                    key.get(1 + U32_LEN..1 + U32_LEN + BLOB_HASH_LEN)
+                        .ok_or_else(|| {
+                            crate::Error::InternalError(format!(
+                                "Invalid key {key:?} in blob hash tables"
+                            ))
+                        })?,
+                )
+                .unwrap();
+                let until = key.deserialize_be_u64(key.len() - U64_LEN)?;
+                if until <= now {
+                    delete_keys.push(ValueKey {
+                        account_id: key.deserialize_be_u32(1)?,
+                        collection: 0,
+                        document_id: 0,
+                        class: ValueClass::Blob(BlobOp::Reserve {
+                            until,
+                            hash: hash.clone(),
+                        }),
+                    });
+                    expired_hashes.insert(hash);
+                } else {
+                    active_hashes.insert(hash);
+                }
+                Ok(true)
+            },
+        )
+        .await?;
+
+        // Delete expired blobs that are not reserved or linked elsewhere
+        for hash in expired_hashes {
+            if active_hashes.contains(&hash) {
+                continue;
+            }
+
+            let mut is_linked = false;
+            self.iterate(
+                IterateParams::new(
+                    ValueKey {
+                        account_id: 0,
+                        collection: 0,
+                        document_id: 0,
+                        class: ValueClass::Blob(BlobOp::Link { hash: hash.clone() }),
+                    },
+                    ValueKey {
+                        account_id: u32::MAX,
+                        collection: u8::MAX,
+                        document_id: u32::MAX,
+                        class: ValueClass::Blob(BlobOp::Link { hash: hash.clone() }),
+                    },
+                )
+                .ascending()
+                .no_values(),
+                |key, _| {
+                    is_linked = key.deserialize_be_u32(key.len() - U32_LEN)? != u32::MAX;
+                    Ok(!is_linked)
+                },
+            )
+            .await?;
+
+            if !is_linked {
+                blob_store.delete_blob(hash.as_ref()).await?;
+                delete_keys.push(ValueKey {
+                    account_id: 0,
+                    collection: 0,
+                    document_id: 0,
+                    class: ValueClass::Blob(BlobOp::Commit { hash }),
+                });
+            }
+        }
+
+        self.clear_blob_keys(delete_keys).await
+    }
+
+    async fn clear_blob_keys(&self, delete_keys: Vec<ValueKey<ValueClass>>) -> crate::Result<()> {
         let mut batch = BatchBuilder::new();
         let mut last_account_id = u32::MAX;
         for key in delete_keys.into_iter() {
@@ -317,6 +317,7 @@ impl<T: AsRef<ValueClass> + Sync + Send> Key for ValueKey<T> {
                     .write(26u8)
                     .write(*principal_id)
                     .write(*has_member),
+                DirectoryClass::ExternalIdToId(id) => serializer.write(27u8).write(id.as_slice()),
             },
             ValueClass::Queue(queue) => match queue {
                 QueueClass::Message(queue_id) => serializer.write(50u8).write(*queue_id),
@@ -499,6 +500,7 @@ impl ValueClass {
             ValueClass::Directory(d) => match d {
                 DirectoryClass::NameToId(v)
                 | DirectoryClass::EmailToId(v)
+                | DirectoryClass::ExternalIdToId(v)
                 | DirectoryClass::Domain(v) => v.len(),
                 DirectoryClass::Principal(_) | DirectoryClass::UsedQuota(_) => U32_LEN,
                 DirectoryClass::Members { .. } | DirectoryClass::MemberOf { .. } => U32_LEN * 2,
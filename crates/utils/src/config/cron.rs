@@ -23,7 +23,7 @@
 
 use std::time::Duration;
 
-use chrono::{Datelike, Local, TimeDelta, TimeZone, Timelike};
+use chrono::{DateTime, Datelike, Local, TimeDelta, TimeZone, Timelike};
 
 use super::utils::ParseValue;
 
@@ -36,7 +36,10 @@ pub enum SimpleCron {
 
 impl SimpleCron {
     pub fn time_to_next(&self) -> Duration {
-        let now = Local::now();
+        self.time_to_next_from(Local::now())
+    }
+
+    pub fn time_to_next_from(&self, now: DateTime<Local>) -> Duration {
         let next = match self {
             SimpleCron::Day { hour, minute } => {
                 let next = Local
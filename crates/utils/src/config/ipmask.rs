@@ -21,7 +21,10 @@
  * for more details.
 */
 
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
 
 use super::utils::{AsKey, ParseKey, ParseValue};
 
@@ -136,6 +139,42 @@ impl ParseValue for IpAddrMask {
     }
 }
 
+impl Display for IpAddrMask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpAddrMask::V4 {
+                addr,
+                mask: u32::MAX,
+            } => write!(f, "{addr}"),
+            IpAddrMask::V4 { addr, mask } => write!(f, "{addr}/{}", mask.leading_ones()),
+            IpAddrMask::V6 {
+                addr,
+                mask: u128::MAX,
+            } => write!(f, "{addr}"),
+            IpAddrMask::V6 { addr, mask } => write!(f, "{addr}/{}", mask.leading_ones()),
+        }
+    }
+}
+
+impl serde::Serialize for IpAddrMask {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for IpAddrMask {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = <String as serde::Deserialize>::deserialize(deserializer)?;
+        IpAddrMask::parse_value("network", &value).map_err(serde::de::Error::custom)
+    }
+}
+
 impl ParseValue for IpAddrOrMask {
     fn parse_value(key: impl AsKey, ip: &str) -> super::Result<Self> {
         if ip.contains('/') {
@@ -170,5 +209,12 @@ mod tests {
             let ip = ip.parse::<IpAddr>().unwrap();
             assert!(!mask.matches(&ip));
         }
+
+        for mask in ["10.0.0.0/8", "192.168.1.1", "2001:db8::/32", "::1"] {
+            assert_eq!(
+                IpAddrMask::parse_value("test", mask).unwrap().to_string(),
+                mask
+            );
+        }
     }
 }
@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use ahash::AHashMap;
 use rustls::{
@@ -34,7 +34,8 @@ use rustls::{
         },
         default_provider,
     },
-    server::ResolvesServerCert,
+    server::{NoServerSessionStorage, ResolvesServerCert, ServerSessionMemoryCache},
+    ticketer::TicketSwitcher,
     ServerConfig, SupportedCipherSuite, ALL_VERSIONS,
 };
 use tokio::net::TcpSocket;
@@ -43,7 +44,7 @@ use tokio_rustls::TlsAcceptor;
 use crate::{
     acme::{directory::ACME_TLS_ALPN_NAME, AcmeManager},
     listener::{
-        tls::{Certificate, CertificateResolver},
+        tls::{Certificate, CertificateResolver, TicketKey},
         TcpAcceptor,
     },
     UnwrapFailure,
@@ -204,6 +205,31 @@ impl Config {
                 }
             }
 
+            // Parse minimum protocol version, TLS versions older than 1.2
+            // are never negotiated
+            if let Some(min_version) = self.value_or_else(
+                ("server.listener", id, "tls.min-version"),
+                "server.tls.min-version",
+            ) {
+                match min_version {
+                    "TLSv1.2" | "0x0303" => (),
+                    "TLSv1.3" | "0x0304" if !tls_v2 || tls_v3 => {
+                        tls_v2 = false;
+                        tls_v3 = true;
+                    }
+                    "TLSv1.3" | "0x0304" => {
+                        return Err(format!(
+                            "Minimum TLS version {min_version:?} excludes all protocols enabled for listener {id:?}."
+                        ))
+                    }
+                    _ => {
+                        return Err(format!(
+                            "Unsupported minimum TLS version {min_version:?} for listener {id:?}.",
+                        ))
+                    }
+                }
+            }
+
             // Parse cipher suites
             let mut ciphers: Vec<SupportedCipherSuite> = Vec::new();
             for (key, protocol) in
@@ -299,6 +325,53 @@ impl Config {
                 )?
                 .unwrap_or(true);
 
+            // Session resumption
+            if self
+                .property_or_else(
+                    ("server.listener", id, "tls.session.resumption"),
+                    "server.tls.session.resumption",
+                )?
+                .unwrap_or(true)
+            {
+                let cache_size = self
+                    .property_or_else::<usize>(
+                        ("server.listener", id, "tls.session.cache-size"),
+                        "server.tls.session.cache-size",
+                    )?
+                    .unwrap_or(256);
+                if cache_size > 0 {
+                    config.session_storage = ServerSessionMemoryCache::new(cache_size);
+                } else {
+                    config.session_storage = Arc::new(NoServerSessionStorage {});
+                }
+
+                if self
+                    .property_or_else(
+                        ("server.listener", id, "tls.session.tickets"),
+                        "server.tls.session.tickets",
+                    )?
+                    .unwrap_or(true)
+                {
+                    // Tickets are accepted for up to twice the rotation interval
+                    let rotate = self
+                        .property_or_else::<Duration>(
+                            ("server.listener", id, "tls.session.ticket-rotation"),
+                            "server.tls.session.ticket-rotation",
+                        )?
+                        .unwrap_or(Duration::from_secs(6 * 60 * 60))
+                        .as_secs()
+                        .clamp(60, 24 * 60 * 60) as u32;
+                    config.ticketer = Arc::new(
+                        TicketSwitcher::new(rotate, TicketKey::generate).map_err(|err| {
+                            format!("Failed to build TLS ticketer for listener {id:?}: {err}")
+                        })?,
+                    );
+                }
+            } else {
+                config.session_storage = Arc::new(NoServerSessionStorage {});
+                config.send_tls13_tickets = 0;
+            }
+
             // Build acceptor
             let acceptor = if let Some(manager) = acme_acceptor {
                 let mut challenge = ServerConfig::builder()
@@ -340,10 +413,7 @@ impl Config {
         Ok(Server {
             id: id.to_string(),
             internal_id: 0,
-            hostname: self
-                .value_or_else(("server.listener", id, "hostname"), "server.hostname")
-                .ok_or("Hostname directive not found.")?
-                .to_string(),
+            hostname: self.listener_hostname(id)?.to_string(),
             data: match protocol {
                 ServerProtocol::Smtp | ServerProtocol::Lmtp => self
                     .value_or_else(("server.listener", id, "greeting"), "server.greeting")
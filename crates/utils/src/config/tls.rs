@@ -107,18 +107,15 @@ impl Config {
             // Find which domains are covered by this ACME manager
             let mut domains = Vec::new();
             for id in self.sub_keys("server.listener", ".protocol") {
-                match (
-                    self.value_or_else(("server.listener", id, "tls.acme"), "server.tls.acme"),
-                    self.value_or_else(("server.listener", id, "hostname"), "server.hostname"),
-                ) {
-                    (Some(listener_acme), Some(hostname)) if listener_acme == acme_id => {
-                        let hostname = hostname.trim().to_lowercase();
-
-                        if !domains.contains(&hostname) {
-                            domains.push(hostname);
-                        }
+                if self
+                    .value_or_else(("server.listener", id, "tls.acme"), "server.tls.acme")
+                    .map_or(false, |listener_acme| listener_acme == acme_id)
+                {
+                    let hostname = self.listener_hostname(id)?.to_lowercase();
+
+                    if !domains.contains(&hostname) {
+                        domains.push(hostname);
                     }
-                    _ => (),
                 }
             }
 
@@ -256,6 +256,37 @@ impl Config {
             .ok_or_else(|| format!("Missing property {:?}.", key.as_key()))
     }
 
+    pub fn server_hostname(&self) -> super::Result<&str> {
+        self.value_hostname("server.hostname")
+    }
+
+    pub fn listener_hostname(&self, id: &str) -> super::Result<&str> {
+        if self.contains_key(("server.listener", id, "hostname")) {
+            self.value_hostname(("server.listener", id, "hostname"))
+        } else {
+            self.server_hostname()
+        }
+    }
+
+    // Trailing dots are accepted and removed
+    pub fn value_hostname(&self, key: impl AsKey) -> super::Result<&str> {
+        let key = key.as_key();
+        let hostname = self.value_require(key.as_str())?;
+        let hostname = hostname.strip_suffix('.').unwrap_or(hostname);
+        if is_fqdn(hostname) {
+            Ok(hostname)
+        } else {
+            Err(format!(
+                concat!(
+                    "Invalid hostname {:?} for property {:?}, a fully qualified domain ",
+                    "name such as \"mail.example.org\" is required. If it was obtained ",
+                    "from the system hostname, set {:?} explicitly."
+                ),
+                hostname, key, key
+            ))
+        }
+    }
+
     pub fn value_require_(&mut self, key: impl AsKey) -> Option<&str> {
         let key = key.as_key();
         if let Some(value) = self.keys.get(&key) {
@@ -360,6 +391,21 @@ pub trait ParseKey<T: ParseValue> {
     fn parse_key(&self, key: impl AsKey) -> super::Result<T>;
 }
 
+fn is_fqdn(hostname: &str) -> bool {
+    let mut labels = 0;
+    hostname.len() <= 253
+        && hostname.split('.').all(|label| {
+            labels += 1;
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|ch| ch.is_ascii_alphanumeric() || ch == b'-')
+        })
+        && labels > 1
+}
+
 impl<T: ParseValue> ParseKey<T> for &str {
     fn parse_key(&self, key: impl AsKey) -> super::Result<T> {
         T::parse_value(key, self)
@@ -723,6 +769,32 @@ impl ParseValue for Duration {
 
 impl ConstantValue for Duration {}
 
+/// Duration bounded to `MIN..=MAX` milliseconds. Values below the minimum are
+/// rejected, values above the maximum are clamped to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DurationRange<const MIN: u64, const MAX: u64>(pub Duration);
+
+impl<const MIN: u64, const MAX: u64> ParseValue for DurationRange<MIN, MAX> {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        let key = key.as_key();
+        let duration = Duration::parse_value(key.as_str(), value)?;
+        if duration >= Duration::from_millis(MIN) {
+            Ok(DurationRange(duration.min(Duration::from_millis(MAX))))
+        } else {
+            Err(format!(
+                "Duration value {:?} for property {:?} is below the minimum of {}ms.",
+                value, key, MIN
+            ))
+        }
+    }
+}
+
+impl<const MIN: u64, const MAX: u64> From<DurationRange<MIN, MAX>> for Duration {
+    fn from(value: DurationRange<MIN, MAX>) -> Self {
+        value.0
+    }
+}
+
 impl<'x> TryFrom<Variable<'x>> for Duration {
     type Error = ();
 
@@ -956,10 +1028,12 @@ impl AsKey for (&str, &str, &str, &str) {
 
 #[cfg(test)]
 mod tests {
-    use std::net::IpAddr;
+    use std::{net::IpAddr, time::Duration};
 
     use crate::config::Config;
 
+    use super::{DurationRange, ParseValue};
+
     #[test]
     fn toml_utils() {
         let toml = r#"
@@ -1018,4 +1092,27 @@ ip = "a:b::1:1"
             "a:b::1:1".parse::<IpAddr>().unwrap()
         );
     }
+
+    #[test]
+    fn duration_range() {
+        type Timeout = DurationRange<1000, 3_600_000>;
+
+        for (value, expected) in [
+            ("1s", Some(Duration::from_secs(1))),
+            ("30s", Some(Duration::from_secs(30))),
+            ("1h", Some(Duration::from_secs(3600))),
+            ("2d", Some(Duration::from_secs(3600))),
+            ("500ms", None),
+            ("0s", None),
+            ("abc", None),
+        ] {
+            assert_eq!(
+                Timeout::parse_value("timeout", value)
+                    .ok()
+                    .map(Duration::from),
+                expected,
+                "{value}"
+            );
+        }
+    }
 }
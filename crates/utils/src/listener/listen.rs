@@ -109,12 +109,8 @@ impl Server {
                                         opts.apply(&stream);
 
                                         tokio::spawn(async move {
-                                            match ProxiedStream::create_from_tokio(stream, Default::default()).await {
-                                                Ok(stream) =>{
-                                                    let remote_addr = stream.proxy_header()
-                                                                            .proxied_address()
-                                                                            .map(|addr| addr.source)
-                                                                            .unwrap_or(remote_addr);
+                                            match accept_proxied(stream, remote_addr).await {
+                                                Ok((stream, remote_addr)) => {
                                                     if let Some(session) = instance.build_session(stream, local_ip, remote_addr, &manager) {
                                                         // Spawn session
                                                         manager.spawn(session, is_tls);
@@ -163,6 +159,22 @@ impl Server {
     }
 }
 
+/// Reads the PROXY protocol (v1 or v2) header sent by a trusted load balancer
+/// and returns the stream along with the address of the original client.
+pub async fn accept_proxied(
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+) -> std::io::Result<(ProxiedStream<TcpStream>, SocketAddr)> {
+    let stream = ProxiedStream::create_from_tokio(stream, Default::default()).await?;
+    let remote_addr = stream
+        .proxy_header()
+        .proxied_address()
+        .map(|addr| addr.source)
+        .unwrap_or(remote_addr);
+
+    Ok((stream, remote_addr))
+}
+
 trait BuildSession {
     fn build_session<T: SessionStream, M: SessionManager>(
         &self,
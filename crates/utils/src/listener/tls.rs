@@ -29,9 +29,14 @@ use std::{
 
 use ahash::AHashMap;
 use arc_swap::ArcSwap;
+use ring::{
+    aead,
+    rand::{SecureRandom, SystemRandom},
+};
 use rustls::{
     client::verify_server_name,
-    server::{ClientHello, ParsedCertificate, ResolvesServerCert},
+    crypto::GetRandomFailed,
+    server::{ClientHello, ParsedCertificate, ProducesTickets, ResolvesServerCert},
     sign::CertifiedKey,
     version::{TLS12, TLS13},
     Error, SupportedProtocolVersion,
@@ -57,6 +62,12 @@ pub struct Certificate {
     pub path: Vec<PathBuf>,
 }
 
+// Session ticket encryption key, rotated by rustls' TicketSwitcher which
+// also reports the ticket lifetime
+pub struct TicketKey {
+    key: aead::LessSafeKey,
+}
+
 impl CertificateResolver {
     pub fn add(&mut self, name: &str, ck: Arc<Certificate>) -> Result<(), Error> {
         let server_name = {
@@ -190,6 +201,72 @@ impl Certificate {
     }
 }
 
+impl TicketKey {
+    pub fn generate() -> Result<Box<dyn ProducesTickets>, GetRandomFailed> {
+        let mut key = [0u8; 32];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| GetRandomFailed)?;
+
+        Ok(Box::new(TicketKey {
+            key: aead::LessSafeKey::new(
+                aead::UnboundKey::new(&aead::CHACHA20_POLY1305, &key)
+                    .map_err(|_| GetRandomFailed)?,
+            ),
+        }))
+    }
+}
+
+impl ProducesTickets for TicketKey {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn lifetime(&self) -> u32 {
+        0
+    }
+
+    fn encrypt(&self, message: &[u8]) -> Option<Vec<u8>> {
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce).ok()?;
+
+        let mut ticket =
+            Vec::with_capacity(nonce.len() + message.len() + self.key.algorithm().tag_len());
+        ticket.extend_from_slice(&nonce);
+        ticket.extend_from_slice(message);
+        self.key
+            .seal_in_place_separate_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::empty(),
+                &mut ticket[nonce.len()..],
+            )
+            .map(|tag| {
+                ticket.extend_from_slice(tag.as_ref());
+                ticket
+            })
+            .ok()
+    }
+
+    fn decrypt(&self, ticket: &[u8]) -> Option<Vec<u8>> {
+        let nonce = aead::Nonce::try_assume_unique_for_key(ticket.get(..aead::NONCE_LEN)?).ok()?;
+        let mut message = ticket.get(aead::NONCE_LEN..)?.to_vec();
+        let message_len = self
+            .key
+            .open_in_place(nonce, aead::Aad::empty(), &mut message)
+            .ok()?
+            .len();
+        message.truncate(message_len);
+
+        Some(message)
+    }
+}
+
+impl std::fmt::Debug for TicketKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TicketKey").finish_non_exhaustive()
+    }
+}
+
 impl std::fmt::Debug for CertificateResolver {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("CertificateResolver")
@@ -21,9 +21,9 @@
  * for more details.
 */
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use directory::QueryBy;
+use directory::{core::cache::AuthCache, QueryBy};
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
 use tokio::{
@@ -33,7 +33,10 @@ use tokio::{
 };
 use tokio_rustls::TlsAcceptor;
 
-use utils::listener::limiter::{ConcurrencyLimiter, InFlight};
+use utils::{
+    config::Config,
+    listener::limiter::{ConcurrencyLimiter, InFlight},
+};
 
 use crate::directory::{DirectoryTest, Item, LookupResult};
 
@@ -121,6 +124,66 @@ async fn imap_directory() {
     shutdown.send(false).ok();
 }
 
+#[tokio::test]
+async fn imap_auth_cache() {
+    // Caching is disabled by default
+    let mut config = Config::new("[directory.imap]\ntype = \"imap\"\n").unwrap();
+    assert!(AuthCache::try_from_config(&mut config, "directory.imap").is_none());
+    let mut config = Config::new("[directory.imap.cache.auth]\nttl = \"0s\"\n").unwrap();
+    assert!(AuthCache::try_from_config(&mut config, "directory.imap").is_none());
+    let mut config = Config::new("[directory.imap.cache.auth]\nttl = \"30s\"\n").unwrap();
+    assert!(AuthCache::try_from_config(&mut config, "directory.imap").is_some());
+
+    let cache = AuthCache::new(10, Duration::from_millis(200));
+    let john = Credentials::Plain {
+        username: "john".to_string(),
+        secret: "ok".to_string(),
+    };
+    let john_bad = Credentials::Plain {
+        username: "john".to_string(),
+        secret: "bad".to_string(),
+    };
+    let token = Credentials::OAuthBearer {
+        token: "john".to_string(),
+    };
+    let xoauth = Credentials::XOauth2 {
+        username: "john".to_string(),
+        secret: "ok".to_string(),
+    };
+    let ambiguous = [
+        Credentials::Plain {
+            username: "a\0b".to_string(),
+            secret: "c".to_string(),
+        },
+        Credentials::Plain {
+            username: "a".to_string(),
+            secret: "b\0c".to_string(),
+        },
+    ];
+
+    // Positive results are served from the cache within the TTL
+    assert!(!cache.get(&john));
+    cache.insert(&john);
+    assert!(cache.get(&john));
+    assert!(!cache.get(&john_bad));
+    assert!(!cache.get(&token));
+    assert!(!cache.get(&xoauth));
+
+    // Field boundaries are part of the key
+    cache.insert(&ambiguous[0]);
+    assert!(cache.get(&ambiguous[0]));
+    assert!(!cache.get(&ambiguous[1]));
+
+    // A failed authentication invalidates the principal's cached secret
+    cache.remove(&john_bad);
+    assert!(!cache.get(&john));
+
+    // Entries expire after the TTL
+    cache.insert(&john);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(!cache.get(&john));
+}
+
 pub fn spawn_mock_imap_server(max_concurrency: u64) -> watch::Sender<bool> {
     let (tx, mut rx) = watch::channel(true);
 
@@ -21,20 +21,32 @@
  * for more details.
 */
 
+use std::{collections::BTreeMap, net::IpAddr, time::Duration};
+
 use directory::{
     backend::internal::{
-        lookup::DirectoryStore, manage::ManageDirectory, PrincipalField, PrincipalUpdate,
-        PrincipalValue,
+        defaults::QUOTA_UNSET,
+        lookup::DirectoryStore,
+        manage::ManageDirectory,
+        password::PasswordPolicy,
+        policy::PrincipalPolicy,
+        preferences::{is_valid_locale, is_valid_timezone},
+        reserved::ReservedNames,
+        PrincipalField, PrincipalFilter, PrincipalUpdate, PrincipalValue, PRINCIPAL_VERSION,
     },
-    DirectoryError, ManagementError, Principal, QueryBy, Type,
+    core::cache::CachedDirectory,
+    Directory, DirectoryError, DirectoryInner, ManagementError, Principal, QueryBy, Type,
+    FEATURES_ALL, FEATURE_IMAP, FEATURE_JMAP, FEATURE_SEND_EXTERNAL,
 };
+use jmap::api::admin::PrincipalResponse;
 use jmap_proto::types::collection::Collection;
 use mail_send::Credentials;
 use store::{
     roaring::RoaringBitmap,
-    write::{BatchBuilder, BitmapClass, ValueClass},
-    BitmapKey, ValueKey,
+    write::{now, BatchBuilder, BitmapClass, ValueClass},
+    BitmapKey, Deserialize, Serialize, Store, ValueKey,
 };
+use utils::config::{ipmask::IpAddrMask, utils::ParseValue, Config};
 
 use crate::directory::DirectoryTest;
 
@@ -649,5 +661,2100 @@ async fn internal_directory() {
                 .unwrap(),
             Some("hello".to_string())
         );
+
+        // Unknown local parts are rejected while there is no catch-all
+        assert!(!store.rcpt("unknown@example.org").await.unwrap());
+        assert_eq!(
+            store.email_to_ids("unknown@example.org").await.unwrap(),
+            Vec::<u32>::new()
+        );
+
+        // Create a catch-all principal for example.org
+        let catch_all_id = store
+            .create_account(
+                Principal {
+                    name: "catchall".to_string(),
+                    emails: vec!["@example.org".to_string()],
+                    ..Default::default()
+                },
+                vec![],
+            )
+            .await
+            .unwrap();
+        assert!(store.rcpt("unknown@example.org").await.unwrap());
+        assert_eq!(
+            store.email_to_ids("unknown@example.org").await.unwrap(),
+            vec![catch_all_id]
+        );
+        assert!(!store.rcpt("unknown@otherdomain.org").await.unwrap());
+        assert!(store.expn("unknown@example.org").await.unwrap().is_empty());
+
+        // Specific mailboxes are not shadowed by the catch-all
+        assert_eq!(
+            store.email_to_ids("jane@example.org").await.unwrap(),
+            vec![1]
+        );
+        assert!(store
+            .create_account(
+                Principal {
+                    name: "bob".to_string(),
+                    emails: vec!["bob@example.org".to_string()],
+                    ..Default::default()
+                },
+                vec![],
+            )
+            .await
+            .is_ok());
+        assert_ne!(
+            store.email_to_ids("bob@example.org").await.unwrap(),
+            vec![catch_all_id]
+        );
+    }
+}
+
+#[tokio::test]
+async fn internal_groups_of() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing group resolution with store {:?}", store_id);
+        store.destroy().await;
+
+        // Create john and three nested groups: john -> sales -> staff -> all
+        let mut ids = Vec::new();
+        for (name, typ) in [
+            ("john", Type::Individual),
+            ("sales", Type::Group),
+            ("staff", Type::Group),
+            ("all", Type::Group),
+        ] {
+            ids.push(
+                store
+                    .create_account(
+                        Principal {
+                            name: name.to_string(),
+                            typ,
+                            ..Default::default()
+                        },
+                        vec![],
+                    )
+                    .await
+                    .unwrap(),
+            );
+        }
+        let (john_id, sales_id, staff_id, all_id) = (ids[0], ids[1], ids[2], ids[3]);
+        for (name, member_of) in [("john", "sales"), ("sales", "staff"), ("staff", "all")] {
+            store
+                .update_account(
+                    QueryBy::Name(name),
+                    vec![PrincipalUpdate::add_item(
+                        PrincipalField::MemberOf,
+                        PrincipalValue::String(member_of.to_string()),
+                    )],
+                )
+                .await
+                .unwrap();
+        }
+
+        // Direct membership only
+        assert_eq!(
+            store.groups_of(john_id, false).await.unwrap(),
+            vec![sales_id]
+        );
+        assert!(store.groups_of(all_id, false).await.unwrap().is_empty());
+
+        // Transitive membership
+        assert_eq!(
+            store.groups_of(john_id, true).await.unwrap(),
+            vec![sales_id, staff_id, all_id]
+        );
+        assert_eq!(store.groups_of(staff_id, true).await.unwrap(), vec![all_id]);
+
+        // Cyclic memberships terminate and do not list the principal itself
+        store
+            .update_account(
+                QueryBy::Name("all"),
+                vec![PrincipalUpdate::add_item(
+                    PrincipalField::MemberOf,
+                    PrincipalValue::String("sales".to_string()),
+                )],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            store.groups_of(john_id, true).await.unwrap(),
+            vec![sales_id, staff_id, all_id]
+        );
+        assert_eq!(
+            store.groups_of(sales_id, true).await.unwrap(),
+            vec![staff_id, all_id]
+        );
+    }
+}
+
+#[tokio::test]
+async fn internal_allowed_networks() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing allowed networks with store {:?}", store_id);
+        store.destroy().await;
+
+        // Create a restricted and an unrestricted account
+        let networks = vec![
+            IpAddrMask::parse_value("test", "10.0.0.0/8").unwrap(),
+            IpAddrMask::parse_value("test", "2001:db8::/32").unwrap(),
+        ];
+        for (name, allowed_networks) in [("john", networks.clone()), ("jane", vec![])] {
+            store
+                .create_account(
+                    Principal {
+                        name: name.to_string(),
+                        secrets: vec!["secret".to_string()],
+                        allowed_networks,
+                        ..Default::default()
+                    },
+                    vec![],
+                )
+                .await
+                .unwrap();
+        }
+
+        // Networks are persisted
+        let john = store
+            .query(QueryBy::Name("john"), false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(john.allowed_networks, networks);
+        let jane = store
+            .query(QueryBy::Name("jane"), false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(jane.allowed_networks.is_empty());
+
+        // Logins are only allowed from the configured networks
+        for (ip, expected) in [
+            ("10.1.2.3", true),
+            ("2001:db8::1", true),
+            ("192.168.1.1", false),
+            ("2001:db9::1", false),
+        ] {
+            let ip = ip.parse::<IpAddr>().unwrap();
+            assert_eq!(john.is_allowed_ip(&ip), expected, "{ip}");
+            assert!(jane.is_allowed_ip(&ip), "{ip}");
+        }
+
+        // Networks can be updated
+        store
+            .update_account(
+                QueryBy::Name("jane"),
+                vec![PrincipalUpdate::add_item(
+                    PrincipalField::AllowedNetworks,
+                    PrincipalValue::String("192.168.1.0/24".to_string()),
+                )],
+            )
+            .await
+            .unwrap();
+        store
+            .update_account(
+                QueryBy::Name("john"),
+                vec![PrincipalUpdate::remove_item(
+                    PrincipalField::AllowedNetworks,
+                    PrincipalValue::String("2001:db8::/32".to_string()),
+                )],
+            )
+            .await
+            .unwrap();
+        let john = store
+            .query(QueryBy::Name("john"), false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(john.allowed_networks, networks[..1].to_vec());
+        let jane = store
+            .query(QueryBy::Name("jane"), false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(jane.is_allowed_ip(&"192.168.1.1".parse::<IpAddr>().unwrap()));
+        assert!(!jane.is_allowed_ip(&"10.1.2.3".parse::<IpAddr>().unwrap()));
+
+        // Clearing the list removes the restriction
+        store
+            .update_account(
+                QueryBy::Name("jane"),
+                vec![PrincipalUpdate::set(
+                    PrincipalField::AllowedNetworks,
+                    PrincipalValue::StringList(vec![]),
+                )],
+            )
+            .await
+            .unwrap();
+        assert!(store
+            .query(QueryBy::Name("jane"), false)
+            .await
+            .unwrap()
+            .unwrap()
+            .allowed_networks
+            .is_empty());
+
+        // Invalid networks are rejected
+        assert_eq!(
+            store
+                .update_account(
+                    QueryBy::Name("jane"),
+                    vec![PrincipalUpdate::add_item(
+                        PrincipalField::AllowedNetworks,
+                        PrincipalValue::String("not-a-network".to_string()),
+                    )],
+                )
+                .await,
+            Err(DirectoryError::Management(ManagementError::InvalidValue {
+                field: PrincipalField::AllowedNetworks,
+                value: "not-a-network".to_string()
+            }))
+        );
+    }
+}
+
+#[tokio::test]
+async fn internal_preferences() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing locale and timezone with store {:?}", store_id);
+        store.destroy().await;
+
+        // Preferences are persisted
+        store
+            .create_account(
+                Principal {
+                    name: "john".to_string(),
+                    locale: Some("en-US".to_string()),
+                    timezone: Some("America/New_York".to_string()),
+                    ..Default::default()
+                },
+                vec![],
+            )
+            .await
+            .unwrap();
+        let john = store
+            .query(QueryBy::Name("john"), false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(john.locale.as_deref(), Some("en-US"));
+        assert_eq!(john.timezone.as_deref(), Some("America/New_York"));
+
+        // Update preferences
+        store
+            .update_account(
+                QueryBy::Name("john"),
+                vec![
+                    PrincipalUpdate::set(
+                        PrincipalField::Locale,
+                        PrincipalValue::String("zh-Hant-TW".to_string()),
+                    ),
+                    PrincipalUpdate::set(
+                        PrincipalField::Timezone,
+                        PrincipalValue::String(String::new()),
+                    ),
+                ],
+            )
+            .await
+            .unwrap();
+        let john = store
+            .query(QueryBy::Name("john"), false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(john.locale.as_deref(), Some("zh-Hant-TW"));
+        assert_eq!(john.timezone, None);
+
+        // Invalid values are rejected
+        assert!(matches!(
+            store
+                .update_account(
+                    QueryBy::Name("john"),
+                    vec![PrincipalUpdate::set(
+                        PrincipalField::Timezone,
+                        PrincipalValue::String("Mars/Olympus_Mons".to_string()),
+                    )],
+                )
+                .await,
+            Err(DirectoryError::Management(ManagementError::InvalidValue {
+                field: PrincipalField::Timezone,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            store
+                .create_account(
+                    Principal {
+                        name: "jane".to_string(),
+                        timezone: Some("Europe/../etc/passwd".to_string()),
+                        ..Default::default()
+                    },
+                    vec![],
+                )
+                .await,
+            Err(DirectoryError::Management(ManagementError::InvalidValue {
+                field: PrincipalField::Timezone,
+                ..
+            }))
+        ));
+    }
+}
+
+#[test]
+fn validate_preferences() {
+    for locale in [
+        "en",
+        "en-US",
+        "es-419",
+        "zh-Hant-TW",
+        "de-CH-1996",
+        "en-US-x-twain",
+    ] {
+        assert!(is_valid_locale(locale), "{locale}");
+    }
+    for locale in ["", "e", "12-US", "en_US", "en-", "en-US-x", "en-abc"] {
+        assert!(!is_valid_locale(locale), "{locale}");
+    }
+    for timezone in [
+        "UTC",
+        "Europe/Madrid",
+        "America/Argentina/Buenos_Aires",
+        "Etc/GMT+5",
+    ] {
+        assert!(is_valid_timezone(timezone), "{timezone}");
+    }
+    for timezone in [
+        "",
+        "Europe",
+        "Mars/Olympus_Mons",
+        "Europe/Atlantis",
+        "europe/madrid",
+        "Europe/",
+        "Europe/../etc/passwd",
+        "America/New York",
+    ] {
+        assert!(!is_valid_timezone(timezone), "{timezone}");
+    }
+}
+
+#[tokio::test]
+async fn internal_group_cache() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing group closure cache with store {:?}", store_id);
+        store.destroy().await;
+
+        let mut settings = Config::new(
+            r#"[directory."test".cache]
+entries = 100
+"#,
+        )
+        .unwrap();
+        let directory = Directory {
+            store: DirectoryInner::Internal(store.clone()),
+            cache: CachedDirectory::try_from_config(&mut settings, ("directory", "test")),
+            on_error: Default::default(),
+            quota_inheritance: Default::default(),
+            query_timeout: None,
+            password_max_age: None,
+        };
+        let cache = directory.cache.as_ref().unwrap();
+
+        // Create john -> sales -> staff and jane -> support
+        let mut ids = Vec::new();
+        for (name, typ) in [
+            ("john", Type::Individual),
+            ("jane", Type::Individual),
+            ("sales", Type::Group),
+            ("staff", Type::Group),
+            ("support", Type::Group),
+            ("all", Type::Group),
+        ] {
+            ids.push(
+                store
+                    .create_account(
+                        Principal {
+                            name: name.to_string(),
+                            typ,
+                            ..Default::default()
+                        },
+                        vec![],
+                    )
+                    .await
+                    .unwrap(),
+            );
+        }
+        let (john_id, jane_id, sales_id, staff_id, support_id, all_id) =
+            (ids[0], ids[1], ids[2], ids[3], ids[4], ids[5]);
+        for (name, member_of) in [("john", "sales"), ("sales", "staff"), ("jane", "support")] {
+            store
+                .update_account(
+                    QueryBy::Name(name),
+                    vec![PrincipalUpdate::add_item(
+                        PrincipalField::MemberOf,
+                        PrincipalValue::String(member_of.to_string()),
+                    )],
+                )
+                .await
+                .unwrap();
+        }
+
+        // Closures are cached after the first lookup
+        assert_eq!(
+            directory.transitive_groups(john_id).await.unwrap(),
+            vec![sales_id, staff_id]
+        );
+        assert_eq!(
+            directory.transitive_groups(jane_id).await.unwrap(),
+            vec![support_id]
+        );
+        assert_eq!(cache.get_groups(john_id), Some(vec![sales_id, staff_id]));
+
+        // Membership changes written by the store drop the cached closures,
+        // including those of indirect members
+        store
+            .update_account(
+                QueryBy::Name("staff"),
+                vec![PrincipalUpdate::add_item(
+                    PrincipalField::MemberOf,
+                    PrincipalValue::String("all".to_string()),
+                )],
+            )
+            .await
+            .unwrap();
+        assert_eq!(cache.get_groups(john_id), None);
+        assert_eq!(cache.get_groups(jane_id), Some(vec![support_id]));
+        assert_eq!(
+            directory.transitive_groups(john_id).await.unwrap(),
+            vec![sales_id, staff_id, all_id]
+        );
+
+        // Principals resolve to all the groups they transitively belong to
+        assert_eq!(
+            directory
+                .query(QueryBy::Id(john_id), true)
+                .await
+                .unwrap()
+                .unwrap()
+                .member_of,
+            vec![sales_id, staff_id, all_id]
+        );
+
+        // Adding a member to a group drops the new member's closure
+        store
+            .update_account(
+                QueryBy::Name("support"),
+                vec![PrincipalUpdate::add_item(
+                    PrincipalField::Members,
+                    PrincipalValue::String("john".to_string()),
+                )],
+            )
+            .await
+            .unwrap();
+        assert_eq!(cache.get_groups(john_id), None);
+        let mut groups = directory.transitive_groups(john_id).await.unwrap();
+        groups.sort_unstable();
+        let mut expected = vec![sales_id, staff_id, support_id, all_id];
+        expected.sort_unstable();
+        assert_eq!(groups, expected);
+
+        // Removing a membership drops the former member's closure
+        store
+            .update_account(
+                QueryBy::Name("sales"),
+                vec![PrincipalUpdate::remove_item(
+                    PrincipalField::MemberOf,
+                    PrincipalValue::String("staff".to_string()),
+                )],
+            )
+            .await
+            .unwrap();
+        assert_eq!(cache.get_groups(john_id), None);
+        assert_eq!(cache.get_groups(sales_id), None);
+        let mut groups = directory.transitive_groups(john_id).await.unwrap();
+        groups.sort_unstable();
+        let mut expected = vec![sales_id, support_id];
+        expected.sort_unstable();
+        assert_eq!(groups, expected);
+        assert!(directory
+            .transitive_groups(sales_id)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Removing a member from a group drops the former member's closure
+        assert_eq!(
+            directory.transitive_groups(jane_id).await.unwrap(),
+            vec![support_id]
+        );
+        store
+            .update_account(
+                QueryBy::Name("support"),
+                vec![PrincipalUpdate::remove_item(
+                    PrincipalField::Members,
+                    PrincipalValue::String("jane".to_string()),
+                )],
+            )
+            .await
+            .unwrap();
+        assert_eq!(cache.get_groups(jane_id), None);
+        assert!(directory
+            .transitive_groups(jane_id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
+
+#[tokio::test]
+async fn internal_type_change() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing principal type changes with store {:?}", store_id);
+        store.destroy().await;
+
+        for (name, typ) in [("john", Type::Individual), ("sales", Type::Group)] {
+            store
+                .create_account(
+                    Principal {
+                        name: name.to_string(),
+                        typ,
+                        ..Default::default()
+                    },
+                    vec![],
+                )
+                .await
+                .unwrap();
+        }
+
+        // Individuals can be promoted to superuser and demoted back
+        for typ in ["superuser", "individual"] {
+            store
+                .update_account(
+                    QueryBy::Name("john"),
+                    vec![PrincipalUpdate::set(
+                        PrincipalField::Type,
+                        PrincipalValue::String(typ.to_string()),
+                    )],
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                store
+                    .query(QueryBy::Name("john"), false)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .typ,
+                Type::parse(typ).unwrap()
+            );
+        }
+
+        // Any other transition is rejected
+        for (name, typ) in [
+            ("john", "group"),
+            ("john", "other"),
+            ("john", "invalid"),
+            ("sales", "superuser"),
+            ("sales", "individual"),
+        ] {
+            assert_eq!(
+                store
+                    .update_account(
+                        QueryBy::Name(name),
+                        vec![PrincipalUpdate::set(
+                            PrincipalField::Type,
+                            PrincipalValue::String(typ.to_string()),
+                        )],
+                    )
+                    .await,
+                Err(DirectoryError::Management(ManagementError::InvalidValue {
+                    field: PrincipalField::Type,
+                    value: typ.to_string()
+                })),
+                "{name} -> {typ}"
+            );
+        }
+        assert_eq!(
+            store
+                .query(QueryBy::Name("sales"), false)
+                .await
+                .unwrap()
+                .unwrap()
+                .typ,
+            Type::Group
+        );
+    }
+}
+
+#[tokio::test]
+async fn internal_search() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing principal search with store {:?}", store_id);
+        store.destroy().await;
+
+        for (name, typ, email) in [
+            ("alice", Type::Individual, "alice@example.org"),
+            ("alex", Type::Individual, "alex@example.net"),
+            ("bob", Type::Individual, "bob@example.org"),
+            ("admin", Type::Superuser, "admin@example.net"),
+            ("sales", Type::Group, "sales@example.org"),
+            ("support", Type::Group, "support@example.org"),
+        ] {
+            store
+                .create_account(
+                    Principal {
+                        name: name.to_string(),
+                        typ,
+                        emails: vec![email.to_string()],
+                        ..Default::default()
+                    },
+                    vec![],
+                )
+                .await
+                .unwrap();
+        }
+
+        for (filter, page, page_size, expected, expected_total) in [
+            // Type filter with pagination boundaries
+            (
+                PrincipalFilter {
+                    typ: Some(Type::Group),
+                    ..Default::default()
+                },
+                1,
+                1,
+                vec!["sales"],
+                2,
+            ),
+            (
+                PrincipalFilter {
+                    typ: Some(Type::Group),
+                    ..Default::default()
+                },
+                2,
+                1,
+                vec!["support"],
+                2,
+            ),
+            (
+                PrincipalFilter {
+                    typ: Some(Type::Group),
+                    ..Default::default()
+                },
+                3,
+                1,
+                vec![],
+                2,
+            ),
+            (
+                PrincipalFilter {
+                    typ: Some(Type::Individual),
+                    ..Default::default()
+                },
+                1,
+                2,
+                vec!["alex", "alice"],
+                3,
+            ),
+            // Superusers share the individual index but are not counted
+            (
+                PrincipalFilter {
+                    typ: Some(Type::Individual),
+                    ..Default::default()
+                },
+                3,
+                1,
+                vec!["bob"],
+                3,
+            ),
+            (
+                PrincipalFilter {
+                    typ: Some(Type::Individual),
+                    ..Default::default()
+                },
+                2,
+                2,
+                vec!["bob"],
+                3,
+            ),
+            (
+                PrincipalFilter {
+                    typ: Some(Type::Superuser),
+                    ..Default::default()
+                },
+                1,
+                10,
+                vec!["admin"],
+                1,
+            ),
+            // Name prefix with pagination boundaries
+            (
+                PrincipalFilter {
+                    name_prefix: Some("al".to_string()),
+                    ..Default::default()
+                },
+                1,
+                1,
+                vec!["alex"],
+                2,
+            ),
+            (
+                PrincipalFilter {
+                    name_prefix: Some("al".to_string()),
+                    ..Default::default()
+                },
+                2,
+                1,
+                vec!["alice"],
+                2,
+            ),
+            (
+                PrincipalFilter {
+                    name_prefix: Some("AL".to_string()),
+                    ..Default::default()
+                },
+                1,
+                0,
+                vec!["alex", "alice"],
+                2,
+            ),
+            (
+                PrincipalFilter {
+                    name_prefix: Some("s".to_string()),
+                    typ: Some(Type::Individual),
+                    ..Default::default()
+                },
+                1,
+                10,
+                vec![],
+                0,
+            ),
+            // Email substring
+            (
+                PrincipalFilter {
+                    email: Some("example.net".to_string()),
+                    ..Default::default()
+                },
+                1,
+                10,
+                vec!["admin", "alex"],
+                2,
+            ),
+        ] {
+            let (principals, total) = store.search(&filter, page, page_size).await.unwrap();
+            assert_eq!(
+                principals.into_iter().map(|p| p.name).collect::<Vec<_>>(),
+                expected,
+                "{filter:?} page {page}"
+            );
+            assert_eq!(total, expected_total, "{filter:?}");
+        }
+    }
+}
+
+#[test]
+fn principal_serialize_canonical() {
+    let principal = Principal::<u32> {
+        id: 1,
+        name: "john".to_string(),
+        secrets: vec!["secret2".to_string(), "secret1".to_string()],
+        emails: vec![
+            "john@example.org".to_string(),
+            "jdoe@example.org".to_string(),
+            "doe@example.org".to_string(),
+        ],
+        member_of: vec![3, 1, 2],
+        ..Default::default()
+    };
+    let reordered = Principal {
+        emails: vec![
+            "john@example.org".to_string(),
+            "doe@example.org".to_string(),
+            "jdoe@example.org".to_string(),
+        ],
+        member_of: vec![1, 2, 3],
+        ..principal.clone()
+    };
+
+    // Same members in a different order produce identical bytes
+    let bytes = (&principal).serialize();
+    assert_eq!(bytes, reordered.serialize());
+
+    // The primary address is kept first and secrets are left untouched
+    let deserialized = Principal::<u32>::deserialize(&bytes).unwrap();
+    assert_eq!(
+        deserialized.emails,
+        vec![
+            "john@example.org".to_string(),
+            "doe@example.org".to_string(),
+            "jdoe@example.org".to_string(),
+        ]
+    );
+    assert_eq!(deserialized.secrets, principal.secrets);
+
+    // A different primary address is a different principal
+    let new_primary = Principal {
+        emails: vec![
+            "jdoe@example.org".to_string(),
+            "doe@example.org".to_string(),
+            "john@example.org".to_string(),
+        ],
+        ..principal.clone()
+    };
+    assert_ne!(bytes, new_primary.serialize());
+}
+
+#[test]
+fn principal_diff() {
+    let old = Principal {
+        name: "john".to_string(),
+        description: Some("John Doe".to_string()),
+        quota: 1024,
+        secrets: vec!["secret".to_string()],
+        emails: vec![
+            "john@example.org".to_string(),
+            "jdoe@example.org".to_string(),
+        ],
+        member_of: vec!["sales".to_string(), "support".to_string()],
+        ..Default::default()
+    };
+
+    // Identical principals produce no updates
+    assert_eq!(Principal::diff(&old, &old.clone()), vec![]);
+
+    // Scalar changes, secrets are redacted
+    let new = Principal {
+        typ: Type::Superuser,
+        quota: 2048,
+        description: None,
+        secrets: vec!["new_secret".to_string()],
+        ..old.clone()
+    };
+    assert_eq!(
+        Principal::diff(&old, &new),
+        vec![
+            PrincipalUpdate::set(
+                PrincipalField::Type,
+                PrincipalValue::String("superuser".to_string())
+            ),
+            PrincipalUpdate::set(PrincipalField::Quota, PrincipalValue::Integer(2048)),
+            PrincipalUpdate::set(
+                PrincipalField::Description,
+                PrincipalValue::String("".to_string())
+            ),
+            PrincipalUpdate::set(
+                PrincipalField::Secrets,
+                PrincipalValue::String("changed".to_string())
+            ),
+        ]
+    );
+
+    // List additions and removals
+    let new = Principal {
+        emails: vec![
+            "john@example.org".to_string(),
+            "john.doe@example.org".to_string(),
+        ],
+        member_of: vec![
+            "sales".to_string(),
+            "support".to_string(),
+            "admins".to_string(),
+        ],
+        ..old.clone()
+    };
+    assert_eq!(
+        Principal::diff(&old, &new),
+        vec![
+            PrincipalUpdate::remove_item(
+                PrincipalField::Emails,
+                PrincipalValue::String("jdoe@example.org".to_string())
+            ),
+            PrincipalUpdate::add_item(
+                PrincipalField::Emails,
+                PrincipalValue::String("john.doe@example.org".to_string())
+            ),
+            PrincipalUpdate::add_item(
+                PrincipalField::MemberOf,
+                PrincipalValue::String("admins".to_string())
+            ),
+        ]
+    );
+
+    // Reordered lists are replaced as a whole
+    let new = Principal {
+        emails: vec![
+            "jdoe@example.org".to_string(),
+            "john@example.org".to_string(),
+        ],
+        member_of: vec!["support".to_string()],
+        ..old.clone()
+    };
+    assert_eq!(
+        Principal::diff(&old, &new),
+        vec![
+            PrincipalUpdate::set(
+                PrincipalField::Emails,
+                PrincipalValue::StringList(vec![
+                    "jdoe@example.org".to_string(),
+                    "john@example.org".to_string(),
+                ])
+            ),
+            PrincipalUpdate::remove_item(
+                PrincipalField::MemberOf,
+                PrincipalValue::String("sales".to_string())
+            ),
+        ]
+    );
+
+    // Allowed networks are replaced as a whole
+    let new = Principal {
+        allowed_networks: vec![IpAddrMask::parse_value("test", "10.0.0.0/8").unwrap()],
+        ..old.clone()
+    };
+    assert_eq!(
+        Principal::diff(&old, &new),
+        vec![PrincipalUpdate::set(
+            PrincipalField::AllowedNetworks,
+            PrincipalValue::StringList(vec!["10.0.0.0/8".to_string()])
+        )]
+    );
+
+    // Attributes are diffed as name=value items
+    let old = Principal {
+        attributes: BTreeMap::from_iter([
+            ("department".to_string(), vec!["sales".to_string()]),
+            ("office".to_string(), vec!["madrid".to_string()]),
+        ]),
+        ..old
+    };
+    let new = Principal {
+        attributes: BTreeMap::from_iter([
+            (
+                "department".to_string(),
+                vec!["sales".to_string(), "support".to_string()],
+            ),
+            ("title".to_string(), vec!["manager".to_string()]),
+        ]),
+        ..old.clone()
+    };
+    assert_eq!(
+        Principal::diff(&old, &new),
+        vec![
+            PrincipalUpdate::remove_item(
+                PrincipalField::Attributes,
+                PrincipalValue::String("office=madrid".to_string())
+            ),
+            PrincipalUpdate::add_item(
+                PrincipalField::Attributes,
+                PrincipalValue::String("department=support".to_string())
+            ),
+            PrincipalUpdate::add_item(
+                PrincipalField::Attributes,
+                PrincipalValue::String("title=manager".to_string())
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn internal_update_attributes() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing attribute updates with store {:?}", store_id);
+        store.destroy().await;
+
+        let old = Principal {
+            name: "john".to_string(),
+            attributes: BTreeMap::from_iter([
+                ("department".to_string(), vec!["sales".to_string()]),
+                ("office".to_string(), vec!["madrid".to_string()]),
+            ]),
+            ..Default::default()
+        };
+        store.create_account(old.clone(), vec![]).await.unwrap();
+
+        // Applying a diff updates the stored attributes
+        let new = Principal {
+            attributes: BTreeMap::from_iter([
+                (
+                    "department".to_string(),
+                    vec!["sales".to_string(), "support".to_string()],
+                ),
+                ("title".to_string(), vec!["manager".to_string()]),
+            ]),
+            ..old.clone()
+        };
+        store
+            .update_account(QueryBy::Name("john"), Principal::diff(&old, &new))
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .query(QueryBy::Name("john"), false)
+                .await
+                .unwrap()
+                .unwrap()
+                .attributes,
+            new.attributes
+        );
+
+        // Attributes without a name are rejected
+        assert_eq!(
+            store
+                .update_account(
+                    QueryBy::Name("john"),
+                    vec![PrincipalUpdate::add_item(
+                        PrincipalField::Attributes,
+                        PrincipalValue::String("=value".to_string()),
+                    )],
+                )
+                .await,
+            Err(DirectoryError::Management(ManagementError::InvalidValue {
+                field: PrincipalField::Attributes,
+                value: "=value".to_string()
+            }))
+        );
+    }
+}
+
+#[test]
+fn password_policy() {
+    let breach_list = std::env::temp_dir().join("stalwart_breached_passwords.txt");
+    std::fs::write(&breach_list, "123456\npassword\nCorrectHorse1!\n").unwrap();
+    let policy = PasswordPolicy::from_config(
+        &Config::new(&format!(
+            concat!(
+                "[authentication.password]\n",
+                "min-length = 8\n",
+                "require = [\"lowercase\", \"uppercase\", \"digit\"]\n",
+                "breach-list = \"{}\"\n"
+            ),
+            breach_list.display()
+        ))
+        .unwrap(),
+        "authentication.password",
+    )
+    .unwrap();
+    std::fs::remove_file(&breach_list).unwrap();
+
+    // Weak passwords are rejected with a descriptive reason
+    for (password, reason) in [
+        ("Ab1", "Password must be at least 8 characters long."),
+        (
+            "abcdefgh1",
+            "Password must contain at least an uppercase letter.",
+        ),
+        (
+            "ABCDEFGH1",
+            "Password must contain at least a lowercase letter.",
+        ),
+        ("Abcdefghi", "Password must contain at least a digit."),
+        ("CorrectHorse1!", "Password has appeared in a data breach."),
+        (
+            "{PLAIN}abcdefgh",
+            "Password must contain at least an uppercase letter.",
+        ),
+        (
+            "{weakpassword1}",
+            "Password must contain at least an uppercase letter.",
+        ),
+        (
+            "$weakpassword1",
+            "Password must contain at least an uppercase letter.",
+        ),
+        (
+            "_weakpassword1",
+            "Password must contain at least an uppercase letter.",
+        ),
+    ] {
+        assert_eq!(
+            policy.check(password),
+            Err(reason.to_string()),
+            "{password}"
+        );
+    }
+
+    // Acceptable and hashed passwords are allowed
+    for password in [
+        "Tr0ub4dor&3xyz",
+        "{PLAIN}Tr0ub4dor&3xyz",
+        "$6$rounds=5000$salt$hash",
+        "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=",
+        "{ssha512}W6ph5Mm5Pz8GgiULbPgzG37mj9g=",
+        "_9G..8147mpcfKT8g0U.",
+    ] {
+        assert_eq!(policy.check(password), Ok(()), "{password}");
+    }
+
+    // Secret updates are validated
+    assert!(matches!(
+        policy.check_updates(&[PrincipalUpdate::set(
+            PrincipalField::Secrets,
+            PrincipalValue::StringList(vec!["password".to_string()])
+        )]),
+        Err(DirectoryError::Management(ManagementError::WeakPassword(_)))
+    ));
+    assert!(policy
+        .check_updates(&[PrincipalUpdate::add_item(
+            PrincipalField::Secrets,
+            PrincipalValue::String("Tr0ub4dor&3xyz".to_string())
+        )])
+        .is_ok());
+    assert!(matches!(
+        policy.check_principal(&Principal {
+            name: "john".to_string(),
+            secrets: vec!["john123".to_string()],
+            ..Default::default()
+        }),
+        Err(DirectoryError::Management(ManagementError::WeakPassword(_)))
+    ));
+
+    // The default policy accepts any password
+    assert_eq!(PasswordPolicy::default().check("a"), Ok(()));
+}
+
+#[tokio::test]
+async fn internal_password_policy() {
+    let config = DirectoryTest::new(None).await;
+    let policy = PrincipalPolicy::from_config(
+        &Config::new("[authentication.password]\nmin-length = 8\n").unwrap(),
+    )
+    .unwrap();
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing password policy with store {:?}", store_id);
+        store.destroy().await;
+
+        // Weak passwords are rejected by the store
+        assert!(matches!(
+            store
+                .create_account_with_policy(
+                    Principal {
+                        name: "john".to_string(),
+                        secrets: vec!["{X}1".to_string()],
+                        ..Default::default()
+                    },
+                    vec![],
+                    &policy,
+                )
+                .await,
+            Err(DirectoryError::Management(ManagementError::WeakPassword(_)))
+        ));
+        assert_eq!(store.get_account_id("john").await.unwrap(), None);
+        store
+            .create_account_with_policy(
+                Principal {
+                    name: "john".to_string(),
+                    secrets: vec!["Tr0ub4dor&3xyz".to_string()],
+                    ..Default::default()
+                },
+                vec![],
+                &policy,
+            )
+            .await
+            .unwrap();
+
+        // Password changes are validated as well
+        assert!(matches!(
+            store
+                .update_account_with_policy(
+                    QueryBy::Name("john"),
+                    vec![PrincipalUpdate::set(
+                        PrincipalField::Secrets,
+                        PrincipalValue::StringList(vec!["$abc".to_string()]),
+                    )],
+                    &policy,
+                )
+                .await,
+            Err(DirectoryError::Management(ManagementError::WeakPassword(_)))
+        ));
+        let john = store
+            .query(QueryBy::Name("john"), false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(john.secrets, vec!["Tr0ub4dor&3xyz".to_string()]);
+    }
+}
+
+#[tokio::test]
+async fn internal_principal_defaults() {
+    let config = DirectoryTest::new(None).await;
+    let policy = PrincipalPolicy::from_config(
+        &Config::new("[directory.defaults]\nquota = 1073741824\n").unwrap(),
+    )
+    .unwrap();
+
+    // Requests without a quota are mapped to the unset sentinel
+    let principal: PrincipalResponse =
+        serde_json::from_str(r#"{"type":"individual","name":"john"}"#).unwrap();
+    assert_eq!(principal.quota, None);
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing principal defaults with store {:?}", store_id);
+        store.destroy().await;
+
+        for (name, quota, expected_quota, policy) in [
+            // Principals created without a quota inherit the default
+            ("john", QUOTA_UNSET, 1073741824, &policy),
+            // An explicit zero keeps the principal unlimited
+            ("jane", 0, 0, &policy),
+            // Explicit quotas are kept as they are
+            ("bill", 500, 500, &policy),
+            // Without a configured default, principals remain unlimited
+            ("mike", QUOTA_UNSET, 0, &PrincipalPolicy::default()),
+        ] {
+            store
+                .create_account_with_policy(
+                    Principal {
+                        name: name.to_string(),
+                        quota,
+                        ..Default::default()
+                    },
+                    vec![],
+                    policy,
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                store
+                    .query(QueryBy::Name(name), false)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .quota,
+                expected_quota,
+                "{name}"
+            );
+        }
+
+        // Resolved quotas are kept when accounts are exported and imported
+        let entries = store.export_accounts(PRINCIPAL_VERSION).await.unwrap();
+        store.destroy().await;
+        store.import_accounts(entries).await.unwrap();
+        for (name, expected_quota) in [("john", 1073741824), ("jane", 0), ("mike", 0)] {
+            assert_eq!(
+                store
+                    .query(QueryBy::Name(name), false)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .quota,
+                expected_quota,
+                "{name}"
+            );
+        }
+    }
+}
+
+#[test]
+fn reserved_names() {
+    let reserved = ReservedNames::from_config(
+        &Config::new(concat!(
+            "[directory.reserved-names]\n",
+            "type = \"glob\"\n",
+            "names = [\"admin\", \"root\", \"postmaster\", \"abuse\", \"no*reply\", \"sys?\"]\n"
+        ))
+        .unwrap(),
+        "directory.reserved-names",
+    )
+    .unwrap();
+
+    // Reserved names and glob matches
+    for name in ["admin", "Root", "postmaster", "noreply", "no-reply", "sys1"] {
+        assert!(reserved.is_reserved(name), "{name}");
+    }
+    for name in ["john", "administrator", "sys12", "reply"] {
+        assert!(!reserved.is_reserved(name), "{name}");
+    }
+
+    // Plain lists do not expand wildcards
+    let reserved = ReservedNames::from_config(
+        &Config::new("[directory.reserved-names]\ntype = \"list\"\nnames = [\"no*reply\"]\n")
+            .unwrap(),
+        "directory.reserved-names",
+    )
+    .unwrap();
+    assert!(reserved.is_reserved("no*reply"));
+    assert!(!reserved.is_reserved("noreply"));
+
+    // Only list and glob lookups are supported
+    assert!(ReservedNames::from_config(
+        &Config::new("[directory.reserved-names]\ntype = \"regex\"\n").unwrap(),
+        "directory.reserved-names",
+    )
+    .is_err());
+
+    // Nothing is reserved by default
+    assert!(!ReservedNames::default().is_reserved("admin"));
+}
+
+#[tokio::test]
+async fn internal_reserved_names() {
+    let config = DirectoryTest::new(None).await;
+    let reserved = ReservedNames::from_config(
+        &Config::new("[directory.reserved-names]\nnames = [\"postmaster\", \"no*reply\"]\n")
+            .unwrap(),
+        "directory.reserved-names",
+    )
+    .unwrap();
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing reserved names with store {:?}", store_id);
+        store.destroy().await;
+
+        // External directories cannot provision accounts with reserved names
+        for name in ["Postmaster", "no-reply"] {
+            assert_eq!(
+                store
+                    .provision_account_id(name, None, &reserved)
+                    .await
+                    .unwrap(),
+                None,
+                "{name}"
+            );
+        }
+        assert_eq!(
+            store
+                .provision_account_id("no-reply", "5e1b-02d7".into(), &reserved)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(store.get_account_id("postmaster").await.unwrap(), None);
+        assert_eq!(
+            store.lookup_by_external_id("5e1b-02d7").await.unwrap(),
+            None
+        );
+
+        // Other names are provisioned as usual
+        let john_id = store
+            .provision_account_id("john", None, &reserved)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(store.get_account_id("john").await.unwrap(), Some(john_id));
+
+        // Once a superuser creates a principal with a reserved name,
+        // external directories are able to map to it
+        let postmaster_id = store
+            .create_account(
+                Principal {
+                    name: "postmaster".to_string(),
+                    ..Default::default()
+                },
+                vec![],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .provision_account_id("Postmaster", None, &reserved)
+                .await
+                .unwrap(),
+            Some(postmaster_id)
+        );
+    }
+}
+
+#[tokio::test]
+async fn internal_features() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing principal feature flags with store {:?}", store_id);
+        store.destroy().await;
+
+        store
+            .create_account(
+                Principal {
+                    name: "john".to_string(),
+                    ..Default::default()
+                },
+                vec![],
+            )
+            .await
+            .unwrap();
+        let features = |store: Store| async move {
+            store
+                .query(QueryBy::Name("john"), false)
+                .await
+                .unwrap()
+                .unwrap()
+                .features
+        };
+
+        // All features are enabled by default
+        assert_eq!(features(store.clone()).await, FEATURES_ALL);
+
+        // Replace the enabled features
+        store
+            .update_account(
+                QueryBy::Name("john"),
+                vec![PrincipalUpdate::set(
+                    PrincipalField::Features,
+                    PrincipalValue::StringList(vec!["imap".to_string(), "jmap".to_string()]),
+                )],
+            )
+            .await
+            .unwrap();
+        let principal = store
+            .query(QueryBy::Name("john"), false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(principal.has_feature(FEATURE_IMAP));
+        assert!(principal.has_feature(FEATURE_JMAP));
+        assert!(!principal.has_feature(FEATURE_SEND_EXTERNAL));
+
+        // Remove and add individual features
+        store
+            .update_account(
+                QueryBy::Name("john"),
+                vec![PrincipalUpdate::remove_item(
+                    PrincipalField::Features,
+                    PrincipalValue::String("imap".to_string()),
+                )],
+            )
+            .await
+            .unwrap();
+        assert_eq!(features(store.clone()).await, FEATURE_JMAP);
+        store
+            .update_account(
+                QueryBy::Name("john"),
+                vec![PrincipalUpdate::add_item(
+                    PrincipalField::Features,
+                    PrincipalValue::String("send-external".to_string()),
+                )],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            features(store.clone()).await,
+            FEATURE_JMAP | FEATURE_SEND_EXTERNAL
+        );
+
+        // Enabling every known feature restores the default
+        store
+            .update_account(
+                QueryBy::Name("john"),
+                vec![PrincipalUpdate::add_item(
+                    PrincipalField::Features,
+                    PrincipalValue::String("imap".to_string()),
+                )],
+            )
+            .await
+            .unwrap();
+        assert_eq!(features(store.clone()).await, FEATURES_ALL);
+
+        // Unknown features are rejected
+        assert_eq!(
+            store
+                .update_account(
+                    QueryBy::Name("john"),
+                    vec![PrincipalUpdate::set(
+                        PrincipalField::Features,
+                        PrincipalValue::StringList(vec!["pop3".to_string()]),
+                    )],
+                )
+                .await,
+            Err(DirectoryError::Management(ManagementError::InvalidValue {
+                field: PrincipalField::Features,
+                value: "pop3".to_string()
+            }))
+        );
+        assert_eq!(features(store.clone()).await, FEATURES_ALL);
+    }
+}
+
+#[test]
+fn principal_serialize_features() {
+    let principal = Principal::<u32> {
+        id: 1,
+        name: "john".to_string(),
+        ..Default::default()
+    };
+
+    // Principals with all features enabled keep the previous format
+    let bytes = (&principal).serialize();
+    assert_eq!(bytes[0], 1);
+    assert_eq!(
+        Principal::<u32>::deserialize(&bytes).unwrap().features,
+        FEATURES_ALL
+    );
+
+    // Restricted features are stored and read back
+    let restricted = Principal {
+        features: FEATURE_IMAP,
+        ..principal.clone()
+    };
+    let bytes = (&restricted).serialize();
+    assert_eq!(bytes[0], 5);
+    let deserialized = Principal::<u32>::deserialize(&bytes).unwrap();
+    assert_eq!(deserialized.features, FEATURE_IMAP);
+    assert!(deserialized.has_feature(FEATURE_IMAP));
+    assert!(!deserialized.has_feature(FEATURE_SEND_EXTERNAL));
+}
+
+#[tokio::test]
+async fn internal_external_id() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing external principal ids with store {:?}", store_id);
+        store.destroy().await;
+
+        // First sync creates the account and links the external id
+        let account_id = store
+            .sync_account_id("john", "3f2a-91c4".into())
+            .await
+            .unwrap();
+        assert_eq!(
+            store.lookup_by_external_id("3f2a-91c4").await.unwrap(),
+            Some(account_id)
+        );
+        assert_eq!(
+            store
+                .query(QueryBy::Id(account_id), false)
+                .await
+                .unwrap()
+                .unwrap()
+                .external_id,
+            Some("3f2a-91c4".to_string())
+        );
+
+        // Syncing again under the same name is a no-op
+        assert_eq!(
+            store
+                .sync_account_id("john", "3f2a-91c4".into())
+                .await
+                .unwrap(),
+            account_id
+        );
+
+        // A rename in the external directory updates the existing record
+        assert_eq!(
+            store
+                .sync_account_id("jdoe", "3f2a-91c4".into())
+                .await
+                .unwrap(),
+            account_id
+        );
+        assert_eq!(
+            store.get_account_name(account_id).await.unwrap(),
+            Some("jdoe".to_string())
+        );
+        assert_eq!(
+            store.get_account_id("jdoe").await.unwrap(),
+            Some(account_id)
+        );
+        assert_eq!(store.get_account_id("john").await.unwrap(), None);
+        assert_eq!(
+            store.list_accounts(None, None).await.unwrap(),
+            vec!["jdoe".to_string()]
+        );
+
+        // External ids are unique
+        assert_eq!(
+            store
+                .create_account(
+                    Principal {
+                        name: "jane".to_string(),
+                        external_id: Some("3f2a-91c4".to_string()),
+                        ..Default::default()
+                    },
+                    vec![],
+                )
+                .await,
+            Err(DirectoryError::Management(ManagementError::AlreadyExists {
+                field: PrincipalField::ExternalId,
+                value: "3f2a-91c4".to_string()
+            }))
+        );
+
+        // Deleting the account releases the external id
+        store.delete_account(QueryBy::Id(account_id)).await.unwrap();
+        assert_eq!(
+            store.lookup_by_external_id("3f2a-91c4").await.unwrap(),
+            None
+        );
+    }
+}
+
+#[test]
+fn principal_serialize_external_id() {
+    let principal = Principal::<u32> {
+        id: 1,
+        name: "john".to_string(),
+        external_id: Some("3f2a-91c4".to_string()),
+        ..Default::default()
+    };
+
+    let bytes = (&principal).serialize();
+    assert_eq!(bytes[0], 6);
+    assert_eq!(Principal::<u32>::deserialize(&bytes).unwrap(), principal);
+
+    // Principals without an external id deserialize as before
+    let principal = serde_json::from_str::<Principal<String>>(
+        r#"{"type":"individual","name":"john","emails":["john@example.org"]}"#,
+    )
+    .unwrap();
+    assert_eq!(principal.external_id, None);
+    assert!(!serde_json::to_string(&principal)
+        .unwrap()
+        .contains("externalId"));
+}
+
+#[test]
+fn principal_serialize_message_count_quota() {
+    let principal = Principal::<u32> {
+        id: 1,
+        name: "john".to_string(),
+        quota: 1024,
+        message_count_quota: Some(500),
+        ..Default::default()
+    };
+
+    let bytes = (&principal).serialize();
+    assert_eq!(bytes[0], 7);
+    assert_eq!(Principal::<u32>::deserialize(&bytes).unwrap(), principal);
+    assert_eq!(principal.serialize_version(6), None);
+
+    // Principals without a message count quota are still written using older layouts
+    let principal = Principal::<u32> {
+        message_count_quota: None,
+        ..principal
+    };
+    assert_eq!((&principal).serialize()[0], 1);
+    assert_eq!(
+        Principal::<u32>::deserialize(&principal.serialize_version(7).unwrap())
+            .unwrap()
+            .message_count_quota,
+        None
+    );
+}
+
+#[test]
+fn principal_serialize_password_changed() {
+    let principal = Principal::<u32> {
+        id: 1,
+        name: "john".to_string(),
+        secrets: vec!["secret".to_string()],
+        password_changed: 1_700_000_000,
+        ..Default::default()
+    };
+
+    let bytes = (&principal).serialize();
+    assert_eq!(bytes[0], 8);
+    assert_eq!(Principal::<u32>::deserialize(&bytes).unwrap(), principal);
+
+    // Older layouts drop the time of the last password change
+    assert_eq!(
+        Principal::<u32>::deserialize(&principal.serialize_version(2).unwrap())
+            .unwrap()
+            .password_changed,
+        0
+    );
+
+    // Untracked passwords are written using the oldest layout
+    let principal = Principal::<u32> {
+        password_changed: 0,
+        ..principal
+    };
+    assert_eq!((&principal).serialize()[0], 1);
+
+    // Principals without a password change time deserialize as before
+    let principal = serde_json::from_str::<Principal<String>>(
+        r#"{"type":"individual","name":"john","secrets":["secret"]}"#,
+    )
+    .unwrap();
+    assert_eq!(principal.password_changed, 0);
+    assert!(!serde_json::to_string(&principal)
+        .unwrap()
+        .contains("passwordChanged"));
+}
+
+#[tokio::test]
+async fn internal_password_expiration() {
+    let config = DirectoryTest::new(None).await;
+    const DAY: u64 = 24 * 60 * 60;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing password expiration with store {:?}", store_id);
+        store.destroy().await;
+
+        let mut directory = Directory {
+            store: DirectoryInner::Internal(store.clone()),
+            cache: None,
+            on_error: Default::default(),
+            quota_inheritance: Default::default(),
+            query_timeout: None,
+            password_max_age: Some(Duration::from_secs(90 * DAY)),
+        };
+
+        // Setting a password records the time it was changed
+        let john_id = store
+            .create_account(
+                Principal {
+                    name: "john".to_string(),
+                    secrets: vec!["secret".to_string()],
+                    ..Default::default()
+                },
+                vec![],
+            )
+            .await
+            .unwrap();
+        let john = store
+            .query(QueryBy::Id(john_id), false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(john.password_changed > 0);
+        assert!(!directory.is_password_expired(&john));
+
+        // Passwords older than the maximum age are expired
+        let jane_id = store
+            .create_account(
+                Principal {
+                    name: "jane".to_string(),
+                    secrets: vec!["secret".to_string(), "app_secret".to_string()],
+                    password_changed: now() - 91 * DAY,
+                    ..Default::default()
+                },
+                vec![],
+            )
+            .await
+            .unwrap();
+        let jane = store
+            .query(QueryBy::Id(jane_id), false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(directory.is_password_expired(&jane));
+
+        // Logins with valid credentials resolve to the expired principal
+        let jane_login = directory
+            .query(
+                QueryBy::Credentials(&Credentials::Plain {
+                    username: "jane".to_string(),
+                    secret: "secret".to_string(),
+                }),
+                false,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(jane_login.id, jane_id);
+        assert!(directory.is_password_expired(&jane_login));
+
+        // Application passwords are told apart from the account password
+        assert!(jane.is_app_password("app_secret").await);
+        assert!(!jane.is_app_password("secret").await);
+        assert!(!jane.is_app_password("wrong_secret").await);
+
+        // Superusers and untracked passwords are exempt
+        assert!(!directory.is_password_expired(&Principal {
+            typ: Type::Superuser,
+            ..jane.clone()
+        }));
+        assert!(!directory.is_password_expired(&Principal {
+            password_changed: 0,
+            ..jane.clone()
+        }));
+
+        // Changing the password renews it
+        store
+            .update_account(
+                QueryBy::Id(jane_id),
+                vec![PrincipalUpdate::set(
+                    PrincipalField::Secrets,
+                    PrincipalValue::StringList(vec!["new_secret".to_string()]),
+                )],
+            )
+            .await
+            .unwrap();
+        let jane = store
+            .query(QueryBy::Id(jane_id), false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(jane.password_changed > now() - DAY);
+        assert!(!directory.is_password_expired(&jane));
+        let jane_login = directory
+            .query(
+                QueryBy::Credentials(&Credentials::Plain {
+                    username: "jane".to_string(),
+                    secret: "new_secret".to_string(),
+                }),
+                false,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!directory.is_password_expired(&jane_login));
+
+        // Passwords never expire without a maximum age
+        directory.password_max_age = None;
+        assert!(!directory.is_password_expired(&Principal {
+            password_changed: now() - 365 * DAY,
+            ..jane
+        }));
+    }
+}
+
+#[tokio::test]
+async fn internal_export_versions() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing account export with store {:?}", store_id);
+        store.destroy().await;
+        store.create_domain("example.org").await.unwrap();
+        for principal in [
+            Principal {
+                name: "sales".to_string(),
+                typ: Type::Group,
+                ..Default::default()
+            },
+            Principal {
+                name: "john".to_string(),
+                description: Some("John Doe".to_string()),
+                secrets: vec!["secret".to_string()],
+                emails: vec!["john@example.org".to_string()],
+                member_of: vec!["sales".to_string()],
+                ..Default::default()
+            },
+            Principal {
+                name: "jane".to_string(),
+                quota: 1024,
+                emails: vec![
+                    "jane@example.org".to_string(),
+                    "jane.doe@example.org".to_string(),
+                ],
+                ..Default::default()
+            },
+        ] {
+            store.create_account(principal, vec![]).await.unwrap();
+        }
+
+        // Export using older layouts and reimport into an empty store
+        for version in [1, 2] {
+            let entries = store.export_accounts(version).await.unwrap();
+            assert_eq!(entries.len(), 3);
+            assert!(entries.iter().all(|entry| entry.entry[0] == version));
+
+            // Failed imports do not leave partially imported accounts behind
+            store.destroy().await;
+            store.create_domain("example.org").await.unwrap();
+            store
+                .create_account(
+                    Principal {
+                        name: "sales".to_string(),
+                        typ: Type::Group,
+                        ..Default::default()
+                    },
+                    vec![],
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                store.import_accounts(entries.clone()).await,
+                Err(DirectoryError::Management(ManagementError::AlreadyExists {
+                    field: PrincipalField::Name,
+                    value: "sales".to_string()
+                }))
+            );
+            for name in ["john", "jane"] {
+                assert_eq!(store.get_account_id(name).await.unwrap(), None, "{name}");
+            }
+
+            store.destroy().await;
+            store.create_domain("example.org").await.unwrap();
+            assert_eq!(store.import_accounts(entries).await.unwrap().len(), 3);
+
+            let john = store
+                .query(QueryBy::Name("john"), false)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(john.description.as_deref(), Some("John Doe"));
+            assert_eq!(john.secrets, vec!["secret".to_string()]);
+            assert_eq!(john.emails, vec!["john@example.org".to_string()]);
+            assert_eq!(
+                john.member_of,
+                vec![store.get_account_id("sales").await.unwrap().unwrap()]
+            );
+            let jane = store
+                .query(QueryBy::Name("jane"), false)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(jane.quota, 1024);
+            assert_eq!(
+                jane.emails,
+                vec![
+                    "jane@example.org".to_string(),
+                    "jane.doe@example.org".to_string()
+                ]
+            );
+        }
+
+        // Older layouts cannot hold newer fields
+        store
+            .create_account(
+                Principal {
+                    name: "bill".to_string(),
+                    locale: Some("en-US".to_string()),
+                    ..Default::default()
+                },
+                vec![],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            store.export_accounts(2).await,
+            Err(DirectoryError::Management(
+                ManagementError::UnsupportedVersion {
+                    version: 2,
+                    accounts: vec!["bill".to_string()]
+                }
+            ))
+        );
+        assert!(matches!(
+            store.export_accounts(PRINCIPAL_VERSION + 1).await,
+            Err(DirectoryError::Unsupported)
+        ));
+        assert_eq!(
+            store
+                .export_accounts(PRINCIPAL_VERSION)
+                .await
+                .unwrap()
+                .len(),
+            4
+        );
+    }
+}
+
+#[tokio::test]
+async fn internal_email_collisions() {
+    let config = DirectoryTest::new(None).await;
+
+    for (store_id, store) in config.stores.stores {
+        println!("Testing e-mail collisions with store {:?}", store_id);
+        store.destroy().await;
+        store.create_domain("example.org").await.unwrap();
+
+        // Clean create, duplicate addresses within the principal are merged
+        let john_id = store
+            .create_account(
+                Principal {
+                    name: "john".to_string(),
+                    emails: vec![
+                        "john@example.org".to_string(),
+                        "John.Doe@example.org".to_string(),
+                        "john@EXAMPLE.org".to_string(),
+                    ],
+                    ..Default::default()
+                },
+                vec![],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .query(QueryBy::Id(john_id), false)
+                .await
+                .unwrap()
+                .unwrap()
+                .emails,
+            vec![
+                "john@example.org".to_string(),
+                "john.doe@example.org".to_string()
+            ]
+        );
+
+        // Any colliding address is reported and nothing is written
+        assert_eq!(
+            store
+                .create_account(
+                    Principal {
+                        name: "jane".to_string(),
+                        emails: vec![
+                            "jane@example.org".to_string(),
+                            "JOHN.DOE@example.org".to_string(),
+                        ],
+                        ..Default::default()
+                    },
+                    vec![],
+                )
+                .await,
+            Err(DirectoryError::Management(ManagementError::AlreadyExists {
+                field: PrincipalField::Emails,
+                value: "john.doe@example.org".to_string()
+            }))
+        );
+        assert_eq!(store.get_account_id("jane").await.unwrap(), None);
+        assert_eq!(
+            store.email_to_ids("jane@example.org").await.unwrap(),
+            Vec::<u32>::new()
+        );
+        assert_eq!(
+            store.email_to_ids("john.doe@example.org").await.unwrap(),
+            vec![john_id]
+        );
+
+        // Concurrent creates claiming the same address, only one wins
+        let (bill, mike) = tokio::join!(
+            store.create_account(
+                Principal {
+                    name: "bill".to_string(),
+                    emails: vec!["sales@example.org".to_string()],
+                    ..Default::default()
+                },
+                vec![],
+            ),
+            store.create_account(
+                Principal {
+                    name: "mike".to_string(),
+                    emails: vec!["sales@example.org".to_string()],
+                    ..Default::default()
+                },
+                vec![],
+            )
+        );
+        assert_eq!(
+            [&bill, &mike]
+                .iter()
+                .filter(|result| result.is_ok())
+                .count(),
+            1,
+            "{bill:?} {mike:?}"
+        );
+        let winner_id = bill.as_ref().or(mike.as_ref()).copied().unwrap();
+        assert_eq!(
+            bill.err().or(mike.err()),
+            Some(DirectoryError::Management(ManagementError::AlreadyExists {
+                field: PrincipalField::Emails,
+                value: "sales@example.org".to_string()
+            }))
+        );
+        assert_eq!(
+            store.email_to_ids("sales@example.org").await.unwrap(),
+            vec![winner_id]
+        );
     }
 }
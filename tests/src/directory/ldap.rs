@@ -21,12 +21,22 @@
  * for more details.
 */
 
-use std::fmt::Debug;
+use std::{collections::BTreeMap, fmt::Debug};
 
-use directory::{backend::internal::manage::ManageDirectory, Principal, QueryBy, Type};
+use directory::{
+    backend::internal::{lookup::DirectoryStore, manage::ManageDirectory},
+    core::config::ConfigDirectory,
+    Principal, QueryBy, Type,
+};
 use mail_send::Credentials;
+use store::Store;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    sync::watch,
+};
 
-use crate::directory::{map_account_ids, DirectoryTest, IntoSortedPrincipal};
+use crate::directory::{dummy_tls_acceptor, map_account_ids, DirectoryTest, IntoSortedPrincipal};
 
 #[tokio::test]
 async fn ldap_directory() {
@@ -95,6 +105,10 @@ async fn ldap_directory() {
             typ: Type::Individual,
             quota: 500000,
             emails: vec!["bill@example.org".to_string(),],
+            attributes: BTreeMap::from_iter([(
+                "employeeNumber".to_string(),
+                vec!["E-1234".to_string()]
+            )]),
             ..Default::default()
         }
         .into_sorted()
@@ -217,6 +231,189 @@ async fn ldap_directory() {
     );
 }
 
+#[tokio::test]
+async fn ldap_extra_attributes() {
+    let mut config = DirectoryTest::new("sqlite".into()).await;
+    let handle = config.directories.directories.remove("ldap").unwrap();
+    let base_store = config.stores.stores.get("sqlite").unwrap();
+    let attributes =
+        BTreeMap::from_iter([("employeeNumber".to_string(), vec!["E-1234".to_string()])]);
+
+    // Extra attributes are mapped from the LDAP entry
+    let principal = handle
+        .query(QueryBy::Name("bill"), false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(principal.attributes, attributes);
+
+    // and a copy is kept in the internal store
+    let principal = base_store
+        .query(QueryBy::Id(principal.id), false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(principal.name, "bill");
+    assert_eq!(principal.attributes, attributes);
+
+    // Principals without extra attributes keep none
+    let principal = handle
+        .query(QueryBy::Name("john"), false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(principal.attributes.is_empty());
+    assert!(base_store
+        .query(QueryBy::Id(principal.id), false)
+        .await
+        .unwrap()
+        .unwrap()
+        .attributes
+        .is_empty());
+}
+
+#[tokio::test]
+async fn ldap_starttls() {
+    let _shutdown_starttls = spawn_mock_ldap_server(9389, true);
+    let _shutdown_plain = spawn_mock_ldap_server(9390, false);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    for (port, allow_invalid_certs, expect_success) in [
+        // Server supports StartTLS
+        (9389, true, true),
+        // Certificate verification is applied after StartTLS
+        (9389, false, false),
+        // Server does not support StartTLS
+        (9390, true, false),
+    ] {
+        let mut config = utils::config::Config::new(&format!(
+            r#"[directory."ldap"]
+type = "ldap"
+url = "ldap://localhost:{port}"
+base-dn = "dc=example,dc=org"
+timeout = "5s"
+
+[directory."ldap".tls]
+mode = "starttls"
+allow-invalid-certs = {allow_invalid_certs}
+
+[directory."ldap".filter]
+name = "(uid=?)"
+
+[directory."ldap".attributes]
+name = "uid"
+"#
+        ))
+        .unwrap();
+        let directory = config
+            .parse_directory(&Default::default(), Store::default())
+            .await
+            .unwrap()
+            .directories
+            .remove("ldap")
+            .unwrap();
+
+        let result = directory.query(QueryBy::Name("john"), false).await;
+        if expect_success {
+            assert_eq!(result.unwrap(), None);
+        } else {
+            assert!(
+                result.is_err(),
+                "port {port}, allow-invalid-certs {allow_invalid_certs}: {result:?}"
+            );
+        }
+    }
+}
+
+pub fn spawn_mock_ldap_server(port: u16, supports_starttls: bool) -> watch::Sender<bool> {
+    let (tx, mut rx) = watch::channel(true);
+
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(format!("127.0.0.1:{port}"))
+            .await
+            .unwrap_or_else(|e| {
+                panic!("Failed to bind mock LDAP server to 127.0.0.1:{port}: {e}");
+            });
+        let acceptor = dummy_tls_acceptor();
+        loop {
+            tokio::select! {
+                stream = listener.accept() => {
+                    let (mut stream, _) = stream.unwrap();
+                    let acceptor = acceptor.clone();
+                    tokio::spawn(async move {
+                        // Expect a StartTLS extended request
+                        let (id, op) = match read_ldap_message(&mut stream).await {
+                            Some(message) => message,
+                            None => return,
+                        };
+                        assert_eq!(op, 0x77, "Expected StartTLS request");
+                        if !supports_starttls {
+                            // Reply with protocolError
+                            let _ = stream.write_all(&ldap_result(&id, 0x78, 2)).await;
+                            return;
+                        }
+                        stream.write_all(&ldap_result(&id, 0x78, 0)).await.unwrap();
+
+                        let mut stream = match acceptor.accept(stream).await {
+                            Ok(stream) => stream,
+                            Err(_) => return,
+                        };
+                        while let Some((id, op)) = read_ldap_message(&mut stream).await {
+                            let response = match op {
+                                // SearchRequest, no entries found
+                                0x63 => ldap_result(&id, 0x65, 0),
+                                // ExtendedRequest
+                                0x77 => ldap_result(&id, 0x78, 0),
+                                // UnbindRequest
+                                0x42 => break,
+                                _ => panic!("Unexpected LDAP operation {op:#x}"),
+                            };
+                            stream.write_all(&response).await.unwrap();
+                            stream.flush().await.unwrap();
+                        }
+                    });
+                },
+                _ = rx.changed() => {
+                    break;
+                }
+            };
+        }
+    });
+
+    tx
+}
+
+async fn read_ldap_message(stream: &mut (impl AsyncRead + Unpin)) -> Option<(Vec<u8>, u8)> {
+    // LDAPMessage ::= SEQUENCE { messageID INTEGER, protocolOp ... }
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await.ok()?;
+    let len = if header[1] & 0x80 != 0 {
+        let mut len_bytes = vec![0u8; (header[1] & 0x7f) as usize];
+        stream.read_exact(&mut len_bytes).await.ok()?;
+        len_bytes
+            .into_iter()
+            .fold(0usize, |len, byte| (len << 8) | byte as usize)
+    } else {
+        header[1] as usize
+    };
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).await.ok()?;
+    let id_len = body[1] as usize;
+    Some((body[2..2 + id_len].to_vec(), body[2 + id_len]))
+}
+
+fn ldap_result(id: &[u8], op: u8, result_code: u8) -> Vec<u8> {
+    // LDAPResult ::= resultCode ENUMERATED, matchedDN LDAPDN, diagnosticMessage LDAPString
+    let result = [0x0a, 0x01, result_code, 0x04, 0x00, 0x04, 0x00];
+    let mut message = vec![0x02, id.len() as u8];
+    message.extend_from_slice(id);
+    message.extend_from_slice(&[op, result.len() as u8]);
+    message.extend_from_slice(&result);
+    let mut bytes = vec![0x30, message.len() as u8];
+    bytes.extend(message);
+    bytes
+}
+
 fn compare_sorted<T: Eq + Debug>(v1: Vec<T>, v2: Vec<T>) {
     for val in v1.iter() {
         assert!(v2.contains(val), "{v1:?} != {v2:?}");
@@ -0,0 +1,150 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::{
+    backend::internal::{ldif::parse_ldif, PrincipalField, PrincipalUpdate, PrincipalValue},
+    Principal, Type, FEATURES_ALL,
+};
+
+#[test]
+fn ldif_roundtrip() {
+    let john = Principal {
+        id: 0,
+        typ: Type::Individual,
+        quota: 1024,
+        message_count_quota: None,
+        name: "john".to_string(),
+        secrets: vec!["{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=".to_string()],
+        emails: vec![
+            "john@example.org".to_string(),
+            "jdoe@example.org".to_string(),
+        ],
+        member_of: vec!["sales".to_string(), "support".to_string()],
+        description: Some("Jöhn Doe".to_string()),
+        attributes: Default::default(),
+        allowed_networks: Default::default(),
+        locale: None,
+        timezone: None,
+        features: FEATURES_ALL,
+        external_id: None,
+        password_changed: 0,
+    };
+    let sales = Principal {
+        id: 0,
+        typ: Type::Group,
+        quota: 0,
+        message_count_quota: None,
+        name: "sales".to_string(),
+        secrets: vec![],
+        emails: vec!["sales@example.org".to_string()],
+        member_of: vec![],
+        description: Some(format!(" Sales team{}", " of example.org".repeat(10))),
+        attributes: Default::default(),
+        allowed_networks: Default::default(),
+        locale: None,
+        timezone: None,
+        features: FEATURES_ALL,
+        external_id: None,
+        password_changed: 0,
+    };
+
+    // Export principals
+    let john_ldif = john.to_ldif("ou=people,dc=example,dc=org");
+    let sales_ldif = sales.to_ldif("ou=groups,dc=example,dc=org");
+    for expected in [
+        "dn: uid=john,ou=people,dc=example,dc=org\n",
+        "objectClass: posixAccount\n",
+        "uid: john\n",
+        "mail: john@example.org\n",
+        "mailAlias: jdoe@example.org\n",
+        "userPassword: {SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n",
+        "memberOf: sales\n",
+        "memberOf: support\n",
+        "diskQuota: 1024\n",
+        // Non-ASCII values are base64 encoded
+        "cn:: SsO2aG4gRG9l\n",
+    ] {
+        assert!(john_ldif.contains(expected), "{expected:?} {john_ldif}");
+    }
+    assert!(sales_ldif.contains("objectClass: posixGroup\n"));
+
+    // Values with a leading space are base64 encoded and long lines folded
+    assert!(sales_ldif.contains("cn:: IFNhbGVzIHRlYW0"), "{sales_ldif}");
+    assert!(
+        sales_ldif
+            .lines()
+            .all(|line| line.len() <= 76 && !line.starts_with("  ")),
+        "{sales_ldif}"
+    );
+
+    // Import principals
+    let ldif = format!("version: 1\n\n# Exported accounts\n{john_ldif}{sales_ldif}");
+    assert_eq!(
+        parse_ldif(&ldif).unwrap(),
+        vec![john.clone(), sales.clone()]
+    );
+
+    // Names are taken from the DN when the uid attribute is missing
+    assert_eq!(
+        parse_ldif("dn: uid=jane,dc=example,dc=org\r\nobjectClass: inetOrgPerson\r\nmail: jane@\r\n example.org\r\n")
+            .unwrap(),
+        vec![Principal {
+            name: "jane".to_string(),
+            typ: Type::Individual,
+            emails: vec!["jane@example.org".to_string()],
+            ..Default::default()
+        }]
+    );
+
+    // Invalid entries are rejected
+    assert!(parse_ldif("uid: jane\ninvalid\n").is_err());
+    assert!(parse_ldif("uid: jane\ncn:: !!!\n").is_err());
+    assert!(parse_ldif("uid: jane\ndiskQuota: lots\n").is_err());
+    assert!(parse_ldif("mail: jane@example.org\n").is_err());
+
+    // Convert to principal updates
+    assert_eq!(
+        sales.into_updates(),
+        vec![
+            PrincipalUpdate::set(
+                PrincipalField::Name,
+                PrincipalValue::String("sales".to_string())
+            ),
+            PrincipalUpdate::set(PrincipalField::Quota, PrincipalValue::Integer(0)),
+            PrincipalUpdate::set(
+                PrincipalField::Description,
+                PrincipalValue::String(format!(" Sales team{}", " of example.org".repeat(10)))
+            ),
+            PrincipalUpdate::set(PrincipalField::Secrets, PrincipalValue::StringList(vec![])),
+            PrincipalUpdate::set(
+                PrincipalField::Emails,
+                PrincipalValue::StringList(vec!["sales@example.org".to_string()])
+            ),
+            PrincipalUpdate::set(PrincipalField::MemberOf, PrincipalValue::StringList(vec![])),
+        ]
+    );
+    assert!(john.into_updates().contains(&PrincipalUpdate::set(
+        PrincipalField::Type,
+        PrincipalValue::String("individual".to_string())
+    )));
+}
@@ -0,0 +1,431 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use directory::{core::config::ConfigDirectory, Directories, Principal, QueryBy, Type};
+use mail_send::Credentials;
+use store::{config::ConfigStore, Store, Stores};
+use utils::config::Config;
+
+use crate::store::TempDir;
+
+const CONFIG: &str = r##"
+[directory."local"]
+type = "memory"
+
+[[directory."local".principals]]
+name = "john"
+secret = "12345"
+email = "john@example.org"
+
+[[directory."local".principals]]
+name = "jane"
+secret = "abcde"
+email = "jane@example.org"
+
+[directory."local".aliases]
+format.type = "multimap"
+format.comment = "#"
+values = "%{file:{ALIASES}}%"
+"##;
+
+const ALIASES: &str = r#"
+# Sales team
+sales@example.org john@example.org
+sales@example.org jane@example.org
+sales@example.org john@example.org
+
+# Support team
+support@example.org jane@example.org, helpdesk@otherdomain.org
+"#;
+
+#[tokio::test]
+async fn memory_aliases() {
+    let temp_dir = TempDir::new("memory_directory_tests", true);
+    let aliases = temp_dir.path.join("aliases");
+    std::fs::write(&aliases, ALIASES).unwrap();
+
+    let mut config = Config::new(&CONFIG.replace("{ALIASES}", aliases.to_str().unwrap())).unwrap();
+    config.resolve_macros().await;
+    let directory = config
+        .parse_directory(&Stores::default(), Store::default())
+        .await
+        .unwrap()
+        .directories
+        .remove("local")
+        .unwrap();
+    let john_id = directory.email_to_ids("john@example.org").await.unwrap();
+    let jane_id = directory.email_to_ids("jane@example.org").await.unwrap();
+
+    // Repeated keys are grouped into multiple values
+    assert!(directory.rcpt("sales@example.org").await.unwrap());
+    assert_eq!(
+        directory.email_to_ids("sales@example.org").await.unwrap(),
+        [john_id.clone(), jane_id.clone()].concat()
+    );
+    assert_eq!(
+        directory.expn("sales@example.org").await.unwrap(),
+        vec![
+            "john@example.org".to_string(),
+            "jane@example.org".to_string()
+        ]
+    );
+
+    // Delimited value lists are split, remote destinations are only expanded
+    assert!(directory.rcpt("support@example.org").await.unwrap());
+    assert_eq!(
+        directory.email_to_ids("support@example.org").await.unwrap(),
+        jane_id
+    );
+    assert_eq!(
+        directory.expn("support@example.org").await.unwrap(),
+        vec![
+            "jane@example.org".to_string(),
+            "helpdesk@otherdomain.org".to_string()
+        ]
+    );
+
+    // Unknown addresses are not resolved
+    assert!(!directory.rcpt("info@example.org").await.unwrap());
+    assert!(directory
+        .email_to_ids("info@example.org")
+        .await
+        .unwrap()
+        .is_empty());
+
+    temp_dir.delete();
+}
+
+const QUOTA_CONFIG: &str = r##"
+[directory."local"]
+type = "memory"
+quota-inheritance = "{POLICY}"
+
+[[directory."local".principals]]
+name = "john"
+secret = "12345"
+email = "john@example.org"
+member-of = ["sales", "support"]
+
+[[directory."local".principals]]
+name = "jane"
+secret = "abcde"
+email = "jane@example.org"
+quota = 1000
+member-of = ["sales", "support"]
+
+[[directory."local".principals]]
+name = "bill"
+secret = "fghij"
+email = "bill@example.org"
+
+[[directory."local".principals]]
+name = "sales"
+class = "group"
+quota = 5000
+
+[[directory."local".principals]]
+name = "support"
+class = "group"
+quota = 2000
+"##;
+
+#[tokio::test]
+async fn memory_quota_inheritance() {
+    for (policy, john_quota) in [("disabled", 0), ("max", 5000), ("min", 2000)] {
+        let mut config = Config::new(&QUOTA_CONFIG.replace("{POLICY}", policy)).unwrap();
+        let directory = config
+            .parse_directory(&Stores::default(), Store::default())
+            .await
+            .unwrap()
+            .directories
+            .remove("local")
+            .unwrap();
+
+        // Members without a quota inherit it from their groups
+        assert_eq!(
+            directory
+                .query(QueryBy::Name("john"), true)
+                .await
+                .unwrap()
+                .unwrap()
+                .quota,
+            john_quota,
+            "policy: {policy}"
+        );
+
+        // An explicit quota always takes precedence
+        assert_eq!(
+            directory
+                .query(QueryBy::Name("jane"), true)
+                .await
+                .unwrap()
+                .unwrap()
+                .quota,
+            1000,
+            "policy: {policy}"
+        );
+
+        // Principals without groups remain unlimited
+        assert_eq!(
+            directory
+                .query(QueryBy::Name("bill"), false)
+                .await
+                .unwrap()
+                .unwrap()
+                .quota,
+            0,
+            "policy: {policy}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn memory_from_principals() {
+    let directory = Directories::from_principals(vec![
+        Principal {
+            id: 1,
+            name: "john".to_string(),
+            secrets: vec!["12345".to_string()],
+            emails: vec![
+                "john@example.org".to_string(),
+                "jdoe@Example.org".to_string(),
+            ],
+            member_of: vec![2],
+            ..Default::default()
+        },
+        Principal {
+            id: 2,
+            typ: Type::Group,
+            name: "sales".to_string(),
+            emails: vec!["sales@example.org".to_string()],
+            ..Default::default()
+        },
+    ])
+    .directories
+    .remove("memory")
+    .unwrap();
+
+    // Principals are resolved by name, id and credentials
+    let john = directory
+        .query(QueryBy::Name("john"), true)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(john.id, 1);
+    assert_eq!(john.member_of, vec![2]);
+    assert_eq!(
+        directory
+            .query(QueryBy::Id(2), false)
+            .await
+            .unwrap()
+            .unwrap()
+            .name,
+        "sales"
+    );
+    assert_eq!(
+        directory
+            .query(
+                QueryBy::Credentials(&Credentials::Plain {
+                    username: "john".to_string(),
+                    secret: "12345".to_string()
+                }),
+                false
+            )
+            .await
+            .unwrap()
+            .map(|p| p.id),
+        Some(1)
+    );
+    assert!(directory
+        .query(QueryBy::Name("jane"), false)
+        .await
+        .unwrap()
+        .is_none());
+
+    // Addresses and domains are indexed
+    assert!(directory.is_local_domain("example.org").await.unwrap());
+    assert!(!directory.is_local_domain("example.net").await.unwrap());
+    assert!(directory.rcpt("jdoe@example.org").await.unwrap());
+    assert!(!directory.rcpt("jane@example.org").await.unwrap());
+    assert_eq!(
+        directory.email_to_ids("john@example.org").await.unwrap(),
+        vec![1]
+    );
+    assert_eq!(
+        directory.email_to_ids("sales@example.org").await.unwrap(),
+        vec![2]
+    );
+}
+
+const QUERY_CONFIG: &str = r##"
+[store."sql"]
+type = "sqlite"
+path = "{TMP}/aliases.db"
+
+[directory."local"]
+type = "memory"
+
+[[directory."local".principals]]
+name = "john"
+secret = "12345"
+email = "john@example.org"
+
+[[directory."local".principals]]
+name = "jane"
+secret = "abcde"
+email = "jane@example.org"
+
+[directory."local".aliases]
+store = "sql"
+query = "SELECT destination FROM aliases WHERE address = ? ORDER BY destination"
+cache.ttl.negative = "200ms"
+"##;
+
+#[tokio::test]
+async fn memory_query_aliases() {
+    let temp_dir = TempDir::new("memory_query_directory_tests", true);
+    let mut config =
+        Config::new(&QUERY_CONFIG.replace("{TMP}", temp_dir.path.to_str().unwrap())).unwrap();
+    let stores = config.parse_stores().await.unwrap();
+    let directory = config
+        .parse_directory(&stores, Store::default())
+        .await
+        .unwrap()
+        .directories
+        .remove("local")
+        .unwrap();
+
+    // Create the aliases table
+    let sql = stores.lookup_stores.get("sql").unwrap();
+    for query in [
+        "CREATE TABLE aliases (address TEXT NOT NULL, destination TEXT NOT NULL)",
+        "INSERT INTO aliases (address, destination) VALUES ('sales@example.org', 'john@example.org')",
+        "INSERT INTO aliases (address, destination) VALUES ('sales@example.org', 'jane@example.org')",
+    ] {
+        sql.query::<usize>(query, vec![]).await.unwrap();
+    }
+    let john_id = directory.email_to_ids("john@example.org").await.unwrap();
+    let jane_id = directory.email_to_ids("jane@example.org").await.unwrap();
+
+    // Hit
+    assert!(directory.rcpt("sales@example.org").await.unwrap());
+    assert_eq!(
+        directory.email_to_ids("sales@example.org").await.unwrap(),
+        [jane_id, john_id.clone()].concat()
+    );
+
+    // Miss
+    assert!(!directory.rcpt("info@example.org").await.unwrap());
+
+    // Cached results are reused until they expire
+    for query in [
+        "DELETE FROM aliases WHERE address = 'sales@example.org'",
+        "INSERT INTO aliases (address, destination) VALUES ('info@example.org', 'john@example.org')",
+    ] {
+        sql.query::<usize>(query, vec![]).await.unwrap();
+    }
+    assert_eq!(
+        directory.expn("sales@example.org").await.unwrap(),
+        vec![
+            "jane@example.org".to_string(),
+            "john@example.org".to_string()
+        ]
+    );
+    assert!(!directory.rcpt("info@example.org").await.unwrap());
+
+    // Missing keys are only cached briefly
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(directory.rcpt("info@example.org").await.unwrap());
+    assert_eq!(
+        directory.email_to_ids("info@example.org").await.unwrap(),
+        john_id
+    );
+    assert!(directory.rcpt("sales@example.org").await.unwrap());
+
+    temp_dir.delete();
+}
+
+const SCHEMES_CONFIG: &str = r##"
+[directory."local"]
+type = "memory"
+
+[[directory."local".principals]]
+name = "john"
+secret = "{SSHA}oyoPmFhfVVnVfuAY6SIjgyIyabtzYWx0"
+email = "john@example.org"
+
+[[directory."local".principals]]
+name = "jane"
+secret = "{plain}abcde"
+email = "jane@example.org"
+
+[[directory."local".principals]]
+name = "bill"
+secret = ["{RC4}fghij", "{PLAIN}fghij"]
+email = "bill@example.org"
+"##;
+
+#[tokio::test]
+async fn memory_secret_schemes() {
+    let mut config = Config::new(SCHEMES_CONFIG).unwrap();
+    let directory = config
+        .parse_directory(&Stores::default(), Store::default())
+        .await
+        .unwrap()
+        .directories
+        .remove("local")
+        .unwrap();
+
+    // Unsupported schemes are reported and ignored
+    assert_eq!(config.errors.len(), 1, "{:?}", config.errors);
+    assert!(config
+        .errors
+        .keys()
+        .all(|key| key.starts_with("directory.local.principals.")));
+
+    for (username, secret, expected) in [
+        ("john", "p4ssw0rd", true),
+        ("john", "{SSHA}oyoPmFhfVVnVfuAY6SIjgyIyabtzYWx0", false),
+        ("jane", "abcde", true),
+        ("jane", "{plain}abcde", false),
+        ("bill", "fghij", true),
+    ] {
+        assert_eq!(
+            directory
+                .query(
+                    QueryBy::Credentials(&Credentials::Plain {
+                        username: username.to_string(),
+                        secret: secret.to_string(),
+                    }),
+                    false,
+                )
+                .await
+                .unwrap()
+                .is_some(),
+            expected,
+            "{username} {secret}"
+        );
+    }
+}
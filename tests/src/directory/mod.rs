@@ -24,19 +24,25 @@
 pub mod imap;
 pub mod internal;
 pub mod ldap;
+pub mod ldif;
+pub mod memory;
 pub mod smtp;
 pub mod sql;
 
 use directory::{
-    backend::internal::manage::ManageDirectory, core::config::ConfigDirectory, AddressMapping,
-    Directories, Principal,
+    backend::internal::manage::ManageDirectory,
+    core::{
+        cache::{membership_generation, CacheStats, CachedDirectory, LookupCache},
+        config::{build_keepalive, ConfigDirectory, KeepaliveSocket, TcpKeepalive},
+    },
+    AddressMapping, Directories, Principal,
 };
 use mail_send::Credentials;
 use rustls::ServerConfig;
 use rustls_pemfile::{certs, pkcs8_private_keys};
 use rustls_pki_types::PrivateKeyDer;
-use std::{borrow::Cow, io::BufReader, path::PathBuf, sync::Arc};
-use store::{config::ConfigStore, LookupStore, Store, Stores};
+use std::{borrow::Cow, io::BufReader, path::PathBuf, sync::Arc, time::Duration};
+use store::{ahash::AHashSet, config::ConfigStore, LookupStore, Store, Stores};
 use tokio_rustls::TlsAcceptor;
 
 use crate::store::TempDir;
@@ -205,6 +211,7 @@ email = "mail"
 email-alias = "givenName"
 quota = "diskQuota"
 type = "objectClass"
+extra = "employeeNumber"
 
 ##############################################################################
 
@@ -239,7 +246,7 @@ implicit = true
 allow-invalid-certs = true
 
 [directory."smtp".cache]
-entries = 500
+size = 500
 ttl = {positive = '10s', negative = '5s'}
 
 ##############################################################################
@@ -671,6 +678,228 @@ async fn address_mappings() {
     }
 }
 
+#[tokio::test]
+async fn directory_health() {
+    // Accept connections without ever sending a greeting
+    let hung_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let hung_port = hung_listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let mut connections = Vec::new();
+        while let Ok((stream, _)) = hung_listener.accept().await {
+            connections.push(stream);
+        }
+    });
+
+    // Obtain a port nobody is listening on
+    let closed_port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let mut config = utils::config::Config::new(&format!(
+        r#"
+    [directory."healthy"]
+    type = "memory"
+
+    [directory."unreachable"]
+    type = "smtp"
+    host = "127.0.0.1"
+    port = {closed_port}
+
+    [directory."hung"]
+    type = "smtp"
+    host = "127.0.0.1"
+    port = {hung_port}
+    "#
+    ))
+    .unwrap();
+    let directories = config
+        .parse_directory(&Stores::default(), Store::default())
+        .await
+        .unwrap();
+
+    let started = std::time::Instant::now();
+    let health = directories
+        .health_with_timeout(Duration::from_millis(500))
+        .await;
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    assert_eq!(
+        health.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(),
+        ["healthy", "hung", "unreachable"]
+    );
+    assert_eq!(health[0].1, Ok(()));
+    assert!(
+        health[1].1.as_ref().unwrap_err().contains("timed out"),
+        "{health:?}"
+    );
+    assert!(health[2].1.is_err(), "{health:?}");
+}
+
+#[test]
+fn cache_ttl_jitter() {
+    let ttl = Duration::from_secs(3600);
+    let mut cache = LookupCache::<String>::new(1000, ttl, ttl, 10);
+    let ttls = (0..1000)
+        .map(|_| cache.jittered_ttl(ttl))
+        .collect::<Vec<_>>();
+
+    // TTLs stay within the 10% jitter band
+    for ttl in &ttls {
+        assert!(
+            (Duration::from_secs(3240)..=Duration::from_secs(3960)).contains(ttl),
+            "{ttl:?}"
+        );
+    }
+
+    // Expirations are spread out across the band
+    let min_ttl = ttls.iter().min().unwrap();
+    let max_ttl = ttls.iter().max().unwrap();
+    assert!(
+        *max_ttl - *min_ttl > Duration::from_secs(360),
+        "{min_ttl:?} {max_ttl:?}"
+    );
+    assert!(ttls.iter().collect::<AHashSet<_>>().len() > 900);
+
+    // Jittered entries are still cached
+    for i in 0..1000 {
+        cache.insert_pos(format!("user{i}@example.org"));
+        cache.insert_neg(format!("unknown{i}@example.org"));
+    }
+    for i in 0..1000 {
+        assert_eq!(cache.get(&format!("user{i}@example.org")), Some(true));
+        assert_eq!(cache.get(&format!("unknown{i}@example.org")), Some(false));
+    }
+
+    // Disabling jitter keeps the configured TTL
+    let cache = LookupCache::<String>::new(1000, ttl, ttl, 0);
+    assert!((0..100).all(|_| cache.jittered_ttl(ttl) == ttl));
+}
+
+#[test]
+fn cache_lru_eviction() {
+    let mut config = utils::config::Config::new(
+        r#"
+[directory."sized".cache]
+size = 3
+
+[directory."legacy".cache]
+entries = 10
+"#,
+    )
+    .unwrap();
+    let cache = CachedDirectory::try_from_config(&mut config, ("directory", "sized")).unwrap();
+    assert_eq!(
+        CachedDirectory::try_from_config(&mut config, ("directory", "legacy"))
+            .unwrap()
+            .stats()
+            .size,
+        10
+    );
+    assert!(CachedDirectory::try_from_config(&mut config, ("directory", "none")).is_none());
+
+    // Fill the cache and touch the oldest entry
+    for i in 0..3 {
+        cache.set_rcpt(&format!("user{i}@example.org"), true);
+    }
+    assert_eq!(cache.get_rcpt("user0@example.org"), Some(true));
+
+    // Inserting beyond the size evicts the least recently used entry
+    cache.set_rcpt("user3@example.org", true);
+    assert_eq!(cache.get_rcpt("user1@example.org"), None);
+    for i in [0, 2, 3] {
+        assert_eq!(cache.get_rcpt(&format!("user{i}@example.org")), Some(true));
+    }
+
+    // Negative entries are bounded separately
+    cache.set_rcpt("unknown@example.org", false);
+    assert_eq!(cache.get_rcpt("user0@example.org"), Some(true));
+    assert_eq!(cache.get_rcpt("unknown@example.org"), Some(false));
+    cache.set_domain("example.org", true);
+    cache.set_groups(1, vec![2, 3], membership_generation());
+    assert_eq!(
+        cache.stats(),
+        CacheStats {
+            size: 3,
+            domains: 1,
+            domains_negative: 0,
+            rcpts: 3,
+            rcpts_negative: 1,
+            groups: 1,
+        }
+    );
+}
+
+#[test]
+fn pool_keepalive() {
+    struct RecordingSocket(std::sync::Mutex<Vec<TcpKeepalive>>);
+
+    impl KeepaliveSocket for RecordingSocket {
+        fn set_keepalive(&self, keepalive: &TcpKeepalive) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(*keepalive);
+            Ok(())
+        }
+    }
+
+    let mut config = utils::config::Config::new(
+        r#"
+[directory."default".pool]
+max-connections = 10
+
+[directory."custom".pool]
+keepalive = "5m"
+keepalive-interval = "30s"
+
+[directory."disabled".pool]
+keepalive = false
+
+[directory."invalid".pool]
+keepalive-interval = "often"
+"#,
+    )
+    .unwrap();
+
+    // Keepalive is enabled by default
+    let socket = RecordingSocket(Default::default());
+    build_keepalive(&mut config, "directory.default")
+        .unwrap()
+        .unwrap()
+        .apply(&socket);
+    assert_eq!(
+        socket.0.lock().unwrap().as_slice(),
+        &[TcpKeepalive {
+            time: Duration::from_secs(60),
+            interval: Duration::from_secs(15),
+        }]
+    );
+
+    // Custom settings are passed to the socket
+    let socket = RecordingSocket(Default::default());
+    build_keepalive(&mut config, "directory.custom")
+        .unwrap()
+        .unwrap()
+        .apply(&socket);
+    assert_eq!(
+        socket.0.lock().unwrap().as_slice(),
+        &[TcpKeepalive {
+            time: Duration::from_secs(300),
+            interval: Duration::from_secs(30),
+        }]
+    );
+
+    // Keepalive can be disabled
+    assert_eq!(
+        build_keepalive(&mut config, "directory.disabled"),
+        Some(None)
+    );
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+    // Invalid settings are reported
+    assert_eq!(build_keepalive(&mut config, "directory.invalid"), None);
+    assert_eq!(config.errors.len(), 1, "{:?}", config.errors);
+}
+
 async fn map_account_ids(store: &Store, names: Vec<impl AsRef<str>>) -> Vec<u32> {
     let mut ids = Vec::with_capacity(names.len());
     for name in names {
@@ -21,11 +21,15 @@
  * for more details.
 */
 
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use directory::{DirectoryError, QueryBy};
+use directory::{core::config::ConfigDirectory, DirectoryError, QueryBy};
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
+use store::Store;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
@@ -33,16 +37,22 @@ use tokio::{
 };
 use tokio_rustls::TlsAcceptor;
 
-use utils::listener::limiter::{ConcurrencyLimiter, InFlight};
+use utils::{
+    config::Config,
+    listener::limiter::{ConcurrencyLimiter, InFlight},
+};
 
-use crate::directory::{DirectoryTest, Item, LookupResult};
+use crate::{
+    directory::{DirectoryTest, Item, LookupResult},
+    smtp::inbound::dummy_stores,
+};
 
 use super::dummy_tls_acceptor;
 
 #[tokio::test]
 async fn smtp_directory() {
     // Spawn mock LMTP server
-    let shutdown = spawn_mock_lmtp_server(5);
+    let shutdown = spawn_mock_lmtp_server(9199, 5);
     tokio::time::sleep(std::time::Duration::from_millis(100)).await;
 
     // Obtain directory handle
@@ -197,14 +207,62 @@ async fn smtp_directory() {
     }
 }
 
-pub fn spawn_mock_lmtp_server(max_concurrency: u64) -> watch::Sender<bool> {
+#[tokio::test]
+async fn smtp_directory_query_timeout() {
+    // Spawn mock LMTP server
+    let shutdown = spawn_mock_lmtp_server(9197, 5);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let directories = Config::new(
+        r#"
+[directory."slow"]
+type = "lmtp"
+host = "127.0.0.1"
+port = 9197
+query-timeout = "200ms"
+
+[directory."slow".pool]
+max-connections = 1
+
+[directory."slow".tls]
+enable = true
+allow-invalid-certs = true
+"#,
+    )
+    .unwrap()
+    .parse_directory(&dummy_stores(), Store::default())
+    .await
+    .unwrap();
+    let handle = directories.directories.get("slow").unwrap();
+
+    // Fast lookups complete normally
+    assert!(handle.rcpt("john-ok@domain").await.unwrap());
+
+    // Lookups running past the query timeout fail with a timeout error
+    let started = Instant::now();
+    assert!(matches!(
+        handle.rcpt("slow-ok@domain").await,
+        Err(DirectoryError::TimedOut)
+    ));
+    assert!(started.elapsed() < Duration::from_millis(800));
+
+    // The interrupted connection is discarded rather than answering later
+    // lookups with the reply to the timed out command
+    tokio::time::sleep(Duration::from_millis(1200)).await;
+    assert!(!handle.rcpt("john@domain").await.unwrap());
+    assert!(handle.rcpt("john-ok@domain").await.unwrap());
+
+    shutdown.send(false).ok();
+}
+
+pub fn spawn_mock_lmtp_server(port: u16, max_concurrency: u64) -> watch::Sender<bool> {
     let (tx, rx) = watch::channel(true);
 
     tokio::spawn(async move {
-        let listener = TcpListener::bind("127.0.0.1:9199")
+        let listener = TcpListener::bind(format!("127.0.0.1:{port}"))
             .await
             .unwrap_or_else(|e| {
-                panic!("Failed to bind mock SMTP server to 127.0.0.1:9199: {e}");
+                panic!("Failed to bind mock SMTP server to 127.0.0.1:{port}: {e}");
             });
         let acceptor = dummy_tls_acceptor();
         let limited = ConcurrencyLimiter::new(max_concurrency);
@@ -250,6 +308,9 @@ async fn accept_smtp(
     }
 
     let mut buf_u8 = vec![0u8; 1024];
+    let mut rcpts = Vec::new();
+    let mut message = None;
+    let mut is_authenticated = false;
 
     loop {
         let br = tokio::select! {
@@ -269,20 +330,57 @@ async fn accept_smtp(
         };
 
         let buf = std::str::from_utf8(&buf_u8[0..br]).unwrap();
-        let response = if buf.starts_with("LHLO") {
-            "250-mx.foobar.org\r\n250 AUTH PLAIN\r\n".to_string()
+        let response = if let Some(data) = &mut message {
+            data.push_str(buf);
+            if !data.ends_with("\r\n.\r\n") {
+                continue;
+            }
+            let is_rejected = data.contains("reject-me");
+            message = None;
+            std::mem::take(&mut rcpts)
+                .into_iter()
+                .map(|rcpt: String| {
+                    if is_rejected {
+                        "554 5.7.1 Message content rejected.\r\n"
+                    } else if rcpt.contains("full") {
+                        "452 4.2.2 Mailbox full.\r\n"
+                    } else {
+                        "250 2.1.5 Message delivered.\r\n"
+                    }
+                })
+                .collect::<String>()
+        } else if buf.starts_with("LHLO") {
+            "250-mx.foobar.org\r\n250-SIZE 1000000\r\n250-DSN\r\n250 AUTH PLAIN\r\n".to_string()
         } else if buf.starts_with("MAIL FROM") {
-            if buf.contains("<>") || buf.contains("ok@") {
+            rcpts.clear();
+            if buf.contains("dsn") && (!buf.contains(" RET=HDRS") || !buf.contains(" ENVID=")) {
+                "555 5.5.4 Missing DSN parameters.\r\n".to_string()
+            } else if buf.contains(" AUTH=") && !is_authenticated {
+                "530 5.7.0 Authentication required.\r\n".to_string()
+            } else if buf.contains(" AUTH=") && !buf.contains(" SIZE=") {
+                "555 5.5.4 Missing SIZE parameter.\r\n".to_string()
+            } else if buf.contains("<>") || buf.contains("ok@") {
                 "250 OK\r\n".to_string()
             } else {
                 "552-I do not\r\n552 like that MAIL FROM.\r\n".to_string()
             }
         } else if buf.starts_with("RCPT TO") {
-            if buf.contains("ok") {
+            if buf.contains("slow") {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            if buf.contains("dsn")
+                && (!buf.contains(" NOTIFY=SUCCESS") || !buf.contains(" ORCPT=rfc822;"))
+            {
+                "555 5.5.4 Missing DSN parameters.\r\n".to_string()
+            } else if buf.contains("ok") {
+                rcpts.push(buf.to_string());
                 "250 OK\r\n".to_string()
             } else {
                 "550-I refuse to\r\n550 accept that recipient.\r\n".to_string()
             }
+        } else if buf.starts_with("DATA") {
+            message = Some(String::new());
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n".to_string()
         } else if buf.starts_with("VRFY") {
             if buf.contains("ok") {
                 format!("250 {}\r\n", buf.split_once(' ').unwrap().1)
@@ -319,6 +417,7 @@ async fn accept_smtp(
         } else if buf.starts_with("AUTH PLAIN") {
             let buf = base64_decode(buf.rsplit_once(' ').unwrap().1.as_bytes()).unwrap();
             if String::from_utf8_lossy(&buf).contains("ok") {
+                is_authenticated = true;
                 "235 Great success!\r\n".to_string()
             } else {
                 "535 No soup for you\r\n".to_string()
@@ -328,6 +427,7 @@ async fn accept_smtp(
         } else if buf.starts_with("QUIT") {
             "250 Arrivederci!\r\n".to_string()
         } else if buf.starts_with("RSET") {
+            rcpts.clear();
             "250 Your wish is my command.\r\n".to_string()
         } else {
             panic!("Unknown command: {}", buf.trim());
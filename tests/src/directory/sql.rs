@@ -21,7 +21,15 @@
  * for more details.
 */
 
-use directory::{backend::internal::manage::ManageDirectory, Principal, QueryBy, Type};
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use directory::{
+    backend::{internal::manage::ManageDirectory, sql::SqlRetry},
+    Principal, QueryBy, Type,
+};
 use mail_send::Credentials;
 use store::{LookupStore, Store};
 
@@ -29,6 +37,50 @@ use crate::directory::{map_account_ids, DirectoryTest};
 
 use super::DirectoryStore;
 
+#[tokio::test]
+async fn sql_retry_transient() {
+    let retry = SqlRetry {
+        attempts: 2,
+        backoff: Duration::from_millis(10),
+    };
+
+    // A backend that fails once with a transient error recovers after a single retry
+    let calls = AtomicU32::new(0);
+    let result = retry
+        .run(|| async {
+            if calls.fetch_add(1, Ordering::Relaxed) == 0 {
+                Err(store::Error::TransientError("connection reset".to_string()))
+            } else {
+                Ok(true)
+            }
+        })
+        .await;
+    assert!(matches!(result, Ok(true)));
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+    // Non-transient errors are not retried
+    let calls = AtomicU32::new(0);
+    let result = retry
+        .run(|| async {
+            calls.fetch_add(1, Ordering::Relaxed);
+            Err::<bool, _>(store::Error::InternalError("syntax error".to_string()))
+        })
+        .await;
+    assert!(matches!(result, Err(store::Error::InternalError(_))));
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+    // Retries are bounded
+    let calls = AtomicU32::new(0);
+    let result = retry
+        .run(|| async {
+            calls.fetch_add(1, Ordering::Relaxed);
+            Err::<bool, _>(store::Error::TransientError("deadlock".to_string()))
+        })
+        .await;
+    assert!(matches!(result, Err(store::Error::TransientError(_))));
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+}
+
 #[tokio::test]
 async fn sql_directory() {
     // Enable logging
@@ -296,8 +348,8 @@ impl DirectoryStore {
         for query in [
             concat!(
                 "CREATE TABLE accounts (name TEXT PRIMARY KEY, secret TEXT, description TEXT,",
-                " type TEXT NOT NULL, quota INTEGER ",
-                "DEFAULT 0, active BOOLEAN DEFAULT TRUE)"
+                " type TEXT NOT NULL, quota INTEGER DEFAULT 0, message_count_quota ",
+                "INTEGER DEFAULT 0, active BOOLEAN DEFAULT TRUE)"
             ),
             concat!(
                 "CREATE TABLE group_members (name TEXT NOT NULL, member_of ",
@@ -422,6 +474,20 @@ impl DirectoryStore {
             .unwrap();
     }
 
+    pub async fn set_test_message_count_quota(&self, login: &str, quota: u32) {
+        self.store
+            .query::<usize>(
+                if self.is_postgresql() {
+                    "UPDATE accounts SET message_count_quota = $1 where name = $2"
+                } else {
+                    "UPDATE accounts SET message_count_quota = ? where name = ?"
+                },
+                vec![quota.into(), login.into()],
+            )
+            .await
+            .unwrap();
+    }
+
     pub async fn add_to_group(&self, login: &str, group: &str) {
         self.store
             .query::<usize>(
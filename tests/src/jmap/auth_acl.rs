@@ -22,7 +22,10 @@
 */
 
 use directory::backend::internal::manage::ManageDirectory;
-use jmap::mailbox::{INBOX_ID, TRASH_ID};
+use jmap::{
+    mailbox::{INBOX_ID, TRASH_ID},
+    JMAP,
+};
 use jmap_client::{
     core::{
         error::{MethodError, MethodErrorType},
@@ -33,8 +36,9 @@ use jmap_client::{
     principal::ACL,
 };
 use jmap_proto::types::id::Id;
-use std::fmt::Debug;
+use std::{fmt::Debug, time::Instant};
 use store::ahash::AHashMap;
+use utils::map::ttl_dashmap::TtlMap;
 
 use crate::jmap::{assert_is_empty, mailbox::destroy_all_mailboxes, test_account_login};
 
@@ -338,6 +342,15 @@ pub async fn test(params: &mut JMAPTest) {
             .await,
     );
 
+    // Let John's session object expire, Jane's account should be read-only
+    expire_session(&server, john_id.document_id());
+    john_client.refresh_session().await.unwrap();
+    assert!(john_client
+        .session()
+        .account(&jane_id.to_string())
+        .unwrap()
+        .is_read_only());
+
     // Grant access and try again
     jane_client
         .mailbox_update_acl(
@@ -348,6 +361,21 @@ pub async fn test(params: &mut JMAPTest) {
         .await
         .unwrap();
 
+    // The session object is reused until its TTL expires, then recomputed
+    john_client.refresh_session().await.unwrap();
+    assert!(john_client
+        .session()
+        .account(&jane_id.to_string())
+        .unwrap()
+        .is_read_only());
+    expire_session(&server, john_id.document_id());
+    john_client.refresh_session().await.unwrap();
+    assert!(!john_client
+        .session()
+        .account(&jane_id.to_string())
+        .unwrap()
+        .is_read_only());
+
     let mut request = john_client
         .set_default_account_id(&jane_id.to_string())
         .build();
@@ -794,6 +822,22 @@ pub async fn test(params: &mut JMAPTest) {
     assert_is_empty(server).await;
 }
 
+fn expire_session(server: &JMAP, account_id: u32) {
+    let keys = server
+        .cache_sessions
+        .iter()
+        .map(|entry| entry.key().clone())
+        .filter(|(id, _)| *id == account_id)
+        .collect::<Vec<_>>();
+    for key in keys {
+        if let Some(session) = server.cache_sessions.get_with_ttl(&key) {
+            server
+                .cache_sessions
+                .insert_with_ttl(key, session, Instant::now());
+        }
+    }
+}
+
 pub fn assert_forbidden<T: Debug>(result: Result<T, jmap_client::Error>) {
     if !matches!(
         result,
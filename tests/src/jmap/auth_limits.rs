@@ -263,6 +263,31 @@ pub async fn test(params: &mut JMAPTest) {
         client.upload(None, b"sleep".to_vec(), None).await,
         Err(jmap_client::Error::Problem(err)) if err.status() == Some(400)));
 
+    // Concurrent session test
+    let mut sessions = Vec::new();
+    for _ in 0..4 {
+        sessions.push(
+            client
+                .event_source(None::<Vec<_>>, false, None, None)
+                .await
+                .unwrap(),
+        );
+    }
+    assert!(matches!(
+        client.event_source(None::<Vec<_>>, false, None, None).await,
+        Err(jmap_client::Error::Problem(err)) if err.status() == Some(400)));
+
+    // Closing a session releases its slot
+    sessions.pop();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    sessions.push(
+        client
+            .event_source(None::<Vec<_>>, false, None, None)
+            .await
+            .unwrap(),
+    );
+    drop(sessions);
+
     // Destroy test accounts
     params.client.set_default_account_id(&account_id);
     destroy_all_mailboxes(params).await;
@@ -25,6 +25,7 @@ use directory::backend::internal::manage::ManageDirectory;
 use jmap::mailbox::INBOX_ID;
 use jmap_proto::types::id::Id;
 use serde_json::Value;
+use utils::config::cron::SimpleCron;
 
 use crate::jmap::{assert_is_empty, jmap_json_request, mailbox::destroy_all_mailboxes};
 
@@ -90,6 +91,71 @@ pub async fn test(params: &mut JMAPTest) {
         response
     );
 
+    // Blob/upload should reject denied types, either declared or sniffed
+    let response = jmap_json_request(
+        r##"[[
+            "Blob/upload",
+            {
+             "accountId": "$$",
+             "create": {
+              "declared": {
+               "data" : [
+               {
+                "data:asText": "The quick brown fox jumped over the lazy dog."
+               }
+              ],
+              "type": "application/x-executable"
+              },
+              "sniffed": {
+               "data" : [
+               {
+                "data:asText": "#!/bin/sh\necho 'The quick brown fox'\n"
+               }
+              ],
+              "type": "text/plain"
+              }
+             }
+            },
+            "R1"
+           ]]"##
+            .replace("$$", &account_id.to_string()),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await;
+    for create_id in ["declared", "sniffed"] {
+        assert_eq!(
+            response
+                .pointer(&format!("/methodResponses/0/1/notCreated/{create_id}/type"))
+                .and_then(|v| v.as_str())
+                .unwrap_or_default(),
+            "forbidden",
+            "Response: {:?}",
+            response
+        );
+    }
+
+    // Uploads to the upload endpoint are also validated
+    let account_id_str = account_id.to_string();
+    assert!(params
+        .client
+        .upload(
+            Some(&account_id_str),
+            b"The quick brown fox".to_vec(),
+            Some("application/x-executable")
+        )
+        .await
+        .is_err());
+    assert!(params
+        .client
+        .upload(
+            Some(&account_id_str),
+            b"#!/bin/sh\necho 'The quick brown fox'\n".to_vec(),
+            Some("text/plain")
+        )
+        .await
+        .is_err());
+
     // Blob/get simple test
     let blob_id = jmap_json_request(
         r#"[[
@@ -498,3 +564,34 @@ pub async fn test(params: &mut JMAPTest) {
     destroy_all_mailboxes(params).await;
     assert_is_empty(server).await;
 }
+
+#[test]
+fn upload_purge_frequency() {
+    // Temporary uploads are purged every hour by default
+    let config = jmap::Config::new(&utils::config::Config::new("").unwrap()).unwrap();
+    assert_eq!(
+        config.upload_tmp_purge_frequency,
+        SimpleCron::Hour { minute: 15 }
+    );
+
+    // Upload purges run on their own schedule
+    let config = jmap::Config::new(
+        &utils::config::Config::new(
+            r#"[jmap.session.purge]
+frequency = "15 * *"
+
+[jmap.protocol.upload.purge]
+frequency = "30 4 *"
+"#,
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        config.upload_tmp_purge_frequency,
+        SimpleCron::Day {
+            hour: 4,
+            minute: 30
+        }
+    );
+}
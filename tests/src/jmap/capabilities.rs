@@ -0,0 +1,104 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::api::session::Session;
+use jmap_proto::{
+    error::request::RequestErrorType,
+    request::{capability::Capability, Request},
+};
+
+#[test]
+fn disabled_capabilities() {
+    let settings = utils::config::Config::new(
+        r#"[jmap.capabilities]
+disable = ["sieve", "urn:ietf:params:jmap:quota"]
+"#,
+    )
+    .unwrap();
+    let config = jmap::Config::new(&settings).unwrap();
+
+    // Disabled capabilities are not advertised
+    for capability in [Capability::Sieve, Capability::Quota] {
+        assert!(config.capabilities.is_disabled(capability));
+        assert!(!config.capabilities.session.contains_key(&capability));
+        assert!(!config.capabilities.account.contains_key(&capability));
+    }
+    for capability in [Capability::Core, Capability::Mail, Capability::Blob] {
+        assert!(!config.capabilities.is_disabled(capability));
+        assert!(config.capabilities.session.contains_key(&capability));
+    }
+    let session =
+        serde_json::to_string(&Session::new("https://localhost", &config.capabilities)).unwrap();
+    assert!(!session.contains(Capability::Sieve.as_str()), "{session}");
+    assert!(!session.contains(Capability::Quota.as_str()), "{session}");
+    assert!(session.contains(Capability::Mail.as_str()), "{session}");
+    assert!(
+        session.contains(Capability::WebSocket.as_str()),
+        "{session}"
+    );
+
+    // Requests using disabled capabilities are rejected
+    let request = Request::parse(
+        br#"{
+            "using": ["urn:ietf:params:jmap:core", "urn:ietf:params:jmap:sieve"],
+            "methodCalls": [["SieveScript/get", {"accountId": "a"}, "0"]]
+        }"#,
+        16,
+        10000,
+    )
+    .unwrap();
+    assert!(matches!(
+        config.capabilities.validate_using(request.using),
+        Err(err) if matches!(err.p_type, RequestErrorType::UnknownCapability)
+    ));
+    assert!(config
+        .capabilities
+        .is_disabled(request.method_calls[0].name.capability()));
+
+    // Methods of enabled capabilities are allowed
+    let request = Request::parse(
+        br#"{
+            "using": ["urn:ietf:params:jmap:core", "urn:ietf:params:jmap:mail"],
+            "methodCalls": [["Email/get", {"accountId": "a"}, "0"],
+                            ["Quota/get", {"accountId": "a"}, "1"]]
+        }"#,
+        16,
+        10000,
+    )
+    .unwrap();
+    assert!(config.capabilities.validate_using(request.using).is_ok());
+    assert!(!config
+        .capabilities
+        .is_disabled(request.method_calls[0].name.capability()));
+    assert!(config
+        .capabilities
+        .is_disabled(request.method_calls[1].name.capability()));
+
+    // Core and unknown capabilities cannot be disabled
+    for disable in ["core", "urn:ietf:params:jmap:unknown"] {
+        let settings =
+            utils::config::Config::new(&format!("[jmap.capabilities]\ndisable = [{disable:?}]\n"))
+                .unwrap();
+        assert!(jmap::Config::new(&settings).is_err(), "{disable}");
+    }
+}
@@ -23,6 +23,7 @@
 
 use std::{fs, path::PathBuf};
 
+use jmap::email::parse::mime_depth;
 use jmap_client::{
     email::{self, Header, HeaderForm},
     mailbox::Role,
@@ -252,6 +253,87 @@ pub async fn test(params: &mut JMAPTest) {
         panic!("Test failed, output saved to {}", test_file.display());
     }
 
+    // Messages nested beyond the maximum depth are not parsed or imported
+    for (depth, expect_ok) in [(20, true), (25, false)] {
+        assert_eq!(
+            params
+                .client
+                .email_import(
+                    nested_message(depth).into_bytes(),
+                    [mailbox_id.clone()],
+                    None::<Vec<String>>,
+                    None,
+                )
+                .await
+                .is_ok(),
+            expect_ok,
+            "depth {depth}"
+        );
+        let blob_id = params
+            .client
+            .upload(None, nested_message(depth).into_bytes(), None)
+            .await
+            .unwrap()
+            .take_blob_id();
+        assert_eq!(
+            params
+                .client
+                .email_parse(&blob_id, [email::Property::Subject].into(), [].into(), None)
+                .await
+                .is_ok(),
+            expect_ok,
+            "depth {depth}"
+        );
+    }
+
     destroy_all_mailboxes(params).await;
     assert_is_empty(server).await;
 }
+
+#[test]
+fn email_parse_max_depth() {
+    // Nesting is limited to 20 levels by default
+    let config = jmap::Config::new(&utils::config::Config::new("").unwrap()).unwrap();
+    assert_eq!(config.mail_parse_max_depth, 20);
+    let config = jmap::Config::new(
+        &utils::config::Config::new("[jmap.email.parse]\nmax-depth = 5\n").unwrap(),
+    )
+    .unwrap();
+    assert_eq!(config.mail_parse_max_depth, 5);
+
+    // Multiparts and attached messages each add a level
+    for depth in [1, 2, 20, 25] {
+        assert_eq!(mime_depth(nested_message(depth).as_bytes()), depth);
+    }
+    let attached = format!(
+        concat!(
+            "Subject: Forward\r\n",
+            "Content-Type: multipart/mixed; boundary=\"fwd\"\r\n\r\n",
+            "--fwd\r\n",
+            "Content-Type: message/rfc822\r\n\r\n",
+            "{}",
+            "--fwd--\r\n"
+        ),
+        nested_message(3)
+    );
+    assert_eq!(mime_depth(attached.as_bytes()), 5);
+
+    // Unterminated multiparts are still measured
+    let truncated = nested_message(30);
+    let truncated = &truncated[..truncated.find("Hello").unwrap()];
+    assert_eq!(mime_depth(truncated.as_bytes()), 30);
+}
+
+fn nested_message(depth: usize) -> String {
+    let mut message = "From: john@example.org\r\nSubject: Nested\r\n".to_string();
+    for level in 1..depth {
+        message.push_str(&format!(
+            "Content-Type: multipart/mixed; boundary=\"={level}=\"\r\n\r\n--={level}=\r\n"
+        ));
+    }
+    message.push_str("Content-Type: text/plain\r\n\r\nHello world\r\n");
+    for level in (1..depth).rev() {
+        message.push_str(&format!("--={level}=--\r\n"));
+    }
+    message
+}
@@ -45,6 +45,7 @@ pub mod auth_acl;
 pub mod auth_limits;
 pub mod auth_oauth;
 pub mod blob;
+pub mod capabilities;
 pub mod crypto;
 pub mod delivery;
 pub mod email_changes;
@@ -200,6 +201,9 @@ max-objects = 100000
 [jmap.protocol.request]
 max-concurrent = 8
 
+[jmap.protocol.session]
+max-concurrent = 4
+
 [jmap.protocol.upload]
 max-size = 5000000
 max-concurrent = 4
@@ -209,6 +213,12 @@ ttl = "1m"
 files = 3
 size = 50000
 
+[jmap.protocol.upload.types]
+deny = ["application/x-executable", "text/x-shellscript"]
+
+[jmap.session]
+ttl = "1m"
+
 [jmap.rate-limit]
 account = "1000/1m"
 authentication = "100/2s"
@@ -229,7 +239,7 @@ type = "sqlite"
 path = "{TMP}/auth.db"
 
 [store."auth".query]
-name = "SELECT name, type, secret, description, quota FROM accounts WHERE name = ? AND active = true"
+name = "SELECT name, type, secret, description, quota, message_count_quota FROM accounts WHERE name = ? AND active = true"
 members = "SELECT member_of FROM group_members WHERE name = ?"
 recipients = "SELECT name FROM emails WHERE address = ?"
 emails = "SELECT address FROM emails WHERE name = ? AND type != 'list' ORDER BY type DESC, address ASC"
@@ -247,6 +257,7 @@ description = "description"
 secret = "secret"
 email = "address"
 quota = "quota"
+message-count-quota = "message_count_quota"
 type = "type"
 
 [store."local/domains"]
@@ -334,6 +334,41 @@ pub async fn test(params: &mut JMAPTest) {
             .len(),
         1,
     );
+
+    // Test delivery message count quota, with the byte quota lifted so that
+    // only the message count can cause a rejection
+    params
+        .directory
+        .set_test_quota("robert@example.com", 0)
+        .await;
+    params
+        .directory
+        .set_test_message_count_quota("robert@example.com", 2)
+        .await;
+    for (i, expected_count) in [(2, 2), (3, 2)] {
+        lmtp.ingest(
+            "jane@example.com",
+            &["robert@example.com"],
+            &String::from_utf8(create_message_with_size(
+                "jane@example.com",
+                "robert@example.com",
+                &format!("Ingest test {i}"),
+                100,
+            ))
+            .unwrap(),
+        )
+        .await;
+        assert_eq!(
+            server
+                .get_document_ids(account_id.document_id(), Collection::Email)
+                .await
+                .unwrap()
+                .unwrap()
+                .len(),
+            expected_count,
+            "for message {i}"
+        );
+    }
     DISABLE_UPLOAD_QUOTA.store(true, std::sync::atomic::Ordering::Relaxed);
 
     // Remove test data
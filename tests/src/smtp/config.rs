@@ -21,10 +21,28 @@
  * for more details.
 */
 
-use std::{fs, net::IpAddr, path::PathBuf, time::Duration};
+use std::{
+    fs,
+    net::IpAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
 
+use mail_auth::hickory_resolver::config::{Protocol, ResolverOpts};
+use mail_send::smtp::tls::build_tls_connector;
+use rustls_pki_types::ServerName;
 use store::config::ConfigStore;
-use tokio::net::TcpSocket;
+use tokio::{
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+        DuplexStream, Lines, ReadBuf,
+    },
+    net::{tcp::OwnedReadHalf, TcpSocket, TcpStream},
+};
+use tokio_rustls::TlsConnector;
 
 use utils::{
     config::{
@@ -37,13 +55,13 @@ use utils::{
 
 use smtp::{
     config::{
-        map_expr_token, throttle::ConfigThrottle, ConfigContext, Throttle, THROTTLE_AUTH_AS,
-        THROTTLE_REMOTE_IP, THROTTLE_SENDER_DOMAIN,
+        map_expr_token, resolver::ConfigResolver, session::ConfigSession, throttle::ConfigThrottle,
+        ConfigContext, Throttle, THROTTLE_AUTH_AS, THROTTLE_REMOTE_IP, THROTTLE_SENDER_DOMAIN,
     },
-    core::{eval::*, ResolveVariable},
+    core::{eval::*, ResolveVariable, SmtpSessionManager, SMTP},
 };
 
-use super::add_test_certs;
+use super::{add_test_certs, inbound::TestMessage, session::VerifyResponse, TestConfig, TestSMTP};
 
 struct TestEnvelope {
     pub local_ip: IpAddr,
@@ -332,6 +350,105 @@ fn parse_throttle() {
     );
 }
 
+#[test]
+fn parse_reject_templates() {
+    let config = Config::new(concat!(
+        "[session.reject]\n",
+        "relay-denied = '{reason} Relaying to {recipient} for {sender} denied, see https://example.org/help'\n",
+        "dkim = '{reason}'\n",
+    ))
+    .unwrap();
+    let reject = config.parse_session_reject().unwrap();
+    assert!(reject.unknown_recipient.is_none());
+
+    // Placeholders are replaced and stripped of line breaks
+    assert_eq!(
+        String::from_utf8(reject.relay_denied.unwrap().render(
+            "550 5.1.2",
+            "john@example.org",
+            "jane@example.net\r\n250 2.1.5 OK",
+            "Relay not allowed.",
+        ))
+        .unwrap(),
+        concat!(
+            "550 5.1.2 Relay not allowed. Relaying to jane@example.net250 2.1.5 OK ",
+            "for john@example.org denied, see https://example.org/help\r\n"
+        )
+    );
+    assert_eq!(
+        reject
+            .dkim
+            .unwrap()
+            .render("550 5.7.20", "", "", "No passing DKIM signatures found."),
+        b"550 5.7.20 No passing DKIM signatures found.\r\n"
+    );
+
+    // Templates with line breaks or unknown placeholders are rejected
+    for template in [r#""Rejected.\r\n250 2.1.5 OK""#, "'{address} is unknown.'"] {
+        assert!(
+            Config::new(&format!("[session.reject]\ndkim = {template}\n"))
+                .unwrap()
+                .parse_session_reject()
+                .is_err(),
+            "{template}"
+        );
+    }
+}
+
+#[test]
+fn parse_resolver() {
+    let config = Config::new(concat!(
+        "[resolver]\n",
+        "type = 'custom'\n",
+        "custom = ['udp://127.0.0.1:5353', 'tcp://192.168.0.1', '10.0.0.1']\n",
+        "timeout = '2s'\n",
+        "attempts = 5\n",
+        "edns = true\n",
+    ))
+    .unwrap();
+    let (resolver, opts) = config.parse_resolver_config().unwrap();
+    assert_eq!(
+        resolver
+            .name_servers()
+            .iter()
+            .map(|ns| (ns.socket_addr.to_string(), ns.protocol))
+            .collect::<Vec<_>>(),
+        vec![
+            ("127.0.0.1:5353".to_string(), Protocol::Udp),
+            ("192.168.0.1:53".to_string(), Protocol::Tcp),
+            ("10.0.0.1:53".to_string(), Protocol::Udp),
+        ]
+    );
+    assert_eq!(opts.timeout, Duration::from_secs(2));
+    assert_eq!(opts.attempts, 5);
+    assert!(opts.edns0);
+
+    // Options left unset keep their defaults
+    let (_, opts) = Config::new("[resolver]\ntype = 'cloudflare'\n")
+        .unwrap()
+        .parse_resolver_config()
+        .unwrap();
+    let defaults = ResolverOpts::default();
+    assert_eq!(opts.timeout, defaults.timeout);
+    assert_eq!(opts.attempts, defaults.attempts);
+    assert_eq!(opts.edns0, defaults.edns0);
+
+    // Invalid name servers are rejected
+    for custom in [
+        "['ftp://10.0.0.1']",
+        "['10.0.0.1:dns']",
+        "['dns.example.org']",
+    ] {
+        assert!(
+            Config::new(&format!("[resolver]\ntype = 'custom'\ncustom = {custom}\n"))
+                .unwrap()
+                .parse_resolver_config()
+                .is_err(),
+            "{custom}"
+        );
+    }
+}
+
 #[test]
 fn parse_servers() {
     let mut file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -633,3 +750,333 @@ impl TestEnvelope {
         }
     }
 }
+
+#[tokio::test]
+async fn tls_session_resumption() {
+    let mut config = Config::new(&add_test_certs(
+        r#"
+[server]
+hostname = "mx.example.org"
+
+[server.listener."smtp"]
+bind = ["127.0.0.1:9925"]
+protocol = "smtp"
+
+[server.listener."submission"]
+bind = ["127.0.0.1:9991"]
+protocol = "smtp"
+tls.session.resumption = false
+
+[server.tls]
+enable = true
+certificate = "default"
+
+[server.tls.session]
+tickets = true
+ticket-rotation = "1h"
+
+[certificate."default"]
+cert = "%{file:{CERT}}%"
+private-key = "%{file:{PK}}%"
+"#,
+    ))
+    .unwrap();
+    config.resolve_macros().await;
+    let servers = config.parse_servers().unwrap().inner;
+
+    for (server, expect_resumed) in servers.iter().zip([true, false]) {
+        let acceptor = match &server.acceptor {
+            TcpAcceptor::Tls(acceptor) => acceptor.clone(),
+            _ => panic!("Expected TLS acceptor for {}", server.id),
+        };
+        let connector = build_tls_connector(true);
+
+        for attempt in 0..2 {
+            let (client, server_io) = tokio::io::duplex(16384);
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let mut stream = acceptor.accept(server_io).await.unwrap();
+                stream.write_all(b"220 OK\r\n").await.unwrap();
+                stream.flush().await.unwrap();
+                let _ = stream.read(&mut [0u8; 1]).await;
+            });
+            let mut stream = connector
+                .connect(
+                    ServerName::try_from("localhost").unwrap(),
+                    CountingStream {
+                        inner: client,
+                        bytes_read: 0,
+                    },
+                )
+                .await
+                .unwrap();
+
+            // Resumed handshakes do not carry the server certificate
+            let (io, conn) = stream.get_ref();
+            let cert_len = conn.peer_certificates().unwrap()[0].len();
+            let resumed = io.bytes_read < cert_len;
+            assert_eq!(
+                resumed,
+                expect_resumed && attempt == 1,
+                "listener {}, attempt {attempt}, read {} bytes, certificate is {cert_len} bytes",
+                server.id,
+                io.bytes_read
+            );
+
+            // Read the greeting so that session tickets are received
+            let mut greeting = [0u8; 8];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(&greeting, b"220 OK\r\n");
+        }
+    }
+}
+
+struct CountingStream {
+    inner: DuplexStream,
+    bytes_read: usize,
+}
+
+impl AsyncRead for CountingStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.bytes_read += buf.filled().len() - filled;
+        result
+    }
+}
+
+impl AsyncWrite for CountingStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[tokio::test]
+async fn tls_min_version() {
+    let mut config = Config::new(&add_test_certs(
+        r#"
+[server]
+hostname = "mx.example.org"
+
+[server.listener."smtp"]
+bind = ["127.0.0.1:9925"]
+protocol = "smtp"
+
+[server.listener."submission"]
+bind = ["127.0.0.1:9991"]
+protocol = "smtp"
+tls.min-version = "TLSv1.3"
+
+[server.tls]
+enable = true
+certificate = "default"
+min-version = "TLSv1.2"
+
+[certificate."default"]
+cert = "%{file:{CERT}}%"
+private-key = "%{file:{PK}}%"
+"#,
+    ))
+    .unwrap();
+    config.resolve_macros().await;
+    let servers = config.parse_servers().unwrap().inner;
+    let acceptors = servers
+        .iter()
+        .map(|server| match &server.acceptor {
+            TcpAcceptor::Tls(acceptor) => acceptor.clone(),
+            _ => panic!("Expected TLS acceptor for {}", server.id),
+        })
+        .collect::<Vec<_>>();
+
+    // TLS 1.1 handshakes are refused with a protocol version alert
+    for acceptor in &acceptors {
+        let (mut client, server) = tokio::io::duplex(4096);
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let _ = acceptor.accept(server).await;
+        });
+        // Record and handshake headers
+        let mut client_hello = vec![0x16, 0x03, 0x01, 0x00, 0x3c, 0x01, 0x00, 0x00, 0x38];
+        // Version TLS 1.1 and random
+        client_hello.extend_from_slice(&[0x03, 0x02]);
+        client_hello.extend_from_slice(&[0u8; 32]);
+        // No session id, TLS_RSA_WITH_AES_128_CBC_SHA, no compression
+        client_hello.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x2f, 0x01, 0x00]);
+        // renegotiation_info and signature_algorithms extensions
+        client_hello.extend_from_slice(&[0x00, 0x0d, 0xff, 0x01, 0x00, 0x01, 0x00]);
+        client_hello.extend_from_slice(&[0x00, 0x0d, 0x00, 0x04, 0x00, 0x02, 0x04, 0x01]);
+        client.write_all(&client_hello).await.unwrap();
+        let mut alert = [0u8; 7];
+        client.read_exact(&mut alert).await.unwrap();
+        assert_eq!(alert[0], 0x15, "{alert:?}");
+        assert_eq!(alert[6], 70, "{alert:?}");
+    }
+
+    // TLS 1.2 is accepted on port 25 but refused on submission, the
+    // handshake on port 25 only fails because the test certificate is not trusted
+    for (acceptor, expected_err) in acceptors
+        .into_iter()
+        .zip(["invalid peer certificate", "ProtocolVersion"])
+    {
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            let _ = acceptor.accept(server).await;
+        });
+        let err = TlsConnector::from(Arc::new(
+            rustls::ClientConfig::builder_with_protocol_versions(&[&rustls::version::TLS12])
+                .with_root_certificates(rustls::RootCertStore::empty())
+                .with_no_client_auth(),
+        ))
+        .connect(ServerName::try_from("localhost").unwrap(), client)
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains(expected_err), "{err}");
+    }
+
+    // Minimum versions excluding all enabled protocols are rejected
+    let mut config = Config::new(&add_test_certs(
+        r#"
+[server]
+hostname = "mx.example.org"
+
+[server.listener."submission"]
+bind = ["127.0.0.1:9991"]
+protocol = "smtp"
+tls.protocols = ["TLSv1.2"]
+tls.min-version = "TLSv1.3"
+
+[server.tls]
+enable = true
+certificate = "default"
+
+[certificate."default"]
+cert = "%{file:{CERT}}%"
+private-key = "%{file:{PK}}%"
+"#,
+    ))
+    .unwrap();
+    config.resolve_macros().await;
+    let err = config.parse_servers().err().unwrap();
+    assert!(err.contains("excludes all protocols"), "{err}");
+}
+
+#[tokio::test]
+async fn server_hostname() {
+    // Hostnames have to be fully qualified
+    for hostname in [
+        "localhost",
+        "-mx.example.org",
+        "mx..example.org",
+        "mx_1.example.org",
+    ] {
+        let config = Config::new(&format!(
+            "[server]\nhostname = {hostname:?}\n\n[server.listener.smtp]\nbind = ['127.0.0.1:9926']\nprotocol = 'smtp'\n"
+        ))
+        .unwrap();
+        assert!(config.parse_servers().is_err(), "{hostname}");
+    }
+
+    // Short hostnames explain how to fix them
+    let err = Config::new(
+        "[server]\nhostname = \"mail\"\n\n[server.listener.smtp]\nbind = ['127.0.0.1:9926']\nprotocol = 'smtp'\n",
+    )
+    .unwrap()
+    .parse_servers()
+    .err()
+    .unwrap();
+    assert!(err.contains("set \"server.hostname\" explicitly"), "{err}");
+
+    // Trailing dots are accepted and removed
+    let config = Config::new(
+        "[server]\nhostname = \"mx.example.org.\"\n\n[server.listener.smtp]\nbind = ['127.0.0.1:9926']\nprotocol = 'smtp'\n",
+    )
+    .unwrap();
+    assert_eq!(
+        config.parse_servers().unwrap().inner[0].hostname,
+        "mx.example.org"
+    );
+
+    // The configured hostname is used in the greeting and in Received headers
+    let config = Config::new(
+        r#"
+[server]
+hostname = "mail.canonical.example"
+greeting = "Test SMTP instance"
+
+[server.listener.smtp]
+bind = ["127.0.0.1:9926"]
+protocol = "smtp"
+
+[server.socket]
+reuse-addr = true
+"#,
+    )
+    .unwrap();
+    let mut servers = config.parse_servers().unwrap();
+    assert_eq!(servers.inner[0].hostname, "mail.canonical.example");
+
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_server_hostname_test");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    let core = Arc::new(core);
+    servers.bind(&config);
+    let manager = SmtpSessionManager::new(core);
+    let _shutdown_tx = servers
+        .spawn(|server, shutdown_rx| server.spawn(manager.clone(), shutdown_rx))
+        .0;
+
+    let stream = TcpStream::connect("127.0.0.1:9926").await.unwrap();
+    let (rx, mut tx) = stream.into_split();
+    let mut lines = BufReader::new(rx).lines();
+    assert_eq!(
+        expect_reply(&mut lines, "220").await,
+        "220 mail.canonical.example Test SMTP instance"
+    );
+    for (command, code) in [
+        ("EHLO mx.foobar.org\r\n", "250"),
+        ("MAIL FROM:<john@foobar.org>\r\n", "250"),
+        ("RCPT TO:<bill@foobar.org>\r\n", "250"),
+        ("DATA\r\n", "354"),
+        (
+            "From: john@foobar.org\r\nTo: bill@foobar.org\r\nSubject: test\r\n\r\ntest\r\n.\r\n",
+            "250",
+        ),
+        ("QUIT\r\n", "221"),
+    ] {
+        tx.write_all(command.as_bytes()).await.unwrap();
+        expect_reply(&mut lines, code).await;
+    }
+
+    qr.expect_message()
+        .await
+        .read_lines(&qr)
+        .await
+        .assert_contains("by mail.canonical.example");
+}
+
+async fn expect_reply(lines: &mut Lines<BufReader<OwnedReadHalf>>, code: &str) -> String {
+    loop {
+        let line = lines.next_line().await.unwrap().unwrap();
+        if !line.starts_with(&format!("{code}-")) {
+            assert!(line.starts_with(code), "{line}");
+            return line;
+        }
+    }
+}
@@ -21,18 +21,23 @@
  * for more details.
 */
 
+use std::sync::Arc;
+
 use directory::core::config::ConfigDirectory;
+use mail_send::Credentials;
+use smtp_proto::{request::receiver::MAX_LINE_LENGTH, AUTH_LOGIN, AUTH_PLAIN};
 use store::Store;
 use utils::config::{if_block::IfBlock, Config};
 
 use crate::smtp::{
     inbound::dummy_stores,
     session::{TestSession, VerifyResponse},
-    ParseTestConfig, TestConfig,
+    ParseTestConfig, TestConfig, TestSMTP,
 };
 use smtp::{
-    config::session::Mechanism,
+    config::{session::Mechanism, CommandLeniency},
     core::{Session, State, SMTP},
+    inbound::auth::{SaslMechanism, SaslOutcome},
 };
 
 const DIRECTORY: &str = r#"
@@ -57,6 +62,13 @@ secret = "p4ssw0rd"
 email = "jane@example.org"
 email-list = ["info@example.org"]
 member-of = ["sales", "support"]
+
+[[directory."local".principals]]
+name = "admin"
+class = "admin"
+description = "Administrator"
+secret = "secret"
+email = "admin@example.org"
 "#;
 
 #[tokio::test]
@@ -134,7 +146,7 @@ async fn auth() {
         .await;
 
     // Users should be able to send emails only from their own email addresses
-    session.mail_from("bill@foobar.org", "501 5.5.4").await;
+    session.mail_from("bill@foobar.org", "553 5.7.1").await;
     session.mail_from("john@example.org", "250").await;
     session.data.mail_from.take();
 
@@ -172,3 +184,353 @@ async fn auth() {
         .cmd("AUTH PLAIN AGpvaG4Ac2VjcmV0", "503 5.5.1")
         .await;
 }
+
+#[tokio::test]
+async fn auth_sender_authorization() {
+    let mut core = SMTP::test();
+    core.shared.directories = Config::new(DIRECTORY)
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap()
+        .directories;
+    let config = &mut core.session.config.auth;
+    config.directory = IfBlock::new("local".to_string());
+    config.must_match_sender = IfBlock::new(true);
+    config.send_as =
+        r#"[{if = "authenticated_as = 'jane' && sender = 'sales@example.org'", then = true},
+    {else = false}]"#
+            .parse_if();
+    let core = Arc::new(core);
+
+    // Only addresses owned by the principal are allowed
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    session.data.authenticated_as = "john".to_string();
+    session.eval_post_auth_params().await;
+    session.mail_from("jane@example.org", "553 5.7.1").await;
+    assert!(session.data.mail_from.is_none());
+    session.mail_from("sales@example.org", "553 5.7.1").await;
+    session.mail_from("JDoe@example.org", "250").await;
+    session.data.mail_from.take();
+
+    // Send-as permissions
+    session.data.authenticated_as = "jane".to_string();
+    session.mail_from("john@example.org", "553 5.7.1").await;
+    session.mail_from("sales@example.org", "250").await;
+    session.data.mail_from.take();
+
+    // Superusers can send from any address
+    session.data.authenticated_as = "admin".to_string();
+    session.mail_from("john@example.org", "250").await;
+}
+
+#[tokio::test]
+async fn auth_send_rate() {
+    let mut core = SMTP::test();
+    let _qr = core.init_test_queue("smtp_auth_send_rate_test");
+    core.shared.directories = Config::new(DIRECTORY)
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap()
+        .directories;
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    let config = &mut core.session.config.auth;
+    config.directory = IfBlock::new("local".to_string());
+    config.send_rate = "\"[2, 1h]\"".parse_if();
+    let core = Arc::new(core);
+
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+
+    // Transactions that are not completed do not count
+    session.data.authenticated_as = "john".to_string();
+    session.eval_post_auth_params().await;
+    for _ in 0..3 {
+        session.mail_from("john@example.org", "250").await;
+        session.cmd("RSET", "250").await;
+    }
+
+    // John exceeds his sending rate once his messages are accepted
+    for _ in 0..2 {
+        session
+            .send_message(
+                "john@example.org",
+                &["bill@foobar.org"],
+                "test:no_dkim",
+                "250",
+            )
+            .await;
+    }
+    session
+        .send_message(
+            "john@example.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "452 4.4.5",
+        )
+        .await;
+
+    // Jane has her own allowance
+    session.data.authenticated_as = "jane".to_string();
+    session.eval_post_auth_params().await;
+    session
+        .send_message(
+            "jane@example.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+
+    // Superusers are not throttled
+    session.data.authenticated_as = "admin".to_string();
+    session.eval_post_auth_params().await;
+    for _ in 0..3 {
+        session
+            .send_message(
+                "admin@example.org",
+                &["bill@foobar.org"],
+                "test:no_dkim",
+                "250",
+            )
+            .await;
+    }
+}
+
+#[tokio::test]
+async fn auth_max_attempts() {
+    let mut core = SMTP::test();
+    core.shared.directories = Config::new(DIRECTORY)
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap()
+        .directories;
+
+    let config = &mut core.session.config.auth;
+    config.directory = IfBlock::new("local".to_string());
+    config.mechanisms = IfBlock::new(Mechanism::from(AUTH_PLAIN | AUTH_LOGIN));
+    config.errors_max = IfBlock::new(10);
+    config.errors_wait = "'10ms'".parse_if();
+    config.max_attempts = IfBlock::new(3);
+
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.stream.tls = true;
+    session.ehlo("mx.foobar.org").await;
+
+    // Failed attempts below the limit keep the session open
+    session.cmd("AUTH PLAIN invalid!", "500 5.5.6").await;
+    session.cmd("AUTH LOGIN", "334").await;
+    session.cmd("invalid!", "500 5.5.6").await;
+    session.cmd("AUTH PLAIN invalid!", "500 5.5.6").await;
+    assert_eq!(session.data.auth_attempts, 3);
+
+    // The next attempt closes the connection, even with valid credentials
+    session
+        .ingest(b"AUTH PLAIN AGpvaG4Ac2VjcmV0\r\n")
+        .await
+        .unwrap_err();
+    session.response().assert_code("421 4.3.0");
+    assert!(session.data.authenticated_as.is_empty());
+}
+
+#[tokio::test]
+async fn auth_after_helo() {
+    let mut core = SMTP::test();
+    core.shared.directories = Config::new(DIRECTORY)
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap()
+        .directories;
+
+    let config = &mut core.session.config;
+    config.auth.directory = IfBlock::new("local".to_string());
+    config.auth.mechanisms = IfBlock::new(Mechanism::from(AUTH_PLAIN | AUTH_LOGIN));
+    config.auth.errors_wait = "'100ms'".parse_if();
+    config.ehlo.helo_extensions = r#"[{if = "remote_ip = '10.0.0.2'", then = 'lenient'},
+    {else = 'strict'}]"#
+        .parse_if_constant::<CommandLeniency>();
+    let core = Arc::new(core);
+
+    // ESMTP extensions are rejected after HELO in strict mode
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.stream.tls = true;
+    session.cmd("HELO mx.foobar.org", "250").await;
+    session
+        .cmd("AUTH PLAIN AGpvaG4AY2hpbWljaGFuZ2Fz", "503 5.5.1")
+        .await;
+
+    // Switching to EHLO enables them
+    session.ehlo("mx.foobar.org").await;
+    session
+        .cmd("AUTH PLAIN AGpvaG4AY2hpbWljaGFuZ2Fz", "535 5.7.8")
+        .await;
+
+    // Lenient mode allows ESMTP extensions after HELO
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    session.stream.tls = true;
+    session.cmd("HELO mx.foobar.org", "250").await;
+    session
+        .cmd("AUTH PLAIN AGpvaG4AY2hpbWljaGFuZ2Fz", "535 5.7.8")
+        .await;
+}
+
+#[tokio::test]
+async fn auth_max_response_size() {
+    let mut core = SMTP::test();
+    core.shared.directories = Config::new(DIRECTORY)
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap()
+        .directories;
+
+    let config = &mut core.session.config.auth;
+    config.directory = IfBlock::new("local".to_string());
+    config.mechanisms = IfBlock::new(Mechanism::from(AUTH_PLAIN | AUTH_LOGIN));
+    config.errors_max = IfBlock::new(10);
+    config.errors_wait = "'10ms'".parse_if();
+    config.max_response_size = r#"[{if = "remote_ip = '10.0.0.2'", then = 0},
+    {else = 1024}]"#
+        .parse_if();
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.stream.tls = true;
+    session.ehlo("mx.foobar.org").await;
+
+    // Oversized initial responses are rejected
+    session
+        .cmd(&format!("AUTH PLAIN {}", "A".repeat(2000)), "500 5.5.6")
+        .await
+        .assert_contains("too long");
+
+    // Oversized continuations are rejected without being buffered
+    session.cmd("AUTH LOGIN", "334").await;
+    for _ in 0..100 {
+        session.ingest("A".repeat(1000).as_bytes()).await.unwrap();
+        match &session.state {
+            State::Sasl(receiver) => assert!(receiver.buf.len() <= 1025),
+            _ => panic!("Expected SASL state"),
+        }
+    }
+    session.ingest(b"\r\n").await.unwrap();
+    session
+        .response()
+        .assert_code("500 5.5.6")
+        .assert_contains("too long");
+
+    // Responses within the limit are accepted
+    session.cmd("AUTH LOGIN", "334").await;
+    session.cmd("amFuZQ==", "334").await;
+    session.cmd("cDRzc3cwcmQ=", "235 2.7.0").await;
+
+    // A zero limit falls back to the maximum line length
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    assert_eq!(session.params.auth_max_response_size, MAX_LINE_LENGTH);
+    session.stream.tls = true;
+    session.ehlo("mx.foobar.org").await;
+    session.cmd("AUTH LOGIN", "334").await;
+    session
+        .ingest("A".repeat(MAX_LINE_LENGTH + 1).as_bytes())
+        .await
+        .unwrap();
+    session.ingest(b"\r\n").await.unwrap();
+    session
+        .response()
+        .assert_code("500 5.5.6")
+        .assert_contains("too long");
+}
+
+struct TestMechanism;
+
+impl SaslMechanism for TestMechanism {
+    fn name(&self) -> &'static str {
+        "X-TEST"
+    }
+
+    fn step(&self, responses: &[Vec<u8>]) -> SaslOutcome {
+        match responses.first() {
+            None => SaslOutcome::Challenge(b"Credentials".to_vec()),
+            Some(response) => match std::str::from_utf8(response)
+                .ok()
+                .and_then(|response| response.split_once('\0'))
+            {
+                Some((username, secret)) => SaslOutcome::Authenticate(Credentials::Plain {
+                    username: username.to_string(),
+                    secret: secret.to_string(),
+                }),
+                None => SaslOutcome::Invalid,
+            },
+        }
+    }
+}
+
+#[tokio::test]
+async fn auth_custom_mechanism() {
+    let mut core = SMTP::test();
+    core.shared.directories = Config::new(DIRECTORY)
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap()
+        .directories;
+
+    let config = &mut core.session.config.auth;
+    config.directory = IfBlock::new("local".to_string());
+    config.mechanisms = IfBlock::new(Mechanism::from(AUTH_PLAIN));
+    config.custom_mechanisms = r#"[{if = "remote_ip = '10.0.0.1'", then = "['x-test']"},
+    {else = false}]"#
+        .parse_if();
+    config.errors_max = IfBlock::new(10);
+    config.errors_wait = "'10ms'".parse_if();
+    config.sasl.register(TestMechanism);
+    let core = Arc::new(core);
+
+    // Mechanisms registered by name are only usable once enabled
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    session
+        .ehlo("mx.foobar.org")
+        .await
+        .assert_contains("AUTH PLAIN")
+        .assert_not_contains("X-TEST");
+    session.cmd("AUTH X-TEST", "554 5.7.8").await;
+
+    // Mechanisms registered by name are advertised by name
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.stream.tls = false;
+    session
+        .ehlo("mx.foobar.org")
+        .await
+        .assert_contains("AUTH X-TEST")
+        .assert_not_contains(" PLAIN");
+
+    // Malformed responses are rejected
+    session.cmd("AUTH X-TEST", "334 Q3JlZGVudGlhbHM=").await;
+    session.cmd("amFuZQ==", "500 5.5.6").await;
+
+    // Authenticate using the custom mechanism
+    session.cmd("AUTH x-test", "334").await;
+    session.cmd("amFuZQBwNHNzdzByZA==", "235 2.7.0").await;
+}
@@ -21,11 +21,19 @@
  * for more details.
 */
 
+use std::sync::Arc;
+
+use utils::config::if_block::IfBlock;
+
 use crate::smtp::{
     session::{TestSession, VerifyResponse},
-    TestConfig,
+    ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::CommandLeniency,
+    core::{Session, SMTP},
+    inbound::{build_response, split_response},
 };
-use smtp::core::{Session, SMTP};
 
 #[tokio::test]
 async fn basic_commands() {
@@ -64,3 +72,181 @@ async fn basic_commands() {
     session.ingest(b"QUIT\r\n").await.unwrap_err();
     session.response().assert_code("221");
 }
+
+#[tokio::test]
+async fn starttls_pipelining() {
+    // Commands pipelined after STARTTLS are rejected
+    let mut session = Session::test(SMTP::test());
+    session.eval_session_params().await;
+    session.stream.tls = false;
+    session.ehlo("mx.foobar.org").await;
+    session
+        .ingest(b"STARTTLS\r\nMAIL FROM:<john@foobar.org>\r\n")
+        .await
+        .unwrap_err();
+    session
+        .response()
+        .assert_code("554 5.5.0")
+        .assert_not_contains("220 2.0.0");
+    assert!(session.data.mail_from.is_none());
+
+    // Or discarded before the handshake
+    let mut core = SMTP::test();
+    core.session.config.extensions.starttls_pipelining = IfBlock::new(CommandLeniency::Lenient);
+    let mut session = Session::test(core);
+    session.eval_session_params().await;
+    session.stream.tls = false;
+    session.ehlo("mx.foobar.org").await;
+    assert!(!session
+        .ingest(b"STARTTLS\r\nMAIL FROM:<john@foobar.org>\r\n")
+        .await
+        .unwrap());
+    session
+        .response()
+        .assert_code("220 2.0.0")
+        .assert_not_contains("250");
+    assert!(session.data.mail_from.is_none());
+
+    // The injected command is not executed after the handshake
+    session.stream.tls = true;
+    session.cmd("RCPT TO:<bill@foobar.org>", "503 5.5.1").await;
+}
+
+#[tokio::test]
+async fn command_leniency() {
+    for (leniency, expected_code) in [
+        (CommandLeniency::Lenient, "250"),
+        (CommandLeniency::Strict, "500 5.5.2"),
+    ] {
+        let mut core = SMTP::test();
+        core.session.config.command_leniency = IfBlock::new(leniency);
+        let mut session = Session::test(core);
+        session.eval_session_params().await;
+        session.ehlo("mx.foobar.org").await;
+
+        // Lowercase verbs
+        session.cmd("mail from:<a@b>", expected_code).await;
+        session.cmd("RSET", "250").await;
+
+        // Extra interior whitespace
+        session.cmd("MAIL  FROM:<a@b>", expected_code).await;
+        session.cmd("RSET", "250").await;
+        session.cmd("MAIL FROM: <a@b>", expected_code).await;
+        session.cmd("RSET", "250").await;
+
+        // Well-formed commands are always accepted
+        session.cmd("MAIL FROM:<a@b>", "250").await;
+    }
+}
+
+#[tokio::test]
+async fn help_command() {
+    let mut core = SMTP::test();
+    let config = &mut core.session.config.help;
+    config.enable = r#"[{if = "remote_ip = '10.0.0.1'", then = false},
+    {else = true}]"#
+        .parse_if();
+    config.message = r#"[{if = "remote_ip = '10.0.0.2'", then = "'Ask postmaster'"},
+    {else = "'See https://example.org/help'"}]"#
+        .parse_if();
+    let core = Arc::new(core);
+
+    // HELP can be disabled
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    session.cmd("HELP", "502 5.5.1").await;
+    session.cmd("HELP QUIT", "502 5.5.1").await;
+
+    // Custom help text
+    for (remote_ip, text) in [
+        ("10.0.0.2", "Ask postmaster"),
+        ("10.0.0.3", "See https://example.org/help"),
+    ] {
+        let mut session = Session::test(core.clone());
+        session.data.remote_ip_str = remote_ip.to_string();
+        session.eval_session_params().await;
+        session.ehlo("mx.foobar.org").await;
+        session.cmd("HELP", "250 2.0.0").await.assert_contains(text);
+    }
+}
+
+#[tokio::test]
+async fn noop_flood() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_noop_flood_test");
+    core.session.config.max_noop_commands = IfBlock::new(3);
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    let mut session = Session::test(core);
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+
+    // Non-productive commands between delivered messages are fine
+    for _ in 0..5 {
+        session.cmd("NOOP", "250").await;
+        session.cmd("RSET", "250").await;
+        session.cmd("HELP", "250").await;
+        session
+            .send_message(
+                "john@foobar.org",
+                &["bill@foobar.org"],
+                "test:no_dkim",
+                "250",
+            )
+            .await;
+        qr.expect_message().await;
+    }
+
+    // Opening and resetting transactions does not reset the count
+    session.cmd("MAIL FROM:<john@foobar.org>", "250").await;
+    session.cmd("RSET", "250").await;
+    session.cmd("MAIL FROM:<john@foobar.org>", "250").await;
+    session.ehlo("mx.foobar.org").await;
+    session.cmd("VRFY john", "252").await;
+    session.cmd("MAIL FROM:<john@foobar.org>", "250").await;
+    session.cmd("RSET", "250").await;
+    session.cmd("MAIL FROM:<john@foobar.org>", "250").await;
+    session.ingest(b"RSET\r\n").await.unwrap_err();
+    session.response().assert_code("421 4.7.0");
+}
+
+#[test]
+fn multiline_responses() {
+    assert_eq!(
+        build_response(
+            "550 5.7.1",
+            "Message rejected.\nPolicy violation.\r\nContact postmaster."
+        ),
+        concat!(
+            "550-5.7.1 Message rejected.\r\n",
+            "550-5.7.1 Policy violation.\r\n",
+            "550 5.7.1 Contact postmaster.\r\n"
+        )
+    );
+    assert_eq!(build_response("250", "OK\r\n"), "250 OK\r\n");
+    assert_eq!(build_response("220", "a\nb"), "220-a\r\n220 b\r\n");
+
+    // Replies that are already multi-line are not prefixed twice
+    let reply = "550 5.7.1 Message rejected.\r\n550-5.7.1 Policy violation.\r\n550 5.7.1 Bye.";
+    let (status, text) = split_response(reply).unwrap();
+    assert_eq!(
+        build_response(status, text),
+        concat!(
+            "550-5.7.1 Message rejected.\r\n",
+            "550-5.7.1 Policy violation.\r\n",
+            "550 5.7.1 Bye.\r\n"
+        )
+    );
+    assert_eq!(
+        build_response("451", "Try later.\n451-4.3.0 Busy.\n451 Bye."),
+        "451-Try later.\r\n451-Busy.\r\n451 Bye.\r\n"
+    );
+
+    assert_eq!(
+        split_response("554 5.7.1 Go away"),
+        Some(("554 5.7.1", "Go away"))
+    );
+    assert_eq!(split_response("421 Bye"), Some(("421", "Bye")));
+    assert_eq!(split_response("Go away"), None);
+}
@@ -21,18 +21,33 @@
  * for more details.
 */
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use directory::core::config::ConfigDirectory;
+use directory::{core::config::ConfigDirectory, QueryBy};
+use mail_parser::MessageParser;
+use mail_send::Credentials;
 use store::Store;
-use utils::config::{if_block::IfBlock, Config};
+use tokio::sync::watch;
+use utils::{
+    config::{if_block::IfBlock, Config, ServerProtocol},
+    listener::ServerInstance,
+};
 
-use crate::smtp::{
-    inbound::{dummy_stores, TestMessage},
-    session::{load_test_message, TestSession, VerifyResponse},
-    ParseTestConfig, TestConfig, TestSMTP,
+use crate::{
+    directory::smtp::spawn_mock_lmtp_server,
+    smtp::{
+        inbound::{dummy_stores, TestMessage},
+        session::{load_test_message, TestServerInstance, TestSession, VerifyResponse},
+        ParseTestConfig, TestConfig, TestSMTP,
+    },
+};
+use smtp::{
+    config::{
+        session::ConfigSession, BareLf, DuplicateAction, DuplicateScope, EightBitHeaders,
+        MissingHeaders,
+    },
+    core::{Session, SMTP},
 };
-use smtp::core::{Session, SMTP};
 
 const DIRECTORY: &str = r#"
 [storage]
@@ -148,7 +163,7 @@ async fn data() {
             "john@doe.org",
             &["bill@foobar.org"],
             "test:loop",
-            "450 4.4.6",
+            "554 5.4.6",
         )
         .await;
 
@@ -249,3 +264,760 @@ async fn data() {
         .assert_is_empty(core.shared.default_blob_store.clone())
         .await;
 }
+
+#[tokio::test]
+async fn received_header() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_received_test");
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.received_ip = r#"[{if = "remote_ip = '10.0.0.2'", then = false},
+    {else = true}]"#
+        .parse_if();
+    config.data.received_protocol = config.data.received_ip.clone();
+    config.data.received_tls = config.data.received_ip.clone();
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // All elements are included by default
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], "test:no_dkim", "250")
+        .await;
+    let lines = qr.expect_message().await.read_lines(&qr).await;
+    let received = received_header_value(&lines);
+    assert!(
+        received.starts_with("Received: from mx.doe.org (unknown [127.0.0.1])"),
+        "{received}"
+    );
+    assert!(
+        received.contains("by mx.example.org (Stalwart SMTP) with ESMTP id "),
+        "{received}"
+    );
+
+    // Remote IP and protocol are omitted for 10.0.0.2
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], "test:no_dkim", "250")
+        .await;
+    let lines = qr.expect_message().await.read_lines(&qr).await;
+    let received = received_header_value(&lines);
+    assert!(
+        received.starts_with("Received: from mx.doe.org\r\n"),
+        "{received}"
+    );
+    assert!(!received.contains("127.0.0.1"), "{received}");
+    assert!(!received.contains(" with "), "{received}");
+    assert!(
+        received.contains("by mx.example.org (Stalwart SMTP) id "),
+        "{received}"
+    );
+
+    // The date is always present as required by RFC 5321
+    assert!(received.contains(";\r\n\t"), "{received}");
+}
+
+#[tokio::test]
+async fn bare_lf() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_bare_lf_test");
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.add_received = IfBlock::new(false);
+    config.data.add_received_spf = IfBlock::new(false);
+    config.data.add_return_path = IfBlock::new(false);
+    config.data.add_auth_results = IfBlock::new(false);
+    config.data.add_message_id = IfBlock::new(false);
+    config.data.add_date = IfBlock::new(false);
+    config.data.bare_lf = r#"[{if = "remote_ip = '10.0.0.1'", then = 'reject'},
+    {if = "remote_ip = '10.0.0.2'", then = 'allow'},
+    {else = 'convert'}]"#
+        .parse_if_constant::<BareLf>();
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Messages with bare LFs are rejected
+    let message = "Subject: test\r\n\r\nfirst line\nsecond line";
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "500 5.5.2")
+        .await;
+    qr.assert_no_events();
+
+    // Messages with CRLF line endings are accepted
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "Subject: test\r\n\r\nfirst line\r\nsecond line",
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
+
+    // Bare LFs are stored as received
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "250")
+        .await;
+    let message = qr.expect_message().await.read_message(&qr).await;
+    assert!(
+        message.ends_with("\r\n\r\nfirst line\nsecond line"),
+        "{message:?}"
+    );
+
+    // Bare LFs are converted to CRLF and the lines that follow them unstuffed
+    session.data.remote_ip_str = "10.0.0.3".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "Subject: test\r\n\r\nfirst line\n..dotted line\nsecond line",
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await.read_message(&qr).await;
+    assert!(
+        message.ends_with("\r\n\r\nfirst line\r\n.dotted line\r\nsecond line"),
+        "{message:?}"
+    );
+
+    // A smuggled message is neutralized and kept as part of the first one
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            concat!(
+                "Subject: test\r\n\r\nlegitimate\n.\r\n",
+                "MAIL FROM:<admin@foobar.org>\r\n",
+                "RCPT TO:<bill@foobar.org>\r\n",
+                "DATA\r\n",
+                "Subject: smuggled"
+            ),
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await.read_message(&qr).await;
+    assert!(
+        message.ends_with(concat!(
+            "\r\n\r\nlegitimate\r\n\r\n",
+            "MAIL FROM:<admin@foobar.org>\r\n",
+            "RCPT TO:<bill@foobar.org>\r\n",
+            "DATA\r\n",
+            "Subject: smuggled"
+        )),
+        "{message:?}"
+    );
+    assert!(!message.contains("\r\n.\r\n"));
+    qr.assert_no_events();
+}
+
+#[tokio::test]
+async fn duplicate_message_id() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_duplicate_test");
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.duplicate_window = IfBlock::new(Duration::from_secs(1));
+    config.data.duplicate_scope = r#"[{if = "remote_ip = '10.0.0.3'", then = 'global'},
+    {else = 'recipient'}]"#
+        .parse_if_constant::<DuplicateScope>();
+    config.data.duplicate_action = r#"[{if = "remote_ip = '10.0.0.2'", then = 'reject'},
+    {else = 'discard'}]"#
+        .parse_if_constant::<DuplicateAction>();
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+    let message = "Message-ID: <1@doe.org>\r\nSubject: test\r\n\r\ntest";
+
+    // First delivery is queued
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "250")
+        .await;
+    qr.expect_message().await;
+
+    // Duplicates within the window are only delivered to new recipients
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org", "jane@foobar.org"],
+            message,
+            "250",
+        )
+        .await;
+    let queued = qr.expect_message().await;
+    assert_eq!(queued.recipients.len(), 1);
+    assert_eq!(queued.recipients[0].address, "jane@foobar.org");
+
+    // Duplicates for all recipients are accepted but not delivered
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "250")
+        .await;
+    qr.assert_no_events();
+
+    // Or rejected when configured
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "550 5.7.1")
+        .await;
+    qr.assert_no_events();
+
+    // Global scope suppresses the Message-ID for any recipient
+    session.data.remote_ip_str = "10.0.0.3".to_string();
+    session.eval_session_params().await;
+    let message_global = "Message-ID: <2@doe.org>\r\nSubject: test\r\n\r\ntest";
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message_global, "250")
+        .await;
+    qr.expect_message().await;
+    session
+        .send_message("john@doe.org", &["mike@test.com"], message_global, "250")
+        .await;
+    qr.assert_no_events();
+
+    // Messages without a Message-ID are never suppressed
+    for _ in 0..2 {
+        session
+            .send_message(
+                "john@doe.org",
+                &["bill@foobar.org"],
+                "Subject: test\r\n\r\ntest",
+                "250",
+            )
+            .await;
+        qr.expect_message().await;
+    }
+
+    // Duplicates outside the window are delivered again
+    tokio::time::sleep(Duration::from_millis(2100)).await;
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "250")
+        .await;
+    qr.expect_message().await;
+}
+
+fn received_header_value(lines: &[String]) -> String {
+    let mut received = String::new();
+    for line in lines {
+        if received.is_empty() {
+            if line.starts_with("Received: ") {
+                received.push_str(line);
+                received.push('\n');
+            }
+        } else if line.starts_with('\t') {
+            received.push_str(line);
+            received.push('\n');
+        } else {
+            break;
+        }
+    }
+    received
+}
+
+#[tokio::test]
+async fn max_received_headers() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_received_limit_test");
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.max_received_headers = IfBlock::new(3);
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Messages exceeding the hop count are rejected
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "test:loop",
+            "554 5.4.6",
+        )
+        .await;
+    qr.assert_no_events();
+
+    // Messages at the hop count limit are accepted
+    let message = load_test_message("loop", "messages");
+    let (_, message) = message.split_once("PDT)\nReceived:").unwrap();
+    assert_eq!(message.matches("Received:").count(), 2);
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("Received:{message}"),
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
+}
+
+#[tokio::test]
+async fn missing_headers() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_missing_headers_test");
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.add_received = IfBlock::new(false);
+    config.data.add_received_spf = IfBlock::new(false);
+    config.data.add_return_path = IfBlock::new(false);
+    config.data.add_auth_results = IfBlock::new(false);
+    config.data.add_message_id = r#"[{if = "!is_empty(authenticated_as)", then = true},
+    {else = false}]"#
+        .parse_if();
+    config.data.add_date = config.data.add_message_id.clone();
+    config.data.missing_headers = r#"[{if = "remote_ip = '10.0.0.2'", then = 'reject'},
+    {else = 'flag'}]"#
+        .parse_if_constant::<MissingHeaders>();
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Authenticated submissions get a Date and Message-ID added
+    session.data.authenticated_as = "john".to_string();
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "Subject: test\r\n\r\ntest",
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await.read_message(&qr).await;
+    assert!(message.starts_with("Date: "), "{message}");
+    assert!(message.contains("\r\nMessage-ID: <"), "{message}");
+    assert!(message.contains(">\r\nSubject: test"), "{message}");
+    assert!(!message.contains("X-Missing-Headers"), "{message}");
+
+    // Inbound messages without them are flagged
+    session.data.authenticated_as.clear();
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "Subject: test\r\n\r\ntest",
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await.read_message(&qr).await;
+    assert!(
+        message.starts_with("X-Missing-Headers: Date, Message-ID\r\nSubject: test"),
+        "{message}"
+    );
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "Date: Mon, 1 Jan 2024 00:00:00 +0000\r\nSubject: test\r\n\r\ntest",
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await.read_message(&qr).await;
+    assert!(
+        message.starts_with("X-Missing-Headers: Message-ID\r\nDate: "),
+        "{message}"
+    );
+
+    // Or rejected
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "Subject: test\r\n\r\ntest",
+            "550 5.6.0",
+        )
+        .await;
+    qr.assert_no_events();
+}
+
+#[tokio::test]
+async fn eight_bit_headers() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_8bit_headers_test");
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.add_received = IfBlock::new(false);
+    config.data.add_received_spf = IfBlock::new(false);
+    config.data.add_return_path = IfBlock::new(false);
+    config.data.add_auth_results = IfBlock::new(false);
+    config.data.add_message_id = IfBlock::new(false);
+    config.data.add_date = IfBlock::new(false);
+    config.data.eight_bit_headers = r#"[{if = "remote_ip = '10.0.0.1'", then = 'reject'},
+    {if = "remote_ip = '10.0.0.2'", then = 'encode'},
+    {else = 'flag'}]"#
+        .parse_if_constant::<EightBitHeaders>();
+    let message = "Subject: Café crème brûlée ready\r\nFrom: john@doe.org\r\n\r\ntest";
+
+    // Unencoded 8-bit headers are rejected
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "550 5.6.0")
+        .await;
+    qr.assert_no_events();
+
+    // Unless the client requested SMTPUTF8
+    session
+        .cmd("MAIL FROM:<john@doe.org> SMTPUTF8", "250")
+        .await;
+    session.rcpt_to("bill@foobar.org", "250").await;
+    session.data(message, "250").await;
+    let queued = qr.expect_message().await.read_message(&qr).await;
+    assert!(queued.starts_with("Subject: Café crème"), "{queued}");
+
+    // Or encoded
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "250")
+        .await;
+    let queued = qr.expect_message().await.read_message(&qr).await;
+    assert!(
+        queued.starts_with(concat!(
+            "Subject: =?utf-8?B?Q2Fmw6kgY3LDqG1lIGJyw7tsw6ll?= ready\r\n",
+            "From: john@doe.org\r\n\r\ntest"
+        )),
+        "{queued}"
+    );
+
+    // Long values are split into several encoded-words
+    let subject = "Ça répète: «été après été» à côté du château où l'on mangeait des crêpes";
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("Subject: {subject}\r\n\r\ntest"),
+            "250",
+        )
+        .await;
+    let queued = qr.expect_message().await.read_message(&qr).await;
+    assert!(queued.is_ascii(), "{queued}");
+    assert_eq!(
+        MessageParser::new()
+            .parse(queued.as_bytes())
+            .unwrap()
+            .subject(),
+        Some(subject)
+    );
+
+    // Display names in address headers are encoded
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            concat!(
+                "From: \"José Pérez\" <jose@doe.org>\r\n",
+                "To: Zoë <zoe@foobar.org>, bill@foobar.org\r\n",
+                "Subject: Café\r\n\r\ntest"
+            ),
+            "250",
+        )
+        .await;
+    let queued = qr.expect_message().await.read_message(&qr).await;
+    assert!(
+        queued.starts_with(concat!(
+            "From: =?utf-8?B?Sm9zw6kgUMOpcmV6?= <jose@doe.org>\r\n",
+            "To: =?utf-8?B?Wm/Dqw==?= <zoe@foobar.org>, bill@foobar.org\r\n",
+            "Subject: =?utf-8?B?Q2Fmw6k=?=\r\n"
+        )),
+        "{queued}"
+    );
+
+    // Messages with 8-bit addresses cannot be encoded and are flagged
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "To: zoë@foobar.org\r\nSubject: Café\r\n\r\ntest",
+            "250",
+        )
+        .await;
+    let queued = qr.expect_message().await.read_message(&qr).await;
+    assert!(
+        queued.starts_with(concat!(
+            "X-Unencoded-Headers: To, Subject\r\n",
+            "To: zoë@foobar.org\r\nSubject: Café\r\n"
+        )),
+        "{queued}"
+    );
+
+    // Inbound messages are flagged
+    session.data.remote_ip_str = "10.0.0.3".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "250")
+        .await;
+    let queued = qr.expect_message().await.read_message(&qr).await;
+    assert!(
+        queued.starts_with("X-Unencoded-Headers: Subject\r\nSubject: Café"),
+        "{queued}"
+    );
+}
+
+#[tokio::test]
+async fn strip_bcc() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_strip_bcc_test");
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.add_received = IfBlock::new(false);
+    config.data.add_received_spf = IfBlock::new(false);
+    config.data.add_return_path = IfBlock::new(false);
+    config.data.add_auth_results = IfBlock::new(false);
+    config.data.add_message_id = IfBlock::new(false);
+    config.data.add_date = IfBlock::new(false);
+    config.data.strip_bcc = Config::new("")
+        .unwrap()
+        .parse_session_data()
+        .unwrap()
+        .strip_bcc;
+    let message = concat!(
+        "From: john@doe.org\r\n",
+        "To: bill@foobar.org\r\n",
+        "Bcc: jane@foobar.org,\r\n",
+        " mike@foobar.org\r\n",
+        "Subject: Secret\r\n",
+        "\r\n",
+        "test"
+    );
+    let rcpts = ["bill@foobar.org", "jane@foobar.org", "mike@foobar.org"];
+
+    // Bcc headers are removed from submitted messages
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.authenticated_as = "john".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+    session
+        .send_message("john@doe.org", &rcpts, message, "250")
+        .await;
+    let queued = qr.expect_message().await;
+    assert_eq!(
+        queued
+            .recipients
+            .iter()
+            .map(|rcpt| rcpt.address.as_str())
+            .collect::<Vec<_>>(),
+        rcpts
+    );
+    let queued = queued.read_message(&qr).await;
+    assert!(
+        queued.starts_with(concat!(
+            "From: john@doe.org\r\n",
+            "To: bill@foobar.org\r\n",
+            "Subject: Secret\r\n",
+            "\r\n",
+            "test"
+        )),
+        "{queued}"
+    );
+
+    // but kept on messages received from other servers
+    session.data.authenticated_as.clear();
+    session.eval_session_params().await;
+    session
+        .send_message("john@doe.org", &rcpts, message, "250")
+        .await;
+    let queued = qr.expect_message().await.read_message(&qr).await;
+    assert!(queued.starts_with(message), "{queued}");
+}
+
+const BACKEND: &str = r#"
+[directory."backend"]
+type = "lmtp"
+host = "127.0.0.1"
+port = 9198
+
+[directory."backend".tls]
+enable = true
+allow-invalid-certs = true
+
+[directory."backend".relay.auth]
+username = "relay"
+secret = "ok"
+
+[directory."backend-anonymous"]
+type = "lmtp"
+host = "127.0.0.1"
+port = 9198
+
+[directory."backend-anonymous".tls]
+enable = true
+allow-invalid-certs = true
+"#;
+
+#[tokio::test]
+async fn proxy_submission() {
+    // Spawn mock LMTP backend
+    let shutdown = spawn_mock_lmtp_server(9198, 5);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_proxy_test");
+    core.shared.directories = Config::new(&format!("{DIRECTORY}{BACKEND}"))
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap()
+        .directories;
+    let config = &mut core.session.config;
+    config.auth.directory = IfBlock::new("local".to_string());
+    config.auth.must_match_sender = IfBlock::new(false);
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.proxy_directory =
+        r#"[{if = "remote_ip = '10.0.0.2'", then = "'backend-anonymous'"},
+    {if = "!is_empty(authenticated_as)", then = "'backend'"},
+    {else = false}]"#
+            .parse_if();
+
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.stream.tls = true;
+    session.ehlo("mx.foobar.org").await;
+    session
+        .cmd("AUTH PLAIN AGpvaG4Ac2VjcmV0", "235 2.7.0")
+        .await;
+
+    // Authenticated submissions are relayed to the backend instead of being queued
+    session
+        .send_message(
+            "ok@foobar.org",
+            &["ok@example.org"],
+            "test:no_dkim",
+            "250 2.1.5",
+        )
+        .await;
+    qr.assert_no_events();
+
+    // Backend rejections are propagated to the client
+    session
+        .send_message(
+            "ok@foobar.org",
+            &["ok@example.org"],
+            "Subject: reject-me\r\n\r\ntest",
+            "554 5.7.1",
+        )
+        .await;
+    session
+        .send_message(
+            "john@foobar.org",
+            &["ok@example.org"],
+            "test:no_dkim",
+            "552",
+        )
+        .await;
+    qr.assert_no_events();
+
+    // DSN parameters are forwarded to the backend
+    session
+        .ingest(b"MAIL FROM:<ok@foobar.org> RET=HDRS ENVID=dsn-test\r\n")
+        .await
+        .unwrap();
+    session.response().assert_code("250");
+    session
+        .ingest(b"RCPT TO:<ok-dsn@example.org> NOTIFY=SUCCESS ORCPT=rfc822;ok-dsn@example.org\r\n")
+        .await
+        .unwrap();
+    session.response().assert_code("250");
+    session.data("test:no_dkim", "250 2.1.5").await;
+
+    // SMTP clients get the first failure when only some recipients were accepted
+    session
+        .send_message(
+            "ok@foobar.org",
+            &["ok@example.org", "ok-full@example.org"],
+            "test:no_dkim",
+            "452 4.2.2",
+        )
+        .await;
+
+    // While LMTP clients get a reply for each recipient
+    let mut instance = ServerInstance::test_with_shutdown(watch::channel(true).1);
+    instance.protocol = ServerProtocol::Lmtp;
+    session.instance = Arc::new(instance);
+    session.cmd("LHLO mx.foobar.org", "250").await;
+    session.mail_from("ok@foobar.org", "250").await;
+    session.rcpt_to("ok-full@example.org", "250").await;
+    session.rcpt_to("ok@example.org", "250").await;
+    session.ingest(b"DATA\r\n").await.unwrap();
+    session.response().assert_code("354");
+    session
+        .ingest(load_test_message("no_dkim", "messages").as_bytes())
+        .await
+        .unwrap();
+    session.ingest(b"\r\n.\r\n").await.unwrap();
+    let replies = session.response();
+    assert_eq!(replies.len(), 2, "{replies:?}");
+    assert!(replies[0].starts_with("452 4.2.2"), "{replies:?}");
+    assert!(replies[1].starts_with("250 2.1.5"), "{replies:?}");
+    session.instance = Arc::new(ServerInstance::test_with_shutdown(watch::channel(true).1));
+    qr.assert_no_events();
+
+    // Messages are not relayed over connections left authenticated by lookups
+    assert!(session
+        .core
+        .get_directory("backend-anonymous")
+        .unwrap()
+        .query(
+            QueryBy::Credentials(&Credentials::Plain {
+                username: "ok".to_string(),
+                secret: "ok".to_string(),
+            }),
+            false,
+        )
+        .await
+        .unwrap()
+        .is_some());
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message(
+            "ok@foobar.org",
+            &["ok@example.org"],
+            "test:no_dkim",
+            "530 5.7.0",
+        )
+        .await;
+    qr.assert_no_events();
+
+    // Unauthenticated messages are still queued locally
+    session.data.authenticated_as.clear();
+    session
+        .send_message(
+            "ok@foobar.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "250 2.0.0",
+        )
+        .await;
+    qr.expect_message().await;
+
+    shutdown.send(false).ok();
+}
@@ -315,3 +315,59 @@ async fn dmarc() {
         .assert_contains("dmarc=pass")
         .assert_contains("Received-SPF: pass");
 }
+
+#[tokio::test]
+async fn dmarc_mail_from_normalization() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_dmarc_normalize_test");
+
+    // Add SPF and DMARC records
+    core.resolvers.dns.txt_add(
+        "example.com",
+        Spf::parse(b"v=spf1 ip4:10.0.0.1 -all").unwrap(),
+        Instant::now() + Duration::from_secs(5),
+    );
+    core.resolvers.dns.txt_add(
+        "_dmarc.example.com",
+        Dmarc::parse(b"v=DMARC1; p=reject; aspf=s;").unwrap(),
+        Instant::now() + Duration::from_secs(5),
+    );
+
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.add_auth_results = IfBlock::new(true);
+    let config = &mut core.mail_auth;
+    config.spf.verify_mail_from = IfBlock::new(VerifyStrategy::Strict);
+    config.dmarc.verify = IfBlock::new(VerifyStrategy::Strict);
+    config.dkim.verify = IfBlock::new(VerifyStrategy::Relaxed);
+
+    // The envelope domain is normalized for alignment, the address is kept as sent
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.example.com").await;
+    session.mail_from("User@EXAMPLE.com.", "250").await;
+    let mail_from = session.data.mail_from.as_ref().unwrap();
+    assert_eq!(mail_from.address, "User@EXAMPLE.com.");
+    assert_eq!(mail_from.domain, "example.com");
+
+    session.rcpt_to("jane@foobar.org", "250").await;
+    session
+        .data(
+            concat!(
+                "From: user@example.com\r\n",
+                "To: jane@foobar.org\r\n",
+                "Subject: test\r\n\r\n",
+                "test\r\n"
+            ),
+            "250",
+        )
+        .await;
+    qr.expect_message()
+        .await
+        .read_lines(&qr)
+        .await
+        .assert_contains("spf=pass")
+        .assert_contains("dmarc=pass");
+}
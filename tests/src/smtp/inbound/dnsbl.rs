@@ -0,0 +1,171 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use mail_auth::common::lru::DnsCache;
+use utils::config::Config;
+
+use crate::smtp::{
+    session::{TestSession, VerifyResponse},
+    TestConfig,
+};
+use smtp::{
+    config::{session::ConfigSession, DnsblAction, DnsblType},
+    core::{Session, SMTP},
+};
+
+const CONFIG: &str = r#"
+[session.dnsbl]
+max-score = 2
+
+[session.dnsbl.list."ip-reject"]
+zone = "bl.dnsbl.test"
+type = "ip"
+action = "reject"
+
+[session.dnsbl.list."ip-score"]
+zone = "score.dnsbl.test"
+type = "ip"
+action = "score"
+score = 1
+
+[session.dnsbl.list."ip-score2"]
+zone = "score2.dnsbl.test"
+type = "ip"
+action = "score"
+score = 1
+
+[session.dnsbl.list."domain"]
+zone = "rhsbl.dnsbl.test"
+type = "domain"
+
+[session.dnsbl.list."domain-score"]
+zone = "score.rhsbl.dnsbl.test"
+type = "domain"
+action = "score"
+score = 1
+"#;
+
+#[tokio::test]
+async fn dnsbl() {
+    let mut core = SMTP::test();
+    core.session.config.dnsbl = Config::new(CONFIG).unwrap().parse_session_dnsbl().unwrap();
+    let dnsbl = &core.session.config.dnsbl;
+    assert_eq!(dnsbl.max_score, Some(2));
+    assert_eq!(dnsbl.lists.len(), 5);
+    assert_eq!(dnsbl.negative_ttl, Duration::from_secs(300));
+    let domain_list = dnsbl.lists.iter().find(|l| l.id == "domain").unwrap();
+    assert_eq!(domain_list.typ, DnsblType::Domain);
+    assert_eq!(domain_list.action, DnsblAction::Reject);
+
+    // Add listings
+    for name in [
+        "2.0.0.10.bl.dnsbl.test",
+        "3.0.0.10.score.dnsbl.test",
+        "4.0.0.10.score.dnsbl.test",
+        "4.0.0.10.score2.dnsbl.test",
+        "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.bl.dnsbl.test",
+        "spammer.org.rhsbl.dnsbl.test",
+        "bulk.org.score.rhsbl.dnsbl.test",
+    ] {
+        core.resolvers.dns.ipv4_add(
+            name,
+            vec!["127.0.0.2".parse().unwrap()],
+            Instant::now() + Duration::from_secs(5),
+        );
+    }
+    // Addresses outside 127.0.0.0/8 are not listings
+    core.resolvers.dns.ipv4_add(
+        "5.0.0.10.bl.dnsbl.test",
+        vec!["10.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(5),
+    );
+
+    let core = Arc::new(core);
+    for (remote_ip, is_allowed) in [
+        ("10.0.0.1", true),
+        ("10.0.0.2", false),
+        ("2001:db8::1", false),
+        ("10.0.0.3", true),
+        ("10.0.0.4", false),
+        ("10.0.0.5", true),
+    ] {
+        let mut session = Session::test(core.clone());
+        session.data.remote_ip_str = remote_ip.to_string();
+        session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+        assert_eq!(session.init_conn().await, is_allowed, "{remote_ip}");
+        if !is_allowed {
+            session
+                .response()
+                .assert_code("554 5.7.1")
+                .assert_contains(remote_ip);
+        }
+    }
+
+    // Non-listed answers are cached
+    core.resolvers.dns.ipv4_add(
+        "1.0.0.10.bl.dnsbl.test",
+        vec!["127.0.0.2".parse().unwrap()],
+        Instant::now() + Duration::from_secs(5),
+    );
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    assert!(session.init_conn().await);
+    assert!(core
+        .resolvers
+        .cache
+        .dnsbl
+        .get("1.0.0.10.bl.dnsbl.test")
+        .is_some());
+
+    // Listed sender domains are rejected at MAIL FROM
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.3".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    assert!(session.init_conn().await);
+    assert_eq!(session.data.dnsbl_score, 1);
+    session.response();
+    session.ehlo("mx.foobar.org").await;
+    session.mail_from("john@spammer.org", "554 5.7.1").await;
+    assert!(session.data.mail_from.is_none());
+    session.mail_from("john@foobar.org", "250").await;
+
+    // Sender domain scores only apply to the current transaction
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    assert!(session.init_conn().await);
+    session.response();
+    session.ehlo("mx.foobar.org").await;
+    for _ in 0..3 {
+        session.mail_from("john@bulk.org", "250").await;
+        assert_eq!(session.data.dnsbl_total_score(), 1);
+        session.rset().await;
+        assert_eq!(session.data.dnsbl_total_score(), 0);
+    }
+}
@@ -21,7 +21,10 @@
  * for more details.
 */
 
-use std::time::{Duration, Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use mail_auth::{common::parse::TxtRecordParser, spf::Spf, SpfResult};
 use smtp_proto::MtPriority;
@@ -111,3 +114,97 @@ async fn ehlo() {
         .assert_not_contains("FUTURERELEASE")
         .assert_not_contains("STARTTLS");
 }
+
+#[tokio::test]
+async fn enhanced_status_codes() {
+    let mut core = SMTP::test();
+    let config = &mut core.session.config;
+    config.ehlo.require = IfBlock::new(true);
+    config.extensions.enhanced_status_codes = r#"[{if = "remote_ip = '10.0.0.2'", then = false},
+    {else = true}]"#
+        .parse_if();
+    let core = Arc::new(core);
+
+    for (remote_ip, expected_response, has_extension) in [
+        ("10.0.0.1", "503 5.5.1 Polite people say EHLO first.", true),
+        ("10.0.0.2", "503 Polite people say EHLO first.", false),
+    ] {
+        let mut session = Session::test(core.clone());
+        session.data.remote_ip_str = remote_ip.to_string();
+        session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+        session.eval_session_params().await;
+
+        // Enhanced status codes are omitted from rejections when disabled
+        session
+            .ingest(b"MAIL FROM:<bill@foobar.org>\r\n")
+            .await
+            .unwrap();
+        assert_eq!(session.response(), vec![expected_response.to_string()]);
+
+        // ENHANCEDSTATUSCODES is only advertised when enabled
+        session.ingest(b"EHLO mx1.foobar.org\r\n").await.unwrap();
+        let response = session.response().assert_code("250");
+        assert_eq!(
+            response
+                .iter()
+                .any(|line| line.contains("ENHANCEDSTATUSCODES")),
+            has_extension,
+            "{response:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn size_extension() {
+    // SIZE defaults to the maximum message size and can be omitted
+    let mut core = SMTP::test();
+    core.session.config.extensions.size = r#"[{if = "remote_ip = '10.0.0.2'", then = false},
+    {else = true}]"#
+        .parse_if();
+    let core = Arc::new(core);
+    for (remote_ip, expected_size) in [("10.0.0.1", Some("SIZE 1048576")), ("10.0.0.2", None)] {
+        let mut session = Session::test(core.clone());
+        session.data.remote_ip_str = remote_ip.to_string();
+        session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+        session.eval_session_params().await;
+        session.ingest(b"EHLO mx1.foobar.org\r\n").await.unwrap();
+        let response = session.response().assert_code("250");
+        assert_eq!(
+            response
+                .iter()
+                .find(|line| line.contains("SIZE"))
+                .map(|line| &line[4..]),
+            expected_size,
+            "{response:?}"
+        );
+    }
+
+    // The advertised SIZE value can be set per listener
+    let mut core = SMTP::test();
+    core.session.config.extensions.size_value =
+        r#"[{if = "listener = 'smtp' && remote_ip = '10.0.0.1'", then = 0},
+    {if = "listener = 'smtp'", then = 5000},
+    {else = 1024}]"#
+            .parse_if();
+    let core = Arc::new(core);
+    for (remote_ip, expected_size) in [("10.0.0.1", "SIZE"), ("10.0.0.2", "SIZE 5000")] {
+        let mut session = Session::test(core.clone());
+        session.data.remote_ip_str = remote_ip.to_string();
+        session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+        session.eval_session_params().await;
+        session.ingest(b"EHLO mx1.foobar.org\r\n").await.unwrap();
+        let response = session.response().assert_code("250");
+        assert_eq!(
+            response
+                .iter()
+                .find(|line| line.contains("SIZE"))
+                .map(|line| &line[4..]),
+            Some(expected_size),
+            "{response:?}"
+        );
+
+        // Enforcement still uses the maximum message size
+        session.eval_rcpt_params().await;
+        assert_eq!(session.params.max_message_size, 1024 * 1024);
+    }
+}
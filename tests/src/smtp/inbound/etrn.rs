@@ -0,0 +1,106 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use store::write::{now, BatchBuilder, QueueClass, ValueClass};
+use utils::config::if_block::IfBlock;
+
+use crate::smtp::{
+    session::{TestSession, VerifyResponse},
+    ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::core::{Session, SMTP};
+
+#[tokio::test]
+async fn etrn() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_etrn_test");
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.extensions.etrn = r#"[{if = "remote_ip = '10.0.0.1'", then = true},
+    {else = false}]"#
+        .parse_if();
+    config.extensions.etrn_domains = vec!["foobar.org".to_string()];
+    config.trusted_networks = r#"["192.168.0.0/16"]"#.parse_trusted_networks();
+
+    // Queue a message and postpone its delivery
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    session
+        .ehlo("mx.foobar.org")
+        .await
+        .assert_not_contains("ETRN");
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], "test:no_dkim", "250")
+        .await;
+    let mut message = qr.expect_message().await;
+    let queue_id = message.id;
+    let prev_due = qr.message_due(queue_id).await;
+    let next_due = now() + 3600;
+    message.domains[0].retry.due = next_due;
+    message
+        .save_changes(&core, prev_due.into(), next_due.into())
+        .await;
+    assert_eq!(qr.message_due(queue_id).await, next_due);
+
+    // Unauthorized clients are refused
+    session.cmd("ETRN foobar.org", "459 4.7.1").await;
+    qr.assert_no_events();
+    assert_eq!(qr.message_due(queue_id).await, next_due);
+
+    // Authorized clients release the queue
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await.assert_contains("ETRN");
+    session.cmd("ETRN #foobar", "458 4.5.0").await;
+
+    // Only configured domains can be released by untrusted clients
+    session.cmd("ETRN unknown.org", "459 4.7.1").await;
+    session.cmd("ETRN @org", "459 4.7.1").await;
+    session.cmd("ETRN sub.foobar.org", "251 2.0.0").await;
+    qr.assert_no_events();
+    assert_eq!(qr.message_due(queue_id).await, next_due);
+
+    // Unreadable queue entries are skipped
+    let mut batch = BatchBuilder::new();
+    batch.set(
+        ValueClass::Queue(QueueClass::Message(1)),
+        b"invalid".to_vec(),
+    );
+    core.shared
+        .default_data_store
+        .write(batch.build())
+        .await
+        .unwrap();
+    session.cmd("ETRN foobar.org", "250 2.0.0").await;
+    qr.read_event().await.assert_reload();
+    assert!(qr.message_due(queue_id).await <= now());
+
+    // Trusted clients can release any domain
+    session.data.remote_ip = "192.168.0.1".parse().unwrap();
+    session.cmd("ETRN @org", "250 2.0.0").await;
+    qr.read_event().await.assert_reload();
+}
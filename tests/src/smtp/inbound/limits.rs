@@ -21,15 +21,28 @@
  * for more details.
 */
 
-use std::time::{Duration, Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use tokio::sync::watch;
+use utils::{
+    config::{if_block::IfBlock, Config},
+    listener::limiter::ConcurrencyLimiter,
+};
 
 use crate::smtp::{
-    session::{TestSession, VerifyResponse},
-    ParseTestConfig, TestConfig,
+    session::{load_test_message, TestSession, VerifyResponse},
+    ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::session::ConfigSession,
+    core::{
+        backpressure::{Backpressure, BackpressureSource},
+        Session, SMTP,
+    },
 };
-use smtp::core::{Session, SMTP};
 
 #[tokio::test]
 async fn limits() {
@@ -52,7 +65,7 @@ async fn limits() {
     let mut buf = vec![b'A'; 2049];
     session.ingest(&buf).await.unwrap();
     session.ingest(b"\r\n").await.unwrap();
-    session.response().assert_code("554 5.3.4");
+    session.response().assert_code("500 5.5.2");
 
     // Invalid command
     buf.extend_from_slice(b"\r\n");
@@ -82,3 +95,391 @@ async fn limits() {
     session.handle_conn().await;
     session.response().assert_code("221 2.0.0");
 }
+
+#[tokio::test]
+async fn max_line_length() {
+    let mut core = SMTP::test();
+    let config = &mut core.session.config;
+    config.max_line_length = IfBlock::new(64);
+    config.data.max_line_length = IfBlock::new(80);
+    config.rcpt.relay = IfBlock::new(true);
+
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+
+    // Overlong command line
+    session
+        .ingest(format!("MAIL FROM:<{}@foobar.org>\r\n", "a".repeat(64)).as_bytes())
+        .await
+        .unwrap();
+    session.response().assert_code("500 5.5.2");
+
+    // Overlong command line split across reads
+    session
+        .ingest(format!("MAIL FROM:<{}", "a".repeat(64)).as_bytes())
+        .await
+        .unwrap();
+    session.ingest(b"@foobar.org>\r\n").await.unwrap();
+    session.response().assert_code("500 5.5.2");
+
+    // The session recovers after the error
+    session.mail_from("john@foobar.org", "250").await;
+    session.rcpt_to("bill@foobar.org", "250").await;
+
+    // Overlong DATA line
+    session.ingest(b"DATA\r\n").await.unwrap();
+    session.response().assert_code("354");
+    session
+        .ingest(format!("Subject: test\r\n\r\n{}\r\n.\r\n", "a".repeat(80)).as_bytes())
+        .await
+        .unwrap();
+    session.response().assert_code("500 5.5.2");
+    assert!(session.data.mail_from.is_none());
+    session.mail_from("john@foobar.org", "250").await;
+}
+
+#[tokio::test]
+async fn phase_timeouts() {
+    let mut core = SMTP::test();
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.mail.timeout = IfBlock::new(Duration::from_millis(100));
+    config.rcpt.timeout = IfBlock::new(Duration::from_millis(150));
+    config.data.timeout_init = IfBlock::new(Duration::from_millis(200));
+    config.data.timeout = IfBlock::new(Duration::from_millis(250));
+    let (_tx, rx) = watch::channel(true);
+    let core = Arc::new(core);
+
+    for phase in ["mail", "rcpt", "data-init", "data"] {
+        let mut session = Session::test_with_shutdown(core.clone(), rx.clone());
+        session.data.remote_ip_str = "10.0.0.1".to_string();
+        session.eval_session_params().await;
+        session.ehlo("mx.foobar.org").await;
+        if phase != "mail" {
+            session.mail_from("john@foobar.org", "250").await;
+            session.rcpt_to("bill@foobar.org", "250").await;
+        }
+        if phase.starts_with("data") {
+            session.cmd("DATA", "354").await;
+        }
+        if phase == "data" {
+            session.write_rx("Subject: test\r\n");
+        }
+
+        // Each phase closes the connection with 421 once its timeout expires
+        let time = Instant::now();
+        session.handle_conn().await;
+        session
+            .response()
+            .assert_code("421 4.4.2")
+            .assert_contains(&format!("waiting for {phase},"));
+        assert!(time.elapsed() < Duration::from_secs(1), "{phase}");
+    }
+
+    // The session timeout still applies when it is shorter
+    let mut core = SMTP::test();
+    core.session.config.timeout = IfBlock::new(Duration::from_millis(100));
+    let mut session = Session::test_with_shutdown(core, rx);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    session.handle_conn().await;
+    session.response().assert_code("221 2.0.0");
+}
+
+#[tokio::test]
+async fn data_concurrency() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_data_concurrency_test");
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.max_concurrent = Some(ConcurrencyLimiter::new(2));
+
+    let core = Arc::new(core);
+    let mut sessions = Vec::new();
+    for _ in 0..3 {
+        let mut session = Session::test(core.clone());
+        session.data.remote_ip_str = "10.0.0.1".to_string();
+        session.eval_session_params().await;
+        session.ehlo("mx.foobar.org").await;
+        session.mail_from("john@foobar.org", "250").await;
+        session.rcpt_to("bill@foobar.org", "250").await;
+        sessions.push(session);
+    }
+
+    // Two transfers in flight
+    for session in sessions.iter_mut().take(2) {
+        session.cmd("DATA", "354").await;
+    }
+
+    // The third transfer is deferred
+    sessions[2].cmd("DATA", "451 4.3.2").await;
+    assert_eq!(sessions[2].data.rcpt_to.len(), 1);
+
+    // BDAT chunks are discarded without being buffered
+    sessions[2]
+        .ingest(b"BDAT 21 LAST\r\nSubject: test\r\n")
+        .await
+        .unwrap();
+    assert!(sessions[2].stream.tx_buf.is_empty());
+    assert!(sessions[2].data.message.is_empty());
+    sessions[2].ingest(b"\r\ntest").await.unwrap();
+    sessions[2].response().assert_code("451 4.3.2");
+    assert_eq!(sessions[2].data.rcpt_to.len(), 1);
+
+    // Completing a transfer frees a slot
+    sessions[0]
+        .ingest(load_test_message("no_dkim", "messages").as_bytes())
+        .await
+        .unwrap();
+    sessions[0].ingest(b"\r\n.\r\n").await.unwrap();
+    sessions[0].response().assert_code("250");
+    qr.expect_message().await;
+    sessions[2].data("test:no_dkim", "250").await;
+    qr.expect_message().await;
+
+    // Disconnecting mid-transfer releases the slot as well
+    for session in sessions.iter_mut().step_by(2) {
+        session.mail_from("john@foobar.org", "250").await;
+        session.rcpt_to("bill@foobar.org", "250").await;
+    }
+    sessions[0].cmd("DATA", "354").await;
+    sessions[2].cmd("DATA", "451 4.3.2").await;
+    sessions.remove(1);
+    sessions[1].cmd("DATA", "354").await;
+}
+
+#[tokio::test]
+async fn data_backpressure() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_data_backpressure_test");
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.backpressure = Some(Backpressure::new(
+        BackpressureSource::DataStore,
+        Duration::from_millis(100),
+        Duration::from_millis(500),
+    ));
+
+    let core = Arc::new(core);
+    let backpressure = core.session.config.data.backpressure.as_ref().unwrap();
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    session.mail_from("john@foobar.org", "250").await;
+    session.rcpt_to("bill@foobar.org", "250").await;
+
+    // Slow writes to another store are ignored
+    backpressure.record(BackpressureSource::BlobStore, Duration::from_secs(2));
+    assert!(!backpressure.is_active());
+
+    // Slow data store writes defer the transfer
+    backpressure.record(BackpressureSource::DataStore, Duration::from_secs(2));
+    session.cmd("DATA", "451 4.3.2").await;
+    assert_eq!(session.data.rcpt_to.len(), 1);
+
+    // A single fast write does not end backpressure
+    backpressure.record(BackpressureSource::DataStore, Duration::from_millis(1));
+    session.cmd("DATA", "451 4.3.2").await;
+
+    // Acceptance resumes once the average latency recovers
+    for _ in 0..10 {
+        backpressure.record(BackpressureSource::DataStore, Duration::from_millis(1));
+    }
+    session.data("test:no_dkim", "250").await;
+    qr.expect_message().await;
+
+    // Stale averages expire after the window
+    session.mail_from("john@foobar.org", "250").await;
+    session.rcpt_to("bill@foobar.org", "250").await;
+    backpressure.record(BackpressureSource::DataStore, Duration::from_secs(2));
+    session.cmd("DATA", "451 4.3.2").await;
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    session.cmd("DATA", "354").await;
+}
+
+#[tokio::test]
+async fn size_per_domain() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_size_domain_test");
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.max_message_size = IfBlock::new(1000);
+    config.data.max_message_size_domain = Config::new(
+        "[session.data.limits]\nsize-domain = [\"large.org 2000\", \"Small.org 100\"]\n",
+    )
+    .unwrap()
+    .parse_session_data()
+    .unwrap()
+    .max_message_size_domain;
+
+    // Invalid sizes are rejected
+    assert!(
+        Config::new("[session.data.limits]\nsize-domain = [\"large.org big\"]\n")
+            .unwrap()
+            .parse_session_data()
+            .is_err()
+    );
+
+    let message = format!("Subject: test\r\n\r\n{}", "A".repeat(500));
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+
+    // Messages within the domain limit are accepted
+    session
+        .send_message("john@foobar.org", &["bill@large.org"], &message, "250")
+        .await;
+    qr.expect_message().await;
+
+    // The stricter limit applies when both domains are recipients
+    session
+        .send_message(
+            "john@foobar.org",
+            &["bill@large.org", "jane@small.org"],
+            &message,
+            "552 5.3.4",
+        )
+        .await;
+    qr.assert_no_events();
+    session.cmd("RSET", "250").await;
+
+    // Declared sizes above a domain limit are rejected at RCPT
+    session
+        .cmd("MAIL FROM:<john@foobar.org> SIZE=500", "250")
+        .await;
+    session.rcpt_to("bill@large.org", "250").await;
+    session.rcpt_to("jane@small.org", "552 5.3.4").await;
+    assert_eq!(session.data.rcpt_to.len(), 1);
+    session.cmd("RSET", "250").await;
+
+    // Domain limits can exceed the global limit
+    let large_message = format!("Subject: test\r\n\r\n{}", "A".repeat(1500));
+    session
+        .send_message(
+            "john@foobar.org",
+            &["bill@large.org"],
+            &large_message,
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
+
+    // Domains without a limit of their own use the global limit
+    session
+        .send_message(
+            "john@foobar.org",
+            &["bill@large.org", "jane@foobar.org"],
+            &large_message,
+            "552 5.3.4",
+        )
+        .await;
+    qr.assert_no_events();
+    session.cmd("RSET", "250").await;
+    session
+        .cmd("MAIL FROM:<john@foobar.org> SIZE=1500", "250")
+        .await;
+    session.rcpt_to("jane@foobar.org", "552 5.3.4").await;
+    session.rcpt_to("bill@large.org", "250").await;
+    session.cmd("RSET", "250").await;
+    session
+        .cmd("MAIL FROM:<john@foobar.org> SIZE=2500", "552 5.3.4")
+        .await;
+
+    // Each transaction starts from the limits of its own recipients
+    session
+        .send_message("john@foobar.org", &["bill@foobar.org"], &message, "250")
+        .await;
+    qr.expect_message().await;
+}
+
+#[tokio::test]
+async fn pipelining_limits() {
+    let mut core = SMTP::test();
+    let config = &mut core.session.config;
+    config.pipelining.max_commands = IfBlock::new(5);
+    config.pipelining.max_size = r#"[{if = "remote_ip = '10.0.0.2'", then = 64},
+    {else = false}]"#
+        .parse_if();
+    config.rcpt.relay = IfBlock::new(true);
+
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+
+    // A batch within the limits is accepted
+    session
+        .ingest(
+            concat!(
+                "MAIL FROM:<john@foobar.org>\r\n",
+                "RCPT TO:<bill@foobar.org>\r\n",
+                "RCPT TO:<jane@foobar.org>\r\n",
+                "NOOP\r\n",
+                "RSET\r\n"
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    session
+        .response()
+        .assert_count("250", 5)
+        .assert_not_contains("421");
+
+    // Limits apply per batch, so the session can keep issuing commands
+    session.mail_from("john@foobar.org", "250").await;
+    session.rcpt_to("bill@foobar.org", "250").await;
+    session.rset().await;
+
+    // Every command above the limit is rejected
+    session
+        .ingest("NOOP\r\n".repeat(8).as_bytes())
+        .await
+        .unwrap();
+    session
+        .response()
+        .assert_count("250", 5)
+        .assert_count("503 5.5.0", 3)
+        .assert_not_contains("421");
+
+    // Commands split across reads belong to the same batch
+    session
+        .ingest(concat!("NOOP\r\n", "NOOP\r\n", "NOOP\r\n", "NO").as_bytes())
+        .await
+        .unwrap();
+    session
+        .ingest(concat!("OP\r\n", "NOOP\r\n", "NOOP\r\n", "NOOP\r\n").as_bytes())
+        .await
+        .unwrap();
+    session
+        .response()
+        .assert_count("250", 5)
+        .assert_count("503 5.5.0", 2);
+
+    // Batches exceeding the maximum size are rejected
+    let mut session = Session::test(session.core);
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    session
+        .ingest(
+            concat!(
+                "MAIL FROM:<john@foobar.org>\r\n",
+                "RCPT TO:<bill@foobar.org>\r\n",
+                "RCPT TO:<jane@foobar.org>\r\n"
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    session
+        .response()
+        .assert_count("250", 2)
+        .assert_code("503 5.5.0");
+    assert_eq!(session.data.rcpt_to.len(), 1);
+}
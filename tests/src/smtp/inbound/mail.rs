@@ -26,17 +26,21 @@ use std::{
     time::{Duration, Instant, SystemTime},
 };
 
-use mail_auth::{common::parse::TxtRecordParser, spf::Spf, IprevResult, SpfResult};
-use smtp_proto::{MtPriority, MAIL_BY_NOTIFY, MAIL_BY_RETURN, MAIL_REQUIRETLS};
+use mail_auth::{common::parse::TxtRecordParser, spf::Spf, IprevResult, SpfResult, MX};
+use smtp_proto::{
+    MtPriority, MAIL_BY_NOTIFY, MAIL_BY_RETURN, MAIL_REQUIRETLS, MAIL_RET_HDRS, RCPT_NOTIFY_DELAY,
+    RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER,
+};
 use utils::config::if_block::IfBlock;
 
 use crate::smtp::{
     session::{TestSession, VerifyResponse},
-    ParseTestConfig, TestConfig,
+    ParseTestConfig, TestConfig, TestSMTP,
 };
 use smtp::{
-    config::VerifyStrategy,
+    config::{AddressLiteral, CommandLeniency, SpfCheck, VerifyStrategy},
     core::{Session, SMTP},
+    inbound::spf::SPF_TEST_RECORDS,
 };
 
 #[tokio::test]
@@ -231,12 +235,19 @@ async fn mail() {
     session.rset().await;
 
     // Test REQUIRETLS extension
+    session
+        .ingest(b"MAIL FROM:<jane@foobar.org> REQUIRETLS\r\n")
+        .await
+        .unwrap();
+    session.response().assert_code("530 5.7.10");
+    session.stream.tls = true;
     session
         .ingest(b"MAIL FROM:<jane@foobar.org> REQUIRETLS\r\n")
         .await
         .unwrap();
     session.response().assert_code("250");
     assert!((session.data.mail_from.as_ref().unwrap().flags & MAIL_REQUIRETLS) != 0);
+    session.stream.tls = false;
     session.rset().await;
 
     // Test DELIVERBY extension with by-mode=R
@@ -311,3 +322,533 @@ async fn mail() {
     session.response().assert_code("501 5.5.4");
     session.rset().await;
 }
+
+#[tokio::test]
+async fn mail_spf_check() {
+    // The sender passes SPF for MAIL FROM but fails it for HELO
+    for (check, expected_code, has_ehlo, has_mail_from) in [
+        (Some(SpfCheck::MailFrom), "250", false, true),
+        (Some(SpfCheck::Helo), "250", true, false),
+        (Some(SpfCheck::Both), "550 5.7.23", true, false),
+        // Without a scope, each identity follows its verify strategy
+        (None, "250", true, true),
+    ] {
+        let mut core = SMTP::test();
+        core.resolvers.dns.txt_add(
+            "foobar.org",
+            Spf::parse(b"v=spf1 ip4:10.0.0.1 -all").unwrap(),
+            Instant::now() + Duration::from_secs(5),
+        );
+        core.resolvers.dns.txt_add(
+            "mx2.foobar.org",
+            Spf::parse(b"v=spf1 ip4:10.0.0.2 -all").unwrap(),
+            Instant::now() + Duration::from_secs(5),
+        );
+        core.mail_auth.spf.verify_ehlo = IfBlock::new(VerifyStrategy::Relaxed);
+        core.mail_auth.spf.verify_mail_from = IfBlock::new(VerifyStrategy::Strict);
+        if let Some(check) = check {
+            core.mail_auth.spf.check = IfBlock::new(check);
+        }
+
+        let mut session = Session::test(Arc::new(core));
+        session.data.remote_ip_str = "10.0.0.1".to_string();
+        session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+        session.eval_session_params().await;
+        session.ingest(b"EHLO mx2.foobar.org\r\n").await.unwrap();
+        session.response().assert_code("250");
+        session
+            .ingest(b"MAIL FROM:<bill@foobar.org>\r\n")
+            .await
+            .unwrap();
+        session.response().assert_code(expected_code);
+
+        assert_eq!(
+            session.data.spf_ehlo.as_ref().map(|spf| spf.result()),
+            has_ehlo.then_some(SpfResult::Fail),
+            "{check:?}"
+        );
+        assert_eq!(
+            session.data.spf_mail_from.as_ref().map(|spf| spf.result()),
+            has_mail_from.then_some(SpfResult::Pass),
+            "{check:?}"
+        );
+    }
+
+    // The sender passes SPF for HELO but fails it for MAIL FROM, under 'both'
+    // the combined result fails even when MAIL FROM is verified relaxed
+    for (check, expected_code) in [(Some(SpfCheck::Both), "550 5.7.23"), (None, "250")] {
+        let mut core = SMTP::test();
+        core.resolvers.dns.txt_add(
+            "foobar.org",
+            Spf::parse(b"v=spf1 ip4:10.0.0.2 -all").unwrap(),
+            Instant::now() + Duration::from_secs(5),
+        );
+        core.resolvers.dns.txt_add(
+            "mx1.foobar.org",
+            Spf::parse(b"v=spf1 ip4:10.0.0.1 -all").unwrap(),
+            Instant::now() + Duration::from_secs(5),
+        );
+        core.mail_auth.spf.verify_ehlo = IfBlock::new(VerifyStrategy::Strict);
+        core.mail_auth.spf.verify_mail_from = IfBlock::new(VerifyStrategy::Relaxed);
+        if let Some(check) = check {
+            core.mail_auth.spf.check = IfBlock::new(check);
+        }
+
+        let mut session = Session::test(Arc::new(core));
+        session.data.remote_ip_str = "10.0.0.1".to_string();
+        session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+        session.eval_session_params().await;
+        session.ingest(b"EHLO mx1.foobar.org\r\n").await.unwrap();
+        session.response().assert_code("250");
+        session
+            .ingest(b"MAIL FROM:<bill@foobar.org>\r\n")
+            .await
+            .unwrap();
+        session.response().assert_code(expected_code);
+        assert_eq!(
+            session.data.spf_ehlo.as_ref().map(|spf| spf.result()),
+            Some(SpfResult::Pass),
+            "{check:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn mail_spf_lookup_limit() {
+    let mut core = SMTP::test();
+    let expires = Instant::now() + Duration::from_secs(5);
+    let add_record = |domain: String, record: String| {
+        core.resolvers.dns.txt_add(
+            domain.clone(),
+            Spf::parse(record.as_bytes()).unwrap(),
+            expires,
+        );
+        SPF_TEST_RECORDS.lock().push((domain, record));
+    };
+
+    // Include chains requiring 5 and 11 DNS lookups
+    for (domain, depth) in [("spf-chain.org", 5), ("spf-deep.org", 11)] {
+        add_record(
+            domain.to_string(),
+            format!("v=spf1 include:s1.{domain} -all"),
+        );
+        for i in 1..depth {
+            add_record(
+                format!("s{i}.{domain}"),
+                format!("v=spf1 include:s{}.{domain} -all", i + 1),
+            );
+        }
+        add_record(
+            format!("s{depth}.{domain}"),
+            "v=spf1 ip4:10.0.0.1 -all".to_string(),
+        );
+    }
+
+    // Record with two macro-expanded lookups
+    add_record(
+        "spf-macro.org".to_string(),
+        "v=spf1 exists:%{i}.a.spf-macro.org exists:%{i}.b.spf-macro.org ip4:10.0.0.1 -all"
+            .to_string(),
+    );
+    core.mail_auth.spf.verify_mail_from = IfBlock::new(VerifyStrategy::Strict);
+
+    let mut session = Session::test(Arc::new(core));
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    assert_eq!(session.params.spf_max_lookups, 10);
+    assert_eq!(session.params.spf_max_macro_lookups, 5);
+
+    // Records within the default limit of 10 DNS lookups are evaluated
+    session.mail_from("bill@spf-chain.org", "250").await;
+    assert_eq!(
+        session.data.spf_mail_from.as_ref().unwrap().result(),
+        SpfResult::Pass
+    );
+    session.rset().await;
+    session.mail_from("bill@spf-macro.org", "250").await;
+    session.rset().await;
+
+    // Exceeding the limit results in a permerror
+    session
+        .ingest(b"MAIL FROM:<bill@spf-deep.org>\r\n")
+        .await
+        .unwrap();
+    session
+        .response()
+        .assert_code("550 5.7.23")
+        .assert_contains("permerror");
+
+    // Lower lookup limit
+    session.params.spf_max_lookups = 3;
+    session
+        .ingest(b"MAIL FROM:<bill@spf-chain.org>\r\n")
+        .await
+        .unwrap();
+    session
+        .response()
+        .assert_code("550 5.7.23")
+        .assert_contains("permerror");
+    assert!(session.data.mail_from.is_none());
+
+    // Macro-expanded lookups are capped separately
+    session.params.spf_max_macro_lookups = 1;
+    session
+        .ingest(b"MAIL FROM:<bill@spf-macro.org>\r\n")
+        .await
+        .unwrap();
+    session
+        .response()
+        .assert_code("550 5.7.23")
+        .assert_contains("permerror");
+}
+
+#[tokio::test]
+async fn mail_auth_parameter() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_mail_auth_test");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // The AUTH identity of an untrusted peer is replaced by <>
+    session
+        .send_message(
+            "<john@doe.org> AUTH=john@doe.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    assert_eq!(qr.expect_message().await.auth.as_deref(), Some("<>"));
+
+    // Authenticated peers are trusted and their AUTH identity is preserved
+    session.data.authenticated_as = "john".to_string();
+    session
+        .send_message(
+            "<john@doe.org> AUTH=john@doe.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    assert_eq!(
+        qr.expect_message().await.auth.as_deref(),
+        Some("john@doe.org")
+    );
+
+    // Nothing is added when the AUTH parameter is missing
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], "test:no_dkim", "250")
+        .await;
+    assert_eq!(qr.expect_message().await.auth, None);
+}
+
+#[tokio::test]
+async fn mail_dsn_parameters() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_mail_dsn_test");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.extensions.dsn = r#"[{if = "remote_ip = '10.0.0.1'", then = true},
+    {else = false}]"#
+        .parse_if();
+
+    // DSN parameters are rejected when the extension is disabled
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await.assert_not_contains("DSN");
+    for param in ["RET=HDRS", "ENVID=abc123"] {
+        session
+            .mail_from(&format!("<john@doe.org> {param}"), "501 5.5.4")
+            .await;
+    }
+    session.mail_from("john@doe.org", "250").await;
+    for param in ["NOTIFY=SUCCESS", "ORCPT=rfc822;bill@foobar.org"] {
+        session
+            .rcpt_to(&format!("<bill@foobar.org> {param}"), "501 5.5.4")
+            .await;
+    }
+
+    // Valid DSN parameters are stored and carried into the queue
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await.assert_contains("DSN");
+    session
+        .mail_from("<john@doe.org> RET=HDRS ENVID=QQ314159", "250")
+        .await;
+    let mail_from = session.data.mail_from.as_ref().unwrap();
+    assert_ne!(mail_from.flags & MAIL_RET_HDRS, 0);
+    assert_eq!(mail_from.dsn_info.as_deref(), Some("QQ314159"));
+    session
+        .rcpt_to(
+            "<bill@foobar.org> NOTIFY=FAILURE,DELAY ORCPT=rfc822;Bill+40FooBar.org",
+            "250",
+        )
+        .await;
+    session
+        .rcpt_to("<jane@foobar.org> NOTIFY=NEVER", "250")
+        .await;
+    session.data("test:no_dkim", "250").await;
+    let message = qr.expect_message().await;
+    assert_ne!(message.flags & MAIL_RET_HDRS, 0);
+    assert_eq!(message.env_id.as_deref(), Some("QQ314159"));
+    assert_eq!(
+        message.recipients[0].flags & (RCPT_NOTIFY_FAILURE | RCPT_NOTIFY_DELAY),
+        RCPT_NOTIFY_FAILURE | RCPT_NOTIFY_DELAY
+    );
+    assert_eq!(
+        message.recipients[0].orcpt.as_deref(),
+        Some("Bill@FooBar.org")
+    );
+    assert_ne!(message.recipients[1].flags & RCPT_NOTIFY_NEVER, 0);
+    assert_eq!(message.recipients[1].orcpt, None);
+
+    // Malformed DSN parameters are rejected
+    for param in ["RET=NONE", "RET=", "ENVID="] {
+        session
+            .mail_from(&format!("<john@doe.org> {param}"), "501 5.5.4")
+            .await;
+    }
+    session.mail_from("john@doe.org", "250").await;
+    for param in [
+        "NOTIFY=",
+        "NOTIFY=NEVER,FAILURE",
+        "NOTIFY=SOMETIMES",
+        "ORCPT=",
+        "ORCPT=rfc822;",
+        "ORCPT=;bill@foobar.org",
+    ] {
+        session
+            .rcpt_to(&format!("<bill@foobar.org> {param}"), "501 5.5.4")
+            .await;
+    }
+}
+
+#[tokio::test]
+async fn mail_verify_sender_domain() {
+    let mut core = SMTP::test();
+    core.resolvers.dns.mx_add(
+        "mx-domain.org",
+        vec![MX {
+            exchanges: vec!["mx.mx-domain.org".to_string()],
+            preference: 10,
+        }],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.resolvers.dns.ipv4_add(
+        "a-domain.org",
+        vec!["10.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.session.config.mail.verify_sender_domain =
+        r#"[{if = "sender_domain = 'unchecked.org'", then = false},
+    {else = true}]"#
+            .parse_if();
+
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx1.foobar.org").await;
+
+    // Domains with either MX or A records are accepted
+    session.mail_from("john@mx-domain.org", "250").await;
+    session.rset().await;
+    session.mail_from("john@a-domain.org", "250").await;
+    session.rset().await;
+
+    // Nonexistent domains are rejected
+    session.mail_from("john@nonexistent.org", "550 5.1.8").await;
+
+    // DNS errors result in a temporary failure
+    session.mail_from("john@_dns_error.org", "451 4.1.8").await;
+
+    // The null sender and disabled domains are not verified
+    session.mail_from("<>", "250").await;
+    session.rset().await;
+    session.mail_from("john@unchecked.org", "250").await;
+}
+
+#[tokio::test]
+async fn mail_trusted_networks() {
+    let mut core = SMTP::test();
+    let config = &mut core.session.config;
+    config.auth.require = IfBlock::new(true);
+    config.rcpt.relay = IfBlock::new(false);
+    config.trusted_networks = r#"["10.0.0.0/8", "::1"]"#.parse_trusted_networks();
+    config.extensions.chunking = r#"[{if = "is_trusted", then = false},
+    {else = true}]"#
+        .parse_if();
+
+    // Trusted networks may send and relay without authenticating
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    assert!(session.is_trusted());
+    session
+        .ehlo("mx.foobar.org")
+        .await
+        .assert_not_contains("CHUNKING");
+    session
+        .mail_from("<bill@foobar.org> AUTH=<bill@foobar.org>", "250")
+        .await;
+    assert_eq!(
+        session.data.mail_from_auth.as_deref(),
+        Some("<bill@foobar.org>")
+    );
+    session.rcpt_to("jane@remote.org", "250").await;
+
+    // Untrusted networks must authenticate first
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "192.168.1.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    assert!(!session.is_trusted());
+    session
+        .ehlo("mx.foobar.org")
+        .await
+        .assert_contains("CHUNKING");
+    session.mail_from("bill@foobar.org", "503 5.5.1").await;
+}
+
+#[tokio::test]
+async fn mail_address_literals() {
+    for policy in [AddressLiteral::Reject, AddressLiteral::Allow] {
+        let mut core = SMTP::test();
+        let config = &mut core.session.config;
+        config.mail.address_literals = IfBlock::new(policy);
+        config.rcpt.address_literals = IfBlock::new(policy);
+        config.rcpt.relay = IfBlock::new(true);
+
+        let mut session = Session::test(core);
+        session.data.remote_ip_str = "10.0.0.1".to_string();
+        session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+        session.eval_session_params().await;
+        session.ehlo("mx1.foobar.org").await;
+
+        // Malformed literals are rejected regardless of the policy
+        session.mail_from("john@[192.0.2.256]", "501 5.1.8").await;
+        session
+            .mail_from("john@[IPv6:2001:db8::zz]", "501 5.1.8")
+            .await;
+
+        match policy {
+            AddressLiteral::Reject => {
+                session.mail_from("john@[192.0.2.1]", "501 5.1.7").await;
+                session
+                    .mail_from("john@[IPv6:2001:db8::1]", "501 5.1.7")
+                    .await;
+                assert!(session.data.mail_from.is_none());
+                session.mail_from("john@foobar.org", "250").await;
+                session.rcpt_to("jane@[192.0.2.2]", "501 5.1.3").await;
+                session
+                    .rcpt_to("jane@[IPv6:2001:db8::2]", "501 5.1.3")
+                    .await;
+                assert!(session.data.rcpt_to.is_empty());
+            }
+            AddressLiteral::Allow => {
+                session.mail_from("john@[192.0.2.1]", "250").await;
+                assert_eq!(
+                    session.data.mail_from.as_ref().unwrap().address,
+                    "john@[192.0.2.1]"
+                );
+                session.rset().await;
+                session.mail_from("john@[IPv6:2001:db8::1]", "250").await;
+                assert_eq!(
+                    session.data.mail_from.as_ref().unwrap().address,
+                    "john@[IPv6:2001:db8::1]"
+                );
+
+                // Command parameters are kept
+                session.rset().await;
+                session
+                    .mail_from("<john@[192.0.2.1]> SIZE=2097152", "552 5.3.4")
+                    .await;
+                session
+                    .mail_from("<john@[192.0.2.1]> SIZE=1024 RET=HDRS", "250")
+                    .await;
+                assert_eq!(session.data.mail_from_size, 1024);
+                assert_eq!(
+                    session.data.mail_from.as_ref().unwrap().flags & MAIL_RET_HDRS,
+                    MAIL_RET_HDRS
+                );
+                session
+                    .rcpt_to(
+                        "<jane@[192.0.2.3]> NOTIFY=FAILURE ORCPT=rfc822;jane@foobar.org",
+                        "250",
+                    )
+                    .await;
+                let rcpt = session.data.rcpt_to.pop().unwrap();
+                assert_eq!(rcpt.address, "jane@[192.0.2.3]");
+                assert_eq!(rcpt.flags & RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_FAILURE);
+                assert_eq!(rcpt.dsn_info.as_deref(), Some("jane@foobar.org"));
+
+                session.rcpt_to("jane@[192.0.2.256]", "501 5.1.3").await;
+                session.rcpt_to("jane@[192.0.2.2]", "250").await;
+                session.rcpt_to("jane@[IPv6:2001:db8::2]", "250").await;
+                assert_eq!(
+                    session
+                        .data
+                        .rcpt_to
+                        .iter()
+                        .map(|rcpt| rcpt.address.as_str())
+                        .collect::<Vec<_>>(),
+                    vec!["jane@[192.0.2.2]", "jane@[IPv6:2001:db8::2]"]
+                );
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn mail_multiple_commands() {
+    for policy in [CommandLeniency::Strict, CommandLeniency::Lenient] {
+        let mut core = SMTP::test();
+        let config = &mut core.session.config;
+        config.mail.multiple_commands = IfBlock::new(policy);
+        config.rcpt.relay = IfBlock::new(true);
+
+        let mut session = Session::test(core);
+        session.data.remote_ip_str = "10.0.0.1".to_string();
+        session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+        session.eval_session_params().await;
+        session.ehlo("mx1.foobar.org").await;
+        session.mail_from("john@foobar.org", "250").await;
+        session.rcpt_to("jane@foobar.org", "250").await;
+
+        match policy {
+            CommandLeniency::Strict => {
+                // The transaction is left untouched
+                session.mail_from("bill@foobar.org", "503 5.5.1").await;
+                assert_eq!(
+                    session.data.mail_from.as_ref().unwrap().address,
+                    "john@foobar.org"
+                );
+                assert_eq!(session.data.rcpt_to.len(), 1);
+            }
+            CommandLeniency::Lenient => {
+                // A new transaction is started with the new sender
+                session.mail_from("bill@foobar.org", "250").await;
+                assert_eq!(
+                    session.data.mail_from.as_ref().unwrap().address,
+                    "bill@foobar.org"
+                );
+                assert!(session.data.rcpt_to.is_empty());
+                session.cmd("DATA", "503 5.5.1").await;
+                session.rcpt_to("jane@foobar.org", "250").await;
+            }
+        }
+    }
+}
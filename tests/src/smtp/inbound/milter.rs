@@ -143,6 +143,26 @@ async fn milter_session() {
         .await;
     qr.assert_no_events();
 
+    // Multi-line replies are relayed without repeating their codes
+    session
+        .mail_from("reply_code_multiline@doe.org", "250")
+        .await;
+    session.rcpt_to("bill@foobar.org", "250").await;
+    session.cmd("DATA", "354").await;
+    session
+        .ingest(b"Subject: test\r\n\r\ntest\r\n.\r\n")
+        .await
+        .unwrap();
+    assert_eq!(
+        session.response(),
+        vec![
+            "550-5.7.1 Message rejected.",
+            "550-5.7.1 Policy violation.",
+            "550 5.7.1 Contact postmaster."
+        ]
+    );
+    qr.assert_no_events();
+
     // Test accept with header addition
     session
         .send_message(
@@ -556,6 +576,15 @@ async fn accept_milter(
                                     code: [b'3', b'2', b'1'],
                                     text: "test".to_string(),
                                 },
+                                "reply_code_multiline" => Action::ReplyCode {
+                                    code: [b'5', b'5', b'0'],
+                                    text: concat!(
+                                        "5.7.1 Message rejected.\r\n",
+                                        "550-5.7.1 Policy violation.\r\n",
+                                        "550 5.7.1 Contact postmaster."
+                                    )
+                                    .to_string(),
+                                },
                                 test_num => {
                                     modidications = tests[test_num.parse::<usize>().unwrap()]
                                         .modifications
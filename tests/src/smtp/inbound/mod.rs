@@ -24,7 +24,7 @@
 use std::time::Duration;
 
 use store::{
-    write::{key::DeserializeBigEndian, Bincode, QueueClass, QueueEvent, ReportEvent, ValueClass},
+    write::{key::DeserializeBigEndian, QueueClass, QueueEvent, ReportEvent, ValueClass},
     Deserialize, IterateParams, Store, Stores, ValueKey, U64_LEN,
 };
 use tokio::sync::mpsc::error::TryRecvError;
@@ -42,12 +42,16 @@ pub mod auth;
 pub mod basic;
 pub mod data;
 pub mod dmarc;
+pub mod dnsbl;
 pub mod ehlo;
+pub mod etrn;
 pub mod limits;
 pub mod mail;
 pub mod milter;
+pub mod proxy;
 pub mod rcpt;
 pub mod rewrite;
+pub mod score;
 pub mod scripts;
 pub mod sign;
 pub mod throttle;
@@ -199,9 +203,9 @@ impl QueueReceiver {
             .iterate(
                 IterateParams::new(from_key, to_key).descending(),
                 |key, value| {
-                    let value = Bincode::<Message>::deserialize(value)?;
-                    assert_eq!(key.deserialize_be_u64(1)?, value.inner.id);
-                    messages.push(value.inner);
+                    let value = Message::deserialize(value)?;
+                    assert_eq!(key.deserialize_be_u64(1)?, value.id);
+                    messages.push(value);
                     Ok(true)
                 },
             )
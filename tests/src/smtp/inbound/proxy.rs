@@ -0,0 +1,73 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::net::SocketAddr;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use utils::listener::listen::accept_proxied;
+
+use crate::smtp::{session::TestSession, ParseTestConfig, TestConfig};
+use smtp::core::{Session, SMTP};
+
+#[tokio::test]
+async fn proxy_protocol() {
+    // Accept a connection that starts with a PROXY v1 header
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let local_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut client = TcpStream::connect(local_addr).await.unwrap();
+        client
+            .write_all(b"PROXY TCP4 192.0.2.10 198.51.100.1 56324 25\r\nEHLO mx.foobar.org\r\n")
+            .await
+            .unwrap();
+    });
+    let (stream, lb_addr) = listener.accept().await.unwrap();
+    let (mut stream, client_addr) = accept_proxied(stream, lb_addr).await.unwrap();
+    assert_eq!(
+        client_addr,
+        "192.0.2.10:56324".parse::<SocketAddr>().unwrap()
+    );
+
+    // The header is consumed and the SMTP conversation follows
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, b"EHLO mx.foobar.org\r\n");
+
+    // Policies are evaluated against the original client address
+    let mut core = SMTP::test();
+    core.session.config.rcpt.relay = r#"[{if = "remote_ip = '192.0.2.10'", then = true},
+    {else = false}]"#
+        .parse_if();
+    let mut session = Session::test(core);
+    for (remote_ip, expected_code) in [(client_addr.ip(), "250"), (lb_addr.ip(), "550 5.1.2")] {
+        session.data.remote_ip = remote_ip;
+        session.data.remote_ip_str = remote_ip.to_string();
+        session.eval_session_params().await;
+        session.ehlo("mx.foobar.org").await;
+        session.mail_from("john@example.net", "250").await;
+        session.rcpt_to("jane@example.org", expected_code).await;
+    }
+}
@@ -21,11 +21,17 @@
  * for more details.
 */
 
-use std::time::Duration;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use directory::core::config::ConfigDirectory;
+use directory::{core::config::ConfigDirectory, QueryBy, FEATURES_ALL, FEATURE_SEND_EXTERNAL};
 use smtp_proto::{RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_SUCCESS};
-use store::Store;
+use store::{
+    write::{now, BatchBuilder, DirectoryClass},
+    Store,
+};
 use utils::config::{if_block::IfBlock, Config};
 
 use crate::smtp::{
@@ -33,7 +39,10 @@ use crate::smtp::{
     session::{TestSession, VerifyResponse},
     ParseTestConfig, TestConfig,
 };
-use smtp::core::{Session, State, SMTP};
+use smtp::{
+    config::{session::ConfigSession, BareLocalPart, OverQuota, Srs},
+    core::{srs::SrsError, Session, State, SMTP},
+};
 
 const DIRECTORY: &str = r#"
 [storage]
@@ -102,6 +111,12 @@ async fn rcpt() {
     rate = '2/1s'
     "#
     .parse_throttle();
+    core.session.config.reject = Config::new(
+        "[session.reject]\nrelay-denied = '{reason} {recipient} is not local, see https://foobar.org/help'\n",
+    )
+    .unwrap()
+    .parse_session_reject()
+    .unwrap();
 
     // RCPT without MAIL FROM
     let mut session = Session::test(core);
@@ -112,7 +127,13 @@ async fn rcpt() {
 
     // Relaying is disabled for 10.0.0.1
     session.mail_from("john@example.net", "250").await;
-    session.rcpt_to("external@domain.com", "550 5.1.2").await;
+    session
+        .ingest(b"RCPT TO:<external@domain.com>\r\n")
+        .await
+        .unwrap();
+    session.response().assert_code(
+        "550 5.1.2 Relay not allowed. external@domain.com is not local, see https://foobar.org/help",
+    );
 
     // DSN is disabled for 10.0.0.1
     session
@@ -173,4 +194,432 @@ async fn rcpt() {
     let rcpt = session.data.rcpt_to.last().unwrap();
     assert!((rcpt.flags & (RCPT_NOTIFY_DELAY | RCPT_NOTIFY_SUCCESS | RCPT_NOTIFY_FAILURE)) != 0);
     assert_eq!(rcpt.dsn_info.as_ref().unwrap(), "Jane.Doe@Foobar.org");
+
+    // Authenticated users without the send-external feature cannot relay
+    session.rset().await;
+    session.data.authenticated_as = "john".to_string();
+    session.data.authenticated_features = FEATURES_ALL & !FEATURE_SEND_EXTERNAL;
+    session.mail_from("john@foobar.org", "250").await;
+    session.rcpt_to("external@domain.com", "550 5.1.2").await;
+    session.rcpt_to("jane@foobar.org", "250").await;
+    session.data.authenticated_features = FEATURES_ALL;
+    session.rcpt_to("external@domain.com", "250").await;
+}
+
+#[tokio::test]
+async fn rcpt_postmaster() {
+    let mut core = SMTP::test();
+
+    core.shared.directories = Config::new(DIRECTORY)
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap()
+        .directories;
+    let config = &mut core.session.config.rcpt;
+    config.directory = IfBlock::new("local".to_string());
+    config.postmaster = r#"[{if = "remote_ip = '10.0.0.1'", then = "'jane@foobar.org'"},
+    {else = false}]"#
+        .parse_if();
+    config.errors_wait = IfBlock::new(Duration::from_millis(5));
+    let core = Arc::new(core);
+
+    // Postmaster is routed to the fallback mailbox
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx1.foobar.org").await;
+    session.mail_from("john@example.net", "250").await;
+    session.rcpt_to("Postmaster", "250").await;
+    session.rcpt_to("postmaster@foobar.org", "250").await;
+    assert_eq!(session.data.rcpt_to.len(), 1);
+    assert_eq!(session.data.rcpt_to[0].address_lcase, "jane@foobar.org");
+
+    // Bare postmaster split across reads
+    session.ingest(b"RCPT TO:<Post").await.unwrap();
+    session.ingest(b"master>\r\n").await.unwrap();
+    session.response().assert_code("250");
+    assert_eq!(session.data.rcpt_to.len(), 1);
+
+    // Postmaster addresses at remote domains are not rerouted
+    session.rcpt_to("postmaster@example.net", "550 5.1.2").await;
+
+    // Postmaster is not accepted when the fallback is disabled
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx1.foobar.org").await;
+    session.mail_from("john@example.net", "250").await;
+    session.rcpt_to("postmaster@foobar.org", "550 5.1.2").await;
+    session.rcpt_to("Postmaster", "550 5.1.2").await;
+    assert!(session.data.rcpt_to.is_empty());
+}
+
+#[tokio::test]
+async fn rcpt_bare_local_part() {
+    for policy in [BareLocalPart::Reject, BareLocalPart::AppendDefaultDomain] {
+        let mut core = SMTP::test();
+
+        core.shared.directories = Config::new(DIRECTORY)
+            .unwrap()
+            .parse_directory(&dummy_stores(), Store::default())
+            .await
+            .unwrap()
+            .directories;
+        let config = &mut core.session.config.rcpt;
+        config.directory = IfBlock::new("local".to_string());
+        config.bare_local_part = IfBlock::new(policy);
+        config.default_domain = IfBlock::new("foobar.org".to_string());
+        config.errors_wait = IfBlock::new(Duration::from_millis(5));
+
+        let mut session = Session::test(core);
+        session.data.remote_ip_str = "10.0.0.1".to_string();
+        session.eval_session_params().await;
+        session.ehlo("mx1.foobar.org").await;
+        session.mail_from("bill@example.net", "250").await;
+
+        match policy {
+            BareLocalPart::Reject => {
+                session.rcpt_to("john", "501 5.1.3").await;
+                assert!(session.data.rcpt_to.is_empty());
+            }
+            BareLocalPart::AppendDefaultDomain => {
+                session.rcpt_to("John", "250").await;
+                session.rcpt_to("robert", "550 5.1.2").await;
+                assert_eq!(session.data.rcpt_to.len(), 1);
+                assert_eq!(session.data.rcpt_to[0].address, "John@foobar.org");
+                assert_eq!(session.data.rcpt_to[0].address_lcase, "john@foobar.org");
+
+                // Commands split across reads are parsed with their parameters
+                session.ingest(b"RCPT TO:<ja").await.unwrap();
+                session
+                    .ingest(b"ne> NOTIFY=FAILURE ORCPT=rfc822;jane@foobar.org\r\n")
+                    .await
+                    .unwrap();
+                session.response().assert_code("250");
+                let rcpt = session.data.rcpt_to.last().unwrap();
+                assert_eq!(rcpt.address_lcase, "jane@foobar.org");
+                assert_eq!(rcpt.flags & RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_FAILURE);
+                assert_eq!(rcpt.dsn_info.as_deref(), Some("jane@foobar.org"));
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn rcpt_tarpit() {
+    let mut core = SMTP::test();
+
+    core.shared.directories = Config::new(DIRECTORY)
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap()
+        .directories;
+    let config = &mut core.session.config.rcpt;
+    config.directory = IfBlock::new("local".to_string());
+    config.errors_max = IfBlock::new(100);
+    config.errors_wait = IfBlock::new(Duration::ZERO);
+    core.session.config.tarpit.delay = IfBlock::new(Duration::from_millis(50));
+    core.session.config.tarpit.max_delay = IfBlock::new(Duration::from_millis(150));
+
+    // The delay grows with each consecutive failure
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx1.foobar.org").await;
+    session.mail_from("john@example.net", "250").await;
+    let mut last_elapsed = Duration::ZERO;
+    for expected_delay in [50, 100] {
+        let time = Instant::now();
+        session.rcpt_to("tom@foobar.org", "550 5.1.2").await;
+        let elapsed = time.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(expected_delay) && elapsed > last_elapsed,
+            "{elapsed:?} {last_elapsed:?}"
+        );
+        last_elapsed = elapsed;
+    }
+
+    // Delays are capped to the maximum
+    for _ in 0..2 {
+        let time = Instant::now();
+        session.rcpt_to("tom@foobar.org", "550 5.1.2").await;
+        let elapsed = time.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(150) && elapsed < Duration::from_millis(300),
+            "{elapsed:?}"
+        );
+    }
+
+    // Authenticated sessions are exempt
+    session.data.authenticated_as = "john".to_string();
+    let time = Instant::now();
+    session.rcpt_to("tom@foobar.org", "550 5.1.2").await;
+    assert!(time.elapsed() < Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn rcpt_lookup_error() {
+    for (policy, expected_code) in [
+        ("tempfail", "451 4.4.3"),
+        ("reject", "550 5.1.1"),
+        ("accept", "250"),
+    ] {
+        let mut core = SMTP::test();
+
+        // Point the directory to a closed port so that every lookup fails
+        core.shared.directories = Config::new(&format!(
+            r#"
+[directory."unreachable"]
+type = "lmtp"
+host = "127.0.0.1"
+port = 9
+lookup.domains = ["foobar.org"]
+on-error = "{policy}"
+"#
+        ))
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap()
+        .directories;
+        let config = &mut core.session.config.rcpt;
+        config.directory = IfBlock::new("unreachable".to_string());
+        config.errors_wait = IfBlock::new(Duration::from_millis(5));
+
+        let mut session = Session::test(core);
+        session.data.remote_ip_str = "10.0.0.1".to_string();
+        session.eval_session_params().await;
+        session.ehlo("mx1.foobar.org").await;
+        session.mail_from("john@example.net", "250").await;
+        session.rcpt_to("jane@foobar.org", expected_code).await;
+        assert_eq!(
+            session.data.rcpt_to.len(),
+            usize::from(policy == "accept"),
+            "policy {policy}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn rcpt_over_quota() {
+    for (policy, expected_code) in [
+        (OverQuota::Reject, "550 5.2.2"),
+        (OverQuota::Defer, "452 4.2.2"),
+    ] {
+        let mut core = SMTP::test();
+        let store = Store::default();
+        core.shared.directories = Config::new(
+            r#"
+[directory."local"]
+type = "memory"
+
+[[directory."local".principals]]
+name = "john"
+secret = "secret"
+email = "john@foobar.org"
+quota = 1000
+
+[[directory."local".principals]]
+name = "jane"
+secret = "secret"
+email = "jane@foobar.org"
+quota = 1000
+"#,
+        )
+        .unwrap()
+        .parse_directory(&dummy_stores(), store.clone())
+        .await
+        .unwrap()
+        .directories;
+        core.shared.default_data_store = store.clone();
+        let config = &mut core.session.config.rcpt;
+        config.directory = IfBlock::new("local".to_string());
+        config.over_quota = IfBlock::new(policy);
+        config.errors_wait = IfBlock::new(Duration::from_millis(5));
+
+        // Fill up John's mailbox
+        let account_id = core.shared.directories["local"]
+            .query(QueryBy::Name("john"), false)
+            .await
+            .unwrap()
+            .unwrap()
+            .id;
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .add(DirectoryClass::UsedQuota(account_id), 1000);
+        store.write(batch.build()).await.unwrap();
+
+        let mut session = Session::test(core);
+        session.data.remote_ip_str = "10.0.0.1".to_string();
+        session.eval_session_params().await;
+        session.ehlo("mx1.foobar.org").await;
+        session.mail_from("bill@example.net", "250").await;
+        session.rcpt_to("john@foobar.org", expected_code).await;
+        session.rcpt_to("jane@foobar.org", "250").await;
+        assert_eq!(session.data.rcpt_to.len(), 1, "policy {policy:?}");
+    }
+}
+
+#[tokio::test]
+async fn rcpt_null_sender() {
+    let mut core = SMTP::test();
+    let config = &mut core.session.config.rcpt;
+    config.relay = IfBlock::new(true);
+    config.max_recipients = IfBlock::new(5);
+    config.max_recipients_null_sender = IfBlock::new(2);
+
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx1.foobar.org").await;
+
+    // Null sender messages are capped
+    session.mail_from("<>", "250").await;
+    session.rcpt_to("jane@foobar.org", "250").await;
+    session.rcpt_to("bill@foobar.org", "250").await;
+    session.rcpt_to("mike@foobar.org", "550 5.5.3").await;
+    assert_eq!(session.data.rcpt_to.len(), 2);
+
+    // Other senders are only subject to the general limit
+    session.rset().await;
+    session.mail_from("john@example.net", "250").await;
+    for rcpt in ["jane", "bill", "mike"] {
+        session.rcpt_to(&format!("{rcpt}@foobar.org"), "250").await;
+    }
+    assert_eq!(session.data.rcpt_to.len(), 3);
+}
+
+#[tokio::test]
+async fn rcpt_srs() {
+    let srs = Srs {
+        domain: "srs.foobar.org".to_string(),
+        secret: "secret".to_string(),
+        max_age: Duration::from_secs(21 * 86400),
+    };
+    let now = now();
+
+    // Round-trip
+    let address = srs.forward("John.Doe@example.net", now).unwrap();
+    assert!(address.starts_with("SRS0="), "{address}");
+    assert!(address.ends_with("=example.net=John.Doe@srs.foobar.org"));
+    assert!(srs.is_srs_address(&address.to_lowercase()));
+    assert_eq!(srs.reverse(&address, now).unwrap(), "John.Doe@example.net");
+    assert_eq!(
+        srs.reverse(&address.to_lowercase(), now + 86400).unwrap(),
+        "john.doe@example.net"
+    );
+
+    // Forged addresses are rejected
+    assert_eq!(
+        srs.reverse(&address.replace("John.Doe@", "jane@"), now),
+        Err(SrsError::HashMismatch)
+    );
+    assert_eq!(
+        Srs {
+            secret: "other secret".to_string(),
+            ..srs.clone()
+        }
+        .reverse(&address, now),
+        Err(SrsError::HashMismatch)
+    );
+    for invalid in [
+        "john@srs.foobar.org",
+        "SRS0=abcd=AA=example.net@srs.foobar.org",
+        "SRS0=abcd=A=example.net=john@srs.foobar.org",
+        &address.replace("@srs.foobar.org", "@example.org"),
+    ] {
+        assert_eq!(
+            srs.reverse(invalid, now),
+            Err(SrsError::Invalid),
+            "{invalid}"
+        );
+    }
+
+    // Expired addresses are rejected
+    assert_eq!(
+        srs.reverse(&address, now + 22 * 86400),
+        Err(SrsError::Expired)
+    );
+
+    // Bounces to SRS addresses are routed to the original sender
+    let mut core = SMTP::test();
+    core.shared.directories = Config::new(DIRECTORY)
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap()
+        .directories;
+    let config = &mut core.session.config.rcpt;
+    config.directory = IfBlock::new("local".to_string());
+    config.errors_wait = IfBlock::new(Duration::from_millis(5));
+    core.session.config.srs = srs.clone().into();
+
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx1.example.net").await;
+    session.mail_from("<>", "250").await;
+    session
+        .rcpt_to(&address.replace("John.Doe@", "jane@"), "550 5.1.1")
+        .await;
+    session
+        .rcpt_to(
+            &srs.forward("john@example.net", now - 30 * 86400).unwrap(),
+            "550 5.1.1",
+        )
+        .await;
+    session.rcpt_to(&address, "250").await;
+    assert_eq!(session.data.rcpt_to.len(), 1);
+    assert_eq!(session.data.rcpt_to[0].address, "John.Doe@example.net");
+    assert_eq!(session.data.rcpt_to[0].domain, "example.net");
+}
+
+#[tokio::test]
+async fn rcpt_deferral() {
+    let mut core = SMTP::test();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.throttle.rcpt_to = r#"[[throttle]]
+    match = "remote_ip = '10.0.0.1'"
+    key = 'sender'
+    rate = '1/1s'
+    "#
+    .parse_throttle();
+    core.session.config.reject = Config::new(concat!(
+        "[session.reject]\n",
+        "deferral = '{reason} Contact postmaster@foobar.org.'\n",
+        "retry-after = '5m'\n",
+    ))
+    .unwrap()
+    .parse_session_reject()
+    .unwrap();
+
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx1.foobar.org").await;
+    session.mail_from("john@example.net", "250").await;
+    session.rcpt_to("jane@foobar.org", "250").await;
+
+    // Deferrals include the configured retry hint and message
+    session
+        .ingest(b"RCPT TO:<bill@foobar.org>\r\n")
+        .await
+        .unwrap();
+    session.response().assert_code(concat!(
+        "451 4.4.5 Rate limit exceeded. Please try again in 5 minute(s). ",
+        "Contact postmaster@foobar.org."
+    ));
+
+    // Enhanced status codes are added or fixed for temporary failures
+    assert_eq!(
+        session.defer_response("451", "", "Greylisted."),
+        b"451 4.7.1 Greylisted. Please try again in 5 minute(s). Contact postmaster@foobar.org.\r\n"
+    );
+    assert_eq!(
+        session.defer_response("451 5.7.1", "", "Greylisted."),
+        b"451 4.7.1 Greylisted. Please try again in 5 minute(s). Contact postmaster@foobar.org.\r\n"
+    );
 }
@@ -0,0 +1,183 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use mail_auth::{DkimResult, DmarcResult, IprevOutput, IprevResult, SpfResult};
+use utils::config::{if_block::IfBlock, Config};
+
+use crate::smtp::{
+    inbound::TestMessage,
+    session::{TestSession, VerifyResponse},
+    TestConfig,
+};
+use smtp::{
+    config::{session::ConfigSession, ScoreSignal},
+    core::{Session, SMTP},
+};
+
+const CONFIG: &str = r#"
+[session.score]
+reject = 6
+spam = 3
+dnsbl = 2
+spf = { fail = 3, softfail = 1.5 }
+dkim = { fail = 2, none = 0.5 }
+dmarc = { fail = 5 }
+iprev = { fail = 2 }
+"#;
+
+#[test]
+fn score() {
+    let config = Config::new(CONFIG).unwrap().parse_session_score().unwrap();
+    assert_eq!(config.reject, Some(6.0));
+    assert_eq!(config.spam, Some(3.0));
+    assert_eq!(config.weights.len(), 6);
+
+    // DMARC failure and a DNSBL listing exceed the reject threshold
+    let score = config.score(
+        &[
+            (ScoreSignal::Spf, &SpfResult::Pass),
+            (ScoreSignal::Dkim, &DkimResult::None),
+            (
+                ScoreSignal::Dmarc,
+                &DmarcResult::Fail(mail_auth::Error::NotAligned),
+            ),
+            (ScoreSignal::Iprev, &IprevResult::Pass),
+        ],
+        1,
+    );
+    assert_eq!(score, 7.5);
+    assert!(config.is_reject(score));
+    assert!(config.is_spam(score));
+
+    // A soft SPF failure without signatures is only marked as spam
+    let score = config.score(
+        &[
+            (ScoreSignal::Spf, &SpfResult::SoftFail),
+            (ScoreSignal::Dkim, &DkimResult::None),
+            (
+                ScoreSignal::Iprev,
+                &IprevResult::Fail(mail_auth::Error::NotAligned),
+            ),
+        ],
+        0,
+    );
+    assert_eq!(score, 4.0);
+    assert!(!config.is_reject(score));
+    assert!(config.is_spam(score));
+
+    // Passing results do not add to the score
+    let score = config.score(
+        &[
+            (ScoreSignal::Spf, &SpfResult::Pass),
+            (ScoreSignal::Dkim, &DkimResult::Pass),
+            (ScoreSignal::Dmarc, &DmarcResult::Pass),
+            (ScoreSignal::Iprev, &IprevResult::Pass),
+        ],
+        0,
+    );
+    assert_eq!(score, 0.0);
+    assert!(!config.is_spam(score));
+
+    // Scoring is disabled without thresholds
+    assert!(!Config::new("")
+        .unwrap()
+        .parse_session_score()
+        .unwrap()
+        .is_enabled());
+}
+
+#[tokio::test]
+async fn score_inbound() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_score_test");
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.score = Config::new(concat!(
+        "[session.score]\n",
+        "reject = 6\n",
+        "spam = 3\n",
+        "dnsbl = 2\n",
+        "iprev.fail = 2\n"
+    ))
+    .unwrap()
+    .parse_session_score()
+    .unwrap();
+
+    // Messages scoring above the spam threshold are flagged
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+    session.data.iprev = IprevOutput {
+        result: IprevResult::Fail(mail_auth::Error::NotAligned),
+        ptr: None,
+    }
+    .into();
+    session.data.dnsbl_score = 1;
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "From: john@doe.org\r\nX-Spam-Status: No\r\nSubject: test\r\n\r\ntest",
+            "250",
+        )
+        .await;
+    qr.expect_message()
+        .await
+        .read_lines(&qr)
+        .await
+        .assert_contains("X-Spam-Status: Yes, score=4.00")
+        .assert_not_contains("X-Spam-Status: No");
+
+    // And rejected above the reject threshold
+    session.data.dnsbl_score = 2;
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "From: john@doe.org\r\nSubject: test\r\n\r\ntest",
+            "550 5.7.1",
+        )
+        .await;
+    qr.assert_no_events();
+
+    // Authenticated submissions are not scored
+    session.data.authenticated_as = "john".to_string();
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "From: john@doe.org\r\nSubject: test\r\n\r\ntest",
+            "250",
+        )
+        .await;
+    qr.expect_message()
+        .await
+        .read_lines(&qr)
+        .await
+        .assert_not_contains("X-Spam-Status");
+}
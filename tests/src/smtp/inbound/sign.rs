@@ -25,7 +25,7 @@ use std::time::{Duration, Instant};
 
 use directory::core::config::ConfigDirectory;
 use mail_auth::{
-    common::{parse::TxtRecordParser, verify::DomainKey},
+    common::{headers::HeaderWriter, parse::TxtRecordParser, verify::DomainKey},
     spf::Spf,
 };
 use store::Store;
@@ -37,7 +37,7 @@ use crate::smtp::{
     ParseTestConfig, TestConfig, TestSMTP,
 };
 use smtp::{
-    config::{auth::ConfigAuth, ConfigContext, VerifyStrategy},
+    config::{auth::ConfigAuth, ConfigContext, DkimSignFailure, VerifyStrategy},
     core::{Session, SMTP},
 };
 
@@ -217,6 +217,258 @@ async fn sign_and_seal() {
         );
 }
 
+#[tokio::test]
+async fn sign_error() {
+    // Signing fails when none of the headers to sign are present
+    let signatures = SIGNATURES.replacen(
+        "headers = ['From', 'To', 'Date', 'Subject', 'Message-ID']",
+        "headers = ['X-Not-Present']",
+        1,
+    );
+    let mut ctx = ConfigContext::new();
+    Config::new(&signatures)
+        .unwrap()
+        .parse_signatures(&mut ctx)
+        .unwrap();
+
+    for (policy, expected_code) in [
+        (DkimSignFailure::SendUnsigned, "250"),
+        (DkimSignFailure::Defer, "451 4.3.0"),
+    ] {
+        let mut core = SMTP::test();
+        let mut qr = core.init_test_queue("smtp_sign_error_test");
+        core.session.config.rcpt.relay = IfBlock::new(true);
+        core.shared.signers = ctx.signers.clone();
+        core.mail_auth.dkim.sign = "\"['rsa']\"".parse_if();
+        core.mail_auth.dkim.on_sign_error = IfBlock::new(policy);
+
+        let mut session = Session::test(core);
+        session.data.remote_ip_str = "10.0.0.1".to_string();
+        session.eval_session_params().await;
+        session.ehlo("mx.example.com").await;
+        session
+            .send_message(
+                "bill@foobar.org",
+                &["jdoe@example.com"],
+                "test:no_dkim",
+                expected_code,
+            )
+            .await;
+
+        match policy {
+            DkimSignFailure::SendUnsigned => {
+                qr.expect_message()
+                    .await
+                    .read_lines(&qr)
+                    .await
+                    .assert_not_contains("DKIM-Signature:");
+            }
+            DkimSignFailure::Defer => {
+                qr.assert_no_events();
+            }
+        }
+    }
+
+    // Signers that do not exist are also signing errors
+    for (policy, expected_code) in [
+        (DkimSignFailure::SendUnsigned, "250"),
+        (DkimSignFailure::Defer, "451 4.3.0"),
+    ] {
+        let mut core = SMTP::test();
+        let mut qr = core.init_test_queue("smtp_sign_error_test");
+        core.session.config.rcpt.relay = IfBlock::new(true);
+        core.mail_auth.dkim.sign = "\"['missing']\"".parse_if();
+        core.mail_auth.dkim.on_sign_error = IfBlock::new(policy);
+
+        let mut session = Session::test(core);
+        session.data.remote_ip_str = "10.0.0.1".to_string();
+        session.eval_session_params().await;
+        session.ehlo("mx.example.com").await;
+        session
+            .send_message(
+                "bill@foobar.org",
+                &["jdoe@example.com"],
+                "test:no_dkim",
+                expected_code,
+            )
+            .await;
+
+        match policy {
+            DkimSignFailure::SendUnsigned => {
+                qr.expect_message()
+                    .await
+                    .read_lines(&qr)
+                    .await
+                    .assert_not_contains("DKIM-Signature:");
+            }
+            DkimSignFailure::Defer => {
+                qr.assert_no_events();
+            }
+        }
+    }
+}
+
+#[test]
+fn sign_body_length() {
+    let signature = SIGNATURES
+        .split_once("[signature.ed]")
+        .unwrap()
+        .1
+        .replace("set-body-length = false\n", "");
+
+    for (auth_config, expect_body_length) in [
+        ("", false),
+        ("[auth.dkim]\nbody-length = false\n", false),
+        ("[auth.dkim]\nbody-length = true\n", true),
+    ] {
+        let mut ctx = ConfigContext::new();
+        Config::new(&format!("{auth_config}[signature.ed]{signature}"))
+            .unwrap()
+            .parse_signatures(&mut ctx)
+            .unwrap();
+        let header = ctx
+            .signers
+            .get("ed")
+            .unwrap()
+            .sign(
+                b"From: bill@example.com\r\nTo: jdoe@example.com\r\nSubject: test\r\n\r\nHello\r\n",
+            )
+            .unwrap()
+            .to_header();
+
+        assert_eq!(
+            header.split(';').any(|tag| tag.trim().starts_with("l=")),
+            expect_body_length,
+            "{auth_config:?}: {header}"
+        );
+    }
+}
+
+#[test]
+fn sign_for_domain() {
+    let signatures = SIGNATURES.replacen(
+        "domain = 'example.com'\nselector = 'ed'",
+        "domain = 'example.org'\nselector = 'ed'",
+        1,
+    );
+    let message =
+        b"From: bill@example.com\r\nTo: jdoe@example.com\r\nSubject: test\r\n\r\nHello\r\n";
+
+    for (auth_config, expect_default) in [
+        ("", None),
+        ("[auth.dkim]\ndefault-signer = 'rsa'\n", Some("s=rsa")),
+    ] {
+        let mut ctx = ConfigContext::new();
+        Config::new(&format!("{auth_config}{signatures}"))
+            .unwrap()
+            .parse_signatures(&mut ctx)
+            .unwrap();
+        let signers = &ctx.domain_signers;
+
+        // Each domain is signed with its own key
+        for (domain, expected_tags) in [
+            ("example.com", ["d=example.com", "s=rsa"]),
+            ("Example.ORG", ["d=example.org", "s=ed"]),
+        ] {
+            let header = signers
+                .sign_for_domain(domain, message)
+                .unwrap()
+                .unwrap()
+                .to_header();
+            let tags = header.split(';').map(|tag| tag.trim()).collect::<Vec<_>>();
+            for expected_tag in expected_tags {
+                assert!(tags.contains(&expected_tag), "{domain}: {header}");
+            }
+        }
+
+        // Unconfigured domains use the default signer, if any
+        assert_eq!(
+            signers
+                .sign_for_domain("example.net", message)
+                .map(|signature| signature.unwrap().to_header())
+                .and_then(|header| {
+                    header
+                        .split(';')
+                        .map(|tag| tag.trim())
+                        .find(|tag| tag.starts_with("s="))
+                        .map(|tag| tag.to_string())
+                }),
+            expect_default.map(|tag| tag.to_string()),
+            "{auth_config:?}"
+        );
+    }
+
+    // Unknown default signers are rejected
+    assert!(Config::new(&format!(
+        "[auth.dkim]\ndefault-signer = 'unknown'\n{signatures}"
+    ))
+    .unwrap()
+    .parse_signatures(&mut ConfigContext::new())
+    .is_err());
+}
+
+#[tokio::test]
+async fn sign_by_domain() {
+    let signatures = SIGNATURES.replacen(
+        "domain = 'example.com'\nselector = 'ed'",
+        "domain = 'example.org'\nselector = 'ed'",
+        1,
+    );
+    let mut ctx = ConfigContext::new();
+    Config::new(&signatures)
+        .unwrap()
+        .parse_signatures(&mut ctx)
+        .unwrap();
+
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_sign_by_domain_test");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.shared.signers = ctx.signers;
+    core.shared.domain_signers = ctx.domain_signers;
+    core.mail_auth.dkim.sign_by_domain = IfBlock::new(true);
+
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.example.com").await;
+    session.data.authenticated_as = "bill".to_string();
+
+    // The From header selects the signer when its domain belongs to the
+    // authenticated user, the return path is used otherwise
+    for (authenticated_emails, from_header, expected_tag) in [
+        (
+            vec!["bill@example.com", "bill@example.org"],
+            "From: bill@Example.ORG\r\n",
+            "d=example.org;",
+        ),
+        (vec!["bill@example.com"], "", "d=example.com;"),
+        (
+            vec!["bill@example.com"],
+            "From: bill@example.org\r\n",
+            "d=example.com;",
+        ),
+    ] {
+        session.data.authenticated_emails = authenticated_emails
+            .into_iter()
+            .map(|email| email.to_string())
+            .collect();
+        session
+            .send_message(
+                "bill@example.com",
+                &["jdoe@example.net"],
+                &format!("{from_header}To: jdoe@example.net\r\nSubject: test\r\n\r\nHello"),
+                "250",
+            )
+            .await;
+        qr.expect_message()
+            .await
+            .read_lines(&qr)
+            .await
+            .assert_count("DKIM-Signature:", 1)
+            .assert_contains(expected_tag);
+    }
+}
+
 pub trait TextConfigContext<'x> {
     fn parse_signatures(self) -> ConfigContext;
 }
@@ -24,7 +24,7 @@
 use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use dashmap::DashMap;
-use directory::{AddressMapping, Directory, DirectoryInner};
+use directory::{core::config::LookupMap, AddressMapping, Directory, DirectoryInner};
 use mail_auth::{
     common::lru::{DnsCache, LruCache},
     hickory_resolver::config::{ResolverConfig, ResolverOpts},
@@ -45,16 +45,19 @@ use smtp::{
         scripts::SieveContext,
         session::{ConfigSession, Mechanism},
         throttle::ConfigThrottle,
-        AggregateReport, ArcAuthConfig, Auth, Connect, Data, DkimAuthConfig, DmarcAuthConfig, Dsn,
-        Ehlo, Extensions, IpRevAuthConfig, Mail, MailAuthConfig, Milter, QueueConfig,
-        QueueOutboundSourceIp, QueueOutboundTimeout, QueueOutboundTls, QueueQuotas, QueueThrottle,
-        Rcpt, Report, ReportAnalysis, ReportConfig, SessionConfig, SessionThrottle, SpfAuthConfig,
-        Throttle, VerifyStrategy,
+        AddressLiteral, AggregateReport, ArcAuthConfig, Auth, BareLf, BareLocalPart,
+        CommandLeniency, Connect, Data, DkimAuthConfig, DkimSignFailure, DmarcAuthConfig, Dnsbl,
+        Dsn, DuplicateAction, DuplicateScope, Ehlo, Extensions, Help, IpRevAuthConfig, Mail,
+        MailAuthConfig, Milter, OverQuota, Pipelining, QueueConfig, QueueOutboundSourceIp,
+        QueueOutboundTimeout, QueueOutboundTls, QueueQuotas, QueueThrottle, Rcpt, Report,
+        ReportAnalysis, ReportConfig, ScoreConfig, SessionConfig, SessionThrottle, SpfAuthConfig,
+        Tarpit, Throttle, VerifyStrategy, DEFAULT_HELP_MESSAGE,
     },
     core::{
         eval::*, throttle::ThrottleKeyHasherBuilder, QueueCore, ReportCore, Resolvers, SessionCore,
         Shared, SieveCore, TlsConnectors, SMTP,
     },
+    inbound::auth::SaslMechanisms,
     outbound::dane::DnssecResolver,
 };
 use utils::{
@@ -78,6 +81,7 @@ pub trait ParseTestConfig {
     fn parse_quota(&self) -> QueueQuotas;
     fn parse_queue_throttle(&self) -> QueueThrottle;
     fn parse_milters(&self) -> Vec<Milter>;
+    fn parse_trusted_networks(&self) -> LookupMap;
 }
 
 impl ParseTestConfig for &str {
@@ -103,6 +107,7 @@ impl ParseTestConfig for &str {
                         V_REMOTE_IP,
                         V_LOCAL_IP,
                         V_PRIORITY,
+                        V_IS_TRUSTED,
                     ],
                 )
             })
@@ -159,6 +164,12 @@ impl ParseTestConfig for &str {
             ])
             .unwrap()
     }
+    fn parse_trusted_networks(&self) -> LookupMap {
+        Config::new(&format!("server.trusted-networks = {self}\n"))
+            .unwrap()
+            .parse_trusted_networks()
+            .unwrap()
+    }
 }
 
 pub trait TestConfig {
@@ -185,6 +196,7 @@ impl TestConfig for SMTP {
                 cache: smtp::core::DnsCache {
                     tlsa: LruCache::with_capacity(100),
                     mta_sts: LruCache::with_capacity(100),
+                    dnsbl: LruCache::with_capacity(100),
                 },
             },
             mail_auth: MailAuthConfig::test(),
@@ -194,6 +206,7 @@ impl TestConfig for SMTP {
             shared: Shared {
                 scripts: Default::default(),
                 signers: Default::default(),
+                domain_signers: Default::default(),
                 sealers: Default::default(),
                 directories: Default::default(),
                 lookup_stores: Default::default(),
@@ -203,6 +216,10 @@ impl TestConfig for SMTP {
                     catch_all: AddressMapping::Disable,
                     subaddressing: AddressMapping::Disable,
                     cache: None,
+                    on_error: Default::default(),
+                    quota_inheritance: Default::default(),
+                    query_timeout: None,
+                    password_max_age: None,
                     blocked_ips: Arc::new(BlockedIps::new(store.clone().into())),
                 }),
                 default_lookup_store: LookupStore::Store(store.clone()),
@@ -232,11 +249,27 @@ impl TestConfig for SessionConfig {
             timeout: IfBlock::new(Duration::from_secs(10)),
             duration: IfBlock::new(Duration::from_secs(10)),
             transfer_limit: IfBlock::new(1024 * 1024),
+            max_line_length: IfBlock::new(512),
+            command_leniency: IfBlock::new(CommandLeniency::Lenient),
+            max_noop_commands: IfBlock::new(20),
             throttle: SessionThrottle {
                 connect: vec![],
                 mail_from: vec![],
                 rcpt_to: vec![],
             },
+            tarpit: Tarpit {
+                delay: IfBlock::default(),
+                multiplier: IfBlock::new(2),
+                max_delay: IfBlock::new(Duration::from_secs(30)),
+            },
+            pipelining: Pipelining {
+                max_commands: IfBlock::default(),
+                max_size: IfBlock::default(),
+            },
+            help: Help {
+                enable: IfBlock::new(true),
+                message: IfBlock::new(DEFAULT_HELP_MESSAGE.to_string()),
+            },
             connect: Connect {
                 script: IfBlock::default(),
             },
@@ -244,55 +277,101 @@ impl TestConfig for SessionConfig {
                 script: IfBlock::default(),
                 require: IfBlock::new(true),
                 reject_non_fqdn: IfBlock::new(false),
+                helo_extensions: IfBlock::new(CommandLeniency::Strict),
             },
             extensions: Extensions {
                 pipelining: IfBlock::new(true),
+                starttls_pipelining: IfBlock::new(CommandLeniency::Strict),
                 chunking: IfBlock::new(true),
                 requiretls: IfBlock::new(true),
                 no_soliciting: IfBlock::new("domain.org".to_string()),
                 future_release: IfBlock::default(),
                 deliver_by: IfBlock::default(),
                 mt_priority: IfBlock::default(),
+                enhanced_status_codes: IfBlock::new(true),
+                size: IfBlock::new(true),
+                size_value: IfBlock::default(),
                 dsn: IfBlock::new(true),
                 expn: IfBlock::new(true),
                 vrfy: IfBlock::new(true),
+                etrn: IfBlock::new(false),
+                etrn_domains: vec![],
             },
             auth: Auth {
                 directory: IfBlock::default(),
                 mechanisms: IfBlock::new(Mechanism::from(AUTH_PLAIN | AUTH_LOGIN)),
+                custom_mechanisms: IfBlock::default(),
                 require: IfBlock::new(false),
                 errors_max: IfBlock::new(10),
                 errors_wait: IfBlock::new(Duration::from_secs(1)),
+                max_attempts: IfBlock::new(10),
+                max_response_size: IfBlock::new(64 * 1024),
+                sasl: SaslMechanisms::default(),
                 allow_plain_text: IfBlock::new(false),
                 must_match_sender: IfBlock::new(false),
+                send_as: IfBlock::new(false),
+                send_rate: IfBlock::default(),
             },
             mail: Mail {
                 script: IfBlock::default(),
                 rewrite: IfBlock::default(),
+                verify_sender_domain: IfBlock::new(false),
+                address_literals: IfBlock::new(AddressLiteral::Reject),
+                multiple_commands: IfBlock::new(CommandLeniency::Strict),
+                timeout: IfBlock::new(Duration::from_secs(5 * 60)),
             },
             rcpt: Rcpt {
                 script: IfBlock::default(),
+                timeout: IfBlock::new(Duration::from_secs(5 * 60)),
                 relay: IfBlock::new(false),
                 directory: IfBlock::default(),
                 errors_max: IfBlock::new(3),
                 errors_wait: IfBlock::new(Duration::from_secs(1)),
                 max_recipients: IfBlock::new(3),
+                max_recipients_null_sender: IfBlock::new(1),
+                over_quota: IfBlock::new(OverQuota::Reject),
                 rewrite: IfBlock::default(),
+                postmaster: IfBlock::default(),
+                bare_local_part: IfBlock::new(BareLocalPart::Reject),
+                default_domain: IfBlock::default(),
+                address_literals: IfBlock::new(AddressLiteral::Reject),
             },
             data: Data {
                 script: IfBlock::default(),
                 max_messages: IfBlock::new(10),
                 max_message_size: IfBlock::new(1024 * 1024),
+                max_message_size_domain: LookupMap::default(),
                 max_received_headers: IfBlock::new(10),
+                max_line_length: IfBlock::new(1000),
+                max_concurrent: None,
+                timeout_init: IfBlock::new(Duration::from_secs(2 * 60)),
+                timeout: IfBlock::new(Duration::from_secs(3 * 60)),
+                backpressure: None,
+                bare_lf: IfBlock::new(BareLf::Convert),
+                duplicate_window: IfBlock::default(),
+                duplicate_scope: IfBlock::new(DuplicateScope::Recipient),
+                duplicate_action: IfBlock::new(DuplicateAction::Discard),
                 add_received: IfBlock::new(true),
                 add_received_spf: IfBlock::new(true),
                 add_return_path: IfBlock::new(true),
                 add_auth_results: IfBlock::new(true),
                 add_message_id: IfBlock::new(true),
                 add_date: IfBlock::new(true),
+                missing_headers: IfBlock::new(smtp::config::MissingHeaders::Allow),
+                eight_bit_headers: IfBlock::new(smtp::config::EightBitHeaders::Allow),
+                strip_bcc: IfBlock::new(true),
+                received_ip: IfBlock::new(true),
+                received_tls: IfBlock::new(true),
+                received_protocol: IfBlock::new(true),
+                proxy_directory: IfBlock::default(),
                 pipe_commands: vec![],
                 milters: vec![],
             },
+            reject: Default::default(),
+            srs: None,
+            dnsbl: Dnsbl::default(),
+            score: ScoreConfig::default(),
+            trusted_networks: Default::default(),
         }
     }
 }
@@ -337,10 +416,12 @@ impl TestConfig for QueueConfig {
                 start: IfBlock::new(smtp::config::RequireOptional::Optional),
                 invalid_certs: IfBlock::new(false),
             },
+            downgrade_8bitmime: IfBlock::new(smtp::config::Downgrade8BitMime::Convert),
             dsn: Dsn {
                 name: IfBlock::new("Mail Delivery Subsystem".to_string()),
                 address: IfBlock::new("MAILER-DAEMON@example.org".to_string()),
                 sign: IfBlock::default(),
+                max_size: IfBlock::new(10 * 1024 * 1024),
             },
             timeout: QueueOutboundTimeout {
                 connect: IfBlock::new(Duration::from_secs(1)),
@@ -372,6 +453,8 @@ impl TestConfig for MailAuthConfig {
             dkim: DkimAuthConfig {
                 verify: IfBlock::new(VerifyStrategy::Relaxed),
                 sign: IfBlock::default(),
+                sign_by_domain: IfBlock::new(false),
+                on_sign_error: IfBlock::new(DkimSignFailure::SendUnsigned),
             },
             arc: ArcAuthConfig {
                 verify: IfBlock::new(VerifyStrategy::Relaxed),
@@ -380,6 +463,9 @@ impl TestConfig for MailAuthConfig {
             spf: SpfAuthConfig {
                 verify_ehlo: IfBlock::new(VerifyStrategy::Relaxed),
                 verify_mail_from: IfBlock::new(VerifyStrategy::Relaxed),
+                check: IfBlock::default(),
+                max_lookups: IfBlock::new(10),
+                max_macro_lookups: IfBlock::new(5),
             },
             dmarc: DmarcAuthConfig {
                 verify: IfBlock::new(VerifyStrategy::Relaxed),
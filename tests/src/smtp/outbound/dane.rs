@@ -245,6 +245,7 @@ async fn dane_test() {
         cache: smtp::core::DnsCache {
             tlsa: LruCache::with_capacity(10),
             mta_sts: LruCache::with_capacity(10),
+            dnsbl: LruCache::with_capacity(10),
         },
     };
 
@@ -28,15 +28,23 @@ use std::{
 
 use mail_auth::MX;
 use smtp_proto::{MAIL_REQUIRETLS, MAIL_RET_HDRS, MAIL_SMTPUTF8, RCPT_NOTIFY_NEVER};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::{mpsc, watch},
+};
 use utils::config::{if_block::IfBlock, ServerProtocol};
 
 use crate::smtp::{
     inbound::{TestMessage, TestQueueEvent},
     outbound::start_test_server,
     session::{TestSession, VerifyResponse},
-    TestConfig, TestSMTP,
+    ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::Downgrade8BitMime,
+    core::{Session, SMTP},
 };
-use smtp::core::{Session, SMTP};
 
 #[tokio::test]
 #[serial_test::serial]
@@ -135,6 +143,7 @@ async fn extensions() {
     remote_qr.assert_no_events();
 
     // Test DSN, SMTPUTF8 and REQUIRETLS extensions
+    session.stream.tls = true;
     session
         .send_message(
             "<john@test.org> ENVID=abc123 RET=HDRS REQUIRETLS SMTPUTF8",
@@ -156,3 +165,169 @@ async fn extensions() {
     assert!((message.flags & MAIL_SMTPUTF8) != 0);
     assert!((message.recipients.last().unwrap().flags & RCPT_NOTIFY_NEVER) != 0);
 }
+
+const MESSAGE_8BIT: &str = concat!(
+    "From: john@test.org\r\n",
+    "To: bill@foobar.org\r\n",
+    "Subject: 8-bit test\r\n",
+    "MIME-Version: 1.0\r\n",
+    "Content-Type: text/plain; charset=utf-8\r\n",
+    "Content-Transfer-Encoding: 8bit\r\n",
+    "\r\n",
+    "Déjà vu\r\n"
+);
+
+#[tokio::test]
+#[serial_test::serial]
+async fn eightbitmime_downgrade() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::TRACE)
+            .finish(),
+    )
+    .unwrap();*/
+
+    // Start a test server that does not advertise 8BITMIME
+    let (mut remote_rx, _shutdown) = spawn_mock_7bit_server().await;
+
+    // Add mock DNS entries
+    let mut core = SMTP::test();
+    core.resolvers.dns.mx_add(
+        "foobar.org",
+        vec![MX {
+            exchanges: vec!["mx.foobar.org".to_string()],
+            preference: 10,
+        }],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.resolvers.dns.ipv4_add(
+        "mx.foobar.org",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+    let mut local_qr = core.init_test_queue("smtp_8bitmime_local");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.queue.config.downgrade_8bitmime =
+        r#"[{if = "sender_domain = 'test.net'", then = "reject"},
+    {else = "convert"}]"#
+            .parse_if_constant::<Downgrade8BitMime>();
+    let core = Arc::new(core);
+
+    // 8-bit bodies are converted to quoted-printable
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    session
+        .send_message("john@test.org", &["bill@foobar.org"], MESSAGE_8BIT, "250")
+        .await;
+    local_qr
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone())
+        .await;
+    local_qr.read_event().await.assert_reload();
+    let (mail_from, message) = remote_rx.recv().await.unwrap();
+    assert!(!mail_from.contains("BODY=8BITMIME"), "{mail_from}");
+    assert!(message.is_ascii(), "{message}");
+    assert!(
+        message.contains("Content-Transfer-Encoding: quoted-printable\r\n"),
+        "{message}"
+    );
+    assert!(
+        !message.contains("Content-Transfer-Encoding: 8bit"),
+        "{message}"
+    );
+    assert!(message.contains("D=C3=A9j=C3=A0 vu"), "{message}");
+
+    // 8-bit content is bounced when the policy is to reject
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    session
+        .send_message("jane@test.net", &["bill@foobar.org"], MESSAGE_8BIT, "250")
+        .await;
+    local_qr
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone())
+        .await;
+    local_qr
+        .expect_message()
+        .await
+        .read_lines(&local_qr)
+        .await
+        .assert_contains("<bill@foobar.org> (connection to 'mx.foobar.org' failed")
+        .assert_contains("8BITMIME not advertised by host")
+        .assert_contains("Action: failed");
+    local_qr.read_event().await.assert_reload();
+    assert!(remote_rx.try_recv().is_err());
+}
+
+async fn spawn_mock_7bit_server() -> (mpsc::Receiver<(String, String)>, watch::Sender<bool>) {
+    let (tx, rx) = mpsc::channel(16);
+    let (shutdown_tx, mut shutdown_rx) = watch::channel(true);
+    let listener = TcpListener::bind("127.0.0.1:9925").await.unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let stream = tokio::select! {
+                stream = listener.accept() => stream.unwrap().0,
+                _ = shutdown_rx.changed() => break,
+            };
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut reader = BufReader::new(reader);
+                let mut mail_from = String::new();
+                writer
+                    .write_all(b"220 mx.foobar.org ESMTP\r\n")
+                    .await
+                    .unwrap();
+
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).await.unwrap_or(0) == 0 {
+                        break;
+                    }
+                    let command = line.to_ascii_uppercase();
+                    let response: &[u8] = if command.starts_with("EHLO") {
+                        b"250-mx.foobar.org\r\n250-SIZE 1000000\r\n250 PIPELINING\r\n"
+                    } else if command.starts_with("MAIL FROM") {
+                        mail_from = line.trim().to_string();
+                        b"250 OK\r\n"
+                    } else if command.starts_with("RCPT TO") {
+                        b"250 OK\r\n"
+                    } else if command.starts_with("DATA") {
+                        writer.write_all(b"354 Send data\r\n").await.unwrap();
+                        let mut message = Vec::new();
+                        while !message.ends_with(b"\r\n.\r\n") {
+                            let mut byte = [0u8; 1];
+                            if reader.read_exact(&mut byte).await.is_err() {
+                                return;
+                            }
+                            message.push(byte[0]);
+                        }
+                        message.truncate(message.len() - 3);
+                        tx.send((
+                            std::mem::take(&mut mail_from),
+                            String::from_utf8_lossy(&message).into_owned(),
+                        ))
+                        .await
+                        .unwrap();
+                        b"250 Queued\r\n"
+                    } else if command.starts_with("QUIT") {
+                        writer.write_all(b"221 Bye\r\n").await.unwrap();
+                        break;
+                    } else {
+                        b"502 Command not implemented\r\n"
+                    };
+                    writer.write_all(response).await.unwrap();
+                }
+            });
+        }
+    });
+
+    (rx, shutdown_tx)
+}
@@ -22,12 +22,12 @@
 */
 
 use std::{
-    sync::Arc,
+    sync::{atomic::Ordering, Arc},
     time::{Duration, Instant},
 };
 
 use crate::smtp::{
-    inbound::TestMessage,
+    inbound::{TestMessage, TestQueueEvent},
     outbound::start_test_server,
     session::{TestSession, VerifyResponse},
     ParseTestConfig, TestConfig, TestSMTP,
@@ -204,3 +204,95 @@ async fn lmtp_delivery() {
     );
     remote_qr.assert_no_events();
 }
+
+#[tokio::test]
+#[serial_test::serial]
+async fn lmtp_max_concurrent() {
+    // Concurrency is only limited when configured
+    assert!(Config::new(REMOTE)
+        .unwrap()
+        .parse_host("lmtp")
+        .unwrap()
+        .concurrency
+        .is_none());
+    let relay_host = Config::new(&format!(
+        "{REMOTE}\n[remote.lmtp.limits]\nconcurrency = 1\n"
+    ))
+    .unwrap()
+    .parse_host("lmtp")
+    .unwrap();
+    let limiter = relay_host.concurrency.clone().unwrap();
+    assert_eq!(limiter.max_concurrent, 1);
+
+    // Start test server
+    let mut core = SMTP::test();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    let mut remote_qr = core.init_test_queue("lmtp_concurrency_remote");
+    let _rx = start_test_server(core.into(), &[ServerProtocol::Lmtp]);
+
+    let mut core = SMTP::test();
+    core.resolvers.dns.ipv4_add(
+        "lmtp.foobar.org",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+    let mut local_qr = core.init_test_queue("lmtp_concurrency_local");
+    core.shared
+        .relay_hosts
+        .insert("lmtp".to_string(), relay_host);
+    core.queue.config.next_hop = IfBlock::new("lmtp".to_string());
+    core.session.config.rcpt.relay = IfBlock::new(true);
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+
+    // Deliveries beyond the limit are put on hold
+    let in_flight = limiter.is_allowed().unwrap();
+    session
+        .send_message("john@test.org", &["bill@foobar.org"], "test:no_dkim", "250")
+        .await;
+    local_qr
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone())
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let on_hold = local_qr.read_event().await.unwrap_on_hold();
+    assert_eq!(on_hold.limiters.len(), 1);
+    assert_eq!(on_hold.limiters[0].concurrent.load(Ordering::Relaxed), 1);
+    remote_qr.assert_no_events();
+
+    // Once the slot is released the next delivery goes through, each
+    // connection to the relay host takes a single slot
+    drop(in_flight);
+    session
+        .send_message(
+            "john@test.org",
+            &["jane@foobar.org", "mike@foobar.net"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    local_qr
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone())
+        .await;
+    for rcpt in ["jane@foobar.org", "mike@foobar.net"] {
+        assert_eq!(
+            remote_qr
+                .expect_message()
+                .await
+                .recipients
+                .into_iter()
+                .map(|r| r.address)
+                .collect::<Vec<_>>(),
+            vec![rcpt.to_string()]
+        );
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(limiter.concurrent.load(Ordering::Relaxed), 0);
+}
@@ -114,3 +114,94 @@ async fn starttls_optional() {
         .await
         .assert_not_contains("using TLSv1.3 with cipher");
 }
+
+#[tokio::test]
+#[serial_test::serial]
+async fn requiretls_no_fallback() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::TRACE)
+            .finish(),
+    )
+    .unwrap();*/
+
+    // Start test server
+    let mut core = SMTP::test();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    let mut remote_qr = core.init_test_queue("smtp_requiretls_remote");
+    let _rx = start_test_server(core.into(), &[ServerProtocol::Smtp]);
+
+    // Add mock DNS entries
+    let mut core = SMTP::test();
+    core.resolvers.dns.mx_add(
+        "foobar.org",
+        vec![MX {
+            exchanges: vec!["mx.foobar.org".to_string()],
+            preference: 10,
+        }],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.resolvers.dns.ipv4_add(
+        "mx.foobar.org",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+
+    // Disable TLS for the next hop
+    let mut local_qr = core.init_test_queue("smtp_requiretls_local");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.extensions.requiretls = IfBlock::new(true);
+    core.queue.config.tls.start = IfBlock::new(RequireOptional::Disable);
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.stream.tls = true;
+    session
+        .ehlo("mx.test.org")
+        .await
+        .assert_contains("REQUIRETLS");
+
+    // Messages without REQUIRETLS are delivered in plain-text
+    session
+        .send_message("john@test.org", &["bill@foobar.org"], "test:no_dkim", "250")
+        .await;
+    local_qr
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone())
+        .await;
+    local_qr.read_event().await.assert_reload();
+    remote_qr
+        .expect_message()
+        .await
+        .read_lines(&remote_qr)
+        .await
+        .assert_not_contains("using TLSv1.3 with cipher");
+
+    // Messages with REQUIRETLS must not fall back to plain-text
+    session
+        .send_message(
+            "<john@test.org> REQUIRETLS",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    local_qr
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone())
+        .await;
+    local_qr
+        .expect_message()
+        .await
+        .read_lines(&local_qr)
+        .await
+        .assert_contains("<bill@foobar.org> (TLS error from 'mx.foobar.org'")
+        .assert_contains("TLS is disabled for this host")
+        .assert_contains("Action: failed");
+    local_qr.read_event().await.assert_reload();
+    remote_qr.assert_no_events();
+}
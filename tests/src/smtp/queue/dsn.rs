@@ -23,9 +23,11 @@
 
 use std::{fs, path::PathBuf, time::SystemTime};
 
-use smtp_proto::{Response, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_SUCCESS};
+use smtp_proto::{
+    Response, MAIL_RET_FULL, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_SUCCESS,
+};
 use store::write::now;
-use utils::BlobHash;
+use utils::{config::if_block::IfBlock, BlobHash};
 
 use crate::smtp::{
     inbound::sign::TextConfigContext, ParseTestConfig, QueueReceiver, TestConfig, TestSMTP,
@@ -87,6 +89,7 @@ async fn generate_dsn() {
         }],
         flags: 0,
         env_id: None,
+        auth: None,
         priority: 0,
         blob_hash: BlobHash::from(dsn_original.as_bytes()),
         quota_keys: vec![],
@@ -162,6 +165,53 @@ async fn generate_dsn() {
     // Load queue
     let queue = qr.read_queued_messages().await;
     assert_eq!(queue.len(), 4);
+
+    // 8-bit originals are returned unmodified
+    let original = b"From: jose@foobar.org\r\nSubject: Caf\xe9\r\n\r\nCaf\xe9 cr\xe8me\r\n";
+    message.blob_hash = BlobHash::from(original.as_slice());
+    message.flags = MAIL_RET_FULL;
+    qr.blob_store
+        .put_blob(message.blob_hash.as_slice(), original)
+        .await
+        .unwrap();
+    for rcpt in &mut message.recipients {
+        rcpt.flags = flags;
+    }
+    message.domains[0].notify.due = now();
+    core.send_dsn(&mut message, &span).await;
+    let dsn_message = qr.expect_message().await;
+    let bytes = qr
+        .blob_store
+        .get_blob(dsn_message.blob_hash.as_slice(), 0..usize::MAX)
+        .await
+        .unwrap()
+        .unwrap();
+    let part = b"Content-Transfer-Encoding: 8bit\r\n\r\n"
+        .iter()
+        .chain(original.iter())
+        .copied()
+        .collect::<Vec<_>>();
+    assert!(bytes.windows(part.len()).any(|window| window == part));
+
+    // Originals above the maximum size are returned as headers only
+    core.queue.config.dsn.max_size = IfBlock::new(16);
+    message.size = original.len();
+    for rcpt in &mut message.recipients {
+        rcpt.flags = flags;
+    }
+    message.domains[0].notify.due = now();
+    core.send_dsn(&mut message, &span).await;
+    let dsn_message = qr.expect_message().await;
+    let bytes = qr
+        .blob_store
+        .get_blob(dsn_message.blob_hash.as_slice(), 0..usize::MAX)
+        .await
+        .unwrap()
+        .unwrap();
+    let headers = b"Subject: Caf\xe9\r\n\r\n";
+    assert!(bytes.windows(headers.len()).any(|window| window == headers));
+    let body = b"Caf\xe9 cr\xe8me";
+    assert!(!bytes.windows(body.len()).any(|window| window == body));
 }
 
 impl QueueReceiver {
@@ -27,9 +27,13 @@ use mail_auth::hickory_resolver::proto::op::ResponseCode;
 
 use smtp::{
     core::SMTP,
-    queue::{Domain, Message, Schedule, Status},
+    queue::{Domain, Message, QueueId, QuotaKey, Recipient, Schedule, Status},
 };
-use store::write::now;
+use store::{
+    write::{now, Bincode},
+    Deserialize, Serialize,
+};
+use utils::BlobHash;
 
 use crate::smtp::{TestConfig, TestSMTP};
 
@@ -135,6 +139,52 @@ fn delivery_events() {
     assert!(message.next_event().is_none());
 }
 
+#[test]
+fn legacy_message_layout() {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct LegacyMessage {
+        id: QueueId,
+        created: u64,
+        blob_hash: BlobHash,
+        return_path: String,
+        return_path_lcase: String,
+        return_path_domain: String,
+        recipients: Vec<Recipient>,
+        domains: Vec<Domain>,
+        flags: u64,
+        env_id: Option<String>,
+        priority: i16,
+        size: usize,
+        quota_keys: Vec<QuotaKey>,
+    }
+
+    // Messages queued before the AUTH parameter was stored are still readable
+    let mut message = new_message(1);
+    message.domains.push(domain("a", 1, 2, 3));
+    let legacy = Bincode::new(LegacyMessage {
+        id: message.id,
+        created: message.created,
+        blob_hash: message.blob_hash.clone(),
+        return_path: message.return_path.clone(),
+        return_path_lcase: message.return_path_lcase.clone(),
+        return_path_domain: message.return_path_domain.clone(),
+        recipients: message.recipients.clone(),
+        domains: message.domains.clone(),
+        flags: message.flags,
+        env_id: message.env_id.clone(),
+        priority: message.priority,
+        size: message.size,
+        quota_keys: message.quota_keys.clone(),
+    })
+    .serialize();
+    assert_eq!(Message::deserialize(&legacy).unwrap(), message);
+
+    // Current layout
+    message.auth = Some("john@example.org".to_string());
+    let bytes = Bincode::new(message.clone()).serialize();
+    assert_eq!(Message::deserialize(&bytes).unwrap(), message);
+}
+
 pub fn new_message(id: u64) -> Message {
     Message {
         size: 0,
@@ -147,6 +197,7 @@ pub fn new_message(id: u64) -> Message {
         domains: vec![],
         flags: 0,
         env_id: None,
+        auth: None,
         priority: 0,
         quota_keys: vec![],
         blob_hash: Default::default(),
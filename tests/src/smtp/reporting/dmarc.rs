@@ -167,3 +167,98 @@ async fn report_dmarc() {
     }
     qr.assert_report_is_empty().await;
 }
+
+#[tokio::test]
+async fn report_dmarc_max_size() {
+    // Create scheduler
+    let mut core = SMTP::test();
+    let config = &mut core.report.config;
+    config.dmarc_aggregate.max_size = IfBlock::new(2048);
+    config.submitter = IfBlock::new("mx.example.org".to_string());
+    config.dmarc_aggregate.address = IfBlock::new("reports@example.org".to_string());
+    config.dmarc_aggregate.org_name = IfBlock::new("Foobar, Inc.".to_string());
+
+    // Create temp dir for queue
+    let mut qr = core.init_test_queue("smtp_report_dmarc_max_size_test");
+    let core = Arc::new(core);
+
+    // Schedule more distinct records than fit in a report, each seen three times,
+    // along with a single record for another domain
+    let dmarc_record =
+        Arc::new(Dmarc::parse(b"v=DMARC1; p=reject; rua=mailto:reports@foobar.org").unwrap());
+    for octet in 1..=20u8 {
+        for _ in 0..3 {
+            core.schedule_dmarc(Box::new(DmarcEvent {
+                domain: "foobar.org".to_string(),
+                report_record: Record::new()
+                    .with_source_ip(IpAddr::from([192, 168, 1, octet]))
+                    .with_action_disposition(ActionDisposition::Reject)
+                    .with_dmarc_dkim_result(DmarcResult::Fail)
+                    .with_dmarc_spf_result(DmarcResult::Fail)
+                    .with_envelope_from("hello@example.org")
+                    .with_header_from("bye@example.org"),
+                dmarc_record: dmarc_record.clone(),
+                interval: AggregateFrequency::Daily,
+            }))
+            .await;
+        }
+    }
+    core.schedule_dmarc(Box::new(DmarcEvent {
+        domain: "foobar.net".to_string(),
+        report_record: Record::new()
+            .with_source_ip(IpAddr::from([10, 0, 0, 1]))
+            .with_action_disposition(ActionDisposition::Pass)
+            .with_dmarc_dkim_result(DmarcResult::Pass)
+            .with_dmarc_spf_result(DmarcResult::Pass),
+        dmarc_record: Arc::new(
+            Dmarc::parse(b"v=DMARC1; p=none; rua=mailto:reports@foobar.net").unwrap(),
+        ),
+        interval: AggregateFrequency::Daily,
+    }))
+    .await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Each domain is aggregated into its own report
+    let events = qr.read_report_events().await;
+    assert_eq!(events.len(), 2);
+    let mut reports = Vec::new();
+    for event in events {
+        match event {
+            QueueClass::DmarcReportHeader(event) => {
+                core.send_dmarc_aggregate_report(event).await;
+            }
+            _ => unreachable!(),
+        }
+        let message = qr.expect_message().await;
+        reports.push(Report::parse_rfc5322(message.read_message(&qr).await.as_bytes()).unwrap());
+    }
+    reports.sort_by(|a, b| a.domain().cmp(b.domain()));
+
+    // A single message was seen for the other domain
+    assert_eq!(reports[0].domain(), "foobar.net");
+    assert_eq!(reports[0].records().len(), 1);
+    assert_eq!(reports[0].records()[0].count(), 1);
+    assert_eq!(
+        reports[0].records()[0].action_disposition(),
+        ActionDisposition::Pass
+    );
+
+    // The report is truncated to the configured size, identical outcomes are counted
+    // in a single record
+    let report = &reports[1];
+    assert_eq!(report.domain(), "foobar.org");
+    assert!(
+        !report.records().is_empty() && report.records().len() < 20,
+        "unexpected record count {}",
+        report.records().len()
+    );
+    assert!(report.records().iter().all(|record| record.count() == 3));
+    assert!(report
+        .records()
+        .iter()
+        .all(
+            |record| record.action_disposition() == ActionDisposition::Reject
+                && record.header_from() == "bye@example.org"
+        ));
+    qr.assert_report_is_empty().await;
+}
@@ -129,8 +129,8 @@ pub async fn blob_tests() {
             BlobQuota { bytes: 0, count: 0 }
         );
 
-        // Purge expired blobs
-        store.purge_blobs(blob_store.clone()).await.unwrap();
+        // Purge expired temporary blobs
+        store.purge_tmp_blobs(blob_store.clone()).await.unwrap();
 
         // Blob hash should no longer exist
         assert!(!store.blob_exists(&hash).await.unwrap());